@@ -0,0 +1,1154 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+
+use once_cell::sync::Lazy;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path;
+use std::sync::Mutex;
+
+const DEFAULT_MPD_LOCATION: &str = "manifest.mpd";
+const DEFAULT_INIT_LOCATION: &str = "init_$RepresentationID$.mp4";
+const DEFAULT_LOCATION: &str = "segment_$RepresentationID$_$Number%05d$.m4s";
+const DEFAULT_TARGET_DURATION: u32 = 10;
+const DEFAULT_DYNAMIC: bool = false;
+const DEFAULT_WINDOW_SIZE: u32 = 5;
+const DEFAULT_MAX_FILES: u32 = 10;
+const DEFAULT_SYNC: bool = true;
+
+const SIGNAL_GET_MPD_STREAM: &str = "get-mpd-stream";
+const SIGNAL_GET_INIT_STREAM: &str = "get-init-stream";
+const SIGNAL_GET_FRAGMENT_STREAM: &str = "get-fragment-stream";
+const SIGNAL_DELETE_FRAGMENT: &str = "delete-fragment";
+
+/// Offset between NTP and UNIX epoch in seconds.
+/// NTP = UNIX + NTP_UNIX_OFFSET.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Reference timestamp meta caps for NTP timestamps.
+static NTP_CAPS: Lazy<gst::Caps> = Lazy::new(|| gst::Caps::builder("timestamp/x-ntp").build());
+
+/// Reference timestamp meta caps for UNIX timestamps.
+static UNIX_CAPS: Lazy<gst::Caps> = Lazy::new(|| gst::Caps::builder("timestamp/x-unix").build());
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "dashmp4sink",
+        gst::DebugColorFlags::empty(),
+        Some("DASH MP4 sink"),
+    )
+});
+
+struct Settings {
+    mpd_location: String,
+    init_location: String,
+    location: String,
+    target_duration: u32,
+    dynamic: bool,
+    window_size: u32,
+    max_files: u32,
+    sync: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mpd_location: String::from(DEFAULT_MPD_LOCATION),
+            init_location: String::from(DEFAULT_INIT_LOCATION),
+            location: String::from(DEFAULT_LOCATION),
+            target_duration: DEFAULT_TARGET_DURATION,
+            dynamic: DEFAULT_DYNAMIC,
+            window_size: DEFAULT_WINDOW_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+            sync: DEFAULT_SYNC,
+        }
+    }
+}
+
+/// A media segment in the timescale of its representation.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    number: u64,
+    start: u64,
+    duration: u64,
+}
+
+/// A single representation, i.e. one sink pad of the element.
+struct Stream {
+    pad: gst::GhostPad,
+    mux: gst::Element,
+    appsink: gst_app::AppSink,
+    timescale: u64,
+    next_number: u64,
+    /// Segments currently listed in the MPD.
+    segments: VecDeque<Segment>,
+    /// Locations of segments that were removed from the MPD but not deleted yet.
+    old_segment_locations: VecDeque<String>,
+    /// Peak bitrate of all segments so far.
+    bandwidth: u64,
+    eos: bool,
+}
+
+#[derive(Default)]
+struct State {
+    streams: BTreeMap<String, Stream>,
+    video_serial: u32,
+    audio_serial: u32,
+    subtitle_serial: u32,
+    /// Wall clock time corresponding to running time zero.
+    availability_start_time: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Default)]
+pub struct DashMP4Sink {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DashMP4Sink {
+    const NAME: &'static str = "GstDashMP4Sink";
+    type Type = super::DashMP4Sink;
+    type ParentType = gst::Bin;
+}
+
+impl ObjectImpl for DashMP4Sink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::builder("mpd-location")
+                    .nick("MPD location")
+                    .blurb("Location of the MPD file to write")
+                    .default_value(Some(DEFAULT_MPD_LOCATION))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("init-location")
+                    .nick("Init location")
+                    .blurb("Location of the init segments relative to the MPD, with DASH template identifiers")
+                    .default_value(Some(DEFAULT_INIT_LOCATION))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("location")
+                    .nick("Location")
+                    .blurb("Location of the media segments relative to the MPD, with DASH template identifiers")
+                    .default_value(Some(DEFAULT_LOCATION))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("target-duration")
+                    .nick("Target duration")
+                    .blurb("The target duration in seconds of a segment")
+                    .minimum(1)
+                    .default_value(DEFAULT_TARGET_DURATION)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("dynamic")
+                    .nick("Dynamic")
+                    .blurb("Write a live (dynamic) MPD instead of a static one")
+                    .default_value(DEFAULT_DYNAMIC)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("window-size")
+                    .nick("Window size")
+                    .blurb("Number of segments listed in a dynamic MPD (0 = all)")
+                    .default_value(DEFAULT_WINDOW_SIZE)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("max-files")
+                    .nick("Max files")
+                    .blurb("Maximum number of segments removed from a dynamic MPD to keep on disk before deleting them (0 = never delete)")
+                    .default_value(DEFAULT_MAX_FILES)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("sync")
+                    .nick("Sync")
+                    .blurb("Sync on the clock")
+                    .default_value(DEFAULT_SYNC)
+                    .mutable_ready()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "mpd-location" => {
+                settings.mpd_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_MPD_LOCATION.into());
+            }
+            "init-location" => {
+                settings.init_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_INIT_LOCATION.into());
+            }
+            "location" => {
+                settings.location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_LOCATION.into());
+            }
+            "target-duration" => {
+                settings.target_duration = value.get().expect("type checked upstream");
+            }
+            "dynamic" => {
+                settings.dynamic = value.get().expect("type checked upstream");
+            }
+            "window-size" => {
+                settings.window_size = value.get().expect("type checked upstream");
+            }
+            "max-files" => {
+                settings.max_files = value.get().expect("type checked upstream");
+            }
+            "sync" => {
+                settings.sync = value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "mpd-location" => settings.mpd_location.to_value(),
+            "init-location" => settings.init_location.to_value(),
+            "location" => settings.location.to_value(),
+            "target-duration" => settings.target_duration.to_value(),
+            "dynamic" => settings.dynamic.to_value(),
+            "window-size" => settings.window_size.to_value(),
+            "max-files" => settings.max_files.to_value(),
+            "sync" => settings.sync.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            let stream_signal = |name: &'static str| {
+                glib::subclass::Signal::builder(name)
+                    .param_types([String::static_type()])
+                    .return_type::<Option<gio::OutputStream>>()
+                    .class_handler(|_, args| {
+                        let elem = args[0].get::<super::DashMP4Sink>().expect("signal arg");
+                        let location = args[1].get::<String>().expect("signal arg");
+                        let imp = elem.imp();
+
+                        Some(imp.new_file_stream(&location).ok().to_value())
+                    })
+                    .accumulator(|_hint, ret, value| {
+                        // First signal handler wins
+                        *ret = value.clone();
+                        false
+                    })
+                    .build()
+            };
+
+            vec![
+                stream_signal(SIGNAL_GET_MPD_STREAM),
+                stream_signal(SIGNAL_GET_INIT_STREAM),
+                stream_signal(SIGNAL_GET_FRAGMENT_STREAM),
+                glib::subclass::Signal::builder(SIGNAL_DELETE_FRAGMENT)
+                    .param_types([String::static_type()])
+                    .return_type::<bool>()
+                    .class_handler(|_, args| {
+                        let elem = args[0].get::<super::DashMP4Sink>().expect("signal arg");
+                        let location = args[1].get::<String>().expect("signal arg");
+                        let imp = elem.imp();
+
+                        imp.delete_fragment(&location);
+                        Some(true.to_value())
+                    })
+                    .accumulator(|_hint, ret, value| {
+                        // First signal handler wins
+                        *ret = value.clone();
+                        false
+                    })
+                    .build(),
+            ]
+        });
+
+        SIGNALS.as_ref()
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.set_element_flags(gst::ElementFlags::SINK);
+        obj.set_suppressed_flags(gst::ElementFlags::SINK | gst::ElementFlags::SOURCE);
+    }
+}
+
+impl GstObjectImpl for DashMP4Sink {}
+
+impl ElementImpl for DashMP4Sink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "DASH MP4 Sink",
+                "Sink/Muxer",
+                "Writes fragmented MP4 segments and a MPEG-DASH MPD",
+                "agent <agent@local>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::new_any();
+
+            ["video_%u", "audio_%u", "subtitle_%u"]
+                .into_iter()
+                .map(|name| {
+                    gst::PadTemplate::new(
+                        name,
+                        gst::PadDirection::Sink,
+                        gst::PadPresence::Request,
+                        &caps,
+                    )
+                    .unwrap()
+                })
+                .collect()
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if transition == gst::StateChange::ReadyToPaused {
+            let (target_duration, sync) = {
+                let settings = self.settings.lock().unwrap();
+                (settings.target_duration, settings.sync)
+            };
+
+            let mut state = self.state.lock().unwrap();
+            state.availability_start_time = None;
+            for stream in state.streams.values_mut() {
+                stream.mux.set_property(
+                    "fragment-duration",
+                    gst::ClockTime::from_seconds(target_duration as u64),
+                );
+                stream.appsink.set_sync(sync);
+
+                stream.next_number = 1;
+                stream.segments.clear();
+                stream.old_segment_locations.clear();
+                stream.bandwidth = 0;
+                stream.eos = false;
+            }
+        }
+
+        self.parent_change_state(transition)
+    }
+
+    fn request_new_pad(
+        &self,
+        templ: &gst::PadTemplate,
+        name: Option<&str>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        let mut state = self.state.lock().unwrap();
+
+        let serial = match templ.name_template() {
+            "video_%u" => &mut state.video_serial,
+            "audio_%u" => &mut state.audio_serial,
+            "subtitle_%u" => &mut state.subtitle_serial,
+            _ => return None,
+        };
+
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => {
+                let name = templ.name_template().replace("%u", &serial.to_string());
+                *serial += 1;
+                name
+            }
+        };
+
+        if state.streams.contains_key(&name) {
+            gst::error!(CAT, imp = self, "Pad {name} already exists");
+            return None;
+        }
+
+        let target_duration = self.settings.lock().unwrap().target_duration;
+        let sync = self.settings.lock().unwrap().sync;
+
+        let mux = gst::ElementFactory::make("dashmp4mux")
+            .name(format!("mux_{name}"))
+            .property(
+                "fragment-duration",
+                gst::ClockTime::from_seconds(target_duration as u64),
+            )
+            .build()
+            .ok()?;
+        let appsink = gst_app::AppSink::builder()
+            .buffer_list(true)
+            .sync(sync)
+            .name(format!("sink_{name}"))
+            .build();
+
+        let obj = self.obj();
+        obj.add_many([&mux, appsink.upcast_ref()]).unwrap();
+        mux.link(&appsink).unwrap();
+
+        let self_weak = self.downgrade();
+        let self_weak_eos = self.downgrade();
+        let stream_name = name.clone();
+        let stream_name_eos = name.clone();
+        appsink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |sink| {
+                    let Some(imp) = self_weak.upgrade() else {
+                        return Err(gst::FlowError::Eos);
+                    };
+
+                    let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    imp.on_new_sample(&stream_name, sample)
+                })
+                .eos(move |_sink| {
+                    let Some(imp) = self_weak_eos.upgrade() else {
+                        return;
+                    };
+
+                    imp.on_eos(&stream_name_eos);
+                })
+                .build(),
+        );
+
+        let pad = gst::GhostPad::builder_from_template(templ)
+            .name(name.as_str())
+            .build();
+        pad.set_target(Some(&mux.static_pad("sink").unwrap()))
+            .unwrap();
+
+        let self_weak = self.downgrade();
+        pad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+            let Some(imp) = self_weak.upgrade() else {
+                return gst::PadProbeReturn::Remove;
+            };
+            let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data else {
+                return gst::PadProbeReturn::Ok;
+            };
+
+            imp.handle_input_buffer(pad, buffer);
+
+            gst::PadProbeReturn::Ok
+        });
+
+        state.streams.insert(
+            name,
+            Stream {
+                pad: pad.clone(),
+                mux: mux.clone(),
+                appsink: appsink.clone(),
+                timescale: 0,
+                next_number: 1,
+                segments: VecDeque::new(),
+                old_segment_locations: VecDeque::new(),
+                bandwidth: 0,
+                eos: false,
+            },
+        );
+        drop(state);
+
+        pad.set_active(true).unwrap();
+        obj.add_pad(&pad).unwrap();
+
+        if obj.current_state() > gst::State::Null {
+            let _ = mux.sync_state_with_parent();
+            let _ = appsink.sync_state_with_parent();
+        }
+
+        Some(pad.upcast())
+    }
+
+    fn release_pad(&self, pad: &gst::Pad) {
+        let Some(stream) = self
+            .state
+            .lock()
+            .unwrap()
+            .streams
+            .remove(pad.name().as_str())
+        else {
+            return;
+        };
+
+        let obj = self.obj();
+        pad.set_active(false).unwrap();
+        obj.remove_pad(pad).unwrap();
+
+        let _ = stream.mux.set_state(gst::State::Null);
+        let _ = stream.appsink.set_state(gst::State::Null);
+        let _ = obj.remove_many([&stream.mux, stream.appsink.upcast_ref()]);
+
+        self.write_mpd();
+    }
+}
+
+impl BinImpl for DashMP4Sink {}
+
+impl DashMP4Sink {
+    /// Returns the directory of the MPD, which is the base for all segment locations.
+    fn base_dir(&self) -> path::PathBuf {
+        let settings = self.settings.lock().unwrap();
+
+        path::Path::new(&settings.mpd_location)
+            .parent()
+            .map(path::Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
+    fn write_to_stream(&self, signal: &str, location: &str, data: &[&[u8]]) -> Result<(), String> {
+        let stream = self
+            .obj()
+            .emit_by_name::<Option<gio::OutputStream>>(signal, &[&location])
+            .ok_or_else(|| format!("No stream to write {location}"))?;
+
+        let mut stream = stream.into_write();
+        for data in data {
+            stream.write_all(data).map_err(|err| err.to_string())?;
+        }
+        stream.flush().map_err(|err| err.to_string())?;
+
+        Ok(())
+    }
+
+    fn on_new_sample(
+        &self,
+        name: &str,
+        sample: gst::Sample,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut buffer_list = sample.buffer_list_owned().unwrap();
+        let mut first = buffer_list.get(0).unwrap();
+
+        let base_dir = self.base_dir();
+        let (init_location, location) = {
+            let settings = self.settings.lock().unwrap();
+            (settings.init_location.clone(), settings.location.clone())
+        };
+
+        if first
+            .flags()
+            .contains(gst::BufferFlags::DISCONT | gst::BufferFlags::HEADER)
+        {
+            let location = base_dir.join(expand_template(&init_location, name, None, None));
+            let map = first.map_readable().unwrap();
+            self.write_to_stream(SIGNAL_GET_INIT_STREAM, location.to_str().unwrap(), &[&map])
+                .map_err(|err| {
+                    gst::error!(CAT, imp = self, "Couldn't write init segment: {err}");
+                    gst::FlowError::Error
+                })?;
+            drop(map);
+
+            buffer_list.make_mut().remove(0..1);
+            if buffer_list.is_empty() {
+                return Ok(gst::FlowSuccess::Ok);
+            }
+
+            first = buffer_list.get(0).unwrap();
+        }
+
+        let segment = sample
+            .segment()
+            .unwrap()
+            .downcast_ref::<gst::ClockTime>()
+            .unwrap();
+        let Some(start) = segment.to_running_time(first.pts().unwrap()) else {
+            gst::error!(CAT, imp = self, "Segment starts before the segment");
+            return Err(gst::FlowError::Error);
+        };
+        let end = start + first.duration().unwrap();
+
+        let mut state = self.state.lock().unwrap();
+
+        if state.availability_start_time.is_none() {
+            state.availability_start_time = Some(self.availability_start_time());
+        }
+
+        let Some(stream) = state.streams.get_mut(name) else {
+            return Err(gst::FlowError::Flushing);
+        };
+
+        if stream.timescale == 0 {
+            stream.timescale = stream
+                .pad
+                .current_caps()
+                .and_then(|caps| {
+                    let s = caps.structure(0)?;
+                    if s.name().starts_with("audio/") {
+                        s.get::<i32>("rate").ok().map(|rate| rate as u64)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(90_000);
+        }
+
+        let timescale = stream.timescale;
+        let to_timescale = |t: gst::ClockTime| {
+            t.nseconds()
+                .mul_div_round(timescale, gst::ClockTime::SECOND.nseconds())
+                .unwrap()
+        };
+        let segment = Segment {
+            number: stream.next_number,
+            start: to_timescale(start),
+            duration: to_timescale(end) - to_timescale(start),
+        };
+        stream.next_number += 1;
+
+        let segment_location = base_dir.join(expand_template(
+            &location,
+            name,
+            Some(segment.number),
+            Some(segment.start),
+        ));
+
+        let maps = buffer_list
+            .iter()
+            .map(|buffer| buffer.map_readable().unwrap())
+            .collect::<Vec<_>>();
+        let data = maps.iter().map(|map| map.as_slice()).collect::<Vec<_>>();
+        let size = data.iter().map(|data| data.len() as u64).sum::<u64>();
+
+        self.write_to_stream(
+            SIGNAL_GET_FRAGMENT_STREAM,
+            segment_location.to_str().unwrap(),
+            &data,
+        )
+        .map_err(|err| {
+            gst::error!(CAT, imp = self, "Couldn't write segment: {err}");
+            gst::FlowError::Error
+        })?;
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Wrote segment {} of {name} with start {start} and duration {}",
+            segment_location.display(),
+            end - start,
+        );
+
+        let duration = end - start;
+        if !duration.is_zero() {
+            let bitrate = (size * 8)
+                .mul_div_ceil(gst::ClockTime::SECOND.nseconds(), duration.nseconds())
+                .unwrap();
+            stream.bandwidth = stream.bandwidth.max(bitrate);
+        }
+        stream.segments.push_back(segment);
+
+        // Remove old segments from a live MPD and delete them once enough segments are
+        // accumulated
+        let settings = self.settings.lock().unwrap();
+        let mut delete_locations = Vec::new();
+        if settings.dynamic && settings.window_size > 0 {
+            while stream.segments.len() > settings.window_size as usize {
+                let old = stream.segments.pop_front().unwrap();
+                let old_location = base_dir.join(expand_template(
+                    &location,
+                    name,
+                    Some(old.number),
+                    Some(old.start),
+                ));
+                stream
+                    .old_segment_locations
+                    .push_back(old_location.to_str().unwrap().to_string());
+            }
+
+            if settings.max_files > 0 {
+                while stream.old_segment_locations.len() > settings.max_files as usize {
+                    delete_locations.push(stream.old_segment_locations.pop_front().unwrap());
+                }
+            }
+        }
+        drop(settings);
+        drop(state);
+
+        for location in delete_locations {
+            if !self
+                .obj()
+                .emit_by_name::<bool>(SIGNAL_DELETE_FRAGMENT, &[&location])
+            {
+                gst::error!(CAT, imp = self, "Could not delete fragment");
+            }
+        }
+
+        self.write_mpd();
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn on_eos(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(stream) = state.streams.get_mut(name) {
+            stream.eos = true;
+        }
+        drop(state);
+
+        self.write_mpd();
+    }
+
+    /// Takes the availability start time from the UTC reference timestamp meta of an input
+    /// buffer, if any.
+    fn handle_input_buffer(&self, pad: &gst::Pad, buffer: &gst::BufferRef) {
+        let mut state = self.state.lock().unwrap();
+        if state.availability_start_time.is_some() {
+            return;
+        }
+
+        let Some(utc_time) = get_utc_time_from_buffer(buffer) else {
+            return;
+        };
+        let Some(running_time) = pad
+            .sticky_event::<gst::event::Segment>(0)
+            .and_then(|event| {
+                event
+                    .segment()
+                    .downcast_ref::<gst::ClockTime>()?
+                    .to_running_time(buffer.pts()?)
+            })
+        else {
+            return;
+        };
+
+        gst::debug!(
+            CAT,
+            obj = pad,
+            "Running time {running_time} corresponds to UTC time {utc_time}"
+        );
+
+        state.availability_start_time = utc_time.checked_sub(running_time).map(clock_time_to_utc);
+    }
+
+    /// Calculates the wall clock time corresponding to running time zero.
+    ///
+    /// With a realtime pipeline clock this is the base time. With any other clock the system time
+    /// is used instead.
+    fn availability_start_time(&self) -> chrono::DateTime<chrono::Utc> {
+        let obj = self.obj();
+        let clock = obj.clock();
+        let base_time = obj.base_time();
+
+        if let Some((clock, base_time)) = clock.as_ref().zip(base_time) {
+            let is_realtime = clock
+                .downcast_ref::<gst::SystemClock>()
+                .map_or(false, |clock| {
+                    clock.property::<gst::ClockType>("clock-type") == gst::ClockType::Realtime
+                });
+
+            if is_realtime {
+                return clock_time_to_utc(base_time);
+            }
+        }
+
+        let now_utc = chrono::Utc::now();
+        let Some(running_time) = clock
+            .zip(base_time)
+            .and_then(|(clock, base_time)| clock.time()?.checked_sub(base_time))
+        else {
+            return now_utc;
+        };
+
+        now_utc - chrono::Duration::nanoseconds(running_time.nseconds() as i64)
+    }
+
+    /// Creates the MPD from the current state of all streams.
+    fn mpd(&self, state: &State) -> Option<dash_mpd::MPD> {
+        let settings = self.settings.lock().unwrap();
+        let all_eos = state.streams.values().all(|stream| stream.eos);
+
+        let mut adaptation_sets =
+            BTreeMap::<(String, Option<String>), dash_mpd::AdaptationSet>::new();
+        let mut presentation_start = None::<gst::ClockTime>;
+        let mut presentation_end = None::<gst::ClockTime>;
+
+        for (name, stream) in &state.streams {
+            let (Some(caps), Some(first), Some(last)) = (
+                stream.pad.current_caps(),
+                stream.segments.front(),
+                stream.segments.back(),
+            ) else {
+                continue;
+            };
+            let s = caps.structure(0).unwrap();
+
+            let from_timescale = |t: u64| {
+                gst::ClockTime::SECOND
+                    .mul_div_round(t, stream.timescale)
+                    .unwrap()
+            };
+            let start = from_timescale(first.start);
+            let end = from_timescale(last.start + last.duration);
+            presentation_start = Some(presentation_start.map_or(start, |t| t.min(start)));
+            presentation_end = Some(presentation_end.map_or(end, |t| t.max(end)));
+
+            let content_type = if s.name().starts_with("video/") {
+                "video"
+            } else if s.name().starts_with("audio/") {
+                "audio"
+            } else {
+                "text"
+            };
+
+            let lang = (0..)
+                .map_while(|idx| stream.pad.sticky_event::<gst::event::Tag>(idx))
+                .find_map(|ev| {
+                    ev.tag()
+                        .get::<gst::tags::LanguageCode>()
+                        .map(|lang| lang.get().to_string())
+                });
+
+            let representation = dash_mpd::Representation {
+                id: Some(name.clone()),
+                codecs: gst_pbutils::codec_utils_caps_get_mime_codec(&caps)
+                    .ok()
+                    .map(String::from),
+                bandwidth: Some(stream.bandwidth.max(1)),
+                width: s.get::<i32>("width").ok().map(|width| width as u64),
+                height: s.get::<i32>("height").ok().map(|height| height as u64),
+                SegmentTemplate: Some(dash_mpd::SegmentTemplate {
+                    timescale: Some(stream.timescale),
+                    initialization: Some(settings.init_location.clone()),
+                    media: Some(settings.location.clone()),
+                    startNumber: Some(first.number),
+                    SegmentTimeline: Some(dash_mpd::SegmentTimeline {
+                        segments: segment_timeline(&stream.segments),
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+
+            let frame_rate = s
+                .get::<gst::Fraction>("framerate")
+                .ok()
+                .filter(|fps| fps.numer() > 0)
+                .map(|fps| format!("{}/{}", fps.numer(), fps.denom()));
+
+            adaptation_sets
+                .entry((content_type.to_string(), lang.clone()))
+                .or_insert_with(|| dash_mpd::AdaptationSet {
+                    contentType: Some(content_type.to_string()),
+                    mimeType: Some(
+                        if content_type == "text" {
+                            "application/mp4"
+                        } else if content_type == "video" {
+                            "video/mp4"
+                        } else {
+                            "audio/mp4"
+                        }
+                        .to_string(),
+                    ),
+                    lang,
+                    frameRate: frame_rate,
+                    segmentAlignment: Some(true),
+                    startWithSAP: Some(1),
+                    ..Default::default()
+                })
+                .representations
+                .push(representation);
+        }
+
+        if adaptation_sets.is_empty() {
+            return None;
+        }
+
+        let target_duration = std::time::Duration::from_secs(settings.target_duration as u64);
+        let media_presentation_duration = presentation_end
+            .opt_saturating_sub(presentation_start)
+            .map(|duration| std::time::Duration::from_nanos(duration.nseconds()));
+
+        let mut mpd = dash_mpd::MPD {
+            xmlns: Some("urn:mpeg:dash:schema:mpd:2011".to_string()),
+            profiles: Some("urn:mpeg:dash:profile:isoff-live:2011".to_string()),
+            minBufferTime: Some(target_duration),
+            periods: vec![dash_mpd::Period {
+                id: Some("0".to_string()),
+                start: Some(std::time::Duration::ZERO),
+                adaptations: adaptation_sets.into_values().collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        if settings.dynamic {
+            mpd.mpdtype = Some("dynamic".to_string());
+            mpd.availabilityStartTime = state.availability_start_time;
+            mpd.publishTime = Some(chrono::Utc::now());
+            if all_eos {
+                // No further updates, clients can play until the end
+                mpd.mediaPresentationDuration = media_presentation_duration;
+            } else {
+                mpd.minimumUpdatePeriod = Some(target_duration);
+                if settings.window_size > 0 {
+                    mpd.timeShiftBufferDepth = Some(target_duration * settings.window_size);
+                }
+            }
+        } else {
+            mpd.mpdtype = Some("static".to_string());
+            mpd.mediaPresentationDuration = media_presentation_duration;
+        }
+
+        Some(mpd)
+    }
+
+    /// Writes the MPD. A static MPD is only written once all streams are finished.
+    fn write_mpd(&self) {
+        let (dynamic, mpd_location) = {
+            let settings = self.settings.lock().unwrap();
+            (settings.dynamic, settings.mpd_location.clone())
+        };
+
+        let state = self.state.lock().unwrap();
+        if !dynamic && !state.streams.values().all(|stream| stream.eos) {
+            return;
+        }
+
+        let Some(mpd) = self.mpd(&state) else {
+            gst::debug!(CAT, imp = self, "No segments yet");
+            return;
+        };
+
+        use serde::ser::Serialize;
+
+        let mut xml = String::new();
+        let mut ser = quick_xml::se::Serializer::new(&mut xml);
+        ser.indent(' ', 2);
+        if let Err(err) = mpd.serialize(ser) {
+            gst::error!(CAT, imp = self, "Failed to serialize MPD: {err}");
+            return;
+        }
+
+        let manifest = format!(
+            r###"<?xml version="1.0" encoding="UTF-8"?>
+{xml}
+"###
+        );
+
+        if let Err(err) =
+            self.write_to_stream(SIGNAL_GET_MPD_STREAM, &mpd_location, &[manifest.as_bytes()])
+        {
+            gst::error!(CAT, imp = self, "Couldn't write MPD: {err}");
+            return;
+        }
+
+        gst::debug!(CAT, imp = self, "Wrote new MPD");
+    }
+
+    fn new_file_stream<P>(&self, location: &P) -> Result<gio::OutputStream, String>
+    where
+        P: AsRef<path::Path>,
+    {
+        let location = location.as_ref();
+
+        if let Some(dir) = location.parent() {
+            if !dir.as_os_str().is_empty() {
+                let _ = fs::create_dir_all(dir);
+            }
+        }
+
+        let file = fs::File::create(location).map_err(move |err| {
+            let error_msg = gst::error_msg!(
+                gst::ResourceError::OpenWrite,
+                [
+                    "Could not open file {} for writing: {}",
+                    location.display(),
+                    err.to_string(),
+                ]
+            );
+            self.post_error_message(error_msg);
+            err.to_string()
+        })?;
+        Ok(gio::WriteOutputStream::new(file).upcast())
+    }
+
+    fn delete_fragment<P>(&self, location: &P)
+    where
+        P: AsRef<path::Path>,
+    {
+        let _ = fs::remove_file(location).map_err(|err| {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Could not delete segment file: {}",
+                err.to_string()
+            );
+        });
+    }
+}
+
+/// Builds the `SegmentTimeline`, compressing consecutive segments with the same duration into a
+/// single repeated entry.
+/// Returns the UTC time of the buffer in the UNIX epoch.
+fn get_utc_time_from_buffer(buffer: &gst::BufferRef) -> Option<gst::ClockTime> {
+    buffer
+        .iter_meta::<gst::ReferenceTimestampMeta>()
+        .find_map(|meta| {
+            if meta.reference().can_intersect(&UNIX_CAPS) {
+                Some(meta.timestamp())
+            } else if meta.reference().can_intersect(&NTP_CAPS) {
+                meta.timestamp().checked_sub(NTP_UNIX_OFFSET.seconds())
+            } else {
+                None
+            }
+        })
+}
+
+/// Converts a time in the UNIX epoch to a UTC date.
+fn clock_time_to_utc(time: gst::ClockTime) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::UNIX_EPOCH + chrono::Duration::nanoseconds(time.nseconds() as i64)
+}
+
+fn segment_timeline(segments: &VecDeque<Segment>) -> Vec<dash_mpd::S> {
+    let mut timeline = Vec::<dash_mpd::S>::new();
+    let mut next_start = None;
+
+    for segment in segments {
+        let contiguous = next_start == Some(segment.start);
+        next_start = Some(segment.start + segment.duration);
+
+        if contiguous {
+            if let Some(last) = timeline.last_mut() {
+                if last.d == segment.duration {
+                    last.r = Some(last.r.unwrap_or(0) + 1);
+                    continue;
+                }
+            }
+        }
+
+        timeline.push(dash_mpd::S {
+            t: (!contiguous).then_some(segment.start),
+            d: segment.duration,
+            ..Default::default()
+        });
+    }
+
+    timeline
+}
+
+/// Expands the DASH template identifiers `$RepresentationID$`, `$Number$` and `$Time$`, the
+/// latter two optionally with a `%0<width>d` format tag, and `$$`.
+fn expand_template(
+    template: &str,
+    representation_id: &str,
+    number: Option<u64>,
+    time: Option<u64>,
+) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut parts = template.split('$');
+
+    // Identifiers are at odd positions between two `$`
+    if let Some(part) = parts.next() {
+        result.push_str(part);
+    }
+    while let Some(identifier) = parts.next() {
+        let Some(literal) = parts.next() else {
+            // Unterminated identifier, keep as is
+            result.push('$');
+            result.push_str(identifier);
+            break;
+        };
+
+        let (name, width) = match identifier.split_once("%0") {
+            Some((name, format)) => (
+                name,
+                format
+                    .strip_suffix('d')
+                    .and_then(|width| width.parse::<usize>().ok()),
+            ),
+            None => (identifier, None),
+        };
+
+        let value = match name {
+            "" => Some(String::from("$")),
+            "RepresentationID" => Some(representation_id.to_string()),
+            "Number" => number.map(|number| number.to_string()),
+            "Time" => time.map(|time| time.to_string()),
+            _ => None,
+        };
+
+        match (value, width) {
+            (Some(value), Some(width)) => result.push_str(&format!("{value:0>width$}")),
+            (Some(value), None) => result.push_str(&value),
+            (None, _) => {
+                result.push('$');
+                result.push_str(identifier);
+                result.push('$');
+            }
+        }
+
+        result.push_str(literal);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_template() {
+        assert_eq!(
+            expand_template(DEFAULT_LOCATION, "video_0", Some(3), Some(1000)),
+            "segment_video_0_00003.m4s"
+        );
+        assert_eq!(
+            expand_template(DEFAULT_INIT_LOCATION, "audio_1", None, None),
+            "init_audio_1.mp4"
+        );
+        assert_eq!(
+            expand_template("$RepresentationID$/$Time$.m4s", "a", Some(1), Some(90000)),
+            "a/90000.m4s"
+        );
+        assert_eq!(expand_template("a$$b", "a", None, None), "a$b");
+        assert_eq!(expand_template("a$Foo$b", "a", None, None), "a$Foo$b");
+    }
+
+    #[test]
+    fn test_segment_timeline() {
+        let segments = [
+            (1, 0, 10),
+            (2, 10, 10),
+            (3, 20, 10),
+            (4, 30, 5),
+            (5, 40, 10),
+        ]
+        .into_iter()
+        .map(|(number, start, duration)| Segment {
+            number,
+            start,
+            duration,
+        })
+        .collect::<VecDeque<_>>();
+
+        let timeline = segment_timeline(&segments)
+            .into_iter()
+            .map(|s| (s.t, s.d, s.r))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            timeline,
+            vec![
+                (Some(0), 10, Some(2)),
+                (None, 5, None),
+                (Some(40), 10, None)
+            ]
+        );
+    }
+}
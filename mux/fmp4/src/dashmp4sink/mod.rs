@@ -0,0 +1,19 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+glib::wrapper! {
+    pub(crate) struct DashMP4Sink(ObjectSubclass<imp::DashMP4Sink>) @extends gst::Bin, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "dashmp4sink",
+        gst::Rank::NONE,
+        DashMP4Sink::static_type(),
+    )
+}
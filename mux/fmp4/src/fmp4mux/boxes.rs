@@ -42,6 +42,9 @@ const FULL_BOX_VERSION_1: u8 = 1;
 
 const FULL_BOX_FLAGS_NONE: u32 = 0;
 
+/// UTC time of the `prft` box is the capture time of the sample.
+const PRFT_FLAGS_CAPTURE_TIME: u32 = 24;
+
 fn write_full_box<T, F: FnOnce(&mut Vec<u8>) -> Result<T, Error>>(
     vec: &mut Vec<u8>,
     fourcc: impl std::borrow::Borrow<[u8; 4]>,
@@ -309,6 +312,12 @@ fn cmaf_brands_from_caps(caps: &gst::CapsRef, compatible_brands: &mut Vec<&'stat
                 }
             }
         }
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" | "text/x-raw" => {
+            compatible_brands.push(b"cwvt");
+        }
+        "application/ttml+xml" => {
+            compatible_brands.push(b"im1t");
+        }
         _ => (),
     }
 }
@@ -434,6 +443,19 @@ fn write_moov(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), E
     }
     write_box(v, b"mvex", |v| write_mvex(v, cfg))?;
 
+    if let Some(ref encryption) = cfg.encryption {
+        for pssh in &encryption.pssh {
+            let map = pssh.map_readable().context("pssh not mappable")?;
+            if map.len() < 8
+                || &map[4..8] != b"pssh"
+                || u32::from_be_bytes(map[..4].try_into().unwrap()) as usize != map.len()
+            {
+                bail!("invalid pssh box");
+            }
+            v.extend_from_slice(&map);
+        }
+    }
+
     Ok(())
 }
 
@@ -754,6 +776,10 @@ fn write_hdlr(
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
         | "audio/x-adpcm" => (b"soun", b"SoundHandler\0".as_slice()),
         "application/x-onvif-metadata" => (b"meta", b"MetadataHandler\0".as_slice()),
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" | "text/x-raw" => {
+            (b"text", b"TextHandler\0".as_slice())
+        }
+        "application/ttml+xml" => (b"subt", b"SubtitleHandler\0".as_slice()),
         _ => unreachable!(),
     };
 
@@ -788,11 +814,19 @@ fn write_minf(
                 write_smhd(v, cfg)
             })?
         }
-        "application/x-onvif-metadata" => {
+        "application/x-onvif-metadata"
+        | "application/x-subtitle-vtt"
+        | "application/x-subtitle-vtt-fragmented"
+        | "text/x-raw" => {
             write_full_box(v, b"nmhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
                 Ok(())
             })?
         }
+        "application/ttml+xml" => {
+            write_full_box(v, b"sthd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
+                Ok(())
+            })?
+        }
         _ => unreachable!(),
     }
 
@@ -895,6 +929,10 @@ fn write_stsd(
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
         | "audio/x-adpcm" => write_audio_sample_entry(v, cfg, stream)?,
         "application/x-onvif-metadata" => write_xml_meta_data_sample_entry(v, cfg, stream)?,
+        "application/x-subtitle-vtt"
+        | "application/x-subtitle-vtt-fragmented"
+        | "text/x-raw"
+        | "application/ttml+xml" => write_text_sample_entry(v, cfg, stream)?,
         _ => unreachable!(),
     }
 
@@ -919,7 +957,7 @@ fn write_sample_entry_box<T, F: FnOnce(&mut Vec<u8>) -> Result<T, Error>>(
 
 fn write_visual_sample_entry(
     v: &mut Vec<u8>,
-    _cfg: &super::HeaderConfiguration,
+    cfg: &super::HeaderConfiguration,
     stream: &super::HeaderStream,
 ) -> Result<(), Error> {
     let s = stream.caps.structure(0).unwrap();
@@ -946,8 +984,13 @@ fn write_visual_sample_entry(
         "video/x-av1" => b"av01",
         _ => unreachable!(),
     };
+    let sample_entry_fourcc = if cfg.encryption.is_some() {
+        b"encv"
+    } else {
+        fourcc
+    };
 
-    write_sample_entry_box(v, fourcc, move |v| {
+    write_sample_entry_box(v, sample_entry_fourcc, move |v| {
         // pre-defined
         v.extend([0u8; 2]);
         // Reserved
@@ -1256,6 +1299,10 @@ fn write_visual_sample_entry(
 
         // TODO: write btrt bitrate box based on tags
 
+        if let Some(ref encryption) = cfg.encryption {
+            write_box(v, b"sinf", move |v| write_sinf(v, encryption, fourcc, true))?;
+        }
+
         Ok(())
     })?;
 
@@ -1302,7 +1349,7 @@ fn av1_tier(tier: Option<&str>) -> u8 {
 
 fn write_audio_sample_entry(
     v: &mut Vec<u8>,
-    _cfg: &super::HeaderConfiguration,
+    cfg: &super::HeaderConfiguration,
     stream: &super::HeaderStream,
 ) -> Result<(), Error> {
     let s = stream.caps.structure(0).unwrap();
@@ -1334,8 +1381,13 @@ fn write_audio_sample_entry(
         .context("FLAC metadata error")?,
         _ => 16u16,
     };
+    let sample_entry_fourcc = if cfg.encryption.is_some() {
+        b"enca"
+    } else {
+        fourcc
+    };
 
-    write_sample_entry_box(v, fourcc, move |v| {
+    write_sample_entry_box(v, sample_entry_fourcc, move |v| {
         // Reserved
         v.extend([0u8; 2 * 4]);
 
@@ -1403,12 +1455,90 @@ fn write_audio_sample_entry(
 
         // TODO: chnl box for channel ordering? probably not needed for AAC
 
+        if let Some(ref encryption) = cfg.encryption {
+            write_box(v, b"sinf", move |v| {
+                write_sinf(v, encryption, fourcc, false)
+            })?;
+        }
+
         Ok(())
     })?;
 
     Ok(())
 }
 
+fn write_sinf(
+    v: &mut Vec<u8>,
+    encryption: &super::EncryptionConfiguration,
+    original_fourcc: &[u8; 4],
+    video: bool,
+) -> Result<(), Error> {
+    write_box(v, b"frma", |v| {
+        v.extend(original_fourcc);
+        Ok(())
+    })?;
+
+    write_full_box(v, b"schm", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+        // Scheme type
+        v.extend(match encryption.scheme {
+            super::EncryptionScheme::Cenc => b"cenc",
+            super::EncryptionScheme::Cbcs => b"cbcs",
+            super::EncryptionScheme::None => unreachable!(),
+        });
+        // Scheme version
+        v.extend(0x0001_0000u32.to_be_bytes());
+        Ok(())
+    })?;
+
+    write_box(v, b"schi", |v| {
+        let version = match encryption.scheme {
+            super::EncryptionScheme::Cenc => FULL_BOX_VERSION_0,
+            _ => FULL_BOX_VERSION_1,
+        };
+
+        write_full_box(v, b"tenc", version, FULL_BOX_FLAGS_NONE, |v| {
+            // Reserved
+            v.push(0);
+
+            // Default crypt / skip byte block
+            if version == FULL_BOX_VERSION_0 {
+                v.push(0);
+            } else {
+                let (crypt, skip) = if video {
+                    super::cenc::CBCS_VIDEO_PATTERN
+                } else {
+                    (0, 0)
+                };
+                v.push((crypt << 4) | skip);
+            }
+
+            // Default is protected
+            v.push(1);
+
+            match encryption.scheme {
+                super::EncryptionScheme::Cenc => {
+                    // Default per-sample IV size
+                    v.push(super::cenc::CENC_PER_SAMPLE_IV_SIZE);
+                    // Default KID
+                    v.extend(encryption.key_id);
+                }
+                super::EncryptionScheme::Cbcs => {
+                    // Default per-sample IV size, constant IV is used
+                    v.push(0);
+                    // Default KID
+                    v.extend(encryption.key_id);
+                    // Default constant IV
+                    v.push(encryption.constant_iv.len() as u8);
+                    v.extend(encryption.constant_iv);
+                }
+                super::EncryptionScheme::None => unreachable!(),
+            }
+
+            Ok(())
+        })
+    })
+}
+
 fn write_esds_aac(v: &mut Vec<u8>, codec_data: &[u8]) -> Result<(), Error> {
     let calculate_len = |mut len| {
         if len > 260144641 {
@@ -1628,6 +1758,201 @@ fn write_xml_meta_data_sample_entry(
     Ok(())
 }
 
+fn write_text_sample_entry(
+    v: &mut Vec<u8>,
+    _cfg: &super::HeaderConfiguration,
+    stream: &super::HeaderStream,
+) -> Result<(), Error> {
+    let s = stream.caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" | "text/x-raw" => {
+            write_sample_entry_box(v, b"wvtt", move |v| {
+                // WebVTT file header without any cues
+                write_box(v, b"vttC", |v| {
+                    v.extend(b"WEBVTT");
+                    Ok(())
+                })
+            })?;
+        }
+        "application/ttml+xml" => {
+            write_sample_entry_box(v, b"stpp", move |v| {
+                // namespace
+                v.extend(TTML_NAMESPACE.as_bytes());
+                v.push(0);
+
+                // schema_location, empty string
+                v.push(0);
+
+                // auxiliary_mime_types, empty string
+                v.push(0);
+
+                Ok(())
+            })?;
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+const TTML_NAMESPACE: &str = "http://www.w3.org/ns/ttml";
+
+/// Converts a buffer of a text stream into a sample for the stream's sample entry.
+///
+/// Gap buffers are converted into empty samples. Returns `None` if the buffer contains nothing
+/// that has to be stored, e.g. only a WebVTT header.
+pub(super) fn create_text_sample(
+    caps: &gst::CapsRef,
+    buffer: &gst::BufferRef,
+) -> Result<Option<gst::Buffer>, Error> {
+    let data = if buffer.flags().contains(gst::BufferFlags::GAP) && buffer.size() == 0 {
+        Some(empty_text_sample(caps))
+    } else {
+        let map = buffer.map_readable().context("buffer not mappable")?;
+        let text = std::str::from_utf8(&map).context("text not valid UTF-8")?;
+
+        let s = caps.structure(0).unwrap();
+        match s.name().as_str() {
+            "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+                create_wvtt_sample(text)?
+            }
+            "text/x-raw" => {
+                let text = text.trim_end_matches(['\n', '\0']);
+                if text.is_empty() {
+                    None
+                } else {
+                    let mut v = vec![];
+                    write_vttc(&mut v, None, "", &escape_vtt_cue_text(text))?;
+                    Some(v)
+                }
+            }
+            "application/ttml+xml" => {
+                if text.trim().is_empty() {
+                    None
+                } else {
+                    Some(map.to_vec())
+                }
+            }
+            _ => unreachable!(),
+        }
+    };
+
+    Ok(data.map(|data| {
+        let mut sample = gst::Buffer::from_mut_slice(data);
+        {
+            let sample = sample.get_mut().unwrap();
+            sample.set_pts(buffer.pts());
+            sample.set_dts(buffer.dts());
+            sample.set_duration(buffer.duration());
+        }
+        sample
+    }))
+}
+
+/// Creates an empty sample for a text stream, to be used for the time between cues.
+pub(super) fn create_empty_text_sample(caps: &gst::CapsRef) -> gst::Buffer {
+    gst::Buffer::from_mut_slice(empty_text_sample(caps))
+}
+
+fn empty_text_sample(caps: &gst::CapsRef) -> Vec<u8> {
+    let s = caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" | "text/x-raw" => {
+            let mut v = vec![];
+            write_box(&mut v, b"vtte", |_v| Ok(())).unwrap();
+            v
+        }
+        "application/ttml+xml" => format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tt xmlns=\"{TTML_NAMESPACE}\"><body/></tt>\n"
+        )
+        .into_bytes(),
+        _ => unreachable!(),
+    }
+}
+
+/// Converts WebVTT cues into `vttc` boxes as defined by ISO/IEC 14496-30.
+fn create_wvtt_sample(text: &str) -> Result<Option<Vec<u8>>, Error> {
+    let text = text.replace("\r\n", "\n");
+
+    let mut v = vec![];
+    for block in text.split("\n\n") {
+        let block = block.trim_matches('\n');
+        let mut lines = block.lines();
+        let Some(first_line) = lines.next() else {
+            continue;
+        };
+
+        // Skip file header, comments and any other blocks that are not cues
+        if ["WEBVTT", "NOTE", "STYLE", "REGION"]
+            .iter()
+            .any(|prefix| first_line.starts_with(prefix))
+        {
+            continue;
+        }
+
+        let (id, timings) = if first_line.contains("-->") {
+            (None, first_line)
+        } else {
+            match lines.next() {
+                Some(line) if line.contains("-->") => (Some(first_line), line),
+                _ => bail!("invalid WebVTT cue"),
+            }
+        };
+
+        // Everything after the end time are the cue settings
+        let (_, end) = timings.split_once("-->").unwrap();
+        let settings = end
+            .trim()
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .map(|(_, settings)| settings.trim())
+            .unwrap_or("");
+
+        let payload = lines.collect::<Vec<_>>().join("\n");
+
+        write_vttc(&mut v, id, settings, &payload)?;
+    }
+
+    if v.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(v))
+    }
+}
+
+fn write_vttc(
+    v: &mut Vec<u8>,
+    id: Option<&str>,
+    settings: &str,
+    payload: &str,
+) -> Result<(), Error> {
+    write_box(v, b"vttc", |v| {
+        if let Some(id) = id {
+            write_box(v, b"iden", |v| {
+                v.extend(id.as_bytes());
+                Ok(())
+            })?;
+        }
+
+        if !settings.is_empty() {
+            write_box(v, b"sttg", |v| {
+                v.extend(settings.as_bytes());
+                Ok(())
+            })?;
+        }
+
+        write_box(v, b"payl", |v| {
+            v.extend(payload.as_bytes());
+            Ok(())
+        })
+    })
+}
+
+fn escape_vtt_cue_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
 fn write_stts(v: &mut Vec<u8>, _cfg: &super::HeaderConfiguration) -> Result<(), Error> {
     // Entry count
     v.extend(0u32.to_be_bytes());
@@ -1754,7 +2079,23 @@ pub(super) fn create_fmp4_fragment_header(
         })?;
     }
 
-    let styp_len = v.len();
+    if let Some(ref prft) = cfg.producer_reference_time {
+        write_full_box(
+            &mut v,
+            b"prft",
+            FULL_BOX_VERSION_1,
+            PRFT_FLAGS_CAPTURE_TIME,
+            |v| write_prft(v, &cfg, prft),
+        )?;
+    }
+
+    for emsg in cfg.event_messages {
+        write_full_box(&mut v, b"emsg", emsg.version, FULL_BOX_FLAGS_NONE, |v| {
+            write_emsg(v, &cfg, emsg)
+        })?;
+    }
+
+    let moof_offset = v.len();
 
     let data_offset_offsets = write_box(&mut v, b"moof", |v| write_moof(v, &cfg))?;
 
@@ -1772,7 +2113,7 @@ pub(super) fn create_fmp4_fragment_header(
         v.extend((size + 16).to_be_bytes());
     }
 
-    let data_offset = v.len() - styp_len;
+    let data_offset = v.len() - moof_offset;
     for data_offset_offset in data_offset_offsets {
         let val = u32::from_be_bytes(v[data_offset_offset..][..4].try_into()?)
             .checked_add(u32::try_from(data_offset)?)
@@ -1780,13 +2121,113 @@ pub(super) fn create_fmp4_fragment_header(
         v[data_offset_offset..][..4].copy_from_slice(&val.to_be_bytes());
     }
 
-    Ok((gst::Buffer::from_mut_slice(v), styp_len as u64))
+    Ok((gst::Buffer::from_mut_slice(v), moof_offset as u64))
+}
+
+fn write_prft(
+    v: &mut Vec<u8>,
+    cfg: &super::FragmentHeaderConfiguration,
+    prft: &super::ProducerReferenceTime,
+) -> Result<(), Error> {
+    let timescale = fragment_header_stream_to_timescale(&cfg.streams[prft.idx]);
+
+    // Reference track ID
+    v.extend((prft.idx as u32 + 1).to_be_bytes());
+
+    // NTP timestamp in 32.32 fixed point format
+    let ntp_seconds = prft.ntp_time.seconds();
+    let ntp_fraction = (prft.ntp_time.nseconds() % gst::ClockTime::SECOND.nseconds())
+        .mul_div_floor(1 << 32, gst::ClockTime::SECOND.nseconds())
+        .unwrap();
+    v.extend(((ntp_seconds << 32) | ntp_fraction).to_be_bytes());
+
+    // Media time
+    v.extend(
+        prft.media_time
+            .nseconds()
+            .mul_div_floor(timescale as u64, gst::ClockTime::SECOND.nseconds())
+            .context("too big media time")?
+            .to_be_bytes(),
+    );
+
+    Ok(())
+}
+
+fn write_emsg(
+    v: &mut Vec<u8>,
+    cfg: &super::FragmentHeaderConfiguration,
+    emsg: &super::EventMessage,
+) -> Result<(), Error> {
+    let timescale = fragment_header_stream_to_timescale(&cfg.streams[emsg.idx]);
+
+    let presentation_time = emsg
+        .presentation_time
+        .nseconds()
+        .mul_div_floor(timescale as u64, gst::ClockTime::SECOND.nseconds())
+        .context("too big presentation time")?;
+    let duration = match emsg.duration {
+        Some(duration) => u32::try_from(
+            duration
+                .nseconds()
+                .mul_div_floor(timescale as u64, gst::ClockTime::SECOND.nseconds())
+                .context("too big event duration")?,
+        )
+        .context("too big event duration")?,
+        None => u32::MAX,
+    };
+
+    let write_strings = |v: &mut Vec<u8>| {
+        v.extend(emsg.scheme_id_uri.as_bytes());
+        v.push(0);
+        v.extend(emsg.value.as_bytes());
+        v.push(0);
+    };
+
+    if emsg.version == 0 {
+        write_strings(v);
+
+        // Timescale
+        v.extend(timescale.to_be_bytes());
+        // Presentation time delta
+        v.extend(
+            u32::try_from(presentation_time)
+                .context("too big presentation time delta")?
+                .to_be_bytes(),
+        );
+        // Event duration
+        v.extend(duration.to_be_bytes());
+        // ID
+        v.extend(emsg.id.to_be_bytes());
+    } else {
+        // Timescale
+        v.extend(timescale.to_be_bytes());
+        // Presentation time
+        v.extend(presentation_time.to_be_bytes());
+        // Event duration
+        v.extend(duration.to_be_bytes());
+        // ID
+        v.extend(emsg.id.to_be_bytes());
+
+        write_strings(v);
+    }
+
+    // Message data
+    let map = emsg
+        .message_data
+        .map_readable()
+        .context("message data not mappable")?;
+    v.extend_from_slice(&map);
+
+    Ok(())
 }
 
 fn write_moof(
     v: &mut Vec<u8>,
     cfg: &super::FragmentHeaderConfiguration,
 ) -> Result<Vec<usize>, Error> {
+    // Start of the moof box, after its header was written already
+    let moof_start = v.len() - 8;
+
     write_full_box(v, b"mfhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
         write_mfhd(v, cfg)
     })?;
@@ -1799,7 +2240,7 @@ fn write_moof(
         }
 
         write_box(v, b"traf", |v| {
-            write_traf(v, cfg, &mut data_offset_offsets, idx, stream)
+            write_traf(v, cfg, moof_start, &mut data_offset_offsets, idx, stream)
         })?;
     }
 
@@ -1862,6 +2303,8 @@ const SAMPLE_SIZE_PRESENT: u32 = 0x2_00;
 const SAMPLE_FLAGS_PRESENT: u32 = 0x4_00;
 const SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT: u32 = 0x8_00;
 
+const USE_SUBSAMPLE_ENCRYPTION: u32 = 0x2;
+
 #[allow(clippy::type_complexity)]
 fn analyze_buffers(
     cfg: &super::FragmentHeaderConfiguration,
@@ -1901,6 +2344,7 @@ fn analyze_buffers(
         timestamp: _timestamp,
         duration: sample_duration,
         composition_time_offset,
+        sample_encryption: _,
     } in cfg.buffers.iter().filter(|b| b.idx == idx)
     {
         if size.is_none() {
@@ -1996,6 +2440,7 @@ fn analyze_buffers(
 fn write_traf(
     v: &mut Vec<u8>,
     cfg: &super::FragmentHeaderConfiguration,
+    moof_start: usize,
     data_offset_offsets: &mut Vec<usize>,
     idx: usize,
     stream: &super::FragmentHeaderStream,
@@ -2067,7 +2512,112 @@ fn write_traf(
         tr_flags &= !FIRST_SAMPLE_FLAGS_PRESENT;
     }
 
-    // TODO: saio, saiz, sbgp, sgpd, subs?
+    write_sample_encryption(v, cfg, moof_start, idx)?;
+
+    // TODO: sbgp, sgpd, subs?
+
+    Ok(())
+}
+
+/// Writes `saiz`, `saio` and `senc` boxes for encrypted tracks.
+fn write_sample_encryption(
+    v: &mut Vec<u8>,
+    cfg: &super::FragmentHeaderConfiguration,
+    moof_start: usize,
+    idx: usize,
+) -> Result<(), Error> {
+    let sample_encryptions = cfg
+        .buffers
+        .iter()
+        .filter(|b| b.idx == idx)
+        .map(|b| b.sample_encryption.as_ref())
+        .collect::<Option<Vec<_>>>();
+    let Some(sample_encryptions) = sample_encryptions else {
+        return Ok(());
+    };
+
+    let use_subsamples = sample_encryptions.iter().any(|e| !e.subsamples.is_empty());
+
+    let info_sizes = sample_encryptions
+        .iter()
+        .map(|e| {
+            let size = e.iv.len()
+                + if use_subsamples {
+                    2 + 6 * e.subsamples.len()
+                } else {
+                    0
+                };
+            u8::try_from(size).context("too many subsamples")
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Nothing to store if a constant IV is used and all samples are fully protected
+    if info_sizes.iter().all(|s| *s == 0) {
+        return Ok(());
+    }
+
+    write_full_box(v, b"saiz", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+        let default_size = if info_sizes.iter().all(|s| *s == info_sizes[0]) {
+            info_sizes[0]
+        } else {
+            0
+        };
+
+        // Default sample info size
+        v.push(default_size);
+        // Sample count
+        v.extend((info_sizes.len() as u32).to_be_bytes());
+        if default_size == 0 {
+            v.extend(&info_sizes);
+        }
+
+        Ok(())
+    })?;
+
+    let saio_offset_offset =
+        write_full_box(v, b"saio", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+            // Entry count
+            v.extend(1u32.to_be_bytes());
+
+            // Offset relative to the moof, will be rewritten later
+            let offset_offset = v.len();
+            v.extend(0u32.to_be_bytes());
+
+            Ok(offset_offset)
+        })?;
+
+    let senc_data_start = write_full_box(
+        v,
+        b"senc",
+        FULL_BOX_VERSION_0,
+        if use_subsamples {
+            USE_SUBSAMPLE_ENCRYPTION
+        } else {
+            FULL_BOX_FLAGS_NONE
+        },
+        |v| {
+            // Sample count
+            v.extend((sample_encryptions.len() as u32).to_be_bytes());
+
+            let senc_data_start = v.len();
+            for e in &sample_encryptions {
+                v.extend(&e.iv);
+
+                if use_subsamples {
+                    v.extend((e.subsamples.len() as u16).to_be_bytes());
+                    for (clear, protected) in &e.subsamples {
+                        v.extend(clear.to_be_bytes());
+                        v.extend(protected.to_be_bytes());
+                    }
+                }
+            }
+
+            Ok(senc_data_start)
+        },
+    )?;
+
+    let offset = u32::try_from(senc_data_start - moof_start).context("too big moof")?;
+    v[saio_offset_offset..][..4].copy_from_slice(&offset.to_be_bytes());
 
     Ok(())
 }
@@ -2145,6 +2695,7 @@ fn write_trun(
         timestamp: _timestamp,
         duration,
         composition_time_offset,
+        sample_encryption: _,
     } in buffers.iter()
     {
         if (tr_flags & SAMPLE_DURATION_PRESENT) != 0 {
@@ -0,0 +1,506 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Common Encryption (ISO/IEC 23001-7) sample encryption for the `cenc` and `cbcs` schemes.
+
+use aes::cipher::{generic_array::GenericArray, BlockEncryptMut, KeyIvInit, StreamCipher};
+use aes::Aes128;
+use anyhow::{bail, Context, Error};
+use bitstream_io::{BigEndian, BitReader};
+use std::io::Cursor;
+
+use super::obu::{ObuType, SizedObu};
+use super::slice_header::{AvcParameterSets, HevcParameterSets};
+use super::{EncryptionScheme, SampleEncryption};
+
+type Aes128Ctr = ctr::Ctr64BE<Aes128>;
+type Aes128CbcEnc = cbc::Encryptor<Aes128>;
+
+const BLOCK_SIZE: usize = 16;
+
+/// Size of the per-sample IVs for the `cenc` scheme.
+pub(crate) const CENC_PER_SAMPLE_IV_SIZE: u8 = 8;
+
+/// Encryption pattern (crypt, skip) in 16 byte blocks for `cbcs` video tracks.
+pub(crate) const CBCS_VIDEO_PATTERN: (u8, u8) = (1, 9);
+
+/// How the samples of a track are split into clear and protected ranges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TrackKind {
+    /// Track is not encrypted.
+    Clear,
+    /// The whole sample is protected.
+    Audio,
+    /// Length-prefixed H.264 NAL units.
+    Avc { nal_length_size: usize },
+    /// Length-prefixed H.265 NAL units.
+    Hevc { nal_length_size: usize },
+    /// AV1 OBUs with size fields.
+    Av1,
+}
+
+impl TrackKind {
+    /// Figures out how to encrypt a track from its caps.
+    pub(crate) fn from_caps(caps: &gst::CapsRef) -> Result<Self, Error> {
+        let s = caps.structure(0).unwrap();
+
+        match s.name().as_str() {
+            "video/x-h264" | "video/x-h265" => {
+                let codec_data = s
+                    .get::<&gst::BufferRef>("codec_data")
+                    .context("no codec_data")?;
+                let map = codec_data
+                    .map_readable()
+                    .context("codec_data not mappable")?;
+
+                if s.name() == "video/x-h264" {
+                    if map.len() < 5 {
+                        bail!("too small codec_data");
+                    }
+                    Ok(TrackKind::Avc {
+                        nal_length_size: (map[4] & 0x03) as usize + 1,
+                    })
+                } else {
+                    if map.len() < 22 {
+                        bail!("too small codec_data");
+                    }
+                    Ok(TrackKind::Hevc {
+                        nal_length_size: (map[21] & 0x03) as usize + 1,
+                    })
+                }
+            }
+            "video/x-av1" => Ok(TrackKind::Av1),
+            "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
+            | "audio/x-adpcm" => Ok(TrackKind::Audio),
+            "application/x-onvif-metadata"
+            | "application/x-subtitle-vtt"
+            | "application/x-subtitle-vtt-fragmented"
+            | "text/x-raw"
+            | "application/ttml+xml" => Ok(TrackKind::Clear),
+            name => bail!("encryption of {name} not supported"),
+        }
+    }
+
+    pub(crate) fn is_video(self) -> bool {
+        matches!(
+            self,
+            TrackKind::Avc { .. } | TrackKind::Hevc { .. } | TrackKind::Av1
+        )
+    }
+}
+
+/// Parameter sets of a track that are needed for finding the end of the slice headers.
+#[derive(Debug)]
+enum ParameterSets {
+    None,
+    Avc(AvcParameterSets),
+    Hevc(HevcParameterSets),
+}
+
+/// A track to be encrypted.
+#[derive(Debug)]
+pub(crate) struct Track {
+    kind: TrackKind,
+    parameter_sets: ParameterSets,
+}
+
+impl Track {
+    /// Creates the track from its caps, including the parameter sets from the `codec_data`.
+    pub(crate) fn from_caps(caps: &gst::CapsRef) -> Result<Self, Error> {
+        let kind = TrackKind::from_caps(caps)?;
+
+        let parameter_sets = match kind {
+            TrackKind::Avc { .. } | TrackKind::Hevc { .. } => {
+                let s = caps.structure(0).unwrap();
+                let codec_data = s
+                    .get::<&gst::BufferRef>("codec_data")
+                    .context("no codec_data")?;
+                let map = codec_data
+                    .map_readable()
+                    .context("codec_data not mappable")?;
+
+                if let TrackKind::Avc { .. } = kind {
+                    ParameterSets::Avc(AvcParameterSets::from_codec_data(&map)?)
+                } else {
+                    ParameterSets::Hevc(HevcParameterSets::from_codec_data(&map)?)
+                }
+            }
+            _ => ParameterSets::None,
+        };
+
+        Ok(Track {
+            kind,
+            parameter_sets,
+        })
+    }
+
+    pub(crate) fn kind(&self) -> TrackKind {
+        self.kind
+    }
+}
+
+/// Pattern (crypt, skip) signalled in the `tenc` box and used for encrypting a track.
+pub(crate) fn pattern(scheme: EncryptionScheme, kind: TrackKind) -> (u8, u8) {
+    if scheme == EncryptionScheme::Cbcs && kind.is_video() {
+        CBCS_VIDEO_PATTERN
+    } else {
+        (0, 0)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Encryptor {
+    scheme: EncryptionScheme,
+    key_id: [u8; 16],
+    key: [u8; 16],
+    /// Constant IV for `cbcs`.
+    constant_iv: [u8; 16],
+    /// Next per-sample IV for `cenc`.
+    next_iv: u64,
+    /// One entry per stream.
+    tracks: Vec<Track>,
+}
+
+impl Encryptor {
+    pub(crate) fn new(
+        scheme: EncryptionScheme,
+        key_id: [u8; 16],
+        key: [u8; 16],
+        constant_iv: [u8; 16],
+        tracks: Vec<Track>,
+    ) -> Self {
+        assert_ne!(scheme, EncryptionScheme::None);
+
+        Encryptor {
+            scheme,
+            key_id,
+            key,
+            constant_iv,
+            next_iv: rand::random(),
+            tracks,
+        }
+    }
+
+    pub(crate) fn constant_iv(&self) -> [u8; 16] {
+        self.constant_iv
+    }
+
+    pub(crate) fn key_id(&self) -> [u8; 16] {
+        self.key_id
+    }
+
+    /// Returns `true` if samples are currently encrypted with a different key.
+    pub(crate) fn key_changed(&self, key_id: [u8; 16], key: [u8; 16]) -> bool {
+        self.key_id != key_id || self.key != key
+    }
+
+    /// Switches to a new key for all following samples.
+    pub(crate) fn set_key(&mut self, key_id: [u8; 16], key: [u8; 16]) {
+        self.key_id = key_id;
+        self.key = key;
+    }
+
+    /// Encrypts the buffer of stream `idx` in place and returns the auxiliary information that
+    /// has to be stored for it, or `None` if the track is not encrypted.
+    pub(crate) fn encrypt(
+        &mut self,
+        idx: usize,
+        buffer: &mut gst::Buffer,
+    ) -> Result<Option<SampleEncryption>, Error> {
+        let track = &mut self.tracks[idx];
+        let kind = track.kind;
+        if kind == TrackKind::Clear {
+            return Ok(None);
+        }
+
+        let mut map = buffer
+            .make_mut()
+            .map_writable()
+            .context("buffer not writable")?;
+        let data = map.as_mut_slice();
+
+        let ranges = match (kind, &mut track.parameter_sets) {
+            (TrackKind::Audio, _) => vec![],
+            (TrackKind::Avc { nal_length_size }, ParameterSets::Avc(sets)) => {
+                nal_ranges(data, nal_length_size, 1, |nal| {
+                    if (1..=5).contains(&(nal[0] & 0x1f)) {
+                        sets.slice_header_len(nal).map(Some)
+                    } else {
+                        sets.update(nal)?;
+                        Ok(None)
+                    }
+                })?
+            }
+            (TrackKind::Hevc { nal_length_size }, ParameterSets::Hevc(sets)) => {
+                nal_ranges(data, nal_length_size, 2, |nal| {
+                    if ((nal[0] >> 1) & 0x3f) < 32 {
+                        sets.slice_header_len(nal).map(Some)
+                    } else {
+                        sets.update(nal)?;
+                        Ok(None)
+                    }
+                })?
+            }
+            (TrackKind::Av1, _) => av1_ranges(data)?,
+            _ => unreachable!(),
+        };
+
+        let iv = match self.scheme {
+            EncryptionScheme::Cenc => {
+                let iv = self.next_iv.to_be_bytes();
+                self.next_iv = self.next_iv.wrapping_add(1);
+                iv.to_vec()
+            }
+            EncryptionScheme::Cbcs => vec![],
+            EncryptionScheme::None => unreachable!(),
+        };
+
+        // Audio is encrypted as a whole without subsamples.
+        let protected_ranges = if kind == TrackKind::Audio {
+            let len = match self.scheme {
+                EncryptionScheme::Cbcs => data.len() / BLOCK_SIZE * BLOCK_SIZE,
+                _ => data.len(),
+            };
+            vec![(0, len)]
+        } else {
+            let mut offset = 0;
+            ranges
+                .iter()
+                .map(|&(clear, protected)| {
+                    let range = (offset + clear as usize, protected as usize);
+                    offset += clear as usize + protected as usize;
+                    range
+                })
+                .collect::<Vec<_>>()
+        };
+
+        match self.scheme {
+            EncryptionScheme::Cenc => {
+                let mut counter = [0u8; 16];
+                counter[..8].copy_from_slice(&iv);
+                // The keystream continues over the protected ranges of all subsamples.
+                let mut cipher = Aes128Ctr::new(&self.key.into(), &counter.into());
+                for &(start, len) in &protected_ranges {
+                    cipher.apply_keystream(&mut data[start..][..len]);
+                }
+            }
+            EncryptionScheme::Cbcs => {
+                let (crypt, skip) = pattern(self.scheme, kind);
+                for &(start, len) in &protected_ranges {
+                    // The CBC chain restarts with the constant IV for every subsample.
+                    let mut cipher = Aes128CbcEnc::new(&self.key.into(), &self.constant_iv.into());
+                    encrypt_cbcs_pattern(&mut cipher, &mut data[start..][..len], crypt, skip);
+                }
+            }
+            EncryptionScheme::None => unreachable!(),
+        }
+
+        Ok(Some(SampleEncryption {
+            iv,
+            subsamples: ranges,
+        }))
+    }
+}
+
+/// Encrypts all complete blocks of `data` following the given pattern. A pattern of (0, 0)
+/// encrypts all complete blocks. Trailing partial blocks stay in the clear.
+fn encrypt_cbcs_pattern(cipher: &mut Aes128CbcEnc, data: &mut [u8], crypt: u8, skip: u8) {
+    let (crypt, skip) = if crypt == 0 && skip == 0 {
+        (1, 0)
+    } else {
+        (crypt as usize, skip as usize)
+    };
+
+    for (i, block) in data.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+        if i % (crypt + skip) < crypt {
+            cipher.encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+    }
+}
+
+/// Collects (clear, protected) subsample ranges into `ranges`, merging leading clear data
+/// and splitting clear ranges that don't fit into 16 bits.
+struct SubsampleBuilder {
+    ranges: Vec<(u16, u32)>,
+    clear: usize,
+}
+
+impl SubsampleBuilder {
+    fn new() -> Self {
+        SubsampleBuilder {
+            ranges: vec![],
+            clear: 0,
+        }
+    }
+
+    fn add_clear(&mut self, len: usize) {
+        self.clear += len;
+    }
+
+    /// Adds a range of which the block-aligned tail is protected.
+    fn add_protected(&mut self, clear: usize, len: usize) -> Result<(), Error> {
+        let protected = len / BLOCK_SIZE * BLOCK_SIZE;
+        self.clear += clear + len - protected;
+
+        if protected == 0 {
+            return Ok(());
+        }
+
+        while self.clear > u16::MAX as usize {
+            self.ranges.push((u16::MAX, 0));
+            self.clear -= u16::MAX as usize;
+        }
+
+        self.ranges.push((
+            self.clear as u16,
+            u32::try_from(protected).context("too big subsample")?,
+        ));
+        self.clear = 0;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<(u16, u32)> {
+        while self.clear > 0 {
+            let clear = std::cmp::min(self.clear, u16::MAX as usize);
+            self.ranges.push((clear as u16, 0));
+            self.clear -= clear;
+        }
+
+        self.ranges
+    }
+}
+
+/// Splits length-prefixed NAL units into subsamples. Only VCL NAL units are protected, leaving
+/// the length prefix, NAL unit header and slice header in the clear.
+///
+/// `slice_header_len` is called for every NAL unit and returns the length of the NAL unit header
+/// and slice header for VCL NAL units, or `None` for NAL units that stay in the clear.
+fn nal_ranges(
+    data: &[u8],
+    nal_length_size: usize,
+    nal_header_size: usize,
+    mut slice_header_len: impl FnMut(&[u8]) -> Result<Option<usize>, Error>,
+) -> Result<Vec<(u16, u32)>, Error> {
+    let mut builder = SubsampleBuilder::new();
+
+    let mut pos = 0;
+    while pos < data.len() {
+        if data.len() - pos < nal_length_size + nal_header_size {
+            bail!("truncated NAL unit");
+        }
+
+        let nal_len = data[pos..][..nal_length_size]
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        if nal_len < nal_header_size || data.len() - pos - nal_length_size < nal_len {
+            bail!("invalid NAL unit length {nal_len}");
+        }
+
+        let nal = &data[pos + nal_length_size..][..nal_len];
+        match slice_header_len(nal).context("invalid NAL unit")? {
+            Some(header_len) if header_len <= nal_len => {
+                builder.add_protected(nal_length_size + header_len, nal_len - header_len)?;
+            }
+            Some(header_len) => bail!("invalid slice header length {header_len}"),
+            None => builder.add_clear(nal_length_size + nal_len),
+        }
+
+        pos += nal_length_size + nal_len;
+    }
+
+    Ok(builder.finish())
+}
+
+/// Splits AV1 OBUs into subsamples. Only the payload of tile group OBUs is protected.
+///
+/// The frame header has to stay in the clear, which would require parsing the full uncompressed
+/// header for OBU_FRAME. Streams using OBU_FRAME are therefore rejected and need separate
+/// OBU_FRAME_HEADER and OBU_TILE_GROUP OBUs instead.
+fn av1_ranges(data: &[u8]) -> Result<Vec<(u16, u32)>, Error> {
+    let mut builder = SubsampleBuilder::new();
+
+    let mut cursor = Cursor::new(data);
+    while (cursor.position() as usize) < data.len() {
+        let obu = SizedObu::parse(&mut BitReader::endian(&mut cursor, BigEndian))
+            .context("invalid OBU")?;
+        let header_len = (obu.header_len + obu.leb_size) as usize;
+        let size = obu.size as usize;
+
+        let start = cursor.position() as usize - header_len;
+        if data.len() - start - header_len < size {
+            bail!("truncated OBU");
+        }
+
+        match obu.obu_type {
+            ObuType::Frame => bail!("encrypting OBU_FRAME is not supported"),
+            ObuType::TileGroup => builder.add_protected(header_len, size)?,
+            _ => builder.add_clear(header_len + size),
+        }
+
+        cursor.set_position((start + header_len + size) as u64);
+    }
+
+    Ok(builder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn avc_subsamples() {
+        // SPS, PPS, SEI and an IDR slice with a 26 bit slice header and 35 bytes of slice data
+        let sps = [0x67, 0x42, 0x00, 0x28, 0xf4, 0x03, 0xc0, 0x11, 0x32];
+        let pps = [0x68, 0xce, 0x3c, 0x80];
+        let sei = [0x06, 0x05, 0x01, 0x00, 0x80];
+        let mut slice = vec![0x65, 0x88, 0x84, 0x07, 0xc0];
+        slice.extend([0xab; 35]);
+
+        let mut data = vec![];
+        for nal in [&sps[..], &pps[..], &sei[..], &slice[..]] {
+            data.extend((nal.len() as u32).to_be_bytes());
+            data.extend(nal);
+        }
+
+        let mut sets = AvcParameterSets::default();
+        let ranges = nal_ranges(&data, 4, 1, |nal| {
+            if (1..=5).contains(&(nal[0] & 0x1f)) {
+                sets.slice_header_len(nal).map(Some)
+            } else {
+                sets.update(nal)?;
+                Ok(None)
+            }
+        })
+        .unwrap();
+
+        // Everything up to the end of the slice header and the unaligned part of the slice data
+        // stays in the clear
+        assert_eq!(ranges, vec![(13 + 8 + 9 + 4 + 5 + 3, 32)]);
+
+        // Without the parameter sets the end of the slice header is unknown
+        let sets = AvcParameterSets::default();
+        assert!(nal_ranges(&data[13 + 8..], 4, 1, |nal| {
+            sets.slice_header_len(nal).map(Some)
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn av1_subsamples() {
+        // temporal delimiter, frame header and tile group with 20 bytes of tile data
+        let mut data = vec![0b0001_0010, 0, 0b0001_1010, 3, 1, 2, 3, 0b0010_0010, 20];
+        data.extend([0xab; 20]);
+
+        // Only the block-aligned tail of the tile data is protected
+        assert_eq!(av1_ranges(&data).unwrap(), vec![(13, 16)]);
+    }
+
+    #[test]
+    fn av1_frame_obu_rejected() {
+        // frame OBU, which contains the frame header that has to stay in the clear
+        let mut data = vec![0b0001_0010, 0, 0b0011_0010, 20];
+        data.extend([0xab; 20]);
+
+        assert!(av1_ranges(&data).is_err());
+    }
+}
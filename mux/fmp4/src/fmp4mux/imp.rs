@@ -17,9 +17,11 @@ use std::mem;
 use std::sync::Mutex;
 
 use crate::fmp4mux::obu::read_seq_header_obu_bytes;
+use anyhow::Context;
 use once_cell::sync::Lazy;
 
 use super::boxes;
+use super::cenc;
 use super::Buffer;
 use super::DeltaFrames;
 
@@ -34,6 +36,23 @@ const UNIX_1601_OFFSET: u64 = 11_644_473_600;
 /// NTP = UNIX + NTP_UNIX_OFFSET.
 const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
 
+/// Name of the custom downstream event for inserting an `emsg` box.
+///
+/// The event is placed into the fragment or chunk that covers its timestamp and has the following
+/// fields:
+///  * `scheme-id-uri` (string)
+///  * `value` (string, optional)
+///  * `timestamp` (clock time, optional): Position in the segment of the pad the event is
+///    received on. The event is placed into the next fragment or chunk if not set. Events after
+///    the end of the stream are placed into the final fragment or chunk at EOS.
+///  * `duration` (clock time, optional): Unknown if not set.
+///  * `id` (u32, optional): Automatically assigned if not set.
+///  * `version` (u32, optional): `emsg` box version 0 or 1, defaults to 1.
+///  * `data` (buffer, optional): Message data.
+const EVENT_MESSAGE_EVENT_NAME: &str = "fmp4mux-emsg";
+
+const SIGNAL_GET_ENCRYPTION_KEY: &str = "get-encryption-key";
+
 /// Reference timestamp meta caps for NTP timestamps.
 static NTP_CAPS: Lazy<gst::Caps> = Lazy::new(|| gst::Caps::builder("timestamp/x-ntp").build());
 
@@ -102,6 +121,8 @@ const DEFAULT_WRITE_MFRA: bool = false;
 const DEFAULT_WRITE_MEHD: bool = false;
 const DEFAULT_INTERLEAVE_BYTES: Option<u64> = None;
 const DEFAULT_INTERLEAVE_TIME: Option<gst::ClockTime> = Some(gst::ClockTime::from_mseconds(250));
+const DEFAULT_ENCRYPTION_SCHEME: super::EncryptionScheme = super::EncryptionScheme::None;
+const DEFAULT_WRITE_PRFT: bool = false;
 
 #[derive(Debug, Clone)]
 struct Settings {
@@ -114,6 +135,12 @@ struct Settings {
     interleave_time: Option<gst::ClockTime>,
     movie_timescale: u32,
     offset_to_zero: bool,
+    encryption_scheme: super::EncryptionScheme,
+    encryption_key_id: Option<[u8; 16]>,
+    encryption_key: Option<[u8; 16]>,
+    encryption_iv: Option<[u8; 16]>,
+    protection_system_headers: Vec<gst::Buffer>,
+    write_prft: bool,
 }
 
 impl Default for Settings {
@@ -128,6 +155,12 @@ impl Default for Settings {
             interleave_time: DEFAULT_INTERLEAVE_TIME,
             movie_timescale: 0,
             offset_to_zero: false,
+            encryption_scheme: DEFAULT_ENCRYPTION_SCHEME,
+            encryption_key_id: None,
+            encryption_key: None,
+            encryption_iv: None,
+            protection_system_headers: Vec::new(),
+            write_prft: DEFAULT_WRITE_PRFT,
         }
     }
 }
@@ -160,6 +193,22 @@ struct PreQueuedBuffer {
     end_dts: Option<gst::Signed<gst::ClockTime>>,
 }
 
+#[derive(Debug)]
+struct PendingEventMessage {
+    /// Sink pad the event was received on.
+    sinkpad: super::FMP4MuxPad,
+
+    /// Running time of the event, or `None` if it goes into the next fragment or chunk.
+    running_time: Option<gst::ClockTime>,
+
+    version: u8,
+    scheme_id_uri: String,
+    value: String,
+    duration: Option<gst::ClockTime>,
+    id: u32,
+    message_data: gst::Buffer,
+}
+
 #[derive(Debug)]
 struct GopBuffer {
     buffer: gst::Buffer,
@@ -208,6 +257,8 @@ struct Stream {
     delta_frames: DeltaFrames,
     /// Whether this stream might have header frames without timestamps that should be ignored.
     discard_header_buffers: bool,
+    /// Whether this is a text stream and buffers have to be converted into samples.
+    text: bool,
 
     /// Currently queued GOPs, including incomplete ones.
     queued_gops: VecDeque<Gop>,
@@ -268,6 +319,19 @@ struct State {
 
     /// If headers (ftyp / moov box) were sent.
     sent_headers: bool,
+
+    /// Sample encryptor if encryption is enabled.
+    encryptor: Option<cenc::Encryptor>,
+    /// Sequence number of the next fragment, for which the key has to be requested before it can
+    /// be drained, and whether draining happens because of a timeout.
+    key_request: Option<(u32, bool)>,
+    /// Key ID and key returned for the fragment with the given sequence number, if any.
+    fragment_key: Option<(u32, Option<([u8; 16], [u8; 16])>)>,
+
+    /// Event messages waiting for the fragment or chunk that covers their timestamp.
+    pending_event_messages: Vec<PendingEventMessage>,
+    /// ID for the next event message that has no ID set.
+    next_event_message_id: u32,
 }
 
 #[derive(Default)]
@@ -327,8 +391,26 @@ impl FMP4Mux {
         }
 
         // Pop buffer here, it will be stored in the pre-queue after calculating its timestamps
-        let Some(mut buffer) = stream.sinkpad.pop_buffer() else {
-            return Ok(None);
+        let mut buffer = loop {
+            let Some(buffer) = stream.sinkpad.pop_buffer() else {
+                return Ok(None);
+            };
+
+            if !stream.text {
+                break buffer;
+            }
+
+            // Convert text buffers into samples right away and fill gaps with empty samples
+            match boxes::create_text_sample(&stream.caps, &buffer) {
+                Ok(Some(sample)) => break sample,
+                Ok(None) => {
+                    gst::trace!(CAT, obj = stream.sinkpad, "Dropping buffer without cues");
+                }
+                Err(err) => {
+                    gst::error!(CAT, obj = stream.sinkpad, "Invalid text buffer: {}", err);
+                    return Err(gst::FlowError::Error);
+                }
+            }
         };
         Self::check_buffer(&buffer, stream)?;
 
@@ -1827,13 +1909,47 @@ impl FMP4Mux {
                 )
             };
 
-            buffers.push_back(Buffer {
-                idx,
-                buffer: buffer.buffer,
-                timestamp,
-                duration,
-                composition_time_offset,
-            });
+            // Text samples must not be displayed longer than their own duration, so fill the
+            // remaining time until the next sample with an empty sample.
+            let cue_duration = buffer
+                .buffer
+                .duration()
+                .filter(|cue_duration| stream.text && *cue_duration < duration);
+
+            if let Some(cue_duration) = cue_duration {
+                let mut empty = boxes::create_empty_text_sample(&stream.caps);
+                {
+                    let empty = empty.get_mut().unwrap();
+                    empty.set_pts(buffer.buffer.pts().map(|pts| pts + cue_duration));
+                    empty.set_duration(duration - cue_duration);
+                }
+
+                buffers.push_back(Buffer {
+                    idx,
+                    buffer: buffer.buffer,
+                    timestamp,
+                    duration: cue_duration,
+                    composition_time_offset,
+                    sample_encryption: None,
+                });
+                buffers.push_back(Buffer {
+                    idx,
+                    buffer: empty,
+                    timestamp: timestamp + cue_duration,
+                    duration: duration - cue_duration,
+                    composition_time_offset,
+                    sample_encryption: None,
+                });
+            } else {
+                buffers.push_back(Buffer {
+                    idx,
+                    buffer: buffer.buffer,
+                    timestamp,
+                    duration,
+                    composition_time_offset,
+                    sample_encryption: None,
+                });
+            }
         }
 
         if buffers.is_empty() {
@@ -2317,6 +2433,26 @@ impl FMP4Mux {
             );
         }
 
+        // Before draining a new fragment its key has to be requested. This happens from aggregate()
+        // without the state lock once everything before was pushed downstream.
+        let sequence_number = std::cmp::max(state.sequence_number, 1);
+        if state.encryptor.is_some()
+            && state.fragment_start_pts == state.chunk_start_pts
+            && state
+                .streams
+                .iter()
+                .any(|stream| !stream.queued_gops.is_empty())
+            && !matches!(state.fragment_key, Some((seqnum, _)) if seqnum == sequence_number)
+        {
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Requesting key for fragment {sequence_number}"
+            );
+            state.key_request = Some((sequence_number, timeout));
+            return Ok((None, None));
+        }
+
         // Collect all buffers and their timing information that are to be drained right now.
         let (
             drained_streams,
@@ -2328,6 +2464,26 @@ impl FMP4Mux {
             fragment_start,
         ) = self.drain_buffers(state, settings, timeout, at_eos)?;
 
+        // Switch to a changed key at the start of a fragment. The key ID is part of the header so
+        // this requires a new header.
+        if fragment_start {
+            if let (Some(encryptor), Some((_, Some((key_id, key))))) =
+                (state.encryptor.as_mut(), state.fragment_key.take())
+            {
+                if encryptor.key_changed(key_id, key) {
+                    gst::info!(
+                        CAT,
+                        imp = self,
+                        "Switching to key ID {}",
+                        hex::encode(key_id)
+                    );
+                    encryptor.set_key(key_id, key);
+                    state.stream_header = None;
+                    state.sent_headers = false;
+                }
+            }
+        }
+
         // Create header now if it was not created before and return the caps
         let mut caps = None;
         if state.stream_header.is_none() {
@@ -2339,16 +2495,34 @@ impl FMP4Mux {
         let (mut interleaved_buffers, mut streams) =
             self.interleave_buffers(settings, drained_streams)?;
 
+        // Encrypt all buffers before the sample auxiliary information is written into the moof
+        if let Some(ref mut encryptor) = state.encryptor {
+            for buffer in &mut interleaved_buffers {
+                buffer.sample_encryption = encryptor
+                    .encrypt(buffer.idx, &mut buffer.buffer)
+                    .map_err(|err| {
+                        gst::error!(CAT, imp = self, "Failed to encrypt buffer: {}", err);
+                        gst::FlowError::Error
+                    })?;
+            }
+        }
+
         // Offset stream start time to start at 0 in ONVIF mode, or if 'offset-to-zero' is enabled,
         // instead of using the UTC time verbatim. This would be used for the tfdt box later.
         // FIXME: Should this use the original DTS-or-PTS running time instead?
         //        That might be negative though!
-        if self.obj().class().as_ref().variant == super::Variant::ONVIF || settings.offset_to_zero {
-            let offset = if let Some(start_dts) = state.start_dts {
-                std::cmp::min(start_dts, state.earliest_pts.unwrap())
+        let start_time_offset = if self.obj().class().as_ref().variant == super::Variant::ONVIF
+            || settings.offset_to_zero
+        {
+            if let Some(start_dts) = state.start_dts {
+                Some(std::cmp::min(start_dts, state.earliest_pts.unwrap()))
             } else {
-                state.earliest_pts.unwrap()
-            };
+                state.earliest_pts
+            }
+        } else {
+            None
+        };
+        if let Some(offset) = start_time_offset {
             for stream in &mut streams {
                 if let Some(start_time) = stream.start_time {
                     stream.start_time = Some(start_time.checked_sub(offset).unwrap());
@@ -2398,7 +2572,35 @@ impl FMP4Mux {
             state.sent_headers = true;
         }
 
-        // TODO: Write prft boxes before moof
+        // Nothing is drained after the final chunk, so all remaining event messages go into it
+        let final_chunk = at_eos
+            && state
+                .streams
+                .iter()
+                .all(|stream| stream.queued_gops.is_empty());
+        let event_messages = self.take_event_messages(
+            state,
+            min_earliest_pts,
+            chunk_end_pts,
+            start_time_offset,
+            final_chunk,
+        );
+
+        let producer_reference_time = if settings.write_prft {
+            let prft =
+                Self::producer_reference_time(&streams, &interleaved_buffers, start_time_offset);
+            if prft.is_none() {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "No reference timestamp for writing a prft box"
+                );
+            }
+            prft
+        } else {
+            None
+        };
+
         // TODO: Write sidx boxes before moof and rewrite once offsets are known
 
         // First sequence number must be 1
@@ -2418,6 +2620,8 @@ impl FMP4Mux {
                 chunk: !fragment_start,
                 streams: streams.as_slice(),
                 buffers: interleaved_buffers.as_slice(),
+                event_messages: event_messages.as_slice(),
+                producer_reference_time,
             })
             .map_err(|err| {
                 gst::error!(
@@ -2523,6 +2727,173 @@ impl FMP4Mux {
         Ok((caps, Some(buffer_list)))
     }
 
+    /// Takes all pending event messages that start before the end of the current chunk, or all
+    /// of them for the final chunk.
+    ///
+    /// Event messages without timestamp are placed at the start of the chunk.
+    fn take_event_messages(
+        &self,
+        state: &mut State,
+        chunk_start_pts: gst::ClockTime,
+        chunk_end_pts: gst::ClockTime,
+        start_time_offset: Option<gst::ClockTime>,
+        final_chunk: bool,
+    ) -> Vec<super::EventMessage> {
+        let variant = self.obj().class().as_ref().variant;
+        let fragment_start_pts = state.fragment_start_pts.unwrap_or(chunk_start_pts);
+
+        let mut event_messages = vec![];
+        for emsg in mem::take(&mut state.pending_event_messages) {
+            let idx = state
+                .streams
+                .iter()
+                .position(|s| s.sinkpad == emsg.sinkpad)
+                .unwrap_or(0);
+
+            // Convert to the timeline of the buffers, which is the UTC time in ONVIF mode
+            let pts = match emsg.running_time {
+                Some(running_time) if variant == super::Variant::ONVIF => {
+                    match state.streams[idx].running_time_utc_time_mapping {
+                        Some(mapping) => running_time_to_utc_time(running_time, mapping),
+                        None if final_chunk => None,
+                        None => {
+                            state.pending_event_messages.push(emsg);
+                            continue;
+                        }
+                    }
+                }
+                running_time => running_time,
+            };
+
+            if !final_chunk && pts.opt_ge(chunk_end_pts).unwrap_or(false) {
+                state.pending_event_messages.push(emsg);
+                continue;
+            }
+
+            let pts = pts.unwrap_or(chunk_start_pts);
+            let presentation_time = if emsg.version == 0 {
+                pts.saturating_sub(fragment_start_pts)
+            } else {
+                pts.saturating_sub(start_time_offset.unwrap_or(gst::ClockTime::ZERO))
+            };
+
+            gst::debug!(
+                CAT,
+                obj = emsg.sinkpad,
+                "Writing event message {} for scheme {} at {}",
+                emsg.id,
+                emsg.scheme_id_uri,
+                pts,
+            );
+
+            event_messages.push(super::EventMessage {
+                idx,
+                version: emsg.version,
+                scheme_id_uri: emsg.scheme_id_uri,
+                value: emsg.value,
+                presentation_time,
+                duration: emsg.duration,
+                id: emsg.id,
+                message_data: emsg.message_data,
+            });
+        }
+
+        event_messages
+    }
+
+    /// Creates the producer reference time from the reference timestamp meta of the first
+    /// buffer of the first stream that has buffers in this chunk.
+    fn producer_reference_time(
+        streams: &[super::FragmentHeaderStream],
+        buffers: &[Buffer],
+        start_time_offset: Option<gst::ClockTime>,
+    ) -> Option<super::ProducerReferenceTime> {
+        let idx = streams.iter().position(|s| s.start_time.is_some())?;
+        let buffer = buffers.iter().find(|b| b.idx == idx)?;
+
+        let ntp_time = buffer
+            .buffer
+            .iter_meta::<gst::ReferenceTimestampMeta>()
+            .find_map(|meta| {
+                if meta.reference().can_intersect(&NTP_CAPS) {
+                    Some(meta.timestamp())
+                } else if meta.reference().can_intersect(&UNIX_CAPS) {
+                    meta.timestamp().checked_add(NTP_UNIX_OFFSET.seconds())
+                } else {
+                    None
+                }
+            })?;
+
+        let pts = match buffer.composition_time_offset {
+            Some(cto) if cto < 0 => buffer
+                .timestamp
+                .checked_sub(gst::ClockTime::from_nseconds(cto.unsigned_abs()))?,
+            Some(cto) => buffer.timestamp + gst::ClockTime::from_nseconds(cto as u64),
+            None => buffer.timestamp,
+        };
+
+        Some(super::ProducerReferenceTime {
+            idx,
+            ntp_time,
+            media_time: pts.checked_sub(start_time_offset.unwrap_or(gst::ClockTime::ZERO))?,
+        })
+    }
+
+    /// Parses an event message from a custom downstream event.
+    fn parse_event_message(
+        state: &mut State,
+        sinkpad: &super::FMP4MuxPad,
+        s: &gst::StructureRef,
+    ) -> Result<PendingEventMessage, anyhow::Error> {
+        let scheme_id_uri = s
+            .get::<String>("scheme-id-uri")
+            .context("no scheme-id-uri")?;
+        let value = s.get_optional::<String>("value")?.unwrap_or_default();
+
+        let version = s.get_optional::<u32>("version")?.unwrap_or(1);
+        if version > 1 {
+            anyhow::bail!("unsupported version {version}");
+        }
+
+        let running_time = match s.get_optional::<gst::ClockTime>("timestamp")? {
+            Some(timestamp) => {
+                let segment = sinkpad
+                    .segment()
+                    .downcast::<gst::ClockTime>()
+                    .ok()
+                    .context("no TIME segment")?;
+                Some(
+                    segment
+                        .to_running_time(timestamp)
+                        .context("timestamp outside segment")?,
+                )
+            }
+            None => None,
+        };
+
+        let id = match s.get_optional::<u32>("id")? {
+            Some(id) => id,
+            None => {
+                let id = state.next_event_message_id;
+                state.next_event_message_id = id.wrapping_add(1);
+                id
+            }
+        };
+
+        Ok(PendingEventMessage {
+            sinkpad: sinkpad.clone(),
+            running_time,
+            version: version as u8,
+            scheme_id_uri,
+            value,
+            duration: s.get_optional::<gst::ClockTime>("duration")?,
+            id,
+            message_data: s
+                .get_optional::<gst::Buffer>("data")?
+                .unwrap_or_else(gst::Buffer::new),
+        })
+    }
+
     /// Drain all chunks that can currently be drained.
     ///
     /// On error the `caps`, `buffers` or `upstream_events` can contain data of already finished
@@ -2562,9 +2933,10 @@ impl FMP4Mux {
             };
 
             // If nothing can't be drained anymore then break the loop, and if all streams are
-            // EOS add the footers.
+            // EOS add the footers. If the key for the next fragment is requested first then
+            // draining continues afterwards.
             if buffer_list.is_none() {
-                if settings.write_mfra && all_eos {
+                if settings.write_mfra && all_eos && state.key_request.is_none() {
                     gst::debug!(CAT, imp = self, "Writing mfra box");
                     match boxes::create_mfra(&state.streams[0].caps, &state.fragment_offsets) {
                         Ok(mut mfra) => {
@@ -2635,6 +3007,7 @@ impl FMP4Mux {
 
             let mut delta_frames = DeltaFrames::IntraOnly;
             let mut discard_header_buffers = false;
+            let mut text = false;
             match s.name().as_str() {
                 "video/x-h264" | "video/x-h265" => {
                     if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
@@ -2693,6 +3066,12 @@ impl FMP4Mux {
                 "audio/x-alaw" | "audio/x-mulaw" => (),
                 "audio/x-adpcm" => (),
                 "application/x-onvif-metadata" => (),
+                "application/x-subtitle-vtt"
+                | "application/x-subtitle-vtt-fragmented"
+                | "application/ttml+xml"
+                | "text/x-raw" => {
+                    text = true;
+                }
                 _ => unreachable!(),
             }
 
@@ -2701,6 +3080,7 @@ impl FMP4Mux {
                 caps,
                 delta_frames,
                 discard_header_buffers,
+                text,
                 pre_queue: VecDeque::new(),
                 queued_gops: VecDeque::new(),
                 fragment_filled: false,
@@ -2717,7 +3097,8 @@ impl FMP4Mux {
             return Err(gst::FlowError::Error);
         }
 
-        // Sort video streams first and then audio streams and then metadata streams, and each group by pad name.
+        // Sort video streams first and then audio streams, metadata streams and text streams, and
+        // each group by pad name.
         state.streams.sort_by(|a, b| {
             let order_of_caps = |caps: &gst::CapsRef| {
                 let s = caps.structure(0).unwrap();
@@ -2728,6 +3109,11 @@ impl FMP4Mux {
                     1
                 } else if s.name().starts_with("application/x-onvif-metadata") {
                     2
+                } else if s.name().starts_with("application/x-subtitle-vtt")
+                    || s.name() == "application/ttml+xml"
+                    || s.name() == "text/x-raw"
+                {
+                    3
                 } else {
                     unimplemented!();
                 }
@@ -2746,6 +3132,91 @@ impl FMP4Mux {
         Ok(())
     }
 
+    /// Requests the key ID and key for the fragment with the given sequence number.
+    ///
+    /// Returns `None` if the current key should be used.
+    fn request_encryption_key(
+        &self,
+        sequence_number: u32,
+    ) -> Result<Option<([u8; 16], [u8; 16])>, gst::FlowError> {
+        let Some(s) = self
+            .obj()
+            .emit_by_name::<Option<gst::Structure>>(SIGNAL_GET_ENCRYPTION_KEY, &[&sequence_number])
+        else {
+            return Ok(None);
+        };
+
+        let bytes = |name: &str| {
+            s.get::<glib::Bytes>(name)
+                .ok()
+                .and_then(|bytes| <[u8; 16]>::try_from(&*bytes).ok())
+        };
+
+        match (bytes("key-id"), bytes("key")) {
+            (Some(key_id), Some(key)) => Ok(Some((key_id, key))),
+            _ => {
+                gst::element_imp_error!(
+                    self,
+                    gst::LibraryError::Encrypt,
+                    ["Invalid encryption key for fragment {}", sequence_number]
+                );
+                Err(gst::FlowError::Error)
+            }
+        }
+    }
+
+    /// Set up the sample encryptor for the configured streams if encryption is enabled.
+    fn create_encryptor(
+        &self,
+        state: &mut State,
+        settings: &Settings,
+    ) -> Result<(), gst::FlowError> {
+        if settings.encryption_scheme == super::EncryptionScheme::None {
+            return Ok(());
+        }
+
+        let (Some(key_id), Some(key)) = (settings.encryption_key_id, settings.encryption_key)
+        else {
+            gst::element_imp_error!(
+                self,
+                gst::LibraryError::Settings,
+                ["Encryption enabled but no key or key ID configured"]
+            );
+            return Err(gst::FlowError::Error);
+        };
+
+        let mut tracks = Vec::with_capacity(state.streams.len());
+        for stream in &state.streams {
+            match cenc::Track::from_caps(&stream.caps) {
+                Ok(track) => tracks.push(track),
+                Err(err) => {
+                    gst::error!(CAT, obj = stream.sinkpad, "Can't encrypt stream: {}", err);
+                    return Err(gst::FlowError::NotNegotiated);
+                }
+            }
+        }
+
+        let constant_iv = settings.encryption_iv.unwrap_or_else(rand::random);
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Encrypting with scheme {:?} and tracks {:?}",
+            settings.encryption_scheme,
+            tracks.iter().map(|track| track.kind()).collect::<Vec<_>>()
+        );
+
+        state.encryptor = Some(cenc::Encryptor::new(
+            settings.encryption_scheme,
+            key_id,
+            key,
+            constant_iv,
+            tracks,
+        ));
+
+        Ok(())
+    }
+
     /// Generate an updated header at the end and the corresponding caps with the new streamheader.
     fn update_header(
         &self,
@@ -2795,6 +3266,15 @@ impl FMP4Mux {
             } else {
                 None
             },
+            encryption: state
+                .encryptor
+                .as_ref()
+                .map(|encryptor| super::EncryptionConfiguration {
+                    scheme: settings.encryption_scheme,
+                    key_id: encryptor.key_id(),
+                    constant_iv: encryptor.constant_iv(),
+                    pssh: settings.protection_system_headers.clone(),
+                }),
         })
         .map_err(|err| {
             gst::error!(CAT, imp = self, "Failed to create FMP4 header: {}", err);
@@ -2964,6 +3444,39 @@ impl ObjectImpl for FMP4Mux {
                     .blurb("Timescale to use for the movie (units per second, 0 is automatic)")
                     .mutable_ready()
                     .build(),
+                glib::ParamSpecEnum::builder_with_default("encryption-scheme", DEFAULT_ENCRYPTION_SCHEME)
+                    .nick("Encryption Scheme")
+                    .blurb("Common Encryption scheme to encrypt all tracks with (needs encryption-key-id and encryption-key)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("encryption-key-id")
+                    .nick("Encryption Key ID")
+                    .blurb("Key ID as 32 hexadecimal characters")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("encryption-key")
+                    .nick("Encryption Key")
+                    .blurb("AES-128 key as 32 hexadecimal characters")
+                    .write_only()
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("encryption-iv")
+                    .nick("Encryption IV")
+                    .blurb("Constant IV for the cbcs scheme as 32 hexadecimal characters (default = random)")
+                    .mutable_ready()
+                    .build(),
+                gst::ParamSpecArray::builder("protection-system-headers")
+                    .nick("Protection System Headers")
+                    .blurb("Protection system specific header (pssh) boxes to add to the header if encrypting")
+                    .element_spec(&glib::ParamSpecBoxed::builder::<gst::Buffer>("pssh").build())
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("write-prft")
+                    .nick("Write prft box")
+                    .blurb("Write producer reference time box before each fragment and chunk with the NTP time from the reference timestamp meta")
+                    .default_value(DEFAULT_WRITE_PRFT)
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -3034,6 +3547,57 @@ impl ObjectImpl for FMP4Mux {
                 settings.movie_timescale = value.get().expect("type checked upstream");
             }
 
+            "encryption-scheme" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.encryption_scheme = value.get().expect("type checked upstream");
+            }
+
+            "encryption-key-id" | "encryption-key" | "encryption-iv" => {
+                let value = value
+                    .get::<Option<&str>>()
+                    .expect("type checked upstream")
+                    .and_then(|s| {
+                        let mut v = [0u8; 16];
+                        match hex::decode_to_slice(s, &mut v) {
+                            Ok(_) => Some(v),
+                            Err(err) => {
+                                gst::error!(
+                                    CAT,
+                                    imp = self,
+                                    "Invalid {} '{}': {}",
+                                    pspec.name(),
+                                    s,
+                                    err
+                                );
+                                None
+                            }
+                        }
+                    });
+
+                let mut settings = self.settings.lock().unwrap();
+                match pspec.name() {
+                    "encryption-key-id" => settings.encryption_key_id = value,
+                    "encryption-key" => settings.encryption_key = value,
+                    "encryption-iv" => settings.encryption_iv = value,
+                    _ => unreachable!(),
+                }
+            }
+
+            "protection-system-headers" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.protection_system_headers = value
+                    .get::<gst::ArrayRef>()
+                    .expect("type checked upstream")
+                    .iter()
+                    .map(|v| v.get::<gst::Buffer>().expect("type checked upstream"))
+                    .collect();
+            }
+
+            "write-prft" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.write_prft = value.get().expect("type checked upstream");
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -3080,10 +3644,64 @@ impl ObjectImpl for FMP4Mux {
                 settings.movie_timescale.to_value()
             }
 
+            "encryption-scheme" => {
+                let settings = self.settings.lock().unwrap();
+                settings.encryption_scheme.to_value()
+            }
+
+            "encryption-key-id" => {
+                let settings = self.settings.lock().unwrap();
+                settings.encryption_key_id.map(hex::encode).to_value()
+            }
+
+            "encryption-iv" => {
+                let settings = self.settings.lock().unwrap();
+                settings.encryption_iv.map(hex::encode).to_value()
+            }
+
+            "protection-system-headers" => {
+                let settings = self.settings.lock().unwrap();
+                gst::Array::new(&settings.protection_system_headers).to_value()
+            }
+
+            "write-prft" => {
+                let settings = self.settings.lock().unwrap();
+                settings.write_prft.to_value()
+            }
+
             _ => unimplemented!(),
         }
     }
 
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            vec![
+                /**
+                 * GstFMP4Mux::get-encryption-key:
+                 * @sequence_number: Sequence number of the fragment
+                 *
+                 * Emitted before each fragment if encryption is enabled. Handlers can return an
+                 * `application/x-cenc-key` structure with the `key-id` and `key` as 16 bytes
+                 * `GBytes` to encrypt this and all following fragments with. If the key changes
+                 * then a new header with the new key ID is output before the fragment.
+                 *
+                 * Returns: %NULL to keep using the current key.
+                 */
+                glib::subclass::Signal::builder(SIGNAL_GET_ENCRYPTION_KEY)
+                    .param_types([u32::static_type()])
+                    .return_type::<Option<gst::Structure>>()
+                    .accumulator(|_hint, ret, value| {
+                        // First signal handler wins
+                        *ret = value.clone();
+                        false
+                    })
+                    .build(),
+            ]
+        });
+
+        SIGNALS.as_ref()
+    }
+
     fn constructed(&self) {
         self.parent_constructed();
 
@@ -3245,6 +3863,31 @@ impl AggregatorImpl for FMP4Mux {
 
                 self.parent_sink_event(aggregator_pad, event)
             }
+            EventView::CustomDownstream(ev)
+                if ev
+                    .structure()
+                    .map_or(false, |s| s.name() == EVENT_MESSAGE_EVENT_NAME) =>
+            {
+                let s = ev.structure().unwrap();
+                let sinkpad = aggregator_pad.downcast_ref::<super::FMP4MuxPad>().unwrap();
+
+                let mut state = self.state.lock().unwrap();
+                match Self::parse_event_message(&mut state, sinkpad, s) {
+                    Ok(emsg) => {
+                        gst::debug!(CAT, obj = aggregator_pad, "Queueing event message {emsg:?}");
+                        state.pending_event_messages.push(emsg);
+                    }
+                    Err(err) => {
+                        gst::warning!(
+                            CAT,
+                            obj = aggregator_pad,
+                            "Dropping invalid event message {s:?}: {err}"
+                        );
+                    }
+                }
+
+                true
+            }
             _ => self.parent_sink_event(aggregator_pad, event),
         }
     }
@@ -3289,6 +3932,7 @@ impl AggregatorImpl for FMP4Mux {
 
         state.current_offset = 0;
         state.fragment_offsets.clear();
+        state.pending_event_messages.clear();
 
         drop(state);
 
@@ -3330,80 +3974,93 @@ impl AggregatorImpl for FMP4Mux {
         true
     }
 
-    fn aggregate(&self, timeout: bool) -> Result<gst::FlowSuccess, gst::FlowError> {
+    fn aggregate(&self, mut timeout: bool) -> Result<gst::FlowSuccess, gst::FlowError> {
         let settings = self.settings.lock().unwrap().clone();
 
-        let all_eos;
-        let mut caps = None;
-        let mut buffers = vec![];
-        let mut upstream_events = vec![];
-        let res = {
-            let mut state = self.state.lock().unwrap();
-
-            // Create streams
-            if state.streams.is_empty() {
-                self.create_streams(&mut state)?;
-            }
+        let all_eos = loop {
+            let all_eos;
+            let mut caps = None;
+            let mut buffers = vec![];
+            let mut upstream_events = vec![];
+            let res = {
+                let mut state = self.state.lock().unwrap();
+
+                // Create streams
+                if state.streams.is_empty() {
+                    self.create_streams(&mut state)?;
+                    self.create_encryptor(&mut state, &settings)?;
+                }
 
-            self.queue_available_buffers(&mut state, &settings, timeout)?;
+                self.queue_available_buffers(&mut state, &settings, timeout)?;
 
-            all_eos = state.streams.iter().all(|stream| stream.sinkpad.is_eos());
-            if all_eos {
-                gst::debug!(CAT, imp = self, "All streams are EOS now");
+                all_eos = state.streams.iter().all(|stream| stream.sinkpad.is_eos());
+                if all_eos {
+                    gst::debug!(CAT, imp = self, "All streams are EOS now");
 
-                let fragment_start_pts = state.fragment_start_pts;
-                let chunk_start_pts = state.chunk_start_pts;
+                    let fragment_start_pts = state.fragment_start_pts;
+                    let chunk_start_pts = state.chunk_start_pts;
 
-                for stream in &mut state.streams {
-                    // Check if this stream is filled enough now that everything is EOS.
-                    self.check_stream_filled(
-                        &settings,
-                        stream,
-                        fragment_start_pts,
-                        chunk_start_pts,
-                        true,
-                    );
+                    for stream in &mut state.streams {
+                        // Check if this stream is filled enough now that everything is EOS.
+                        self.check_stream_filled(
+                            &settings,
+                            stream,
+                            fragment_start_pts,
+                            chunk_start_pts,
+                            true,
+                        );
+                    }
                 }
-            }
 
-            // Calculate the earliest PTS, i.e. the start of the first fragment, if not known yet.
-            self.calculate_earliest_pts(
-                &settings,
-                &mut state,
-                &mut upstream_events,
-                all_eos,
-                timeout,
-            );
+                // Calculate the earliest PTS, i.e. the start of the first fragment, if not known yet.
+                self.calculate_earliest_pts(
+                    &settings,
+                    &mut state,
+                    &mut upstream_events,
+                    all_eos,
+                    timeout,
+                );
 
-            // Drain everything that can be drained at this point
-            self.drain(
-                &mut state,
-                &settings,
-                all_eos,
-                timeout,
-                &mut caps,
-                &mut buffers,
-                &mut upstream_events,
-            )
-        };
+                // Drain everything that can be drained at this point
+                self.drain(
+                    &mut state,
+                    &settings,
+                    all_eos,
+                    timeout,
+                    &mut caps,
+                    &mut buffers,
+                    &mut upstream_events,
+                )
+            };
 
-        for (sinkpad, event) in upstream_events {
-            sinkpad.push_event(event);
-        }
+            for (sinkpad, event) in upstream_events {
+                sinkpad.push_event(event);
+            }
 
-        if let Some(caps) = caps {
-            gst::debug!(CAT, imp = self, "Setting caps on source pad: {:?}", caps);
-            self.obj().set_src_caps(&caps);
-        }
+            if let Some(caps) = caps {
+                gst::debug!(CAT, imp = self, "Setting caps on source pad: {:?}", caps);
+                self.obj().set_src_caps(&caps);
+            }
 
-        for buffer_list in buffers {
-            gst::trace!(CAT, imp = self, "Pushing buffer list {:?}", buffer_list);
-            self.obj().finish_buffer_list(buffer_list)?;
-        }
+            for buffer_list in buffers {
+                gst::trace!(CAT, imp = self, "Pushing buffer list {:?}", buffer_list);
+                self.obj().finish_buffer_list(buffer_list)?;
+            }
 
-        // If an error happened above while draining, return this now after pushing
-        // any output that was produced before the error.
-        res?;
+            // If an error happened above while draining, return this now after pushing
+            // any output that was produced before the error.
+            res?;
+
+            // If draining stopped at the start of a new fragment then request its key now and
+            // continue draining.
+            let key_request = self.state.lock().unwrap().key_request.take();
+            let Some((sequence_number, key_request_timeout)) = key_request else {
+                break all_eos;
+            };
+            let key = self.request_encryption_key(sequence_number)?;
+            self.state.lock().unwrap().fragment_key = Some((sequence_number, key));
+            timeout = key_request_timeout;
+        };
 
         if !all_eos {
             return Ok(gst::FlowSuccess::Ok);
@@ -3585,6 +4242,12 @@ impl ElementImpl for ISOFMP4Mux {
                         .field("channels", gst::IntRange::<i32>::new(1, 8))
                         .field("rate", gst::IntRange::<i32>::new(1, 10 * u16::MAX as i32))
                         .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/x-subtitle-vtt-fragmented").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
+                    gst::Structure::builder("text/x-raw")
+                        .field("format", "utf8")
+                        .build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
@@ -3681,6 +4344,12 @@ impl ElementImpl for CMAFMux {
                         .field("channels", gst::IntRange::new(1, u16::MAX as i32))
                         .field("rate", gst::IntRange::new(1, i32::MAX))
                         .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/x-subtitle-vtt-fragmented").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
+                    gst::Structure::builder("text/x-raw")
+                        .field("format", "utf8")
+                        .build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
@@ -3794,6 +4463,12 @@ impl ElementImpl for DASHMP4Mux {
                         .field("channels", gst::IntRange::new(1i32, 8))
                         .field("rate", gst::IntRange::new(1, i32::MAX))
                         .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/x-subtitle-vtt-fragmented").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
+                    gst::Structure::builder("text/x-raw")
+                        .field("format", "utf8")
+                        .build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
@@ -12,7 +12,9 @@ use gst::prelude::*;
 mod boxes;
 mod imp;
 
+mod cenc;
 mod obu;
+mod slice_header;
 
 glib::wrapper! {
     pub(crate) struct FMP4MuxPad(ObjectSubclass<imp::FMP4MuxPad>) @extends gst_base::AggregatorPad, gst::Pad, gst::Object;
@@ -44,6 +46,7 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
         FMP4Mux::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
         FMP4MuxPad::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
         HeaderUpdateMode::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+        EncryptionScheme::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
     }
     gst::Element::register(
         Some(plugin),
@@ -92,6 +95,23 @@ pub(crate) struct HeaderConfiguration {
     /// Start UTC time in ONVIF mode.
     /// Since Jan 1 1601 in 100ns units.
     start_utc_time: Option<u64>,
+
+    /// Common Encryption configuration if the tracks are encrypted.
+    encryption: Option<EncryptionConfiguration>,
+}
+
+#[derive(Debug)]
+pub(crate) struct EncryptionConfiguration {
+    scheme: EncryptionScheme,
+
+    /// Default key ID of all tracks.
+    key_id: [u8; 16],
+
+    /// Constant IV for the `cbcs` scheme.
+    constant_iv: [u8; 16],
+
+    /// Complete `pssh` boxes to be included in the `moov`.
+    pssh: Vec<gst::Buffer>,
 }
 
 #[derive(Debug)]
@@ -121,6 +141,48 @@ pub(crate) struct FragmentHeaderConfiguration<'a> {
 
     streams: &'a [FragmentHeaderStream],
     buffers: &'a [Buffer],
+
+    /// Event messages to write before the `moof`.
+    event_messages: &'a [EventMessage],
+
+    /// Producer reference time to write before the `moof`.
+    producer_reference_time: Option<ProducerReferenceTime>,
+}
+
+#[derive(Debug)]
+pub(crate) struct EventMessage {
+    /// Index of the stream whose timescale is used.
+    idx: usize,
+
+    /// `emsg` box version.
+    ///
+    /// Version 0 signals the presentation time relative to the earliest presentation time of the
+    /// fragment, version 1 signals the presentation time on the media timeline.
+    version: u8,
+
+    scheme_id_uri: String,
+    value: String,
+
+    /// Presentation time or presentation time delta, depending on the version.
+    presentation_time: gst::ClockTime,
+
+    /// Duration of the event, `None` if unknown.
+    duration: Option<gst::ClockTime>,
+
+    id: u32,
+    message_data: gst::Buffer,
+}
+
+#[derive(Debug)]
+pub(crate) struct ProducerReferenceTime {
+    /// Index of the reference stream.
+    idx: usize,
+
+    /// UTC time in the NTP epoch.
+    ntp_time: gst::ClockTime,
+
+    /// Presentation time of the reference stream corresponding to the UTC time.
+    media_time: gst::ClockTime,
 }
 
 #[derive(Debug)]
@@ -177,6 +239,18 @@ pub(crate) struct Buffer {
 
     /// Composition time offset
     composition_time_offset: Option<i64>,
+
+    /// Sample auxiliary information if this buffer is encrypted
+    sample_encryption: Option<SampleEncryption>,
+}
+
+#[derive(Debug)]
+pub(crate) struct SampleEncryption {
+    /// Per-sample IV, empty if a constant IV is used.
+    iv: Vec<u8>,
+
+    /// Clear and protected byte counts, empty if the whole sample is protected.
+    subsamples: Vec<(u16, u32)>,
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -212,3 +286,18 @@ pub(crate) enum HeaderUpdateMode {
     Rewrite,
     Update,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[repr(i32)]
+#[enum_type(name = "GstFMP4MuxEncryptionScheme")]
+pub(crate) enum EncryptionScheme {
+    #[enum_value(name = "None: No encryption", nick = "none")]
+    None,
+    #[enum_value(
+        name = "cenc: AES-CTR full sample and subsample encryption",
+        nick = "cenc"
+    )]
+    Cenc,
+    #[enum_value(name = "cbcs: AES-CBC subsample pattern encryption", nick = "cbcs")]
+    Cbcs,
+}
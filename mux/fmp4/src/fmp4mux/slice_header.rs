@@ -0,0 +1,1562 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Minimal H.264 / H.265 parameter set and slice header parsing.
+//!
+//! This only parses as much as is needed to find where the slice header of a VCL NAL unit ends,
+//! which is required for keeping it in the clear when encrypting the slice data.
+
+use anyhow::{bail, Context, Error};
+use bitstream_io::{BigEndian, BitRead, BitReader};
+use std::collections::HashMap;
+use std::io::Cursor;
+
+type Reader<'a> = BitReader<Cursor<&'a [u8]>, BigEndian>;
+
+/// Removes the emulation prevention bytes from a NAL unit.
+///
+/// Returns the RBSP and the positions in the RBSP in front of which a byte was removed.
+fn unescape(data: &[u8]) -> (Vec<u8>, Vec<usize>) {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut removed = vec![];
+
+    let mut zeroes = 0;
+    for &b in data {
+        if zeroes >= 2 && b == 0x03 {
+            removed.push(rbsp.len());
+            zeroes = 0;
+            continue;
+        }
+
+        zeroes = if b == 0 { zeroes + 1 } else { 0 };
+        rbsp.push(b);
+    }
+
+    (rbsp, removed)
+}
+
+/// Converts a number of RBSP bytes into the corresponding number of NAL unit bytes.
+fn escaped_len(rbsp_len: usize, removed: &[usize]) -> usize {
+    rbsp_len + removed.iter().filter(|&&pos| pos < rbsp_len).count()
+}
+
+/// Reads an `ue(v)` value.
+///
+/// None of the parsed syntax elements can have values larger than 2^25 - 2 in valid streams, so
+/// larger values are rejected to avoid overflows in later calculations.
+fn read_ue(r: &mut Reader) -> Result<u32, Error> {
+    let zeroes = r.read_unary1()?;
+    if zeroes > 24 {
+        bail!("too big exp-golomb code");
+    }
+
+    let value = r.read::<u32>(zeroes)?;
+    Ok((1 << zeroes) - 1 + value)
+}
+
+fn read_se(r: &mut Reader) -> Result<i32, Error> {
+    let k = read_ue(r)? as i64;
+    let value = if k % 2 == 1 { (k + 1) / 2 } else { -(k / 2) };
+
+    Ok(value as i32)
+}
+
+/// Number of bits needed for values in `0..n`, i.e. Ceil(Log2(n)).
+fn ceil_log2(n: u32) -> u32 {
+    if n <= 1 {
+        0
+    } else {
+        32 - (n - 1).leading_zeros()
+    }
+}
+
+/// Number of bytes from the start of the NAL unit to the end of the slice header, given the
+/// position in bits after the slice header in the RBSP following the NAL unit header.
+fn header_len(nal_header_size: usize, bits: u64, removed: &[usize]) -> usize {
+    nal_header_size + escaped_len((bits as usize + 7) / 8, removed)
+}
+
+#[derive(Debug, Clone)]
+struct AvcSps {
+    chroma_array_type: u32,
+    separate_colour_plane: bool,
+    log2_max_frame_num: u32,
+    pic_order_cnt_type: u32,
+    log2_max_pic_order_cnt_lsb: u32,
+    delta_pic_order_always_zero: bool,
+    frame_mbs_only: bool,
+    pic_size_in_map_units: u32,
+}
+
+#[derive(Debug, Clone)]
+struct AvcPps {
+    sps_id: u32,
+    entropy_coding_mode: bool,
+    bottom_field_pic_order_in_frame_present: bool,
+    num_slice_groups_minus1: u32,
+    slice_group_map_type: u32,
+    slice_group_change_rate: u32,
+    num_ref_idx_l0_default_active_minus1: u32,
+    num_ref_idx_l1_default_active_minus1: u32,
+    weighted_pred: bool,
+    weighted_bipred_idc: u32,
+    deblocking_filter_control_present: bool,
+    redundant_pic_cnt_present: bool,
+}
+
+fn avc_scaling_list(r: &mut Reader, size: usize) -> Result<(), Error> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta = read_se(r)?;
+            next_scale = (last_scale + delta + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_avc_sps(rbsp: &[u8]) -> Result<(u32, AvcSps), Error> {
+    let mut r = Reader::endian(Cursor::new(rbsp), BigEndian);
+
+    let profile_idc = r.read::<u8>(8)?;
+    // constraint flags and level
+    r.skip(16)?;
+    let sps_id = read_ue(&mut r)?;
+    if sps_id > 31 {
+        bail!("invalid SPS id {sps_id}");
+    }
+
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = read_ue(&mut r)?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.read_bit()?;
+        }
+        // bit_depth_luma_minus8, bit_depth_chroma_minus8
+        read_ue(&mut r)?;
+        read_ue(&mut r)?;
+        // qpprime_y_zero_transform_bypass_flag
+        r.skip(1)?;
+        if r.read_bit()? {
+            for i in 0..if chroma_format_idc != 3 { 8 } else { 12 } {
+                if r.read_bit()? {
+                    avc_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    let log2_max_frame_num = read_ue(&mut r)? + 4;
+    if log2_max_frame_num > 16 {
+        bail!("invalid log2_max_frame_num {log2_max_frame_num}");
+    }
+
+    let pic_order_cnt_type = read_ue(&mut r)?;
+    let mut log2_max_pic_order_cnt_lsb = 0;
+    let mut delta_pic_order_always_zero = false;
+    match pic_order_cnt_type {
+        0 => {
+            log2_max_pic_order_cnt_lsb = read_ue(&mut r)? + 4;
+            if log2_max_pic_order_cnt_lsb > 16 {
+                bail!("invalid log2_max_pic_order_cnt_lsb {log2_max_pic_order_cnt_lsb}");
+            }
+        }
+        1 => {
+            delta_pic_order_always_zero = r.read_bit()?;
+            // offset_for_non_ref_pic, offset_for_top_to_bottom_field
+            read_se(&mut r)?;
+            read_se(&mut r)?;
+            let num_ref_frames_in_pic_order_cnt_cycle = read_ue(&mut r)?;
+            if num_ref_frames_in_pic_order_cnt_cycle > 255 {
+                bail!("invalid num_ref_frames_in_pic_order_cnt_cycle");
+            }
+            for _ in 0..num_ref_frames_in_pic_order_cnt_cycle {
+                read_se(&mut r)?;
+            }
+        }
+        2 => (),
+        _ => bail!("invalid pic_order_cnt_type {pic_order_cnt_type}"),
+    }
+
+    // max_num_ref_frames, gaps_in_frame_num_value_allowed_flag
+    read_ue(&mut r)?;
+    r.skip(1)?;
+    let pic_width_in_mbs = read_ue(&mut r)? + 1;
+    let pic_height_in_map_units = read_ue(&mut r)? + 1;
+    let frame_mbs_only = r.read_bit()?;
+
+    Ok((
+        sps_id,
+        AvcSps {
+            chroma_array_type: if separate_colour_plane {
+                0
+            } else {
+                chroma_format_idc
+            },
+            separate_colour_plane,
+            log2_max_frame_num,
+            pic_order_cnt_type,
+            log2_max_pic_order_cnt_lsb,
+            delta_pic_order_always_zero,
+            frame_mbs_only,
+            pic_size_in_map_units: pic_width_in_mbs
+                .checked_mul(pic_height_in_map_units)
+                .context("too big picture")?,
+        },
+    ))
+}
+
+fn parse_avc_pps(rbsp: &[u8]) -> Result<(u32, AvcPps), Error> {
+    let mut r = Reader::endian(Cursor::new(rbsp), BigEndian);
+
+    let pps_id = read_ue(&mut r)?;
+    if pps_id > 255 {
+        bail!("invalid PPS id {pps_id}");
+    }
+    let sps_id = read_ue(&mut r)?;
+    let entropy_coding_mode = r.read_bit()?;
+    let bottom_field_pic_order_in_frame_present = r.read_bit()?;
+
+    let num_slice_groups_minus1 = read_ue(&mut r)?;
+    let mut slice_group_map_type = 0;
+    let mut slice_group_change_rate = 1;
+    if num_slice_groups_minus1 > 7 {
+        bail!("invalid num_slice_groups_minus1 {num_slice_groups_minus1}");
+    } else if num_slice_groups_minus1 > 0 {
+        slice_group_map_type = read_ue(&mut r)?;
+        match slice_group_map_type {
+            0 => {
+                for _ in 0..=num_slice_groups_minus1 {
+                    // run_length_minus1
+                    read_ue(&mut r)?;
+                }
+            }
+            2 => {
+                for _ in 0..num_slice_groups_minus1 {
+                    // top_left, bottom_right
+                    read_ue(&mut r)?;
+                    read_ue(&mut r)?;
+                }
+            }
+            3..=5 => {
+                // slice_group_change_direction_flag
+                r.skip(1)?;
+                slice_group_change_rate = read_ue(&mut r)? + 1;
+            }
+            6 => {
+                let pic_size_in_map_units = read_ue(&mut r)? + 1;
+                let bits = ceil_log2(num_slice_groups_minus1 + 1);
+                for _ in 0..pic_size_in_map_units {
+                    r.skip(bits)?;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let num_ref_idx_l0_default_active_minus1 = read_ue(&mut r)?;
+    let num_ref_idx_l1_default_active_minus1 = read_ue(&mut r)?;
+    let weighted_pred = r.read_bit()?;
+    let weighted_bipred_idc = r.read::<u32>(2)?;
+    // pic_init_qp_minus26, pic_init_qs_minus26, chroma_qp_index_offset
+    read_se(&mut r)?;
+    read_se(&mut r)?;
+    read_se(&mut r)?;
+    let deblocking_filter_control_present = r.read_bit()?;
+    // constrained_intra_pred_flag
+    r.skip(1)?;
+    let redundant_pic_cnt_present = r.read_bit()?;
+
+    Ok((
+        pps_id,
+        AvcPps {
+            sps_id,
+            entropy_coding_mode,
+            bottom_field_pic_order_in_frame_present,
+            num_slice_groups_minus1,
+            slice_group_map_type,
+            slice_group_change_rate,
+            num_ref_idx_l0_default_active_minus1,
+            num_ref_idx_l1_default_active_minus1,
+            weighted_pred,
+            weighted_bipred_idc,
+            deblocking_filter_control_present,
+            redundant_pic_cnt_present,
+        },
+    ))
+}
+
+/// H.264 parameter sets of a stream.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct AvcParameterSets {
+    sps: HashMap<u32, AvcSps>,
+    pps: HashMap<u32, AvcPps>,
+}
+
+impl AvcParameterSets {
+    /// Creates the parameter sets from the SPS and PPS stored in an `avcC` box.
+    pub(crate) fn from_codec_data(codec_data: &[u8]) -> Result<Self, Error> {
+        let mut sets = Self::default();
+
+        if codec_data.len() < 6 {
+            bail!("too small codec_data");
+        }
+
+        let mut data = &codec_data[5..];
+        for mask in [0x1f, 0xff] {
+            let (&count, rest) = data.split_first().context("too small codec_data")?;
+            data = rest;
+            for _ in 0..(count & mask) {
+                if data.len() < 2 {
+                    bail!("too small codec_data");
+                }
+                let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+                if data.len() < 2 + len {
+                    bail!("too small codec_data");
+                }
+                sets.update(&data[2..][..len])?;
+                data = &data[2 + len..];
+            }
+        }
+
+        Ok(sets)
+    }
+
+    /// Stores the parameter set if `nal` is an SPS or PPS NAL unit.
+    pub(crate) fn update(&mut self, nal: &[u8]) -> Result<(), Error> {
+        let Some(&header) = nal.first() else {
+            return Ok(());
+        };
+
+        match header & 0x1f {
+            7 => {
+                let (rbsp, _) = unescape(&nal[1..]);
+                let (id, sps) = parse_avc_sps(&rbsp).context("invalid SPS")?;
+                self.sps.insert(id, sps);
+            }
+            8 => {
+                let (rbsp, _) = unescape(&nal[1..]);
+                let (id, pps) = parse_avc_pps(&rbsp).context("invalid PPS")?;
+                self.pps.insert(id, pps);
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes from the start of the slice NAL unit to the end of its slice
+    /// header.
+    pub(crate) fn slice_header_len(&self, nal: &[u8]) -> Result<usize, Error> {
+        let nal_ref_idc = (nal[0] >> 5) & 0x03;
+        let nal_unit_type = nal[0] & 0x1f;
+        if !matches!(nal_unit_type, 1 | 5) {
+            bail!("unsupported slice NAL unit type {nal_unit_type}");
+        }
+        let idr = nal_unit_type == 5;
+
+        let (rbsp, removed) = unescape(&nal[1..]);
+        let mut r = Reader::endian(Cursor::new(&rbsp[..]), BigEndian);
+
+        // first_mb_in_slice
+        read_ue(&mut r)?;
+        let slice_type = read_ue(&mut r)? % 5;
+        let (p, b, i, sp, si) = (
+            slice_type == 0,
+            slice_type == 1,
+            slice_type == 2,
+            slice_type == 3,
+            slice_type == 4,
+        );
+
+        let pps_id = read_ue(&mut r)?;
+        let pps = self
+            .pps
+            .get(&pps_id)
+            .with_context(|| format!("unknown PPS {pps_id}"))?;
+        let sps = self
+            .sps
+            .get(&pps.sps_id)
+            .with_context(|| format!("unknown SPS {}", pps.sps_id))?;
+
+        if sps.separate_colour_plane {
+            // colour_plane_id
+            r.skip(2)?;
+        }
+        // frame_num
+        r.skip(sps.log2_max_frame_num)?;
+
+        let mut field_pic = false;
+        if !sps.frame_mbs_only {
+            field_pic = r.read_bit()?;
+            if field_pic {
+                // bottom_field_flag
+                r.skip(1)?;
+            }
+        }
+
+        if idr {
+            // idr_pic_id
+            read_ue(&mut r)?;
+        }
+
+        if sps.pic_order_cnt_type == 0 {
+            // pic_order_cnt_lsb
+            r.skip(sps.log2_max_pic_order_cnt_lsb)?;
+            if pps.bottom_field_pic_order_in_frame_present && !field_pic {
+                // delta_pic_order_cnt_bottom
+                read_se(&mut r)?;
+            }
+        }
+        if sps.pic_order_cnt_type == 1 && !sps.delta_pic_order_always_zero {
+            // delta_pic_order_cnt[0]
+            read_se(&mut r)?;
+            if pps.bottom_field_pic_order_in_frame_present && !field_pic {
+                // delta_pic_order_cnt[1]
+                read_se(&mut r)?;
+            }
+        }
+
+        if pps.redundant_pic_cnt_present {
+            // redundant_pic_cnt
+            read_ue(&mut r)?;
+        }
+
+        if b {
+            // direct_spatial_mv_pred_flag
+            r.skip(1)?;
+        }
+
+        let mut num_ref_idx_l0_active_minus1 = pps.num_ref_idx_l0_default_active_minus1;
+        let mut num_ref_idx_l1_active_minus1 = pps.num_ref_idx_l1_default_active_minus1;
+        if p || sp || b {
+            // num_ref_idx_active_override_flag
+            if r.read_bit()? {
+                num_ref_idx_l0_active_minus1 = read_ue(&mut r)?;
+                if b {
+                    num_ref_idx_l1_active_minus1 = read_ue(&mut r)?;
+                }
+            }
+        }
+        if num_ref_idx_l0_active_minus1 > 31 || num_ref_idx_l1_active_minus1 > 31 {
+            bail!("invalid number of reference indices");
+        }
+
+        // ref_pic_list_modification()
+        let lists = if b {
+            2
+        } else if i || si {
+            0
+        } else {
+            1
+        };
+        for _ in 0..lists {
+            // ref_pic_list_modification_flag
+            if r.read_bit()? {
+                loop {
+                    match read_ue(&mut r)? {
+                        // abs_diff_pic_num_minus1 / long_term_pic_num
+                        0..=2 => {
+                            read_ue(&mut r)?;
+                        }
+                        3 => break,
+                        idc => bail!("invalid modification_of_pic_nums_idc {idc}"),
+                    }
+                }
+            }
+        }
+
+        if (pps.weighted_pred && (p || sp)) || (pps.weighted_bipred_idc == 1 && b) {
+            // pred_weight_table()
+            // luma_log2_weight_denom
+            read_ue(&mut r)?;
+            if sps.chroma_array_type != 0 {
+                // chroma_log2_weight_denom
+                read_ue(&mut r)?;
+            }
+            let mut num_refs = vec![num_ref_idx_l0_active_minus1];
+            if b {
+                num_refs.push(num_ref_idx_l1_active_minus1);
+            }
+            for num_ref_idx_active_minus1 in num_refs {
+                for _ in 0..=num_ref_idx_active_minus1 {
+                    // luma_weight_flag
+                    if r.read_bit()? {
+                        // luma_weight, luma_offset
+                        read_se(&mut r)?;
+                        read_se(&mut r)?;
+                    }
+                    // chroma_weight_flag
+                    if sps.chroma_array_type != 0 && r.read_bit()? {
+                        for _ in 0..2 {
+                            // chroma_weight, chroma_offset
+                            read_se(&mut r)?;
+                            read_se(&mut r)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        if nal_ref_idc != 0 {
+            // dec_ref_pic_marking()
+            if idr {
+                // no_output_of_prior_pics_flag, long_term_reference_flag
+                r.skip(2)?;
+            } else if r.read_bit()? {
+                // adaptive_ref_pic_marking_mode_flag
+                loop {
+                    let mmco = read_ue(&mut r)?;
+                    match mmco {
+                        0 => break,
+                        // difference_of_pic_nums_minus1 / long_term_pic_num /
+                        // max_long_term_frame_idx_plus1 / long_term_frame_idx
+                        1 | 2 | 4 | 6 => {
+                            read_ue(&mut r)?;
+                        }
+                        // difference_of_pic_nums_minus1, long_term_frame_idx
+                        3 => {
+                            read_ue(&mut r)?;
+                            read_ue(&mut r)?;
+                        }
+                        5 => (),
+                        _ => bail!("invalid memory_management_control_operation {mmco}"),
+                    }
+                }
+            }
+        }
+
+        if pps.entropy_coding_mode && !i && !si {
+            // cabac_init_idc
+            read_ue(&mut r)?;
+        }
+        // slice_qp_delta
+        read_se(&mut r)?;
+        if sp || si {
+            if sp {
+                // sp_for_switch_flag
+                r.skip(1)?;
+            }
+            // slice_qs_delta
+            read_se(&mut r)?;
+        }
+
+        if pps.deblocking_filter_control_present {
+            // disable_deblocking_filter_idc
+            if read_ue(&mut r)? != 1 {
+                // slice_alpha_c0_offset_div2, slice_beta_offset_div2
+                read_se(&mut r)?;
+                read_se(&mut r)?;
+            }
+        }
+
+        if pps.num_slice_groups_minus1 > 0 && (3..=5).contains(&pps.slice_group_map_type) {
+            // slice_group_change_cycle
+            let max = (sps.pic_size_in_map_units + pps.slice_group_change_rate - 1)
+                / pps.slice_group_change_rate;
+            r.skip(ceil_log2(max + 1))?;
+        }
+
+        Ok(header_len(1, r.position_in_bits()?, &removed))
+    }
+}
+
+/// A short-term reference picture set, as a list of (delta POC, used by current picture).
+#[derive(Debug, Default, Clone)]
+struct StRefPicSet {
+    negative: Vec<(i32, bool)>,
+    positive: Vec<(i32, bool)>,
+}
+
+impl StRefPicSet {
+    fn num_delta_pocs(&self) -> usize {
+        self.negative.len() + self.positive.len()
+    }
+
+    fn num_used(&self) -> u32 {
+        self.negative
+            .iter()
+            .chain(self.positive.iter())
+            .filter(|(_, used)| *used)
+            .count() as u32
+    }
+
+    /// Parses `st_ref_pic_set(sets.len())` given the previously parsed sets of the SPS.
+    ///
+    /// `in_slice_header` is set when parsing the set of a slice header instead of the SPS.
+    fn parse(r: &mut Reader, sets: &[StRefPicSet], in_slice_header: bool) -> Result<Self, Error> {
+        let idx = sets.len();
+
+        if idx != 0 && r.read_bit()? {
+            // inter_ref_pic_set_prediction_flag
+            let delta_idx = if in_slice_header {
+                read_ue(r)? as usize + 1
+            } else {
+                1
+            };
+            if delta_idx > idx {
+                bail!("invalid delta_idx_minus1");
+            }
+            let ref_set = &sets[idx - delta_idx];
+
+            let sign = r.read_bit()?;
+            let abs_delta_rps = read_ue(r)? as i32 + 1;
+            let delta_rps = if sign { -abs_delta_rps } else { abs_delta_rps };
+
+            let mut used_by_curr_pic = vec![];
+            let mut use_delta = vec![];
+            for _ in 0..=ref_set.num_delta_pocs() {
+                let used = r.read_bit()?;
+                used_by_curr_pic.push(used);
+                use_delta.push(used || r.read_bit()?);
+            }
+
+            let num_negative = ref_set.negative.len();
+            let num_delta_pocs = ref_set.num_delta_pocs();
+
+            let mut set = StRefPicSet::default();
+            for (j, &(delta_poc, _)) in ref_set.positive.iter().enumerate().rev() {
+                let d_poc = delta_poc + delta_rps;
+                if d_poc < 0 && use_delta[num_negative + j] {
+                    set.negative
+                        .push((d_poc, used_by_curr_pic[num_negative + j]));
+                }
+            }
+            if delta_rps < 0 && use_delta[num_delta_pocs] {
+                set.negative
+                    .push((delta_rps, used_by_curr_pic[num_delta_pocs]));
+            }
+            for (j, &(delta_poc, _)) in ref_set.negative.iter().enumerate() {
+                let d_poc = delta_poc + delta_rps;
+                if d_poc < 0 && use_delta[j] {
+                    set.negative.push((d_poc, used_by_curr_pic[j]));
+                }
+            }
+
+            for (j, &(delta_poc, _)) in ref_set.negative.iter().enumerate().rev() {
+                let d_poc = delta_poc + delta_rps;
+                if d_poc > 0 && use_delta[j] {
+                    set.positive.push((d_poc, used_by_curr_pic[j]));
+                }
+            }
+            if delta_rps > 0 && use_delta[num_delta_pocs] {
+                set.positive
+                    .push((delta_rps, used_by_curr_pic[num_delta_pocs]));
+            }
+            for (j, &(delta_poc, _)) in ref_set.positive.iter().enumerate() {
+                let d_poc = delta_poc + delta_rps;
+                if d_poc > 0 && use_delta[num_negative + j] {
+                    set.positive
+                        .push((d_poc, used_by_curr_pic[num_negative + j]));
+                }
+            }
+
+            Ok(set)
+        } else {
+            let num_negative = read_ue(r)?;
+            let num_positive = read_ue(r)?;
+            if num_negative > 16 || num_positive > 16 {
+                bail!("invalid short-term reference picture set");
+            }
+
+            let mut set = StRefPicSet::default();
+            let mut poc = 0;
+            for _ in 0..num_negative {
+                poc -= read_ue(r)? as i32 + 1;
+                set.negative.push((poc, r.read_bit()?));
+            }
+            let mut poc = 0;
+            for _ in 0..num_positive {
+                poc += read_ue(r)? as i32 + 1;
+                set.positive.push((poc, r.read_bit()?));
+            }
+
+            Ok(set)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct HevcSps {
+    chroma_array_type: u32,
+    separate_colour_plane: bool,
+    pic_size_in_ctbs: u32,
+    log2_max_pic_order_cnt_lsb: u32,
+    sample_adaptive_offset_enabled: bool,
+    st_ref_pic_sets: Vec<StRefPicSet>,
+    long_term_ref_pics_present: bool,
+    /// `used_by_curr_pic_lt_sps_flag` of the long-term reference pictures of the SPS.
+    lt_ref_pics_used: Vec<bool>,
+    temporal_mvp_enabled: bool,
+}
+
+#[derive(Debug, Clone)]
+struct HevcPps {
+    sps_id: u32,
+    dependent_slice_segments_enabled: bool,
+    output_flag_present: bool,
+    num_extra_slice_header_bits: u32,
+    cabac_init_present: bool,
+    num_ref_idx_l0_default_active_minus1: u32,
+    num_ref_idx_l1_default_active_minus1: u32,
+    slice_chroma_qp_offsets_present: bool,
+    weighted_pred: bool,
+    weighted_bipred: bool,
+    tiles_enabled: bool,
+    entropy_coding_sync_enabled: bool,
+    loop_filter_across_slices_enabled: bool,
+    deblocking_filter_override_enabled: bool,
+    deblocking_filter_disabled: bool,
+    lists_modification_present: bool,
+    slice_segment_header_extension_present: bool,
+    chroma_qp_offset_list_enabled: bool,
+}
+
+fn hevc_profile_tier_level(r: &mut Reader, max_sub_layers_minus1: u32) -> Result<(), Error> {
+    // general profile, tier and level
+    r.skip(96)?;
+
+    let mut sub_layers = vec![];
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layers.push((r.read_bit()?, r.read_bit()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        for _ in max_sub_layers_minus1..8 {
+            r.skip(2)?;
+        }
+    }
+    for (profile_present, level_present) in sub_layers {
+        if profile_present {
+            r.skip(88)?;
+        }
+        if level_present {
+            r.skip(8)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn hevc_scaling_list_data(r: &mut Reader) -> Result<(), Error> {
+    for size_id in 0..4 {
+        let step = if size_id == 3 { 3 } else { 1 };
+        for _ in (0..6).step_by(step) {
+            // scaling_list_pred_mode_flag
+            if !r.read_bit()? {
+                // scaling_list_pred_matrix_id_delta
+                read_ue(r)?;
+            } else {
+                let coef_num = std::cmp::min(64, 1 << (4 + (size_id << 1)));
+                if size_id > 1 {
+                    // scaling_list_dc_coef_minus8
+                    read_se(r)?;
+                }
+                for _ in 0..coef_num {
+                    // scaling_list_delta_coef
+                    read_se(r)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_hevc_sps(rbsp: &[u8]) -> Result<(u32, HevcSps), Error> {
+    let mut r = Reader::endian(Cursor::new(rbsp), BigEndian);
+
+    // sps_video_parameter_set_id
+    r.skip(4)?;
+    let max_sub_layers_minus1 = r.read::<u32>(3)?;
+    // sps_temporal_id_nesting_flag
+    r.skip(1)?;
+    hevc_profile_tier_level(&mut r, max_sub_layers_minus1)?;
+
+    let sps_id = read_ue(&mut r)?;
+    if sps_id > 15 {
+        bail!("invalid SPS id {sps_id}");
+    }
+    let chroma_format_idc = read_ue(&mut r)?;
+    let mut separate_colour_plane = false;
+    if chroma_format_idc == 3 {
+        separate_colour_plane = r.read_bit()?;
+    }
+    let pic_width = read_ue(&mut r)?;
+    let pic_height = read_ue(&mut r)?;
+    // conformance_window_flag
+    if r.read_bit()? {
+        for _ in 0..4 {
+            read_ue(&mut r)?;
+        }
+    }
+    // bit_depth_luma_minus8, bit_depth_chroma_minus8
+    read_ue(&mut r)?;
+    read_ue(&mut r)?;
+    let log2_max_pic_order_cnt_lsb = read_ue(&mut r)? + 4;
+    if log2_max_pic_order_cnt_lsb > 16 {
+        bail!("invalid log2_max_pic_order_cnt_lsb {log2_max_pic_order_cnt_lsb}");
+    }
+
+    let sub_layer_ordering_info_present = r.read_bit()?;
+    let first = if sub_layer_ordering_info_present {
+        0
+    } else {
+        max_sub_layers_minus1
+    };
+    for _ in first..=max_sub_layers_minus1 {
+        // sps_max_dec_pic_buffering_minus1, sps_max_num_reorder_pics,
+        // sps_max_latency_increase_plus1
+        read_ue(&mut r)?;
+        read_ue(&mut r)?;
+        read_ue(&mut r)?;
+    }
+
+    let log2_min_cb_size = read_ue(&mut r)? + 3;
+    let log2_ctb_size = log2_min_cb_size + read_ue(&mut r)?;
+    if log2_ctb_size > 6 {
+        bail!("invalid CTB size");
+    }
+    // log2_min_luma_transform_block_size_minus2, log2_diff_max_min_luma_transform_block_size,
+    // max_transform_hierarchy_depth_inter, max_transform_hierarchy_depth_intra
+    for _ in 0..4 {
+        read_ue(&mut r)?;
+    }
+
+    // scaling_list_enabled_flag
+    if r.read_bit()? {
+        // sps_scaling_list_data_present_flag
+        if r.read_bit()? {
+            hevc_scaling_list_data(&mut r)?;
+        }
+    }
+    // amp_enabled_flag
+    r.skip(1)?;
+    let sample_adaptive_offset_enabled = r.read_bit()?;
+    // pcm_enabled_flag
+    if r.read_bit()? {
+        // pcm_sample_bit_depth_luma_minus1, pcm_sample_bit_depth_chroma_minus1
+        r.skip(8)?;
+        // log2_min_pcm_luma_coding_block_size_minus3,
+        // log2_diff_max_min_pcm_luma_coding_block_size
+        read_ue(&mut r)?;
+        read_ue(&mut r)?;
+        // pcm_loop_filter_disabled_flag
+        r.skip(1)?;
+    }
+
+    let num_short_term_ref_pic_sets = read_ue(&mut r)? as usize;
+    if num_short_term_ref_pic_sets > 64 {
+        bail!("invalid num_short_term_ref_pic_sets {num_short_term_ref_pic_sets}");
+    }
+    let mut st_ref_pic_sets = Vec::with_capacity(num_short_term_ref_pic_sets);
+    for _ in 0..num_short_term_ref_pic_sets {
+        let set = StRefPicSet::parse(&mut r, &st_ref_pic_sets, false)?;
+        st_ref_pic_sets.push(set);
+    }
+
+    let long_term_ref_pics_present = r.read_bit()?;
+    let mut lt_ref_pics_used = vec![];
+    if long_term_ref_pics_present {
+        let num_long_term_ref_pics = read_ue(&mut r)?;
+        if num_long_term_ref_pics > 32 {
+            bail!("invalid num_long_term_ref_pics_sps {num_long_term_ref_pics}");
+        }
+        for _ in 0..num_long_term_ref_pics {
+            // lt_ref_pic_poc_lsb_sps
+            r.skip(log2_max_pic_order_cnt_lsb)?;
+            lt_ref_pics_used.push(r.read_bit()?);
+        }
+    }
+    let temporal_mvp_enabled = r.read_bit()?;
+
+    let ctb_size = 1 << log2_ctb_size;
+    let pic_size_in_ctbs = ((pic_width + ctb_size - 1) / ctb_size)
+        .checked_mul((pic_height + ctb_size - 1) / ctb_size)
+        .context("too big picture")?;
+
+    Ok((
+        sps_id,
+        HevcSps {
+            chroma_array_type: if separate_colour_plane {
+                0
+            } else {
+                chroma_format_idc
+            },
+            separate_colour_plane,
+            pic_size_in_ctbs,
+            log2_max_pic_order_cnt_lsb,
+            sample_adaptive_offset_enabled,
+            st_ref_pic_sets,
+            long_term_ref_pics_present,
+            lt_ref_pics_used,
+            temporal_mvp_enabled,
+        },
+    ))
+}
+
+fn parse_hevc_pps(rbsp: &[u8]) -> Result<(u32, HevcPps), Error> {
+    let mut r = Reader::endian(Cursor::new(rbsp), BigEndian);
+
+    let pps_id = read_ue(&mut r)?;
+    if pps_id > 63 {
+        bail!("invalid PPS id {pps_id}");
+    }
+    let sps_id = read_ue(&mut r)?;
+    let dependent_slice_segments_enabled = r.read_bit()?;
+    let output_flag_present = r.read_bit()?;
+    let num_extra_slice_header_bits = r.read::<u32>(3)?;
+    // sign_data_hiding_enabled_flag
+    r.skip(1)?;
+    let cabac_init_present = r.read_bit()?;
+    let num_ref_idx_l0_default_active_minus1 = read_ue(&mut r)?;
+    let num_ref_idx_l1_default_active_minus1 = read_ue(&mut r)?;
+    // init_qp_minus26
+    read_se(&mut r)?;
+    // constrained_intra_pred_flag
+    r.skip(1)?;
+    let transform_skip_enabled = r.read_bit()?;
+    // cu_qp_delta_enabled_flag
+    if r.read_bit()? {
+        // diff_cu_qp_delta_depth
+        read_ue(&mut r)?;
+    }
+    // pps_cb_qp_offset, pps_cr_qp_offset
+    read_se(&mut r)?;
+    read_se(&mut r)?;
+    let slice_chroma_qp_offsets_present = r.read_bit()?;
+    let weighted_pred = r.read_bit()?;
+    let weighted_bipred = r.read_bit()?;
+    // transquant_bypass_enabled_flag
+    r.skip(1)?;
+    let tiles_enabled = r.read_bit()?;
+    let entropy_coding_sync_enabled = r.read_bit()?;
+    if tiles_enabled {
+        let num_tile_columns_minus1 = read_ue(&mut r)?;
+        let num_tile_rows_minus1 = read_ue(&mut r)?;
+        if num_tile_columns_minus1 > 19 || num_tile_rows_minus1 > 21 {
+            bail!("invalid number of tiles");
+        }
+        // uniform_spacing_flag
+        if !r.read_bit()? {
+            for _ in 0..num_tile_columns_minus1 + num_tile_rows_minus1 {
+                // column_width_minus1 / row_height_minus1
+                read_ue(&mut r)?;
+            }
+        }
+        // loop_filter_across_tiles_enabled_flag
+        r.skip(1)?;
+    }
+    let loop_filter_across_slices_enabled = r.read_bit()?;
+
+    let mut deblocking_filter_override_enabled = false;
+    let mut deblocking_filter_disabled = false;
+    // deblocking_filter_control_present_flag
+    if r.read_bit()? {
+        deblocking_filter_override_enabled = r.read_bit()?;
+        deblocking_filter_disabled = r.read_bit()?;
+        if !deblocking_filter_disabled {
+            // pps_beta_offset_div2, pps_tc_offset_div2
+            read_se(&mut r)?;
+            read_se(&mut r)?;
+        }
+    }
+    // pps_scaling_list_data_present_flag
+    if r.read_bit()? {
+        hevc_scaling_list_data(&mut r)?;
+    }
+    let lists_modification_present = r.read_bit()?;
+    // log2_parallel_merge_level_minus2
+    read_ue(&mut r)?;
+    let slice_segment_header_extension_present = r.read_bit()?;
+
+    let mut chroma_qp_offset_list_enabled = false;
+    // pps_extension_present_flag
+    if r.read_bit()? {
+        let range_extension = r.read_bit()?;
+        let other_extensions = r.read::<u8>(7)?;
+        if other_extensions != 0 {
+            bail!("unsupported PPS extensions");
+        }
+
+        if range_extension {
+            if transform_skip_enabled {
+                // log2_max_transform_skip_block_size_minus2
+                read_ue(&mut r)?;
+            }
+            // cross_component_prediction_enabled_flag
+            r.skip(1)?;
+            chroma_qp_offset_list_enabled = r.read_bit()?;
+        }
+    }
+
+    Ok((
+        pps_id,
+        HevcPps {
+            sps_id,
+            dependent_slice_segments_enabled,
+            output_flag_present,
+            num_extra_slice_header_bits,
+            cabac_init_present,
+            num_ref_idx_l0_default_active_minus1,
+            num_ref_idx_l1_default_active_minus1,
+            slice_chroma_qp_offsets_present,
+            weighted_pred,
+            weighted_bipred,
+            tiles_enabled,
+            entropy_coding_sync_enabled,
+            loop_filter_across_slices_enabled,
+            deblocking_filter_override_enabled,
+            deblocking_filter_disabled,
+            lists_modification_present,
+            slice_segment_header_extension_present,
+            chroma_qp_offset_list_enabled,
+        },
+    ))
+}
+
+/// H.265 parameter sets of a stream.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct HevcParameterSets {
+    sps: HashMap<u32, HevcSps>,
+    pps: HashMap<u32, HevcPps>,
+}
+
+impl HevcParameterSets {
+    /// Creates the parameter sets from the SPS and PPS stored in an `hvcC` box.
+    pub(crate) fn from_codec_data(codec_data: &[u8]) -> Result<Self, Error> {
+        let mut sets = Self::default();
+
+        if codec_data.len() < 23 {
+            bail!("too small codec_data");
+        }
+
+        let num_arrays = codec_data[22];
+        let mut data = &codec_data[23..];
+        for _ in 0..num_arrays {
+            if data.len() < 3 {
+                bail!("too small codec_data");
+            }
+            let num_nalus = u16::from_be_bytes([data[1], data[2]]);
+            data = &data[3..];
+            for _ in 0..num_nalus {
+                if data.len() < 2 {
+                    bail!("too small codec_data");
+                }
+                let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+                if data.len() < 2 + len {
+                    bail!("too small codec_data");
+                }
+                sets.update(&data[2..][..len])?;
+                data = &data[2 + len..];
+            }
+        }
+
+        Ok(sets)
+    }
+
+    /// Stores the parameter set if `nal` is an SPS or PPS NAL unit of the base layer.
+    pub(crate) fn update(&mut self, nal: &[u8]) -> Result<(), Error> {
+        if nal.len() < 2 {
+            return Ok(());
+        }
+
+        let nal_unit_type = (nal[0] >> 1) & 0x3f;
+        let layer_id = ((nal[0] & 0x01) << 5) | (nal[1] >> 3);
+        if layer_id != 0 {
+            return Ok(());
+        }
+
+        match nal_unit_type {
+            33 => {
+                let (rbsp, _) = unescape(&nal[2..]);
+                let (id, sps) = parse_hevc_sps(&rbsp).context("invalid SPS")?;
+                self.sps.insert(id, sps);
+            }
+            34 => {
+                let (rbsp, _) = unescape(&nal[2..]);
+                let (id, pps) = parse_hevc_pps(&rbsp).context("invalid PPS")?;
+                self.pps.insert(id, pps);
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes from the start of the slice segment NAL unit to the end of
+    /// its slice segment header.
+    pub(crate) fn slice_header_len(&self, nal: &[u8]) -> Result<usize, Error> {
+        let nal_unit_type = (nal[0] >> 1) & 0x3f;
+        if nal_unit_type > 21 || (10..=15).contains(&nal_unit_type) {
+            bail!("unsupported slice NAL unit type {nal_unit_type}");
+        }
+
+        let (rbsp, removed) = unescape(&nal[2..]);
+        let mut r = Reader::endian(Cursor::new(&rbsp[..]), BigEndian);
+
+        let first_slice_segment_in_pic = r.read_bit()?;
+        if (16..=23).contains(&nal_unit_type) {
+            // no_output_of_prior_pics_flag
+            r.skip(1)?;
+        }
+        let pps_id = read_ue(&mut r)?;
+        let pps = self
+            .pps
+            .get(&pps_id)
+            .with_context(|| format!("unknown PPS {pps_id}"))?;
+        let sps = self
+            .sps
+            .get(&pps.sps_id)
+            .with_context(|| format!("unknown SPS {}", pps.sps_id))?;
+
+        let mut dependent_slice_segment = false;
+        if !first_slice_segment_in_pic {
+            if pps.dependent_slice_segments_enabled {
+                dependent_slice_segment = r.read_bit()?;
+            }
+            // slice_segment_address
+            r.skip(ceil_log2(sps.pic_size_in_ctbs))?;
+        }
+
+        if !dependent_slice_segment {
+            // slice_reserved_flag
+            r.skip(pps.num_extra_slice_header_bits)?;
+            let slice_type = read_ue(&mut r)?;
+            let (b, p) = (slice_type == 0, slice_type == 1);
+            if pps.output_flag_present {
+                // pic_output_flag
+                r.skip(1)?;
+            }
+            if sps.separate_colour_plane {
+                // colour_plane_id
+                r.skip(2)?;
+            }
+
+            let mut num_pic_total_curr = 0;
+            let mut slice_temporal_mvp_enabled = false;
+            // Not IDR_W_RADL or IDR_N_LP
+            if nal_unit_type != 19 && nal_unit_type != 20 {
+                // slice_pic_order_cnt_lsb
+                r.skip(sps.log2_max_pic_order_cnt_lsb)?;
+
+                let num_sets = sps.st_ref_pic_sets.len();
+                // short_term_ref_pic_set_sps_flag
+                if !r.read_bit()? {
+                    let set = StRefPicSet::parse(&mut r, &sps.st_ref_pic_sets, true)?;
+                    num_pic_total_curr += set.num_used();
+                } else {
+                    let idx = if num_sets > 1 {
+                        r.read::<u32>(ceil_log2(num_sets as u32))? as usize
+                    } else {
+                        0
+                    };
+                    let set = sps
+                        .st_ref_pic_sets
+                        .get(idx)
+                        .context("invalid short_term_ref_pic_set_idx")?;
+                    num_pic_total_curr += set.num_used();
+                }
+
+                if sps.long_term_ref_pics_present {
+                    let num_lt_sps = sps.lt_ref_pics_used.len() as u32;
+                    let num_long_term_sps = if num_lt_sps > 0 { read_ue(&mut r)? } else { 0 };
+                    let num_long_term_pics = read_ue(&mut r)?;
+                    if num_long_term_sps > num_lt_sps || num_long_term_pics > 32 {
+                        bail!("invalid number of long-term reference pictures");
+                    }
+                    for i in 0..num_long_term_sps + num_long_term_pics {
+                        let used = if i < num_long_term_sps {
+                            let idx = if num_lt_sps > 1 {
+                                r.read::<u32>(ceil_log2(num_lt_sps))?
+                            } else {
+                                0
+                            };
+                            *sps.lt_ref_pics_used
+                                .get(idx as usize)
+                                .context("invalid lt_idx_sps")?
+                        } else {
+                            // poc_lsb_lt
+                            r.skip(sps.log2_max_pic_order_cnt_lsb)?;
+                            r.read_bit()?
+                        };
+                        if used {
+                            num_pic_total_curr += 1;
+                        }
+                        // delta_poc_msb_present_flag
+                        if r.read_bit()? {
+                            // delta_poc_msb_cycle_lt
+                            read_ue(&mut r)?;
+                        }
+                    }
+                }
+
+                if sps.temporal_mvp_enabled {
+                    slice_temporal_mvp_enabled = r.read_bit()?;
+                }
+            }
+
+            let mut slice_sao_luma = false;
+            let mut slice_sao_chroma = false;
+            if sps.sample_adaptive_offset_enabled {
+                slice_sao_luma = r.read_bit()?;
+                if sps.chroma_array_type != 0 {
+                    slice_sao_chroma = r.read_bit()?;
+                }
+            }
+
+            if p || b {
+                let mut num_ref_idx_l0_active_minus1 = pps.num_ref_idx_l0_default_active_minus1;
+                let mut num_ref_idx_l1_active_minus1 = pps.num_ref_idx_l1_default_active_minus1;
+                // num_ref_idx_active_override_flag
+                if r.read_bit()? {
+                    num_ref_idx_l0_active_minus1 = read_ue(&mut r)?;
+                    if b {
+                        num_ref_idx_l1_active_minus1 = read_ue(&mut r)?;
+                    }
+                }
+                if num_ref_idx_l0_active_minus1 > 14 || num_ref_idx_l1_active_minus1 > 14 {
+                    bail!("invalid number of reference indices");
+                }
+
+                if pps.lists_modification_present && num_pic_total_curr > 1 {
+                    // ref_pic_lists_modification()
+                    let bits = ceil_log2(num_pic_total_curr);
+                    // ref_pic_list_modification_flag_l0
+                    if r.read_bit()? {
+                        // list_entry_l0
+                        r.skip(bits * (num_ref_idx_l0_active_minus1 + 1))?;
+                    }
+                    // ref_pic_list_modification_flag_l1
+                    if b && r.read_bit()? {
+                        // list_entry_l1
+                        r.skip(bits * (num_ref_idx_l1_active_minus1 + 1))?;
+                    }
+                }
+
+                if b {
+                    // mvd_l1_zero_flag
+                    r.skip(1)?;
+                }
+                if pps.cabac_init_present {
+                    // cabac_init_flag
+                    r.skip(1)?;
+                }
+                if slice_temporal_mvp_enabled {
+                    let collocated_from_l0 = if b { r.read_bit()? } else { true };
+                    if (collocated_from_l0 && num_ref_idx_l0_active_minus1 > 0)
+                        || (!collocated_from_l0 && num_ref_idx_l1_active_minus1 > 0)
+                    {
+                        // collocated_ref_idx
+                        read_ue(&mut r)?;
+                    }
+                }
+
+                if (pps.weighted_pred && p) || (pps.weighted_bipred && b) {
+                    // pred_weight_table()
+                    // luma_log2_weight_denom
+                    read_ue(&mut r)?;
+                    if sps.chroma_array_type != 0 {
+                        // delta_chroma_log2_weight_denom
+                        read_se(&mut r)?;
+                    }
+                    let mut num_refs = vec![num_ref_idx_l0_active_minus1 + 1];
+                    if b {
+                        num_refs.push(num_ref_idx_l1_active_minus1 + 1);
+                    }
+                    for num_refs in num_refs {
+                        let mut luma_weight = vec![];
+                        for _ in 0..num_refs {
+                            luma_weight.push(r.read_bit()?);
+                        }
+                        let mut chroma_weight = vec![false; num_refs as usize];
+                        if sps.chroma_array_type != 0 {
+                            for flag in chroma_weight.iter_mut() {
+                                *flag = r.read_bit()?;
+                            }
+                        }
+                        for (luma, chroma) in luma_weight.into_iter().zip(chroma_weight) {
+                            if luma {
+                                // delta_luma_weight, luma_offset
+                                read_se(&mut r)?;
+                                read_se(&mut r)?;
+                            }
+                            if chroma {
+                                for _ in 0..2 {
+                                    // delta_chroma_weight, delta_chroma_offset
+                                    read_se(&mut r)?;
+                                    read_se(&mut r)?;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                // five_minus_max_num_merge_cand
+                read_ue(&mut r)?;
+            }
+
+            // slice_qp_delta
+            read_se(&mut r)?;
+            if pps.slice_chroma_qp_offsets_present {
+                // slice_cb_qp_offset, slice_cr_qp_offset
+                read_se(&mut r)?;
+                read_se(&mut r)?;
+            }
+            if pps.chroma_qp_offset_list_enabled {
+                // cu_chroma_qp_offset_enabled_flag
+                r.skip(1)?;
+            }
+
+            let mut deblocking_filter_override = false;
+            if pps.deblocking_filter_override_enabled {
+                deblocking_filter_override = r.read_bit()?;
+            }
+            let mut slice_deblocking_filter_disabled = pps.deblocking_filter_disabled;
+            if deblocking_filter_override {
+                slice_deblocking_filter_disabled = r.read_bit()?;
+                if !slice_deblocking_filter_disabled {
+                    // slice_beta_offset_div2, slice_tc_offset_div2
+                    read_se(&mut r)?;
+                    read_se(&mut r)?;
+                }
+            }
+            if pps.loop_filter_across_slices_enabled
+                && (slice_sao_luma || slice_sao_chroma || !slice_deblocking_filter_disabled)
+            {
+                // slice_loop_filter_across_slices_enabled_flag
+                r.skip(1)?;
+            }
+        }
+
+        if pps.tiles_enabled || pps.entropy_coding_sync_enabled {
+            let num_entry_point_offsets = read_ue(&mut r)?;
+            if num_entry_point_offsets > 0 {
+                let offset_len = read_ue(&mut r)? + 1;
+                if offset_len > 32 {
+                    bail!("invalid offset_len_minus1");
+                }
+                for _ in 0..num_entry_point_offsets {
+                    // entry_point_offset_minus1
+                    r.skip(offset_len)?;
+                }
+            }
+        }
+
+        if pps.slice_segment_header_extension_present {
+            let len = read_ue(&mut r)?;
+            if len > 256 {
+                bail!("invalid slice_segment_header_extension_length");
+            }
+            // slice_segment_header_extension_data_byte
+            r.skip(8 * len)?;
+        }
+
+        // byte_alignment()
+        if !r.read_bit()? {
+            bail!("invalid slice segment header");
+        }
+        r.byte_align();
+
+        Ok(header_len(2, r.position_in_bits()?, &removed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes bits given as a string of '0' and '1', padded with zeroes to full bytes.
+    fn bits(s: &str) -> Vec<u8> {
+        let s = s.replace(' ', "");
+        let mut data = vec![0u8; (s.len() + 7) / 8];
+        for (i, c) in s.chars().enumerate() {
+            if c == '1' {
+                data[i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn exp_golomb() {
+        let data = bits("1 010 011 00100 00101 0000001111000");
+        let mut r = Reader::endian(Cursor::new(&data[..]), BigEndian);
+        assert_eq!(read_ue(&mut r).unwrap(), 0);
+        assert_eq!(read_ue(&mut r).unwrap(), 1);
+        assert_eq!(read_se(&mut r).unwrap(), -1);
+        assert_eq!(read_se(&mut r).unwrap(), 2);
+        assert_eq!(read_se(&mut r).unwrap(), -2);
+        assert_eq!(read_ue(&mut r).unwrap(), 119);
+    }
+
+    #[test]
+    fn emulation_prevention() {
+        let (rbsp, removed) = unescape(&[0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00]);
+        assert_eq!(rbsp, [0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(removed, [2, 5]);
+        assert_eq!(escaped_len(2, &removed), 2);
+        assert_eq!(escaped_len(3, &removed), 4);
+        assert_eq!(escaped_len(6, &removed), 8);
+    }
+
+    #[test]
+    fn avc_slice_header() {
+        let mut sets = AvcParameterSets::default();
+
+        // Baseline SPS: log2_max_frame_num 4, POC type 0 with 4 bit LSB, 1920x1088
+        let mut sps = vec![0x67, 66, 0, 40];
+        sps.extend(bits("1 1 1 1 010 0 0000001111000 0000001000100 1 1 0 0 1"));
+        sets.update(&sps).unwrap();
+
+        // PPS: CAVLC, one slice group, deblocking filter control present
+        let mut pps = vec![0x68];
+        pps.extend(bits("1 1 0 0 1 1 1 0 00 1 1 1 1 0 0 1"));
+        sets.update(&pps).unwrap();
+
+        // IDR I slice: first_mb_in_slice 0, slice_type 7, pps_id 0, frame_num 0, idr_pic_id 0,
+        // pic_order_cnt_lsb 0, dec_ref_pic_marking, slice_qp_delta -1, deblocking 0/0/0
+        let mut slice = vec![0x65];
+        slice.extend(bits("1 0001000 1 0000 1 0000 0 0 011 1 1 1"));
+        slice.extend([0xab; 16]);
+
+        // 26 bits of slice header after the NAL unit header
+        assert_eq!(sets.slice_header_len(&slice).unwrap(), 1 + 4);
+
+        // Emulation prevention bytes in the slice header are counted too: idr_pic_id 65535
+        // and slice_qp_delta -2 give 60 bits with an emulation prevention byte after 6 bytes
+        let slice = [
+            0x65, 0x88, 0x80, 0x00, 0x04, 0x00, 0x00, 0x03, 0x02, 0xf0, 0xab, 0xab,
+        ];
+        assert_eq!(sets.slice_header_len(&slice).unwrap(), 1 + 9);
+    }
+
+    #[test]
+    fn avc_codec_data() {
+        let mut sps = vec![0x67, 66, 0, 40];
+        sps.extend(bits("1 1 1 1 010 0 0000001111000 0000001000100 1 1 0 0 1"));
+        let mut pps = vec![0x68];
+        pps.extend(bits("1 1 0 0 1 1 1 0 00 1 1 1 1 0 0 1"));
+
+        let mut codec_data = vec![1, 66, 0, 40, 0xff, 0xe1];
+        codec_data.extend((sps.len() as u16).to_be_bytes());
+        codec_data.extend(&sps);
+        codec_data.push(1);
+        codec_data.extend((pps.len() as u16).to_be_bytes());
+        codec_data.extend(&pps);
+
+        let sets = AvcParameterSets::from_codec_data(&codec_data).unwrap();
+        let mut slice = vec![0x65];
+        slice.extend(bits("1 0001000 1 0000 1 0000 0 0 011 1 1 1"));
+        assert_eq!(sets.slice_header_len(&slice).unwrap(), 5);
+
+        // Slices referencing unknown parameter sets are rejected
+        let mut slice = vec![0x65];
+        slice.extend(bits("1 0001000 010 0000 1 0000 0 0 011 1 1 1"));
+        assert!(sets.slice_header_len(&slice).is_err());
+    }
+
+    fn hevc_sps() -> Vec<u8> {
+        let mut sps = vec![0x42, 0x01];
+        sps.extend(bits(concat!(
+            // vps_id 0, max_sub_layers_minus1 0, temporal_id_nesting 1
+            "0000 000 1",
+            // profile_tier_level: 96 bits
+            "00000001 01100000 00000000 00000000 00000000 00000000",
+            "00000000 00000000 00000000 00000000 00000000 01011101",
+            // sps_id 0, chroma_format_idc 1
+            "1 010",
+            // 1920x1080
+            "0000000000 11110000001 0000000000 10000111001",
+            // conformance window, bit depths, log2_max_poc_lsb 8 (minus4 = 4)
+            "0 1 1 00101",
+            // sub_layer_ordering_info_present, 3x ue
+            "1 1 1 1",
+            // log2_min_cb 3 (0), diff 3, tb sizes 0, 3, depths 0, 0
+            "1 00100 1 00100 1 1",
+            // no scaling lists, amp, sao enabled, no pcm
+            "0 1 1 0",
+            // 2 short-term RPS
+            "011",
+            // RPS 0: 1 negative (-1, used), 0 positive
+            "010 1 1 1",
+            // RPS 1: inter predicted from RPS 0 with delta_rps -1,
+            // used_by_curr_pic (1, 1)
+            "1 1 1 1 1",
+            // no long-term, temporal MVP enabled
+            "0 1",
+        )));
+        sps
+    }
+
+    fn hevc_pps() -> Vec<u8> {
+        let mut pps = vec![0x44, 0x01];
+        pps.extend(bits(concat!(
+            // pps_id 0, sps_id 0, dependent slices, output flag, 0 extra bits
+            "1 1 0 0 000",
+            // sign hiding, cabac_init_present, num_ref_idx defaults 0/0
+            "0 1 1 1",
+            // init_qp 0, constrained intra, transform skip, cu_qp_delta
+            "1 0 0 0",
+            // cb/cr offsets, slice chroma offsets, weighted pred/bipred
+            "1 1 0 0 0",
+            // transquant bypass, tiles, entropy sync
+            "0 0 1",
+            // loop filter across slices, no deblocking control, no scaling lists
+            "1 0 0",
+            // lists modification, parallel merge level, no header extension, no extensions
+            "0 1 0 0",
+        )));
+        pps
+    }
+
+    #[test]
+    fn hevc_slice_header() {
+        let mut sets = HevcParameterSets::default();
+        sets.update(&hevc_sps()).unwrap();
+        sets.update(&hevc_pps()).unwrap();
+
+        let sps = &sets.sps[&0];
+        assert_eq!(sps.pic_size_in_ctbs, 30 * 17);
+        assert_eq!(sps.st_ref_pic_sets.len(), 2);
+        assert_eq!(sps.st_ref_pic_sets[0].negative, [(-1, true)]);
+        assert_eq!(sps.st_ref_pic_sets[1].negative, [(-1, true), (-2, true)]);
+        assert!(sps.st_ref_pic_sets[1].positive.is_empty());
+
+        // IDR_W_RADL, first slice: no_output_of_prior_pics 0, pps_id 0, I slice, SAO luma and
+        // chroma, slice_qp_delta 0, loop filter across slices, 1 entry point with 8 bit offset,
+        // byte alignment
+        let mut slice = vec![0x26, 0x01];
+        slice.extend(bits("1 0 1 011 1 1 1 1 010 0001000 00010000 1"));
+        slice.extend([0xab; 16]);
+        // 29 bits including the alignment bit
+        assert_eq!(sets.slice_header_len(&slice).unwrap(), 2 + 4);
+
+        // TRAIL_R P slice using short-term RPS 1 of the SPS, second slice segment
+        let mut slice = vec![0x02, 0x01];
+        slice.extend(bits(concat!(
+            // first_slice_segment_in_pic 0, pps_id 0, slice_segment_address (9 bits)
+            "0 1 000000001",
+            // P slice, poc lsb, short_term_ref_pic_set_sps_flag 1, idx 1
+            "010 00000010 1 1",
+            // slice_temporal_mvp_enabled, SAO luma/chroma off
+            "1 0 0",
+            // num_ref_idx_active_override 0, cabac_init_flag 0, merge cand 0
+            "0 0 1",
+            // slice_qp_delta 0, loop filter across slices
+            "1 1",
+            // no entry points, byte alignment
+            "1 1",
+        )));
+        slice.extend([0xab; 16]);
+        // 34 bits including the alignment bit
+        assert_eq!(sets.slice_header_len(&slice).unwrap(), 2 + 5);
+    }
+}
@@ -14,10 +14,14 @@
  */
 use gst::glib;
 
+mod dashmp4sink;
 mod fmp4mux;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
-    fmp4mux::register(plugin)
+    fmp4mux::register(plugin)?;
+    dashmp4sink::register(plugin)?;
+
+    Ok(())
 }
 
 gst::plugin_define!(
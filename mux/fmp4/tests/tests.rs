@@ -16,6 +16,7 @@ fn init() {
     INIT.call_once(|| {
         gst::init().unwrap();
         gstfmp4::plugin_register_static().unwrap();
+        gstmp4::plugin_register_static().unwrap();
     });
 }
 
@@ -2058,3 +2059,695 @@ fn test_roundtrip_vp9_flac() {
     let pipeline = pipeline.downcast().unwrap();
     to_completion(&pipeline);
 }
+
+#[test]
+fn test_roundtrip_demux_vp9_flac() {
+    init();
+
+    let pipeline = gst::parse::launch(
+        r#"
+        videotestsrc num-buffers=99 ! vp9enc ! vp9parse ! mux.
+        audiotestsrc num-buffers=149 ! flacenc ! flacparse ! mux.
+        isofmp4mux name=mux ! isomp4demux name=demux
+        demux.audio_0 ! queue ! flacdec ! fakesink
+        demux.video_0 ! queue ! vp9dec ! fakesink
+        "#,
+    )
+    .unwrap();
+    let pipeline = pipeline.downcast().unwrap();
+    to_completion(&pipeline);
+}
+
+/// Returns the content of the first box with the given fourcc path, skipping `skip` bytes of
+/// each container box's content before its children.
+fn find_box<'a>(mut data: &'a [u8], path: &[(&[u8; 4], usize)]) -> Option<&'a [u8]> {
+    let ((fourcc, skip), rest) = path.split_first()?;
+
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        assert!((8..=data.len()).contains(&size));
+
+        if &data[4..8] == *fourcc {
+            let content = &data[8..size];
+            if rest.is_empty() {
+                return Some(content);
+            }
+            return find_box(&content[*skip..], rest);
+        }
+
+        data = &data[size..];
+    }
+
+    None
+}
+
+fn test_encryption(scheme: &str) {
+    use aes::cipher::{generic_array::GenericArray, BlockDecryptMut, KeyIvInit, StreamCipher};
+
+    const KEY_ID: [u8; 16] = *b"0123456789abcdef";
+    const KEY: [u8; 16] = *b"fedcba9876543210";
+    const IV: [u8; 16] = *b"ivivivivivivivi!";
+
+    let mut h = gst_check::Harness::new("cmafmux");
+    let mux = h.element().unwrap();
+    mux.set_property("fragment-duration", 5.seconds());
+    mux.set_property_from_str("encryption-scheme", scheme);
+    mux.set_property("encryption-key-id", hex::encode(KEY_ID));
+    mux.set_property("encryption-key", hex::encode(KEY));
+    mux.set_property("encryption-iv", hex::encode(IV));
+
+    // pssh v0 box with the clear key system ID and no data
+    let mut pssh = vec![0, 0, 0, 32];
+    pssh.extend(b"pssh");
+    pssh.extend([0u8; 4]);
+    pssh.extend([
+        0x10, 0x77, 0xef, 0xec, 0xc0, 0xb2, 0x4d, 0x02, 0xac, 0xe3, 0x3c, 0x1e, 0x52, 0xe2, 0xfb,
+        0x4b,
+    ]);
+    pssh.extend(0u32.to_be_bytes());
+    mux.set_property(
+        "protection-system-headers",
+        gst::Array::new([gst::Buffer::from_slice(pssh.clone())]),
+    );
+
+    // Baseline profile SPS and PPS, the slice header can only be found with them
+    let sps = [0x67, 0x42, 0x00, 0x28, 0xf4, 0x03, 0xc0, 0x11, 0x32];
+    let pps = [0x68, 0xce, 0x3c, 0x80];
+
+    // avcC with 4 byte NAL length fields
+    let mut codec_data = vec![1u8, 0x42, 0x00, 0x28, 0xff, 0xe1];
+    codec_data.extend((sps.len() as u16).to_be_bytes());
+    codec_data.extend(sps);
+    codec_data.push(1);
+    codec_data.extend((pps.len() as u16).to_be_bytes());
+    codec_data.extend(pps);
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field("codec_data", gst::Buffer::from_slice(codec_data))
+        .build();
+    h.set_src_caps(caps);
+    h.play();
+
+    // Each sample is a SEI NAL unit followed by an IDR slice NAL unit. The slice header is 26
+    // bits long and ends in the 4th byte after the NAL unit header.
+    let mut samples = vec![];
+    for i in 0..3u8 {
+        let mut data = vec![];
+        data.extend(5u32.to_be_bytes());
+        data.extend([0x06, i, i, i, 0x80]);
+        data.extend(97u32.to_be_bytes());
+        data.extend([0x65, 0x88, 0x84, 0x07, 0xc0 | i]);
+        data.extend((0..92u8).map(|b| b.wrapping_mul(i + 1)));
+        samples.push(data.clone());
+
+        let mut buffer = gst::Buffer::from_mut_slice(data);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(u64::from(i).seconds());
+            buffer.set_dts(u64::from(i).seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    let header = header.map_readable().unwrap();
+    let tenc = find_box(
+        &header,
+        &[
+            (b"moov", 0),
+            (b"trak", 0),
+            (b"mdia", 0),
+            (b"minf", 0),
+            (b"stbl", 0),
+            // full box header and entry count
+            (b"stsd", 8),
+            // visual sample entry
+            (b"encv", 78),
+            (b"sinf", 0),
+            (b"schi", 0),
+            (b"tenc", 0),
+        ],
+    )
+    .unwrap();
+    assert_eq!(&tenc[8..24], &KEY_ID);
+    let frma = find_box(
+        &header,
+        &[
+            (b"moov", 0),
+            (b"trak", 0),
+            (b"mdia", 0),
+            (b"minf", 0),
+            (b"stbl", 0),
+            (b"stsd", 8),
+            (b"encv", 78),
+            (b"sinf", 0),
+            (b"frma", 0),
+        ],
+    )
+    .unwrap();
+    assert_eq!(frma, b"avc1");
+    let moov_pssh = find_box(&header, &[(b"moov", 0), (b"pssh", 0)]).unwrap();
+    assert_eq!(moov_pssh, &pssh[8..]);
+
+    let fragment_header = h.pull().unwrap();
+    let fragment_header = fragment_header.map_readable().unwrap();
+    let moof = find_box(&fragment_header, &[(b"moof", 0)]).unwrap();
+    let senc = find_box(
+        &fragment_header,
+        &[(b"moof", 0), (b"traf", 0), (b"senc", 0)],
+    )
+    .unwrap();
+    assert_eq!(u32::from_be_bytes(senc[..4].try_into().unwrap()) & 0x2, 0x2);
+    assert_eq!(u32::from_be_bytes(senc[4..8].try_into().unwrap()), 3);
+    let mut senc = &senc[8..];
+
+    // saio has a single offset relative to the start of the moof that points at the first senc
+    // entry
+    let saio = find_box(
+        &fragment_header,
+        &[(b"moof", 0), (b"traf", 0), (b"saio", 0)],
+    )
+    .unwrap();
+    assert_eq!(u32::from_be_bytes(saio[4..8].try_into().unwrap()), 1);
+    let moof_start = moof.as_ptr() as usize - 8;
+    let first_entry = senc.as_ptr() as usize;
+    assert_eq!(
+        u32::from_be_bytes(saio[8..12].try_into().unwrap()) as usize,
+        first_entry - moof_start
+    );
+
+    for sample in samples {
+        let buffer = h.pull().unwrap();
+        let mut data = buffer.map_readable().unwrap().to_vec();
+        assert_ne!(data, sample);
+
+        let iv_size = if scheme == "cenc" { 8 } else { 0 };
+        let iv = &senc[..iv_size];
+        let subsample_count = u16::from_be_bytes(senc[iv_size..][..2].try_into().unwrap());
+        let mut subsamples = vec![];
+        for i in 0..subsample_count as usize {
+            let entry = &senc[iv_size + 2 + 6 * i..][..6];
+            subsamples.push((
+                u16::from_be_bytes(entry[..2].try_into().unwrap()) as usize,
+                u32::from_be_bytes(entry[2..].try_into().unwrap()) as usize,
+            ));
+        }
+        senc = &senc[iv_size + 2 + 6 * subsample_count as usize..];
+
+        // The SEI, the NAL unit header and the slice header must be in the clear, followed by
+        // the unaligned part of the slice data
+        assert_eq!(subsamples, vec![(4 + 5 + 4 + 5 + 12, 80)]);
+        assert_eq!(data[..4 + 5 + 4 + 5], sample[..4 + 5 + 4 + 5]);
+
+        if scheme == "cenc" {
+            let mut counter = [0u8; 16];
+            counter[..8].copy_from_slice(iv);
+            let mut cipher = ctr::Ctr64BE::<aes::Aes128>::new(&KEY.into(), &counter.into());
+            let mut offset = 0;
+            for (clear, protected) in subsamples {
+                cipher.apply_keystream(&mut data[offset + clear..][..protected]);
+                offset += clear + protected;
+            }
+        } else {
+            let mut offset = 0;
+            for (clear, protected) in subsamples {
+                let mut cipher = cbc::Decryptor::<aes::Aes128>::new(&KEY.into(), &IV.into());
+                // 1:9 pattern
+                for (i, block) in data[offset + clear..][..protected]
+                    .chunks_exact_mut(16)
+                    .enumerate()
+                {
+                    if i % 10 == 0 {
+                        cipher.decrypt_block_mut(GenericArray::from_mut_slice(block));
+                    }
+                }
+                offset += clear + protected;
+            }
+        }
+
+        assert_eq!(data, sample);
+    }
+}
+
+#[test]
+fn test_encryption_cenc() {
+    init();
+
+    test_encryption("cenc");
+}
+
+#[test]
+fn test_encryption_cbcs() {
+    init();
+
+    test_encryption("cbcs");
+}
+
+#[test]
+fn test_encryption_key_rotation() {
+    use aes::cipher::{generic_array::GenericArray, BlockDecryptMut, KeyIvInit};
+    use std::sync::{Arc, Mutex};
+
+    init();
+
+    const KEY_ID: [u8; 16] = *b"0123456789abcdef";
+    const KEY: [u8; 16] = *b"fedcba9876543210";
+    const NEW_KEY_ID: [u8; 16] = *b"ghijklmnopqrstuv";
+    const NEW_KEY: [u8; 16] = *b"vutsrqponmlkjihg";
+    const IV: [u8; 16] = *b"ivivivivivivivi!";
+
+    let mut h = gst_check::Harness::new("cmafmux");
+    let mux = h.element().unwrap();
+    mux.set_property("fragment-duration", 1.seconds());
+    mux.set_property_from_str("encryption-scheme", "cbcs");
+    mux.set_property("encryption-key-id", hex::encode(KEY_ID));
+    mux.set_property("encryption-key", hex::encode(KEY));
+    mux.set_property("encryption-iv", hex::encode(IV));
+
+    // Switch to the new key from the second fragment on
+    let requested = Arc::new(Mutex::new(vec![]));
+    mux.connect("get-encryption-key", false, {
+        let requested = requested.clone();
+        move |args| {
+            let sequence_number = args[1].get::<u32>().unwrap();
+            requested.lock().unwrap().push(sequence_number);
+
+            if sequence_number != 2 {
+                return None;
+            }
+
+            Some(
+                gst::Structure::builder("application/x-cenc-key")
+                    .field("key-id", gst::glib::Bytes::from(&NEW_KEY_ID))
+                    .field("key", gst::glib::Bytes::from(&NEW_KEY))
+                    .build()
+                    .to_value(),
+            )
+        }
+    });
+
+    let sps = [0x67, 0x42, 0x00, 0x28, 0xf4, 0x03, 0xc0, 0x11, 0x32];
+    let pps = [0x68, 0xce, 0x3c, 0x80];
+
+    let mut codec_data = vec![1u8, 0x42, 0x00, 0x28, 0xff, 0xe1];
+    codec_data.extend((sps.len() as u16).to_be_bytes());
+    codec_data.extend(sps);
+    codec_data.push(1);
+    codec_data.extend((pps.len() as u16).to_be_bytes());
+    codec_data.extend(pps);
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field("codec_data", gst::Buffer::from_slice(codec_data))
+        .build();
+    h.set_src_caps(caps);
+    h.play();
+
+    // One IDR slice per fragment
+    let mut samples = vec![];
+    for i in 0..3u8 {
+        let mut data = vec![];
+        data.extend(97u32.to_be_bytes());
+        data.extend([0x65, 0x88, 0x84, 0x07, 0xc0 | i]);
+        data.extend((0..92u8).map(|b| b.wrapping_mul(i + 1)));
+        samples.push(data.clone());
+
+        let mut buffer = gst::Buffer::from_mut_slice(data);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(u64::from(i).seconds());
+            buffer.set_dts(u64::from(i).seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let tenc_key_id = |header: &gst::Buffer| {
+        assert!(header
+            .flags()
+            .contains(gst::BufferFlags::DISCONT | gst::BufferFlags::HEADER));
+        let header = header.map_readable().unwrap();
+        let tenc = find_box(
+            &header,
+            &[
+                (b"moov", 0),
+                (b"trak", 0),
+                (b"mdia", 0),
+                (b"minf", 0),
+                (b"stbl", 0),
+                (b"stsd", 8),
+                (b"encv", 78),
+                (b"sinf", 0),
+                (b"schi", 0),
+                (b"tenc", 0),
+            ],
+        )
+        .unwrap();
+        <[u8; 16]>::try_from(&tenc[8..24]).unwrap()
+    };
+
+    // The first fragment is encrypted with the configured key, the following ones with the new
+    // key after a new header with the new key ID
+    for (idx, sample) in samples.iter().enumerate() {
+        if idx < 2 {
+            let header = h.pull().unwrap();
+            let key_id = tenc_key_id(&header);
+            assert_eq!(key_id, if idx == 0 { KEY_ID } else { NEW_KEY_ID });
+        }
+        let key = if idx == 0 { KEY } else { NEW_KEY };
+
+        let fragment_header = h.pull().unwrap();
+        let fragment_header = fragment_header.map_readable().unwrap();
+        let senc = find_box(
+            &fragment_header,
+            &[(b"moof", 0), (b"traf", 0), (b"senc", 0)],
+        )
+        .unwrap();
+        assert_eq!(u32::from_be_bytes(senc[4..8].try_into().unwrap()), 1);
+        assert_eq!(u16::from_be_bytes(senc[8..10].try_into().unwrap()), 1);
+        let clear = u16::from_be_bytes(senc[10..12].try_into().unwrap()) as usize;
+        let protected = u32::from_be_bytes(senc[12..16].try_into().unwrap()) as usize;
+
+        let buffer = h.pull().unwrap();
+        let mut data = buffer.map_readable().unwrap().to_vec();
+        assert_ne!(&data, sample);
+
+        let mut cipher = cbc::Decryptor::<aes::Aes128>::new(&key.into(), &IV.into());
+        for (i, block) in data[clear..][..protected].chunks_exact_mut(16).enumerate() {
+            if i % 10 == 0 {
+                cipher.decrypt_block_mut(GenericArray::from_mut_slice(block));
+            }
+        }
+        assert_eq!(&data, sample);
+    }
+
+    assert_eq!(*requested.lock().unwrap(), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_webvtt() {
+    init();
+
+    let mut h = gst_check::Harness::new("cmafmux");
+    h.element()
+        .unwrap()
+        .set_property("fragment-duration", 5.seconds());
+    h.set_src_caps(gst::Caps::builder("application/x-subtitle-vtt-fragmented").build());
+    h.play();
+
+    // Two cues with a gap of one second between them
+    for (pts, text) in [(0, "Hello"), (2, "World")] {
+        let mut buffer = gst::Buffer::from_mut_slice(
+            format!(
+                "00:00:0{pts}.000 --> 00:00:0{}.000 line:0\n{text}\n",
+                pts + 1
+            )
+            .into_bytes(),
+        );
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    let header = header.map_readable().unwrap();
+    let hdlr = find_box(
+        &header,
+        &[(b"moov", 0), (b"trak", 0), (b"mdia", 0), (b"hdlr", 0)],
+    )
+    .unwrap();
+    assert_eq!(&hdlr[8..12], b"text");
+    let vttc_config = find_box(
+        &header,
+        &[
+            (b"moov", 0),
+            (b"trak", 0),
+            (b"mdia", 0),
+            (b"minf", 0),
+            (b"stbl", 0),
+            (b"stsd", 8),
+            // sample entry
+            (b"wvtt", 8),
+            (b"vttC", 0),
+        ],
+    )
+    .unwrap();
+    assert_eq!(vttc_config, b"WEBVTT");
+
+    let _fragment_header = h.pull().unwrap();
+
+    for text in [Some("Hello"), None, Some("World")] {
+        let buffer = h.pull().unwrap();
+        let data = buffer.map_readable().unwrap();
+        match text {
+            Some(text) => {
+                let settings = find_box(&data, &[(b"vttc", 0), (b"sttg", 0)]).unwrap();
+                assert_eq!(settings, b"line:0");
+                let payload = find_box(&data, &[(b"vttc", 0), (b"payl", 0)]).unwrap();
+                assert_eq!(payload, text.as_bytes());
+            }
+            None => {
+                assert_eq!(data.as_slice(), b"\0\0\0\x08vtte");
+            }
+        }
+        assert_eq!(buffer.duration(), Some(gst::ClockTime::SECOND));
+    }
+}
+
+#[test]
+fn test_emsg_prft() {
+    init();
+
+    let mut h = gst_check::Harness::new("cmafmux");
+    let mux = h.element().unwrap();
+    mux.set_property("fragment-duration", 2.seconds());
+    mux.set_property("write-prft", true);
+
+    h.set_src_caps(
+        gst::Caps::builder("audio/mpeg")
+            .field("mpegversion", 4i32)
+            .field("channels", 1i32)
+            .field("rate", 44100i32)
+            .field("stream-format", "raw")
+            .field("base-profile", "lc")
+            .field("profile", "lc")
+            .field("level", "2")
+            .field(
+                "codec_data",
+                gst::Buffer::from_slice([0x12, 0x08, 0x56, 0xe5, 0x00]),
+            )
+            .build(),
+    );
+    h.play();
+
+    const NTP_START: u64 = 3_900_000_000;
+    let ntp_caps = gst::Caps::builder("timestamp/x-ntp").build();
+
+    for i in 0..4 {
+        if i == 2 {
+            // Event in the middle of the second fragment
+            assert!(h.push_event(gst::event::CustomDownstream::new(
+                gst::Structure::builder("fmp4mux-emsg")
+                    .field("scheme-id-uri", "urn:scte:scte35:2013:bin")
+                    .field("timestamp", gst::ClockTime::from_mseconds(2500))
+                    .field("id", 42u32)
+                    .field("data", gst::Buffer::from_slice([1u8, 2, 3]))
+                    .build()
+            )));
+        }
+
+        let mut buffer = gst::Buffer::with_size(1).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(i.seconds());
+            buffer.set_dts(i.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            gst::ReferenceTimestampMeta::add(
+                buffer,
+                &ntp_caps,
+                (NTP_START + i).seconds(),
+                gst::ClockTime::NONE,
+            );
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    // Event after the end of the stream ends up in the final fragment
+    assert!(h.push_event(gst::event::CustomDownstream::new(
+        gst::Structure::builder("fmp4mux-emsg")
+            .field("scheme-id-uri", "urn:scte:scte35:2013:bin")
+            .field("timestamp", gst::ClockTime::from_seconds(10))
+            .field("id", 43u32)
+            .build()
+    )));
+
+    h.push_event(gst::event::Eos::new());
+
+    let _header = h.pull().unwrap();
+
+    for fragment in 0..2u64 {
+        let fragment_header = h.pull().unwrap();
+        let fragment_header = fragment_header.map_readable().unwrap();
+
+        let prft = find_box(&fragment_header, &[(b"prft", 0)]).unwrap();
+        // Version 1 and capture time flags
+        assert_eq!(prft[..4], [1, 0, 0, 24]);
+        assert_eq!(prft[4..8], 1u32.to_be_bytes());
+        assert_eq!(
+            prft[8..16],
+            ((NTP_START + 2 * fragment) << 32).to_be_bytes()
+        );
+        assert_eq!(prft[16..24], (2 * fragment * 44100).to_be_bytes());
+
+        let emsg = find_box(&fragment_header, &[(b"emsg", 0)]);
+        if fragment == 0 {
+            assert!(emsg.is_none());
+        } else {
+            let emsg = emsg.unwrap();
+            assert_eq!(emsg[..4], [1, 0, 0, 0]);
+            assert_eq!(emsg[4..8], 44100u32.to_be_bytes());
+            assert_eq!(emsg[8..16], 110_250u64.to_be_bytes());
+            assert_eq!(emsg[16..20], u32::MAX.to_be_bytes());
+            assert_eq!(emsg[20..24], 42u32.to_be_bytes());
+            assert_eq!(&emsg[24..], b"urn:scte:scte35:2013:bin\0\0\x01\x02\x03");
+
+            let next = emsg.as_ptr() as usize - fragment_header.as_ptr() as usize + emsg.len();
+            let emsg = find_box(&fragment_header[next..], &[(b"emsg", 0)]).unwrap();
+            assert_eq!(emsg[8..16], 441_000u64.to_be_bytes());
+            assert_eq!(emsg[20..24], 43u32.to_be_bytes());
+            assert_eq!(&emsg[24..], b"urn:scte:scte35:2013:bin\0\0");
+        }
+
+        for _ in 0..2 {
+            let _buffer = h.pull().unwrap();
+        }
+    }
+}
+
+/// Output stream writing into a shared buffer.
+struct MemoryFile(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_dashmp4sink_static_mpd() {
+    use std::collections::BTreeMap;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    init();
+
+    let mut h = gst_check::Harness::with_padnames("dashmp4sink", Some("video_%u"), None);
+    let sink = h.element().unwrap();
+    sink.set_property("target-duration", 5u32);
+    sink.set_property("sync", false);
+
+    let files = Arc::new(Mutex::new(BTreeMap::<String, Arc<Mutex<Vec<u8>>>>::new()));
+    let (mpd_sender, mpd_receiver) = mpsc::channel();
+
+    for signal in ["get-mpd-stream", "get-init-stream", "get-fragment-stream"] {
+        let files = files.clone();
+        let mpd_sender = Mutex::new(mpd_sender.clone());
+        sink.connect(signal, false, move |args| {
+            let location = args[1].get::<String>().unwrap();
+            let data = Arc::new(Mutex::new(Vec::new()));
+            files.lock().unwrap().insert(location.clone(), data.clone());
+            if signal == "get-mpd-stream" {
+                let _ = mpd_sender.lock().unwrap().send(data.clone());
+            }
+
+            let stream = gio::WriteOutputStream::new(MemoryFile(data));
+            Some(stream.upcast::<gio::OutputStream>().to_value())
+        });
+    }
+
+    let caps = gst::Caps::builder("video/x-h264")
+        .field("width", 1920i32)
+        .field("height", 1080i32)
+        .field("framerate", gst::Fraction::new(30, 1))
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .field("codec_data", gst::Buffer::with_size(1).unwrap())
+        .build();
+    h.set_src_caps(caps);
+    h.play();
+
+    // Push 7 buffers of 1s each, 1st and 6 buffer without DELTA_UNIT flag
+    for i in 0..7 {
+        let mut buffer = gst::Buffer::with_size(1).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(i.seconds());
+            buffer.set_dts(i.seconds());
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 && i != 5 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+    h.push_event(gst::event::Eos::new());
+
+    // A static MPD is only written once at EOS
+    let mpd = mpd_receiver
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .expect("no MPD written");
+    let mpd = loop {
+        let mpd = String::from_utf8(mpd.lock().unwrap().clone()).unwrap();
+        if mpd.ends_with("</MPD>\n") {
+            break mpd;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+
+    assert_eq!(
+        files.lock().unwrap().keys().collect::<Vec<_>>(),
+        vec![
+            "init_video_0.mp4",
+            "manifest.mpd",
+            "segment_video_0_00001.m4s",
+            "segment_video_0_00002.m4s",
+        ]
+    );
+
+    assert!(mpd.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<MPD"));
+    assert!(mpd.contains("type=\"static\""));
+    assert!(mpd.contains("contentType=\"video\""));
+    assert!(mpd.contains("id=\"video_0\""));
+    assert!(mpd.contains("width=\"1920\""));
+    assert!(mpd.contains("timescale=\"90000\""));
+    assert!(mpd.contains("startNumber=\"1\""));
+    assert!(mpd.contains("initialization=\"init_$RepresentationID$.mp4\""));
+    assert!(mpd.contains("media=\"segment_$RepresentationID$_$Number%05d$.m4s\""));
+    assert!(mpd.contains("t=\"0\""));
+    assert!(mpd.contains("d=\"450000\""));
+    assert!(mpd.contains("d=\"180000\""));
+    assert!(!mpd.contains("availabilityStartTime"));
+}
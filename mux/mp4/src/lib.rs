@@ -14,10 +14,14 @@
  */
 use gst::glib;
 
+mod mp4demux;
 mod mp4mux;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
-    mp4mux::register(plugin)
+    mp4mux::register(plugin)?;
+    mp4demux::register(plugin)?;
+
+    Ok(())
 }
 
 gst::plugin_define!(
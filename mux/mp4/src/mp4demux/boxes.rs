@@ -0,0 +1,1573 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::prelude::*;
+
+use anyhow::{anyhow, bail, Context, Error};
+use std::collections::HashMap;
+
+/// Header of a box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct BoxHeader {
+    pub(super) fourcc: [u8; 4],
+    /// Size of the box header itself
+    pub(super) header_size: u64,
+    /// Size of the whole box including the header
+    ///
+    /// This is `None` if the box extends until the end of the file.
+    pub(super) size: Option<u64>,
+}
+
+/// Parses a box header at the beginning of `data`.
+///
+/// Returns `Ok(None)` if more data is needed.
+pub(super) fn parse_box_header(data: &[u8]) -> Result<Option<BoxHeader>, Error> {
+    if data.len() < 8 {
+        return Ok(None);
+    }
+
+    let size = u32::from_be_bytes(data[0..4].try_into().unwrap());
+    let fourcc = data[4..8].try_into().unwrap();
+
+    let (header_size, size) = match size {
+        0 => (8, None),
+        1 => {
+            if data.len() < 16 {
+                return Ok(None);
+            }
+            let size = u64::from_be_bytes(data[8..16].try_into().unwrap());
+            if size < 16 {
+                bail!("invalid extended box size {size}");
+            }
+            (16, Some(size))
+        }
+        size if size < 8 => bail!("invalid box size {size}"),
+        size => (8, Some(size as u64)),
+    };
+
+    Ok(Some(BoxHeader {
+        fourcc,
+        header_size,
+        size,
+    }))
+}
+
+/// Big-endian reader over the content of a box.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.remaining() < len {
+            bail!("unexpected end of box");
+        }
+        let bytes = &self.data[self.pos..][..len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos..];
+        self.pos = self.data.len();
+        rest
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), Error> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn i16(&mut self) -> Result<i16, Error> {
+        Ok(i16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, Error> {
+        Ok(i64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn fourcc(&mut self) -> Result<[u8; 4], Error> {
+        Ok(self.bytes(4)?.try_into().unwrap())
+    }
+
+    /// Reads a 32 bit value for version 0 boxes and a 64 bit value otherwise.
+    fn u32_or_u64(&mut self, version: u8) -> Result<u64, Error> {
+        if version == 0 {
+            self.u32().map(u64::from)
+        } else {
+            self.u64()
+        }
+    }
+
+    /// Reads the version and flags of a full box.
+    fn full_box_header(&mut self) -> Result<(u8, u32), Error> {
+        let v = self.u32()?;
+        Ok(((v >> 24) as u8, v & 0x00_ff_ff_ff))
+    }
+
+    /// Reads a NUL-terminated UTF-8 string.
+    fn c_string(&mut self) -> Result<String, Error> {
+        let rest = &self.data[self.pos..];
+        let len = rest
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| anyhow!("unterminated string"))?;
+        let s = std::str::from_utf8(&rest[..len]).context("invalid string")?;
+        self.pos += len + 1;
+        Ok(s.to_string())
+    }
+}
+
+/// Iterates over the child boxes in `data`.
+fn children(data: &[u8]) -> impl Iterator<Item = Result<([u8; 4], &[u8]), Error>> {
+    let mut data = data;
+
+    std::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+
+        let res = match parse_box_header(data) {
+            Ok(Some(header)) => {
+                let size = header.size.unwrap_or(data.len() as u64);
+                if size > data.len() as u64 {
+                    Err(anyhow!(
+                        "{} box with size {size} exceeds parent",
+                        fourcc_to_string(header.fourcc)
+                    ))
+                } else {
+                    let (child, rest) = data.split_at(size as usize);
+                    data = rest;
+                    Ok((header.fourcc, &child[header.header_size as usize..]))
+                }
+            }
+            Ok(None) => Err(anyhow!("truncated box header")),
+            Err(err) => Err(err),
+        };
+
+        if res.is_err() {
+            data = &[];
+        }
+
+        Some(res)
+    })
+}
+
+/// Returns the content of the first child box with `fourcc` in `data`.
+fn find_child<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> Result<Option<&'a [u8]>, Error> {
+    for child in children(data) {
+        let (child_fourcc, content) = child?;
+        if &child_fourcc == fourcc {
+            return Ok(Some(content));
+        }
+    }
+
+    Ok(None)
+}
+
+pub(super) fn fourcc_to_string(fourcc: [u8; 4]) -> String {
+    fourcc
+        .iter()
+        .map(|b| {
+            if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// Major and compatible brands from an `ftyp` or `styp` box.
+#[derive(Debug, Clone)]
+pub(super) struct FileType {
+    pub(super) major_brand: [u8; 4],
+    pub(super) compatible_brands: Vec<[u8; 4]>,
+}
+
+/// Parses the content of a `ftyp` box.
+pub(super) fn parse_ftyp(data: &[u8]) -> Result<FileType, Error> {
+    let mut r = Reader::new(data);
+
+    let major_brand = r.fourcc()?;
+    let _minor_version = r.u32()?;
+    let mut compatible_brands = vec![];
+    while r.remaining() >= 4 {
+        compatible_brands.push(r.fourcc()?);
+    }
+
+    Ok(FileType {
+        major_brand,
+        compatible_brands,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Movie {
+    /// Movie timescale
+    pub(super) timescale: u32,
+    /// Movie duration in movie timescale, if known
+    pub(super) duration: Option<u64>,
+    /// Whether the movie is fragmented, i.e. has a `mvex` box
+    pub(super) fragmented: bool,
+    pub(super) tracks: Vec<Track>,
+}
+
+impl Movie {
+    pub(super) fn duration(&self) -> Option<gst::ClockTime> {
+        self.duration
+            .and_then(|d| d.mul_div_round(*gst::ClockTime::SECOND, self.timescale as u64))
+            .map(gst::ClockTime::from_nseconds)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct TrackExtends {
+    pub(super) default_sample_duration: u32,
+    pub(super) default_sample_size: u32,
+    pub(super) default_sample_flags: u32,
+}
+
+/// Mapping of the track media to the movie timeline, from the `elst` box.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct Edit {
+    /// Empty time at the beginning of the track in movie timescale
+    pub(super) empty_duration: u64,
+    /// Start of the media in media timescale
+    pub(super) media_time: u64,
+    /// Duration of the edit in movie timescale, or `None` if it lasts until the end of the media
+    pub(super) duration: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Sample {
+    /// Absolute offset of the sample data in the file
+    pub(super) offset: u64,
+    pub(super) size: u32,
+    /// Decoding time in media timescale
+    pub(super) dts: u64,
+    /// Duration in media timescale
+    pub(super) duration: u32,
+    /// Composition time offset in media timescale
+    pub(super) composition_time_offset: i64,
+    pub(super) sync_point: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Track {
+    pub(super) id: u32,
+    /// Handler type, e.g. `vide` or `soun`
+    pub(super) handler_type: [u8; 4],
+    /// Media timescale
+    pub(super) timescale: u32,
+    pub(super) language_code: Option<[u8; 3]>,
+    /// FourCC of the first sample entry
+    pub(super) sample_entry: [u8; 4],
+    /// Caps of the first sample entry, or `None` if it is not supported
+    pub(super) caps: Option<gst::Caps>,
+    pub(super) edit: Edit,
+    /// Defaults for track fragments
+    pub(super) trex: TrackExtends,
+    /// Samples from the sample table
+    ///
+    /// This is empty for fragmented files.
+    pub(super) samples: Vec<Sample>,
+}
+
+impl Track {
+    pub(super) fn to_clock_time(&self, ts: u64) -> gst::ClockTime {
+        gst::ClockTime::from_nseconds(
+            ts.mul_div_round(*gst::ClockTime::SECOND, self.timescale as u64)
+                .unwrap_or(u64::MAX),
+        )
+    }
+}
+
+/// Parses the content of a `moov` box.
+pub(super) fn parse_moov(data: &[u8]) -> Result<Movie, Error> {
+    let mvhd = find_child(data, b"mvhd")?.ok_or_else(|| anyhow!("no mvhd box"))?;
+    let (timescale, duration) = parse_mvhd(mvhd).context("mvhd")?;
+
+    let mut fragmented = false;
+    let mut fragment_duration = None;
+    let mut trexs = HashMap::new();
+    if let Some(mvex) = find_child(data, b"mvex")? {
+        fragmented = true;
+        for child in children(mvex) {
+            match child? {
+                (fourcc, content) if &fourcc == b"mehd" => {
+                    let mut r = Reader::new(content);
+                    let (version, _flags) = r.full_box_header()?;
+                    fragment_duration = Some(r.u32_or_u64(version)?).filter(|d| *d != 0);
+                }
+                (fourcc, content) if &fourcc == b"trex" => {
+                    let mut r = Reader::new(content);
+                    let _ = r.full_box_header()?;
+                    let track_id = r.u32()?;
+                    let _default_sample_description_index = r.u32()?;
+                    trexs.insert(
+                        track_id,
+                        TrackExtends {
+                            default_sample_duration: r.u32()?,
+                            default_sample_size: r.u32()?,
+                            default_sample_flags: r.u32()?,
+                        },
+                    );
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let mut tracks = vec![];
+    for child in children(data) {
+        let (fourcc, content) = child?;
+        if &fourcc != b"trak" {
+            continue;
+        }
+
+        let mut track = parse_trak(content).context("trak")?;
+        if let Some(trex) = trexs.get(&track.id) {
+            track.trex = *trex;
+        }
+        tracks.push(track);
+    }
+
+    Ok(Movie {
+        timescale,
+        duration: duration.or(fragment_duration),
+        fragmented,
+        tracks,
+    })
+}
+
+fn parse_mvhd(data: &[u8]) -> Result<(u32, Option<u64>), Error> {
+    let mut r = Reader::new(data);
+    let (version, _flags) = r.full_box_header()?;
+
+    // Creation and modification time
+    r.u32_or_u64(version)?;
+    r.u32_or_u64(version)?;
+
+    let timescale = r.u32()?;
+    if timescale == 0 {
+        bail!("invalid movie timescale 0");
+    }
+    let duration = r.u32_or_u64(version)?;
+    let duration =
+        if duration == 0 || (version == 0 && duration == u32::MAX as u64) || duration == u64::MAX {
+            None
+        } else {
+            Some(duration)
+        };
+
+    Ok((timescale, duration))
+}
+
+fn parse_trak(data: &[u8]) -> Result<Track, Error> {
+    let tkhd = find_child(data, b"tkhd")?.ok_or_else(|| anyhow!("no tkhd box"))?;
+    let mut r = Reader::new(tkhd);
+    let (version, _flags) = r.full_box_header()?;
+    // Creation and modification time
+    r.u32_or_u64(version)?;
+    r.u32_or_u64(version)?;
+    let id = r.u32()?;
+
+    let mdia = find_child(data, b"mdia")?.ok_or_else(|| anyhow!("no mdia box"))?;
+
+    let mdhd = find_child(mdia, b"mdhd")?.ok_or_else(|| anyhow!("no mdhd box"))?;
+    let mut r = Reader::new(mdhd);
+    let (version, _flags) = r.full_box_header()?;
+    // Creation and modification time
+    r.u32_or_u64(version)?;
+    r.u32_or_u64(version)?;
+    let timescale = r.u32()?;
+    if timescale == 0 {
+        bail!("invalid media timescale 0");
+    }
+    let _duration = r.u32_or_u64(version)?;
+    let language = r.u16()?;
+    let language_code = if language == 0 || language == 0x7fff {
+        None
+    } else {
+        Some([
+            (((language >> 10) & 0x1f) as u8) + 0x60,
+            (((language >> 5) & 0x1f) as u8) + 0x60,
+            ((language & 0x1f) as u8) + 0x60,
+        ])
+        .filter(|code| code != b"und")
+    };
+
+    let hdlr = find_child(mdia, b"hdlr")?.ok_or_else(|| anyhow!("no hdlr box"))?;
+    let mut r = Reader::new(hdlr);
+    r.full_box_header()?;
+    let _pre_defined = r.u32()?;
+    let handler_type = r.fourcc()?;
+
+    let stbl = find_child(mdia, b"minf")?
+        .map(|minf| find_child(minf, b"stbl"))
+        .transpose()?
+        .flatten()
+        .ok_or_else(|| anyhow!("no stbl box"))?;
+
+    let stsd = find_child(stbl, b"stsd")?.ok_or_else(|| anyhow!("no stsd box"))?;
+    let (sample_entry, caps) = parse_stsd(stsd).context("stsd")?;
+
+    let samples = parse_sample_table(stbl).context("stbl")?;
+
+    let edit = if let Some(elst) = find_child(data, b"edts")?
+        .map(|edts| find_child(edts, b"elst"))
+        .transpose()?
+        .flatten()
+    {
+        parse_elst(elst).context("elst")?
+    } else {
+        Edit::default()
+    };
+
+    let mut caps = caps;
+    if let Some(caps) = caps.as_mut() {
+        // Derive the framerate from the most common sample duration
+        if &handler_type == b"vide" && !samples.is_empty() {
+            let mut durations = HashMap::<u32, usize>::new();
+            for sample in &samples {
+                *durations.entry(sample.duration).or_default() += 1;
+            }
+            let (duration, _) = durations
+                .into_iter()
+                .max_by_key(|(duration, count)| (*count, *duration))
+                .unwrap();
+            let s = caps.make_mut().structure_mut(0).unwrap();
+            if duration > 0 && timescale <= i32::MAX as u32 && duration <= i32::MAX as u32 {
+                s.set(
+                    "framerate",
+                    gst::Fraction::new(timescale as i32, duration as i32),
+                );
+            } else {
+                s.set("framerate", gst::Fraction::new(0, 1));
+            }
+        }
+    }
+
+    Ok(Track {
+        id,
+        handler_type,
+        timescale,
+        language_code,
+        sample_entry,
+        caps,
+        edit,
+        trex: TrackExtends::default(),
+        samples,
+    })
+}
+
+fn parse_elst(data: &[u8]) -> Result<Edit, Error> {
+    let mut r = Reader::new(data);
+    let (version, _flags) = r.full_box_header()?;
+    let entry_count = r.u32()?;
+
+    let mut edit = Edit::default();
+    for _ in 0..entry_count {
+        let segment_duration = r.u32_or_u64(version)?;
+        let media_time = if version == 0 {
+            r.i32()? as i64
+        } else {
+            r.i64()?
+        };
+        let _media_rate = r.u32()?;
+
+        if media_time == -1 {
+            edit.empty_duration += segment_duration;
+            continue;
+        }
+
+        edit.media_time = media_time.max(0) as u64;
+        edit.duration = Some(segment_duration).filter(|d| *d != 0);
+
+        // Only the first non-empty edit is supported
+        break;
+    }
+
+    Ok(edit)
+}
+
+fn parse_sample_table(stbl: &[u8]) -> Result<Vec<Sample>, Error> {
+    let Some(stsz) = find_child(stbl, b"stsz")? else {
+        return Ok(vec![]);
+    };
+
+    let mut r = Reader::new(stsz);
+    r.full_box_header()?;
+    let sample_size = r.u32()?;
+    let sample_count = r.u32()? as usize;
+    if sample_count == 0 {
+        return Ok(vec![]);
+    }
+    if sample_size == 0 && r.remaining() < sample_count * 4 {
+        bail!("too small stsz box");
+    }
+
+    let mut samples = Vec::with_capacity(sample_count);
+    for _ in 0..sample_count {
+        samples.push(Sample {
+            offset: 0,
+            size: if sample_size == 0 {
+                r.u32()?
+            } else {
+                sample_size
+            },
+            dts: 0,
+            duration: 0,
+            composition_time_offset: 0,
+            sync_point: true,
+        });
+    }
+
+    // Decoding times
+    let stts = find_child(stbl, b"stts")?.ok_or_else(|| anyhow!("no stts box"))?;
+    let mut r = Reader::new(stts);
+    r.full_box_header()?;
+    let entry_count = r.u32()?;
+    let mut dts = 0u64;
+    let mut idx = 0;
+    for _ in 0..entry_count {
+        let count = r.u32()? as usize;
+        let delta = r.u32()?;
+        for sample in samples.iter_mut().skip(idx).take(count) {
+            sample.dts = dts;
+            sample.duration = delta;
+            dts += delta as u64;
+        }
+        idx += count;
+    }
+    if idx < sample_count {
+        bail!("stts covers only {idx} of {sample_count} samples");
+    }
+
+    // Composition time offsets
+    if let Some(ctts) = find_child(stbl, b"ctts")? {
+        let mut r = Reader::new(ctts);
+        r.full_box_header()?;
+        let entry_count = r.u32()?;
+        let mut idx = 0;
+        for _ in 0..entry_count {
+            let count = r.u32()? as usize;
+            // Version 0 offsets are unsigned but negative offsets are commonly written there too
+            let offset = r.i32()? as i64;
+            for sample in samples.iter_mut().skip(idx).take(count) {
+                sample.composition_time_offset = offset;
+            }
+            idx += count;
+        }
+    }
+
+    // Sync samples
+    if let Some(stss) = find_child(stbl, b"stss")? {
+        let mut r = Reader::new(stss);
+        r.full_box_header()?;
+        let entry_count = r.u32()?;
+        for sample in samples.iter_mut() {
+            sample.sync_point = false;
+        }
+        for _ in 0..entry_count {
+            let number = r.u32()? as usize;
+            if let Some(sample) = number.checked_sub(1).and_then(|idx| samples.get_mut(idx)) {
+                sample.sync_point = true;
+            }
+        }
+    }
+
+    // Chunk offsets
+    let chunk_offsets = if let Some(stco) = find_child(stbl, b"stco")? {
+        let mut r = Reader::new(stco);
+        r.full_box_header()?;
+        let entry_count = r.u32()?;
+        (0..entry_count)
+            .map(|_| r.u32().map(u64::from))
+            .collect::<Result<Vec<_>, _>>()?
+    } else if let Some(co64) = find_child(stbl, b"co64")? {
+        let mut r = Reader::new(co64);
+        r.full_box_header()?;
+        let entry_count = r.u32()?;
+        (0..entry_count)
+            .map(|_| r.u64())
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        bail!("no stco or co64 box");
+    };
+
+    // Sample to chunk mapping
+    let stsc = find_child(stbl, b"stsc")?.ok_or_else(|| anyhow!("no stsc box"))?;
+    let mut r = Reader::new(stsc);
+    r.full_box_header()?;
+    let entry_count = r.u32()?;
+    let mut entries = Vec::with_capacity(entry_count as usize);
+    for _ in 0..entry_count {
+        let first_chunk = r.u32()?;
+        let samples_per_chunk = r.u32()?;
+        let _sample_description_index = r.u32()?;
+        if first_chunk == 0 {
+            bail!("invalid first chunk 0");
+        }
+        entries.push((first_chunk as usize - 1, samples_per_chunk as usize));
+    }
+
+    let mut samples_iter = samples.iter_mut();
+    for (idx, (first_chunk, samples_per_chunk)) in entries.iter().enumerate() {
+        let last_chunk = entries
+            .get(idx + 1)
+            .map(|(first_chunk, _)| *first_chunk)
+            .unwrap_or(chunk_offsets.len());
+
+        for chunk_offset in chunk_offsets.iter().take(last_chunk).skip(*first_chunk) {
+            let mut offset = *chunk_offset;
+            for _ in 0..*samples_per_chunk {
+                let Some(sample) = samples_iter.next() else {
+                    return Ok(samples);
+                };
+                sample.offset = offset;
+                offset += sample.size as u64;
+            }
+        }
+    }
+
+    if samples_iter.next().is_some() {
+        bail!("stsc / chunk offsets don't cover all samples");
+    }
+
+    Ok(samples)
+}
+
+/// Parses the content of a `stsd` box and returns the FourCC of the first sample entry and the
+/// corresponding caps, if supported.
+fn parse_stsd(data: &[u8]) -> Result<([u8; 4], Option<gst::Caps>), Error> {
+    let mut r = Reader::new(data);
+    r.full_box_header()?;
+    let entry_count = r.u32()?;
+    if entry_count == 0 {
+        bail!("no sample entries");
+    }
+
+    let (fourcc, content) = children(r.rest())
+        .next()
+        .ok_or_else(|| anyhow!("no sample entries"))??;
+
+    let mut r = Reader::new(content);
+    // Reserved
+    r.skip(6)?;
+    // Data reference index
+    r.u16()?;
+
+    let caps = match &fourcc {
+        b"avc1" | b"avc3" | b"hvc1" | b"hev1" | b"vp08" | b"vp09" | b"av01" | b"jpeg" => {
+            Some(parse_visual_sample_entry(fourcc, &mut r)?)
+        }
+        b"mp4a" | b"Opus" | b"fLaC" | b"alaw" | b"ulaw" | b"ms\x00\x45" => {
+            parse_audio_sample_entry(fourcc, &mut r)?
+        }
+        b"metx" => parse_xml_meta_data_sample_entry(&mut r)?,
+        _ => None,
+    };
+
+    Ok((fourcc, caps))
+}
+
+fn codec_data_buffer(data: &[u8]) -> gst::Buffer {
+    gst::Buffer::from_mut_slice(data.to_vec())
+}
+
+fn parse_visual_sample_entry(fourcc: [u8; 4], r: &mut Reader) -> Result<gst::Caps, Error> {
+    // Pre-defined, reserved, pre-defined
+    r.skip(2 + 2 + 3 * 4)?;
+    let width = r.u16()?;
+    let height = r.u16()?;
+    // Horizontal / vertical resolution, reserved, frame count, compressor name, depth,
+    // pre-defined
+    r.skip(4 + 4 + 4 + 2 + 32 + 2 + 2)?;
+
+    let mut s = match &fourcc {
+        b"avc1" | b"avc3" => gst::Structure::builder("video/x-h264")
+            .field(
+                "stream-format",
+                if &fourcc == b"avc1" { "avc" } else { "avc3" },
+            )
+            .field("alignment", "au")
+            .build(),
+        b"hvc1" | b"hev1" => gst::Structure::builder("video/x-h265")
+            .field(
+                "stream-format",
+                if &fourcc == b"hvc1" { "hvc1" } else { "hev1" },
+            )
+            .field("alignment", "au")
+            .build(),
+        b"vp08" => gst::Structure::new_empty("video/x-vp8"),
+        b"vp09" => gst::Structure::new_empty("video/x-vp9"),
+        b"av01" => gst::Structure::builder("video/x-av1")
+            .field("stream-format", "obu-stream")
+            .field("alignment", "tu")
+            .build(),
+        b"jpeg" => gst::Structure::builder("image/jpeg")
+            .field("parsed", true)
+            .build(),
+        _ => unreachable!(),
+    };
+
+    s.set("width", width as i32);
+    s.set("height", height as i32);
+
+    for child in children(r.rest()) {
+        let (child_fourcc, content) = child?;
+        match &child_fourcc {
+            b"avcC" | b"hvcC" | b"av1C" => {
+                s.set("codec_data", codec_data_buffer(content));
+            }
+            b"vpcC" => {
+                let mut r = Reader::new(content);
+                r.full_box_header()?;
+                let profile = r.u8()?;
+                let _level = r.u8()?;
+                let b = r.u8()?;
+                let bit_depth = (b >> 4) as u32;
+                let chroma_subsampling = (b >> 1) & 0x7;
+                let full_range = b & 0x1 != 0;
+                let primaries = r.u8()?;
+                let transfer = r.u8()?;
+                let matrix = r.u8()?;
+
+                s.set("profile", profile.to_string());
+                s.set("bit-depth-luma", bit_depth);
+                s.set("bit-depth-chroma", bit_depth);
+                s.set(
+                    "chroma-format",
+                    match chroma_subsampling {
+                        0 | 1 => "4:2:0",
+                        2 => "4:2:2",
+                        _ => "4:4:4",
+                    },
+                );
+                if chroma_subsampling == 0 {
+                    s.set("chroma-site", "v-cosited");
+                }
+                let colorimetry = gst_video::VideoColorimetry::new(
+                    if full_range {
+                        gst_video::VideoColorRange::Range0_255
+                    } else {
+                        gst_video::VideoColorRange::Range16_235
+                    },
+                    gst_video::VideoColorMatrix::from_iso(matrix as u32),
+                    gst_video::VideoTransferFunction::from_iso(transfer as u32),
+                    gst_video::VideoColorPrimaries::from_iso(primaries as u32),
+                );
+                s.set("colorimetry", colorimetry.to_string());
+            }
+            b"pasp" => {
+                let mut r = Reader::new(content);
+                let numer = r.u32()?;
+                let denom = r.u32()?;
+                if numer > 0 && denom > 0 && numer <= i32::MAX as u32 && denom <= i32::MAX as u32 {
+                    s.set(
+                        "pixel-aspect-ratio",
+                        gst::Fraction::new(numer as i32, denom as i32),
+                    );
+                }
+            }
+            b"colr" if !s.has_field("colorimetry") => {
+                let mut r = Reader::new(content);
+                if &r.fourcc()? != b"nclx" {
+                    continue;
+                }
+                let primaries = r.u16()?;
+                let transfer = r.u16()?;
+                let matrix = r.u16()?;
+                let full_range = r.u8()? & 0x80 != 0;
+                let colorimetry = gst_video::VideoColorimetry::new(
+                    if full_range {
+                        gst_video::VideoColorRange::Range0_255
+                    } else {
+                        gst_video::VideoColorRange::Range16_235
+                    },
+                    gst_video::VideoColorMatrix::from_iso(matrix as u32),
+                    gst_video::VideoTransferFunction::from_iso(transfer as u32),
+                    gst_video::VideoColorPrimaries::from_iso(primaries as u32),
+                );
+                s.set("colorimetry", colorimetry.to_string());
+            }
+            b"fiel" => {
+                let mut r = Reader::new(content);
+                let fields = r.u8()?;
+                let order = r.u8()?;
+                if fields == 2 {
+                    s.set("interlace-mode", "interleaved");
+                    s.set(
+                        "field-order",
+                        if order == 9 || order == 14 {
+                            "top-field-first"
+                        } else {
+                            "bottom-field-first"
+                        },
+                    );
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if (s.name() == "video/x-h264" || s.name() == "video/x-h265") && !s.has_field("codec_data") {
+        bail!("no codec configuration for {}", fourcc_to_string(fourcc));
+    }
+
+    Ok(gst::Caps::builder_full().structure(s).build())
+}
+
+fn parse_audio_sample_entry(fourcc: [u8; 4], r: &mut Reader) -> Result<Option<gst::Caps>, Error> {
+    let version = r.u16()?;
+    // Revision, vendor
+    r.skip(2 + 4)?;
+    let channels = r.u16()?;
+    let sample_size = r.u16()?;
+    // Pre-defined, reserved
+    r.skip(2 + 2)?;
+    let mut rate = r.u32()? >> 16;
+
+    match version {
+        0 => (),
+        // QuickTime sound description version 1
+        1 => r.skip(4 * 4)?,
+        _ => bail!("unsupported audio sample entry version {version}"),
+    }
+
+    let children = children(r.rest()).collect::<Result<Vec<_>, _>>()?;
+    if let Some((_, content)) = children.iter().find(|(fourcc, _)| fourcc == b"srat") {
+        let mut r = Reader::new(content);
+        r.full_box_header()?;
+        rate = r.u32()?;
+    }
+    let child = |child_fourcc: &[u8; 4]| {
+        children
+            .iter()
+            .find(|(fourcc, _)| fourcc == child_fourcc)
+            .map(|(_, content)| *content)
+    };
+
+    let mut caps = match &fourcc {
+        b"mp4a" => {
+            let esds = child(b"esds").ok_or_else(|| anyhow!("no esds box"))?;
+            let Some((object_type, codec_data)) = parse_esds(esds).context("esds")? else {
+                return Ok(None);
+            };
+
+            match object_type {
+                // AAC
+                0x40 | 0x66 | 0x67 | 0x68 => {
+                    let codec_data = codec_data.ok_or_else(|| anyhow!("no AAC codec_data"))?;
+                    let mut caps = gst::Caps::builder("audio/mpeg")
+                        .field("mpegversion", 4i32)
+                        .field("framed", true)
+                        .field("stream-format", "raw")
+                        .field("codec_data", codec_data_buffer(codec_data))
+                        .build();
+                    let _ = gst_pbutils::codec_utils_aac_caps_set_level_and_profile(
+                        caps.get_mut().unwrap(),
+                        codec_data,
+                    );
+                    caps
+                }
+                // MP3
+                0x69 | 0x6b => gst::Caps::builder("audio/mpeg")
+                    .field("mpegversion", 1i32)
+                    .field("layer", 3i32)
+                    .field("parsed", true)
+                    .build(),
+                _ => return Ok(None),
+            }
+        }
+        b"Opus" => {
+            let dops = child(b"dOps").ok_or_else(|| anyhow!("no dOps box"))?;
+            let mut r = Reader::new(dops);
+            let _version = r.u8()?;
+            let channels = r.u8()?;
+            let pre_skip = r.u16()?;
+            let rate = r.u32()?;
+            let output_gain = r.i16()?;
+            let channel_mapping_family = r.u8()?;
+            let (stream_count, coupled_count, channel_mapping) = if channel_mapping_family > 0 {
+                let stream_count = r.u8()?;
+                let coupled_count = r.u8()?;
+                (
+                    stream_count,
+                    coupled_count,
+                    r.bytes(channels as usize)?.to_vec(),
+                )
+            } else {
+                (1, (channels > 1) as u8, vec![])
+            };
+
+            let header = gst_pbutils::codec_utils_opus_create_header(
+                rate,
+                channels,
+                channel_mapping_family,
+                stream_count,
+                coupled_count,
+                &channel_mapping,
+                pre_skip,
+                output_gain,
+            )
+            .context("creating Opus header")?;
+
+            return gst_pbutils::codec_utils_opus_create_caps_from_header(&header, None)
+                .context("creating Opus caps")
+                .map(Some);
+        }
+        b"fLaC" => {
+            let dfla = child(b"dfLa").ok_or_else(|| anyhow!("no dfLa box"))?;
+            let mut r = Reader::new(dfla);
+            r.full_box_header()?;
+
+            // Metadata blocks including their headers
+            let mut blocks = vec![];
+            while r.remaining() >= 4 {
+                let block_header = r.bytes(4)?;
+                let len =
+                    u32::from_be_bytes([0, block_header[1], block_header[2], block_header[3]]);
+                let mut block = block_header.to_vec();
+                block.extend_from_slice(r.bytes(len as usize)?);
+                blocks.push(block);
+            }
+            let (streaminfo, remainder) = blocks
+                .split_first()
+                .ok_or_else(|| anyhow!("no FLAC STREAMINFO"))?;
+            if streaminfo.len() != 38 || streaminfo[0] & 0x7f != 0 {
+                bail!("invalid FLAC STREAMINFO");
+            }
+
+            // Ogg FLAC mapping header followed by the STREAMINFO
+            let mut header = Vec::with_capacity(13 + 38);
+            header.extend(b"\x7FFLAC\x01\x00");
+            header.extend((remainder.len() as u16).to_be_bytes());
+            header.extend(b"fLaC");
+            header.extend_from_slice(streaminfo);
+
+            let streamheader = gst::Array::new(
+                std::iter::once(gst::Buffer::from_mut_slice(header))
+                    .chain(remainder.iter().map(|block| codec_data_buffer(block))),
+            );
+
+            gst::Caps::builder("audio/x-flac")
+                .field("framed", true)
+                .field("streamheader", streamheader)
+                .build()
+        }
+        b"alaw" => gst::Caps::new_empty_simple("audio/x-alaw"),
+        b"ulaw" => gst::Caps::new_empty_simple("audio/x-mulaw"),
+        b"ms\x00\x45" => gst::Caps::builder("audio/x-adpcm")
+            .field("layout", "g726")
+            .field("bitrate", sample_size as i32 * 8000)
+            .build(),
+        _ => unreachable!(),
+    };
+
+    {
+        let caps = caps.get_mut().unwrap();
+        let s = caps.structure_mut(0).unwrap();
+        if !s.has_field("channels") {
+            s.set("channels", channels as i32);
+        }
+        if !s.has_field("rate") && rate > 0 {
+            s.set("rate", rate as i32);
+        }
+    }
+
+    Ok(Some(caps))
+}
+
+/// Parses an `esds` box and returns the object type and decoder specific info.
+fn parse_esds(data: &[u8]) -> Result<Option<(u8, Option<&[u8]>)>, Error> {
+    fn descriptor<'a>(r: &mut Reader<'a>) -> Result<(u8, &'a [u8]), Error> {
+        let tag = r.u8()?;
+        let mut len = 0usize;
+        for _ in 0..4 {
+            let b = r.u8()?;
+            len = (len << 7) | (b & 0x7f) as usize;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok((tag, r.bytes(len)?))
+    }
+
+    let mut r = Reader::new(data);
+    r.full_box_header()?;
+
+    let (tag, es_descriptor) = descriptor(&mut r)?;
+    if tag != 0x03 {
+        bail!("no ES descriptor");
+    }
+    let mut r = Reader::new(es_descriptor);
+    // ES ID
+    r.u16()?;
+    let flags = r.u8()?;
+    if flags & 0x80 != 0 {
+        // Depends on ES ID
+        r.u16()?;
+    }
+    if flags & 0x40 != 0 {
+        // URL
+        let len = r.u8()?;
+        r.skip(len as usize)?;
+    }
+    if flags & 0x20 != 0 {
+        // OCR ES ID
+        r.u16()?;
+    }
+
+    while r.remaining() > 0 {
+        let (tag, content) = descriptor(&mut r)?;
+        if tag != 0x04 {
+            continue;
+        }
+
+        let mut r = Reader::new(content);
+        let object_type = r.u8()?;
+        // Stream type, buffer size, max / avg bitrate
+        r.skip(1 + 3 + 4 + 4)?;
+
+        let mut decoder_specific_info = None;
+        while r.remaining() > 0 {
+            let (tag, content) = descriptor(&mut r)?;
+            if tag == 0x05 {
+                decoder_specific_info = Some(content);
+                break;
+            }
+        }
+
+        return Ok(Some((object_type, decoder_specific_info)));
+    }
+
+    Ok(None)
+}
+
+fn parse_xml_meta_data_sample_entry(r: &mut Reader) -> Result<Option<gst::Caps>, Error> {
+    let _content_encoding = r.c_string()?;
+    let namespace = r.c_string()?;
+
+    match namespace.as_str() {
+        "http://www.onvif.org/ver10/schema" => Ok(Some(
+            gst::Caps::builder("application/x-onvif-metadata")
+                .field("parsed", true)
+                .build(),
+        )),
+        _ => Ok(None),
+    }
+}
+
+const TFHD_BASE_DATA_OFFSET_PRESENT: u32 = 0x0_00_01;
+const TFHD_SAMPLE_DESCRIPTION_INDEX_PRESENT: u32 = 0x0_00_02;
+const TFHD_DEFAULT_SAMPLE_DURATION_PRESENT: u32 = 0x0_00_08;
+const TFHD_DEFAULT_SAMPLE_SIZE_PRESENT: u32 = 0x0_00_10;
+const TFHD_DEFAULT_SAMPLE_FLAGS_PRESENT: u32 = 0x0_00_20;
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x2_00_00;
+
+const TRUN_DATA_OFFSET_PRESENT: u32 = 0x0_01;
+const TRUN_FIRST_SAMPLE_FLAGS_PRESENT: u32 = 0x0_04;
+const TRUN_SAMPLE_DURATION_PRESENT: u32 = 0x1_00;
+const TRUN_SAMPLE_SIZE_PRESENT: u32 = 0x2_00;
+const TRUN_SAMPLE_FLAGS_PRESENT: u32 = 0x4_00;
+const TRUN_SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT: u32 = 0x8_00;
+
+const SAMPLE_FLAGS_NON_SYNC_SAMPLE: u32 = 0x1_00_00;
+
+/// Samples of one track in a movie fragment.
+#[derive(Debug, Clone)]
+pub(super) struct TrackFragment {
+    pub(super) track_id: u32,
+    /// Decoding time of the first sample in media timescale, from the `tfdt` box
+    pub(super) base_media_decode_time: Option<u64>,
+    /// Samples with decoding times relative to the first sample
+    pub(super) samples: Vec<Sample>,
+}
+
+/// Parses the content of a `moof` box that starts at `moof_offset` in the file.
+pub(super) fn parse_moof(
+    data: &[u8],
+    moof_offset: u64,
+    tracks: &[Track],
+) -> Result<Vec<TrackFragment>, Error> {
+    let mut fragments = vec![];
+    let mut next_data_offset = moof_offset;
+
+    for child in children(data) {
+        let (fourcc, content) = child?;
+        if &fourcc != b"traf" {
+            continue;
+        }
+
+        let tfhd = find_child(content, b"tfhd")?.ok_or_else(|| anyhow!("no tfhd box"))?;
+        let mut r = Reader::new(tfhd);
+        let (_version, tf_flags) = r.full_box_header()?;
+        let track_id = r.u32()?;
+        let trex = tracks
+            .iter()
+            .find(|track| track.id == track_id)
+            .map(|track| track.trex)
+            .ok_or_else(|| anyhow!("traf for unknown track {track_id}"))?;
+
+        let base_data_offset = if tf_flags & TFHD_BASE_DATA_OFFSET_PRESENT != 0 {
+            r.u64()?
+        } else if tf_flags & TFHD_DEFAULT_BASE_IS_MOOF != 0 {
+            moof_offset
+        } else {
+            next_data_offset
+        };
+        if tf_flags & TFHD_SAMPLE_DESCRIPTION_INDEX_PRESENT != 0 {
+            r.u32()?;
+        }
+        let default_duration = if tf_flags & TFHD_DEFAULT_SAMPLE_DURATION_PRESENT != 0 {
+            r.u32()?
+        } else {
+            trex.default_sample_duration
+        };
+        let default_size = if tf_flags & TFHD_DEFAULT_SAMPLE_SIZE_PRESENT != 0 {
+            r.u32()?
+        } else {
+            trex.default_sample_size
+        };
+        let default_flags = if tf_flags & TFHD_DEFAULT_SAMPLE_FLAGS_PRESENT != 0 {
+            r.u32()?
+        } else {
+            trex.default_sample_flags
+        };
+
+        let base_media_decode_time = find_child(content, b"tfdt")?
+            .map(|tfdt| {
+                let mut r = Reader::new(tfdt);
+                let (version, _flags) = r.full_box_header()?;
+                r.u32_or_u64(version)
+            })
+            .transpose()?;
+
+        let mut samples = vec![];
+        let mut data_offset = base_data_offset;
+        let mut dts = 0u64;
+        for child in children(content) {
+            let (fourcc, content) = child?;
+            if &fourcc != b"trun" {
+                continue;
+            }
+
+            let mut r = Reader::new(content);
+            let (version, tr_flags) = r.full_box_header()?;
+            let sample_count = r.u32()?;
+            if tr_flags & TRUN_DATA_OFFSET_PRESENT != 0 {
+                data_offset = base_data_offset
+                    .checked_add_signed(r.i32()? as i64)
+                    .ok_or_else(|| anyhow!("invalid data offset"))?;
+            }
+            let first_sample_flags = if tr_flags & TRUN_FIRST_SAMPLE_FLAGS_PRESENT != 0 {
+                Some(r.u32()?)
+            } else {
+                None
+            };
+
+            for idx in 0..sample_count {
+                let duration = if tr_flags & TRUN_SAMPLE_DURATION_PRESENT != 0 {
+                    r.u32()?
+                } else {
+                    default_duration
+                };
+                let size = if tr_flags & TRUN_SAMPLE_SIZE_PRESENT != 0 {
+                    r.u32()?
+                } else {
+                    default_size
+                };
+                let flags = if tr_flags & TRUN_SAMPLE_FLAGS_PRESENT != 0 {
+                    r.u32()?
+                } else if let Some(first_sample_flags) = first_sample_flags.filter(|_| idx == 0) {
+                    first_sample_flags
+                } else {
+                    default_flags
+                };
+                let composition_time_offset =
+                    if tr_flags & TRUN_SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT != 0 {
+                        if version == 0 {
+                            r.u32()? as i64
+                        } else {
+                            r.i32()? as i64
+                        }
+                    } else {
+                        0
+                    };
+
+                samples.push(Sample {
+                    offset: data_offset,
+                    size,
+                    dts,
+                    duration,
+                    composition_time_offset,
+                    sync_point: flags & SAMPLE_FLAGS_NON_SYNC_SAMPLE == 0,
+                });
+
+                data_offset += size as u64;
+                dts += duration as u64;
+            }
+        }
+
+        next_data_offset = data_offset;
+
+        fragments.push(TrackFragment {
+            track_id,
+            base_media_decode_time,
+            samples,
+        });
+    }
+
+    Ok(fragments)
+}
+
+/// Random access point of a fragmented file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct RandomAccessPoint {
+    pub(super) track_id: u32,
+    /// Presentation time in media timescale of the track
+    pub(super) time: u64,
+    /// Offset of the `moof` box
+    pub(super) moof_offset: u64,
+}
+
+/// Parses the content of a `mfra` box.
+pub(super) fn parse_mfra(data: &[u8]) -> Result<Vec<RandomAccessPoint>, Error> {
+    let mut points = vec![];
+
+    for child in children(data) {
+        let (fourcc, content) = child?;
+        if &fourcc != b"tfra" {
+            continue;
+        }
+
+        let mut r = Reader::new(content);
+        let (version, _flags) = r.full_box_header()?;
+        let track_id = r.u32()?;
+        let lengths = r.u32()?;
+        let length_size_of_traf_num = ((lengths >> 4) & 0x3) as usize + 1;
+        let length_size_of_trun_num = ((lengths >> 2) & 0x3) as usize + 1;
+        let length_size_of_sample_num = (lengths & 0x3) as usize + 1;
+        let entry_count = r.u32()?;
+
+        for _ in 0..entry_count {
+            let time = r.u32_or_u64(version)?;
+            let moof_offset = r.u32_or_u64(version)?;
+            r.skip(length_size_of_traf_num + length_size_of_trun_num + length_size_of_sample_num)?;
+
+            points.push(RandomAccessPoint {
+                track_id,
+                time,
+                moof_offset,
+            });
+        }
+    }
+
+    Ok(points)
+}
+
+/// Parses the content of a `mfro` box and returns the size of the `mfra` box.
+pub(super) fn parse_mfro(data: &[u8]) -> Result<u32, Error> {
+    let mut r = Reader::new(data);
+    r.full_box_header()?;
+    r.u32()
+}
+
+/// Subsegment reference from a `sidx` box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct SegmentReference {
+    /// Earliest presentation time of the subsegment
+    pub(super) time: gst::ClockTime,
+    /// Offset of the first byte of the subsegment
+    pub(super) offset: u64,
+    pub(super) starts_with_sap: bool,
+}
+
+/// Parses the content of a `sidx` box that ends at `end_offset` in the file and returns the
+/// track id and its subsegments.
+pub(super) fn parse_sidx(
+    data: &[u8],
+    end_offset: u64,
+) -> Result<(u32, Vec<SegmentReference>), Error> {
+    let mut r = Reader::new(data);
+    let (version, _flags) = r.full_box_header()?;
+    let reference_id = r.u32()?;
+    let timescale = r.u32()?;
+    if timescale == 0 {
+        bail!("invalid timescale 0");
+    }
+    let mut time = r.u32_or_u64(version)?;
+    let mut offset = end_offset + r.u32_or_u64(version)?;
+    // Reserved
+    r.u16()?;
+    let reference_count = r.u16()?;
+
+    let mut references = vec![];
+    for _ in 0..reference_count {
+        let reference = r.u32()?;
+        let reference_type = reference >> 31;
+        let size = (reference & 0x7f_ff_ff_ff) as u64;
+        let duration = r.u32()?;
+        let sap = r.u32()?;
+
+        // Only media references, no references to other sidx boxes
+        if reference_type == 0 {
+            references.push(SegmentReference {
+                time: gst::ClockTime::from_nseconds(
+                    time.mul_div_round(*gst::ClockTime::SECOND, timescale as u64)
+                        .unwrap_or(u64::MAX),
+                ),
+                offset,
+                starts_with_sap: sap >> 31 != 0,
+            });
+        }
+
+        time += duration as u64;
+        offset += size;
+    }
+
+    Ok((reference_id, references))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_box(v: &mut Vec<u8>, fourcc: &[u8; 4], content: &[u8]) {
+        v.extend((8 + content.len() as u32).to_be_bytes());
+        v.extend(fourcc);
+        v.extend(content);
+    }
+
+    #[test]
+    fn test_box_header() {
+        assert_eq!(parse_box_header(&[0, 0, 0]).unwrap(), None);
+        assert_eq!(
+            parse_box_header(b"\x00\x00\x00\x10moov").unwrap(),
+            Some(BoxHeader {
+                fourcc: *b"moov",
+                header_size: 8,
+                size: Some(16),
+            })
+        );
+        assert_eq!(
+            parse_box_header(b"\x00\x00\x00\x00mdat").unwrap(),
+            Some(BoxHeader {
+                fourcc: *b"mdat",
+                header_size: 8,
+                size: None,
+            })
+        );
+        assert_eq!(parse_box_header(b"\x00\x00\x00\x01mdat").unwrap(), None);
+        assert_eq!(
+            parse_box_header(b"\x00\x00\x00\x01mdat\x00\x00\x00\x01\x00\x00\x00\x00").unwrap(),
+            Some(BoxHeader {
+                fourcc: *b"mdat",
+                header_size: 16,
+                size: Some(0x1_00_00_00_00),
+            })
+        );
+        assert!(parse_box_header(b"\x00\x00\x00\x04free").is_err());
+    }
+
+    #[test]
+    fn test_moof() {
+        let tracks = [Track {
+            id: 1,
+            handler_type: *b"vide",
+            timescale: 90_000,
+            language_code: None,
+            sample_entry: *b"avc1",
+            caps: None,
+            edit: Edit::default(),
+            trex: TrackExtends::default(),
+            samples: vec![],
+        }];
+
+        let mut tfhd = vec![];
+        tfhd.extend(
+            (TFHD_DEFAULT_BASE_IS_MOOF | TFHD_DEFAULT_SAMPLE_DURATION_PRESENT).to_be_bytes(),
+        );
+        tfhd.extend(1u32.to_be_bytes());
+        tfhd.extend(3000u32.to_be_bytes());
+
+        let mut tfdt = vec![];
+        tfdt.extend((1u32 << 24).to_be_bytes());
+        tfdt.extend(90_000u64.to_be_bytes());
+
+        let mut trun = vec![];
+        trun.extend(
+            (TRUN_DATA_OFFSET_PRESENT
+                | TRUN_FIRST_SAMPLE_FLAGS_PRESENT
+                | TRUN_SAMPLE_SIZE_PRESENT
+                | TRUN_SAMPLE_FLAGS_PRESENT)
+                .to_be_bytes(),
+        );
+        trun.extend(2u32.to_be_bytes());
+        trun.extend(100i32.to_be_bytes());
+        trun.extend(SAMPLE_FLAGS_NON_SYNC_SAMPLE.to_be_bytes());
+        trun.extend(10u32.to_be_bytes());
+        trun.extend(0u32.to_be_bytes());
+        trun.extend(20u32.to_be_bytes());
+        trun.extend(SAMPLE_FLAGS_NON_SYNC_SAMPLE.to_be_bytes());
+
+        let mut traf = vec![];
+        write_box(&mut traf, b"tfhd", &tfhd);
+        write_box(&mut traf, b"tfdt", &tfdt);
+        write_box(&mut traf, b"trun", &trun);
+
+        let mut moof = vec![];
+        write_box(&mut moof, b"traf", &traf);
+
+        let fragments = parse_moof(&moof, 1000, &tracks).unwrap();
+        assert_eq!(fragments.len(), 1);
+        assert_eq!(fragments[0].track_id, 1);
+        assert_eq!(fragments[0].base_media_decode_time, Some(90_000));
+        assert_eq!(
+            fragments[0].samples,
+            vec![
+                Sample {
+                    offset: 1100,
+                    size: 10,
+                    dts: 0,
+                    duration: 3000,
+                    composition_time_offset: 0,
+                    sync_point: true,
+                },
+                Sample {
+                    offset: 1110,
+                    size: 20,
+                    dts: 3000,
+                    duration: 3000,
+                    composition_time_offset: 0,
+                    sync_point: false,
+                },
+            ]
+        );
+
+        // Unknown track
+        assert!(parse_moof(&moof, 1000, &[]).is_err());
+    }
+
+    #[test]
+    fn test_sample_table() {
+        let mut stbl = vec![];
+
+        let mut stsz = vec![0u8; 4];
+        stsz.extend(0u32.to_be_bytes());
+        stsz.extend(4u32.to_be_bytes());
+        for size in [10u32, 20, 30, 40] {
+            stsz.extend(size.to_be_bytes());
+        }
+        write_box(&mut stbl, b"stsz", &stsz);
+
+        let mut stts = vec![0u8; 4];
+        stts.extend(1u32.to_be_bytes());
+        stts.extend(4u32.to_be_bytes());
+        stts.extend(1024u32.to_be_bytes());
+        write_box(&mut stbl, b"stts", &stts);
+
+        let mut stss = vec![0u8; 4];
+        stss.extend(1u32.to_be_bytes());
+        stss.extend(3u32.to_be_bytes());
+        write_box(&mut stbl, b"stss", &stss);
+
+        // Two chunks with 3 and 1 samples
+        let mut stsc = vec![0u8; 4];
+        stsc.extend(2u32.to_be_bytes());
+        for entry in [[1u32, 3, 1], [2, 1, 1]] {
+            for v in entry {
+                stsc.extend(v.to_be_bytes());
+            }
+        }
+        write_box(&mut stbl, b"stsc", &stsc);
+
+        let mut stco = vec![0u8; 4];
+        stco.extend(2u32.to_be_bytes());
+        stco.extend(100u32.to_be_bytes());
+        stco.extend(1000u32.to_be_bytes());
+        write_box(&mut stbl, b"stco", &stco);
+
+        let samples = parse_sample_table(&stbl).unwrap();
+        assert_eq!(
+            samples
+                .iter()
+                .map(|s| (s.offset, s.size, s.dts, s.sync_point))
+                .collect::<Vec<_>>(),
+            vec![
+                (100, 10, 0, false),
+                (110, 20, 1024, false),
+                (130, 30, 2048, true),
+                (1000, 40, 3072, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sidx() {
+        let mut sidx = vec![];
+        sidx.extend(0u32.to_be_bytes());
+        // Reference ID, timescale
+        sidx.extend(1u32.to_be_bytes());
+        sidx.extend(1000u32.to_be_bytes());
+        // Earliest presentation time, first offset
+        sidx.extend(500u32.to_be_bytes());
+        sidx.extend(10u32.to_be_bytes());
+        // Reserved, reference count
+        sidx.extend(0u16.to_be_bytes());
+        sidx.extend(2u16.to_be_bytes());
+        for (size, duration) in [(1000u32, 2000u32), (2000, 2000)] {
+            sidx.extend(size.to_be_bytes());
+            sidx.extend(duration.to_be_bytes());
+            sidx.extend(0x8000_0000u32.to_be_bytes());
+        }
+
+        let (reference_id, references) = parse_sidx(&sidx, 100).unwrap();
+        assert_eq!(reference_id, 1);
+        assert_eq!(
+            references,
+            vec![
+                SegmentReference {
+                    time: gst::ClockTime::from_mseconds(500),
+                    offset: 110,
+                    starts_with_sap: true,
+                },
+                SegmentReference {
+                    time: gst::ClockTime::from_mseconds(2500),
+                    offset: 1110,
+                    starts_with_sap: true,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,1582 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+use super::boxes;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "mp4demux",
+        gst::DebugColorFlags::empty(),
+        Some("MP4Demux Element"),
+    )
+});
+
+/// Maximum size of boxes that are parsed in memory.
+const MAX_BOX_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Number of bytes to read for a box header, including the extended size.
+const BOX_HEADER_READ_SIZE: u64 = 16;
+
+struct Stream {
+    pad: gst::Pad,
+    track: boxes::Track,
+    /// Media time at which the presentation of this track starts
+    ///
+    /// For fragmented tracks without edit list this is only known after the first fragment.
+    media_start: Option<gst::ClockTime>,
+    /// Empty time in the presentation before the media starts
+    empty_duration: gst::ClockTime,
+    /// Duration of the media in the presentation, if limited by the edit list
+    media_duration: Option<gst::ClockTime>,
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    need_segment: bool,
+    discont: bool,
+    /// Decoding time after the last sample of the previous fragment in media timescale
+    next_fragment_dts: u64,
+}
+
+impl Stream {
+    /// Converts a position in the presentation to the media time of this track.
+    fn media_time(&self, position: gst::ClockTime) -> gst::ClockTime {
+        self.media_start.unwrap_or(gst::ClockTime::ZERO)
+            + position.saturating_sub(self.empty_duration)
+    }
+
+    /// Converts a media time of this track to a position in the presentation.
+    fn position(&self, media_time: gst::ClockTime) -> gst::ClockTime {
+        self.empty_duration
+            + media_time.saturating_sub(self.media_start.unwrap_or(gst::ClockTime::ZERO))
+    }
+
+    fn pts(&self, sample: &boxes::Sample) -> gst::ClockTime {
+        self.track.to_clock_time(
+            sample
+                .dts
+                .checked_add_signed(sample.composition_time_offset)
+                .unwrap_or(0),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingSample {
+    stream_idx: usize,
+    sample: boxes::Sample,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for the header of the next top-level box
+    BoxHeader,
+    /// Waiting for the complete content of a top-level box
+    BoxContent(boxes::BoxHeader),
+    /// Waiting for the data of the next pending sample
+    Samples,
+    /// Nothing left to do
+    Eos,
+}
+
+/// Where to continue after a seek.
+#[derive(Debug)]
+struct SeekTarget {
+    /// Position in the presentation after snapping to a keyframe if requested
+    position: gst::ClockTime,
+    /// Offset of the first data that is needed
+    offset: u64,
+    /// Samples to output for non-fragmented files
+    samples: Option<VecDeque<PendingSample>>,
+}
+
+struct State {
+    phase: Phase,
+    /// Offset of the current top-level box
+    offset: u64,
+    fragmented: bool,
+    duration: Option<gst::ClockTime>,
+    streams: Vec<Stream>,
+    /// Ranges of all `mdat` boxes that were seen so far
+    mdats: Vec<(u64, Option<u64>)>,
+    /// Samples that are going to be output next, in file order
+    pending_samples: VecDeque<PendingSample>,
+    /// Offset of the next top-level box after all pending samples were output, or `None` if
+    /// there is nothing after them
+    resume_offset: Option<u64>,
+    /// Start times of known fragments and the offsets of their `moof` boxes, sorted by time
+    fragment_index: Vec<(gst::ClockTime, u64)>,
+    /// Random access points from the `mfra` box before the `moov` box was parsed
+    random_access_points: Vec<boxes::RandomAccessPoint>,
+    first_moof_offset: Option<u64>,
+    /// Whether the `mfra` box was already looked for in pull mode
+    mfra_checked: bool,
+    /// Position in the presentation that the segments start at
+    seek_position: gst::ClockTime,
+    /// Stop position in the presentation, if any
+    seek_stop: Option<gst::ClockTime>,
+    segment_seqnum: gst::Seqnum,
+    group_id: gst::GroupId,
+    last_position: Option<gst::ClockTime>,
+    /// Push mode only: data that was received but not handled yet, and its offset
+    adapter: gst_base::UniqueAdapter,
+    adapter_offset: u64,
+    /// Push mode only: seek that is applied once upstream has flushed
+    pending_seek: Option<(SeekTarget, gst::Seqnum)>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            phase: Phase::BoxHeader,
+            offset: 0,
+            fragmented: false,
+            duration: None,
+            streams: vec![],
+            mdats: vec![],
+            pending_samples: VecDeque::new(),
+            resume_offset: None,
+            fragment_index: vec![],
+            random_access_points: vec![],
+            first_moof_offset: None,
+            mfra_checked: false,
+            seek_position: gst::ClockTime::ZERO,
+            seek_stop: None,
+            segment_seqnum: gst::Seqnum::next(),
+            group_id: gst::GroupId::next(),
+            last_position: None,
+            adapter: gst_base::UniqueAdapter::new(),
+            adapter_offset: 0,
+            pending_seek: None,
+        }
+    }
+}
+
+impl State {
+    fn add_fragment_index_entry(&mut self, time: gst::ClockTime, offset: u64) {
+        if self.fragment_index.iter().any(|(_, o)| *o == offset) {
+            return;
+        }
+        let idx = self.fragment_index.partition_point(|(t, _)| *t <= time);
+        self.fragment_index.insert(idx, (time, offset));
+    }
+}
+
+/// Things to do after handling data, outside the state lock.
+enum Output {
+    /// New pads to expose
+    Pads(Vec<gst::Pad>),
+    Event(gst::Pad, gst::Event),
+    Buffer(gst::Pad, gst::Buffer),
+}
+
+pub(crate) struct MP4Demux {
+    sinkpad: gst::Pad,
+    state: Mutex<State>,
+    flow_combiner: Mutex<gst_base::UniqueFlowCombiner>,
+}
+
+impl MP4Demux {
+    fn sink_activate(&self, pad: &gst::Pad) -> Result<(), gst::LoggableError> {
+        let mut query = gst::query::Scheduling::new();
+        let mode = if !pad.peer_query(&mut query) {
+            gst::debug!(CAT, obj = pad, "Scheduling query failed on peer");
+            gst::PadMode::Push
+        } else if query
+            .has_scheduling_mode_with_flags(gst::PadMode::Pull, gst::SchedulingFlags::SEEKABLE)
+        {
+            gst::debug!(CAT, obj = pad, "Activating in Pull mode");
+            gst::PadMode::Pull
+        } else {
+            gst::debug!(CAT, obj = pad, "Activating in Push mode");
+            gst::PadMode::Push
+        };
+
+        pad.activate_mode(mode, true)?;
+        Ok(())
+    }
+
+    fn sink_activatemode(
+        &self,
+        _pad: &gst::Pad,
+        mode: gst::PadMode,
+        active: bool,
+    ) -> Result<(), gst::LoggableError> {
+        if active {
+            *self.state.lock().unwrap() = State::default();
+            self.flow_combiner.lock().unwrap().reset();
+
+            if mode == gst::PadMode::Pull {
+                self.start_task()?;
+            }
+        } else if mode == gst::PadMode::Pull {
+            let _ = self.sinkpad.stop_task();
+        }
+
+        Ok(())
+    }
+
+    fn start_task(&self) -> Result<(), gst::LoggableError> {
+        let self_ = self.ref_counted();
+        let res = self.sinkpad.start_task(move || {
+            self_.loop_fn();
+        });
+        if res.is_err() {
+            return Err(gst::loggable_error!(CAT, "Failed to start pad task"));
+        }
+        Ok(())
+    }
+
+    fn loop_fn(&self) {
+        let Err(flow) = self.pull_data() else {
+            return;
+        };
+
+        match flow {
+            gst::FlowError::Flushing => {
+                gst::debug!(CAT, imp = self, "Pausing after flow {flow:?}");
+            }
+            gst::FlowError::Eos => {
+                gst::debug!(CAT, imp = self, "Pausing after flow {flow:?}");
+                self.push_eos();
+            }
+            _ => {
+                gst::error!(CAT, imp = self, "Pausing after flow {flow:?}");
+                self.push_eos();
+
+                if flow != gst::FlowError::Error {
+                    gst::element_imp_error!(
+                        self,
+                        gst::StreamError::Failed,
+                        ["Streaming stopped, reason: {flow:?}"]
+                    );
+                }
+            }
+        }
+
+        let _ = self.sinkpad.pause_task();
+    }
+
+    /// Reads and handles the next data in pull mode.
+    fn pull_data(&self) -> Result<(), gst::FlowError> {
+        let mut state = self.state.lock().unwrap();
+
+        if !state.mfra_checked {
+            state.mfra_checked = true;
+            drop(state);
+            self.read_mfra();
+            state = self.state.lock().unwrap();
+        }
+
+        let Some((offset, size)) = self.next_read(&state) else {
+            return Err(gst::FlowError::Eos);
+        };
+        let phase = state.phase;
+        drop(state);
+
+        let size = u32::try_from(size).map_err(|_| {
+            gst::element_imp_error!(self, gst::StreamError::Demux, ["Too big read of {size}"]);
+            gst::FlowError::Error
+        })?;
+
+        gst::trace!(CAT, imp = self, "Pulling {size} bytes at offset {offset}");
+        let buffer = self.sinkpad.pull_range(offset, size)?;
+        if phase != Phase::BoxHeader && buffer.size() < size as usize {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Got only {} of {size} bytes at offset {offset}, file is truncated",
+                buffer.size(),
+            );
+            return Err(gst::FlowError::Eos);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let outputs = self.handle_data(&mut state, buffer).map_err(|err| {
+            self.post_error_message(err);
+            gst::FlowError::Error
+        })?;
+        drop(state);
+
+        self.handle_outputs(outputs)?;
+
+        Ok(())
+    }
+
+    /// Looks for a `mfra` box at the end of the file in pull mode.
+    fn read_mfra(&self) {
+        let mut q = gst::query::Duration::new(gst::Format::Bytes);
+        if !self.sinkpad.peer_query(&mut q) {
+            return;
+        }
+        let size = match q.result() {
+            gst::GenericFormattedValue::Bytes(Some(size)) => *size,
+            _ => return,
+        };
+        if size < 16 {
+            return;
+        }
+
+        let Ok(buffer) = self.sinkpad.pull_range(size - 16, 16) else {
+            return;
+        };
+        let Ok(map) = buffer.map_readable() else {
+            return;
+        };
+        let mfra_size = match boxes::parse_box_header(&map) {
+            Ok(Some(header)) if &header.fourcc == b"mfro" && header.size == Some(16) => {
+                match boxes::parse_mfro(&map[8..]) {
+                    Ok(mfra_size) => mfra_size as u64,
+                    Err(_) => return,
+                }
+            }
+            _ => return,
+        };
+        drop(map);
+
+        if mfra_size < 16 || mfra_size > size || mfra_size > MAX_BOX_SIZE {
+            return;
+        }
+
+        let Ok(buffer) = self.sinkpad.pull_range(size - mfra_size, mfra_size as u32) else {
+            return;
+        };
+        let Ok(map) = buffer.map_readable() else {
+            return;
+        };
+        let header = match boxes::parse_box_header(&map) {
+            Ok(Some(header)) if &header.fourcc == b"mfra" && header.size == Some(mfra_size) => {
+                header
+            }
+            _ => return,
+        };
+
+        match boxes::parse_mfra(&map[header.header_size as usize..]) {
+            Ok(random_access_points) => {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Found {} random access points",
+                    random_access_points.len()
+                );
+                self.state.lock().unwrap().random_access_points = random_access_points;
+            }
+            Err(err) => {
+                gst::warning!(CAT, imp = self, "Failed to parse mfra box: {err:?}");
+            }
+        }
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst::log!(CAT, obj = pad, "Handling buffer {:?}", buffer);
+
+        let mut state = self.state.lock().unwrap();
+        state.adapter.push(buffer);
+
+        loop {
+            let Some((offset, size)) = self.next_read(&state) else {
+                return Err(gst::FlowError::Eos);
+            };
+
+            if offset < state.adapter_offset {
+                // Data before the current position is needed, e.g. samples before the moov
+                drop(state);
+                return self.seek_upstream(offset);
+            }
+
+            let skip = offset - state.adapter_offset;
+            if skip > 0 {
+                let flush = std::cmp::min(skip, state.adapter.available() as u64);
+                state.adapter.flush(flush as usize);
+                state.adapter_offset += flush;
+                if flush < skip {
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+            }
+
+            if (state.adapter.available() as u64) < size {
+                gst::trace!(CAT, imp = self, "Need more data");
+                return Ok(gst::FlowSuccess::Ok);
+            }
+
+            let buffer = state.adapter.take_buffer(size as usize).unwrap();
+            state.adapter_offset += size;
+
+            let outputs = self.handle_data(&mut state, buffer).map_err(|err| {
+                self.post_error_message(err);
+                gst::FlowError::Error
+            })?;
+
+            if !outputs.is_empty() {
+                drop(state);
+                self.handle_outputs(outputs)?;
+                state = self.state.lock().unwrap();
+            }
+        }
+    }
+
+    fn seek_upstream(&self, offset: u64) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst::debug!(CAT, imp = self, "Seeking upstream to offset {offset}");
+
+        let event = gst::event::Seek::new(
+            1.0,
+            gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+            gst::SeekType::Set,
+            Some(gst::format::Bytes::from_u64(offset)),
+            gst::SeekType::None,
+            None::<gst::format::Bytes>,
+        );
+
+        if !self.sinkpad.push_event(event) {
+            gst::element_imp_error!(
+                self,
+                gst::StreamError::Demux,
+                ["Upstream is not seekable, can't read data at offset {offset}"]
+            );
+            return Err(gst::FlowError::Error);
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Returns offset and size of the next data that is needed.
+    fn next_read(&self, state: &State) -> Option<(u64, u64)> {
+        match state.phase {
+            Phase::BoxHeader => Some((state.offset, BOX_HEADER_READ_SIZE)),
+            Phase::BoxContent(header) => Some((
+                state.offset + header.header_size,
+                header.size.unwrap() - header.header_size,
+            )),
+            Phase::Samples => state
+                .pending_samples
+                .front()
+                .map(|s| (s.sample.offset, s.sample.size as u64)),
+            Phase::Eos => None,
+        }
+    }
+
+    /// Handles the data that was requested by `next_read()`.
+    fn handle_data(
+        &self,
+        state: &mut State,
+        buffer: gst::Buffer,
+    ) -> Result<Vec<Output>, gst::ErrorMessage> {
+        match state.phase {
+            Phase::BoxHeader => {
+                let map = buffer.map_readable().unwrap();
+                self.handle_box_header(state, &map)
+            }
+            Phase::BoxContent(header) => {
+                let map = buffer.map_readable().unwrap();
+                let res = self.handle_box(state, header, &map);
+                state.offset += header.size.unwrap();
+                if state.phase == Phase::BoxContent(header) {
+                    state.phase = Phase::BoxHeader;
+                }
+                res
+            }
+            Phase::Samples => Ok(self.handle_sample(state, buffer)),
+            Phase::Eos => unreachable!(),
+        }
+    }
+
+    fn handle_box_header(
+        &self,
+        state: &mut State,
+        data: &[u8],
+    ) -> Result<Vec<Output>, gst::ErrorMessage> {
+        let header = match boxes::parse_box_header(data) {
+            Ok(Some(header)) => header,
+            Ok(None) => {
+                gst::debug!(CAT, imp = self, "Reached end of file");
+                state.phase = Phase::Eos;
+                return Ok(vec![]);
+            }
+            Err(err) => {
+                return Err(gst::error_msg!(
+                    gst::StreamError::Demux,
+                    ["Invalid box header at offset {}: {err}", state.offset]
+                ));
+            }
+        };
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Found {} box with size {:?} at offset {}",
+            boxes::fourcc_to_string(header.fourcc),
+            header.size,
+            state.offset,
+        );
+
+        let end = header.size.map(|size| state.offset + size);
+
+        match &header.fourcc {
+            b"ftyp" | b"moov" | b"moof" | b"sidx" | b"mfra" => {
+                let Some(size) = header.size else {
+                    return Err(gst::error_msg!(
+                        gst::StreamError::Demux,
+                        [
+                            "{} box extends to the end of the file",
+                            boxes::fourcc_to_string(header.fourcc)
+                        ]
+                    ));
+                };
+                if size > MAX_BOX_SIZE {
+                    return Err(gst::error_msg!(
+                        gst::StreamError::Demux,
+                        [
+                            "Too big {} box of {size} bytes",
+                            boxes::fourcc_to_string(header.fourcc)
+                        ]
+                    ));
+                }
+
+                state.phase = Phase::BoxContent(header);
+            }
+            b"mdat" => {
+                let range = (state.offset + header.header_size, end);
+                if !state.mdats.contains(&range) {
+                    state.mdats.push(range);
+                }
+
+                if !state.pending_samples.is_empty() {
+                    if state.fragmented {
+                        state.resume_offset = end;
+                    }
+                    state.phase = Phase::Samples;
+                } else if let Some(end) = end {
+                    state.offset = end;
+                } else if state.streams.is_empty() {
+                    return Err(gst::error_msg!(
+                        gst::StreamError::Demux,
+                        ["No moov box found before the end of the file"]
+                    ));
+                } else {
+                    state.phase = Phase::Eos;
+                }
+            }
+            _ => {
+                if let Some(end) = end {
+                    state.offset = end;
+                } else {
+                    state.phase = Phase::Eos;
+                }
+            }
+        }
+
+        Ok(vec![])
+    }
+
+    fn handle_box(
+        &self,
+        state: &mut State,
+        header: boxes::BoxHeader,
+        data: &[u8],
+    ) -> Result<Vec<Output>, gst::ErrorMessage> {
+        let end = state.offset + header.size.unwrap();
+
+        match &header.fourcc {
+            b"ftyp" => {
+                let ftyp = boxes::parse_ftyp(data).map_err(|err| {
+                    gst::error_msg!(gst::StreamError::Demux, ["Invalid ftyp box: {err:?}"])
+                })?;
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Major brand {}, compatible brands {:?}",
+                    boxes::fourcc_to_string(ftyp.major_brand),
+                    ftyp.compatible_brands
+                        .iter()
+                        .map(|b| boxes::fourcc_to_string(*b))
+                        .collect::<Vec<_>>(),
+                );
+            }
+            b"moov" => {
+                if !state.streams.is_empty() {
+                    gst::warning!(CAT, imp = self, "Ignoring additional moov box");
+                    return Ok(vec![]);
+                }
+
+                let movie = boxes::parse_moov(data).map_err(|err| {
+                    gst::error_msg!(gst::StreamError::Demux, ["Invalid moov box: {err:?}"])
+                })?;
+
+                return self.handle_moov(state, movie);
+            }
+            b"moof" => {
+                if state.streams.is_empty() {
+                    return Err(gst::error_msg!(
+                        gst::StreamError::Demux,
+                        ["moof box before moov box"]
+                    ));
+                }
+
+                self.handle_moof(state, data)?;
+            }
+            b"sidx" => match boxes::parse_sidx(data, end) {
+                Ok((reference_id, references)) => {
+                    gst::debug!(
+                        CAT,
+                        imp = self,
+                        "Found {} subsegments for track {reference_id}",
+                        references.len()
+                    );
+                    for reference in references.iter().filter(|r| r.starts_with_sap) {
+                        state.add_fragment_index_entry(reference.time, reference.offset);
+                    }
+                }
+                Err(err) => {
+                    gst::warning!(CAT, imp = self, "Failed to parse sidx box: {err:?}");
+                }
+            },
+            b"mfra" => {
+                // Already handled in pull mode and useless at the end of the stream in push mode
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(vec![])
+    }
+
+    fn handle_moov(
+        &self,
+        state: &mut State,
+        movie: boxes::Movie,
+    ) -> Result<Vec<Output>, gst::ErrorMessage> {
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Movie with {} tracks, timescale {}, duration {}, fragmented {}",
+            movie.tracks.len(),
+            movie.timescale,
+            movie.duration().display(),
+            movie.fragmented,
+        );
+
+        state.fragmented = movie.fragmented;
+        state.duration = movie.duration();
+
+        let templates = self.obj().pad_template_list();
+        let mut counts = std::collections::HashMap::<&str, u32>::new();
+        let mut pads = vec![];
+
+        for track in movie.tracks.iter().cloned() {
+            let Some(caps) = track.caps.clone() else {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Unsupported sample entry {} for track {}",
+                    boxes::fourcc_to_string(track.sample_entry),
+                    track.id
+                );
+                continue;
+            };
+
+            let s = caps.structure(0).unwrap();
+            let prefix = if s.name().starts_with("video/") || s.name().starts_with("image/") {
+                "video"
+            } else if s.name().starts_with("audio/") {
+                "audio"
+            } else if s.name().starts_with("application/x-onvif-metadata") {
+                "meta"
+            } else {
+                "subtitle"
+            };
+            let count = counts.entry(prefix).or_default();
+            let name = format!("{prefix}_{count}");
+            *count += 1;
+
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Creating pad {name} for {} track {} with caps {caps:?}",
+                boxes::fourcc_to_string(track.handler_type),
+                track.id
+            );
+
+            let templ = templates
+                .iter()
+                .find(|templ| templ.name_template() == format!("{prefix}_%u"))
+                .unwrap();
+            let pad = gst::Pad::builder_from_template(templ)
+                .name(name.as_str())
+                .event_function(|pad, parent, event| {
+                    MP4Demux::catch_panic_pad_function(
+                        parent,
+                        || false,
+                        |demux| demux.src_event(pad, event),
+                    )
+                })
+                .query_function(|pad, parent, query| {
+                    MP4Demux::catch_panic_pad_function(
+                        parent,
+                        || false,
+                        |demux| demux.src_query(pad, query),
+                    )
+                })
+                .build();
+
+            pad.set_active(true).unwrap();
+
+            let stream_id = pad.create_stream_id(&*self.obj(), Some(track.id.to_string().as_str()));
+            pad.push_event(
+                gst::event::StreamStart::builder(&stream_id)
+                    .group_id(state.group_id)
+                    .build(),
+            );
+            pad.push_event(gst::event::Caps::new(&caps));
+
+            let mut tags = gst::TagList::new();
+            {
+                let tags = tags.get_mut().unwrap();
+                tags.add::<gst::tags::ContainerFormat>(&"ISO MP4/M4A", gst::TagMergeMode::Replace);
+                if let Some(language_code) = track.language_code {
+                    if let Ok(language_code) = std::str::from_utf8(&language_code) {
+                        tags.add::<gst::tags::LanguageCode>(
+                            &language_code,
+                            gst::TagMergeMode::Replace,
+                        );
+                    }
+                }
+                let _ = gst_pbutils::pb_utils_add_codec_description_to_tag_list(tags, &caps);
+            }
+            pad.push_event(gst::event::Tag::new(tags));
+
+            let edit = track.edit;
+            let media_start = if edit.media_time > 0 || !movie.fragmented {
+                Some(track.to_clock_time(edit.media_time))
+            } else {
+                None
+            };
+            let movie_time = |t: u64| {
+                gst::ClockTime::from_nseconds(
+                    t.mul_div_round(*gst::ClockTime::SECOND, movie.timescale as u64)
+                        .unwrap_or(u64::MAX),
+                )
+            };
+
+            state.streams.push(Stream {
+                pad: pad.clone(),
+                media_start,
+                empty_duration: movie_time(edit.empty_duration),
+                media_duration: edit.duration.map(movie_time),
+                segment: gst::FormattedSegment::new(),
+                need_segment: true,
+                discont: true,
+                next_fragment_dts: 0,
+                track,
+            });
+
+            self.flow_combiner.lock().unwrap().add_pad(&pad);
+            pads.push(pad);
+        }
+
+        if pads.is_empty() {
+            return Err(gst::error_msg!(
+                gst::StreamError::Demux,
+                ["This file contains no playable streams"]
+            ));
+        }
+
+        // Random access points from the mfra box are in the timescale of their track
+        for point in std::mem::take(&mut state.random_access_points) {
+            let Some(stream) = state.streams.first() else {
+                break;
+            };
+            if stream.track.id != point.track_id {
+                continue;
+            }
+            let time = stream.track.to_clock_time(point.time);
+            state.add_fragment_index_entry(time, point.moof_offset);
+        }
+
+        if !state.fragmented {
+            let mut samples = state
+                .streams
+                .iter()
+                .enumerate()
+                .flat_map(|(stream_idx, stream)| {
+                    stream
+                        .track
+                        .samples
+                        .iter()
+                        .map(move |sample| PendingSample {
+                            stream_idx,
+                            sample: *sample,
+                        })
+                })
+                .collect::<Vec<_>>();
+            samples.sort_by_key(|s| s.sample.offset);
+            state.pending_samples = samples.into();
+            state.resume_offset = None;
+
+            // If the media data is before the moov, continue with the samples directly
+            let first_offset = state.pending_samples.front().map(|s| s.sample.offset);
+            if first_offset.is_some_and(|offset| {
+                state
+                    .mdats
+                    .iter()
+                    .any(|(start, end)| offset >= *start && end.map_or(true, |end| offset < end))
+            }) {
+                state.phase = Phase::Samples;
+            }
+        }
+
+        Ok(vec![Output::Pads(pads)])
+    }
+
+    fn handle_moof(&self, state: &mut State, data: &[u8]) -> Result<(), gst::ErrorMessage> {
+        let moof_offset = state.offset;
+
+        let tracks = state
+            .streams
+            .iter()
+            .map(|stream| stream.track.clone())
+            .collect::<Vec<_>>();
+        let fragments = boxes::parse_moof(data, moof_offset, &tracks).map_err(|err| {
+            gst::error_msg!(gst::StreamError::Demux, ["Invalid moof box: {err:?}"])
+        })?;
+
+        let mut samples = vec![];
+        let mut fragment_start = None;
+        for fragment in fragments {
+            let Some(stream_idx) = state
+                .streams
+                .iter()
+                .position(|stream| stream.track.id == fragment.track_id)
+            else {
+                continue;
+            };
+            let stream = &mut state.streams[stream_idx];
+
+            let base_dts = fragment
+                .base_media_decode_time
+                .unwrap_or(stream.next_fragment_dts);
+
+            for mut sample in fragment.samples {
+                sample.dts += base_dts;
+                stream.next_fragment_dts = sample.dts + sample.duration as u64;
+
+                let pts = stream.pts(&sample);
+                if fragment_start.map_or(true, |start| pts < start) {
+                    fragment_start = Some(pts);
+                }
+
+                samples.push(PendingSample { stream_idx, sample });
+            }
+        }
+
+        let Some(fragment_start) = fragment_start else {
+            gst::debug!(CAT, imp = self, "Empty fragment at offset {moof_offset}");
+            return Ok(());
+        };
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Fragment with {} samples starting at {fragment_start} at offset {moof_offset}",
+            samples.len()
+        );
+
+        // Fragmented tracks without edit list start at the first fragment
+        for stream in &mut state.streams {
+            if stream.media_start.is_none() {
+                stream.media_start = Some(fragment_start);
+            }
+        }
+
+        if state.first_moof_offset.is_none() {
+            state.first_moof_offset = Some(moof_offset);
+        }
+        state.add_fragment_index_entry(fragment_start, moof_offset);
+
+        samples.sort_by_key(|s| s.sample.offset);
+        state.pending_samples = samples.into();
+
+        Ok(())
+    }
+
+    fn handle_sample(&self, state: &mut State, mut buffer: gst::Buffer) -> Vec<Output> {
+        let PendingSample { stream_idx, sample } = state.pending_samples.pop_front().unwrap();
+
+        if state.pending_samples.is_empty() {
+            let sample_end = sample.offset + sample.size as u64;
+            match state.resume_offset.take() {
+                Some(resume_offset) => {
+                    state.offset = std::cmp::max(resume_offset, sample_end);
+                    state.phase = Phase::BoxHeader;
+                }
+                None if state.fragmented => {
+                    state.offset = sample_end;
+                    state.phase = Phase::BoxHeader;
+                }
+                None => {
+                    state.phase = Phase::Eos;
+                }
+            }
+        }
+
+        let mut outputs = vec![];
+        let seek_position = state.seek_position;
+        let seek_stop = state.seek_stop;
+        let segment_seqnum = state.segment_seqnum;
+        let stream = &mut state.streams[stream_idx];
+
+        if stream.need_segment {
+            stream.segment = segment_for_stream(stream, seek_position, seek_stop);
+            stream.need_segment = false;
+
+            gst::debug!(
+                CAT,
+                obj = stream.pad,
+                "Pushing segment {:?}",
+                stream.segment
+            );
+            outputs.push(Output::Event(
+                stream.pad.clone(),
+                gst::event::Segment::builder(&stream.segment)
+                    .seqnum(segment_seqnum)
+                    .build(),
+            ));
+        }
+
+        let pts = stream.pts(&sample);
+        {
+            let buffer = buffer.make_mut();
+            buffer.set_pts(pts);
+            buffer.set_dts(stream.track.to_clock_time(sample.dts));
+            buffer.set_duration(stream.track.to_clock_time(sample.duration as u64));
+            buffer.set_offset(gst::BUFFER_OFFSET_NONE);
+            buffer.set_offset_end(gst::BUFFER_OFFSET_NONE);
+            let mut flags = gst::BufferFlags::empty();
+            if !sample.sync_point {
+                flags |= gst::BufferFlags::DELTA_UNIT;
+            }
+            if stream.discont {
+                flags |= gst::BufferFlags::DISCONT;
+                stream.discont = false;
+            }
+            buffer.set_flags(flags);
+        }
+
+        gst::trace!(
+            CAT,
+            obj = stream.pad,
+            "Outputting sample of size {} at offset {} with pts {pts}",
+            sample.size,
+            sample.offset,
+        );
+
+        if let Some(position) = stream.segment.to_stream_time(pts) {
+            if state.last_position.map_or(true, |last| position > last) {
+                state.last_position = Some(position);
+            }
+        }
+
+        let pad = state.streams[stream_idx].pad.clone();
+        outputs.push(Output::Buffer(pad, buffer));
+
+        outputs
+    }
+
+    fn handle_outputs(&self, outputs: Vec<Output>) -> Result<gst::FlowSuccess, gst::FlowError> {
+        for output in outputs {
+            match output {
+                Output::Pads(pads) => {
+                    for pad in pads {
+                        self.obj().add_pad(&pad).unwrap();
+                    }
+                    self.obj().no_more_pads();
+                }
+                Output::Event(pad, event) => {
+                    pad.push_event(event);
+                }
+                Output::Buffer(pad, buffer) => {
+                    let res = pad.push(buffer);
+                    gst::trace!(CAT, obj = pad, "Pushing buffer returned {res:?}");
+                    self.flow_combiner
+                        .lock()
+                        .unwrap()
+                        .update_pad_flow(&pad, res)?;
+                }
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn src_pads(&self) -> Vec<gst::Pad> {
+        self.state
+            .lock()
+            .unwrap()
+            .streams
+            .iter()
+            .map(|stream| stream.pad.clone())
+            .collect()
+    }
+
+    fn push_eos(&self) {
+        let (pads, seqnum) = {
+            let state = self.state.lock().unwrap();
+            (
+                state
+                    .streams
+                    .iter()
+                    .map(|stream| stream.pad.clone())
+                    .collect::<Vec<_>>(),
+                state.segment_seqnum,
+            )
+        };
+
+        if pads.is_empty() {
+            gst::element_imp_error!(
+                self,
+                gst::StreamError::Demux,
+                ["This file contains no playable streams"]
+            );
+            return;
+        }
+
+        for pad in pads {
+            pad.push_event(gst::event::Eos::builder().seqnum(seqnum).build());
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst::log!(CAT, obj = pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(_) | EventView::StreamStart(_) => true,
+            EventView::Segment(ev) => {
+                // Time segments are created from the media itself, only the byte offset is
+                // relevant here
+                let mut state = self.state.lock().unwrap();
+                if let Some(segment) = ev.segment().downcast_ref::<gst::format::Bytes>() {
+                    let offset = segment.start().map(|start| *start).unwrap_or(0);
+                    gst::debug!(CAT, obj = pad, "Continuing at offset {offset}");
+                    state.adapter.clear();
+                    state.adapter_offset = offset;
+                }
+                true
+            }
+            EventView::FlushStop(_) => {
+                let mut state = self.state.lock().unwrap();
+                state.adapter.clear();
+                for stream in &mut state.streams {
+                    stream.need_segment = true;
+                    stream.discont = true;
+                }
+                if let Some((target, seqnum)) = state.pending_seek.take() {
+                    apply_seek(&mut state, target, seqnum);
+                }
+                drop(state);
+                self.flow_combiner.lock().unwrap().reset();
+
+                gst::Pad::event_default(pad, Some(&*self.obj()), event)
+            }
+            EventView::Eos(_) => {
+                if self.src_pads().is_empty() {
+                    gst::element_imp_error!(
+                        self,
+                        gst::StreamError::Demux,
+                        ["This file contains no playable streams"]
+                    );
+                }
+                gst::Pad::event_default(pad, Some(&*self.obj()), event)
+            }
+            _ => gst::Pad::event_default(pad, Some(&*self.obj()), event),
+        }
+    }
+
+    /// Finds where to continue when seeking to `position` in the presentation.
+    fn seek_target(
+        &self,
+        state: &State,
+        position: gst::ClockTime,
+        key_unit: bool,
+    ) -> Option<SeekTarget> {
+        let reference = state.streams.first()?;
+
+        if state.fragmented {
+            let media_time = reference.media_time(position);
+            let (time, offset) = state
+                .fragment_index
+                .iter()
+                .rev()
+                .find(|(time, _)| *time <= media_time)
+                .copied()
+                .or_else(|| {
+                    state
+                        .first_moof_offset
+                        .map(|offset| (gst::ClockTime::ZERO, offset))
+                })?;
+
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Seeking to fragment at {time} at offset {offset}"
+            );
+
+            return Some(SeekTarget {
+                position: if key_unit {
+                    reference.position(time)
+                } else {
+                    position
+                },
+                offset,
+                samples: None,
+            });
+        }
+
+        // Index of the last sync sample at or before the media time
+        let sync_sample_before = |stream: &Stream, media_time: gst::ClockTime| {
+            stream
+                .track
+                .samples
+                .iter()
+                .enumerate()
+                .filter(|(_, sample)| sample.sync_point && stream.pts(sample) <= media_time)
+                .map(|(idx, _)| idx)
+                .last()
+                .unwrap_or(0)
+        };
+
+        let mut position = position;
+        if key_unit {
+            // Snap to the keyframe of the first track with non-sync samples
+            if let Some(stream) = state
+                .streams
+                .iter()
+                .find(|stream| stream.track.samples.iter().any(|s| !s.sync_point))
+            {
+                let idx = sync_sample_before(stream, stream.media_time(position));
+                if let Some(sample) = stream.track.samples.get(idx) {
+                    position = stream.position(stream.pts(sample));
+                }
+            }
+        }
+
+        let mut samples = state
+            .streams
+            .iter()
+            .enumerate()
+            .flat_map(|(stream_idx, stream)| {
+                let idx = sync_sample_before(stream, stream.media_time(position));
+                stream.track.samples[idx..]
+                    .iter()
+                    .map(move |sample| PendingSample {
+                        stream_idx,
+                        sample: *sample,
+                    })
+            })
+            .collect::<Vec<_>>();
+        samples.sort_by_key(|s| s.sample.offset);
+
+        let offset = samples.first()?.sample.offset;
+
+        gst::debug!(CAT, imp = self, "Seeking to {position} at offset {offset}");
+
+        Some(SeekTarget {
+            position,
+            offset,
+            samples: Some(samples.into()),
+        })
+    }
+
+    fn perform_seek(&self, event: &gst::event::Seek) -> bool {
+        let (rate, flags, start_type, start, stop_type, stop) = event.get();
+
+        let Ok(start) = Option::<gst::ClockTime>::try_from(start) else {
+            gst::debug!(CAT, imp = self, "Only seeking in time is supported");
+            return false;
+        };
+        let Ok(stop) = Option::<gst::ClockTime>::try_from(stop) else {
+            gst::debug!(CAT, imp = self, "Only seeking in time is supported");
+            return false;
+        };
+
+        if rate != 1.0 {
+            gst::debug!(CAT, imp = self, "Only playback rate 1.0 is supported");
+            return false;
+        }
+
+        if !flags.contains(gst::SeekFlags::FLUSH) {
+            gst::debug!(CAT, imp = self, "Only flushing seeks are supported");
+            return false;
+        }
+
+        if start_type != gst::SeekType::Set
+            || (stop_type != gst::SeekType::Set && stop_type != gst::SeekType::None)
+        {
+            gst::debug!(CAT, imp = self, "Relative seeks are not supported");
+            return false;
+        }
+
+        let start = start.unwrap_or(gst::ClockTime::ZERO);
+        let stop = if stop_type == gst::SeekType::Set {
+            stop
+        } else {
+            None
+        };
+        let seqnum = event.seqnum();
+        let key_unit = flags.contains(gst::SeekFlags::KEY_UNIT);
+
+        if self.sinkpad.mode() == gst::PadMode::Pull {
+            let Some(target) = self.seek_target(&self.state.lock().unwrap(), start, key_unit)
+            else {
+                gst::debug!(CAT, imp = self, "Can't seek before knowing the streams");
+                return false;
+            };
+
+            let pads = self.src_pads();
+
+            self.sinkpad
+                .push_event(gst::event::FlushStart::builder().seqnum(seqnum).build());
+            for pad in &pads {
+                pad.push_event(gst::event::FlushStart::builder().seqnum(seqnum).build());
+            }
+
+            let _ = self.sinkpad.pause_task();
+
+            self.sinkpad
+                .push_event(gst::event::FlushStop::builder(true).seqnum(seqnum).build());
+            for pad in &pads {
+                pad.push_event(gst::event::FlushStop::builder(true).seqnum(seqnum).build());
+            }
+
+            let mut state = self.state.lock().unwrap();
+            apply_seek(&mut state, target, seqnum);
+            state.seek_stop = stop;
+            drop(state);
+
+            self.flow_combiner.lock().unwrap().reset();
+
+            match self.start_task() {
+                Err(error) => {
+                    error.log();
+                    false
+                }
+                _ => true,
+            }
+        } else {
+            let mut state = self.state.lock().unwrap();
+            let Some(target) = self.seek_target(&state, start, key_unit) else {
+                gst::debug!(CAT, imp = self, "Can't seek before knowing the streams");
+                return false;
+            };
+
+            let offset = target.offset;
+            state.pending_seek = Some((target, seqnum));
+            state.seek_stop = stop;
+            drop(state);
+
+            let event = gst::event::Seek::builder(
+                1.0,
+                gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+                gst::SeekType::Set,
+                Some(gst::format::Bytes::from_u64(offset)),
+                gst::SeekType::None,
+                None::<gst::format::Bytes>,
+            )
+            .seqnum(seqnum)
+            .build();
+
+            if self.sinkpad.push_event(event) {
+                true
+            } else {
+                gst::debug!(CAT, imp = self, "Upstream can't seek to offset {offset}");
+                self.state.lock().unwrap().pending_seek = None;
+                false
+            }
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst::log!(CAT, obj = pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Seek(ev) => {
+                // Let upstream handle the seek first if it can seek in time
+                if self.sinkpad.mode() == gst::PadMode::Push
+                    && self.sinkpad.push_event(event.clone())
+                {
+                    return true;
+                }
+
+                self.perform_seek(ev)
+            }
+            _ => gst::Pad::event_default(pad, Some(&*self.obj()), event),
+        }
+    }
+
+    fn src_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryViewMut;
+
+        gst::log!(CAT, obj = pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryViewMut::Position(q) if q.format() == gst::Format::Time => {
+                let state = self.state.lock().unwrap();
+                let Some(position) = state.last_position else {
+                    return false;
+                };
+                q.set(position);
+                true
+            }
+            QueryViewMut::Duration(q) if q.format() == gst::Format::Time => {
+                let state = self.state.lock().unwrap();
+                let Some(duration) = state.duration else {
+                    return false;
+                };
+                q.set(duration);
+                true
+            }
+            QueryViewMut::Seeking(q) if q.format() == gst::Format::Time => {
+                let state = self.state.lock().unwrap();
+                if state.streams.is_empty() {
+                    return false;
+                }
+                let duration = state.duration;
+                drop(state);
+
+                let seekable = if self.sinkpad.mode() == gst::PadMode::Pull {
+                    true
+                } else {
+                    let mut peer_query = gst::query::Seeking::new(gst::Format::Bytes);
+                    self.sinkpad.peer_query(&mut peer_query) && peer_query.result().0
+                };
+
+                q.set(seekable, gst::ClockTime::ZERO, duration);
+                true
+            }
+            _ => gst::Pad::query_default(pad, Some(&*self.obj()), query),
+        }
+    }
+}
+
+/// Creates the segment for `stream` when starting at `position` in the presentation.
+fn segment_for_stream(
+    stream: &Stream,
+    position: gst::ClockTime,
+    stop: Option<gst::ClockTime>,
+) -> gst::FormattedSegment<gst::ClockTime> {
+    let media_start = stream.media_start.unwrap_or(gst::ClockTime::ZERO);
+
+    let mut segment = gst::FormattedSegment::<gst::ClockTime>::new();
+    if position >= stream.empty_duration {
+        segment.set_start(stream.media_time(position));
+        segment.set_time(position);
+    } else {
+        // Running time of the media start has to include the remaining empty time
+        segment.set_start(media_start);
+        segment.set_time(stream.empty_duration);
+        segment.set_base(stream.empty_duration - position);
+    }
+    segment.set_position(segment.start());
+
+    let media_stop = stream.media_duration.map(|duration| media_start + duration);
+    let seek_stop = stop.map(|stop| stream.media_time(stop));
+    segment.set_stop(match (media_stop, seek_stop) {
+        (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+        (a, b) => a.or(b),
+    });
+
+    segment
+}
+
+fn apply_seek(state: &mut State, target: SeekTarget, seqnum: gst::Seqnum) {
+    match target.samples {
+        Some(samples) => {
+            state.pending_samples = samples;
+            state.resume_offset = None;
+            state.phase = Phase::Samples;
+        }
+        None => {
+            state.pending_samples.clear();
+            state.resume_offset = None;
+            state.offset = target.offset;
+            state.phase = Phase::BoxHeader;
+        }
+    }
+
+    state.seek_position = target.position;
+    state.segment_seqnum = seqnum;
+    state.last_position = None;
+    for stream in &mut state.streams {
+        stream.need_segment = true;
+        stream.discont = true;
+        stream.next_fragment_dts = 0;
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for MP4Demux {
+    const NAME: &'static str = "GstISOMP4Demux";
+    type Type = super::MP4Demux;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_from_template(&templ)
+            .activate_function(|pad, parent| {
+                MP4Demux::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::loggable_error!(CAT, "Panic activating sink pad")),
+                    |demux| demux.sink_activate(pad),
+                )
+            })
+            .activatemode_function(|pad, parent, mode, active| {
+                MP4Demux::catch_panic_pad_function(
+                    parent,
+                    || {
+                        Err(gst::loggable_error!(
+                            CAT,
+                            "Panic activating sink pad with mode"
+                        ))
+                    },
+                    |demux| demux.sink_activatemode(pad, mode, active),
+                )
+            })
+            .chain_function(|pad, parent, buffer| {
+                MP4Demux::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |demux| demux.sink_chain(pad, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                MP4Demux::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |demux| demux.sink_event(pad, event),
+                )
+            })
+            .build();
+
+        Self {
+            sinkpad,
+            state: Mutex::new(State::default()),
+            flow_combiner: Mutex::new(gst_base::UniqueFlowCombiner::new()),
+        }
+    }
+}
+
+impl ObjectImpl for MP4Demux {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        self.obj().add_pad(&self.sinkpad).unwrap();
+    }
+}
+
+impl GstObjectImpl for MP4Demux {}
+
+impl ElementImpl for MP4Demux {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "ISOMP4Demux",
+                "Codec/Demuxer",
+                "ISO MP4 demuxer",
+                "agent <agent@local>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &[
+                    gst::Structure::new_empty("video/quicktime"),
+                    gst::Structure::new_empty("audio/x-m4a"),
+                    gst::Structure::new_empty("application/x-3gp"),
+                ]
+                .into_iter()
+                .collect::<gst::Caps>(),
+            )
+            .unwrap();
+
+            let video_src_pad_template = gst::PadTemplate::new(
+                "video_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &gst::Caps::new_any(),
+            )
+            .unwrap();
+
+            let audio_src_pad_template = gst::PadTemplate::new(
+                "audio_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &gst::Caps::new_any(),
+            )
+            .unwrap();
+
+            let subtitle_src_pad_template = gst::PadTemplate::new(
+                "subtitle_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &gst::Caps::new_any(),
+            )
+            .unwrap();
+
+            let meta_src_pad_template = gst::PadTemplate::new(
+                "meta_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &gst::Caps::new_any(),
+            )
+            .unwrap();
+
+            vec![
+                sink_pad_template,
+                video_src_pad_template,
+                audio_src_pad_template,
+                subtitle_src_pad_template,
+                meta_src_pad_template,
+            ]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst::trace!(CAT, imp = self, "Changing state {:?}", transition);
+
+        let res = self.parent_change_state(transition)?;
+
+        if transition == gst::StateChange::PausedToReady {
+            let streams = std::mem::take(&mut self.state.lock().unwrap().streams);
+            let mut flow_combiner = self.flow_combiner.lock().unwrap();
+            for stream in streams {
+                flow_combiner.remove_pad(&stream.pad);
+                let _ = self.obj().remove_pad(&stream.pad);
+            }
+            flow_combiner.reset();
+        }
+
+        Ok(res)
+    }
+}
@@ -115,6 +115,30 @@ pub(super) fn create_mdat_header(size: Option<u64>) -> Result<gst::Buffer, Error
     Ok(gst::Buffer::from_mut_slice(v))
 }
 
+/// Creates `free` box *header* for a box of `size` bytes including the header.
+pub(super) fn create_free_header(size: u64) -> Result<gst::Buffer, Error> {
+    let size = u32::try_from(size).context("too big free box")?;
+    if size < 8 {
+        bail!("too small free box");
+    }
+
+    let mut v = vec![];
+    v.extend(size.to_be_bytes());
+    v.extend(b"free");
+
+    Ok(gst::Buffer::from_mut_slice(v))
+}
+
+/// Creates zero-filled `free` box of `size` bytes including the header.
+pub(super) fn create_free(size: u64) -> Result<gst::Buffer, Error> {
+    let header = create_free_header(size)?;
+
+    let mut v = vec![0u8; size as usize];
+    header.copy_to_slice(0, &mut v[..8]).unwrap();
+
+    Ok(gst::Buffer::from_mut_slice(v))
+}
+
 /// Offset between UNIX epoch and Jan 1 1601 epoch in seconds.
 /// 1601 = UNIX + UNIX_1601_OFFSET.
 const UNIX_1601_OFFSET: u64 = 11_644_473_600;
@@ -531,6 +555,10 @@ fn write_hdlr(
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
         | "audio/x-adpcm" => (b"soun", b"SoundHandler\0".as_slice()),
         "application/x-onvif-metadata" => (b"meta", b"MetadataHandler\0".as_slice()),
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" | "text/x-raw" => {
+            (b"text", b"TextHandler\0".as_slice())
+        }
+        "application/ttml+xml" => (b"subt", b"SubtitleHandler\0".as_slice()),
         _ => unreachable!(),
     };
 
@@ -565,11 +593,19 @@ fn write_minf(
                 write_smhd(v, header)
             })?
         }
-        "application/x-onvif-metadata" => {
+        "application/x-onvif-metadata"
+        | "application/x-subtitle-vtt"
+        | "application/x-subtitle-vtt-fragmented"
+        | "text/x-raw" => {
             write_full_box(v, b"nmhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
                 Ok(())
             })?
         }
+        "application/ttml+xml" => {
+            write_full_box(v, b"sthd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
+                Ok(())
+            })?
+        }
         _ => unreachable!(),
     }
 
@@ -720,6 +756,10 @@ fn write_stsd(
         "audio/mpeg" | "audio/x-opus" | "audio/x-flac" | "audio/x-alaw" | "audio/x-mulaw"
         | "audio/x-adpcm" => write_audio_sample_entry(v, header, stream)?,
         "application/x-onvif-metadata" => write_xml_meta_data_sample_entry(v, header, stream)?,
+        "application/x-subtitle-vtt"
+        | "application/x-subtitle-vtt-fragmented"
+        | "text/x-raw"
+        | "application/ttml+xml" => write_text_sample_entry(v, header, stream)?,
         _ => unreachable!(),
     }
 
@@ -1454,6 +1494,233 @@ fn write_xml_meta_data_sample_entry(
     Ok(())
 }
 
+fn write_text_sample_entry(
+    v: &mut Vec<u8>,
+    _header: &super::Header,
+    stream: &super::Stream,
+) -> Result<(), Error> {
+    let s = stream.caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+            write_sample_entry_box(v, b"wvtt", move |v| {
+                // WebVTT file header without any cues
+                write_box(v, b"vttC", |v| {
+                    v.extend(b"WEBVTT");
+                    Ok(())
+                })
+            })?;
+        }
+        "application/ttml+xml" => {
+            write_sample_entry_box(v, b"stpp", move |v| {
+                // namespace
+                v.extend(TTML_NAMESPACE.as_bytes());
+                v.push(0);
+
+                // schema_location, empty string
+                v.push(0);
+
+                // auxiliary_mime_types, empty string
+                v.push(0);
+
+                Ok(())
+            })?;
+        }
+        "text/x-raw" => {
+            write_sample_entry_box(v, b"tx3g", move |v| {
+                // Display flags
+                v.extend(0u32.to_be_bytes());
+
+                // Horizontal justification: center
+                v.push(1);
+                // Vertical justification: bottom
+                v.push(0xff);
+
+                // Background color RGBA
+                v.extend([0u8; 4]);
+
+                // Default text box: top, left, bottom, right
+                v.extend([0u8; 4 * 2]);
+
+                // Default style: start char, end char
+                v.extend(0u16.to_be_bytes());
+                v.extend(0u16.to_be_bytes());
+                // Font ID
+                v.extend(1u16.to_be_bytes());
+                // Face style flags
+                v.push(0);
+                // Font size
+                v.push(18);
+                // Text color RGBA
+                v.extend([0xffu8; 4]);
+
+                write_box(v, b"ftab", |v| {
+                    // Entry count
+                    v.extend(1u16.to_be_bytes());
+
+                    // Font ID
+                    v.extend(1u16.to_be_bytes());
+
+                    // Font name
+                    let name = b"Sans-Serif";
+                    v.push(name.len() as u8);
+                    v.extend(name);
+
+                    Ok(())
+                })
+            })?;
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+const TTML_NAMESPACE: &str = "http://www.w3.org/ns/ttml";
+
+/// Converts a buffer of a text stream into a sample for the stream's sample entry.
+///
+/// Gap buffers are converted into empty samples. Returns `None` if the buffer contains nothing
+/// that has to be stored, e.g. only a WebVTT header.
+pub(super) fn create_text_sample(
+    caps: &gst::CapsRef,
+    buffer: &gst::BufferRef,
+) -> Result<Option<gst::Buffer>, Error> {
+    let data = if buffer.flags().contains(gst::BufferFlags::GAP) && buffer.size() == 0 {
+        Some(empty_text_sample(caps))
+    } else {
+        let map = buffer.map_readable().context("buffer not mappable")?;
+        let text = std::str::from_utf8(&map).context("text not valid UTF-8")?;
+
+        let s = caps.structure(0).unwrap();
+        match s.name().as_str() {
+            "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+                create_wvtt_sample(text)?
+            }
+            "text/x-raw" => {
+                let text = text.trim_end_matches(['\n', '\0']);
+                if text.is_empty() {
+                    None
+                } else {
+                    let len = u16::try_from(text.len()).context("too long text")?;
+                    let mut v = Vec::with_capacity(2 + text.len());
+                    v.extend(len.to_be_bytes());
+                    v.extend(text.as_bytes());
+                    Some(v)
+                }
+            }
+            "application/ttml+xml" => {
+                if text.trim().is_empty() {
+                    None
+                } else {
+                    Some(map.to_vec())
+                }
+            }
+            _ => unreachable!(),
+        }
+    };
+
+    Ok(data.map(|data| {
+        let mut sample = gst::Buffer::from_mut_slice(data);
+        {
+            let sample = sample.get_mut().unwrap();
+            sample.set_pts(buffer.pts());
+            sample.set_dts(buffer.dts());
+            sample.set_duration(buffer.duration());
+        }
+        sample
+    }))
+}
+
+/// Creates an empty sample for a text stream, to be used for the time between cues.
+pub(super) fn create_empty_text_sample(caps: &gst::CapsRef) -> gst::Buffer {
+    gst::Buffer::from_mut_slice(empty_text_sample(caps))
+}
+
+fn empty_text_sample(caps: &gst::CapsRef) -> Vec<u8> {
+    let s = caps.structure(0).unwrap();
+    match s.name().as_str() {
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+            let mut v = vec![];
+            write_box(&mut v, b"vtte", |_v| Ok(())).unwrap();
+            v
+        }
+        "application/ttml+xml" => format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tt xmlns=\"{TTML_NAMESPACE}\"><body/></tt>\n"
+        )
+        .into_bytes(),
+        "text/x-raw" => 0u16.to_be_bytes().to_vec(),
+        _ => unreachable!(),
+    }
+}
+
+/// Converts WebVTT cues into `vttc` boxes as defined by ISO/IEC 14496-30.
+fn create_wvtt_sample(text: &str) -> Result<Option<Vec<u8>>, Error> {
+    let text = text.replace("\r\n", "\n");
+
+    let mut v = vec![];
+    for block in text.split("\n\n") {
+        let block = block.trim_matches('\n');
+        let mut lines = block.lines();
+        let Some(first_line) = lines.next() else {
+            continue;
+        };
+
+        // Skip file header, comments and any other blocks that are not cues
+        if ["WEBVTT", "NOTE", "STYLE", "REGION"]
+            .iter()
+            .any(|prefix| first_line.starts_with(prefix))
+        {
+            continue;
+        }
+
+        let (id, timings) = if first_line.contains("-->") {
+            (None, first_line)
+        } else {
+            match lines.next() {
+                Some(line) if line.contains("-->") => (Some(first_line), line),
+                _ => bail!("invalid WebVTT cue"),
+            }
+        };
+
+        // Everything after the end time are the cue settings
+        let (_, end) = timings.split_once("-->").unwrap();
+        let settings = end
+            .trim()
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .map(|(_, settings)| settings.trim())
+            .unwrap_or("");
+
+        let payload = lines.collect::<Vec<_>>().join("\n");
+
+        write_box(&mut v, b"vttc", |v| {
+            if let Some(id) = id {
+                write_box(v, b"iden", |v| {
+                    v.extend(id.as_bytes());
+                    Ok(())
+                })?;
+            }
+
+            if !settings.is_empty() {
+                write_box(v, b"sttg", |v| {
+                    v.extend(settings.as_bytes());
+                    Ok(())
+                })?;
+            }
+
+            write_box(v, b"payl", |v| {
+                v.extend(payload.as_bytes());
+                Ok(())
+            })
+        })?;
+    }
+
+    if v.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(v))
+    }
+}
+
 fn write_stts(
     v: &mut Vec<u8>,
     _header: &super::Header,
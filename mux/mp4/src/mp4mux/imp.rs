@@ -71,12 +71,20 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
 
 const DEFAULT_INTERLEAVE_BYTES: Option<u64> = None;
 const DEFAULT_INTERLEAVE_TIME: Option<gst::ClockTime> = Some(gst::ClockTime::from_mseconds(500));
+const DEFAULT_FASTSTART: bool = false;
+const DEFAULT_FASTSTART_RESERVED_SIZE: u64 = 0;
+const DEFAULT_RESERVED_MAX_DURATION: Option<gst::ClockTime> = None;
+const DEFAULT_RESERVED_MOOV_UPDATE_PERIOD: Option<gst::ClockTime> = Some(gst::ClockTime::SECOND);
 
 #[derive(Debug, Clone)]
 struct Settings {
     interleave_bytes: Option<u64>,
     interleave_time: Option<gst::ClockTime>,
     movie_timescale: u32,
+    faststart: bool,
+    faststart_reserved_size: u64,
+    reserved_max_duration: Option<gst::ClockTime>,
+    reserved_moov_update_period: Option<gst::ClockTime>,
 }
 
 impl Default for Settings {
@@ -85,6 +93,10 @@ impl Default for Settings {
             interleave_bytes: DEFAULT_INTERLEAVE_BYTES,
             interleave_time: DEFAULT_INTERLEAVE_TIME,
             movie_timescale: 0,
+            faststart: DEFAULT_FASTSTART,
+            faststart_reserved_size: DEFAULT_FASTSTART_RESERVED_SIZE,
+            reserved_max_duration: DEFAULT_RESERVED_MAX_DURATION,
+            reserved_moov_update_period: DEFAULT_RESERVED_MOOV_UPDATE_PERIOD,
         }
     }
 }
@@ -111,6 +123,8 @@ struct Stream {
     delta_frames: super::DeltaFrames,
     /// Whether this stream might have header frames without timestamps that should be ignored.
     discard_header_buffers: bool,
+    /// Whether this is a text stream and buffers have to be converted into samples.
+    text: bool,
 
     /// Already written out chunks with their samples for this stream
     chunks: Vec<super::Chunk>,
@@ -157,6 +171,15 @@ struct State {
     /// Size of the `mdat` as written so far.
     mdat_size: u64,
 
+    /// Offset and size of the space reserved for the `moov` box before the `mdat` box.
+    reserved_moov: Option<(u64, u64)>,
+
+    /// End PTS of the samples in the last `moov` box written into the reserved space.
+    last_checkpoint: Option<gst::ClockTime>,
+
+    /// Set if the reserved space became too small for updating the `moov` box.
+    checkpoints_stopped: bool,
+
     /// Language code from tags
     language_code: Option<[u8; 3]>,
 }
@@ -202,6 +225,7 @@ impl MP4Mux {
         sinkpad: &super::MP4MuxPad,
         delta_frames: super::DeltaFrames,
         discard_headers: bool,
+        text_caps: Option<&gst::CapsRef>,
         pre_queue: &mut VecDeque<(gst::FormattedSegment<gst::ClockTime>, gst::Buffer)>,
         running_time_utc_time_mapping: &Option<(gst::Signed<gst::ClockTime>, gst::ClockTime)>,
     ) -> Result<Option<(gst::FormattedSegment<gst::ClockTime>, gst::Buffer)>, gst::FlowError> {
@@ -209,8 +233,32 @@ impl MP4Mux {
             return Ok(Some((segment.clone(), buffer.clone())));
         }
 
-        let Some(mut buffer) = sinkpad.peek_buffer() else {
-            return Ok(None);
+        let mut converted = false;
+        let mut buffer = loop {
+            let Some(buffer) = sinkpad.peek_buffer() else {
+                return Ok(None);
+            };
+
+            let Some(caps) = text_caps else {
+                break buffer;
+            };
+
+            // Convert text buffers into samples right away and fill gaps with empty samples.
+            // The converted sample is put into the pre-queue below so this only happens once.
+            match boxes::create_text_sample(caps, &buffer) {
+                Ok(Some(sample)) => {
+                    converted = true;
+                    break sample;
+                }
+                Ok(None) => {
+                    gst::trace!(CAT, obj = sinkpad, "Dropping buffer without cues");
+                    sinkpad.drop_buffer();
+                }
+                Err(err) => {
+                    gst::error!(CAT, obj = sinkpad, "Invalid text buffer: {}", err);
+                    return Err(gst::FlowError::Error);
+                }
+            }
         };
         Self::check_buffer(&buffer, sinkpad, delta_frames, discard_headers)?;
         let mut segment = match sinkpad.segment().downcast::<gst::ClockTime>().ok() {
@@ -280,6 +328,10 @@ impl MP4Mux {
             // Drop current buffer as it is now queued
             sinkpad.drop_buffer();
             pre_queue.push_back((segment.clone(), buffer.clone()));
+        } else if converted {
+            // Drop current buffer as the converted sample is now queued
+            sinkpad.drop_buffer();
+            pre_queue.push_back((segment.clone(), buffer.clone()));
         }
 
         Ok(Some((segment, buffer)))
@@ -404,6 +456,18 @@ impl MP4Mux {
             // Fall through below and pop the first buffer finally
         }
 
+        // Text buffers are only ever taken from the pre-queue after conversion
+        if stream.text && stream.pre_queue.is_empty() {
+            self.peek_buffer(
+                &stream.sinkpad,
+                stream.delta_frames,
+                stream.discard_header_buffers,
+                Some(&stream.caps),
+                &mut stream.pre_queue,
+                &stream.running_time_utc_time_mapping,
+            )?;
+        }
+
         if let Some((segment, buffer)) = stream.pre_queue.pop_front() {
             return Ok(Some((segment, buffer)));
         }
@@ -475,6 +539,7 @@ impl MP4Mux {
                         &stream.sinkpad,
                         stream.delta_frames,
                         stream.discard_header_buffers,
+                        stream.text.then(|| stream.caps.as_ref()),
                         &mut stream.pre_queue,
                         &stream.running_time_utc_time_mapping,
                     )?;
@@ -886,34 +951,53 @@ impl MP4Mux {
 
             let duration = buffer.duration.unwrap();
             let composition_time_offset = buffer.composition_time_offset;
-            let mut buffer = buffer.buffer;
-
-            stream.queued_chunk_time += duration;
-            stream.queued_chunk_bytes += buffer.size() as u64;
-
-            stream
-                .chunks
-                .last_mut()
-                .unwrap()
-                .samples
-                .push(super::Sample {
-                    sync_point: !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT),
-                    duration,
-                    composition_time_offset,
-                    size: buffer.size() as u32,
-                });
+            let buffer = buffer.buffer;
+
+            // Text samples must not be displayed longer than their own duration, so fill the
+            // remaining time until the next sample with an empty sample.
+            let cue_duration = buffer
+                .duration()
+                .filter(|cue_duration| stream.text && *cue_duration < duration);
+            let samples = if let Some(cue_duration) = cue_duration {
+                vec![
+                    (buffer, cue_duration),
+                    (
+                        boxes::create_empty_text_sample(&stream.caps),
+                        duration - cue_duration,
+                    ),
+                ]
+            } else {
+                vec![(buffer, duration)]
+            };
 
-            {
-                let buffer = buffer.make_mut();
-                buffer.set_dts(None);
-                buffer.set_pts(None);
-                buffer.set_duration(duration);
-                buffer.unset_flags(gst::BufferFlags::all());
-            }
+            for (mut buffer, duration) in samples {
+                stream.queued_chunk_time += duration;
+                stream.queued_chunk_bytes += buffer.size() as u64;
+
+                stream
+                    .chunks
+                    .last_mut()
+                    .unwrap()
+                    .samples
+                    .push(super::Sample {
+                        sync_point: !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT),
+                        duration,
+                        composition_time_offset,
+                        size: buffer.size() as u32,
+                    });
+
+                {
+                    let buffer = buffer.make_mut();
+                    buffer.set_dts(None);
+                    buffer.set_pts(None);
+                    buffer.set_duration(duration);
+                    buffer.unset_flags(gst::BufferFlags::all());
+                }
 
-            state.current_offset += buffer.size() as u64;
-            state.mdat_size += buffer.size() as u64;
-            buffers.add(buffer);
+                state.current_offset += buffer.size() as u64;
+                state.mdat_size += buffer.size() as u64;
+                buffers.add(buffer);
+            }
         }
 
         Ok(())
@@ -942,6 +1026,7 @@ impl MP4Mux {
 
             let mut delta_frames = super::DeltaFrames::IntraOnly;
             let mut discard_header_buffers = false;
+            let mut text = false;
             match s.name().as_str() {
                 "video/x-h264" | "video/x-h265" => {
                     if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
@@ -1000,6 +1085,12 @@ impl MP4Mux {
                 "audio/x-alaw" | "audio/x-mulaw" => (),
                 "audio/x-adpcm" => (),
                 "application/x-onvif-metadata" => (),
+                "application/x-subtitle-vtt"
+                | "application/x-subtitle-vtt-fragmented"
+                | "application/ttml+xml"
+                | "text/x-raw" => {
+                    text = true;
+                }
                 _ => unreachable!(),
             }
 
@@ -1009,6 +1100,7 @@ impl MP4Mux {
                 caps,
                 delta_frames,
                 discard_header_buffers,
+                text,
                 chunks: Vec::new(),
                 pending_buffer: None,
                 queued_chunk_time: gst::ClockTime::ZERO,
@@ -1026,7 +1118,8 @@ impl MP4Mux {
             return Err(gst::FlowError::Error);
         }
 
-        // Sort video streams first and then audio streams and then metadata streams, and each group by pad name.
+        // Sort video streams first and then audio streams, metadata streams and text streams, and
+        // each group by pad name.
         state.streams.sort_by(|a, b| {
             let order_of_caps = |caps: &gst::CapsRef| {
                 let s = caps.structure(0).unwrap();
@@ -1037,6 +1130,11 @@ impl MP4Mux {
                     1
                 } else if s.name().starts_with("application/x-onvif-metadata") {
                     2
+                } else if s.name().starts_with("application/x-subtitle-vtt")
+                    || s.name() == "application/ttml+xml"
+                    || s.name() == "text/x-raw"
+                {
+                    3
                 } else {
                     unimplemented!();
                 }
@@ -1054,6 +1152,166 @@ impl MP4Mux {
 
         Ok(())
     }
+
+    /// Estimates the size of the `moov` box based on the upstream duration.
+    fn estimate_moov_size(&self, settings: &Settings, state: &State) -> Option<u64> {
+        let duration = state
+            .streams
+            .iter()
+            .filter_map(|stream| stream.sinkpad.peer_query_duration::<gst::ClockTime>())
+            .max()?;
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Estimating moov box size for duration {duration}"
+        );
+
+        Some(self.estimate_moov_size_for_duration(settings, state, duration))
+    }
+
+    /// Estimates the size of the `moov` box for `duration` of media.
+    fn estimate_moov_size_for_duration(
+        &self,
+        settings: &Settings,
+        state: &State,
+        duration: gst::ClockTime,
+    ) -> u64 {
+        // Chunks per second, assuming at least one chunk per second per stream
+        let chunk_rate = settings
+            .interleave_time
+            .map(|interleave_time| {
+                std::cmp::max(
+                    gst::ClockTime::SECOND.nseconds() / interleave_time.nseconds(),
+                    1,
+                )
+            })
+            .unwrap_or(1);
+
+        // mvhd and other fixed size boxes
+        let mut size = 1024;
+        for stream in &state.streams {
+            let s = stream.caps.structure(0).unwrap();
+
+            let sample_rate = if s.name().starts_with("video/") || s.name().starts_with("image/") {
+                match s.get::<gst::Fraction>("framerate") {
+                    Ok(framerate) if framerate.numer() > 0 && framerate.denom() > 0 => {
+                        (framerate.numer() as u64 + framerate.denom() as u64 - 1)
+                            / framerate.denom() as u64
+                    }
+                    _ => 60,
+                }
+            } else if s.name().starts_with("audio/") {
+                // Compressed audio frames and raw audio buffers are usually 20ms or longer
+                50
+            } else {
+                10
+            };
+
+            // stsz and stts entries, plus ctts and stss entries if needed
+            let mut bytes_per_sample = 4 + 8;
+            if stream.delta_frames.requires_dts() {
+                bytes_per_sample += 8;
+            }
+            if !stream.delta_frames.intra_only() {
+                bytes_per_sample += 4;
+            }
+
+            // co64 and stsc entries
+            let bytes_per_chunk = 8 + 12;
+
+            // Fixed size boxes of the trak and the sample entry with codec specific data
+            let codec_data_size = s
+                .get::<gst::Buffer>("codec_data")
+                .map(|codec_data| codec_data.size() as u64)
+                .unwrap_or(0)
+                + s.get::<gst::ArrayRef>("streamheader")
+                    .map(|streamheader| {
+                        streamheader
+                            .iter()
+                            .filter_map(|v| v.get::<gst::Buffer>().ok())
+                            .map(|buffer| buffer.size() as u64)
+                            .sum::<u64>()
+                    })
+                    .unwrap_or(0);
+
+            let seconds = duration.seconds() + 1;
+            size += 1024
+                + codec_data_size
+                + seconds * (sample_rate * bytes_per_sample + chunk_rate * bytes_per_chunk);
+        }
+
+        // Add some headroom for estimation errors
+        size + size / 10
+    }
+
+    /// Creates a `moov` box for all samples that were written so far.
+    fn create_moov(
+        &self,
+        settings: &Settings,
+        state: &State,
+    ) -> Result<gst::Buffer, gst::FlowError> {
+        let mut streams = Vec::with_capacity(state.streams.len());
+        for stream in &state.streams {
+            let pad_settings = stream.sinkpad.imp().settings.lock().unwrap().clone();
+            let (earliest_pts, end_pts) = match Option::zip(stream.earliest_pts, stream.end_pts) {
+                Some(res) => res,
+                None => continue, // empty stream
+            };
+
+            streams.push(super::Stream {
+                caps: stream.caps.clone(),
+                delta_frames: stream.delta_frames,
+                trak_timescale: pad_settings.trak_timescale,
+                start_dts: stream.start_dts,
+                earliest_pts,
+                end_pts,
+                chunks: stream.chunks.clone(),
+                extra_header_data: stream.extra_header_data.clone(),
+            });
+        }
+
+        boxes::create_moov(super::Header {
+            variant: self.obj().class().as_ref().variant,
+            movie_timescale: settings.movie_timescale,
+            streams,
+            language_code: state.language_code,
+        })
+        .map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to create moov box: {err}");
+            gst::FlowError::Error
+        })
+    }
+
+    /// Creates the buffers for filling the reserved space of `size` bytes with `moov`, or `None`
+    /// if it does not fit.
+    fn reserved_space_buffers(
+        &self,
+        size: u64,
+        moov: &gst::Buffer,
+    ) -> Result<Option<gst::BufferList>, gst::FlowError> {
+        let moov_size = moov.size() as u64;
+
+        // A remaining gap has to be big enough for a free box header
+        if moov_size != size && moov_size + 8 > size {
+            return Ok(None);
+        }
+
+        let mut buffers = gst::BufferList::new();
+        {
+            let buffers = buffers.get_mut().unwrap();
+            buffers.add(moov.clone());
+            if moov_size < size {
+                let free = boxes::create_free_header(size - moov_size).map_err(|err| {
+                    gst::error!(CAT, imp = self, "Failed to create free box header: {err}");
+                    gst::FlowError::Error
+                })?;
+                buffers.add(free);
+            }
+        }
+
+        Ok(Some(buffers))
+    }
 }
 
 #[glib::object_subclass]
@@ -1089,6 +1347,38 @@ impl ObjectImpl for MP4Mux {
                     .blurb("Timescale to use for the movie (units per second, 0 is automatic)")
                     .mutable_ready()
                     .build(),
+                glib::ParamSpecBoolean::builder("faststart")
+                    .nick("Fast Start")
+                    .blurb("Reserve space for the moov box before the mdat box and write it there if it fits")
+                    .default_value(DEFAULT_FASTSTART)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("faststart-reserved-size")
+                    .nick("Fast Start Reserved Size")
+                    .blurb("Bytes to reserve for the moov box in fast start mode (0 = estimate from the upstream duration)")
+                    .default_value(DEFAULT_FASTSTART_RESERVED_SIZE)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("reserved-max-duration")
+                    .nick("Reserved Max Duration")
+                    .blurb("Reserve space after the ftyp box for a moov box of up to this duration and periodically write a moov checkpoint into it (in nanoseconds, NONE = disabled)")
+                    .default_value(
+                        DEFAULT_RESERVED_MAX_DURATION
+                            .map(gst::ClockTime::nseconds)
+                            .unwrap_or(u64::MAX),
+                    )
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("reserved-moov-update-period")
+                    .nick("Reserved moov Update Period")
+                    .blurb("Interval at which the moov checkpoint in the reserved space is updated (in nanoseconds, NONE = only at the end)")
+                    .default_value(
+                        DEFAULT_RESERVED_MOOV_UPDATE_PERIOD
+                            .map(gst::ClockTime::nseconds)
+                            .unwrap_or(u64::MAX),
+                    )
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -1118,6 +1408,33 @@ impl ObjectImpl for MP4Mux {
                 settings.movie_timescale = value.get().expect("type checked upstream");
             }
 
+            "faststart" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.faststart = value.get().expect("type checked upstream");
+            }
+
+            "faststart-reserved-size" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.faststart_reserved_size = value.get().expect("type checked upstream");
+            }
+
+            "reserved-max-duration" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.reserved_max_duration = match value.get().expect("type checked upstream") {
+                    Some(gst::ClockTime::ZERO) | None => None,
+                    v => v,
+                };
+            }
+
+            "reserved-moov-update-period" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.reserved_moov_update_period =
+                    match value.get().expect("type checked upstream") {
+                        Some(gst::ClockTime::ZERO) | None => None,
+                        v => v,
+                    };
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -1139,6 +1456,26 @@ impl ObjectImpl for MP4Mux {
                 settings.movie_timescale.to_value()
             }
 
+            "faststart" => {
+                let settings = self.settings.lock().unwrap();
+                settings.faststart.to_value()
+            }
+
+            "faststart-reserved-size" => {
+                let settings = self.settings.lock().unwrap();
+                settings.faststart_reserved_size.to_value()
+            }
+
+            "reserved-max-duration" => {
+                let settings = self.settings.lock().unwrap();
+                settings.reserved_max_duration.to_value()
+            }
+
+            "reserved-moov-update-period" => {
+                let settings = self.settings.lock().unwrap();
+                settings.reserved_moov_update_period.to_value()
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -1402,6 +1739,44 @@ impl AggregatorImpl for MP4Mux {
             state.current_offset += ftyp.size() as u64;
             buffers.get_mut().unwrap().add(ftyp);
 
+            let reserved_size = if let Some(reserved_max_duration) = settings.reserved_max_duration
+            {
+                Some(self.estimate_moov_size_for_duration(&settings, &state, reserved_max_duration))
+            } else if settings.faststart {
+                if settings.faststart_reserved_size > 0 {
+                    Some(settings.faststart_reserved_size)
+                } else {
+                    let size = self.estimate_moov_size(&settings, &state);
+                    if size.is_none() {
+                        gst::warning!(
+                            CAT,
+                            imp = self,
+                            "Can't estimate moov box size, writing it at the end"
+                        );
+                    }
+                    size
+                }
+            } else {
+                None
+            };
+
+            if let Some(reserved_size) = reserved_size {
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "Reserving {reserved_size} bytes for moov box at offset {}",
+                    state.current_offset
+                );
+
+                let free = boxes::create_free(reserved_size).map_err(|err| {
+                    gst::error!(CAT, imp = self, "Failed to create free box: {err}");
+                    gst::FlowError::Error
+                })?;
+                state.reserved_moov = Some((state.current_offset, reserved_size));
+                state.current_offset += reserved_size;
+                buffers.get_mut().unwrap().add(free);
+            }
+
             gst::info!(
                 CAT,
                 imp = self,
@@ -1426,8 +1801,12 @@ impl AggregatorImpl for MP4Mux {
             Err(err) => return Err(err),
         };
 
+        // Buffers to write into the reserved space after all other buffers, and the offset to
+        // continue writing at afterwards
+        let mut reserved_buffers = None;
         if res == Err(gst::FlowError::Eos) {
-            // Create moov box now and append it to the buffers
+            // Create moov box now and append it to the buffers, or write it into the reserved
+            // space later
 
             gst::info!(
                 CAT,
@@ -1437,39 +1816,87 @@ impl AggregatorImpl for MP4Mux {
                 state.mdat_size
             );
 
-            let mut streams = Vec::with_capacity(state.streams.len());
-            for stream in state.streams.drain(..) {
-                let pad_settings = stream.sinkpad.imp().settings.lock().unwrap().clone();
-                let (earliest_pts, end_pts) = match Option::zip(stream.earliest_pts, stream.end_pts)
-                {
-                    Some(res) => res,
-                    None => continue, // empty stream
-                };
+            let moov = self.create_moov(&settings, &state)?;
+            state.streams.clear();
 
-                streams.push(super::Stream {
-                    caps: stream.caps.clone(),
-                    delta_frames: stream.delta_frames,
-                    trak_timescale: pad_settings.trak_timescale,
-                    start_dts: stream.start_dts,
-                    earliest_pts,
-                    end_pts,
-                    chunks: stream.chunks,
-                    extra_header_data: stream.extra_header_data.clone(),
-                });
+            let mut append_moov = true;
+            if let Some((offset, size)) = state.reserved_moov {
+                if let Some(buffers) = self.reserved_space_buffers(size, &moov)? {
+                    reserved_buffers = Some((offset, buffers, None));
+                    append_moov = false;
+                } else {
+                    gst::element_imp_warning!(
+                        self,
+                        gst::StreamError::Mux,
+                        [
+                            "Reserved {size} bytes are too small for the moov box of {} bytes, writing it at the end",
+                            moov.size()
+                        ]
+                    );
+
+                    // Turn the outdated moov box of the last checkpoint into free space
+                    if state.last_checkpoint.is_some() {
+                        let free = boxes::create_free_header(size).map_err(|err| {
+                            gst::error!(CAT, imp = self, "Failed to create free box header: {err}");
+                            gst::FlowError::Error
+                        })?;
+                        let mut buffers = gst::BufferList::new();
+                        buffers.get_mut().unwrap().add(free);
+                        reserved_buffers = Some((offset, buffers, None));
+                    }
+                }
             }
 
-            let moov = boxes::create_moov(super::Header {
-                variant: self.obj().class().as_ref().variant,
-                movie_timescale: settings.movie_timescale,
-                streams,
-                language_code: state.language_code,
-            })
-            .map_err(|err| {
-                gst::error!(CAT, imp = self, "Failed to create moov box: {err}");
-                gst::FlowError::Error
-            })?;
-            state.current_offset += moov.size() as u64;
-            buffers.get_mut().unwrap().add(moov);
+            if append_moov {
+                state.current_offset += moov.size() as u64;
+                buffers.get_mut().unwrap().add(moov);
+            }
+        } else if let Some((offset, size)) = state.reserved_moov {
+            // Checkpoint the moov box regularly into the reserved space
+            let position = state.streams.iter().filter_map(|s| s.end_pts).max();
+            let checkpoint_due = match (
+                position,
+                state.last_checkpoint,
+                settings.reserved_moov_update_period,
+            ) {
+                (Some(position), Some(last_checkpoint), Some(period)) => {
+                    position >= last_checkpoint + period
+                }
+                (Some(position), None, Some(period)) => {
+                    let start = state.streams.iter().filter_map(|s| s.earliest_pts).min();
+                    start.map_or(false, |start| position >= start + period)
+                }
+                _ => false,
+            };
+
+            if checkpoint_due
+                && settings.reserved_max_duration.is_some()
+                && !state.checkpoints_stopped
+            {
+                let moov = self.create_moov(&settings, &state)?;
+                if let Some(buffers) = self.reserved_space_buffers(size, &moov)? {
+                    gst::debug!(
+                        CAT,
+                        imp = self,
+                        "Checkpointing moov box of size {} at position {}",
+                        moov.size(),
+                        position.display(),
+                    );
+                    state.last_checkpoint = position;
+                    reserved_buffers = Some((offset, buffers, Some(state.current_offset)));
+                } else {
+                    gst::element_imp_warning!(
+                        self,
+                        gst::StreamError::Mux,
+                        [
+                            "Reserved {size} bytes are too small for the moov box of {} bytes, not updating it anymore",
+                            moov.size()
+                        ]
+                    );
+                    // Keep the last checkpoint but don't try again
+                    state.checkpoints_stopped = true;
+                }
+            }
         }
 
         drop(state);
@@ -1515,6 +1942,33 @@ impl AggregatorImpl for MP4Mux {
             }
         }
 
+        if let Some((offset, buffers, continue_offset)) = reserved_buffers {
+            gst::info!(
+                CAT,
+                imp = self,
+                "Writing {} bytes into reserved space at offset {offset}",
+                buffers.calculate_size(),
+            );
+
+            let mut segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+            segment.set_start(gst::format::Bytes::from_u64(offset));
+            self.obj().update_segment(&segment);
+            if let Err(err) = self.obj().finish_buffer_list(buffers) {
+                gst::error!(
+                    CAT,
+                    imp = self,
+                    "Failed pushing buffers into reserved space downstream: {err:?}",
+                );
+                return Err(err);
+            }
+
+            if let Some(continue_offset) = continue_offset {
+                let mut segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+                segment.set_start(gst::format::Bytes::from_u64(continue_offset));
+                self.obj().update_segment(&segment);
+            }
+        }
+
         res
     }
 }
@@ -1648,6 +2102,12 @@ impl ElementImpl for ISOMP4Mux {
                         .field("channels", gst::IntRange::<i32>::new(1, 8))
                         .field("rate", gst::IntRange::<i32>::new(1, 10 * u16::MAX as i32))
                         .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/x-subtitle-vtt-fragmented").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
+                    gst::Structure::builder("text/x-raw")
+                        .field("format", "utf8")
+                        .build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
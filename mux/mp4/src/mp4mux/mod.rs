@@ -72,7 +72,7 @@ impl DeltaFrames {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct Sample {
     /// Sync point
     sync_point: bool,
@@ -89,7 +89,7 @@ pub(crate) struct Sample {
     size: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct Chunk {
     /// Chunk start offset
     offset: u64,
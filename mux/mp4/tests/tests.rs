@@ -85,6 +85,26 @@ fn test_basic_with(video_enc: &str, audio_enc: &str, cb: impl FnOnce(&Path)) {
     cb(&location)
 }
 
+/// Returns the fourccs of all top-level boxes of the file.
+fn top_level_boxes(location: &Path) -> Vec<[u8; 4]> {
+    let data = std::fs::read(location).unwrap();
+
+    let mut boxes = vec![];
+    let mut data = &data[..];
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as u64;
+        let size = match size {
+            0 => data.len() as u64,
+            1 => u64::from_be_bytes(data[8..16].try_into().unwrap()),
+            size => size,
+        };
+        boxes.push(data[4..8].try_into().unwrap());
+        data = &data[size as usize..];
+    }
+
+    boxes
+}
+
 #[test]
 fn test_basic_x264_aac() {
     init();
@@ -172,3 +192,353 @@ fn test_roundtrip_av1_aac() {
         pipeline.into_completion();
     })
 }
+
+#[test]
+fn test_roundtrip_demux_vp9_flac() {
+    init();
+    test_basic_with("vp9enc ! vp9parse", "flacenc ! flacparse", |location| {
+        let Ok(pipeline) = gst::parse::launch(
+            "filesrc name=src ! isomp4demux name=demux \
+             demux.audio_0 ! queue ! flacdec ! fakesink \
+             demux.video_0 ! queue ! vp9dec ! fakesink",
+        ) else {
+            panic!("could not build decoding pipeline")
+        };
+        let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+        pipeline
+            .by_name("src")
+            .unwrap()
+            .set_property("location", location.display().to_string());
+        pipeline.into_completion();
+    })
+}
+
+#[test]
+fn test_roundtrip_demux_push_av1_aac() {
+    init();
+    test_basic_with("av1enc ! av1parse", "avenc_aac ! aacparse", |location| {
+        // The queue before the demuxer forces push mode
+        let Ok(pipeline) = gst::parse::launch(
+            "filesrc name=src ! queue ! isomp4demux name=demux \
+             demux.audio_0 ! queue ! avdec_aac ! fakesink \
+             demux.video_0 ! queue ! av1dec ! fakesink",
+        ) else {
+            panic!("could not build decoding pipeline")
+        };
+        let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+        pipeline
+            .by_name("src")
+            .unwrap()
+            .set_property("location", location.display().to_string());
+        pipeline.into_completion();
+    })
+}
+
+#[test]
+fn test_demux_seek() {
+    init();
+    test_basic_with("vp9enc ! vp9parse", "flacenc ! flacparse", |location| {
+        let Ok(pipeline) = gst::parse::launch(
+            "filesrc name=src ! isomp4demux name=demux \
+             demux.audio_0 ! queue ! fakesink name=audiosink \
+             demux.video_0 ! queue ! fakesink name=videosink",
+        ) else {
+            panic!("could not build decoding pipeline")
+        };
+        let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+        pipeline
+            .by_name("src")
+            .unwrap()
+            .set_property("location", location.display().to_string());
+
+        pipeline.set_state(gst::State::Paused).unwrap();
+        let (res, _, _) = pipeline.state(gst::ClockTime::NONE);
+        res.unwrap();
+
+        assert_eq!(
+            pipeline.query_duration::<gst::ClockTime>(),
+            Some(gst::ClockTime::from_mseconds(3_300))
+        );
+
+        let first_pts = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let sinkpad = pipeline
+            .by_name("audiosink")
+            .unwrap()
+            .static_pad("sink")
+            .unwrap();
+        sinkpad.add_probe(gst::PadProbeType::BUFFER, {
+            let first_pts = first_pts.clone();
+            move |_pad, info| {
+                let mut first_pts = first_pts.lock().unwrap();
+                if first_pts.is_none() {
+                    *first_pts = info.buffer().unwrap().pts();
+                }
+                gst::PadProbeReturn::Ok
+            }
+        });
+
+        pipeline
+            .seek_simple(
+                gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+                gst::ClockTime::from_seconds(2),
+            )
+            .unwrap();
+
+        pipeline.into_completion();
+
+        // Every FLAC frame is a sync point so the first audio buffer is right before the seek
+        // position
+        let first_pts = first_pts.lock().unwrap().expect("No audio buffer");
+        assert!(first_pts > gst::ClockTime::from_mseconds(1_900));
+        assert!(first_pts <= gst::ClockTime::from_seconds(2));
+    })
+}
+
+fn test_faststart_with(reserved_size: u64, cb: impl FnOnce(&Path)) {
+    let Ok(pipeline) = gst::parse::launch(&format!(
+        "videotestsrc num-buffers=99 ! vp9enc ! vp9parse ! mux. \
+         audiotestsrc num-buffers=140 ! flacenc ! flacparse ! mux. \
+         isomp4mux name=mux faststart=true faststart-reserved-size={reserved_size} ! \
+         filesink name=sink"
+    )) else {
+        println!("could not build encoding pipeline");
+        return;
+    };
+    let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut location = dir.path().to_owned();
+    location.push("test.mp4");
+
+    let sink = pipeline.by_name("sink").unwrap();
+    sink.set_property("location", location.to_str().expect("Non-UTF8 filename"));
+    pipeline.into_completion();
+
+    cb(&location)
+}
+
+#[test]
+fn test_faststart() {
+    init();
+    test_faststart_with(65_536, |location| {
+        assert_eq!(
+            top_level_boxes(location),
+            [*b"ftyp", *b"moov", *b"free", *b"free", *b"mdat"]
+        );
+
+        let Ok(pipeline) = gst::parse::launch(
+            "filesrc name=src ! isomp4demux name=demux \
+             demux.audio_0 ! queue ! flacdec ! fakesink \
+             demux.video_0 ! queue ! vp9dec ! fakesink",
+        ) else {
+            panic!("could not build decoding pipeline")
+        };
+        let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+        pipeline
+            .by_name("src")
+            .unwrap()
+            .set_property("location", location.display().to_string());
+        pipeline.into_completion();
+    })
+}
+
+#[test]
+fn test_faststart_reserved_size_too_small() {
+    init();
+    test_faststart_with(128, |location| {
+        // The moov box does not fit and is written at the end instead
+        assert_eq!(
+            top_level_boxes(location),
+            [*b"ftyp", *b"free", *b"free", *b"mdat", *b"moov"]
+        );
+    })
+}
+
+#[test]
+fn test_robust_muxing_without_eos() {
+    init();
+
+    let Ok(pipeline) = gst::parse::launch(
+        "videotestsrc num-buffers=99 ! vp9enc ! vp9parse name=video ! mux. \
+         audiotestsrc num-buffers=140 ! flacenc ! flacparse name=audio ! mux. \
+         isomp4mux name=mux reserved-max-duration=10000000000 \
+         reserved-moov-update-period=500000000 ! filesink name=sink",
+    ) else {
+        println!("could not build encoding pipeline");
+        return;
+    };
+    let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut location = dir.path().to_owned();
+    location.push("test.mp4");
+
+    let sink = pipeline.by_name("sink").unwrap();
+    sink.set_property("location", location.to_str().expect("Non-UTF8 filename"));
+
+    // Drop EOS before the muxer to simulate a recording that is interrupted before the muxer
+    // can finish the file
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for name in ["video", "audio"] {
+        let sender = sender.clone();
+        pipeline
+            .by_name(name)
+            .unwrap()
+            .static_pad("src")
+            .unwrap()
+            .add_probe(
+                gst::PadProbeType::EVENT_DOWNSTREAM,
+                move |_pad, info| match info.event() {
+                    Some(ev) if ev.type_() == gst::EventType::Eos => {
+                        let _ = sender.send(());
+                        gst::PadProbeReturn::Drop
+                    }
+                    _ => gst::PadProbeReturn::Ok,
+                },
+            );
+    }
+
+    // A checkpoint seeks back to the reserved space and then continues at the end of the mdat
+    // box. Once the muxer continues after the first checkpoint, the moov box covering the first
+    // 500ms was written by the sink.
+    let (checkpoint_sender, checkpoint_receiver) = std::sync::mpsc::channel();
+    let n_segments = std::sync::atomic::AtomicUsize::new(0);
+    pipeline
+        .by_name("mux")
+        .unwrap()
+        .static_pad("src")
+        .unwrap()
+        .add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_pad, info| {
+            let Some(gst::EventView::Segment(ev)) = info.event().map(|ev| ev.view()) else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let segment = ev.segment().downcast_ref::<gst::format::Bytes>().unwrap();
+            if segment.start().is_some_and(|start| *start > 0)
+                && n_segments.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 1
+            {
+                let _ = checkpoint_sender.send(());
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+    checkpoint_receiver.recv().unwrap();
+    receiver.recv().unwrap();
+    receiver.recv().unwrap();
+    pipeline.set_state(gst::State::Null).unwrap();
+    drop(pipeline);
+
+    // The checkpointed moov box is before the unfinished mdat box
+    assert_eq!(
+        top_level_boxes(&location),
+        [*b"ftyp", *b"moov", *b"free", *b"free", *b"mdat"]
+    );
+
+    let Ok(pipeline) = gst::parse::launch(
+        "filesrc name=src ! isomp4demux name=demux \
+         demux.audio_0 ! queue ! flacdec ! fakesink \
+         demux.video_0 ! queue ! vp9dec ! fakesink",
+    ) else {
+        panic!("could not build decoding pipeline")
+    };
+    let pipeline = Pipeline(pipeline.downcast::<gst::Pipeline>().unwrap());
+    pipeline
+        .by_name("src")
+        .unwrap()
+        .set_property("location", location.display().to_string());
+
+    pipeline.set_state(gst::State::Paused).unwrap();
+    let (res, _, _) = pipeline.state(gst::ClockTime::NONE);
+    res.unwrap();
+    let duration = pipeline.query_duration::<gst::ClockTime>().unwrap();
+    assert!(duration >= gst::ClockTime::from_mseconds(500));
+    pipeline.into_completion();
+}
+
+/// Returns the content of the box at the given path. Each entry is the fourcc of the box and the
+/// number of bytes to skip in its content before the child boxes start.
+fn find_box<'a>(mut data: &'a [u8], path: &[(&[u8; 4], usize)]) -> Option<&'a [u8]> {
+    let ((fourcc, skip), rest) = path.split_first()?;
+
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        assert!((8..=data.len()).contains(&size));
+
+        if &data[4..8] == *fourcc {
+            let content = &data[8..size];
+            if rest.is_empty() {
+                return Some(content);
+            }
+            return find_box(&content[*skip..], rest);
+        }
+
+        data = &data[size..];
+    }
+
+    None
+}
+
+#[test]
+fn test_tx3g() {
+    init();
+
+    let mut h = gst_check::Harness::new("isomp4mux");
+    h.set_src_caps(
+        gst::Caps::builder("text/x-raw")
+            .field("format", "utf8")
+            .build(),
+    );
+    h.play();
+
+    // Two cues with a gap of one second between them
+    for (pts, text) in [(0, "Hello"), (2, "World")] {
+        let mut buffer = gst::Buffer::from_slice(text);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(pts));
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let mut buffers = vec![];
+    while let Ok(Some(buffer)) = h.pull_until_eos() {
+        buffers.push(buffer.map_readable().unwrap().to_vec());
+    }
+
+    // Cues are stored with a 16 bit length prefix and the gap as empty sample
+    let samples = [b"\0\x05Hello".as_slice(), b"\0\0", b"\0\x05World"];
+    let first_sample = buffers
+        .iter()
+        .position(|buffer| buffer == samples[0])
+        .unwrap();
+    assert_eq!(buffers[first_sample..][..3], samples);
+
+    let moov = buffers
+        .iter()
+        .find(|buffer| buffer.len() >= 8 && &buffer[4..8] == b"moov")
+        .unwrap();
+    let hdlr = find_box(
+        moov,
+        &[(b"moov", 0), (b"trak", 0), (b"mdia", 0), (b"hdlr", 0)],
+    )
+    .unwrap();
+    assert_eq!(&hdlr[8..12], b"text");
+    let stbl = [
+        (b"moov", 0),
+        (b"trak", 0),
+        (b"mdia", 0),
+        (b"minf", 0),
+        (b"stbl", 0),
+    ];
+    let tx3g = find_box(moov, &[&stbl[..], &[(b"stsd", 8), (b"tx3g", 0)]].concat());
+    assert!(tx3g.is_some());
+    // Three samples of one second each in a timescale of 10000
+    let stts = find_box(moov, &[&stbl[..], &[(b"stts", 0)]].concat()).unwrap();
+    assert_eq!(
+        stts,
+        [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0x27, 0x10].as_slice()
+    );
+}
@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::HlsEncryptionMethod;
+use aes::cipher::{generic_array::GenericArray, BlockEncryptMut, KeyIvInit};
+use aes::Aes128;
+use gst::glib;
+use once_cell::sync::Lazy;
+use std::io::{self, Write};
+
+type Aes128CbcEnc = cbc::Encryptor<Aes128>;
+
+/// Name of the structures returned by the `get-encryption-key` signal.
+pub const KEY_STRUCTURE_NAME: &str = "application/x-hls-key";
+
+const BLOCK_SIZE: usize = 16;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "hlsencryption",
+        gst::DebugColorFlags::empty(),
+        Some("HLS segment encryption"),
+    )
+});
+
+/// Key to encrypt segments with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentKey {
+    pub key: [u8; 16],
+    /// IV to encrypt all segments with. Otherwise the index of each segment is used.
+    pub iv: Option<[u8; 16]>,
+    pub uri: String,
+}
+
+impl SegmentKey {
+    /// Parses a key from a structure with `key`, `uri` and optionally `iv` fields.
+    pub fn from_structure(s: &gst::StructureRef) -> Result<Self, String> {
+        let bytes = |name: &str| -> Result<Option<[u8; 16]>, String> {
+            let Some(bytes) = s
+                .get_optional::<glib::Bytes>(name)
+                .map_err(|err| format!("Invalid {name}: {err}"))?
+            else {
+                return Ok(None);
+            };
+
+            <[u8; 16]>::try_from(&*bytes)
+                .map(Some)
+                .map_err(|_| format!("Invalid {name} length {}", bytes.len()))
+        };
+
+        let key = bytes("key")?.ok_or_else(|| String::from("No key"))?;
+        let iv = bytes("iv")?;
+        let uri = s
+            .get::<String>("uri")
+            .map_err(|err| format!("Invalid uri: {err}"))?;
+
+        Ok(SegmentKey { key, iv, uri })
+    }
+
+    pub fn to_structure(&self) -> gst::Structure {
+        let mut s = gst::Structure::builder(KEY_STRUCTURE_NAME)
+            .field("key", glib::Bytes::from(&self.key))
+            .field("uri", &self.uri)
+            .build();
+        if let Some(ref iv) = self.iv {
+            s.set("iv", glib::Bytes::from(iv));
+        }
+
+        s
+    }
+
+    /// Returns the `EXT-X-KEY` for segments encrypted with this key, without IV.
+    pub fn to_m3u8_key(&self, method: HlsEncryptionMethod) -> m3u8_rs::Key {
+        let method = match method {
+            HlsEncryptionMethod::SampleAes => m3u8_rs::KeyMethod::SampleAES,
+            _ => m3u8_rs::KeyMethod::AES128,
+        };
+
+        m3u8_rs::Key {
+            method,
+            uri: Some(self.uri.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the IV to encrypt the segment with the given index with.
+    pub fn iv_for_segment(&self, fragment_id: u32) -> [u8; 16] {
+        self.iv
+            .unwrap_or_else(|| (fragment_id as u128).to_be_bytes())
+    }
+}
+
+/// Replaces the `%d` style placeholder in `template` with the key index, if any.
+pub fn format_key_template(template: &str, key_index: u32) -> Result<String, String> {
+    if !template.contains('%') {
+        return Ok(template.to_string());
+    }
+
+    sprintf::sprintf!(template, key_index).map_err(|err| format!("{err:?}"))
+}
+
+/// Formats an IV for the `IV` attribute of `EXT-X-KEY`.
+pub fn format_iv(iv: &[u8; 16]) -> String {
+    format!("0x{}", hex::encode_upper(iv))
+}
+
+/// Parses a key or IV from 32 hexadecimal characters.
+pub fn parse_hex_key(s: &str) -> Result<[u8; 16], hex::FromHexError> {
+    let mut v = [0u8; 16];
+    hex::decode_to_slice(s, &mut v)?;
+    Ok(v)
+}
+
+/// Encrypts everything written to it with AES-128-CBC and PKCS#7 padding.
+///
+/// The final padded block is written once the writer is dropped.
+pub struct Aes128Writer<W: Write> {
+    inner: W,
+    cipher: Aes128CbcEnc,
+    /// Data that does not fill a complete block yet.
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Aes128Writer<W> {
+    pub fn new(inner: W, key: &[u8; 16], iv: &[u8; 16]) -> Self {
+        Self {
+            inner,
+            cipher: Aes128CbcEnc::new(key.into(), iv.into()),
+            pending: Vec::with_capacity(BLOCK_SIZE),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // There's always padding, a full block of it if the data is a multiple of the block size
+        let padding = BLOCK_SIZE - self.pending.len();
+        self.pending.resize(BLOCK_SIZE, padding as u8);
+        self.cipher
+            .encrypt_block_mut(GenericArray::from_mut_slice(&mut self.pending));
+
+        self.inner.write_all(&self.pending)?;
+        self.pending.clear();
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for Aes128Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+
+        let len = self.pending.len() - self.pending.len() % BLOCK_SIZE;
+        for block in self.pending[..len].chunks_exact_mut(BLOCK_SIZE) {
+            self.cipher
+                .encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        self.inner.write_all(&self.pending[..len])?;
+        self.pending.drain(..len);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for Aes128Writer<W> {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            gst::error!(CAT, "Could not write final encrypted block: {}", err);
+        }
+    }
+}
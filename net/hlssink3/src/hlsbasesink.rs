@@ -7,7 +7,9 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::playlist::Playlist;
+use crate::encryption::{self, Aes128Writer, SegmentKey};
+use crate::playlist::{PartialSegment, Playlist, RenditionReport};
+use crate::HlsEncryptionMethod;
 use chrono::{DateTime, Duration, Utc};
 use gio::prelude::*;
 use gst::glib;
@@ -15,10 +17,12 @@ use gst::prelude::*;
 use gst::subclass::prelude::*;
 use m3u8_rs::MediaSegment;
 use once_cell::sync::Lazy;
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 use std::path;
-use std::sync::Mutex;
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
 
 const DEFAULT_PLAYLIST_LOCATION: &str = "playlist.m3u8";
 const DEFAULT_MAX_NUM_SEGMENT_FILES: u32 = 10;
@@ -26,10 +30,15 @@ const DEFAULT_PLAYLIST_LENGTH: u32 = 5;
 const DEFAULT_PROGRAM_DATE_TIME_TAG: bool = false;
 const DEFAULT_CLOCK_TRACKING_FOR_PDT: bool = true;
 const DEFAULT_ENDLIST: bool = true;
+const DEFAULT_ENCRYPTION_METHOD: HlsEncryptionMethod = HlsEncryptionMethod::None;
+const DEFAULT_KEY_ROTATION_INTERVAL: u32 = 0;
 
 const SIGNAL_GET_PLAYLIST_STREAM: &str = "get-playlist-stream";
 const SIGNAL_GET_FRAGMENT_STREAM: &str = "get-fragment-stream";
 const SIGNAL_DELETE_FRAGMENT: &str = "delete-fragment";
+const SIGNAL_WAIT_FOR_PLAYLIST: &str = "wait-for-playlist";
+const SIGNAL_ADD_RENDITION_REPORT: &str = "add-rendition-report";
+const SIGNAL_GET_ENCRYPTION_KEY: &str = "get-encryption-key";
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -47,6 +56,12 @@ struct Settings {
     enable_program_date_time: bool,
     pdt_follows_pipeline_clock: bool,
     enable_endlist: bool,
+    encryption_method: HlsEncryptionMethod,
+    encryption_key: Option<[u8; 16]>,
+    encryption_iv: Option<[u8; 16]>,
+    key_uri: Option<String>,
+    key_location: Option<String>,
+    key_rotation_interval: u32,
 }
 
 impl Default for Settings {
@@ -59,6 +74,12 @@ impl Default for Settings {
             enable_program_date_time: DEFAULT_PROGRAM_DATE_TIME_TAG,
             pdt_follows_pipeline_clock: DEFAULT_CLOCK_TRACKING_FOR_PDT,
             enable_endlist: DEFAULT_ENDLIST,
+            encryption_method: DEFAULT_ENCRYPTION_METHOD,
+            encryption_key: None,
+            encryption_iv: None,
+            key_uri: None,
+            key_location: None,
+            key_rotation_interval: DEFAULT_KEY_ROTATION_INTERVAL,
         }
     }
 }
@@ -72,6 +93,14 @@ pub struct PlaylistContext {
     playlist_location: String,
     max_num_segment_files: usize,
     playlist_length: u32,
+    /// Last written playlist content.
+    rendered_playlist: Option<String>,
+    encryption_method: HlsEncryptionMethod,
+    key_rotation_interval: u32,
+    /// Index of the current key and the key itself.
+    current_key: Option<(u32, SegmentKey)>,
+    /// Keys of the segments that are currently written by their location.
+    segment_keys: HashMap<String, m3u8_rs::Key>,
 }
 
 #[derive(Default)]
@@ -83,6 +112,15 @@ pub struct State {
 pub struct HlsBaseSink {
     settings: Mutex<Settings>,
     state: Mutex<State>,
+    /// Signalled whenever a new playlist was written.
+    playlist_cond: Condvar,
+    /// Other renditions to report in the playlist.
+    rendition_reports: Mutex<Vec<(String, glib::WeakRef<super::HlsBaseSink>)>>,
+    /// Media sequence number and partial segment index of the last written playlist.
+    ///
+    /// Kept separately from the state so that other renditions can read it while writing their
+    /// own playlist.
+    last_part: Mutex<Option<(u64, Option<u64>)>>,
 }
 
 #[glib::object_subclass]
@@ -141,6 +179,38 @@ impl ObjectImpl for HlsBaseSink {
                     .blurb("Write \"EXT-X-ENDLIST\" tag to manifest at the end of stream")
                     .default_value(DEFAULT_ENDLIST)
                     .build(),
+                glib::ParamSpecEnum::builder_with_default("encryption-method", DEFAULT_ENCRYPTION_METHOD)
+                    .nick("Encryption Method")
+                    .blurb("Method to encrypt the segments with (needs key-uri or key-location)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("encryption-key")
+                    .nick("Encryption Key")
+                    .blurb("AES-128 key as 32 hexadecimal characters (default = new random key for every key rotation)")
+                    .write_only()
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("encryption-iv")
+                    .nick("Encryption IV")
+                    .blurb("IV as 32 hexadecimal characters (default = index of each segment for AES-128, random for SAMPLE-AES)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("key-uri")
+                    .nick("Key URI")
+                    .blurb("URI of the key in the playlist, %d is replaced by the key index (default = file name of the key-location)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("key-location")
+                    .nick("Key Location")
+                    .blurb("Location of the key files to write, %d is replaced by the key index (default = don't write keys)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("key-rotation-interval")
+                    .nick("Key Rotation Interval")
+                    .blurb("Number of segments after which a new key is used (0 = never). With SAMPLE-AES a new init segment is written for every new key.")
+                    .default_value(DEFAULT_KEY_ROTATION_INTERVAL)
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -177,6 +247,43 @@ impl ObjectImpl for HlsBaseSink {
             "enable-endlist" => {
                 settings.enable_endlist = value.get().expect("type checked upstream");
             }
+            "encryption-method" => {
+                settings.encryption_method = value.get().expect("type checked upstream");
+            }
+            "encryption-key" | "encryption-iv" => {
+                let value = value
+                    .get::<Option<&str>>()
+                    .expect("type checked upstream")
+                    .and_then(|s| match encryption::parse_hex_key(s) {
+                        Ok(v) => Some(v),
+                        Err(err) => {
+                            gst::error!(
+                                CAT,
+                                imp = self,
+                                "Invalid {} '{}': {}",
+                                pspec.name(),
+                                s,
+                                err
+                            );
+                            None
+                        }
+                    });
+
+                match pspec.name() {
+                    "encryption-key" => settings.encryption_key = value,
+                    "encryption-iv" => settings.encryption_iv = value,
+                    _ => unreachable!(),
+                }
+            }
+            "key-uri" => {
+                settings.key_uri = value.get().expect("type checked upstream");
+            }
+            "key-location" => {
+                settings.key_location = value.get().expect("type checked upstream");
+            }
+            "key-rotation-interval" => {
+                settings.key_rotation_interval = value.get().expect("type checked upstream");
+            }
             _ => unimplemented!(),
         };
     }
@@ -194,6 +301,11 @@ impl ObjectImpl for HlsBaseSink {
             "enable-program-date-time" => settings.enable_program_date_time.to_value(),
             "pdt-follows-pipeline-clock" => settings.pdt_follows_pipeline_clock.to_value(),
             "enable-endlist" => settings.enable_endlist.to_value(),
+            "encryption-method" => settings.encryption_method.to_value(),
+            "encryption-iv" => settings.encryption_iv.map(hex::encode).to_value(),
+            "key-uri" => settings.key_uri.to_value(),
+            "key-location" => settings.key_location.to_value(),
+            "key-rotation-interval" => settings.key_rotation_interval.to_value(),
             _ => unimplemented!(),
         }
     }
@@ -250,6 +362,91 @@ impl ObjectImpl for HlsBaseSink {
                         false
                     })
                     .build(),
+                /**
+                 * GstHlsBaseSink::wait-for-playlist:
+                 * @msn: Media sequence number requested via `_HLS_msn`, or -1
+                 * @part: Partial segment requested via `_HLS_part`, or -1
+                 * @timeout: Maximum time to wait in nanoseconds
+                 *
+                 * Blocks until the playlist contains the requested segment or partial segment
+                 * and returns its content. This allows an HTTP server to implement blocking
+                 * playlist reloads. Returns %NULL on timeout.
+                 */
+                glib::subclass::Signal::builder(SIGNAL_WAIT_FOR_PLAYLIST)
+                    .param_types([
+                        i64::static_type(),
+                        i64::static_type(),
+                        u64::static_type(),
+                    ])
+                    .return_type::<Option<String>>()
+                    .action()
+                    .class_handler(|_, args| {
+                        let elem = args[0].get::<super::HlsBaseSink>().expect("signal arg");
+                        let msn = args[1].get::<i64>().expect("signal arg");
+                        let part = args[2].get::<i64>().expect("signal arg");
+                        let timeout = args[3].get::<u64>().expect("signal arg");
+                        let imp = elem.imp();
+
+                        Some(
+                            imp.wait_for_playlist(
+                                u64::try_from(msn).ok(),
+                                u64::try_from(part).ok(),
+                                gst::ClockTime::from_nseconds(timeout),
+                            )
+                            .to_value(),
+                        )
+                    })
+                    .build(),
+                /**
+                 * GstHlsBaseSink::add-rendition-report:
+                 * @uri: URI of the other rendition's playlist relative to this playlist
+                 * @sink: Sink writing the other rendition
+                 *
+                 * Adds an `EXT-X-RENDITION-REPORT` for another rendition to the playlist.
+                 */
+                glib::subclass::Signal::builder(SIGNAL_ADD_RENDITION_REPORT)
+                    .param_types([String::static_type(), super::HlsBaseSink::static_type()])
+                    .action()
+                    .class_handler(|_, args| {
+                        let elem = args[0].get::<super::HlsBaseSink>().expect("signal arg");
+                        let uri = args[1].get::<String>().expect("signal arg");
+                        let sink = args[2].get::<super::HlsBaseSink>().expect("signal arg");
+                        let imp = elem.imp();
+
+                        imp.rendition_reports
+                            .lock()
+                            .unwrap()
+                            .push((uri, sink.downgrade()));
+                        None
+                    })
+                    .build(),
+                /**
+                 * GstHlsBaseSink::get-encryption-key:
+                 * @key_index: Index of the key, increased with every key rotation
+                 *
+                 * Returns the key to encrypt segments with as `application/x-hls-key`
+                 * structure with the `key` as 16 bytes `GBytes`, the `uri` of the key in the
+                 * playlist and optionally an `iv` as 16 bytes `GBytes`.
+                 *
+                 * By default the key and IV are taken from the properties, or a random key
+                 * is created and written to the key-location.
+                 */
+                glib::subclass::Signal::builder(SIGNAL_GET_ENCRYPTION_KEY)
+                    .param_types([u32::static_type()])
+                    .return_type::<Option<gst::Structure>>()
+                    .class_handler(|_, args| {
+                        let elem = args[0].get::<super::HlsBaseSink>().expect("signal arg");
+                        let key_index = args[1].get::<u32>().expect("signal arg");
+                        let imp = elem.imp();
+
+                        Some(imp.new_encryption_key(key_index).to_value())
+                    })
+                    .accumulator(|_hint, ret, value| {
+                        // First signal handler wins
+                        *ret = value.clone();
+                        false
+                    })
+                    .build(),
             ]
         });
 
@@ -304,7 +501,13 @@ impl HlsBaseSink {
             playlist_location: settings.playlist_location.clone(),
             max_num_segment_files: settings.max_num_segment_files,
             playlist_length: settings.playlist_length,
+            rendered_playlist: None,
+            encryption_method: settings.encryption_method,
+            key_rotation_interval: settings.key_rotation_interval,
+            current_key: None,
+            segment_keys: HashMap::new(),
         });
+        *self.last_part.lock().unwrap() = None;
     }
 
     fn close_playlist(&self) {
@@ -317,6 +520,50 @@ impl HlsBaseSink {
                 let _ = self.write_playlist(&mut context);
             }
         }
+        drop(state);
+
+        // Wake up all waiters, there won't be any further playlist updates
+        self.playlist_cond.notify_all();
+    }
+
+    /// Waits until the playlist contains the given segment or partial segment.
+    ///
+    /// Returns the playlist content, or `None` on timeout or if no playlist is written.
+    fn wait_for_playlist(
+        &self,
+        msn: Option<u64>,
+        part: Option<u64>,
+        timeout: gst::ClockTime,
+    ) -> Option<String> {
+        let deadline = Instant::now() + std::time::Duration::from_nanos(timeout.nseconds());
+
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let context = state.context.as_ref()?;
+            if let Some(ref rendered_playlist) = context.rendered_playlist {
+                if context.playlist.is_ended()
+                    || msn.map_or(true, |msn| context.playlist.contains(msn, part))
+                {
+                    return Some(rendered_playlist.clone());
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Timeout waiting for segment {msn:?} part {part:?}"
+                );
+                return None;
+            }
+
+            state = self
+                .playlist_cond
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
     }
 
     pub fn get_fragment_stream(&self, fragment_id: u32) -> Option<(gio::OutputStream, String)> {
@@ -341,6 +588,8 @@ impl HlsBaseSink {
 
         gst::trace!(CAT, imp = self, "Segment location formatted: {}", location);
 
+        let key = self.segment_key(context, fragment_id).ok()?;
+
         let stream = match self
             .obj()
             .emit_by_name::<Option<gio::OutputStream>>(SIGNAL_GET_FRAGMENT_STREAM, &[&location])
@@ -349,9 +598,149 @@ impl HlsBaseSink {
             None => return None,
         };
 
+        let Some(key) = key else {
+            return Some((stream, location));
+        };
+
+        let mut m3u8_key = key.to_m3u8_key(context.encryption_method);
+        let stream = if context.encryption_method == HlsEncryptionMethod::Aes128 {
+            // The IV is always written explicitly as the media sequence number of a segment
+            // is not known yet
+            let iv = key.iv_for_segment(fragment_id);
+            m3u8_key.iv = Some(encryption::format_iv(&iv));
+
+            gio::WriteOutputStream::new(Aes128Writer::new(stream.into_write(), &key.key, &iv))
+                .upcast()
+        } else {
+            stream
+        };
+        context.segment_keys.insert(location.clone(), m3u8_key);
+
         Some((stream, location))
     }
 
+    /// Returns the key to encrypt the fragment with the given index with, if encryption is
+    /// enabled.
+    pub fn encryption_key(&self, fragment_id: u32) -> Result<Option<SegmentKey>, gst::FlowError> {
+        let mut state = self.state.lock().unwrap();
+        let context = match state.context.as_mut() {
+            Some(context) => context,
+            None => {
+                gst::error!(CAT, imp = self, "Playlist is not configured",);
+
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        self.segment_key(context, fragment_id)
+    }
+
+    pub fn encryption_method(&self) -> HlsEncryptionMethod {
+        self.settings.lock().unwrap().encryption_method
+    }
+
+    fn segment_key(
+        &self,
+        context: &mut PlaylistContext,
+        fragment_id: u32,
+    ) -> Result<Option<SegmentKey>, gst::FlowError> {
+        if context.encryption_method == HlsEncryptionMethod::None {
+            return Ok(None);
+        }
+
+        let key_index = fragment_id
+            .checked_div(context.key_rotation_interval)
+            .unwrap_or(0);
+        if let Some((index, ref key)) = context.current_key {
+            if index == key_index {
+                return Ok(Some(key.clone()));
+            }
+        }
+
+        let key = self
+            .obj()
+            .emit_by_name::<Option<gst::Structure>>(SIGNAL_GET_ENCRYPTION_KEY, &[&key_index])
+            .ok_or_else(|| {
+                gst::element_imp_error!(
+                    self,
+                    gst::LibraryError::Encrypt,
+                    ["No encryption key {}", key_index]
+                );
+                gst::FlowError::Error
+            })
+            .and_then(|s| {
+                SegmentKey::from_structure(&s).map_err(|err| {
+                    gst::element_imp_error!(
+                        self,
+                        gst::LibraryError::Encrypt,
+                        ["Invalid encryption key {}: {}", key_index, err]
+                    );
+                    gst::FlowError::Error
+                })
+            })?;
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Using encryption key {} with URI {}",
+            key_index,
+            key.uri
+        );
+        context.current_key = Some((key_index, key.clone()));
+
+        Ok(Some(key))
+    }
+
+    /// Creates the key with the given index from the settings, writing it to the key location.
+    fn new_encryption_key(&self, key_index: u32) -> Option<gst::Structure> {
+        let settings = self.settings.lock().unwrap();
+        let key = settings.encryption_key.unwrap_or_else(rand::random);
+        let iv = settings.encryption_iv;
+        let key_uri = settings.key_uri.clone();
+        let key_location = settings.key_location.clone();
+        drop(settings);
+
+        let format = |template: &str| {
+            encryption::format_key_template(template, key_index)
+                .map_err(|err| {
+                    gst::error!(CAT, imp = self, "Couldn't build key location, err: {}", err);
+                })
+                .ok()
+        };
+
+        let key_location = match key_location {
+            Some(ref template) => Some(format(template)?),
+            None => None,
+        };
+
+        if let Some(ref location) = key_location {
+            let mut stream = self.new_file_stream(location).ok()?.into_write();
+            if let Err(err) = stream.write_all(&key).and_then(|_| stream.flush()) {
+                gst::element_imp_error!(
+                    self,
+                    gst::ResourceError::Write,
+                    ["Could not write key {}: {}", location, err]
+                );
+                return None;
+            }
+        }
+
+        let uri = match (key_uri, key_location) {
+            (Some(template), _) => format(&template)?,
+            (None, Some(location)) => self.get_segment_uri(&location),
+            (None, None) => {
+                gst::element_imp_error!(
+                    self,
+                    gst::LibraryError::Settings,
+                    ["Encryption enabled but neither key-uri nor key-location configured"]
+                );
+                return None;
+            }
+        };
+
+        Some(SegmentKey { key, iv, uri }.to_structure())
+    }
+
     pub fn get_segment_uri(&self, location: &str) -> String {
         let settings = self.settings.lock().unwrap();
         let file_name = path::Path::new(&location)
@@ -429,6 +818,10 @@ impl HlsBaseSink {
             }
         }
 
+        if let Some(key) = context.segment_keys.remove(location) {
+            segment.key = Some(key);
+        }
+
         context.playlist.add_segment(segment);
 
         if context.playlist.is_type_undefined() {
@@ -438,6 +831,34 @@ impl HlsBaseSink {
         self.write_playlist(context)
     }
 
+    /// Adds a partial segment of the segment that is currently written for Low-Latency HLS.
+    pub fn add_partial_segment(
+        &self,
+        part: PartialSegment,
+        map: Option<m3u8_rs::Map>,
+        preload_hint: Option<(String, u64)>,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.lock().unwrap();
+        let context = match state.context.as_mut() {
+            Some(context) => context,
+            None => {
+                gst::error!(CAT, imp = self, "Playlist is not configured",);
+
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        let key = context
+            .current_key
+            .as_ref()
+            .map(|(_, key)| key.to_m3u8_key(context.encryption_method));
+        context
+            .playlist
+            .add_partial_segment(part, map, key, preload_hint);
+
+        self.render_playlist(context)
+    }
+
     fn write_playlist(
         &self,
         context: &mut PlaylistContext,
@@ -453,6 +874,58 @@ impl HlsBaseSink {
             .playlist
             .update_playlist_state(context.playlist_length as usize);
 
+        self.render_playlist(context)?;
+
+        if context.playlist.is_type_undefined() && context.max_num_segment_files > 0 {
+            // Cleanup old segments from filesystem
+            while context.old_segment_locations.len() > context.max_num_segment_files {
+                let old_segment_location = context.old_segment_locations.remove(0);
+                if !self
+                    .obj()
+                    .emit_by_name::<bool>(SIGNAL_DELETE_FRAGMENT, &[&old_segment_location])
+                {
+                    gst::error!(CAT, imp = self, "Could not delete fragment");
+                }
+            }
+        }
+
+        gst::debug!(CAT, imp = self, "Wrote new playlist file!");
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Writes the current playlist without updating its state.
+    fn render_playlist(
+        &self,
+        context: &mut PlaylistContext,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let rendition_reports = self
+            .rendition_reports
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(uri, sink)| {
+                let sink = sink.upgrade()?;
+                let (last_msn, last_part) = (*sink.imp().last_part.lock().unwrap())?;
+                Some(RenditionReport {
+                    uri: uri.clone(),
+                    last_msn,
+                    last_part,
+                })
+            })
+            .collect();
+        context.playlist.set_rendition_reports(rendition_reports);
+
+        let mut content = Vec::new();
+        context.playlist.write_to(&mut content).map_err(|err| {
+            gst::error!(
+                CAT,
+                imp = self,
+                "Could not write new playlist: {}",
+                err.to_string()
+            );
+            gst::FlowError::Error
+        })?;
+
         // Acquires the playlist file handle so we can update it with new content. By default, this
         // is expected to be the same file every time.
         let mut playlist_stream = self
@@ -471,18 +944,15 @@ impl HlsBaseSink {
             })?
             .into_write();
 
-        context
-            .playlist
-            .write_to(&mut playlist_stream)
-            .map_err(|err| {
-                gst::error!(
-                    CAT,
-                    imp = self,
-                    "Could not write new playlist: {}",
-                    err.to_string()
-                );
-                gst::FlowError::Error
-            })?;
+        playlist_stream.write_all(&content).map_err(|err| {
+            gst::error!(
+                CAT,
+                imp = self,
+                "Could not write new playlist: {}",
+                err.to_string()
+            );
+            gst::FlowError::Error
+        })?;
         playlist_stream.flush().map_err(|err| {
             gst::error!(
                 CAT,
@@ -493,20 +963,10 @@ impl HlsBaseSink {
             gst::FlowError::Error
         })?;
 
-        if context.playlist.is_type_undefined() && context.max_num_segment_files > 0 {
-            // Cleanup old segments from filesystem
-            while context.old_segment_locations.len() > context.max_num_segment_files {
-                let old_segment_location = context.old_segment_locations.remove(0);
-                if !self
-                    .obj()
-                    .emit_by_name::<bool>(SIGNAL_DELETE_FRAGMENT, &[&old_segment_location])
-                {
-                    gst::error!(CAT, imp = self, "Could not delete fragment");
-                }
-            }
-        }
+        *self.last_part.lock().unwrap() = context.playlist.last_part();
+        context.rendered_playlist = Some(String::from_utf8(content).unwrap());
+        self.playlist_cond.notify_all();
 
-        gst::debug!(CAT, imp = self, "Wrote new playlist file!");
         Ok(gst::FlowSuccess::Ok)
     }
 
@@ -8,8 +8,8 @@
 
 use crate::hlsbasesink::HlsBaseSinkImpl;
 use crate::hlssink3::HlsSink3PlaylistType;
-use crate::playlist::Playlist;
-use crate::HlsBaseSink;
+use crate::playlist::{PartialSegment, Playlist};
+use crate::{HlsBaseSink, HlsEncryptionMethod};
 use gio::prelude::*;
 use gst::glib;
 use gst::prelude::*;
@@ -26,7 +26,10 @@ const DEFAULT_PLAYLIST_TYPE: HlsSink3PlaylistType = HlsSink3PlaylistType::Unspec
 const DEFAULT_SYNC: bool = true;
 const DEFAULT_LATENCY: gst::ClockTime =
     gst::ClockTime::from_mseconds((DEFAULT_TARGET_DURATION * 500) as u64);
+const DEFAULT_PART_DURATION: Option<gst::ClockTime> = None;
+const DEFAULT_BLOCKING_RELOAD: bool = false;
 const SIGNAL_GET_INIT_STREAM: &str = "get-init-stream";
+const CENC_KEY_STRUCTURE_NAME: &str = "application/x-cenc-key";
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -49,6 +52,8 @@ struct HlsCmafSinkSettings {
     playlist_type: Option<MediaPlaylistType>,
     sync: bool,
     latency: gst::ClockTime,
+    part_duration: Option<gst::ClockTime>,
+    blocking_reload: bool,
 
     cmafmux: gst::Element,
     appsink: gst_app::AppSink,
@@ -78,6 +83,8 @@ impl Default for HlsCmafSinkSettings {
             playlist_type: None,
             sync: DEFAULT_SYNC,
             latency: DEFAULT_LATENCY,
+            part_duration: DEFAULT_PART_DURATION,
+            blocking_reload: DEFAULT_BLOCKING_RELOAD,
             cmafmux,
             appsink,
         }
@@ -90,6 +97,21 @@ struct HlsCmafSinkState {
     segment_idx: u32,
     init_segment: Option<m3u8_rs::Map>,
     new_header: bool,
+    current_segment: Option<CurrentSegment>,
+    /// Key that samples are currently encrypted with and its key ID.
+    current_key: Option<([u8; 16], [u8; 16])>,
+}
+
+/// Segment that is currently written as partial segments.
+struct CurrentSegment {
+    stream: gio::OutputStreamWrite<gio::OutputStream>,
+    location: String,
+    uri: String,
+    running_time: Option<gst::ClockTime>,
+    map: Option<m3u8_rs::Map>,
+    duration: gst::ClockTime,
+    offset: u64,
+    num_parts: u32,
 }
 
 #[derive(Default)]
@@ -144,6 +166,18 @@ impl ObjectImpl for HlsCmafSink {
                     .maximum(i64::MAX as u64)
                     .default_value(DEFAULT_LATENCY.nseconds())
                     .build(),
+                glib::ParamSpecUInt64::builder("part-duration")
+                    .nick("Part duration")
+                    .blurb("Target duration of Low-Latency HLS partial segments in nanoseconds (default = no partial segments)")
+                    .default_value(u64::MAX)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("blocking-reload")
+                    .nick("Blocking reload")
+                    .blurb("Advertise support for blocking playlist reloads via the wait-for-playlist signal")
+                    .default_value(DEFAULT_BLOCKING_RELOAD)
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -186,6 +220,15 @@ impl ObjectImpl for HlsCmafSink {
                 settings.latency = value.get().expect("type checked upstream");
                 settings.cmafmux.set_property("latency", settings.latency);
             }
+            "part-duration" => {
+                settings.part_duration = value.get().expect("type checked upstream");
+                settings
+                    .cmafmux
+                    .set_property("chunk-duration", settings.part_duration);
+            }
+            "blocking-reload" => {
+                settings.blocking_reload = value.get().expect("type checked upstream");
+            }
             _ => unimplemented!(),
         };
     }
@@ -202,6 +245,8 @@ impl ObjectImpl for HlsCmafSink {
             }
             "sync" => settings.sync.to_value(),
             "latency" => settings.latency.to_value(),
+            "part-duration" => settings.part_duration.to_value(),
+            "blocking-reload" => settings.blocking_reload.to_value(),
             _ => unimplemented!(),
         }
     }
@@ -239,12 +284,23 @@ impl ObjectImpl for HlsCmafSink {
             .unwrap();
         settings.cmafmux.link(&settings.appsink).unwrap();
 
+        let self_weak = self.downgrade();
+        settings
+            .cmafmux
+            .connect("get-encryption-key", false, move |args| {
+                let imp = self_weak.upgrade()?;
+                let sequence_number = args[1].get::<u32>().expect("signal arg");
+
+                Some(imp.fragment_encryption_key(sequence_number).to_value())
+            });
+
         let sinkpad = settings.cmafmux.static_pad("sink").unwrap();
         let gpad = gst::GhostPad::with_target(&sinkpad).unwrap();
 
         obj.add_pad(&gpad).unwrap();
 
         let self_weak = self.downgrade();
+        let self_weak_eos = self.downgrade();
         settings.appsink.set_callbacks(
             gst_app::AppSinkCallbacks::builder()
                 .new_sample(move |sink| {
@@ -255,6 +311,13 @@ impl ObjectImpl for HlsCmafSink {
                     let sample = sink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
                     imp.on_new_sample(sample)
                 })
+                .eos(move |_sink| {
+                    let Some(imp) = self_weak_eos.upgrade() else {
+                        return;
+                    };
+
+                    let _ = imp.finish_segment();
+                })
                 .build(),
         );
     }
@@ -301,6 +364,7 @@ impl ElementImpl for HlsCmafSink {
                         .field("channels", gst::IntRange::new(1, u16::MAX as i32))
                         .field("rate", gst::IntRange::new(1, i32::MAX))
                         .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
@@ -318,17 +382,37 @@ impl ElementImpl for HlsCmafSink {
         transition: gst::StateChange,
     ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
         if transition == gst::StateChange::ReadyToPaused {
-            let (target_duration, playlist_type, segment_template) = {
+            if base_imp!(self).encryption_method() == HlsEncryptionMethod::Aes128 {
+                gst::element_imp_error!(
+                    self,
+                    gst::LibraryError::Settings,
+                    ["AES-128 encryption is not supported for CMAF segments, use SAMPLE-AES"]
+                );
+                return Err(gst::StateChangeError);
+            }
+
+            let (target_duration, playlist_type, segment_template, part_duration, blocking_reload) = {
                 let settings = self.settings.lock().unwrap();
                 (
                     settings.target_duration,
                     settings.playlist_type.clone(),
                     settings.location.clone(),
+                    settings.part_duration,
+                    settings.blocking_reload,
                 )
             };
 
-            let playlist = self.start(target_duration, playlist_type);
+            let mut playlist = self.start(target_duration, playlist_type);
+            if let Some(part_duration) = part_duration {
+                playlist.enable_low_latency(
+                    part_duration.mseconds() as f32 / 1_000f32,
+                    blocking_reload,
+                );
+            }
             base_imp!(self).open_playlist(playlist, segment_template);
+
+            self.configure_encryption()
+                .map_err(|_| gst::StateChangeError)?;
         }
 
         self.parent_change_state(transition)
@@ -363,6 +447,73 @@ impl HlsCmafSink {
         Playlist::new(playlist, turn_vod, true)
     }
 
+    /// Configures the muxer to encrypt all samples with the cbcs scheme for SAMPLE-AES.
+    ///
+    /// Keys are rotated by the muxer via `fragment_encryption_key()`.
+    fn configure_encryption(&self) -> Result<(), gst::FlowError> {
+        let key = base_imp!(self).encryption_key(0)?;
+        let settings = self.settings.lock().unwrap();
+
+        let Some(key) = key else {
+            settings
+                .cmafmux
+                .set_property_from_str("encryption-scheme", "none");
+            return Ok(());
+        };
+
+        gst::debug!(CAT, imp = self, "Encrypting samples with key {}", key.uri);
+
+        let key_id: [u8; 16] = rand::random();
+        self.state.lock().unwrap().current_key = Some((key.key, key_id));
+        settings
+            .cmafmux
+            .set_property_from_str("encryption-scheme", "cbcs");
+        settings
+            .cmafmux
+            .set_property("encryption-key-id", hex::encode(key_id));
+        settings
+            .cmafmux
+            .set_property("encryption-key", hex::encode(key.key));
+        settings
+            .cmafmux
+            .set_property("encryption-iv", key.iv.map(hex::encode));
+
+        Ok(())
+    }
+
+    /// Returns the key for the fragment with the given sequence number to the muxer.
+    ///
+    /// Every new key gets a new key ID, which makes the muxer write a new init segment.
+    fn fragment_encryption_key(&self, sequence_number: u32) -> Option<gst::Structure> {
+        // Fragment sequence numbers start at 1
+        let fragment_id = sequence_number.checked_sub(1)?;
+        let key = base_imp!(self).encryption_key(fragment_id).ok()??;
+
+        let mut state = self.state.lock().unwrap();
+        let key_id = match state.current_key {
+            Some((current_key, key_id)) if current_key == key.key => key_id,
+            _ => {
+                let key_id = rand::random();
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Rotating to key {} for segment {}",
+                    key.uri,
+                    fragment_id
+                );
+                state.current_key = Some((key.key, key_id));
+                key_id
+            }
+        };
+
+        Some(
+            gst::Structure::builder(CENC_KEY_STRUCTURE_NAME)
+                .field("key-id", glib::Bytes::from(&key_id))
+                .field("key", glib::Bytes::from(&key.key))
+                .build(),
+        )
+    }
+
     fn on_init_segment(&self) -> Result<gio::OutputStreamWrite<gio::OutputStream>, String> {
         let settings = self.settings.lock().unwrap();
         let mut state = self.state.lock().unwrap();
@@ -483,6 +634,11 @@ impl HlsCmafSink {
         let running_time = segment.to_running_time(first.pts().unwrap());
         let dur = first.duration().unwrap();
 
+        if self.settings.lock().unwrap().part_duration.is_some() {
+            let fragment_start = !first.flags().contains(gst::BufferFlags::DELTA_UNIT);
+            return self.add_partial_segment(&buffer_list, fragment_start, dur, running_time);
+        }
+
         let (mut stream, location) = self.on_new_fragment().map_err(|err| {
             gst::error!(
                 CAT,
@@ -508,4 +664,115 @@ impl HlsCmafSink {
 
         self.add_segment(dur.mseconds() as f32 / 1_000f32, running_time, location)
     }
+
+    /// Writes a chunk of the muxer as partial segment of the current segment.
+    ///
+    /// A new segment is started whenever the chunk starts a new fragment.
+    fn add_partial_segment(
+        &self,
+        buffer_list: &gst::BufferListRef,
+        fragment_start: bool,
+        duration: gst::ClockTime,
+        running_time: Option<gst::ClockTime>,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        if fragment_start || self.state.lock().unwrap().current_segment.is_none() {
+            self.finish_segment()?;
+
+            let (stream, location) = self.on_new_fragment().map_err(|err| {
+                gst::error!(
+                    CAT,
+                    imp = self,
+                    "Couldn't get output stream for segment, {err}",
+                );
+                gst::FlowError::Error
+            })?;
+            let uri = base_imp!(self).get_segment_uri(&location);
+
+            let mut state = self.state.lock().unwrap();
+            let map = if state.new_header {
+                state.new_header = false;
+                state.init_segment.clone()
+            } else {
+                None
+            };
+
+            state.current_segment = Some(CurrentSegment {
+                stream,
+                location,
+                uri,
+                running_time,
+                map,
+                duration: gst::ClockTime::ZERO,
+                offset: 0,
+                num_parts: 0,
+            });
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let segment = state.current_segment.as_mut().unwrap();
+
+        let mut size = 0;
+        for buffer in buffer_list {
+            let map = buffer.map_readable().unwrap();
+
+            segment.stream.write(&map).map_err(|_| {
+                gst::error!(CAT, imp = self, "Couldn't write segment to output stream",);
+                gst::FlowError::Error
+            })?;
+            size += map.len() as u64;
+        }
+
+        segment.stream.flush().map_err(|_| {
+            gst::error!(CAT, imp = self, "Couldn't flush output stream",);
+            gst::FlowError::Error
+        })?;
+
+        let part = PartialSegment {
+            uri: segment.uri.clone(),
+            duration: duration.mseconds() as f32 / 1_000f32,
+            byte_range: (size, segment.offset),
+            independent: segment.num_parts == 0,
+        };
+        // The init segment is only announced together with the first partial segment
+        let map = if segment.num_parts == 0 {
+            segment.map.clone()
+        } else {
+            None
+        };
+
+        segment.offset += size;
+        segment.duration += duration;
+        segment.num_parts += 1;
+
+        let preload_hint = Some((segment.uri.clone(), segment.offset));
+        drop(state);
+
+        base_imp!(self).add_partial_segment(part, map, preload_hint)
+    }
+
+    /// Adds the segment that is currently written as partial segments to the playlist.
+    fn finish_segment(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let Some(segment) = self.state.lock().unwrap().current_segment.take() else {
+            return Ok(gst::FlowSuccess::Ok);
+        };
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Finished segment {} with {} partial segments",
+            segment.location,
+            segment.num_parts
+        );
+
+        base_imp!(self).add_segment(
+            &segment.location,
+            segment.running_time,
+            MediaSegment {
+                uri: segment.uri,
+                duration: segment.duration.mseconds() as f32 / 1_000f32,
+                map: segment.map,
+                ..Default::default()
+            },
+        )
+    }
 }
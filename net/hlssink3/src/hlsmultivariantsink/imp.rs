@@ -0,0 +1,1136 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use super::{HlsMultivariantSinkMuxerType, HlsMultivariantSinkPad};
+use crate::encryption::{self, SegmentKey};
+use crate::HlsEncryptionMethod;
+use gio::prelude::*;
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use m3u8_rs::{
+    AlternativeMedia, AlternativeMediaType, MasterPlaylist, Resolution, SessionKey, VariantStream,
+};
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path;
+use std::sync::Mutex;
+
+const DEFAULT_MULTIVARIANT_PLAYLIST_LOCATION: &str = "multivariant.m3u8";
+const DEFAULT_MUXER_TYPE: HlsMultivariantSinkMuxerType = HlsMultivariantSinkMuxerType::Cmaf;
+const DEFAULT_TARGET_DURATION: u32 = 15;
+const DEFAULT_PLAYLIST_LENGTH: u32 = 5;
+const DEFAULT_ENCRYPTION_METHOD: HlsEncryptionMethod = HlsEncryptionMethod::None;
+const DEFAULT_KEY_ROTATION_INTERVAL: u32 = 0;
+
+const SIGNAL_GET_MULTIVARIANT_PLAYLIST_STREAM: &str = "get-multivariant-playlist-stream";
+const SIGNAL_GET_PLAYLIST_STREAM: &str = "get-playlist-stream";
+const SIGNAL_GET_INIT_STREAM: &str = "get-init-stream";
+const SIGNAL_GET_FRAGMENT_STREAM: &str = "get-fragment-stream";
+const SIGNAL_DELETE_FRAGMENT: &str = "delete-fragment";
+const SIGNAL_GET_ENCRYPTION_KEY: &str = "get-encryption-key";
+
+/// Minimum amount of media after which the measured bitrate of a rendition is used.
+const BITRATE_MEASUREMENT_DURATION: gst::ClockTime = gst::ClockTime::from_seconds(1);
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "hlsmultivariantsink",
+        gst::DebugColorFlags::empty(),
+        Some("HLS multivariant sink"),
+    )
+});
+
+struct Settings {
+    multivariant_playlist_location: String,
+    muxer_type: HlsMultivariantSinkMuxerType,
+    target_duration: u32,
+    playlist_length: u32,
+    encryption_method: HlsEncryptionMethod,
+    key_uri: Option<String>,
+    key_location: Option<String>,
+    key_rotation_interval: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            multivariant_playlist_location: String::from(DEFAULT_MULTIVARIANT_PLAYLIST_LOCATION),
+            muxer_type: DEFAULT_MUXER_TYPE,
+            target_duration: DEFAULT_TARGET_DURATION,
+            playlist_length: DEFAULT_PLAYLIST_LENGTH,
+            encryption_method: DEFAULT_ENCRYPTION_METHOD,
+            key_uri: None,
+            key_location: None,
+            key_rotation_interval: DEFAULT_KEY_ROTATION_INTERVAL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RenditionType {
+    Video,
+    Audio,
+    Subtitle,
+}
+
+struct Rendition {
+    pad: HlsMultivariantSinkPad,
+    sink: gst::Element,
+    rendition_type: RenditionType,
+    /// Whether the media sink was started after receiving caps.
+    started: bool,
+    eos: bool,
+    caps: Option<gst::Caps>,
+    /// Bitrate from upstream tags, preferring the maximum bitrate.
+    tag_bitrate: Option<u32>,
+    measured_bytes: u64,
+    measured_start: Option<gst::ClockTime>,
+    measured_end: Option<gst::ClockTime>,
+    /// Measured bitrate as last written to the multivariant playlist.
+    measured_bitrate: Option<u64>,
+}
+
+impl Rendition {
+    fn new(pad: HlsMultivariantSinkPad, sink: gst::Element, rendition_type: RenditionType) -> Self {
+        Self {
+            pad,
+            sink,
+            rendition_type,
+            started: false,
+            eos: false,
+            caps: None,
+            tag_bitrate: None,
+            measured_bytes: 0,
+            measured_start: None,
+            measured_end: None,
+            measured_bitrate: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.pad.clone(), self.sink.clone(), self.rendition_type);
+    }
+
+    /// Accounts a buffer to the measured bitrate.
+    ///
+    /// Returns `true` if the measured bitrate changed considerably.
+    fn measure(
+        &mut self,
+        size: usize,
+        pts: Option<gst::ClockTime>,
+        duration: Option<gst::ClockTime>,
+    ) -> bool {
+        let Some(pts) = pts else {
+            return false;
+        };
+        let end = pts + duration.unwrap_or(gst::ClockTime::ZERO);
+
+        self.measured_bytes += size as u64;
+        let start = *self.measured_start.get_or_insert(pts);
+        let end = *self
+            .measured_end
+            .insert(self.measured_end.map_or(end, |e| e.max(end)));
+
+        let span = end.saturating_sub(start);
+        if span < BITRATE_MEASUREMENT_DURATION {
+            return false;
+        }
+
+        let bitrate = (self.measured_bytes * 8)
+            .mul_div_floor(gst::ClockTime::SECOND.nseconds(), span.nseconds())
+            .unwrap();
+
+        // Only update the multivariant playlist if the bitrate changed by more than 10%
+        match self.measured_bitrate {
+            Some(old) if bitrate.abs_diff(old) <= old / 10 => false,
+            _ => {
+                self.measured_bitrate = Some(bitrate);
+                true
+            }
+        }
+    }
+
+    fn bandwidth(&self, settings: &super::pad::Settings) -> Option<u64> {
+        if settings.bandwidth > 0 {
+            return Some(settings.bandwidth as u64);
+        }
+
+        self.tag_bitrate
+            .map(|bitrate| bitrate as u64)
+            .or(self.measured_bitrate)
+    }
+
+    fn codec(&self) -> Option<String> {
+        let caps = self.caps.as_ref()?;
+
+        gst_pbutils::codec_utils_caps_get_mime_codec(caps)
+            .ok()
+            .map(String::from)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    renditions: BTreeMap<String, Rendition>,
+    video_serial: u32,
+    audio_serial: u32,
+    subtitle_serial: u32,
+    /// Content of the last rendered multivariant playlist.
+    multivariant_playlist: Option<String>,
+    /// Increased with every newly rendered multivariant playlist.
+    multivariant_playlist_serial: u64,
+}
+
+#[derive(Default)]
+pub struct HlsMultivariantSink {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    /// Encryption keys shared by all renditions by their index.
+    keys: Mutex<BTreeMap<u32, gst::Structure>>,
+    /// Serializes writing the multivariant playlist. Contains the serial of the last written one.
+    written_multivariant_playlist: Mutex<u64>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for HlsMultivariantSink {
+    const NAME: &'static str = "GstHlsMultivariantSink";
+    type Type = super::HlsMultivariantSink;
+    type ParentType = gst::Bin;
+}
+
+impl ObjectImpl for HlsMultivariantSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::builder("multivariant-playlist-location")
+                    .nick("Multivariant playlist location")
+                    .blurb("Location of the multivariant playlist to write. Media playlists are written relative to it.")
+                    .default_value(Some(DEFAULT_MULTIVARIANT_PLAYLIST_LOCATION))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("muxer-type", DEFAULT_MUXER_TYPE)
+                    .nick("Muxer type")
+                    .blurb("Container format of the audio and video renditions")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("target-duration")
+                    .nick("Target duration")
+                    .blurb("The target duration in seconds of a segment/file of each rendition")
+                    .default_value(DEFAULT_TARGET_DURATION)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("playlist-length")
+                    .nick("Playlist length")
+                    .blurb("Length of the media playlists. If this is set to 0, the playlists will be infinite.")
+                    .default_value(DEFAULT_PLAYLIST_LENGTH)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("encryption-method", DEFAULT_ENCRYPTION_METHOD)
+                    .nick("Encryption Method")
+                    .blurb("Method to encrypt the audio and video segments with (needs key-uri)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("key-uri")
+                    .nick("Key URI")
+                    .blurb("URI of the key in the playlists, %d is replaced by the key index")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("key-location")
+                    .nick("Key Location")
+                    .blurb("Location of the key files to write, %d is replaced by the key index (default = don't write keys)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("key-rotation-interval")
+                    .nick("Key Rotation Interval")
+                    .blurb("Number of segments after which a new key is used (0 = never). With SAMPLE-AES a new init segment is written for every new key.")
+                    .default_value(DEFAULT_KEY_ROTATION_INTERVAL)
+                    .mutable_ready()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "multivariant-playlist-location" => {
+                settings.multivariant_playlist_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_MULTIVARIANT_PLAYLIST_LOCATION.into());
+            }
+            "muxer-type" => {
+                settings.muxer_type = value.get().expect("type checked upstream");
+            }
+            "target-duration" => {
+                settings.target_duration = value.get().expect("type checked upstream");
+            }
+            "playlist-length" => {
+                settings.playlist_length = value.get().expect("type checked upstream");
+            }
+            "encryption-method" => {
+                settings.encryption_method = value.get().expect("type checked upstream");
+            }
+            "key-uri" => {
+                settings.key_uri = value.get().expect("type checked upstream");
+            }
+            "key-location" => {
+                settings.key_location = value.get().expect("type checked upstream");
+            }
+            "key-rotation-interval" => {
+                settings.key_rotation_interval = value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "multivariant-playlist-location" => settings.multivariant_playlist_location.to_value(),
+            "muxer-type" => settings.muxer_type.to_value(),
+            "target-duration" => settings.target_duration.to_value(),
+            "playlist-length" => settings.playlist_length.to_value(),
+            "encryption-method" => settings.encryption_method.to_value(),
+            "key-uri" => settings.key_uri.to_value(),
+            "key-location" => settings.key_location.to_value(),
+            "key-rotation-interval" => settings.key_rotation_interval.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            let stream_signal = |name: &'static str| {
+                glib::subclass::Signal::builder(name)
+                    .param_types([String::static_type()])
+                    .return_type::<Option<gio::OutputStream>>()
+                    .class_handler(|_, args| {
+                        let elem = args[0]
+                            .get::<super::HlsMultivariantSink>()
+                            .expect("signal arg");
+                        let location = args[1].get::<String>().expect("signal arg");
+                        let imp = elem.imp();
+
+                        Some(imp.new_file_stream(&location).ok().to_value())
+                    })
+                    .accumulator(|_hint, ret, value| {
+                        // First signal handler wins
+                        *ret = value.clone();
+                        false
+                    })
+                    .build()
+            };
+
+            vec![
+                stream_signal(SIGNAL_GET_MULTIVARIANT_PLAYLIST_STREAM),
+                // The following signals are forwarded from the media sinks of each rendition
+                stream_signal(SIGNAL_GET_PLAYLIST_STREAM),
+                stream_signal(SIGNAL_GET_INIT_STREAM),
+                stream_signal(SIGNAL_GET_FRAGMENT_STREAM),
+                glib::subclass::Signal::builder(SIGNAL_DELETE_FRAGMENT)
+                    .param_types([String::static_type()])
+                    .return_type::<bool>()
+                    .class_handler(|_, args| {
+                        let elem = args[0]
+                            .get::<super::HlsMultivariantSink>()
+                            .expect("signal arg");
+                        let location = args[1].get::<String>().expect("signal arg");
+                        let imp = elem.imp();
+
+                        imp.delete_fragment(&location);
+                        Some(true.to_value())
+                    })
+                    .accumulator(|_hint, ret, value| {
+                        // First signal handler wins
+                        *ret = value.clone();
+                        false
+                    })
+                    .build(),
+                /**
+                 * GstHlsMultivariantSink::get-encryption-key:
+                 * @key_index: Index of the key, increased with every key rotation
+                 *
+                 * Returns the key to encrypt the segments of all renditions with, see
+                 * #GstHlsBaseSink::get-encryption-key. Each key is only requested once.
+                 *
+                 * By default a random key is created and written to the key-location.
+                 */
+                glib::subclass::Signal::builder(SIGNAL_GET_ENCRYPTION_KEY)
+                    .param_types([u32::static_type()])
+                    .return_type::<Option<gst::Structure>>()
+                    .class_handler(|_, args| {
+                        let elem = args[0]
+                            .get::<super::HlsMultivariantSink>()
+                            .expect("signal arg");
+                        let key_index = args[1].get::<u32>().expect("signal arg");
+                        let imp = elem.imp();
+
+                        Some(imp.new_encryption_key(key_index).to_value())
+                    })
+                    .accumulator(|_hint, ret, value| {
+                        // First signal handler wins
+                        *ret = value.clone();
+                        false
+                    })
+                    .build(),
+            ]
+        });
+
+        SIGNALS.as_ref()
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.set_element_flags(gst::ElementFlags::SINK);
+        obj.set_suppressed_flags(gst::ElementFlags::SINK | gst::ElementFlags::SOURCE);
+    }
+}
+
+impl GstObjectImpl for HlsMultivariantSink {}
+
+impl ElementImpl for HlsMultivariantSink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "HTTP Live Streaming multivariant sink",
+                "Sink/Muxer",
+                "HTTP Live Streaming sink writing multiple renditions and their multivariant playlist",
+                "agent <agent@local>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::new_any();
+
+            ["video_%u", "audio_%u", "subtitle_%u"]
+                .into_iter()
+                .map(|name| {
+                    gst::PadTemplate::with_gtype(
+                        name,
+                        gst::PadDirection::Sink,
+                        gst::PadPresence::Request,
+                        &caps,
+                        HlsMultivariantSinkPad::static_type(),
+                    )
+                    .unwrap()
+                })
+                .collect()
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        let ret = self.parent_change_state(transition)?;
+
+        if transition == gst::StateChange::PausedToReady {
+            let mut state = self.state.lock().unwrap();
+
+            // Media sinks are started again once new caps are received
+            for rendition in state.renditions.values_mut() {
+                rendition.sink.set_locked_state(true);
+                let _ = rendition.sink.set_state(gst::State::Null);
+                rendition.reset();
+            }
+            state.multivariant_playlist = None;
+            drop(state);
+
+            self.keys.lock().unwrap().clear();
+        }
+
+        Ok(ret)
+    }
+
+    fn request_new_pad(
+        &self,
+        templ: &gst::PadTemplate,
+        name: Option<&str>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        let muxer_type = self.settings.lock().unwrap().muxer_type;
+        let mut state = self.state.lock().unwrap();
+
+        let (rendition_type, serial) = match templ.name_template() {
+            "video_%u" => (RenditionType::Video, &mut state.video_serial),
+            "audio_%u" => (RenditionType::Audio, &mut state.audio_serial),
+            "subtitle_%u" => (RenditionType::Subtitle, &mut state.subtitle_serial),
+            other_name => {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "requested_new_pad: name \"{}\" is not video, audio or subtitle",
+                    other_name
+                );
+                return None;
+            }
+        };
+
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => {
+                let name = templ.name_template().replace("%u", &serial.to_string());
+                *serial += 1;
+                name
+            }
+        };
+
+        if state.renditions.contains_key(&name) {
+            gst::error!(CAT, imp = self, "Pad {name} already exists");
+            return None;
+        }
+
+        // Subtitles are only supported as WebVTT in CMAF
+        let use_cmaf = muxer_type == HlsMultivariantSinkMuxerType::Cmaf
+            || rendition_type == RenditionType::Subtitle;
+        let sink = if use_cmaf {
+            gst::ElementFactory::make("hlscmafsink")
+        } else {
+            gst::ElementFactory::make("hlssink3")
+        }
+        .name(format!("sink_{name}"))
+        .build()
+        .ok()?;
+
+        let mut signals = vec![SIGNAL_GET_PLAYLIST_STREAM, SIGNAL_GET_FRAGMENT_STREAM];
+        if use_cmaf {
+            signals.push(SIGNAL_GET_INIT_STREAM);
+        }
+        for signal in signals {
+            let self_weak = self.downgrade();
+            sink.connect(signal, false, move |args| {
+                let imp = self_weak.upgrade()?;
+                let location = args[1].get::<String>().expect("signal arg");
+
+                Some(
+                    imp.obj()
+                        .emit_by_name::<Option<gio::OutputStream>>(signal, &[&location])
+                        .to_value(),
+                )
+            });
+        }
+        let self_weak = self.downgrade();
+        sink.connect(SIGNAL_DELETE_FRAGMENT, false, move |args| {
+            let imp = self_weak.upgrade()?;
+            let location = args[1].get::<String>().expect("signal arg");
+
+            Some(
+                imp.obj()
+                    .emit_by_name::<bool>(SIGNAL_DELETE_FRAGMENT, &[&location])
+                    .to_value(),
+            )
+        });
+        let self_weak = self.downgrade();
+        sink.connect(SIGNAL_GET_ENCRYPTION_KEY, false, move |args| {
+            let imp = self_weak.upgrade()?;
+            let key_index = args[1].get::<u32>().expect("signal arg");
+
+            Some(imp.encryption_key(key_index).to_value())
+        });
+
+        // The media sink is only started once caps are known
+        sink.set_locked_state(true);
+        self.obj().add(&sink).unwrap();
+
+        let target = if use_cmaf {
+            sink.static_pad("sink").unwrap()
+        } else if rendition_type == RenditionType::Video {
+            sink.request_pad_simple("video").unwrap()
+        } else {
+            sink.request_pad_simple("audio").unwrap()
+        };
+
+        let pad = gst::PadBuilder::<HlsMultivariantSinkPad>::from_template(templ)
+            .name(name.as_str())
+            .build();
+        pad.set_target(Some(&target)).unwrap();
+
+        let self_weak = self.downgrade();
+        pad.add_probe(
+            gst::PadProbeType::BUFFER
+                | gst::PadProbeType::BUFFER_LIST
+                | gst::PadProbeType::EVENT_DOWNSTREAM,
+            move |pad, info| {
+                if let Some(imp) = self_weak.upgrade() {
+                    imp.sink_probe(pad, info);
+                }
+
+                gst::PadProbeReturn::Ok
+            },
+        );
+
+        state
+            .renditions
+            .insert(name, Rendition::new(pad.clone(), sink, rendition_type));
+        drop(state);
+
+        pad.set_active(true).unwrap();
+        self.obj().add_pad(&pad).unwrap();
+
+        Some(pad.upcast())
+    }
+
+    fn release_pad(&self, pad: &gst::Pad) {
+        let Some(rendition) = self
+            .state
+            .lock()
+            .unwrap()
+            .renditions
+            .remove(pad.name().as_str())
+        else {
+            return;
+        };
+
+        gst::debug!(CAT, imp = self, "Removing rendition {}", pad.name());
+
+        pad.set_active(false).unwrap();
+        self.obj().remove_pad(pad).unwrap();
+
+        let _ = rendition.sink.set_state(gst::State::Null);
+        let _ = self.obj().remove(&rendition.sink);
+
+        self.update_multivariant_playlist();
+    }
+}
+
+impl BinImpl for HlsMultivariantSink {}
+
+impl HlsMultivariantSink {
+    fn sink_probe(&self, pad: &HlsMultivariantSinkPad, info: &gst::PadProbeInfo) {
+        match info.data {
+            Some(gst::PadProbeData::Buffer(ref buffer)) => {
+                self.handle_buffers(pad, std::iter::once(&**buffer));
+            }
+            Some(gst::PadProbeData::BufferList(ref list)) => {
+                self.handle_buffers(pad, list.iter());
+            }
+            Some(gst::PadProbeData::Event(ref event)) => {
+                self.handle_event(pad, event);
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_buffers<'a>(
+        &self,
+        pad: &HlsMultivariantSinkPad,
+        buffers: impl Iterator<Item = &'a gst::BufferRef>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let Some(rendition) = state.renditions.get_mut(pad.name().as_str()) else {
+            return;
+        };
+
+        let mut changed = false;
+        for buffer in buffers {
+            changed |= rendition.measure(buffer.size(), buffer.pts(), buffer.duration());
+        }
+
+        if changed {
+            gst::debug!(
+                CAT,
+                obj = pad,
+                "Measured bitrate {:?}",
+                rendition.measured_bitrate
+            );
+            drop(state);
+            self.update_multivariant_playlist();
+        }
+    }
+
+    fn handle_event(&self, pad: &HlsMultivariantSinkPad, event: &gst::Event) {
+        use gst::EventView;
+
+        let mut state = self.state.lock().unwrap();
+        let Some(rendition) = state.renditions.get_mut(pad.name().as_str()) else {
+            return;
+        };
+
+        match event.view() {
+            EventView::Caps(ev) => {
+                gst::debug!(CAT, obj = pad, "Received caps {:?}", ev.caps());
+                rendition.caps = Some(ev.caps_owned());
+
+                if !rendition.started {
+                    rendition.started = true;
+                    let sink = rendition.sink.clone();
+                    let rendition_type = rendition.rendition_type;
+                    drop(state);
+
+                    // Caps are only forwarded to the media sink after the probe returns
+                    self.start_rendition(pad, &sink, rendition_type);
+                } else {
+                    drop(state);
+                }
+            }
+            EventView::Tag(ev) => {
+                let tags = ev.tag();
+                let Some(bitrate) = tags
+                    .get::<gst::tags::MaximumBitrate>()
+                    .or_else(|| tags.get::<gst::tags::Bitrate>())
+                    .map(|v| v.get())
+                else {
+                    return;
+                };
+
+                if rendition.tag_bitrate == Some(bitrate) {
+                    return;
+                }
+                rendition.tag_bitrate = Some(bitrate);
+                drop(state);
+            }
+            EventView::Eos(_) => {
+                gst::debug!(CAT, obj = pad, "Rendition ended");
+                rendition.eos = true;
+                drop(state);
+            }
+            EventView::StreamStart(_) | EventView::FlushStop(_) => {
+                if !rendition.eos {
+                    return;
+                }
+                rendition.eos = false;
+                drop(state);
+            }
+            _ => return,
+        }
+
+        self.update_multivariant_playlist();
+    }
+
+    /// Configures and starts the media sink of a rendition.
+    fn start_rendition(
+        &self,
+        pad: &HlsMultivariantSinkPad,
+        sink: &gst::Element,
+        rendition_type: RenditionType,
+    ) {
+        let pad_settings = pad.imp().settings();
+        let settings = self.settings.lock().unwrap();
+
+        let uri = pad_settings
+            .uri
+            .unwrap_or_else(|| format!("{}/playlist.m3u8", pad.name()));
+        let playlist_location = path::Path::new(&settings.multivariant_playlist_location)
+            .parent()
+            .unwrap_or_else(|| path::Path::new(""))
+            .join(uri);
+        let dir = playlist_location
+            .parent()
+            .unwrap_or_else(|| path::Path::new(""));
+
+        gst::info!(
+            CAT,
+            obj = pad,
+            "Starting rendition with playlist {}",
+            playlist_location.display()
+        );
+
+        sink.set_property("playlist-location", playlist_location.to_str().unwrap());
+        sink.set_property("target-duration", settings.target_duration);
+        sink.set_property("playlist-length", settings.playlist_length);
+        // Subtitles are never encrypted
+        let encryption_method = if rendition_type == RenditionType::Subtitle {
+            HlsEncryptionMethod::None
+        } else {
+            settings.encryption_method
+        };
+        sink.set_property("encryption-method", encryption_method);
+        sink.set_property("key-rotation-interval", settings.key_rotation_interval);
+        if sink.has_property("init-location", None) {
+            sink.set_property("init-location", dir.join("init%05d.mp4").to_str().unwrap());
+            sink.set_property("location", dir.join("segment%05d.m4s").to_str().unwrap());
+        } else {
+            sink.set_property("location", dir.join("segment%05d.ts").to_str().unwrap());
+        }
+        drop(settings);
+
+        sink.set_locked_state(false);
+        if sink.sync_state_with_parent().is_err() {
+            gst::element_imp_error!(
+                self,
+                gst::CoreError::StateChange,
+                ["Failed to start media sink for {}", pad.name()]
+            );
+        }
+    }
+
+    /// Creates the multivariant playlist from all currently active renditions.
+    ///
+    /// Renditions are active once they received caps and until they are finished. Once all
+    /// renditions are finished the multivariant playlist contains all of them again.
+    fn multivariant_playlist(&self, state: &State) -> Option<MasterPlaylist> {
+        let all_eos = state
+            .renditions
+            .values()
+            .filter(|r| r.started)
+            .all(|r| r.eos);
+
+        let renditions = state
+            .renditions
+            .iter()
+            .filter(|(_, r)| r.started && r.caps.is_some() && (all_eos || !r.eos))
+            .map(|(name, r)| {
+                let settings = r.pad.imp().settings();
+                let uri = settings
+                    .uri
+                    .clone()
+                    .unwrap_or_else(|| format!("{name}/playlist.m3u8"));
+                (name, r, settings, uri)
+            })
+            .collect::<Vec<_>>();
+
+        let group_id = |r: &Rendition, settings: &super::pad::Settings| {
+            settings.group_id.clone().unwrap_or_else(|| {
+                String::from(if r.rendition_type == RenditionType::Audio {
+                    "audio"
+                } else {
+                    "subtitles"
+                })
+            })
+        };
+
+        let mut alternatives = Vec::new();
+        for (name, r, settings, uri) in &renditions {
+            let media_type = match r.rendition_type {
+                RenditionType::Video => continue,
+                RenditionType::Audio => AlternativeMediaType::Audio,
+                RenditionType::Subtitle => AlternativeMediaType::Subtitles,
+            };
+
+            let channels = r
+                .caps
+                .as_ref()
+                .and_then(|caps| caps.structure(0))
+                .and_then(|s| s.get::<i32>("channels").ok())
+                .map(|channels| channels.to_string());
+
+            alternatives.push(AlternativeMedia {
+                media_type,
+                uri: Some(uri.clone()),
+                group_id: group_id(r, settings),
+                language: settings.language.clone(),
+                name: settings
+                    .rendition_name
+                    .clone()
+                    .unwrap_or_else(|| name.to_string()),
+                default: settings.default,
+                autoselect: true,
+                channels,
+                ..Default::default()
+            });
+        }
+
+        let audio_renditions = |group: &str| {
+            renditions
+                .iter()
+                .filter(|(_, r, settings, _)| {
+                    r.rendition_type == RenditionType::Audio && group_id(r, settings) == group
+                })
+                .collect::<Vec<_>>()
+        };
+        let has_group = |group: &str| alternatives.iter().any(|alt| alt.group_id == group);
+
+        let mut variants = Vec::new();
+        for (_, r, settings, uri) in &renditions {
+            if r.rendition_type != RenditionType::Video {
+                continue;
+            }
+
+            let Some(bandwidth) = r.bandwidth(settings) else {
+                continue;
+            };
+
+            let mut codecs = r.codec().into_iter().collect::<Vec<_>>();
+            let mut audio_bandwidth = 0;
+            for (_, audio, audio_settings, _) in audio_renditions(&settings.audio_group_id) {
+                audio_bandwidth = audio_bandwidth.max(audio.bandwidth(audio_settings).unwrap_or(0));
+                if let Some(codec) = audio.codec() {
+                    if !codecs.contains(&codec) {
+                        codecs.push(codec);
+                    }
+                }
+            }
+
+            let s = r.caps.as_ref().and_then(|caps| caps.structure(0));
+            let resolution = s.and_then(|s| {
+                Some(Resolution {
+                    width: s.get::<i32>("width").ok()? as u64,
+                    height: s.get::<i32>("height").ok()? as u64,
+                })
+            });
+            let frame_rate = s
+                .and_then(|s| s.get::<gst::Fraction>("framerate").ok())
+                .filter(|fps| fps.numer() > 0 && fps.denom() > 0)
+                .map(|fps| fps.numer() as f64 / fps.denom() as f64);
+
+            variants.push(VariantStream {
+                uri: uri.clone(),
+                bandwidth: bandwidth + audio_bandwidth,
+                codecs: (!codecs.is_empty()).then(|| codecs.join(",")),
+                resolution,
+                frame_rate,
+                audio: has_group(&settings.audio_group_id).then(|| settings.audio_group_id.clone()),
+                subtitles: has_group(&settings.subtitle_group_id)
+                    .then(|| settings.subtitle_group_id.clone()),
+                ..Default::default()
+            });
+        }
+
+        // Without video, each audio rendition is a variant of its own
+        if variants.is_empty() {
+            alternatives.clear();
+
+            for (_, r, settings, uri) in &renditions {
+                if r.rendition_type != RenditionType::Audio {
+                    continue;
+                }
+
+                let Some(bandwidth) = r.bandwidth(settings) else {
+                    continue;
+                };
+
+                variants.push(VariantStream {
+                    uri: uri.clone(),
+                    bandwidth,
+                    codecs: r.codec(),
+                    ..Default::default()
+                });
+            }
+        }
+
+        if variants.is_empty() {
+            return None;
+        }
+
+        // Allow players to preload the current key
+        let settings = self.settings.lock().unwrap();
+        let session_key = self
+            .keys
+            .lock()
+            .unwrap()
+            .values()
+            .next_back()
+            .and_then(|s| SegmentKey::from_structure(s).ok())
+            .map(|key| SessionKey(key.to_m3u8_key(settings.encryption_method)))
+            .into_iter()
+            .collect();
+        drop(settings);
+
+        Some(MasterPlaylist {
+            version: Some(
+                if self.settings.lock().unwrap().muxer_type == HlsMultivariantSinkMuxerType::Cmaf {
+                    6
+                } else {
+                    4
+                },
+            ),
+            variants,
+            alternatives,
+            session_key,
+            independent_segments: true,
+            ..Default::default()
+        })
+    }
+
+    /// Writes the multivariant playlist if its content changed.
+    fn update_multivariant_playlist(&self) {
+        let (content, serial) = {
+            let mut state = self.state.lock().unwrap();
+
+            let Some(playlist) = self.multivariant_playlist(&state) else {
+                gst::debug!(CAT, imp = self, "No complete variant yet");
+                return;
+            };
+
+            let mut content = Vec::new();
+            playlist.write_to(&mut content).unwrap();
+            let content = String::from_utf8(content).unwrap();
+
+            if state.multivariant_playlist.as_ref() == Some(&content) {
+                return;
+            }
+
+            state.multivariant_playlist = Some(content.clone());
+            state.multivariant_playlist_serial += 1;
+            (content, state.multivariant_playlist_serial)
+        };
+
+        // The stream is requested and written without the state lock. Playlists that were
+        // rendered concurrently are written in order, and outdated ones are skipped.
+        let mut written_serial = self.written_multivariant_playlist.lock().unwrap();
+        if *written_serial > serial {
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Newer multivariant playlist already written"
+            );
+            return;
+        }
+
+        let location = self
+            .settings
+            .lock()
+            .unwrap()
+            .multivariant_playlist_location
+            .clone();
+
+        let Some(stream) = self.obj().emit_by_name::<Option<gio::OutputStream>>(
+            SIGNAL_GET_MULTIVARIANT_PLAYLIST_STREAM,
+            &[&location],
+        ) else {
+            gst::error!(
+                CAT,
+                imp = self,
+                "Could not get stream to write multivariant playlist content"
+            );
+            self.multivariant_playlist_not_written(serial);
+            return;
+        };
+
+        let mut stream = stream.into_write();
+        if let Err(err) = stream
+            .write_all(content.as_bytes())
+            .and_then(|_| stream.flush())
+        {
+            gst::error!(
+                CAT,
+                imp = self,
+                "Could not write multivariant playlist: {}",
+                err.to_string()
+            );
+            self.multivariant_playlist_not_written(serial);
+            return;
+        }
+
+        gst::debug!(CAT, imp = self, "Wrote new multivariant playlist");
+        *written_serial = serial;
+    }
+
+    /// Makes sure that the next update writes the multivariant playlist again if the one with
+    /// the given serial is still the latest.
+    fn multivariant_playlist_not_written(&self, serial: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.multivariant_playlist_serial == serial {
+            state.multivariant_playlist = None;
+        }
+    }
+
+    /// Returns the key with the given index, requesting it only once for all renditions.
+    fn encryption_key(&self, key_index: u32) -> Option<gst::Structure> {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(key) = keys.get(&key_index) {
+            return Some(key.clone());
+        }
+
+        let key = self
+            .obj()
+            .emit_by_name::<Option<gst::Structure>>(SIGNAL_GET_ENCRYPTION_KEY, &[&key_index])?;
+        keys.insert(key_index, key.clone());
+        drop(keys);
+
+        gst::debug!(CAT, imp = self, "New encryption key {key_index}");
+        self.update_multivariant_playlist();
+
+        Some(key)
+    }
+
+    /// Creates a random key with the given index, writing it to the key location.
+    fn new_encryption_key(&self, key_index: u32) -> Option<gst::Structure> {
+        let settings = self.settings.lock().unwrap();
+        let key_uri = settings.key_uri.clone();
+        let key_location = settings.key_location.as_ref().map(|location| {
+            path::Path::new(&settings.multivariant_playlist_location)
+                .parent()
+                .unwrap_or_else(|| path::Path::new(""))
+                .join(location)
+        });
+        drop(settings);
+
+        let format = |template: &str| {
+            encryption::format_key_template(template, key_index)
+                .map_err(|err| {
+                    gst::error!(CAT, imp = self, "Couldn't build key location, err: {}", err);
+                })
+                .ok()
+        };
+
+        let Some(uri) = key_uri.and_then(|template| format(&template)) else {
+            gst::element_imp_error!(
+                self,
+                gst::LibraryError::Settings,
+                ["Encryption enabled but no valid key-uri configured"]
+            );
+            return None;
+        };
+
+        let key: [u8; 16] = rand::random();
+        if let Some(location) = key_location {
+            let location = format(location.to_str().unwrap())?;
+            let mut stream = self.new_file_stream(&location).ok()?.into_write();
+            if let Err(err) = stream.write_all(&key).and_then(|_| stream.flush()) {
+                gst::element_imp_error!(
+                    self,
+                    gst::ResourceError::Write,
+                    ["Could not write key {}: {}", location, err]
+                );
+                return None;
+            }
+        }
+
+        Some(SegmentKey { key, iv: None, uri }.to_structure())
+    }
+
+    fn new_file_stream<P>(&self, location: &P) -> Result<gio::OutputStream, String>
+    where
+        P: AsRef<path::Path>,
+    {
+        let location = location.as_ref();
+
+        if let Some(dir) = location.parent() {
+            if !dir.as_os_str().is_empty() {
+                let _ = fs::create_dir_all(dir);
+            }
+        }
+
+        let file = fs::File::create(location).map_err(move |err| {
+            let error_msg = gst::error_msg!(
+                gst::ResourceError::OpenWrite,
+                [
+                    "Could not open file {} for writing: {}",
+                    location.display(),
+                    err.to_string(),
+                ]
+            );
+            self.post_error_message(error_msg);
+            err.to_string()
+        })?;
+        Ok(gio::WriteOutputStream::new(file).upcast())
+    }
+
+    fn delete_fragment<P>(&self, location: &P)
+    where
+        P: AsRef<path::Path>,
+    {
+        let _ = fs::remove_file(location).map_err(|err| {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Could not delete segment file: {}",
+                err.to_string()
+            );
+        });
+    }
+}
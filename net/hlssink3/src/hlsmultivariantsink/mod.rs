@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: MPL-2.0
+#![allow(clippy::non_send_fields_in_send_ty, unused_doc_comments)]
+
+/**
+ * plugin-hlssink3:
+ *
+ * Since: plugins-rs-0.8.0
+ */
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+mod pad;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstHlsMultivariantSinkMuxerType")]
+#[non_exhaustive]
+pub enum HlsMultivariantSinkMuxerType {
+    #[enum_value(
+        name = "CMAF: Write renditions as fragmented MP4 via hlscmafsink",
+        nick = "cmaf"
+    )]
+    Cmaf = 0,
+
+    #[enum_value(
+        name = "MPEG-TS: Write renditions as MPEG-TS via hlssink3",
+        nick = "mpegts"
+    )]
+    MpegTs = 1,
+}
+
+glib::wrapper! {
+    pub struct HlsMultivariantSink(ObjectSubclass<imp::HlsMultivariantSink>) @extends gst::Bin, gst::Element, gst::Object;
+}
+
+glib::wrapper! {
+    pub struct HlsMultivariantSinkPad(ObjectSubclass<pad::HlsMultivariantSinkPad>) @extends gst::GhostPad, gst::ProxyPad, gst::Pad, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    #[cfg(feature = "doc")]
+    {
+        HlsMultivariantSinkMuxerType::static_type()
+            .mark_as_plugin_api(gst::PluginAPIFlags::empty());
+        HlsMultivariantSinkPad::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    }
+
+    gst::Element::register(
+        Some(plugin),
+        "hlsmultivariantsink",
+        gst::Rank::NONE,
+        HlsMultivariantSink::static_type(),
+    )?;
+
+    Ok(())
+}
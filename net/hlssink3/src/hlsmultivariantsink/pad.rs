@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+
+const DEFAULT_AUDIO_GROUP_ID: &str = "audio";
+const DEFAULT_SUBTITLE_GROUP_ID: &str = "subtitles";
+const DEFAULT_DEFAULT: bool = false;
+const DEFAULT_BANDWIDTH: u32 = 0;
+
+#[derive(Debug, Clone)]
+pub(super) struct Settings {
+    /// Location of the media playlist relative to the multivariant playlist.
+    pub uri: Option<String>,
+    /// Name of the audio or subtitle rendition.
+    pub rendition_name: Option<String>,
+    pub language: Option<String>,
+    /// Group of audio and subtitle renditions.
+    pub group_id: Option<String>,
+    /// Groups referenced by video renditions.
+    pub audio_group_id: String,
+    pub subtitle_group_id: String,
+    pub default: bool,
+    pub bandwidth: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            uri: None,
+            rendition_name: None,
+            language: None,
+            group_id: None,
+            audio_group_id: String::from(DEFAULT_AUDIO_GROUP_ID),
+            subtitle_group_id: String::from(DEFAULT_SUBTITLE_GROUP_ID),
+            default: DEFAULT_DEFAULT,
+            bandwidth: DEFAULT_BANDWIDTH,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HlsMultivariantSinkPad {
+    settings: Mutex<Settings>,
+}
+
+impl HlsMultivariantSinkPad {
+    pub(super) fn settings(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for HlsMultivariantSinkPad {
+    const NAME: &'static str = "GstHlsMultivariantSinkPad";
+    type Type = super::HlsMultivariantSinkPad;
+    type ParentType = gst::GhostPad;
+}
+
+impl ObjectImpl for HlsMultivariantSinkPad {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::builder("uri")
+                    .nick("URI")
+                    .blurb("Location of the media playlist relative to the multivariant playlist (default = <pad name>/playlist.m3u8)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("rendition-name")
+                    .nick("Rendition name")
+                    .blurb("Name of the audio or subtitle rendition (default = pad name)")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("language")
+                    .nick("Language")
+                    .blurb("Language of the audio or subtitle rendition")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("group-id")
+                    .nick("Group ID")
+                    .blurb("Group of the audio or subtitle rendition (default = \"audio\" or \"subtitles\")")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("audio-group-id")
+                    .nick("Audio group ID")
+                    .blurb("Group of audio renditions used by the video rendition")
+                    .default_value(Some(DEFAULT_AUDIO_GROUP_ID))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("subtitle-group-id")
+                    .nick("Subtitle group ID")
+                    .blurb("Group of subtitle renditions used by the video rendition")
+                    .default_value(Some(DEFAULT_SUBTITLE_GROUP_ID))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("default")
+                    .nick("Default")
+                    .blurb("Whether the audio or subtitle rendition is the default of its group")
+                    .default_value(DEFAULT_DEFAULT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("bandwidth")
+                    .nick("Bandwidth")
+                    .blurb("Peak bitrate of the rendition in bits per second (0 = from tags or measured)")
+                    .default_value(DEFAULT_BANDWIDTH)
+                    .mutable_playing()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "uri" => {
+                settings.uri = value.get().expect("type checked upstream");
+            }
+            "rendition-name" => {
+                settings.rendition_name = value.get().expect("type checked upstream");
+            }
+            "language" => {
+                settings.language = value.get().expect("type checked upstream");
+            }
+            "group-id" => {
+                settings.group_id = value.get().expect("type checked upstream");
+            }
+            "audio-group-id" => {
+                settings.audio_group_id = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_AUDIO_GROUP_ID.into());
+            }
+            "subtitle-group-id" => {
+                settings.subtitle_group_id = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_SUBTITLE_GROUP_ID.into());
+            }
+            "default" => {
+                settings.default = value.get().expect("type checked upstream");
+            }
+            "bandwidth" => {
+                settings.bandwidth = value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "uri" => settings.uri.to_value(),
+            "rendition-name" => settings.rendition_name.to_value(),
+            "language" => settings.language.to_value(),
+            "group-id" => settings.group_id.to_value(),
+            "audio-group-id" => settings.audio_group_id.to_value(),
+            "subtitle-group-id" => settings.subtitle_group_id.to_value(),
+            "default" => settings.default.to_value(),
+            "bandwidth" => settings.bandwidth.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for HlsMultivariantSinkPad {}
+impl PadImpl for HlsMultivariantSinkPad {}
+impl ProxyPadImpl for HlsMultivariantSinkPad {}
+impl GhostPadImpl for HlsMultivariantSinkPad {}
@@ -10,7 +10,7 @@
 use crate::hlsbasesink::HlsBaseSinkImpl;
 use crate::hlssink3::HlsSink3PlaylistType;
 use crate::playlist::Playlist;
-use crate::HlsBaseSink;
+use crate::{HlsBaseSink, HlsEncryptionMethod};
 use gio::prelude::*;
 use gst::glib;
 use gst::prelude::*;
@@ -125,6 +125,8 @@ struct HlsSink3State {
     fragment_opened_at: Option<gst::ClockTime>,
     fragment_running_time: Option<gst::ClockTime>,
     current_segment_location: Option<String>,
+    /// Stream of the current segment if it is encrypted.
+    encrypted_stream: Option<gio::OutputStream>,
 }
 
 #[derive(Default)]
@@ -333,6 +335,15 @@ impl ElementImpl for HlsSink3 {
         transition: gst::StateChange,
     ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
         if transition == gst::StateChange::ReadyToPaused {
+            if base_imp!(self).encryption_method() == HlsEncryptionMethod::SampleAes {
+                gst::element_imp_error!(
+                    self,
+                    gst::LibraryError::Settings,
+                    ["SAMPLE-AES encryption is not supported for MPEG-TS segments"]
+                );
+                return Err(gst::StateChangeError);
+            }
+
             let (target_duration, playlist_type, i_frames_only, segment_template) = {
                 let settings = self.settings.lock().unwrap();
                 (
@@ -527,6 +538,9 @@ impl HlsSink3 {
         let mut state = self.state.lock().unwrap();
         state.current_segment_location = Some(segment_file_location.clone());
         state.fragment_running_time = running_time;
+        state.encrypted_stream = (base_imp!(self).encryption_method()
+            == HlsEncryptionMethod::Aes128)
+            .then(|| fragment_stream.clone());
 
         let settings = self.settings.lock().unwrap();
         settings
@@ -563,8 +577,17 @@ impl HlsSink3 {
 
         let duration = ((closed_at - opened_at).mseconds() as f32) / 1_000f32;
         let running_time = state.fragment_running_time;
+        let encrypted_stream = state.encrypted_stream.take();
         drop(state);
 
+        // The final encrypted block is only written once the stream is closed, which has to
+        // happen before the segment is added to the playlist
+        if let Some(stream) = encrypted_stream {
+            if let Err(err) = stream.close(gio::Cancellable::NONE) {
+                gst::error!(CAT, imp = self, "Failed to close encrypted segment: {err}");
+            }
+        }
+
         let obj = self.obj();
         let base_imp = obj.upcast_ref::<HlsBaseSink>().imp();
         let uri = base_imp.get_segment_uri(&location);
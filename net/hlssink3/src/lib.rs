@@ -14,11 +14,34 @@
  */
 use gst::glib;
 
+mod encryption;
 mod hlsbasesink;
 pub mod hlscmafsink;
+pub mod hlsmultivariantsink;
 pub mod hlssink3;
 mod playlist;
 
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstHlsEncryptionMethod")]
+#[non_exhaustive]
+pub enum HlsEncryptionMethod {
+    #[enum_value(name = "None: Segments are not encrypted", nick = "none")]
+    None = 0,
+
+    #[enum_value(
+        name = "AES-128: Encrypt complete MPEG-TS segments with AES-128-CBC",
+        nick = "aes-128"
+    )]
+    Aes128 = 1,
+
+    #[enum_value(
+        name = "SAMPLE-AES: Encrypt the samples of CMAF segments with the cbcs scheme",
+        nick = "sample-aes"
+    )]
+    SampleAes = 2,
+}
+
 glib::wrapper! {
     pub struct HlsBaseSink(ObjectSubclass<hlsbasesink::HlsBaseSink>) @extends gst::Bin, gst::Element, gst::Object;
 }
@@ -28,10 +51,12 @@ pub fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     {
         use gst::prelude::*;
         HlsBaseSink::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+        HlsEncryptionMethod::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
     }
 
     hlssink3::register(plugin)?;
     hlscmafsink::register(plugin)?;
+    hlsmultivariantsink::register(plugin)?;
 
     Ok(())
 }
@@ -6,9 +6,48 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use m3u8_rs::{MediaPlaylist, MediaPlaylistType, MediaSegment};
+use m3u8_rs::{ExtTag, MediaPlaylist, MediaPlaylistType, MediaSegment};
 use std::io::Write;
 
+/// Tag of the partial segments in the playlist.
+const PART_TAG: &str = "X-PART";
+
+/// A partial segment for Low-Latency HLS.
+#[derive(Debug, Clone)]
+pub struct PartialSegment {
+    pub uri: String,
+    pub duration: f32,
+    /// Length and offset of the partial segment in the segment file.
+    pub byte_range: (u64, u64),
+    /// Whether the partial segment starts with an independent frame.
+    pub independent: bool,
+}
+
+impl PartialSegment {
+    fn to_ext_tag(&self) -> ExtTag {
+        let mut rest = format!(
+            "DURATION={},URI=\"{}\",BYTERANGE=\"{}@{}\"",
+            self.duration, self.uri, self.byte_range.0, self.byte_range.1
+        );
+        if self.independent {
+            rest.push_str(",INDEPENDENT=YES");
+        }
+
+        ExtTag {
+            tag: String::from(PART_TAG),
+            rest: Some(rest),
+        }
+    }
+}
+
+/// Last media sequence number and partial segment of another rendition.
+#[derive(Debug, Clone)]
+pub struct RenditionReport {
+    pub uri: String,
+    pub last_msn: u64,
+    pub last_part: Option<u64>,
+}
+
 /// An HLS playlist.
 ///
 /// Controls the changes that needs to happen in the playlist as new segments are added. This
@@ -21,6 +60,20 @@ pub struct Playlist {
     status: PlaylistRenderState,
     turn_vod: bool,
     is_cmaf: bool,
+
+    /// Part target duration if Low-Latency HLS is enabled.
+    part_target: Option<f32>,
+    /// Partial segments of the segment that is currently written.
+    pending_parts: Vec<PartialSegment>,
+    /// Init segment of the segment that is currently written, if it changed.
+    pending_map: Option<m3u8_rs::Map>,
+    /// Key of the segment that is currently written.
+    pending_key: Option<m3u8_rs::Key>,
+    /// Key of the last segment added to the playlist.
+    last_key: Option<m3u8_rs::Key>,
+    /// URI and offset of the next partial segment.
+    preload_hint: Option<(String, u64)>,
+    rendition_reports: Vec<RenditionReport>,
 }
 
 impl Playlist {
@@ -31,13 +84,131 @@ impl Playlist {
             status: PlaylistRenderState::Init,
             turn_vod,
             is_cmaf,
+            part_target: None,
+            pending_parts: Vec::new(),
+            pending_map: None,
+            pending_key: None,
+            last_key: None,
+            preload_hint: None,
+            rendition_reports: Vec::new(),
+        }
+    }
+
+    /// Enables Low-Latency HLS with partial segments of the given target duration.
+    pub fn enable_low_latency(&mut self, part_target: f32, can_block_reload: bool) {
+        self.part_target = Some(part_target);
+
+        // Players should stay at least three part target durations behind the live edge
+        let mut server_control = format!("PART-HOLD-BACK={}", 3.0 * part_target);
+        if can_block_reload {
+            server_control.insert_str(0, "CAN-BLOCK-RELOAD=YES,");
         }
+        self.inner.unknown_tags.push(ExtTag {
+            tag: String::from("X-SERVER-CONTROL"),
+            rest: Some(server_control),
+        });
+        self.inner.unknown_tags.push(ExtTag {
+            tag: String::from("X-PART-INF"),
+            rest: Some(format!("PART-TARGET={part_target}")),
+        });
     }
 
     /// Adds a new segment to the playlist.
-    pub fn add_segment(&mut self, segment: MediaSegment) {
+    ///
+    /// All pending partial segments are moved to this segment. The key of the segment is only
+    /// written if it changed.
+    pub fn add_segment(&mut self, mut segment: MediaSegment) {
         self.start();
+
+        if segment.key.is_some() {
+            if segment.key == self.last_key {
+                segment.key = None;
+            } else {
+                self.last_key.clone_from(&segment.key);
+            }
+        }
+
+        if !self.pending_parts.is_empty() {
+            segment
+                .unknown_tags
+                .extend(self.pending_parts.drain(..).map(|part| part.to_ext_tag()));
+        }
+        self.pending_map = None;
+        self.pending_key = None;
+        self.preload_hint = None;
+
         self.inner.segments.push(segment);
+
+        // Only keep partial segments of the last three target durations
+        if self.part_target.is_some() {
+            let mut duration = 0.0;
+            for segment in self.inner.segments.iter_mut().rev() {
+                if duration > 3.0 * self.inner.target_duration {
+                    segment.unknown_tags.retain(|tag| tag.tag != PART_TAG);
+                }
+                duration += segment.duration;
+            }
+        }
+    }
+
+    /// Adds a partial segment of the segment that is currently written.
+    ///
+    /// `map` is the init segment if it changed with this segment, `key` the key the segment is
+    /// encrypted with and `preload_hint` the URI and offset of the next partial segment.
+    pub fn add_partial_segment(
+        &mut self,
+        part: PartialSegment,
+        map: Option<m3u8_rs::Map>,
+        key: Option<m3u8_rs::Key>,
+        preload_hint: Option<(String, u64)>,
+    ) {
+        self.start();
+
+        if map.is_some() {
+            self.pending_map = map;
+        }
+        self.pending_key = key;
+        self.pending_parts.push(part);
+        self.preload_hint = preload_hint;
+    }
+
+    /// Sets the rendition reports to write at the end of the playlist.
+    pub fn set_rendition_reports(&mut self, rendition_reports: Vec<RenditionReport>) {
+        self.rendition_reports = rendition_reports;
+    }
+
+    /// Returns the media sequence number of the segment that is currently written.
+    pub fn next_media_sequence(&self) -> u64 {
+        self.inner.media_sequence + self.inner.segments.len() as u64
+    }
+
+    /// Returns the media sequence number and index of the partial segment that was added last.
+    pub fn last_part(&self) -> Option<(u64, Option<u64>)> {
+        let next_msn = self.next_media_sequence();
+
+        if !self.pending_parts.is_empty() {
+            return Some((next_msn, Some(self.pending_parts.len() as u64 - 1)));
+        }
+
+        let last_segment = self.inner.segments.last()?;
+        let num_parts = last_segment
+            .unknown_tags
+            .iter()
+            .filter(|tag| tag.tag == PART_TAG)
+            .count() as u64;
+
+        Some((next_msn - 1, num_parts.checked_sub(1)))
+    }
+
+    /// Returns true if the playlist contains the segment with the given media sequence number,
+    /// or the given partial segment of it.
+    pub fn contains(&self, msn: u64, part: Option<u64>) -> bool {
+        let next_msn = self.next_media_sequence();
+
+        match part {
+            Some(part) if msn == next_msn => part < self.pending_parts.len() as u64,
+            _ => msn < next_msn,
+        }
     }
 
     /// Updates the playlist based on current state.
@@ -53,18 +224,17 @@ impl Playlist {
 
         // Remove oldest segments if playlist is at maximum expected capacity
         if max_playlist_length > 0 {
-            if self.is_cmaf {
-                // init segment uri will be specified only if it's updated
-                // or in case of the very first segment.
-                while self.inner.segments.len() > max_playlist_length {
-                    let to_remove = self.inner.segments.remove(0);
-                    if self.inner.segments[0].map.is_none() {
-                        self.inner.segments[0].map.clone_from(&to_remove.map)
-                    }
+            // init segment uri and key will be specified only if they're updated
+            // or in case of the very first segment.
+            while self.inner.segments.len() > max_playlist_length {
+                let to_remove = self.inner.segments.remove(0);
+                let first = &mut self.inner.segments[0];
+                if self.is_cmaf && first.map.is_none() {
+                    first.map.clone_from(&to_remove.map)
+                }
+                if first.key.is_none() {
+                    first.key.clone_from(&to_remove.key)
                 }
-            } else if self.inner.segments.len() > max_playlist_length {
-                let remove_len = self.inner.segments.len() - max_playlist_length;
-                self.inner.segments.drain(0..remove_len);
             }
         }
 
@@ -91,6 +261,11 @@ impl Playlist {
         self.inner.playlist_type.is_none()
     }
 
+    /// Returns true if the playlist has ended.
+    pub fn is_ended(&self) -> bool {
+        self.inner.end_list
+    }
+
     /// Returns true if the playlist internal status started.
     pub fn is_rendering(&self) -> bool {
         self.status == PlaylistRenderState::Started
@@ -103,8 +278,64 @@ impl Playlist {
 
     /// Writes the playlist in textual format to the provided `Write` reference.
     pub fn write_to<T: Write>(&self, w: &mut T) -> std::io::Result<()> {
-        self.inner.write_to(w)
+        self.inner.write_to(w)?;
+
+        if self.inner.end_list {
+            return Ok(());
+        }
+
+        // Partial segments of the segment that is currently written follow all complete segments
+        if let Some(ref key) = self.pending_key {
+            if self.last_key.as_ref() != Some(key) {
+                write_key(w, key)?;
+            }
+        }
+        if let Some(ref map) = self.pending_map {
+            writeln!(w, "#EXT-X-MAP:URI=\"{}\"", map.uri)?;
+        }
+        for part in &self.pending_parts {
+            writeln!(w, "{}", part.to_ext_tag())?;
+        }
+        if let Some((ref uri, offset)) = self.preload_hint {
+            writeln!(
+                w,
+                "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{uri}\",BYTERANGE-START={offset}"
+            )?;
+        }
+
+        for report in &self.rendition_reports {
+            write!(
+                w,
+                "#EXT-X-RENDITION-REPORT:URI=\"{}\",LAST-MSN={}",
+                report.uri, report.last_msn
+            )?;
+            if let Some(last_part) = report.last_part {
+                write!(w, ",LAST-PART={last_part}")?;
+            }
+            writeln!(w)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes an `EXT-X-KEY` tag.
+fn write_key<T: Write>(w: &mut T, key: &m3u8_rs::Key) -> std::io::Result<()> {
+    let method = match key.method {
+        m3u8_rs::KeyMethod::None => "NONE",
+        m3u8_rs::KeyMethod::AES128 => "AES-128",
+        m3u8_rs::KeyMethod::SampleAES => "SAMPLE-AES",
+        m3u8_rs::KeyMethod::Other(ref method) => method,
+    };
+
+    write!(w, "#EXT-X-KEY:METHOD={method}")?;
+    if let Some(ref uri) = key.uri {
+        write!(w, ",URI=\"{uri}\"")?;
+    }
+    if let Some(ref iv) = key.iv {
+        write!(w, ",IV={iv}")?;
     }
+    writeln!(w)
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -7,9 +7,11 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use gio::prelude::*;
+use gst::glib;
 use gst::prelude::*;
 use gsthlssink3::hlssink3::HlsSink3PlaylistType;
 use once_cell::sync::Lazy;
+use std::collections::HashMap;
 use std::io::Write;
 use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
@@ -440,3 +442,931 @@ segments/my-own-filename-000.ts
 
     Ok(())
 }
+
+#[test]
+fn test_hlscmafsink_low_latency_playlist() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 90;
+
+    let pipeline = gst::Pipeline::with_name("video_pipeline");
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let capsfilter = try_create_element!("capsfilter");
+    capsfilter.set_property(
+        "caps",
+        gst::Caps::builder("video/x-raw")
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build(),
+    );
+    let x264enc = try_create_element!("x264enc");
+    x264enc.set_property("key-int-max", 30u32);
+    let h264parse = try_create_element!("h264parse");
+
+    let hlscmafsink = gst::ElementFactory::make("hlscmafsink")
+        .name("test_hlscmafsink")
+        .property("target-duration", 1u32)
+        .property("part-duration", gst::ClockTime::from_mseconds(200))
+        .property("blocking-reload", true)
+        .build()
+        .expect("Must be able to instantiate hlscmafsink");
+
+    let playlist_content = Arc::new(Mutex::new(String::from("")));
+
+    hlscmafsink.connect("get-playlist-stream", false, {
+        let playlist_content = playlist_content.clone();
+        move |_args| {
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&playlist_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    hlscmafsink.connect("get-init-stream", false, move |_args| {
+        let stream = gio::MemoryOutputStream::new_resizable();
+        Some(stream.to_value())
+    });
+
+    hlscmafsink.connect("get-fragment-stream", false, move |_args| {
+        let stream = gio::MemoryOutputStream::new_resizable();
+        Some(stream.to_value())
+    });
+
+    hlscmafsink.connect("delete-fragment", false, move |_| Some(true.to_value()));
+
+    try_or_pause!(pipeline.add_many([&video_src, &capsfilter, &x264enc, &h264parse, &hlscmafsink]));
+    try_or_pause!(gst::Element::link_many([
+        &video_src,
+        &capsfilter,
+        &x264enc,
+        &h264parse,
+        &hlscmafsink
+    ]));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+    assert!(eos);
+
+    // The playlist is still open, so a blocking reload for a future segment times out while
+    // one for an existing segment returns immediately
+    let playlist = hlscmafsink.emit_by_name::<Option<String>>(
+        "wait-for-playlist",
+        &[&1i64, &0i64, &gst::ClockTime::ZERO.nseconds()],
+    );
+    let playlist = playlist.expect("playlist should contain the first segment");
+    let future = hlscmafsink.emit_by_name::<Option<String>>(
+        "wait-for-playlist",
+        &[
+            &1000i64,
+            &-1i64,
+            &gst::ClockTime::from_mseconds(10).nseconds(),
+        ],
+    );
+    assert!(future.is_none());
+
+    pipeline.set_state(gst::State::Null).unwrap();
+
+    assert!(playlist.contains("#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK="));
+    assert!(playlist.contains("#EXT-X-PART-INF:PART-TARGET=0.2\n"));
+    assert!(playlist.contains("URI=\"segment00000.m4s\",BYTERANGE=\""));
+    assert!(playlist.contains("#EXT-X-PART:DURATION="));
+    assert!(playlist.contains(",INDEPENDENT=YES\n"));
+    assert!(playlist.contains("\nsegment00000.m4s\n"));
+
+    // The final playlist has no partial segment hints anymore
+    let final_playlist = playlist_content.lock().unwrap();
+    assert!(final_playlist.ends_with("#EXT-X-ENDLIST\n"));
+    assert!(!final_playlist.contains("#EXT-X-PRELOAD-HINT"));
+
+    Ok(())
+}
+
+#[test]
+fn test_hlscmafsink_blocking_reload_and_rendition_reports() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 60;
+
+    let pipeline = gst::Pipeline::with_name("video_pipeline");
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let capsfilter = try_create_element!("capsfilter");
+    capsfilter.set_property(
+        "caps",
+        gst::Caps::builder("video/x-raw")
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build(),
+    );
+    let x264enc = try_create_element!("x264enc");
+    x264enc.set_property("key-int-max", 30u32);
+    let h264parse = try_create_element!("h264parse");
+    let tee = try_create_element!("tee");
+    let queue1 = try_create_element!("queue", "queue1");
+    let queue2 = try_create_element!("queue", "queue2");
+
+    let create_sink = |name: &str, playlist_content: &Arc<Mutex<String>>| {
+        let hlscmafsink = gst::ElementFactory::make("hlscmafsink")
+            .name(name)
+            .property("target-duration", 1u32)
+            .property("part-duration", gst::ClockTime::from_mseconds(200))
+            .property("blocking-reload", true)
+            .build()
+            .expect("Must be able to instantiate hlscmafsink");
+
+        hlscmafsink.connect("get-playlist-stream", false, {
+            let playlist_content = playlist_content.clone();
+            move |_args| {
+                let playlist = MemoryPlaylistFile {
+                    handler: Arc::clone(&playlist_content),
+                };
+                playlist.clear_content();
+                let output = gio::WriteOutputStream::new(playlist);
+                Some(output.to_value())
+            }
+        });
+
+        hlscmafsink.connect("get-init-stream", false, move |_args| {
+            let stream = gio::MemoryOutputStream::new_resizable();
+            Some(stream.to_value())
+        });
+
+        hlscmafsink.connect("get-fragment-stream", false, move |_args| {
+            let stream = gio::MemoryOutputStream::new_resizable();
+            Some(stream.to_value())
+        });
+
+        hlscmafsink.connect("delete-fragment", false, move |_| Some(true.to_value()));
+
+        hlscmafsink
+    };
+
+    let playlist_content1 = Arc::new(Mutex::new(String::from("")));
+    let playlist_content2 = Arc::new(Mutex::new(String::from("")));
+    let hlscmafsink1 = create_sink("test_hlscmafsink1", &playlist_content1);
+    let hlscmafsink2 = create_sink("test_hlscmafsink2", &playlist_content2);
+
+    hlscmafsink1.emit_by_name::<()>(
+        "add-rendition-report",
+        &[
+            &"rendition2.m3u8",
+            hlscmafsink2
+                .downcast_ref::<gsthlssink3::HlsBaseSink>()
+                .unwrap(),
+        ],
+    );
+
+    try_or_pause!(pipeline.add_many([
+        &video_src,
+        &capsfilter,
+        &x264enc,
+        &h264parse,
+        &tee,
+        &queue1,
+        &queue2,
+        &hlscmafsink1,
+        &hlscmafsink2
+    ]));
+    try_or_pause!(gst::Element::link_many([
+        &video_src,
+        &capsfilter,
+        &x264enc,
+        &h264parse,
+        &tee
+    ]));
+    try_or_pause!(gst::Element::link_many([&tee, &queue1, &hlscmafsink1]));
+    try_or_pause!(gst::Element::link_many([&tee, &queue2, &hlscmafsink2]));
+
+    // The live source doesn't produce any data in PAUSED, so the request for the second
+    // partial segment of the first segment has to block until the pipeline is playing
+    assert_eq!(
+        pipeline.set_state(gst::State::Paused),
+        Ok(gst::StateChangeSuccess::NoPreroll)
+    );
+
+    let waiter = std::thread::spawn({
+        let hlscmafsink1 = hlscmafsink1.clone();
+        move || {
+            hlscmafsink1.emit_by_name::<Option<String>>(
+                "wait-for-playlist",
+                &[&0i64, &1i64, &gst::ClockTime::from_seconds(10).nseconds()],
+            )
+        }
+    });
+
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!waiter.is_finished());
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let playlist = waiter
+        .join()
+        .unwrap()
+        .expect("playlist should contain the second partial segment");
+    assert!(playlist.matches("#EXT-X-PART:DURATION=").count() >= 2);
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+    assert!(eos);
+
+    pipeline.set_state(gst::State::Null).unwrap();
+
+    // The first rendition reports the last partial segment of the second one
+    let playlist1 = playlist_content1.lock().unwrap();
+    assert!(playlist1.contains("#EXT-X-RENDITION-REPORT:URI=\"rendition2.m3u8\",LAST-MSN="));
+    let playlist2 = playlist_content2.lock().unwrap();
+    assert!(!playlist2.contains("#EXT-X-RENDITION-REPORT"));
+
+    Ok(())
+}
+
+#[test]
+fn test_hlsmultivariantsink_playlist_content() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 60;
+
+    let pipeline = gst::Pipeline::with_name("multivariant_pipeline");
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+    let capsfilter = try_create_element!("capsfilter");
+    capsfilter.set_property(
+        "caps",
+        gst::Caps::builder("video/x-raw")
+            .field("width", 320i32)
+            .field("height", 240i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build(),
+    );
+    let x264enc = try_create_element!("x264enc");
+    x264enc.set_property("key-int-max", 30u32);
+    let h264parse = try_create_element!("h264parse");
+
+    let audio_src = try_create_element!("audiotestsrc");
+    audio_src.set_property("is-live", true);
+    audio_src.set_property("num-buffers", BUFFER_NB);
+    let audio_enc = try_or_pause!(gst::ElementFactory::make("avenc_aac").build());
+
+    let sink = gst::ElementFactory::make("hlsmultivariantsink")
+        .property("multivariant-playlist-location", "/www/media/main.m3u8")
+        .property("target-duration", 1u32)
+        .build()
+        .expect("Must be able to instantiate hlsmultivariantsink");
+
+    let multivariant_content = Arc::new(Mutex::new(String::from("")));
+    let playlist_locations = Arc::new(Mutex::new(Vec::new()));
+
+    sink.connect("get-multivariant-playlist-stream", false, {
+        let multivariant_content = multivariant_content.clone();
+        move |_args| {
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&multivariant_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    sink.connect("get-playlist-stream", false, {
+        let playlist_locations = playlist_locations.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+            let mut playlist_locations = playlist_locations.lock().unwrap();
+            if !playlist_locations.contains(&location) {
+                playlist_locations.push(location);
+            }
+
+            let stream = gio::MemoryOutputStream::new_resizable();
+            Some(stream.to_value())
+        }
+    });
+
+    for signal in ["get-init-stream", "get-fragment-stream"] {
+        sink.connect(signal, false, move |_args| {
+            let stream = gio::MemoryOutputStream::new_resizable();
+            Some(stream.to_value())
+        });
+    }
+    sink.connect("delete-fragment", false, move |_| Some(true.to_value()));
+
+    try_or_pause!(pipeline.add_many([
+        &video_src,
+        &capsfilter,
+        &x264enc,
+        &h264parse,
+        &audio_src,
+        &audio_enc,
+        &sink
+    ]));
+    try_or_pause!(gst::Element::link_many([
+        &video_src,
+        &capsfilter,
+        &x264enc,
+        &h264parse
+    ]));
+    try_or_pause!(audio_src.link(&audio_enc));
+
+    let video_pad = sink.request_pad_simple("video_%u").unwrap();
+    video_pad.set_property("bandwidth", 1_000_000u32);
+    let audio_pad = sink.request_pad_simple("audio_%u").unwrap();
+    audio_pad.set_property("bandwidth", 128_000u32);
+    audio_pad.set_property("language", "en");
+    audio_pad.set_property("rendition-name", "English");
+    audio_pad.set_property("default", true);
+
+    h264parse
+        .static_pad("src")
+        .unwrap()
+        .link(&video_pad)
+        .unwrap();
+    audio_enc
+        .static_pad("src")
+        .unwrap()
+        .link(&audio_pad)
+        .unwrap();
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    let mut playlist_locations = playlist_locations.lock().unwrap().clone();
+    playlist_locations.sort();
+    assert_eq!(
+        playlist_locations,
+        vec![
+            "/www/media/audio_0/playlist.m3u8",
+            "/www/media/video_0/playlist.m3u8"
+        ]
+    );
+
+    let content = multivariant_content.lock().unwrap();
+    assert!(content.starts_with("#EXTM3U\n"));
+    assert!(content.contains("#EXT-X-MEDIA:TYPE=AUDIO,"));
+    assert!(content.contains("URI=\"audio_0/playlist.m3u8\""));
+    assert!(content.contains("GROUP-ID=\"audio\""));
+    assert!(content.contains("LANGUAGE=\"en\""));
+    assert!(content.contains("NAME=\"English\""));
+    assert!(content.contains("DEFAULT=YES"));
+    assert!(content.contains("#EXT-X-STREAM-INF:BANDWIDTH=1128000,"));
+    assert!(content.contains("RESOLUTION=320x240"));
+    assert!(content.contains("AUDIO=\"audio\""));
+    assert!(content.contains("avc1."));
+    assert!(content.contains("mp4a.40.2"));
+    assert!(content.contains("\nvideo_0/playlist.m3u8\n"));
+
+    Ok(())
+}
+
+/// Decrypts an AES-128 encrypted segment and removes its padding.
+fn decrypt_segment(data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    use aes::cipher::{generic_array::GenericArray, BlockDecryptMut, KeyIvInit};
+
+    assert_eq!(data.len() % 16, 0);
+
+    let mut cipher = cbc::Decryptor::<aes::Aes128>::new(key.into(), iv.into());
+    let mut data = data.to_vec();
+    for block in data.chunks_exact_mut(16) {
+        cipher.decrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+
+    let padding = *data.last().unwrap() as usize;
+    assert!((1..=16).contains(&padding));
+    assert!(data[data.len() - padding..]
+        .iter()
+        .all(|&b| b as usize == padding));
+    data.truncate(data.len() - padding);
+
+    data
+}
+
+#[test]
+fn test_hlssink3_aes128_encryption() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 75;
+
+    let pipeline = gst::Pipeline::with_name("video_pipeline");
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let capsfilter = try_create_element!("capsfilter");
+    capsfilter.set_property(
+        "caps",
+        gst::Caps::builder("video/x-raw")
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build(),
+    );
+    let x264enc = try_create_element!("x264enc");
+    x264enc.set_property("key-int-max", 30u32);
+    let h264parse = try_create_element!("h264parse");
+
+    let hlssink3 = gst::ElementFactory::make("hlssink3")
+        .name("test_hlssink3")
+        .property("target-duration", 1u32)
+        .property("playlist-length", 0u32)
+        .property("key-rotation-interval", 1u32)
+        .property_from_str("encryption-method", "aes-128")
+        .build()
+        .expect("Must be able to instantiate hlssink3");
+
+    // Stand-in for a key server, keys by their URI
+    let key_server = Arc::new(Mutex::new(HashMap::<String, [u8; 16]>::new()));
+    hlssink3.connect("get-encryption-key", false, {
+        let key_server = key_server.clone();
+        move |args| {
+            let key_index = args[1].get::<u32>().expect("No key index given");
+
+            let key = [key_index as u8 + 1; 16];
+            let uri = format!("http://127.0.0.1:8080/keys/{key_index}");
+            key_server.lock().unwrap().insert(uri.clone(), key);
+
+            Some(
+                gst::Structure::builder("application/x-hls-key")
+                    .field("key", glib::Bytes::from(&key))
+                    .field("uri", uri)
+                    .build()
+                    .to_value(),
+            )
+        }
+    });
+
+    let playlist_content = Arc::new(Mutex::new(String::from("")));
+    hlssink3.connect("get-playlist-stream", false, {
+        let playlist_content = playlist_content.clone();
+        move |_args| {
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&playlist_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    let fragments = Arc::new(Mutex::new(HashMap::new()));
+    hlssink3.connect("get-fragment-stream", false, {
+        let fragments = fragments.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+
+            let stream = gio::MemoryOutputStream::new_resizable();
+            fragments.lock().unwrap().insert(location, stream.clone());
+            Some(stream.to_value())
+        }
+    });
+
+    hlssink3.connect("delete-fragment", false, move |_args| Some(true.to_value()));
+
+    try_or_pause!(pipeline.add_many([&video_src, &capsfilter, &x264enc, &h264parse, &hlssink3]));
+    try_or_pause!(gst::Element::link_many([
+        &video_src,
+        &capsfilter,
+        &x264enc,
+        &h264parse,
+        &hlssink3
+    ]));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(err) => panic!("Error: {err:?}"),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    let content = playlist_content.lock().unwrap().clone();
+    let playlist = m3u8_rs::parse_media_playlist_res(content.as_bytes()).unwrap();
+    assert!(playlist.segments.len() >= 2, "{content}");
+
+    let key_server = key_server.lock().unwrap();
+    let fragments = fragments.lock().unwrap();
+    for (idx, segment) in playlist.segments.iter().enumerate() {
+        // Keys are rotated with every segment
+        let key = segment.key.as_ref().expect("No key for segment");
+        assert_eq!(key.method, m3u8_rs::KeyMethod::AES128);
+        let uri = key.uri.as_ref().unwrap();
+        assert_eq!(uri, &format!("http://127.0.0.1:8080/keys/{idx}"));
+
+        let iv = key.iv.as_ref().unwrap();
+        let mut iv_bytes = [0u8; 16];
+        hex::decode_to_slice(iv.strip_prefix("0x").unwrap(), &mut iv_bytes).unwrap();
+        assert_eq!(iv_bytes, (idx as u128).to_be_bytes());
+
+        let stream = &fragments[&segment.uri];
+        stream.close(gio::Cancellable::NONE).unwrap();
+        let data = stream.steal_as_bytes();
+
+        let data = decrypt_segment(&data, &key_server[uri], &iv_bytes);
+        assert!(!data.is_empty());
+        assert_eq!(data.len() % 188, 0);
+        assert!(data.chunks(188).all(|packet| packet[0] == 0x47));
+    }
+
+    Ok(())
+}
+
+/// Returns the content of the box at the given path, skipping the given number of bytes of
+/// each box's content before its children.
+fn find_box<'a>(mut data: &'a [u8], path: &[(&[u8; 4], usize)]) -> Option<&'a [u8]> {
+    let ((fourcc, skip), rest) = path.split_first()?;
+
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        assert!((8..=data.len()).contains(&size));
+
+        if &data[4..8] == *fourcc {
+            let content = &data[8..size];
+            if rest.is_empty() {
+                return Some(content);
+            }
+            return find_box(&content[*skip..], rest);
+        }
+
+        data = &data[size..];
+    }
+
+    None
+}
+
+#[test]
+fn test_hlscmafsink_sample_aes_encryption() -> Result<(), ()> {
+    use aes::cipher::{generic_array::GenericArray, BlockDecryptMut, KeyIvInit};
+
+    init();
+
+    const BUFFER_NB: i32 = 120;
+
+    let pipeline = gst::Pipeline::with_name("video_pipeline");
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let capsfilter = try_create_element!("capsfilter");
+    capsfilter.set_property(
+        "caps",
+        gst::Caps::builder("video/x-raw")
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build(),
+    );
+    let x264enc = try_create_element!("x264enc");
+    x264enc.set_property("key-int-max", 30u32);
+    let h264parse = try_create_element!("h264parse");
+
+    let hlscmafsink = gst::ElementFactory::make("hlscmafsink")
+        .name("test_hlscmafsink")
+        .property("target-duration", 1u32)
+        .property("playlist-length", 0u32)
+        .property("key-rotation-interval", 2u32)
+        .property_from_str("encryption-method", "sample-aes")
+        .build()
+        .expect("Must be able to instantiate hlscmafsink");
+
+    // Samples as they are passed to the muxer, before encryption
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    h264parse
+        .static_pad("src")
+        .unwrap()
+        .add_probe(gst::PadProbeType::BUFFER, {
+            let samples = samples.clone();
+            move |_pad, info| {
+                if let Some(buffer) = info.buffer() {
+                    let data = buffer.map_readable().unwrap().to_vec();
+                    samples.lock().unwrap().push(data);
+                }
+                gst::PadProbeReturn::Ok
+            }
+        });
+
+    let keys = Arc::new(Mutex::new(HashMap::<String, [u8; 16]>::new()));
+    hlscmafsink.connect("get-encryption-key", false, {
+        let keys = keys.clone();
+        move |args| {
+            let key_index = args[1].get::<u32>().expect("No key index given");
+
+            let key = [key_index as u8 + 1; 16];
+            let uri = format!("keys/{key_index}.key");
+            keys.lock().unwrap().insert(uri.clone(), key);
+
+            Some(
+                gst::Structure::builder("application/x-hls-key")
+                    .field("key", glib::Bytes::from(&key))
+                    .field("uri", uri)
+                    .build()
+                    .to_value(),
+            )
+        }
+    });
+
+    let playlist_content = Arc::new(Mutex::new(String::from("")));
+    hlscmafsink.connect("get-playlist-stream", false, {
+        let playlist_content = playlist_content.clone();
+        move |_args| {
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&playlist_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    let streams = Arc::new(Mutex::new(HashMap::new()));
+    for signal in ["get-init-stream", "get-fragment-stream"] {
+        hlscmafsink.connect(signal, false, {
+            let streams = streams.clone();
+            move |args| {
+                let location = args[1].get::<String>().expect("No location given");
+
+                let stream = gio::MemoryOutputStream::new_resizable();
+                streams.lock().unwrap().insert(location, stream.clone());
+                Some(stream.to_value())
+            }
+        });
+    }
+
+    hlscmafsink.connect("delete-fragment", false, move |_args| Some(true.to_value()));
+
+    try_or_pause!(pipeline.add_many([&video_src, &capsfilter, &x264enc, &h264parse, &hlscmafsink]));
+    try_or_pause!(gst::Element::link_many([
+        &video_src,
+        &capsfilter,
+        &x264enc,
+        &h264parse,
+        &hlscmafsink
+    ]));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(err) => panic!("Error: {err:?}"),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    let content = playlist_content.lock().unwrap().clone();
+    let playlist = m3u8_rs::parse_media_playlist_res(content.as_bytes()).unwrap();
+    assert!(playlist.segments.len() >= 3, "{content}");
+
+    let streams = streams.lock().unwrap();
+    let data = |location: &str| {
+        let stream = &streams[location];
+        stream.close(gio::Cancellable::NONE).unwrap();
+        stream.steal_as_bytes()
+    };
+
+    let keys = keys.lock().unwrap();
+    let samples = samples.lock().unwrap();
+    let mut samples = samples.iter();
+    let mut key_ids = vec![];
+    let mut constant_iv = [0u8; 16];
+    for (idx, segment) in playlist.segments.iter().enumerate() {
+        // Every key rotation comes with a new init segment that has the new key ID
+        let key = segment.key.as_ref().expect("No key for segment");
+        assert_eq!(key.method, m3u8_rs::KeyMethod::SampleAES);
+        let uri = key.uri.as_ref().unwrap();
+        assert_eq!(uri, &format!("keys/{}.key", idx / 2));
+        assert!(key.iv.is_none());
+
+        if idx % 2 == 0 {
+            let map = segment.map.as_ref().expect("No init segment for new key");
+            assert_eq!(map.uri, format!("init{:05}.mp4", idx / 2));
+
+            let init = data(&map.uri);
+            let tenc = find_box(
+                &init,
+                &[
+                    (b"moov", 0),
+                    (b"trak", 0),
+                    (b"mdia", 0),
+                    (b"minf", 0),
+                    (b"stbl", 0),
+                    (b"stsd", 8),
+                    (b"encv", 78),
+                    (b"sinf", 0),
+                    (b"schi", 0),
+                    (b"tenc", 0),
+                ],
+            )
+            .unwrap();
+            // 1:9 pattern and a constant IV instead of per-sample IVs
+            assert_eq!(tenc[5], 0x19);
+            assert_eq!(tenc[7], 0);
+            assert_eq!(tenc[24], 16);
+            key_ids.push(tenc[8..24].to_vec());
+            constant_iv.copy_from_slice(&tenc[25..41]);
+        } else {
+            assert!(segment.map.is_none());
+        }
+
+        let fragment = data(&segment.uri);
+        let moof = find_box(&fragment, &[(b"moof", 0)]).unwrap();
+        let senc = find_box(&fragment, &[(b"moof", 0), (b"traf", 0), (b"senc", 0)]).unwrap();
+        let sample_count = u32::from_be_bytes(senc[4..8].try_into().unwrap());
+        let mut senc = &senc[8..];
+
+        // saio points at the first senc entry relative to the start of the moof
+        let saio = find_box(&fragment, &[(b"moof", 0), (b"traf", 0), (b"saio", 0)]).unwrap();
+        assert_eq!(
+            u32::from_be_bytes(saio[8..12].try_into().unwrap()) as usize,
+            senc.as_ptr() as usize - (moof.as_ptr() as usize - 8)
+        );
+
+        let mut mdat = find_box(&fragment, &[(b"mdat", 0)]).unwrap();
+        for _ in 0..sample_count {
+            let sample = samples.next().expect("More samples than input buffers");
+            let mut data = mdat[..sample.len()].to_vec();
+            mdat = &mdat[sample.len()..];
+
+            let subsample_count = u16::from_be_bytes(senc[..2].try_into().unwrap()) as usize;
+            let mut offset = 0;
+            for entry in senc[2..][..6 * subsample_count].chunks_exact(6) {
+                let clear = u16::from_be_bytes(entry[..2].try_into().unwrap()) as usize;
+                let protected = u32::from_be_bytes(entry[2..].try_into().unwrap()) as usize;
+
+                let mut cipher =
+                    cbc::Decryptor::<aes::Aes128>::new(&keys[uri].into(), &constant_iv.into());
+                for (i, block) in data[offset + clear..][..protected]
+                    .chunks_exact_mut(16)
+                    .enumerate()
+                {
+                    if i % 10 == 0 {
+                        cipher.decrypt_block_mut(GenericArray::from_mut_slice(block));
+                    }
+                }
+                offset += clear + protected;
+            }
+            senc = &senc[2 + 6 * subsample_count..];
+
+            assert_eq!(&data, sample);
+        }
+        assert!(mdat.is_empty());
+    }
+
+    assert!(key_ids.len() >= 2);
+    assert_ne!(key_ids[0], key_ids[1]);
+
+    Ok(())
+}
+
+#[test]
+fn test_hlsmultivariantsink_session_key() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 30;
+
+    let pipeline = gst::Pipeline::with_name("multivariant_pipeline");
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+    let x264enc = try_create_element!("x264enc");
+    let h264parse = try_create_element!("h264parse");
+
+    let sink = gst::ElementFactory::make("hlsmultivariantsink")
+        .property("multivariant-playlist-location", "/www/media/main.m3u8")
+        .property("target-duration", 1u32)
+        .property_from_str("encryption-method", "sample-aes")
+        .property("key-uri", "keys/%d.key")
+        .build()
+        .expect("Must be able to instantiate hlsmultivariantsink");
+
+    let multivariant_content = Arc::new(Mutex::new(String::from("")));
+    sink.connect("get-multivariant-playlist-stream", false, {
+        let multivariant_content = multivariant_content.clone();
+        move |_args| {
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&multivariant_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    for signal in [
+        "get-playlist-stream",
+        "get-init-stream",
+        "get-fragment-stream",
+    ] {
+        sink.connect(signal, false, move |_args| {
+            let stream = gio::MemoryOutputStream::new_resizable();
+            Some(stream.to_value())
+        });
+    }
+    sink.connect("delete-fragment", false, move |_| Some(true.to_value()));
+
+    try_or_pause!(pipeline.add_many([&video_src, &x264enc, &h264parse, &sink]));
+    try_or_pause!(gst::Element::link_many([&video_src, &x264enc, &h264parse]));
+
+    let video_pad = sink.request_pad_simple("video_%u").unwrap();
+    video_pad.set_property("bandwidth", 1_000_000u32);
+    h264parse
+        .static_pad("src")
+        .unwrap()
+        .link(&video_pad)
+        .unwrap();
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(err) => panic!("Error: {err:?}"),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    // Players can preload the key of the renditions from the multivariant playlist
+    let content = multivariant_content.lock().unwrap();
+    let playlist = m3u8_rs::parse_master_playlist_res(content.as_bytes()).unwrap();
+    assert_eq!(playlist.session_key.len(), 1, "{content}");
+    let key = &playlist.session_key[0].0;
+    assert_eq!(key.method, m3u8_rs::KeyMethod::SampleAES);
+    assert_eq!(key.uri.as_deref(), Some("keys/0.key"));
+
+    Ok(())
+}
@@ -3,9 +3,11 @@
  *
  * Implements the [Google Congestion Control algorithm](https://datatracker.ietf.org/doc/html/draft-ietf-rmcat-gcc-02).
  *
- * This element should always be placed right before a `rtpsession` and will only work
- * when [twcc](https://datatracker.ietf.org/doc/html/draft-holmer-rmcat-transport-wide-cc-extensions-01) is enabled
- * as the bandwidth estimation relies on it.
+ * This element should always be placed right before a `rtpsession` or `rtpsend` and will
+ * only work when [twcc](https://datatracker.ietf.org/doc/html/draft-holmer-rmcat-transport-wide-cc-extensions-01)
+ * is enabled as the bandwidth estimation relies on it. With `rtpsend`, the transport-wide
+ * sequence number header extension is enabled by adding it as `extmap-<id>` field to the
+ * caps of the payload type.
  *
  * This element implements the pacing as describe in the spec by running its
  * own streaming thread on its srcpad. It implements the mathematic as closely
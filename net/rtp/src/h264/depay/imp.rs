@@ -0,0 +1,487 @@
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtph264depay2
+ * @see_also: rtph264pay2, h264parse, avdec_h264
+ *
+ * Depayload an H.264 video stream from RTP packets as per [RFC 6184][rfc-6184].
+ *
+ * Single NAL unit packets, STAP-A aggregation packets and FU-A fragmentation units are supported.
+ * The output is in byte-stream format with one access unit per buffer. Parameter sets from the
+ * `sprop-parameter-sets` field of the caps are inserted in front of the first access unit unless
+ * it already contains an SPS.
+ *
+ * [rfc-6184]: https://www.rfc-editor.org/rfc/rfc6184.html
+ *
+ * ## Example pipeline
+ *
+ * ```shell
+ * gst-launch-1.0 udpsrc address=127.0.0.1 port=5004 caps='application/x-rtp,media=video,clock-rate=90000,encoding-name=H264' ! rtpjitterbuffer latency=100 ! rtph264depay2 request-keyframe=true ! decodebin3 ! videoconvertscale ! autovideosink
+ * ```
+ *
+ * This will depayload and decode an incoming RTP H.264 video stream. You can use the
+ * #rtph264pay2 and #x264enc elements to create such an RTP stream.
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use std::{mem, sync::Mutex};
+
+use atomic_refcell::AtomicRefCell;
+
+use gst::{glib, prelude::*, subclass::prelude::*};
+
+use once_cell::sync::Lazy;
+
+use crate::basedepay::{PacketToBufferRelation, RtpBaseDepay2Ext};
+use crate::h26x::{self, START_CODE};
+
+const NAL_TYPE_IDR: u8 = 5;
+const NAL_TYPE_SPS: u8 = 7;
+const NAL_TYPE_STAP_A: u8 = 24;
+const NAL_TYPE_FU_A: u8 = 28;
+
+#[derive(Clone, Default)]
+struct Settings {
+    request_keyframe: bool,
+    wait_for_keyframe: bool,
+}
+
+#[derive(Default)]
+struct State {
+    /// Parameter sets from the `sprop-parameter-sets` caps field.
+    sprop_parameter_sets: Vec<Vec<u8>>,
+    /// Set if the parameter sets from the caps still have to be inserted into the stream.
+    needs_sprop_parameter_sets: bool,
+
+    /// Extended RTP timestamp of the current access unit.
+    au_timestamp: Option<u64>,
+    /// First and last extended seqnum of the current access unit.
+    au_ext_seqnums: Option<(u64, u64)>,
+    /// Byte-stream data of the current access unit.
+    au: Vec<u8>,
+    au_is_keyframe: bool,
+    au_has_sps: bool,
+
+    /// Currently reassembled FU-A NAL unit, including the reconstructed NAL unit header.
+    fragment: Option<Vec<u8>>,
+
+    /// Set once a keyframe was output after the last reset.
+    seen_keyframe: bool,
+    /// Set if a keyframe was requested already after the last reset.
+    keyframe_requested: bool,
+}
+
+impl State {
+    fn push_nal_unit(&mut self, nal_unit: &[u8]) {
+        match nal_unit[0] & 0x1f {
+            NAL_TYPE_IDR => self.au_is_keyframe = true,
+            NAL_TYPE_SPS => self.au_has_sps = true,
+            _ => (),
+        }
+
+        self.au.extend_from_slice(&START_CODE);
+        self.au.extend_from_slice(nal_unit);
+    }
+}
+
+#[derive(Default)]
+pub struct RtpH264Depay {
+    state: AtomicRefCell<State>,
+    settings: Mutex<Settings>,
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtph264depay2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP H.264 Depayloader"),
+    )
+});
+
+impl RtpH264Depay {
+    /// Resets the current access unit and keyframe tracking but keeps the parameter sets from the
+    /// caps, which are re-inserted in front of the next access unit.
+    fn reset(&self, state: &mut State) {
+        gst::debug!(CAT, imp = self, "resetting state");
+
+        *state = State {
+            needs_sprop_parameter_sets: !state.sprop_parameter_sets.is_empty(),
+            sprop_parameter_sets: mem::take(&mut state.sprop_parameter_sets),
+            ..State::default()
+        };
+    }
+
+    /// Discards the current access unit, e.g. because of corrupted or missing packets.
+    fn discard_au(&self, state: &mut State) {
+        state.au_timestamp = None;
+        state.au_ext_seqnums = None;
+        state.au.clear();
+        state.au_is_keyframe = false;
+        state.au_has_sps = false;
+        state.fragment = None;
+    }
+
+    /// Drops the current access unit, if any, when its last packet with the marker bit set was
+    /// not received, e.g. because of a discontinuity. It would otherwise be decoded as if it was
+    /// complete.
+    fn drop_incomplete_au(&self, state: &mut State) {
+        if let Some((_, end_ext_seqnum)) = state.au_ext_seqnums {
+            gst::debug!(CAT, imp = self, "Dropping incomplete access unit");
+            self.obj().drop_packets(..=end_ext_seqnum);
+        }
+
+        self.discard_au(state);
+    }
+
+    /// Outputs the current access unit, if any.
+    fn finish_au(
+        &self,
+        settings: &Settings,
+        state: &mut State,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        if state.fragment.take().is_some() {
+            gst::warning!(CAT, imp = self, "Dropping incomplete fragmented NAL unit");
+        }
+
+        let Some((start_ext_seqnum, end_ext_seqnum)) = state.au_ext_seqnums else {
+            return Ok(gst::FlowSuccess::Ok);
+        };
+
+        if state.au.is_empty() {
+            self.obj().drop_packets(..=end_ext_seqnum);
+            self.discard_au(state);
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        // If necessary wait for a keyframe if we never saw one so far and/or request one from
+        // upstream.
+        if !state.au_is_keyframe && !state.seen_keyframe {
+            if settings.request_keyframe && !state.keyframe_requested {
+                gst::debug!(CAT, imp = self, "Requesting keyframe from upstream");
+                let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+                    .all_headers(true)
+                    .build();
+                let _ = self.obj().sink_pad().push_event(event);
+                state.keyframe_requested = true;
+            }
+
+            if settings.wait_for_keyframe {
+                gst::trace!(CAT, imp = self, "Waiting for keyframe");
+                self.obj().drop_packets(..=end_ext_seqnum);
+                self.discard_au(state);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        }
+
+        let mut au = mem::take(&mut state.au);
+        if state.needs_sprop_parameter_sets && !state.au_has_sps {
+            gst::debug!(CAT, imp = self, "Inserting parameter sets from caps");
+
+            let mut parameter_sets = Vec::new();
+            for nal_unit in &state.sprop_parameter_sets {
+                parameter_sets.extend_from_slice(&START_CODE);
+                parameter_sets.extend_from_slice(nal_unit);
+            }
+            au.splice(0..0, parameter_sets);
+        }
+        state.needs_sprop_parameter_sets = false;
+
+        let mut buffer = gst::Buffer::from_mut_slice(au);
+        {
+            let buffer = buffer.get_mut().unwrap();
+
+            if state.au_is_keyframe {
+                gst::trace!(CAT, imp = self, "Finishing keyframe");
+                state.seen_keyframe = true;
+            } else {
+                gst::trace!(CAT, imp = self, "Finishing delta-frame");
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+
+            // Set MARKER flag on the output so that the parser knows that this buffer ends a full
+            // access unit.
+            buffer.set_flags(gst::BufferFlags::MARKER);
+        }
+
+        self.discard_au(state);
+
+        self.obj().queue_buffer(
+            PacketToBufferRelation::Seqnums(start_ext_seqnum..=end_ext_seqnum),
+            buffer,
+        )
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpH264Depay {
+    const NAME: &'static str = "GstRtpH264Depay2";
+    type Type = super::RtpH264Depay;
+    type ParentType = crate::basedepay::RtpBaseDepay2;
+}
+
+impl ObjectImpl for RtpH264Depay {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecBoolean::builder("request-keyframe")
+                    .nick("Request Keyframe")
+                    .blurb("Request new keyframe when packet loss is detected")
+                    .default_value(Settings::default().request_keyframe)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("wait-for-keyframe")
+                    .nick("Wait For Keyframe")
+                    .blurb("Wait for the next keyframe after packet loss")
+                    .default_value(Settings::default().wait_for_keyframe)
+                    .mutable_ready()
+                    .build(),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "request-keyframe" => {
+                self.settings.lock().unwrap().request_keyframe = value.get().unwrap();
+            }
+            "wait-for-keyframe" => {
+                self.settings.lock().unwrap().wait_for_keyframe = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "request-keyframe" => self.settings.lock().unwrap().request_keyframe.to_value(),
+            "wait-for-keyframe" => self.settings.lock().unwrap().wait_for_keyframe.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpH264Depay {}
+
+impl ElementImpl for RtpH264Depay {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP H.264 Depayloader",
+                "Codec/Depayloader/Network/RTP",
+                "Depayload H.264 from RTP packets",
+                "agent <agent@local>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("application/x-rtp")
+                    .field("media", "video")
+                    .field("clock-rate", 90_000i32)
+                    .field("encoding-name", "H264")
+                    .build(),
+            )
+            .unwrap();
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("video/x-h264")
+                    .field("stream-format", "byte-stream")
+                    .field("alignment", "au")
+                    .build(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl crate::basedepay::RtpBaseDepay2Impl for RtpH264Depay {
+    const ALLOWED_META_TAGS: &'static [&'static str] = &["video"];
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn set_sink_caps(&self, caps: &gst::Caps) -> bool {
+        gst::debug!(CAT, imp = self, "received caps {caps:?}");
+
+        let s = caps.structure(0).unwrap();
+
+        if let Ok(packetization_mode) = s.get::<&str>("packetization-mode") {
+            if packetization_mode == "2" {
+                gst::error!(
+                    CAT,
+                    imp = self,
+                    "Interleaved packetization mode not supported"
+                );
+                return false;
+            }
+        }
+
+        let mut state = self.state.borrow_mut();
+        state.sprop_parameter_sets = s
+            .get::<&str>("sprop-parameter-sets")
+            .map(h26x::parse_sprop)
+            .unwrap_or_default();
+        state.needs_sprop_parameter_sets = !state.sprop_parameter_sets.is_empty();
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Got {} parameter sets from caps",
+            state.sprop_parameter_sets.len()
+        );
+
+        self.obj()
+            .set_src_caps(&self.obj().src_pad().pad_template_caps());
+
+        true
+    }
+
+    fn drain(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.borrow_mut();
+
+        // Complete access units are output once their marker bit is received, anything left
+        // over is missing its end.
+        self.drop_incomplete_au(&mut state);
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn flush(&self) {
+        let mut state = self.state.borrow_mut();
+        self.reset(&mut state);
+    }
+
+    fn handle_packet(
+        &self,
+        packet: &crate::basedepay::Packet,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        gst::trace!(CAT, imp = self, "Handling RTP packet {packet:?}");
+        let mut state = self.state.borrow_mut();
+
+        let payload = packet.payload();
+        if payload.is_empty() {
+            gst::warning!(CAT, imp = self, "Empty RTP packet");
+            self.discard_au(&mut state);
+            self.obj().drop_packet(packet);
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        // A new timestamp starts a new access unit even if the marker bit of the previous one got
+        // lost.
+        if state
+            .au_timestamp
+            .is_some_and(|timestamp| timestamp != packet.ext_timestamp())
+        {
+            gst::debug!(CAT, imp = self, "Timestamp changed without marker bit");
+            self.finish_au(&settings, &mut state)?;
+        }
+
+        let nal_type = payload[0] & 0x1f;
+        match nal_type {
+            1..=23 => {
+                state.push_nal_unit(payload);
+            }
+            NAL_TYPE_STAP_A => {
+                let mut data = &payload[1..];
+                while !data.is_empty() {
+                    let Some(len) = data
+                        .get(..2)
+                        .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+                    else {
+                        break;
+                    };
+
+                    let Some(nal_unit) = data.get(2..2 + len).filter(|n| !n.is_empty()) else {
+                        gst::warning!(CAT, imp = self, "Invalid STAP-A packet");
+                        self.discard_au(&mut state);
+                        self.obj().drop_packet(packet);
+                        return Ok(gst::FlowSuccess::Ok);
+                    };
+
+                    state.push_nal_unit(nal_unit);
+                    data = &data[2 + len..];
+                }
+            }
+            NAL_TYPE_FU_A => {
+                if payload.len() < 3 {
+                    gst::warning!(CAT, imp = self, "Too short FU-A packet");
+                    self.discard_au(&mut state);
+                    self.obj().drop_packet(packet);
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+
+                let fu_header = payload[1];
+                let start = fu_header & 0x80 != 0;
+                let end = fu_header & 0x40 != 0;
+
+                if start {
+                    if state.fragment.is_some() {
+                        gst::warning!(CAT, imp = self, "Dropping incomplete fragmented NAL unit");
+                    }
+
+                    let mut fragment = Vec::with_capacity(payload.len() * 2);
+                    fragment.push((payload[0] & 0xe0) | (fu_header & 0x1f));
+                    fragment.extend_from_slice(&payload[2..]);
+                    state.fragment = Some(fragment);
+                } else if let Some(ref mut fragment) = state.fragment {
+                    fragment.extend_from_slice(&payload[2..]);
+                } else {
+                    gst::warning!(CAT, imp = self, "Missing start of fragmented NAL unit");
+                    self.discard_au(&mut state);
+                    self.obj().drop_packet(packet);
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+
+                if end {
+                    let fragment = state.fragment.take().unwrap();
+                    state.push_nal_unit(&fragment);
+                }
+            }
+            _ => {
+                gst::warning!(CAT, imp = self, "Unsupported NAL unit type {nal_type}");
+                self.discard_au(&mut state);
+                self.obj().drop_packet(packet);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        }
+
+        state.au_timestamp = Some(packet.ext_timestamp());
+        state.au_ext_seqnums = Some(match state.au_ext_seqnums {
+            Some((start, _)) => (start, packet.ext_seqnum()),
+            None => (packet.ext_seqnum(), packet.ext_seqnum()),
+        });
+
+        // The marker bit is set for the last packet of an access unit.
+        if packet.marker_bit() {
+            self.finish_au(&settings, &mut state)?;
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
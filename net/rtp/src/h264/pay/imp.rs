@@ -0,0 +1,608 @@
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtph264pay2
+ * @see_also: rtph264depay2, h264parse, x264enc
+ *
+ * Payload an H.264 video stream into RTP packets as per [RFC 6184][rfc-6184] using
+ * packetization-mode 1.
+ *
+ * NAL units of the same access unit are aggregated into STAP-A packets if they fit into a single
+ * packet, and NAL units that don't fit are fragmented into FU-A packets. Aggregation can be
+ * disabled via the `aggregate-mode` property.
+ *
+ * The SPS and PPS are signalled in the `sprop-parameter-sets` field of the caps. Additionally
+ * they can be re-sent in-band in front of IDR frames via the `config-interval` property, and
+ * they're always re-sent with the next IDR frame if a downstream element requests a keyframe
+ * with all headers.
+ *
+ * [rfc-6184]: https://www.rfc-editor.org/rfc/rfc6184.html
+ *
+ * ## Example pipeline
+ *
+ * |[
+ * gst-launch-1.0 videotestsrc ! video/x-raw,width=1280,height=720,format=I420 ! timeoverlay font-desc=Sans,22 ! x264enc tune=zerolatency ! h264parse ! rtph264pay2 config-interval=-1 ! udpsink host=127.0.0.1 port=5004
+ * ]| This will create and payload an H.264 video stream with a test pattern and send it out via
+ * UDP to localhost port 5004.
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use atomic_refcell::AtomicRefCell;
+use gst::{glib, prelude::*, subclass::prelude::*};
+use smallvec::SmallVec;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    basepay::{RtpBasePay2Ext, RtpBasePay2ImplExt},
+    h26x::{self, StreamFormat},
+};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtph264pay2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP H.264 Payloader"),
+    )
+});
+
+const NAL_TYPE_IDR: u8 = 5;
+const NAL_TYPE_SPS: u8 = 7;
+const NAL_TYPE_PPS: u8 = 8;
+const NAL_TYPE_AUD: u8 = 9;
+const NAL_TYPE_STAP_A: u8 = 24;
+const NAL_TYPE_FU_A: u8 = 28;
+
+const DEFAULT_CONFIG_INTERVAL: i32 = 0;
+
+#[derive(Clone)]
+struct Settings {
+    aggregate_mode: super::AggregateMode,
+    config_interval: i32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            aggregate_mode: super::AggregateMode::default(),
+            config_interval: DEFAULT_CONFIG_INTERVAL,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    stream_format: StreamFormat,
+    /// Last known SPS, either from the caps or from the stream.
+    sps: Vec<Vec<u8>>,
+    /// Last known PPS, either from the caps or from the stream.
+    pps: Vec<Vec<u8>>,
+    /// PTS of the last IDR frame that was sent together with SPS / PPS.
+    last_config_pts: Option<gst::ClockTime>,
+}
+
+#[derive(Default)]
+pub struct RtpH264Pay {
+    settings: Mutex<Settings>,
+    state: AtomicRefCell<State>,
+    /// Set when downstream requested a keyframe with all headers.
+    force_config: AtomicBool,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpH264Pay {
+    const NAME: &'static str = "GstRtpH264Pay2";
+    type Type = super::RtpH264Pay;
+    type ParentType = crate::basepay::RtpBasePay2;
+}
+
+impl ObjectImpl for RtpH264Pay {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::builder::<super::AggregateMode>("aggregate-mode")
+                    .nick("Aggregate Mode")
+                    .blurb("Whether to aggregate NAL units of an access unit into STAP-A packets")
+                    .default_value(Settings::default().aggregate_mode)
+                    .mutable_ready()
+                    .build(),
+                // Using same type/semantics as C payloaders
+                glib::ParamSpecInt::builder("config-interval")
+                    .nick("SPS PPS Send Interval")
+                    .blurb("Send SPS and PPS in-band in front of IDR frames at this interval in seconds (0 = disabled, -1 = with every IDR frame)")
+                    .default_value(DEFAULT_CONFIG_INTERVAL)
+                    .minimum(-1)
+                    .maximum(3600)
+                    .mutable_playing()
+                    .build(),
+            ]
+        });
+
+        &PROPERTIES
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "aggregate-mode" => {
+                self.settings.lock().unwrap().aggregate_mode = value.get().unwrap();
+            }
+            "config-interval" => {
+                self.settings.lock().unwrap().config_interval = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "aggregate-mode" => self.settings.lock().unwrap().aggregate_mode.to_value(),
+            "config-interval" => self.settings.lock().unwrap().config_interval.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpH264Pay {}
+
+impl ElementImpl for RtpH264Pay {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP H.264 payloader",
+                "Codec/Payloader/Network/RTP",
+                "Payload H.264 as RTP packets",
+                "agent <agent@local>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("video/x-h264")
+                    .field(
+                        "stream-format",
+                        gst::List::new(["avc", "avc3", "byte-stream"]),
+                    )
+                    .field("alignment", "au")
+                    .build(),
+            )
+            .unwrap();
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("application/x-rtp")
+                    .field("media", "video")
+                    .field("clock-rate", 90_000i32)
+                    .field("encoding-name", "H264")
+                    .build(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl RtpH264Pay {
+    /// Configures the src caps based on the currently known SPS / PPS.
+    fn update_src_caps(&self, state: &State) {
+        let mut caps_builder = gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("clock-rate", 90_000i32)
+            .field("encoding-name", "H264")
+            .field("packetization-mode", "1");
+
+        if let Some(sps) = state.sps.first().filter(|sps| sps.len() >= 4) {
+            caps_builder = caps_builder.field("profile-level-id", hex::encode(&sps[1..4]));
+        }
+
+        if !state.sps.is_empty() && !state.pps.is_empty() {
+            let parameter_sets = state
+                .sps
+                .iter()
+                .chain(state.pps.iter())
+                .cloned()
+                .collect::<Vec<_>>();
+            caps_builder =
+                caps_builder.field("sprop-parameter-sets", h26x::sprop_string(&parameter_sets));
+        }
+
+        self.obj().set_src_caps(&caps_builder.build());
+    }
+
+    /// Packetizes the NAL units of one access unit.
+    fn packetize(
+        &self,
+        id: u64,
+        nal_units: &[&[u8]],
+        aggregate: bool,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let max_payload_size = self.obj().max_payload_size() as usize;
+
+        // Need space for at least the FU indicator, FU header and one byte of payload.
+        if max_payload_size < 3 {
+            gst::error!(CAT, imp = self, "Too small MTU configured for stream");
+            gst::element_imp_error!(
+                self,
+                gst::LibraryError::Settings,
+                ["Too small MTU configured for stream"]
+            );
+            return Err(gst::FlowError::Error);
+        }
+
+        let mut stap = SmallVec::<[&[u8]; 16]>::new();
+        let mut stap_size = 1;
+
+        for (idx, &nal_unit) in nal_units.iter().enumerate() {
+            let last = idx + 1 == nal_units.len();
+
+            if aggregate && stap_size + 2 + nal_unit.len() <= max_payload_size {
+                stap.push(nal_unit);
+                stap_size += 2 + nal_unit.len();
+
+                if last {
+                    self.queue_stap(id, &stap, true)?;
+                }
+                continue;
+            }
+
+            // Doesn't fit into the current STAP-A anymore, so send out what we have so far.
+            self.queue_stap(id, &stap, false)?;
+            stap.clear();
+            stap_size = 1;
+
+            if aggregate && !last && stap_size + 2 + nal_unit.len() <= max_payload_size {
+                stap.push(nal_unit);
+                stap_size += 2 + nal_unit.len();
+                continue;
+            }
+
+            if nal_unit.len() <= max_payload_size {
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Sending NAL unit of size {} in a single packet",
+                    nal_unit.len(),
+                );
+
+                self.obj().queue_packet(
+                    id.into(),
+                    rtp_types::RtpPacketBuilder::new()
+                        .marker_bit(last)
+                        .payload(nal_unit),
+                )?;
+                continue;
+            }
+
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Fragmenting NAL unit of size {} into FU-A packets",
+                nal_unit.len(),
+            );
+
+            let fu_indicator = (nal_unit[0] & 0xe0) | NAL_TYPE_FU_A;
+            let nal_type = nal_unit[0] & 0x1f;
+
+            let mut data = &nal_unit[1..];
+            let mut first = true;
+            while !data.is_empty() {
+                let payload_size = std::cmp::min(max_payload_size - 2, data.len());
+                let end = payload_size == data.len();
+
+                let fu_header = [
+                    fu_indicator,
+                    (if first { 0x80 } else { 0x00 }) | (if end { 0x40 } else { 0x00 }) | nal_type,
+                ];
+
+                self.obj().queue_packet(
+                    id.into(),
+                    rtp_types::RtpPacketBuilder::new()
+                        .marker_bit(last && end)
+                        .payload(fu_header.as_slice())
+                        .payload(&data[..payload_size]),
+                )?;
+
+                data = &data[payload_size..];
+                first = false;
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Queues a packet for the aggregated NAL units.
+    ///
+    /// A single NAL unit is sent as-is without STAP-A header.
+    fn queue_stap(
+        &self,
+        id: u64,
+        nal_units: &[&[u8]],
+        marker: bool,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        match nal_units {
+            [] => Ok(gst::FlowSuccess::Ok),
+            [nal_unit] => self.obj().queue_packet(
+                id.into(),
+                rtp_types::RtpPacketBuilder::new()
+                    .marker_bit(marker)
+                    .payload(*nal_unit),
+            ),
+            _ => {
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Aggregating {} NAL units into a STAP-A packet",
+                    nal_units.len(),
+                );
+
+                // F bit is set if any of the aggregated NAL units has it set, NRI is the maximum
+                // of all aggregated NAL units.
+                let forbidden = nal_units.iter().fold(0, |f, n| f | (n[0] & 0x80));
+                let nri = nal_units.iter().map(|n| n[0] & 0x60).max().unwrap();
+                let stap_header = [forbidden | nri | NAL_TYPE_STAP_A];
+
+                let sizes = nal_units
+                    .iter()
+                    .map(|n| (n.len() as u16).to_be_bytes())
+                    .collect::<SmallVec<[[u8; 2]; 16]>>();
+
+                let mut builder = rtp_types::RtpPacketBuilder::new()
+                    .marker_bit(marker)
+                    .payload(stap_header.as_slice());
+                for (size, nal_unit) in Iterator::zip(sizes.iter(), nal_units.iter()) {
+                    builder = builder.payload(size.as_slice()).payload(*nal_unit);
+                }
+
+                self.obj().queue_packet(id.into(), builder)
+            }
+        }
+    }
+}
+
+impl crate::basepay::RtpBasePay2Impl for RtpH264Pay {
+    const ALLOWED_META_TAGS: &'static [&'static str] = &["video"];
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+        self.force_config.store(false, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn set_sink_caps(&self, caps: &gst::Caps) -> bool {
+        gst::debug!(CAT, imp = self, "received caps {caps:?}");
+
+        let s = caps.structure(0).unwrap();
+        let mut state = self.state.borrow_mut();
+
+        match s.get::<&str>("stream-format") {
+            Ok(stream_format @ ("avc" | "avc3")) => {
+                if let Ok(codec_data) = s.get::<gst::Buffer>("codec_data") {
+                    let map = codec_data.map_readable().unwrap();
+                    match parse_avcc(&map) {
+                        Ok((length_size, sps, pps)) => {
+                            gst::debug!(
+                                CAT,
+                                imp = self,
+                                "Parsed avcC with NAL length size {length_size}, {} SPS and {} PPS",
+                                sps.len(),
+                                pps.len(),
+                            );
+                            state.stream_format = StreamFormat::LengthPrefixed(length_size);
+                            if !sps.is_empty() {
+                                state.sps = sps;
+                            }
+                            if !pps.is_empty() {
+                                state.pps = pps;
+                            }
+                        }
+                        Err(err) => {
+                            gst::error!(CAT, imp = self, "Failed to parse codec_data: {err}");
+                            return false;
+                        }
+                    }
+                } else if stream_format == "avc3" {
+                    // Parameter sets are only in-band, assume 4 byte NAL unit lengths
+                    state.stream_format = StreamFormat::LengthPrefixed(4);
+                } else {
+                    gst::error!(CAT, imp = self, "avc caps without codec_data");
+                    return false;
+                }
+            }
+            _ => {
+                state.stream_format = StreamFormat::ByteStream;
+            }
+        }
+
+        self.update_src_caps(&state);
+
+        true
+    }
+
+    fn handle_buffer(
+        &self,
+        buffer: &gst::Buffer,
+        id: u64,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.borrow_mut();
+        let settings = self.settings.lock().unwrap().clone();
+
+        gst::trace!(CAT, imp = self, "received buffer of size {}", buffer.size());
+
+        let map = buffer.map_readable().map_err(|_| {
+            gst::element_imp_error!(
+                self,
+                gst::ResourceError::Read,
+                ["Failed to map buffer readable"]
+            );
+
+            gst::FlowError::Error
+        })?;
+
+        // Parameter sets that are inserted in front of the access unit, if any.
+        let mut config = Vec::new();
+
+        let mut nal_units = match h26x::split_nal_units(state.stream_format, &map) {
+            Ok(nal_units) => nal_units,
+            Err(err) => {
+                gst::warning!(CAT, imp = self, "Failed to parse access unit: {err}");
+                self.obj().drop_buffers(..=id);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+
+        if nal_units.is_empty() {
+            gst::warning!(CAT, imp = self, "Access unit without NAL units");
+            self.obj().drop_buffers(..=id);
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        let mut sps = Vec::new();
+        let mut pps = Vec::new();
+        let mut is_idr = false;
+        for nal_unit in &nal_units {
+            match nal_unit[0] & 0x1f {
+                NAL_TYPE_IDR => is_idr = true,
+                NAL_TYPE_SPS => sps.push(nal_unit.to_vec()),
+                NAL_TYPE_PPS => pps.push(nal_unit.to_vec()),
+                _ => (),
+            }
+        }
+
+        let has_config = !sps.is_empty() && !pps.is_empty();
+        let mut config_changed = false;
+        if !sps.is_empty() && sps != state.sps {
+            state.sps = sps;
+            config_changed = true;
+        }
+        if !pps.is_empty() && pps != state.pps {
+            state.pps = pps;
+            config_changed = true;
+        }
+        if config_changed {
+            gst::debug!(CAT, imp = self, "Parameter sets changed");
+            self.update_src_caps(&state);
+        }
+
+        // Decide whether SPS / PPS have to be inserted in front of this IDR frame.
+        if is_idr {
+            let force_config = self.force_config.swap(false, Ordering::SeqCst);
+
+            let insert_config = !has_config
+                && !state.sps.is_empty()
+                && !state.pps.is_empty()
+                && (force_config
+                    || match settings.config_interval {
+                        0 => false,
+                        -1 => true,
+                        interval => Option::zip(buffer.pts(), state.last_config_pts).map_or(
+                            true,
+                            |(pts, last_config_pts)| {
+                                pts.saturating_sub(last_config_pts)
+                                    >= gst::ClockTime::from_seconds(interval as u64)
+                            },
+                        ),
+                    });
+
+            if insert_config {
+                gst::debug!(CAT, imp = self, "Inserting SPS / PPS in front of IDR frame");
+                config.extend(state.sps.iter().chain(state.pps.iter()).cloned());
+            }
+
+            if has_config || insert_config {
+                state.last_config_pts = buffer.pts();
+            }
+        }
+
+        if !config.is_empty() {
+            // Parameter sets go after the access unit delimiter, if any.
+            let pos = nal_units
+                .iter()
+                .position(|nal_unit| nal_unit[0] & 0x1f != NAL_TYPE_AUD)
+                .unwrap_or(nal_units.len());
+            nal_units.insert_many(pos, config.iter().map(Vec::as_slice));
+        }
+        drop(state);
+
+        self.packetize(
+            id,
+            &nal_units,
+            settings.aggregate_mode == super::AggregateMode::ZeroLatency,
+        )
+    }
+
+    fn src_event(&self, event: gst::Event) -> Result<gst::FlowSuccess, gst::FlowError> {
+        if let Ok(fku) = gst_video::UpstreamForceKeyUnitEvent::parse(&event) {
+            if fku.all_headers {
+                gst::debug!(CAT, imp = self, "Sending SPS / PPS with next IDR frame");
+                self.force_config.store(true, Ordering::SeqCst);
+            }
+        }
+
+        self.parent_src_event(event)
+    }
+}
+
+/// Parses an `AVCDecoderConfigurationRecord` and returns the NAL unit length size, SPS and PPS.
+#[allow(clippy::type_complexity)]
+fn parse_avcc(data: &[u8]) -> Result<(usize, Vec<Vec<u8>>, Vec<Vec<u8>>), anyhow::Error> {
+    use anyhow::Context as _;
+
+    fn read_nal_units<'a>(
+        data: &mut &'a [u8],
+        count: usize,
+    ) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+        let mut nal_units = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = data
+                .get(..2)
+                .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+                .context("Too short")?;
+            let nal_unit = data.get(2..2 + len).context("Too short")?;
+            nal_units.push(nal_unit.to_vec());
+            *data = &data[2 + len..];
+        }
+
+        Ok(nal_units)
+    }
+
+    if data.len() < 6 {
+        anyhow::bail!("Too short");
+    }
+    if data[0] != 1 {
+        anyhow::bail!("Unsupported version {}", data[0]);
+    }
+
+    let length_size = (data[4] & 0x03) as usize + 1;
+    let num_sps = (data[5] & 0x1f) as usize;
+
+    let mut data = &data[6..];
+    let sps = read_nal_units(&mut data, num_sps)?;
+
+    let num_pps = *data.first().context("Too short")? as usize;
+    data = &data[1..];
+    let pps = read_nal_units(&mut data, num_pps)?;
+
+    Ok((length_size, sps, pps))
+}
@@ -0,0 +1,347 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::tests::{run_test_pipeline, ExpectedBuffer, ExpectedPacket, Source};
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        crate::plugin_register_static().expect("rtph264 test");
+    });
+}
+
+const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40, 0x16, 0xe8, 0x40];
+const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+/// Creates a NAL unit with the given header byte and size.
+fn nal_unit(header: u8, size: usize) -> Vec<u8> {
+    let mut nal_unit = vec![0xab; size];
+    nal_unit[0] = header;
+    nal_unit
+}
+
+/// Creates a byte-stream access unit from the given NAL units.
+fn access_unit(pos: u64, nal_units: &[&[u8]]) -> gst::Buffer {
+    let mut data = Vec::new();
+    for nal_unit in nal_units {
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+        data.extend_from_slice(nal_unit);
+    }
+
+    let is_idr = nal_units.iter().any(|nal_unit| nal_unit[0] & 0x1f == 5);
+
+    let mut buffer = gst::Buffer::from_mut_slice(data);
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::from_mseconds(pos * 40));
+        if !is_idr {
+            buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+        }
+    }
+
+    buffer
+}
+
+fn caps() -> gst::Caps {
+    gst::Caps::builder("video/x-h264")
+        .field("stream-format", "byte-stream")
+        .field("alignment", "au")
+        .build()
+}
+
+#[test]
+fn test_h264_aggregate_and_fragment() {
+    init();
+
+    let idr = nal_unit(0x65, 3000);
+    let non_idr = nal_unit(0x41, 100);
+
+    let buffers = vec![
+        access_unit(0, &[SPS, PPS, &idr]),
+        access_unit(1, &[&non_idr]),
+        access_unit(2, &[&non_idr]),
+    ];
+
+    let pay = "rtph264pay2";
+    let depay = "rtph264depay2";
+
+    let expected_pay = vec![
+        vec![
+            // SPS and PPS aggregated into a STAP-A packet
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::DISCONT)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(12 + 1 + 2 + SPS.len() + 2 + PPS.len())
+                .build(),
+            // IDR fragmented into three FU-A packets
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::MARKER)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(true)
+                .size(12 + 2 + (2999 - 2 * 1386))
+                .build(),
+        ],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(3_600)
+            .marker_bit(true)
+            .size(12 + 100)
+            .build()],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(7_200)
+            .marker_bit(true)
+            .size(12 + 100)
+            .build()],
+    ];
+
+    let expected_depay = vec![
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .size(4 + SPS.len() + 4 + PPS.len() + 4 + 3000)
+            .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .size(4 + 100)
+            .flags(gst::BufferFlags::MARKER | gst::BufferFlags::DELTA_UNIT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .size(4 + 100)
+            .flags(gst::BufferFlags::MARKER | gst::BufferFlags::DELTA_UNIT)
+            .build()],
+    ];
+
+    run_test_pipeline(
+        Source::Buffers(caps(), buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
+
+#[test]
+fn test_h264_no_aggregation() {
+    init();
+
+    let idr = nal_unit(0x65, 500);
+
+    let buffers = vec![access_unit(0, &[SPS, PPS, &idr])];
+
+    let pay = "rtph264pay2 aggregate-mode=none";
+    let depay = "rtph264depay2";
+
+    let expected_pay = vec![vec![
+        ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::DISCONT)
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(false)
+            .size(12 + SPS.len())
+            .build(),
+        ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::empty())
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(false)
+            .size(12 + PPS.len())
+            .build(),
+        ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(true)
+            .size(12 + 500)
+            .build(),
+    ]];
+
+    let expected_depay = vec![vec![ExpectedBuffer::builder()
+        .pts(gst::ClockTime::from_mseconds(0))
+        .size(4 + SPS.len() + 4 + PPS.len() + 4 + 500)
+        .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+        .build()]];
+
+    run_test_pipeline(
+        Source::Buffers(caps(), buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
+
+#[test]
+fn test_h264_drop_incomplete_au() {
+    init();
+
+    let idr = nal_unit(0x65, 500);
+    let non_idr = nal_unit(0x41, 100);
+
+    let buffers = vec![
+        access_unit(0, &[SPS, PPS, &idr]),
+        access_unit(1, &[&non_idr]),
+    ];
+
+    let pay = "rtph264pay2 aggregate-mode=none";
+    let depay = "rtph264depay2";
+
+    let expected_pay = vec![
+        vec![
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::DISCONT)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(12 + SPS.len())
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(12 + PPS.len())
+                .build(),
+            // The last packet of the access unit gets lost
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::MARKER)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(true)
+                .size(12 + 500)
+                .drop(true)
+                .build(),
+        ],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(3_600)
+            .marker_bit(true)
+            .size(12 + 100)
+            .build()],
+    ];
+
+    // The incomplete first access unit is not output
+    let expected_depay = vec![vec![ExpectedBuffer::builder()
+        .pts(gst::ClockTime::from_mseconds(40))
+        .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER | gst::BufferFlags::DELTA_UNIT)
+        .build()]];
+
+    run_test_pipeline(
+        Source::Buffers(caps(), buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
+
+#[test]
+fn test_h264_config_interval() {
+    init();
+
+    let idr = nal_unit(0x65, 500);
+    let non_idr = nal_unit(0x41, 100);
+
+    // Only the first IDR frame has SPS / PPS, the payloader has to insert them in front of the
+    // second one.
+    let buffers = vec![
+        access_unit(0, &[SPS, PPS, &idr]),
+        access_unit(1, &[&non_idr]),
+        access_unit(2, &[&idr]),
+    ];
+
+    let pay = "rtph264pay2 config-interval=-1";
+    let depay = "rtph264depay2";
+
+    let keyframe_packet_size = 12 + 1 + 2 + SPS.len() + 2 + PPS.len() + 2 + 500;
+    let keyframe_size = 4 + SPS.len() + 4 + PPS.len() + 4 + 500;
+
+    let expected_pay = vec![
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(true)
+            .size(keyframe_packet_size)
+            .build()],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(3_600)
+            .marker_bit(true)
+            .size(12 + 100)
+            .build()],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(7_200)
+            .marker_bit(true)
+            .size(keyframe_packet_size)
+            .build()],
+    ];
+
+    let expected_depay = vec![
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .size(keyframe_size)
+            .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .size(4 + 100)
+            .flags(gst::BufferFlags::MARKER | gst::BufferFlags::DELTA_UNIT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .size(keyframe_size)
+            .flags(gst::BufferFlags::MARKER)
+            .build()],
+    ];
+
+    run_test_pipeline(
+        Source::Buffers(caps(), buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
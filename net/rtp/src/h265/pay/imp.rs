@@ -0,0 +1,659 @@
+// SPDX-License-Identifier: MPL-2.0
+
+/**
+ * SECTION:element-rtph265pay2
+ * @see_also: rtph265depay2, h265parse, x265enc
+ *
+ * Payload an H.265 video stream into RTP packets as per [RFC 7798][rfc-7798].
+ *
+ * NAL units of the same access unit are aggregated into aggregation packets (AP) if they fit into
+ * a single packet, and NAL units that don't fit are fragmented into fragmentation units (FU).
+ * Aggregation can be disabled via the `aggregate-mode` property.
+ *
+ * The VPS, SPS and PPS are signalled in the `sprop-vps`, `sprop-sps` and `sprop-pps` fields of
+ * the caps. Additionally they can be re-sent in-band in front of IRAP pictures via the
+ * `config-interval` property, and they're always re-sent with the next IRAP picture if a
+ * downstream element requests a keyframe with all headers.
+ *
+ * [rfc-7798]: https://www.rfc-editor.org/rfc/rfc7798.html
+ *
+ * ## Example pipeline
+ *
+ * |[
+ * gst-launch-1.0 videotestsrc ! video/x-raw,width=1280,height=720,format=I420 ! timeoverlay font-desc=Sans,22 ! x265enc tune=zerolatency ! h265parse ! rtph265pay2 config-interval=-1 ! udpsink host=127.0.0.1 port=5004
+ * ]| This will create and payload an H.265 video stream with a test pattern and send it out via
+ * UDP to localhost port 5004.
+ *
+ * Since: plugins-rs-0.13.0
+ */
+use atomic_refcell::AtomicRefCell;
+use gst::{glib, prelude::*, subclass::prelude::*};
+use smallvec::SmallVec;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    basepay::{RtpBasePay2Ext, RtpBasePay2ImplExt},
+    h26x::{self, StreamFormat},
+};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rtph265pay2",
+        gst::DebugColorFlags::empty(),
+        Some("RTP H.265 Payloader"),
+    )
+});
+
+const NAL_TYPE_VPS: u8 = 32;
+const NAL_TYPE_SPS: u8 = 33;
+const NAL_TYPE_PPS: u8 = 34;
+const NAL_TYPE_AUD: u8 = 35;
+const NAL_TYPE_AP: u8 = 48;
+const NAL_TYPE_FU: u8 = 49;
+
+/// Range of NAL unit types of IRAP pictures.
+const NAL_TYPES_IRAP: std::ops::RangeInclusive<u8> = 16..=23;
+
+fn nal_type(nal_unit: &[u8]) -> u8 {
+    (nal_unit[0] >> 1) & 0x3f
+}
+
+const DEFAULT_CONFIG_INTERVAL: i32 = 0;
+
+#[derive(Clone)]
+struct Settings {
+    aggregate_mode: super::AggregateMode,
+    config_interval: i32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            aggregate_mode: super::AggregateMode::default(),
+            config_interval: DEFAULT_CONFIG_INTERVAL,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    stream_format: StreamFormat,
+    /// Last known VPS, either from the caps or from the stream.
+    vps: Vec<Vec<u8>>,
+    /// Last known SPS, either from the caps or from the stream.
+    sps: Vec<Vec<u8>>,
+    /// Last known PPS, either from the caps or from the stream.
+    pps: Vec<Vec<u8>>,
+    /// PTS of the last IRAP picture that was sent together with VPS / SPS / PPS.
+    last_config_pts: Option<gst::ClockTime>,
+}
+
+#[derive(Default)]
+pub struct RtpH265Pay {
+    settings: Mutex<Settings>,
+    state: AtomicRefCell<State>,
+    /// Set when downstream requested a keyframe with all headers.
+    force_config: AtomicBool,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RtpH265Pay {
+    const NAME: &'static str = "GstRtpH265Pay2";
+    type Type = super::RtpH265Pay;
+    type ParentType = crate::basepay::RtpBasePay2;
+}
+
+impl ObjectImpl for RtpH265Pay {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecEnum::builder::<super::AggregateMode>("aggregate-mode")
+                    .nick("Aggregate Mode")
+                    .blurb("Whether to aggregate NAL units of an access unit into aggregation packets")
+                    .default_value(Settings::default().aggregate_mode)
+                    .mutable_ready()
+                    .build(),
+                // Using same type/semantics as C payloaders
+                glib::ParamSpecInt::builder("config-interval")
+                    .nick("VPS SPS PPS Send Interval")
+                    .blurb("Send VPS, SPS and PPS in-band in front of IRAP pictures at this interval in seconds (0 = disabled, -1 = with every IRAP picture)")
+                    .default_value(DEFAULT_CONFIG_INTERVAL)
+                    .minimum(-1)
+                    .maximum(3600)
+                    .mutable_playing()
+                    .build(),
+            ]
+        });
+
+        &PROPERTIES
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "aggregate-mode" => {
+                self.settings.lock().unwrap().aggregate_mode = value.get().unwrap();
+            }
+            "config-interval" => {
+                self.settings.lock().unwrap().config_interval = value.get().unwrap();
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "aggregate-mode" => self.settings.lock().unwrap().aggregate_mode.to_value(),
+            "config-interval" => self.settings.lock().unwrap().config_interval.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for RtpH265Pay {}
+
+impl ElementImpl for RtpH265Pay {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "RTP H.265 payloader",
+                "Codec/Payloader/Network/RTP",
+                "Payload H.265 as RTP packets",
+                "agent <agent@local>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("video/x-h265")
+                    .field(
+                        "stream-format",
+                        gst::List::new(["hvc1", "hev1", "byte-stream"]),
+                    )
+                    .field("alignment", "au")
+                    .build(),
+            )
+            .unwrap();
+
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("application/x-rtp")
+                    .field("media", "video")
+                    .field("clock-rate", 90_000i32)
+                    .field("encoding-name", "H265")
+                    .build(),
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl RtpH265Pay {
+    /// Configures the src caps based on the currently known VPS / SPS / PPS.
+    fn update_src_caps(&self, state: &State) {
+        let mut caps_builder = gst::Caps::builder("application/x-rtp")
+            .field("media", "video")
+            .field("clock-rate", 90_000i32)
+            .field("encoding-name", "H265");
+
+        for (field, nal_units) in [
+            ("sprop-vps", &state.vps),
+            ("sprop-sps", &state.sps),
+            ("sprop-pps", &state.pps),
+        ] {
+            if !nal_units.is_empty() {
+                caps_builder = caps_builder.field(field, h26x::sprop_string(nal_units));
+            }
+        }
+
+        self.obj().set_src_caps(&caps_builder.build());
+    }
+
+    /// Packetizes the NAL units of one access unit.
+    fn packetize(
+        &self,
+        id: u64,
+        nal_units: &[&[u8]],
+        aggregate: bool,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let max_payload_size = self.obj().max_payload_size() as usize;
+
+        // Need space for at least the payload header, FU header and one byte of payload.
+        if max_payload_size < 4 {
+            gst::error!(CAT, imp = self, "Too small MTU configured for stream");
+            gst::element_imp_error!(
+                self,
+                gst::LibraryError::Settings,
+                ["Too small MTU configured for stream"]
+            );
+            return Err(gst::FlowError::Error);
+        }
+
+        let mut ap = SmallVec::<[&[u8]; 16]>::new();
+        let mut ap_size = 2;
+
+        for (idx, &nal_unit) in nal_units.iter().enumerate() {
+            let last = idx + 1 == nal_units.len();
+
+            if aggregate && ap_size + 2 + nal_unit.len() <= max_payload_size {
+                ap.push(nal_unit);
+                ap_size += 2 + nal_unit.len();
+
+                if last {
+                    self.queue_ap(id, &ap, true)?;
+                }
+                continue;
+            }
+
+            // Doesn't fit into the current AP anymore, so send out what we have so far.
+            self.queue_ap(id, &ap, false)?;
+            ap.clear();
+            ap_size = 2;
+
+            if aggregate && !last && ap_size + 2 + nal_unit.len() <= max_payload_size {
+                ap.push(nal_unit);
+                ap_size += 2 + nal_unit.len();
+                continue;
+            }
+
+            if nal_unit.len() <= max_payload_size {
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Sending NAL unit of size {} in a single packet",
+                    nal_unit.len(),
+                );
+
+                self.obj().queue_packet(
+                    id.into(),
+                    rtp_types::RtpPacketBuilder::new()
+                        .marker_bit(last)
+                        .payload(nal_unit),
+                )?;
+                continue;
+            }
+
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Fragmenting NAL unit of size {} into FU packets",
+                nal_unit.len(),
+            );
+
+            // Payload header is the NAL unit header with the type replaced.
+            let payload_header = [(nal_unit[0] & 0x81) | (NAL_TYPE_FU << 1), nal_unit[1]];
+            let nal_type = nal_type(nal_unit);
+
+            let mut data = &nal_unit[2..];
+            let mut first = true;
+            while !data.is_empty() {
+                let payload_size = std::cmp::min(max_payload_size - 3, data.len());
+                let end = payload_size == data.len();
+
+                let fu_header = [
+                    payload_header[0],
+                    payload_header[1],
+                    (if first { 0x80 } else { 0x00 }) | (if end { 0x40 } else { 0x00 }) | nal_type,
+                ];
+
+                self.obj().queue_packet(
+                    id.into(),
+                    rtp_types::RtpPacketBuilder::new()
+                        .marker_bit(last && end)
+                        .payload(fu_header.as_slice())
+                        .payload(&data[..payload_size]),
+                )?;
+
+                data = &data[payload_size..];
+                first = false;
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Queues a packet for the aggregated NAL units.
+    ///
+    /// A single NAL unit is sent as-is without AP header.
+    fn queue_ap(
+        &self,
+        id: u64,
+        nal_units: &[&[u8]],
+        marker: bool,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        match nal_units {
+            [] => Ok(gst::FlowSuccess::Ok),
+            [nal_unit] => self.obj().queue_packet(
+                id.into(),
+                rtp_types::RtpPacketBuilder::new()
+                    .marker_bit(marker)
+                    .payload(*nal_unit),
+            ),
+            _ => {
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Aggregating {} NAL units into an AP packet",
+                    nal_units.len(),
+                );
+
+                // F bit is set if any of the aggregated NAL units has it set, LayerId and TID
+                // are the minimum of all aggregated NAL units.
+                let forbidden = nal_units.iter().fold(0, |f, n| f | (n[0] & 0x80));
+                let layer_id = nal_units
+                    .iter()
+                    .map(|n| (((n[0] & 0x01) as u16) << 5) | ((n[1] >> 3) as u16))
+                    .min()
+                    .unwrap();
+                let tid = nal_units.iter().map(|n| n[1] & 0x07).min().unwrap();
+                let ap_header = [
+                    forbidden | (NAL_TYPE_AP << 1) | (layer_id >> 5) as u8,
+                    (((layer_id & 0x1f) as u8) << 3) | tid,
+                ];
+
+                let sizes = nal_units
+                    .iter()
+                    .map(|n| (n.len() as u16).to_be_bytes())
+                    .collect::<SmallVec<[[u8; 2]; 16]>>();
+
+                let mut builder = rtp_types::RtpPacketBuilder::new()
+                    .marker_bit(marker)
+                    .payload(ap_header.as_slice());
+                for (size, nal_unit) in Iterator::zip(sizes.iter(), nal_units.iter()) {
+                    builder = builder.payload(size.as_slice()).payload(*nal_unit);
+                }
+
+                self.obj().queue_packet(id.into(), builder)
+            }
+        }
+    }
+}
+
+impl crate::basepay::RtpBasePay2Impl for RtpH265Pay {
+    const ALLOWED_META_TAGS: &'static [&'static str] = &["video"];
+
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+        self.force_config.store(false, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.borrow_mut() = State::default();
+
+        Ok(())
+    }
+
+    fn set_sink_caps(&self, caps: &gst::Caps) -> bool {
+        gst::debug!(CAT, imp = self, "received caps {caps:?}");
+
+        let s = caps.structure(0).unwrap();
+        let mut state = self.state.borrow_mut();
+
+        match s.get::<&str>("stream-format") {
+            Ok(stream_format @ ("hvc1" | "hev1")) => {
+                if let Ok(codec_data) = s.get::<gst::Buffer>("codec_data") {
+                    let map = codec_data.map_readable().unwrap();
+                    match parse_hvcc(&map) {
+                        Ok((length_size, vps, sps, pps)) => {
+                            gst::debug!(
+                                CAT,
+                                imp = self,
+                                "Parsed hvcC with NAL length size {length_size}, {} VPS, {} SPS and {} PPS",
+                                vps.len(),
+                                sps.len(),
+                                pps.len(),
+                            );
+                            state.stream_format = StreamFormat::LengthPrefixed(length_size);
+                            if !vps.is_empty() {
+                                state.vps = vps;
+                            }
+                            if !sps.is_empty() {
+                                state.sps = sps;
+                            }
+                            if !pps.is_empty() {
+                                state.pps = pps;
+                            }
+                        }
+                        Err(err) => {
+                            gst::error!(CAT, imp = self, "Failed to parse codec_data: {err}");
+                            return false;
+                        }
+                    }
+                } else if stream_format == "hev1" {
+                    // Parameter sets are only in-band, assume 4 byte NAL unit lengths
+                    state.stream_format = StreamFormat::LengthPrefixed(4);
+                } else {
+                    gst::error!(CAT, imp = self, "hvc1 caps without codec_data");
+                    return false;
+                }
+            }
+            _ => {
+                state.stream_format = StreamFormat::ByteStream;
+            }
+        }
+
+        self.update_src_caps(&state);
+
+        true
+    }
+
+    fn handle_buffer(
+        &self,
+        buffer: &gst::Buffer,
+        id: u64,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.borrow_mut();
+        let settings = self.settings.lock().unwrap().clone();
+
+        gst::trace!(CAT, imp = self, "received buffer of size {}", buffer.size());
+
+        let map = buffer.map_readable().map_err(|_| {
+            gst::element_imp_error!(
+                self,
+                gst::ResourceError::Read,
+                ["Failed to map buffer readable"]
+            );
+
+            gst::FlowError::Error
+        })?;
+
+        // Parameter sets that are inserted in front of the access unit, if any.
+        let mut config = Vec::new();
+
+        let mut nal_units = match h26x::split_nal_units(state.stream_format, &map) {
+            Ok(nal_units) => nal_units,
+            Err(err) => {
+                gst::warning!(CAT, imp = self, "Failed to parse access unit: {err}");
+                self.obj().drop_buffers(..=id);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+
+        // NAL units always have a two byte header
+        nal_units.retain(|nal_unit| nal_unit.len() >= 2);
+
+        if nal_units.is_empty() {
+            gst::warning!(CAT, imp = self, "Access unit without NAL units");
+            self.obj().drop_buffers(..=id);
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        let mut vps = Vec::new();
+        let mut sps = Vec::new();
+        let mut pps = Vec::new();
+        let mut is_irap = false;
+        for nal_unit in &nal_units {
+            match nal_type(nal_unit) {
+                NAL_TYPE_VPS => vps.push(nal_unit.to_vec()),
+                NAL_TYPE_SPS => sps.push(nal_unit.to_vec()),
+                NAL_TYPE_PPS => pps.push(nal_unit.to_vec()),
+                t if NAL_TYPES_IRAP.contains(&t) => is_irap = true,
+                _ => (),
+            }
+        }
+
+        let has_config = !vps.is_empty() && !sps.is_empty() && !pps.is_empty();
+        let mut config_changed = false;
+        let state_ref = &mut *state;
+        for (new, old) in [
+            (vps, &mut state_ref.vps),
+            (sps, &mut state_ref.sps),
+            (pps, &mut state_ref.pps),
+        ] {
+            if !new.is_empty() && new != *old {
+                *old = new;
+                config_changed = true;
+            }
+        }
+        if config_changed {
+            gst::debug!(CAT, imp = self, "Parameter sets changed");
+            self.update_src_caps(&state);
+        }
+
+        // Decide whether VPS / SPS / PPS have to be inserted in front of this IRAP picture.
+        if is_irap {
+            let force_config = self.force_config.swap(false, Ordering::SeqCst);
+
+            let insert_config = !has_config
+                && !state.vps.is_empty()
+                && !state.sps.is_empty()
+                && !state.pps.is_empty()
+                && (force_config
+                    || match settings.config_interval {
+                        0 => false,
+                        -1 => true,
+                        interval => Option::zip(buffer.pts(), state.last_config_pts).map_or(
+                            true,
+                            |(pts, last_config_pts)| {
+                                pts.saturating_sub(last_config_pts)
+                                    >= gst::ClockTime::from_seconds(interval as u64)
+                            },
+                        ),
+                    });
+
+            if insert_config {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Inserting VPS / SPS / PPS in front of IRAP picture"
+                );
+                config.extend(
+                    state
+                        .vps
+                        .iter()
+                        .chain(state.sps.iter())
+                        .chain(state.pps.iter())
+                        .cloned(),
+                );
+            }
+
+            if has_config || insert_config {
+                state.last_config_pts = buffer.pts();
+            }
+        }
+
+        if !config.is_empty() {
+            // Parameter sets go after the access unit delimiter, if any.
+            let pos = nal_units
+                .iter()
+                .position(|nal_unit| nal_type(nal_unit) != NAL_TYPE_AUD)
+                .unwrap_or(nal_units.len());
+            nal_units.insert_many(pos, config.iter().map(Vec::as_slice));
+        }
+        drop(state);
+
+        self.packetize(
+            id,
+            &nal_units,
+            settings.aggregate_mode == super::AggregateMode::ZeroLatency,
+        )
+    }
+
+    fn src_event(&self, event: gst::Event) -> Result<gst::FlowSuccess, gst::FlowError> {
+        if let Ok(fku) = gst_video::UpstreamForceKeyUnitEvent::parse(&event) {
+            if fku.all_headers {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Sending VPS / SPS / PPS with next IRAP picture"
+                );
+                self.force_config.store(true, Ordering::SeqCst);
+            }
+        }
+
+        self.parent_src_event(event)
+    }
+}
+
+/// Parses an `HEVCDecoderConfigurationRecord` and returns the NAL unit length size, VPS, SPS
+/// and PPS.
+#[allow(clippy::type_complexity)]
+fn parse_hvcc(
+    data: &[u8],
+) -> Result<(usize, Vec<Vec<u8>>, Vec<Vec<u8>>, Vec<Vec<u8>>), anyhow::Error> {
+    use anyhow::Context as _;
+
+    fn read_u16(data: &mut &[u8]) -> Result<u16, anyhow::Error> {
+        let v = data
+            .get(..2)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]))
+            .context("Too short")?;
+        *data = &data[2..];
+        Ok(v)
+    }
+
+    if data.len() < 23 {
+        anyhow::bail!("Too short");
+    }
+    if data[0] != 1 {
+        anyhow::bail!("Unsupported version {}", data[0]);
+    }
+
+    let length_size = (data[21] & 0x03) as usize + 1;
+    let num_arrays = data[22] as usize;
+
+    let mut vps = Vec::new();
+    let mut sps = Vec::new();
+    let mut pps = Vec::new();
+
+    let mut data = &data[23..];
+    for _ in 0..num_arrays {
+        let nal_type = *data.first().context("Too short")? & 0x3f;
+        data = &data[1..];
+
+        let num_nal_units = read_u16(&mut data)?;
+        for _ in 0..num_nal_units {
+            let len = read_u16(&mut data)? as usize;
+            let nal_unit = data.get(..len).context("Too short")?.to_vec();
+            data = &data[len..];
+
+            match nal_type {
+                NAL_TYPE_VPS => vps.push(nal_unit),
+                NAL_TYPE_SPS => sps.push(nal_unit),
+                NAL_TYPE_PPS => pps.push(nal_unit),
+                _ => (),
+            }
+        }
+    }
+
+    Ok((length_size, vps, sps, pps))
+}
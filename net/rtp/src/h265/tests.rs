@@ -0,0 +1,372 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::tests::{run_test_pipeline, ExpectedBuffer, ExpectedPacket, Source};
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        crate::plugin_register_static().expect("rtph265 test");
+    });
+}
+
+const VPS: &[u8] = &[
+    0x40, 0x01, 0x0c, 0x01, 0xff, 0xff, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90,
+];
+const SPS: &[u8] = &[
+    0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03,
+    0x00, 0x5d, 0xa0, 0x02,
+];
+const PPS: &[u8] = &[0x44, 0x01, 0xc1, 0x72, 0xb4, 0x62];
+
+/// Creates a NAL unit with the given NAL unit type and size.
+fn nal_unit(nal_type: u8, size: usize) -> Vec<u8> {
+    let mut nal_unit = vec![0xab; size];
+    nal_unit[0] = nal_type << 1;
+    nal_unit[1] = 0x01;
+    nal_unit
+}
+
+/// Creates a byte-stream access unit from the given NAL units.
+fn access_unit(pos: u64, nal_units: &[&[u8]]) -> gst::Buffer {
+    let mut data = Vec::new();
+    for nal_unit in nal_units {
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+        data.extend_from_slice(nal_unit);
+    }
+
+    let is_irap = nal_units
+        .iter()
+        .any(|nal_unit| (16..=23).contains(&((nal_unit[0] >> 1) & 0x3f)));
+
+    let mut buffer = gst::Buffer::from_mut_slice(data);
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::from_mseconds(pos * 40));
+        if !is_irap {
+            buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+        }
+    }
+
+    buffer
+}
+
+fn caps() -> gst::Caps {
+    gst::Caps::builder("video/x-h265")
+        .field("stream-format", "byte-stream")
+        .field("alignment", "au")
+        .build()
+}
+
+#[test]
+fn test_h265_aggregate_and_fragment() {
+    init();
+
+    let irap = nal_unit(19, 3000);
+    let non_irap = nal_unit(1, 100);
+
+    let buffers = vec![
+        access_unit(0, &[VPS, SPS, PPS, &irap]),
+        access_unit(1, &[&non_irap]),
+        access_unit(2, &[&non_irap]),
+    ];
+
+    let pay = "rtph265pay2";
+    let depay = "rtph265depay2";
+
+    let expected_pay = vec![
+        vec![
+            // VPS, SPS and PPS aggregated into an AP packet
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::DISCONT)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(12 + 2 + 2 + VPS.len() + 2 + SPS.len() + 2 + PPS.len())
+                .build(),
+            // IRAP picture fragmented into three FU packets
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(1400)
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::MARKER)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(true)
+                .size(12 + 3 + (2998 - 2 * 1385))
+                .build(),
+        ],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(3_600)
+            .marker_bit(true)
+            .size(12 + 100)
+            .build()],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(7_200)
+            .marker_bit(true)
+            .size(12 + 100)
+            .build()],
+    ];
+
+    let expected_depay = vec![
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .size(4 + VPS.len() + 4 + SPS.len() + 4 + PPS.len() + 4 + 3000)
+            .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .size(4 + 100)
+            .flags(gst::BufferFlags::MARKER | gst::BufferFlags::DELTA_UNIT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .size(4 + 100)
+            .flags(gst::BufferFlags::MARKER | gst::BufferFlags::DELTA_UNIT)
+            .build()],
+    ];
+
+    run_test_pipeline(
+        Source::Buffers(caps(), buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
+
+#[test]
+fn test_h265_no_aggregation() {
+    init();
+
+    let irap = nal_unit(19, 500);
+
+    let buffers = vec![access_unit(0, &[VPS, SPS, PPS, &irap])];
+
+    let pay = "rtph265pay2 aggregate-mode=none";
+    let depay = "rtph265depay2";
+
+    let expected_pay = vec![vec![
+        ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::DISCONT)
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(false)
+            .size(12 + VPS.len())
+            .build(),
+        ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::empty())
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(false)
+            .size(12 + SPS.len())
+            .build(),
+        ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::empty())
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(false)
+            .size(12 + PPS.len())
+            .build(),
+        ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(true)
+            .size(12 + 500)
+            .build(),
+    ]];
+
+    let expected_depay = vec![vec![ExpectedBuffer::builder()
+        .pts(gst::ClockTime::from_mseconds(0))
+        .size(4 + VPS.len() + 4 + SPS.len() + 4 + PPS.len() + 4 + 500)
+        .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+        .build()]];
+
+    run_test_pipeline(
+        Source::Buffers(caps(), buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
+
+#[test]
+fn test_h265_drop_incomplete_au() {
+    init();
+
+    let irap = nal_unit(19, 500);
+    let trail = nal_unit(1, 100);
+
+    let buffers = vec![
+        access_unit(0, &[VPS, SPS, PPS, &irap]),
+        access_unit(1, &[&trail]),
+    ];
+
+    let pay = "rtph265pay2 aggregate-mode=none";
+    let depay = "rtph265depay2";
+
+    let expected_pay = vec![
+        vec![
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::DISCONT)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(12 + VPS.len())
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(12 + SPS.len())
+                .build(),
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::empty())
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(false)
+                .size(12 + PPS.len())
+                .build(),
+            // The last packet of the access unit gets lost
+            ExpectedPacket::builder()
+                .pts(gst::ClockTime::from_mseconds(0))
+                .flags(gst::BufferFlags::MARKER)
+                .pt(96)
+                .rtp_time(0)
+                .marker_bit(true)
+                .size(12 + 500)
+                .drop(true)
+                .build(),
+        ],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(3_600)
+            .marker_bit(true)
+            .size(12 + 100)
+            .build()],
+    ];
+
+    // The incomplete first access unit is not output
+    let expected_depay = vec![vec![ExpectedBuffer::builder()
+        .pts(gst::ClockTime::from_mseconds(40))
+        .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER | gst::BufferFlags::DELTA_UNIT)
+        .build()]];
+
+    run_test_pipeline(
+        Source::Buffers(caps(), buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
+
+#[test]
+fn test_h265_config_interval() {
+    init();
+
+    let irap = nal_unit(19, 500);
+    let non_irap = nal_unit(1, 100);
+
+    // Only the first IRAP picture has VPS / SPS / PPS, the payloader has to insert them in front
+    // of the second one.
+    let buffers = vec![
+        access_unit(0, &[VPS, SPS, PPS, &irap]),
+        access_unit(1, &[&non_irap]),
+        access_unit(2, &[&irap]),
+    ];
+
+    let pay = "rtph265pay2 config-interval=-1";
+    let depay = "rtph265depay2";
+
+    let keyframe_packet_size = 12 + 2 + 2 + VPS.len() + 2 + SPS.len() + 2 + PPS.len() + 2 + 500;
+    let keyframe_size = 4 + VPS.len() + 4 + SPS.len() + 4 + PPS.len() + 4 + 500;
+
+    let expected_pay = vec![
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(0)
+            .marker_bit(true)
+            .size(keyframe_packet_size)
+            .build()],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(3_600)
+            .marker_bit(true)
+            .size(12 + 100)
+            .build()],
+        vec![ExpectedPacket::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .flags(gst::BufferFlags::MARKER)
+            .pt(96)
+            .rtp_time(7_200)
+            .marker_bit(true)
+            .size(keyframe_packet_size)
+            .build()],
+    ];
+
+    let expected_depay = vec![
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(0))
+            .size(keyframe_size)
+            .flags(gst::BufferFlags::DISCONT | gst::BufferFlags::MARKER)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(40))
+            .size(4 + 100)
+            .flags(gst::BufferFlags::MARKER | gst::BufferFlags::DELTA_UNIT)
+            .build()],
+        vec![ExpectedBuffer::builder()
+            .pts(gst::ClockTime::from_mseconds(80))
+            .size(keyframe_size)
+            .flags(gst::BufferFlags::MARKER)
+            .build()],
+    ];
+
+    run_test_pipeline(
+        Source::Buffers(caps(), buffers),
+        pay,
+        depay,
+        expected_pay,
+        expected_depay,
+    );
+}
@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Helpers shared between the H.264 and H.265 payloaders and depayloaders.
+
+use anyhow::{bail, Context as _};
+use gst::glib;
+use smallvec::SmallVec;
+
+/// Start code that is prepended to each NAL unit in byte-stream format output.
+pub const START_CODE: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
+
+/// How NAL units are delimited in the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamFormat {
+    /// Annex B byte-stream format with start codes.
+    #[default]
+    ByteStream,
+    /// NAL units are prefixed with their length in the given number of bytes.
+    LengthPrefixed(usize),
+}
+
+/// Splits `data` into its NAL units, without start codes or length prefixes.
+pub fn split_nal_units(
+    format: StreamFormat,
+    data: &[u8],
+) -> Result<SmallVec<[&[u8]; 16]>, anyhow::Error> {
+    let mut nal_units = SmallVec::new();
+
+    match format {
+        StreamFormat::ByteStream => {
+            let Some(start) = find_start_code(data) else {
+                bail!("No start code found");
+            };
+
+            let mut data = &data[start + 3..];
+            while !data.is_empty() {
+                let end = find_start_code(data).unwrap_or(data.len());
+                let mut nal_unit = &data[..end];
+                data = data.get(end + 3..).unwrap_or(&[]);
+
+                // Trailing zeroes are either part of a 4 byte start code or trailing_zero_8bits.
+                // NAL units themselves can never end with a zero byte.
+                while let [head @ .., 0] = nal_unit {
+                    nal_unit = head;
+                }
+
+                if !nal_unit.is_empty() {
+                    nal_units.push(nal_unit);
+                }
+            }
+        }
+        StreamFormat::LengthPrefixed(length_size) => {
+            let mut data = data;
+            while !data.is_empty() {
+                if data.len() < length_size {
+                    bail!("Incomplete NAL unit length");
+                }
+
+                let len = data[..length_size]
+                    .iter()
+                    .fold(0usize, |len, b| (len << 8) | *b as usize);
+                data = &data[length_size..];
+
+                let nal_unit = data
+                    .get(..len)
+                    .with_context(|| format!("NAL unit of length {len} exceeds buffer"))?;
+                data = &data[len..];
+
+                if !nal_unit.is_empty() {
+                    nal_units.push(nal_unit);
+                }
+            }
+        }
+    }
+
+    Ok(nal_units)
+}
+
+fn find_start_code(data: &[u8]) -> Option<usize> {
+    data.windows(3).position(|w| w == [0x00, 0x00, 0x01])
+}
+
+/// Parses a comma-separated list of base64 encoded NAL units, as used in the `sprop-*` SDP
+/// parameters.
+pub fn parse_sprop(s: &str) -> Vec<Vec<u8>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(glib::base64_decode)
+        .filter(|nal_unit| !nal_unit.is_empty())
+        .collect()
+}
+
+/// Creates a comma-separated list of base64 encoded NAL units for the `sprop-*` SDP parameters.
+pub fn sprop_string(nal_units: &[Vec<u8>]) -> String {
+    nal_units
+        .iter()
+        .map(|nal_unit| glib::base64_encode(nal_unit).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_byte_stream() {
+        let data = [
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x01, 0x02, 0x00, 0x00, 0x01, 0x68, 0x03, 0x00, 0x00,
+            0x00, 0x01, 0x65, 0x04, 0x05, 0x00,
+        ];
+
+        let nal_units = split_nal_units(StreamFormat::ByteStream, &data).unwrap();
+        assert_eq!(
+            nal_units.as_slice(),
+            &[&[0x67, 0x01, 0x02][..], &[0x68, 0x03], &[0x65, 0x04, 0x05]]
+        );
+
+        assert!(split_nal_units(StreamFormat::ByteStream, &[0x65, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_split_length_prefixed() {
+        let data = [
+            0x00, 0x00, 0x00, 0x02, 0x67, 0x01, 0x00, 0x00, 0x00, 0x01, 0x68,
+        ];
+
+        let nal_units = split_nal_units(StreamFormat::LengthPrefixed(4), &data).unwrap();
+        assert_eq!(nal_units.as_slice(), &[&[0x67, 0x01][..], &[0x68]]);
+
+        assert!(split_nal_units(StreamFormat::LengthPrefixed(4), &data[..9]).is_err());
+    }
+
+    #[test]
+    fn test_sprop() {
+        let nal_units = vec![vec![0x67, 0x42, 0xc0, 0x1f], vec![0x68, 0xce, 0x3c, 0x80]];
+
+        let s = sprop_string(&nal_units);
+        assert_eq!(s, "Z0LAHw==,aM48gA==");
+        assert_eq!(parse_sprop(&s), nal_units);
+    }
+}
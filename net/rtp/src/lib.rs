@@ -30,9 +30,12 @@ mod audio_discont;
 mod baseaudiopay;
 mod basedepay;
 mod basepay;
+mod h26x;
 
 mod ac3;
 mod av1;
+mod h264;
+mod h265;
 mod jpeg;
 mod klv;
 mod mp2t;
@@ -68,6 +71,12 @@ fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     av1::depay::register(plugin)?;
     av1::pay::register(plugin)?;
 
+    h264::depay::register(plugin)?;
+    h264::pay::register(plugin)?;
+
+    h265::depay::register(plugin)?;
+    h265::pay::register(plugin)?;
+
     jpeg::depay::register(plugin)?;
     jpeg::pay::register(plugin)?;
 
@@ -97,6 +97,70 @@ mod imp {
             ret.build()
         }
 
+        pub fn set_srtp_keys(&self, srtp_keys: Option<gst::Structure>) {
+            let Some(session) = self.session() else {
+                return;
+            };
+            let mut session = session.lock().unwrap();
+            let Some(srtp_keys) = srtp_keys else {
+                session.srtp.clear_keys();
+                return;
+            };
+
+            let mut ssrcs = vec![];
+            for (key, value) in srtp_keys.iter() {
+                let Ok(ssrc) = key.parse::<u32>() else {
+                    gst::warning!(CAT, "failed to parse key as an ssrc");
+                    continue;
+                };
+                let Ok(caps) = value.get::<gst::Caps>() else {
+                    gst::warning!(CAT, "{ssrc} does not contain a caps value");
+                    continue;
+                };
+                if let Err(err) = session.srtp.set_key(ssrc, Some(caps)) {
+                    gst::warning!(CAT, "Failed to set key for ssrc {ssrc}: {err}");
+                    continue;
+                }
+                ssrcs.push(ssrc);
+            }
+
+            let removed = session
+                .srtp
+                .keys()
+                .map(|(ssrc, _caps)| ssrc)
+                .filter(|ssrc| !ssrcs.contains(ssrc))
+                .collect::<Vec<_>>();
+            for ssrc in removed {
+                let _ = session.srtp.set_key(ssrc, None);
+            }
+        }
+
+        pub fn srtp_keys(&self) -> gst::Structure {
+            let mut ret = gst::Structure::builder("application/x-rtp2-srtp-keys");
+            let Some(session) = self.session() else {
+                return ret.build();
+            };
+            let session = session.lock().unwrap();
+
+            for (ssrc, caps) in session.srtp.keys() {
+                ret = ret.field(ssrc.to_string(), caps);
+            }
+
+            ret.build()
+        }
+
+        pub fn set_srtp(&self, srtp: bool) {
+            let Some(session) = self.session() else {
+                return;
+            };
+            session.lock().unwrap().srtp.set_enabled(srtp);
+        }
+
+        pub fn srtp(&self) -> bool {
+            self.session()
+                .is_some_and(|session| session.lock().unwrap().srtp.is_enabled())
+        }
+
         pub fn stats(&self) -> Option<gst::Structure> {
             let session = self.session()?;
             let session = session.lock().unwrap();
@@ -114,10 +178,21 @@ mod imp {
     impl ObjectImpl for Rtp2Session {
         fn properties() -> &'static [glib::ParamSpec] {
             static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
-                vec![glib::ParamSpecBoxed::builder::<gst::Structure>("pt-map")
-                    .nick("RTP Payload Type Map")
-                    .blurb("Mapping of RTP payload type to caps")
-                    .build()]
+                vec![
+                    glib::ParamSpecBoxed::builder::<gst::Structure>("pt-map")
+                        .nick("RTP Payload Type Map")
+                        .blurb("Mapping of RTP payload type to caps")
+                        .build(),
+                    glib::ParamSpecBoolean::builder("srtp")
+                        .nick("SRTP")
+                        .blurb("Whether to protect RTP and RTCP with SRTP / SRTCP, dropping packets of SSRCs without key")
+                        .default_value(false)
+                        .build(),
+                    glib::ParamSpecBoxed::builder::<gst::Structure>("srtp-keys")
+                        .nick("SRTP Keys")
+                        .blurb("Mapping of SSRC to application/x-srtp caps with the srtp-key, srtp-cipher, srtp-auth, srtcp-cipher, srtcp-auth and roc fields")
+                        .build(),
+                ]
             });
 
             PROPERTIES.as_ref()
@@ -126,6 +201,8 @@ mod imp {
         fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
             match pspec.name() {
                 "pt-map" => self.pt_map().to_value(),
+                "srtp" => self.srtp().to_value(),
+                "srtp-keys" => self.srtp_keys().to_value(),
                 "stats" => self.stats().to_value(),
                 _ => unreachable!(),
             }
@@ -138,6 +215,12 @@ mod imp {
                         .get::<Option<gst::Structure>>()
                         .expect("Type checked upstream"),
                 ),
+                "srtp" => self.set_srtp(value.get::<bool>().expect("Type checked upstream")),
+                "srtp-keys" => self.set_srtp_keys(
+                    value
+                        .get::<Option<gst::Structure>>()
+                        .expect("Type checked upstream"),
+                ),
                 _ => unreachable!(),
             }
         }
@@ -151,6 +234,10 @@ mod imp {
                     glib::subclass::Signal::builder("bye-ssrc")
                         .param_types([u32::static_type()])
                         .build(),
+                    glib::subclass::Signal::builder("request-srtp-key")
+                        .param_types([u32::static_type()])
+                        .return_type::<Option<gst::Caps>>()
+                        .build(),
                 ]
             });
 
@@ -312,4 +399,52 @@ mod tests {
         let _rtcp = h_rtcp.pull().unwrap();
         assert_eq!(bye_ssrc_receiver.recv().unwrap(), ssrc);
     }
+
+    #[test]
+    fn srtp_request_key() {
+        test_init();
+        let ssrc = 0x12345678;
+        let id = next_element_counter();
+        let rtpbin2 = gst::ElementFactory::make("rtpsend")
+            .property("rtp-id", id.to_string())
+            .build()
+            .unwrap();
+        let mut h =
+            gst_check::Harness::with_element(&rtpbin2, Some("rtp_sink_0"), Some("rtp_src_0"));
+        let session = h
+            .element()
+            .unwrap()
+            .emit_by_name::<gst::glib::Object>("get-session", &[&0u32]);
+        let key_caps = gst::Caps::builder("application/x-srtp")
+            .field("srtp-key", gst::Buffer::from_mut_slice(vec![0x42; 30]))
+            .field("srtp-cipher", "aes-128-icm")
+            .field("srtp-auth", "hmac-sha1-80")
+            .build();
+        let caps = key_caps.clone();
+        session.connect("request-srtp-key", false, move |args| {
+            let key_ssrc = args[1].get::<u32>().unwrap();
+            (key_ssrc == ssrc).then(|| caps.to_value())
+        });
+        session.set_property("srtp", true);
+        h.set_src_caps_str("application/x-rtp,payload=96,clock-rate=90000");
+        let mut segment = gst::Segment::new();
+        segment.set_format(gst::Format::Time);
+        h.push_event(gst::event::Segment::builder(&segment).build());
+        let data = generate_rtp_packet(ssrc, 0x34, 0x10, 16);
+        h.push(gst::Buffer::from_slice(data.clone())).unwrap();
+
+        let protected = h.pull().unwrap();
+        let protected = protected.map_readable().unwrap();
+        // 80 bit authentication tag
+        assert_eq!(protected.len(), data.len() + 10);
+        assert_eq!(protected[..12], data[..12]);
+        assert_ne!(protected[12..data.len()], data[12..]);
+
+        let srtp_keys = session.property::<gst::Structure>("srtp-keys");
+        assert!(srtp_keys.has_name("application/x-rtp2-srtp-keys"));
+        assert_eq!(
+            srtp_keys.get::<gst::Caps>(ssrc.to_string()).unwrap(),
+            key_caps
+        );
+    }
 }
@@ -4,15 +4,18 @@ use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
     task::Waker,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
-use gst::glib;
+use gst::{glib, prelude::*};
 use once_cell::sync::{Lazy, OnceCell};
 
 use super::config::Rtp2Session;
+use super::rtx::RetransmissionHistory;
 use super::session::{RtpProfile, Session};
 use super::source::ReceivedRb;
+use super::srtp::{self, SrtpContext};
+use super::twcc::{self, TwccReceiver, TwccSender};
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -142,6 +145,70 @@ impl SharedSession {
             config: Rtp2Session::new(weak_inner),
         }
     }
+
+    /// Applies the SRTP `transform` to the packet in `data` if SRTP is enabled for this session,
+    /// otherwise returns `Ok(None)`.
+    ///
+    /// If no key is known for the SSRC of the packet, the key is requested via the
+    /// `request-srtp-key` signal. Must be called without the session lock held.
+    pub(crate) fn srtp_transform(
+        &self,
+        transform: srtp::Transform,
+        data: &[u8],
+    ) -> Result<Option<Vec<u8>>, srtp::Error> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.srtp.is_enabled() {
+            return Ok(None);
+        }
+
+        match inner.srtp.transform(transform, data) {
+            Err(srtp::Error::NoKey(ssrc)) => {
+                drop(inner);
+                let caps = self
+                    .config
+                    .emit_by_name::<Option<gst::Caps>>("request-srtp-key", &[&ssrc])
+                    .ok_or(srtp::Error::NoKey(ssrc))?;
+
+                let mut inner = self.inner.lock().unwrap();
+                inner.srtp.set_key(ssrc, Some(caps))?;
+                inner.srtp.transform(transform, data).map(Some)
+            }
+            res => res.map(Some),
+        }
+    }
+
+    /// Applies the SRTP `transform` to `buffer`, see [`Self::srtp_transform`]. Timestamps and
+    /// flags are kept.
+    pub(crate) fn srtp_transform_buffer(
+        &self,
+        transform: srtp::Transform,
+        buffer: gst::Buffer,
+    ) -> Result<gst::Buffer, srtp::Error> {
+        let res = {
+            let mapped = buffer
+                .map_readable()
+                .map_err(|_| srtp::Error::InvalidPacket)?;
+            self.srtp_transform(transform, &mapped)?
+        };
+        Ok(match res {
+            Some(data) => buffer_from_data(&buffer, data),
+            None => buffer,
+        })
+    }
+}
+
+/// Creates a new buffer with `data` and the timestamps and flags of `buffer`.
+fn buffer_from_data(buffer: &gst::Buffer, data: Vec<u8>) -> gst::Buffer {
+    let mut new_buffer = gst::Buffer::from_mut_slice(data);
+    {
+        let new_buffer = new_buffer.get_mut().unwrap();
+        new_buffer.set_pts(buffer.pts());
+        new_buffer.set_dts(buffer.dts());
+        new_buffer.set_duration(buffer.duration());
+        new_buffer.set_flags(buffer.flags());
+    }
+
+    new_buffer
 }
 
 #[derive(Debug)]
@@ -154,6 +221,16 @@ pub(crate) struct SharedSessionInner {
 
     pub(crate) rtcp_waker: Option<Waker>,
     pub(crate) rtp_send_sinkpad: Option<gst::Pad>,
+
+    // Recently sent packets for retransmission and the pending retransmission requests (ssrc,
+    // seqnums) that still have to be handled by the sender
+    pub(crate) rtx_history: RetransmissionHistory,
+    pub(crate) pending_retransmissions: Vec<(u32, Vec<u16>)>,
+
+    pub(crate) srtp: SrtpContext,
+
+    pub(crate) twcc_sender: TwccSender,
+    pub(crate) twcc_receiver: TwccReceiver,
 }
 
 impl SharedSessionInner {
@@ -166,6 +243,14 @@ impl SharedSessionInner {
             pt_map: HashMap::default(),
             rtcp_waker: None,
             rtp_send_sinkpad: None,
+
+            rtx_history: RetransmissionHistory::default(),
+            pending_retransmissions: Vec::new(),
+
+            srtp: SrtpContext::default(),
+
+            twcc_sender: TwccSender::default(),
+            twcc_receiver: TwccReceiver::default(),
         }
     }
 
@@ -193,13 +278,143 @@ impl SharedSessionInner {
         )
     }
 
+    /// Returns the RTX payload type (RFC 4588) and its caps for retransmissions of packets with
+    /// payload type `pt`, if any.
+    pub(crate) fn rtx_pt_from_pt(&self, pt: u8) -> Option<(u8, &gst::Caps)> {
+        self.pt_map
+            .iter()
+            .find(|(_rtx_pt, caps)| rtx_apt_from_caps(caps) == Some(pt))
+            .map(|(&rtx_pt, caps)| (rtx_pt, caps))
+    }
+
+    /// Returns the payload type of the original packets if `rtx_pt` is an RTX payload type.
+    pub(crate) fn pt_from_rtx_pt(&self, rtx_pt: u8) -> Option<u8> {
+        self.pt_map
+            .get(&rtx_pt)
+            .and_then(|caps| rtx_apt_from_caps(caps))
+    }
+
+    /// Creates the retransmission packets for all pending retransmission requests.
+    pub(crate) fn take_retransmissions(&mut self) -> Vec<gst::Buffer> {
+        let mut buffers = vec![];
+
+        for (ssrc, seqnums) in std::mem::take(&mut self.pending_retransmissions) {
+            let Some(pt) = self
+                .session
+                .local_send_source_by_ssrc(ssrc)
+                .and_then(|source| source.payload_type())
+            else {
+                continue;
+            };
+            let Some((rtx_pt, _caps)) = self.rtx_pt_from_pt(pt) else {
+                gst::debug!(CAT, "No RTX payload type for pt {pt} of ssrc {ssrc}");
+                continue;
+            };
+
+            for seqnum in seqnums {
+                if let Some(buffer) = self.rtx_history.retransmission(ssrc, seqnum, rtx_pt) {
+                    gst::trace!(CAT, "Retransmitting packet {seqnum} of ssrc {ssrc}");
+                    buffers.push(buffer);
+                } else {
+                    gst::debug!(
+                        CAT,
+                        "Packet {seqnum} of ssrc {ssrc} not available for retransmission"
+                    );
+                }
+            }
+        }
+
+        buffers
+    }
+
+    /// Returns the id of the transport-wide sequence number header extension for `pt`, if
+    /// configured in its caps. RTX payload types without their own configuration use the one of
+    /// the original payload type.
+    fn twcc_extension_id(&self, pt: u8) -> Option<u8> {
+        let extension_id = |pt| {
+            self.pt_map
+                .get(&pt)
+                .and_then(|caps| caps.structure(0))
+                .and_then(twcc::extension_id_from_caps)
+        };
+
+        extension_id(pt).or_else(|| self.pt_from_rtx_pt(pt).and_then(extension_id))
+    }
+
+    /// Writes the transport-wide sequence number into `buffer` if transport-wide congestion
+    /// control is enabled for its payload type, and remembers the send `time`.
+    pub(crate) fn twcc_send_buffer(
+        &mut self,
+        buffer: gst::Buffer,
+        time: Option<gst::ClockTime>,
+    ) -> gst::Buffer {
+        let data = match buffer.map_readable() {
+            Ok(mapped) => self.twcc_write_seqnum(&mapped, time),
+            Err(_) => None,
+        };
+
+        match data {
+            Some(data) => buffer_from_data(&buffer, data),
+            None => buffer,
+        }
+    }
+
+    fn twcc_write_seqnum(&mut self, data: &[u8], time: Option<gst::ClockTime>) -> Option<Vec<u8>> {
+        let rtp = rtp_types::RtpPacket::parse(data).ok()?;
+        let id = self.twcc_extension_id(rtp.payload_type())?;
+
+        let Some(data) = twcc::write_seqnum(data, id, self.twcc_sender.next_seqnum()) else {
+            gst::warning!(CAT, "Failed to write transport-wide sequence number");
+            return None;
+        };
+        self.twcc_sender
+            .packet_sent(time, data.len(), rtp.payload_type(), rtp.ssrc());
+
+        Some(data)
+    }
+
+    /// Records the arrival of `rtp` for transport-wide congestion control feedback.
+    pub(crate) fn twcc_receive_packet(&mut self, rtp: &rtp_types::RtpPacket, now: Instant) {
+        let Some(seqnum) = self
+            .twcc_extension_id(rtp.payload_type())
+            .and_then(|id| twcc::read_seqnum(rtp, id))
+        else {
+            return;
+        };
+
+        if self.twcc_receiver.packet_received(seqnum, rtp.ssrc(), now) {
+            // wake the rtcp task so that it schedules the feedback
+            if let Some(waker) = self.rtcp_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Returns a transport-wide feedback RTCP packet if one is due.
+    pub(crate) fn poll_twcc_feedback(
+        &mut self,
+        now: Instant,
+        ntp_now: SystemTime,
+    ) -> Option<Vec<u8>> {
+        self.twcc_receiver.poll_feedback_timeout()?;
+        let sender_ssrc = self.session.ensure_internal_send_src();
+        let feedback = self.twcc_receiver.poll_feedback(now, sender_ssrc)?;
+        Some(self.session.feedback_packet(feedback, now, ntp_now))
+    }
+
     pub fn pt_map(&self) -> impl Iterator<Item = (u8, &gst::Caps)> + '_ {
         self.pt_map.iter().map(|(&k, v)| (k, v))
     }
 
     pub fn stats(&self) -> gst::Structure {
         let mut session_stats = gst::Structure::builder("application/x-rtpbin2-session-stats")
-            .field("id", self.id as u64);
+            .field("id", self.id as u64)
+            .field(
+                "packets-retransmitted",
+                self.rtx_history.num_retransmitted(),
+            )
+            .field("srtp-auth-failures", self.srtp.num_auth_failures())
+            .field("srtp-replayed", self.srtp.num_replayed());
         for ssrc in self.session.ssrcs() {
             if let Some(ls) = self.session.local_send_source_by_ssrc(ssrc) {
                 let mut source_stats =
@@ -369,6 +584,29 @@ pub fn pt_clock_rate_from_caps(caps: &gst::CapsRef) -> Option<(u8, u32)> {
     }
 }
 
+/// Retrieves an unsigned integer field from caps, which can either be an integer or a string when
+/// it was converted from an SDP `fmtp` attribute.
+pub(crate) fn caps_field_u32(s: &gst::StructureRef, field: &str) -> Option<u32> {
+    s.get::<i32>(field)
+        .ok()
+        .and_then(|v| u32::try_from(v).ok())
+        .or_else(|| s.get::<u32>(field).ok())
+        .or_else(|| s.get::<&str>(field).ok().and_then(|v| v.parse().ok()))
+}
+
+/// Returns the associated payload type (`apt`) if `caps` are for an RTX payload type.
+fn rtx_apt_from_caps(caps: &gst::CapsRef) -> Option<u8> {
+    let s = caps.structure(0)?;
+    if !s
+        .get::<&str>("encoding-name")
+        .is_ok_and(|encoding_name| encoding_name.eq_ignore_ascii_case("RTX"))
+    {
+        return None;
+    }
+
+    caps_field_u32(s, "apt").and_then(|apt| u8::try_from(apt).ok())
+}
+
 static RUST_CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
         "rust-log",
@@ -1,15 +1,27 @@
 use crate::utils::ExtendedSeqnum;
 use rtp_types::RtpPacket;
 use std::cmp::Ordering;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::{Duration, Instant};
 
+// Time to wait after detecting a gap before requesting a retransmission, to not request
+// retransmissions for packets that were only slightly reordered
+const RTX_REORDER_DELAY: Duration = Duration::from_millis(10);
+// Round trip time to assume as long as none is known
+const DEFAULT_RTT: Duration = Duration::from_millis(40);
+// Minimum time between two retransmission requests for the same packet
+const MIN_RTX_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+// Gaps larger than this are not considered for retransmission requests
+const MAX_RTX_GAP: u64 = 1000;
+
 #[derive(Debug, Clone, Copy)]
 struct Stats {
     num_late: u64,
     num_lost: u64,
     num_duplicates: u64,
     num_pushed: u64,
+    num_rtx_requests: u64,
+    num_rtx_recovered: u64,
 }
 
 impl From<Stats> for gst::Structure {
@@ -19,6 +31,8 @@ impl From<Stats> for gst::Structure {
             .field("num-duplicates", stats.num_duplicates)
             .field("num-lost", stats.num_lost)
             .field("num-pushed", stats.num_pushed)
+            .field("num-rtx-requests", stats.num_rtx_requests)
+            .field("num-rtx-recovered", stats.num_rtx_recovered)
             .build()
     }
 }
@@ -38,10 +52,22 @@ pub struct JitterBuffer {
     last_output_seqnum: Option<u64>,
     extended_seqnum: ExtendedSeqnum,
     last_input_ts: Option<u64>,
+    // Highest extended seqnum received so far, for detecting gaps
+    max_input_seqnum: Option<u64>,
+    // Packets that were detected as missing and did not arrive yet
+    missing: BTreeMap<u64, MissingPacket>,
     stats: Stats,
     flushing: bool,
 }
 
+#[derive(Debug)]
+struct MissingPacket {
+    // When the gap was detected
+    detected: Instant,
+    // When a retransmission was last requested, if any
+    last_request: Option<Instant>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum PollResult {
     Forward { id: usize, discont: bool },
@@ -102,16 +128,24 @@ impl JitterBuffer {
             last_input_ts: None,
             last_output_seqnum: None,
             extended_seqnum: ExtendedSeqnum::default(),
+            max_input_seqnum: None,
+            missing: BTreeMap::new(),
             stats: Stats {
                 num_late: 0,
                 num_lost: 0,
                 num_duplicates: 0,
                 num_pushed: 0,
+                num_rtx_requests: 0,
+                num_rtx_recovered: 0,
             },
             flushing: true,
         }
     }
 
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
     pub fn queue_serialized_item(&mut self) -> QueueResult {
         let id = self.packet_counter;
         self.packet_counter += 1;
@@ -130,6 +164,8 @@ impl JitterBuffer {
         trace!("Flush changed from {} to {flushing}", self.flushing);
         self.flushing = flushing;
         self.last_output_seqnum = None;
+        self.max_input_seqnum = None;
+        self.missing.clear();
     }
 
     pub fn queue_packet(&mut self, rtp: &RtpPacket, mut pts: u64, now: Instant) -> QueueResult {
@@ -170,6 +206,11 @@ impl JitterBuffer {
 
         self.seqnums.insert(seqnum);
 
+        let requested = self
+            .missing
+            .remove(&seqnum)
+            .is_some_and(|missing| missing.last_request.is_some());
+
         if let Some(last_output_seqnum) = self.last_output_seqnum {
             if last_output_seqnum >= seqnum {
                 debug!(
@@ -182,6 +223,34 @@ impl JitterBuffer {
             }
         }
 
+        if requested {
+            trace!("Received requested packet {seqnum}");
+            self.stats.num_rtx_recovered += 1;
+        }
+
+        match self.max_input_seqnum {
+            Some(max_input_seqnum) if seqnum <= max_input_seqnum => (),
+            Some(max_input_seqnum) => {
+                let gap = seqnum - max_input_seqnum - 1;
+                if gap > MAX_RTX_GAP {
+                    debug!("Gap of {gap} packets too big for requesting retransmissions");
+                } else if gap > 0 {
+                    trace!("Detected gap of {gap} packets before {seqnum}");
+                    for missing in max_input_seqnum + 1..seqnum {
+                        self.missing.insert(
+                            missing,
+                            MissingPacket {
+                                detected: now,
+                                last_request: None,
+                            },
+                        );
+                    }
+                }
+                self.max_input_seqnum = Some(seqnum);
+            }
+            None => self.max_input_seqnum = Some(seqnum),
+        }
+
         let id = self.packet_counter;
         self.packet_counter += 1;
         let item = Item {
@@ -260,6 +329,14 @@ impl JitterBuffer {
             // Safe unwrap, we know the queue isn't empty at this point
             let packet = self.items.pop_first().unwrap();
 
+            // Missing packets before this one can't be output anymore
+            while let Some(entry) = self.missing.first_entry() {
+                if *entry.key() > packet.seqnum {
+                    break;
+                }
+                entry.remove();
+            }
+
             self.stats.num_pushed += 1;
 
             PollResult::Forward {
@@ -272,6 +349,63 @@ impl JitterBuffer {
         }
     }
 
+    /// Returns the sequence numbers of missing packets for which a retransmission should be
+    /// requested now, and the next time this should be called again.
+    ///
+    /// Retransmissions are requested once per round trip time for as long as the retransmitted
+    /// packet can still arrive in time to be output.
+    pub fn poll_nacks(
+        &mut self,
+        now: Instant,
+        rtt: Option<Duration>,
+    ) -> (Vec<u16>, Option<Instant>) {
+        if self.flushing {
+            return (vec![], None);
+        }
+
+        let rtt = rtt.unwrap_or(DEFAULT_RTT);
+        let retry_interval = rtt.max(MIN_RTX_RETRY_INTERVAL);
+        let latency = self.latency;
+        let stats = &mut self.stats;
+        let mut seqnums = vec![];
+        let mut next_timeout: Option<Instant> = None;
+
+        self.missing.retain(|&seqnum, missing| {
+            let due = match missing.last_request {
+                None => missing.detected + RTX_REORDER_DELAY,
+                Some(last_request) => last_request + retry_interval,
+            };
+
+            // The packet following the gap arrived when the gap was detected and will be output
+            // one latency later, a retransmission has to arrive before that
+            if due.max(now) + rtt > missing.detected + latency {
+                trace!("Giving up requesting retransmission of {seqnum}");
+                return false;
+            }
+
+            if due <= now {
+                seqnums.push(seqnum as u16);
+                missing.last_request = Some(now);
+                stats.num_rtx_requests += 1;
+            } else if next_timeout.map_or(true, |next_timeout| due < next_timeout) {
+                next_timeout = Some(due);
+            }
+
+            true
+        });
+
+        if !seqnums.is_empty() {
+            // Retries are due one retry interval after the requests of now
+            let retry = now + retry_interval;
+            if next_timeout.map_or(true, |next_timeout| retry < next_timeout) {
+                next_timeout = Some(retry);
+            }
+            debug!("Requesting retransmission of {seqnums:?}");
+        }
+
+        (seqnums, next_timeout)
+    }
+
     pub fn stats(&self) -> gst::Structure {
         self.stats.into()
     }
@@ -666,4 +800,68 @@ mod tests {
         jb.set_flushing(false);
         assert_eq!(jb.poll(now), PollResult::Empty);
     }
+
+    #[test]
+    fn nacks() {
+        let mut jb = JitterBuffer::new(Duration::from_millis(200));
+        jb.set_flushing(false);
+
+        let rtt = Some(Duration::from_millis(20));
+        let start = Instant::now();
+
+        let rtp_data = generate_rtp_packet(0x12345678, 0, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        jb.queue_packet(&packet, 0, start);
+
+        // Packets 1 and 2 are missing
+        let rtp_data = generate_rtp_packet(0x12345678, 3, 5400, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        jb.queue_packet(&packet, 60_000_000, start);
+
+        // Wait a bit for reordered packets before requesting a retransmission
+        assert_eq!(
+            jb.poll_nacks(start, rtt),
+            (vec![], Some(start + Duration::from_millis(10)))
+        );
+
+        let now = start + Duration::from_millis(10);
+        assert_eq!(
+            jb.poll_nacks(now, rtt),
+            (vec![1, 2], Some(now + Duration::from_millis(20)))
+        );
+
+        // Packet 1 is retransmitted
+        let now = start + Duration::from_millis(25);
+        let rtp_data = generate_rtp_packet(0x12345678, 1, 1800, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        let QueueResult::Queued(_id) = jb.queue_packet(&packet, 20_000_000, now) else {
+            unreachable!()
+        };
+
+        assert_eq!(
+            jb.poll_nacks(now, rtt),
+            (vec![], Some(start + Duration::from_millis(30)))
+        );
+
+        // Packet 2 is requested again once per round trip time until a retransmission could not
+        // arrive in time anymore
+        let mut now = start + Duration::from_millis(30);
+        let mut n_requests = 1;
+        loop {
+            let (seqnums, next) = jb.poll_nacks(now, rtt);
+            if seqnums.is_empty() {
+                assert_eq!(next, None);
+                break;
+            }
+            assert_eq!(seqnums, vec![2]);
+            n_requests += 1;
+            now = next.unwrap();
+        }
+        assert_eq!(now, start + Duration::from_millis(190));
+        assert_eq!(n_requests, 9);
+
+        let stats = jb.stats();
+        assert_eq!(stats.get::<u64>("num-rtx-requests").unwrap(), 10);
+        assert_eq!(stats.get::<u64>("num-rtx-recovered").unwrap(), 1);
+    }
 }
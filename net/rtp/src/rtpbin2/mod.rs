@@ -8,10 +8,13 @@ mod internal;
 mod jitterbuffer;
 mod rtprecv;
 mod rtpsend;
+mod rtx;
 mod session;
 mod source;
+mod srtp;
 mod sync;
 mod time;
+mod twcc;
 
 glib::wrapper! {
     pub struct RtpSend(ObjectSubclass<rtpsend::RtpSend>) @extends gst::Element, gst::Object;
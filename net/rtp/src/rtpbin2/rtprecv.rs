@@ -14,12 +14,15 @@ use once_cell::sync::Lazy;
 
 use super::internal::{pt_clock_rate_from_caps, GstRustLogger, SharedRtpState, SharedSession};
 use super::jitterbuffer::{self, JitterBuffer};
+use super::rtx;
 use super::session::{
-    KeyUnitRequestType, RecvReply, RequestRemoteKeyUnitReply, RtcpRecvReply, RtpProfile,
-    RTCP_MIN_REPORT_INTERVAL,
+    KeyUnitRequestType, RecvReply, RequestNackReply, RequestRemoteKeyUnitReply, RtcpRecvReply,
+    RtpProfile, RTCP_MIN_REPORT_INTERVAL,
 };
 use super::source::SourceState;
+use super::srtp;
 use super::sync;
+use super::twcc;
 
 use crate::rtpbin2::RUNTIME;
 
@@ -54,18 +57,68 @@ impl Default for Settings {
 #[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
 struct JitterBufferStream {
     store: Arc<Mutex<JitterBufferStore>>,
+    session: SharedSession,
+    pt: u8,
+    ssrc: u32,
     sleep: Pin<Box<tokio::time::Sleep>>,
     pending_item: Option<JitterBufferItem>,
 }
 
 impl JitterBufferStream {
-    fn new(store: Arc<Mutex<JitterBufferStore>>) -> Self {
+    fn new(
+        store: Arc<Mutex<JitterBufferStore>>,
+        session: SharedSession,
+        pt: u8,
+        ssrc: u32,
+    ) -> Self {
         Self {
             store,
+            session,
+            pt,
+            ssrc,
             sleep: Box::pin(tokio::time::sleep(Duration::from_secs(1))),
             pending_item: None,
         }
     }
+
+    // Requests retransmissions for the packets the jitterbuffer is missing if generic NACKs were
+    // negotiated for the payload type, and returns the next time this has to be done again.
+    fn poll_nacks(&self, now: Instant) -> Option<Instant> {
+        let session_inner = self.session.inner.lock().unwrap();
+        let caps = session_inner.caps_from_pt(self.pt);
+        if !caps
+            .structure(0)
+            .is_some_and(|s| s.has_field("rtcp-fb-nack"))
+        {
+            return None;
+        }
+        let rtt = session_inner.session.round_trip_time(self.ssrc);
+        drop(session_inner);
+
+        let mut jitterbuffer_store = self.store.lock().unwrap();
+        let (seqnums, timeout) = jitterbuffer_store.jitterbuffer.poll_nacks(now, rtt);
+        let latency = jitterbuffer_store.jitterbuffer.latency();
+        drop(jitterbuffer_store);
+
+        if !seqnums.is_empty() {
+            let mut session_inner = self.session.inner.lock().unwrap();
+            let replies = session_inner
+                .session
+                .request_nack(now, self.ssrc, seqnums, latency);
+            for reply in replies {
+                match reply {
+                    RequestNackReply::TimerReconsideration => {
+                        if let Some(waker) = session_inner.rtcp_waker.take() {
+                            // reconsider timers means that we wake the rtcp task to get a new timeout
+                            waker.wake();
+                        }
+                    }
+                }
+            }
+        }
+
+        timeout
+    }
 }
 
 impl futures::stream::Stream for JitterBufferStream {
@@ -76,12 +129,13 @@ impl futures::stream::Stream for JitterBufferStream {
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item>> {
         let now = Instant::now();
-        let mut lowest_wait = None;
 
         if let Some(item) = self.pending_item.take() {
             return Poll::Ready(Some(item));
         }
 
+        let mut lowest_wait = self.poll_nacks(now);
+
         let mut jitterbuffer_store = self.store.lock().unwrap();
         let mut pending_item = None;
         let mut next_pending_item = None;
@@ -292,6 +346,9 @@ struct RecvSession {
     rtp_recv_srcpads: Vec<RtpRecvSrcPad>,
     recv_flow_combiner: Arc<Mutex<gst_base::UniqueFlowCombiner>>,
 
+    // RTX SSRC to original SSRC
+    rtx_ssrc_map: HashMap<u32, u32>,
+
     rtcp_recv_sinkpad: Option<gst::Pad>,
 }
 
@@ -313,10 +370,49 @@ impl RecvSession {
             rtp_recv_srcpads: vec![],
             recv_flow_combiner: Arc::new(Mutex::new(gst_base::UniqueFlowCombiner::new())),
 
+            rtx_ssrc_map: HashMap::new(),
+
             rtcp_recv_sinkpad: None,
         }
     }
 
+    /// Unwraps `data` into the original packet if it is a retransmission packet (RFC 4588).
+    ///
+    /// Returns `Err(())` for retransmission packets that can't be associated with any stream.
+    fn unwrap_rtx(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, ()> {
+        let Ok(rtp) = rtp_types::RtpPacket::parse(data) else {
+            return Ok(None);
+        };
+
+        let session_inner = self.internal_session.inner.lock().unwrap();
+        let Some(pt) = session_inner.pt_from_rtx_pt(rtp.payload_type()) else {
+            return Ok(None);
+        };
+        drop(session_inner);
+
+        let ssrc = match self.rtx_ssrc_map.get(&rtp.ssrc()) {
+            Some(&ssrc) => ssrc,
+            None => {
+                // With SSRC multiplexing the RTX stream can be associated with the original
+                // stream if it is the only one with the associated payload type
+                let mut candidates = self.rtp_recv_srcpads.iter().filter(|r| r.pt == pt);
+                let (Some(recv_pad), None) = (candidates.next(), candidates.next()) else {
+                    return Err(());
+                };
+                gst::debug!(
+                    CAT,
+                    "Associating RTX ssrc {} with ssrc {}",
+                    rtp.ssrc(),
+                    recv_pad.ssrc
+                );
+                self.rtx_ssrc_map.insert(rtp.ssrc(), recv_pad.ssrc);
+                recv_pad.ssrc
+            }
+        };
+
+        rtx::unwrap(data, pt, ssrc).map(Some).ok_or(())
+    }
+
     fn start_rtp_task(&mut self, pad: &gst::Pad) -> Result<(), glib::BoolError> {
         gst::debug!(CAT, obj = pad, "Starting rtp recv src task");
 
@@ -329,6 +425,8 @@ impl RecvSession {
         let pad_weak = pad.downgrade();
         let recv_flow_combiner = self.recv_flow_combiner.clone();
         let store = recv_pad.jitter_buffer_store.clone();
+        let internal_session = self.internal_session.clone();
+        let (pt, ssrc) = (recv_pad.pt, recv_pad.ssrc);
 
         {
             let mut store = store.lock().unwrap();
@@ -347,9 +445,10 @@ impl RecvSession {
 
             let recv_flow_combiner = recv_flow_combiner.clone();
             let store = store.clone();
+            let internal_session = internal_session.clone();
 
             RUNTIME.block_on(async move {
-                let mut stream = JitterBufferStream::new(store);
+                let mut stream = JitterBufferStream::new(store, internal_session, pt, ssrc);
                 while let Some(item) = stream.next().await {
                     match item {
                         JitterBufferItem::PacketList(list) => {
@@ -677,6 +776,46 @@ impl RtpRecv {
                         .map(|a| a.into())
                         .ok()
                 });
+
+        buffer = match session
+            .internal_session
+            .srtp_transform_buffer(srtp::Transform::UnprotectRtp, buffer)
+        {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                gst::debug!(CAT, obj = pad, "Dropping packet: {err}");
+                return Ok(RecvRtpBuffer::Drop);
+            }
+        };
+
+        let unwrapped = {
+            let mapped = buffer.map_readable().map_err(|e| {
+                gst::error!(CAT, imp = self, "Failed to map input buffer {e:?}");
+                gst::FlowError::Error
+            })?;
+            session.unwrap_rtx(&mapped)
+        };
+        match unwrapped {
+            Ok(Some(data)) => {
+                gst::trace!(CAT, obj = pad, "Unwrapped retransmission packet");
+                let mut unwrapped = gst::Buffer::from_mut_slice(data);
+                {
+                    let unwrapped = unwrapped.get_mut().unwrap();
+                    unwrapped.set_dts(buffer.dts());
+                }
+                buffer = unwrapped;
+            }
+            Ok(None) => (),
+            Err(()) => {
+                gst::debug!(
+                    CAT,
+                    obj = pad,
+                    "Dropping retransmission packet for unknown stream"
+                );
+                return Ok(RecvRtpBuffer::Drop);
+            }
+        }
+
         let mapped = buffer.map_readable().map_err(|e| {
             gst::error!(CAT, imp = self, "Failed to map input buffer {e:?}");
             gst::FlowError::Error
@@ -708,6 +847,8 @@ impl RtpRecv {
         let internal_session = session.internal_session.clone();
         let mut session_inner = internal_session.inner.lock().unwrap();
 
+        session_inner.twcc_receive_packet(&rtp, now);
+
         let pts = {
             let mut sync_context = self.sync_context.lock().unwrap();
             let sync_context = sync_context.as_mut().unwrap();
@@ -1146,6 +1287,18 @@ impl RtpRecv {
                         .map(|a| a.into())
                         .ok()
                 });
+
+        let buffer = match session
+            .internal_session
+            .srtp_transform_buffer(srtp::Transform::UnprotectRtcp, buffer)
+        {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                gst::debug!(CAT, imp = self, "Dropping rtcp packet: {err}");
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        };
+
         let mapped = buffer.map_readable().map_err(|e| {
             gst::error!(CAT, imp = self, "Failed to map input buffer {e:?}");
             gst::FlowError::Error
@@ -1161,6 +1314,7 @@ impl RtpRecv {
                 return Ok(gst::FlowSuccess::Ok);
             }
         };
+        let twcc_feedback = twcc::parse_feedback_packets(&mapped);
 
         let internal_session = session.internal_session.clone();
         let mut session_inner = internal_session.inner.lock().unwrap();
@@ -1171,10 +1325,35 @@ impl RtpRecv {
             session_inner
                 .session
                 .handle_rtcp_recv(rtcp, mapped.len(), addr, now, ntp_now);
+        let twcc_packets = twcc_feedback
+            .iter()
+            .flat_map(|feedback| session_inner.twcc_sender.handle_feedback(feedback))
+            .collect::<Vec<_>>();
         let rtp_send_sinkpad = session_inner.rtp_send_sinkpad.clone();
         drop(session_inner);
         drop(state);
 
+        if let Some(pad) = rtp_send_sinkpad
+            .as_ref()
+            .filter(|_| !twcc_packets.is_empty())
+        {
+            // Same event as sent by rtpsession, e.g. for rtpgccbwe
+            gst::trace!(
+                CAT,
+                imp = self,
+                "Sending transport-wide feedback for {} packets upstream",
+                twcc_packets.len()
+            );
+            pad.push_event(
+                gst::event::CustomUpstream::builder(
+                    gst::Structure::builder("RTPTWCCPackets")
+                        .field("packets", glib::ValueArray::new(twcc_packets))
+                        .build(),
+                )
+                .build(),
+            );
+        }
+
         for reply in replies {
             match reply {
                 RtcpRecvReply::NewSsrc(ssrc) => {
@@ -1227,6 +1406,19 @@ impl RtpRecv {
                         );
                     }
                 }
+                RtcpRecvReply::RequestRetransmission { ssrc, seqnums } => {
+                    gst::debug!(
+                        CAT,
+                        imp = self,
+                        "Retransmission requested for ssrc {ssrc}: {seqnums:?}"
+                    );
+                    let mut session_inner = internal_session.inner.lock().unwrap();
+                    session_inner.pending_retransmissions.push((ssrc, seqnums));
+                    // the rtcp task of the sender takes care of the retransmissions
+                    if let Some(waker) = session_inner.rtcp_waker.take() {
+                        waker.wake();
+                    }
+                }
                 RtcpRecvReply::NewCName((cname, ssrc)) => {
                     let mut sync_context = self.sync_context.lock().unwrap();
 
@@ -1372,7 +1564,7 @@ impl RtpRecv {
 
                 if let Some((pt, clock_rate)) = pt_clock_rate_from_caps(caps.caps()) {
                     if let Some(session) = state.mut_session_by_id(id) {
-                        let caps = caps.caps_owned();
+                        let caps = srtp::unprotected_caps(caps.caps());
                         session.rtp_recv_sink_caps = Some(caps.clone());
 
                         let mut session_inner = session.internal_session.inner.lock().unwrap();
@@ -1696,8 +1888,13 @@ impl ElementImpl for RtpRecv {
             let rtp_caps = gst::Caps::builder_full()
                 .structure(gst::Structure::builder("application/x-rtp").build())
                 .build();
-            let rtcp_caps = gst::Caps::builder_full()
+            let rtp_sink_caps = gst::Caps::builder_full()
+                .structure(gst::Structure::builder("application/x-rtp").build())
+                .structure(gst::Structure::builder("application/x-srtp").build())
+                .build();
+            let rtcp_sink_caps = gst::Caps::builder_full()
                 .structure(gst::Structure::builder("application/x-rtcp").build())
+                .structure(gst::Structure::builder("application/x-srtcp").build())
                 .build();
 
             vec![
@@ -1705,14 +1902,14 @@ impl ElementImpl for RtpRecv {
                     "rtp_sink_%u",
                     gst::PadDirection::Sink,
                     gst::PadPresence::Request,
-                    &rtp_caps,
+                    &rtp_sink_caps,
                 )
                 .unwrap(),
                 gst::PadTemplate::new(
                     "rtcp_sink_%u",
                     gst::PadDirection::Sink,
                     gst::PadPresence::Request,
-                    &rtcp_caps,
+                    &rtcp_sink_caps,
                 )
                 .unwrap(),
                 gst::PadTemplate::new(
@@ -2000,6 +2197,7 @@ impl ElementImpl for RtpRecv {
                     session.recv_flow_combiner.lock().unwrap().clear();
                     session.rtp_recv_srcpads.clear();
                     session.recv_store.clear();
+                    session.rtx_ssrc_map.clear();
 
                     session.rtp_recv_sink_caps = None;
                     session.rtp_recv_sink_segment = None;
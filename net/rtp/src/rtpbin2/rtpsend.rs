@@ -11,9 +11,13 @@ use futures::StreamExt;
 use gst::{glib, prelude::*, subclass::prelude::*};
 use once_cell::sync::Lazy;
 
-use super::internal::{pt_clock_rate_from_caps, GstRustLogger, SharedRtpState, SharedSession};
+use super::internal::{
+    caps_field_u32, pt_clock_rate_from_caps, GstRustLogger, SharedRtpState, SharedSession,
+};
+use super::rtx::DEFAULT_RTX_TIME;
 use super::session::{RtcpSendReply, RtpProfile, SendReply, RTCP_MIN_REPORT_INTERVAL};
 use super::source::SourceState;
+use super::srtp;
 
 use crate::rtpbin2::RUNTIME;
 
@@ -76,6 +80,13 @@ impl Default for Settings {
     }
 }
 
+#[derive(Debug)]
+enum RtcpSendItem {
+    Rtcp(RtcpSendReply),
+    // Retransmissions requested by the receivers via NACK
+    Retransmissions(Vec<gst::Buffer>),
+}
+
 #[derive(Debug)]
 #[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
 struct RtcpSendStream {
@@ -95,7 +106,7 @@ impl RtcpSendStream {
 }
 
 impl futures::stream::Stream for RtcpSendStream {
-    type Item = RtcpSendReply;
+    type Item = RtcpSendItem;
 
     fn poll_next(
         self: Pin<&mut Self>,
@@ -107,14 +118,28 @@ impl futures::stream::Stream for RtcpSendStream {
         let mut lowest_wait = None;
         if let Some(session) = state.mut_session_by_id(self.session_id) {
             let mut session_inner = session.internal_session.inner.lock().unwrap();
+            if !session_inner.pending_retransmissions.is_empty() {
+                let buffers = session_inner.take_retransmissions();
+                if !buffers.is_empty() {
+                    return Poll::Ready(Some(RtcpSendItem::Retransmissions(buffers)));
+                }
+            }
+            if let Some(data) = session_inner.poll_twcc_feedback(now, ntp_now) {
+                return Poll::Ready(Some(RtcpSendItem::Rtcp(RtcpSendReply::Data(data))));
+            }
             if let Some(reply) = session_inner.session.poll_rtcp_send(now, ntp_now) {
-                return Poll::Ready(Some(reply));
+                return Poll::Ready(Some(RtcpSendItem::Rtcp(reply)));
             }
             if let Some(wait) = session_inner.session.poll_rtcp_send_timeout(now) {
                 if lowest_wait.map_or(true, |lowest_wait| wait < lowest_wait) {
                     lowest_wait = Some(wait);
                 }
             }
+            if let Some(wait) = session_inner.twcc_receiver.poll_feedback_timeout() {
+                if lowest_wait.map_or(true, |lowest_wait| wait < lowest_wait) {
+                    lowest_wait = Some(wait);
+                }
+            }
             session_inner.rtcp_waker = Some(cx.waker().clone());
         }
         drop(state);
@@ -202,16 +227,79 @@ impl SendSession {
         // i.e. we should only allow a single pad push, but still allow other rtcp tasks to
         // continue operating
         let sem = Arc::new(tokio::sync::Semaphore::new(1));
-        while let Some(reply) = stream.next().await {
+        let rtx_sem = Arc::new(tokio::sync::Semaphore::new(1));
+        while let Some(item) = stream.next().await {
+            let reply = match item {
+                RtcpSendItem::Rtcp(reply) => reply,
+                RtcpSendItem::Retransmissions(buffers) => {
+                    let pads = {
+                        let state = state.lock().unwrap();
+                        let Some(session) = state.session_by_id(session_id) else {
+                            continue;
+                        };
+                        Option::zip(
+                            session.rtp_send_sinkpad.clone(),
+                            session.rtp_send_srcpad.clone(),
+                        )
+                        .map(|pads| (pads, session.internal_session.clone()))
+                    };
+                    let Some(((rtp_sinkpad, rtp_srcpad), internal_session)) = pads else {
+                        continue;
+                    };
+
+                    let acquired = rtx_sem.clone().acquire_owned().await;
+                    RUNTIME.spawn_blocking(move || {
+                        // Serialize with the regular data flow
+                        let _stream_lock = rtp_sinkpad.stream_lock();
+                        let time = rtp_srcpad
+                            .parent_element()
+                            .and_then(|element| element.current_clock_time());
+                        for buffer in buffers {
+                            // Retransmissions are separate packets on the transport and need
+                            // their own transport-wide sequence number
+                            let buffer = internal_session
+                                .inner
+                                .lock()
+                                .unwrap()
+                                .twcc_send_buffer(buffer, time);
+                            let buffer = match internal_session
+                                .srtp_transform_buffer(srtp::Transform::ProtectRtp, buffer)
+                            {
+                                Ok(buffer) => buffer,
+                                Err(err) => {
+                                    gst::warning!(
+                                        CAT,
+                                        obj = rtp_srcpad,
+                                        "Failed to protect retransmission: {err}"
+                                    );
+                                    continue;
+                                }
+                            };
+                            if let Err(e) = rtp_srcpad.push(buffer) {
+                                gst::warning!(
+                                    CAT,
+                                    obj = rtp_srcpad,
+                                    "Failed to send retransmission: flow return {e:?}"
+                                );
+                                break;
+                            }
+                        }
+                        drop(acquired);
+                    });
+                    continue;
+                }
+            };
+
             let send = {
                 let state = state.lock().unwrap();
                 let Some(session) = state.session_by_id(session_id) else {
                     continue;
                 };
                 match reply {
-                    RtcpSendReply::Data(data) => {
-                        session.rtcp_send_srcpad.clone().map(|pad| (pad, data))
-                    }
+                    RtcpSendReply::Data(data) => session
+                        .rtcp_send_srcpad
+                        .clone()
+                        .map(|pad| (pad, session.internal_session.clone(), data)),
                     RtcpSendReply::SsrcBye(ssrc) => {
                         session
                             .internal_session
@@ -222,9 +310,23 @@ impl SendSession {
                 }
             };
 
-            if let Some((rtcp_srcpad, data)) = send {
+            if let Some((rtcp_srcpad, internal_session, data)) = send {
                 let acquired = sem.clone().acquire_owned().await;
                 RUNTIME.spawn_blocking(move || {
+                    let data = match internal_session
+                        .srtp_transform(srtp::Transform::ProtectRtcp, &data)
+                    {
+                        Ok(protected) => protected.unwrap_or(data),
+                        Err(err) => {
+                            gst::warning!(
+                                CAT,
+                                obj = rtcp_srcpad,
+                                "Failed to protect rtcp data: {err}"
+                            );
+                            drop(acquired);
+                            return;
+                        }
+                    };
                     let buffer = gst::Buffer::from_mut_slice(data);
                     if let Err(e) = rtcp_srcpad.push(buffer) {
                         gst::warning!(
@@ -356,8 +458,31 @@ impl RtpSend {
                 SendReply::Drop => return Ok(gst::FlowSuccess::Ok),
             }
         }
+
+        // Keep the packet around if retransmissions are possible for its payload type
+        if let Some((_rtx_pt, rtx_caps)) = session_inner.rtx_pt_from_pt(rtp.payload_type()) {
+            let s = rtx_caps.structure(0).unwrap();
+            let max_age = caps_field_u32(s, "rtx-time")
+                .map(|rtx_time| Duration::from_millis(rtx_time as u64))
+                .unwrap_or(DEFAULT_RTX_TIME);
+            let rtx_ssrc = caps_field_u32(s, "ssrc");
+
+            let ssrc = rtp.ssrc();
+            session_inner.rtx_history.store(
+                ssrc,
+                rtp.sequence_number(),
+                buffer.clone(),
+                now,
+                max_age,
+            );
+            if let Some(rtx_ssrc) = rtx_ssrc {
+                session_inner.rtx_history.set_rtx_ssrc(ssrc, rtx_ssrc);
+            }
+        }
+
         // TODO: handle other processing
         drop(mapped);
+        let buffer = session_inner.twcc_send_buffer(buffer, self.obj().current_clock_time());
         drop(session_inner);
 
         for ssrc in ssrc_collision {
@@ -373,6 +498,15 @@ impl RtpSend {
             );
         }
 
+        let buffer =
+            match internal_session.srtp_transform_buffer(srtp::Transform::ProtectRtp, buffer) {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    gst::warning!(CAT, obj = sinkpad, "Failed to protect packet: {err}");
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+            };
+
         srcpad.push(buffer)
     }
 
@@ -422,12 +556,14 @@ impl RtpSend {
     fn rtp_sink_event(&self, pad: &gst::Pad, event: gst::Event, id: usize) -> bool {
         match event.view() {
             gst::EventView::Caps(caps) => {
+                let mut srtp_enabled = false;
                 if let Some((pt, clock_rate)) = pt_clock_rate_from_caps(caps.caps()) {
                     let state = self.state.lock().unwrap();
                     if let Some(session) = state.session_by_id(id) {
                         let mut session = session.internal_session.inner.lock().unwrap();
                         session.session.set_pt_clock_rate(pt, clock_rate);
                         session.add_caps(caps.caps_owned());
+                        srtp_enabled = session.srtp.is_enabled();
                     }
                 } else {
                     gst::warning!(
@@ -436,6 +572,14 @@ impl RtpSend {
                         "input caps are missing payload or clock-rate fields"
                     );
                 }
+
+                let event = if srtp_enabled {
+                    gst::event::Caps::builder(&srtp::protected_caps(caps.caps()))
+                        .seqnum(event.seqnum())
+                        .build()
+                } else {
+                    event
+                };
                 gst::Pad::event_default(pad, Some(&*self.obj()), event)
             }
             gst::EventView::Eos(_eos) => {
@@ -632,8 +776,13 @@ impl ElementImpl for RtpSend {
             let rtp_caps = gst::Caps::builder_full()
                 .structure(gst::Structure::builder("application/x-rtp").build())
                 .build();
-            let rtcp_caps = gst::Caps::builder_full()
+            let rtp_src_caps = gst::Caps::builder_full()
+                .structure(gst::Structure::builder("application/x-rtp").build())
+                .structure(gst::Structure::builder("application/x-srtp").build())
+                .build();
+            let rtcp_src_caps = gst::Caps::builder_full()
                 .structure(gst::Structure::builder("application/x-rtcp").build())
+                .structure(gst::Structure::builder("application/x-srtcp").build())
                 .build();
 
             vec![
@@ -648,14 +797,14 @@ impl ElementImpl for RtpSend {
                     "rtp_src_%u",
                     gst::PadDirection::Src,
                     gst::PadPresence::Sometimes,
-                    &rtp_caps,
+                    &rtp_src_caps,
                 )
                 .unwrap(),
                 gst::PadTemplate::new(
                     "rtcp_src_%u",
                     gst::PadDirection::Src,
                     gst::PadPresence::Request,
-                    &rtcp_caps,
+                    &rtcp_src_caps,
                 )
                 .unwrap(),
             ]
@@ -792,7 +941,18 @@ impl ElementImpl for RtpSend {
                     let stream_start = gst::event::StreamStart::builder(&stream_id).build();
                     let seqnum = stream_start.seqnum();
 
-                    let caps = gst::Caps::new_empty_simple("application/x-rtcp");
+                    let srtp_enabled = session
+                        .internal_session
+                        .inner
+                        .lock()
+                        .unwrap()
+                        .srtp
+                        .is_enabled();
+                    let caps = gst::Caps::new_empty_simple(if srtp_enabled {
+                        "application/x-srtcp"
+                    } else {
+                        "application/x-rtcp"
+                    });
                     let caps = gst::event::Caps::builder(&caps).seqnum(seqnum).build();
 
                     let segment = gst::FormattedSegment::<gst::ClockTime>::new();
@@ -947,6 +1107,10 @@ impl ElementImpl for RtpSend {
                 let mut state = self.state.lock().unwrap();
                 for session in state.sessions.iter_mut() {
                     session.stop_rtcp_task();
+
+                    let mut session_inner = session.internal_session.inner.lock().unwrap();
+                    session_inner.rtx_history.clear();
+                    session_inner.pending_retransmissions.clear();
                 }
             }
             _ => (),
@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use rand::prelude::*;
+use rtp_types::RtpPacket;
+
+/// How long sent packets are kept around for retransmission if the RTX payload type does not
+/// specify an `rtx-time`
+pub const DEFAULT_RTX_TIME: Duration = Duration::from_secs(1);
+
+// Upper bound on the number of packets stored per SSRC, independent of the `rtx-time`
+const MAX_HISTORY_PACKETS: usize = 10_000;
+
+/// Returns the size of the RTP header of `data`, including CSRCs and header extensions.
+fn header_len(rtp: &RtpPacket, data: &[u8]) -> usize {
+    let padding = if data[0] & 0x20 != 0 {
+        data[data.len() - 1] as usize
+    } else {
+        0
+    };
+
+    data.len() - padding - rtp.payload().len()
+}
+
+/// Wraps the RTP packet in `data` into a retransmission packet as specified in RFC 4588 section 4.
+///
+/// The RTP header is kept apart from payload type, sequence number and SSRC and the original
+/// sequence number is prepended to the payload. Padding is removed.
+pub fn wrap(data: &[u8], rtx_pt: u8, rtx_ssrc: u32, rtx_seqnum: u16) -> Option<Vec<u8>> {
+    let rtp = RtpPacket::parse(data).ok()?;
+    let header_len = header_len(&rtp, data);
+
+    let mut rtx = Vec::with_capacity(header_len + 2 + rtp.payload().len());
+    rtx.extend_from_slice(&data[..header_len]);
+    rtx.extend_from_slice(&rtp.sequence_number().to_be_bytes());
+    rtx.extend_from_slice(rtp.payload());
+
+    rtx[0] &= !0x20;
+    rtx[1] = (rtx[1] & 0x80) | (rtx_pt & 0x7f);
+    rtx[2..4].copy_from_slice(&rtx_seqnum.to_be_bytes());
+    rtx[8..12].copy_from_slice(&rtx_ssrc.to_be_bytes());
+
+    Some(rtx)
+}
+
+/// Unwraps the retransmission packet in `data` back into the original RTP packet with payload
+/// type `pt` and SSRC `ssrc`.
+///
+/// Returns `None` if `data` is not a valid retransmission packet.
+pub fn unwrap(data: &[u8], pt: u8, ssrc: u32) -> Option<Vec<u8>> {
+    let rtp = RtpPacket::parse(data).ok()?;
+    let header_len = header_len(&rtp, data);
+
+    let payload = rtp.payload();
+    if payload.len() < 2 {
+        return None;
+    }
+
+    let mut packet = Vec::with_capacity(header_len + payload.len() - 2);
+    packet.extend_from_slice(&data[..header_len]);
+    packet.extend_from_slice(&payload[2..]);
+
+    packet[0] &= !0x20;
+    packet[1] = (packet[1] & 0x80) | (pt & 0x7f);
+    packet[2..4].copy_from_slice(&payload[..2]);
+    packet[8..12].copy_from_slice(&ssrc.to_be_bytes());
+
+    Some(packet)
+}
+
+#[derive(Debug)]
+struct StoredPacket {
+    seqnum: u16,
+    time: Instant,
+    buffer: gst::Buffer,
+}
+
+#[derive(Debug)]
+struct StreamHistory {
+    rtx_ssrc: u32,
+    next_rtx_seqnum: u16,
+    packets: VecDeque<StoredPacket>,
+}
+
+/// Keeps recently sent packets around so that they can be retransmitted when the receiver
+/// requests them with a NACK.
+#[derive(Debug, Default)]
+pub struct RetransmissionHistory {
+    streams: HashMap<u32, StreamHistory>,
+    num_retransmitted: u64,
+}
+
+impl RetransmissionHistory {
+    /// Store a sent packet of `ssrc` with `seqnum`. Packets older than `max_age` are forgotten.
+    pub fn store(
+        &mut self,
+        ssrc: u32,
+        seqnum: u16,
+        buffer: gst::Buffer,
+        now: Instant,
+        max_age: Duration,
+    ) {
+        let stream = self.streams.entry(ssrc).or_insert_with(|| {
+            let mut rng = rand::thread_rng();
+            StreamHistory {
+                rtx_ssrc: rng.gen(),
+                next_rtx_seqnum: rng.gen(),
+                packets: VecDeque::new(),
+            }
+        });
+
+        while stream.packets.front().is_some_and(|packet| {
+            now.saturating_duration_since(packet.time) > max_age
+                || stream.packets.len() >= MAX_HISTORY_PACKETS
+        }) {
+            stream.packets.pop_front();
+        }
+
+        stream.packets.push_back(StoredPacket {
+            seqnum,
+            time: now,
+            buffer,
+        });
+    }
+
+    /// Overrides the randomly selected RTX SSRC for the stream with `ssrc`.
+    pub fn set_rtx_ssrc(&mut self, ssrc: u32, rtx_ssrc: u32) {
+        if let Some(stream) = self.streams.get_mut(&ssrc) {
+            stream.rtx_ssrc = rtx_ssrc;
+        }
+    }
+
+    /// Creates a retransmission packet with payload type `rtx_pt` for the packet of `ssrc` with
+    /// `seqnum`, if it is still available.
+    pub fn retransmission(&mut self, ssrc: u32, seqnum: u16, rtx_pt: u8) -> Option<gst::Buffer> {
+        let stream = self.streams.get_mut(&ssrc)?;
+        let packet = stream
+            .packets
+            .iter()
+            .rev()
+            .find(|packet| packet.seqnum == seqnum)?;
+
+        let map = packet.buffer.map_readable().ok()?;
+        let rtx = wrap(&map, rtx_pt, stream.rtx_ssrc, stream.next_rtx_seqnum)?;
+        drop(map);
+
+        stream.next_rtx_seqnum = stream.next_rtx_seqnum.wrapping_add(1);
+        self.num_retransmitted += 1;
+
+        let mut buffer = gst::Buffer::from_mut_slice(rtx);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(packet.buffer.pts());
+            buffer.set_dts(packet.buffer.dts());
+        }
+
+        Some(buffer)
+    }
+
+    pub fn num_retransmitted(&self) -> u64 {
+        self.num_retransmitted
+    }
+
+    pub fn clear(&mut self) {
+        self.streams.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtpbin2::session::tests::generate_rtp_packet;
+
+    #[test]
+    fn wrap_unwrap() {
+        let data = generate_rtp_packet(0x12345678, 100, 3000, 8);
+
+        let rtx = wrap(&data, 97, 0x87654321, 5).unwrap();
+        let rtx_packet = RtpPacket::parse(&rtx).unwrap();
+        assert_eq!(rtx_packet.payload_type(), 97);
+        assert_eq!(rtx_packet.ssrc(), 0x87654321);
+        assert_eq!(rtx_packet.sequence_number(), 5);
+        assert_eq!(rtx_packet.timestamp(), 3000);
+        assert_eq!(&rtx_packet.payload()[..2], &100u16.to_be_bytes());
+        assert_eq!(rtx_packet.payload().len(), 10);
+
+        let original = unwrap(&rtx, 96, 0x12345678).unwrap();
+        assert_eq!(original, data);
+    }
+
+    #[test]
+    fn unwrap_empty_payload() {
+        let data = generate_rtp_packet(0x12345678, 100, 3000, 0);
+        assert!(unwrap(&data, 96, 0x12345678).is_none());
+    }
+
+    #[test]
+    fn history_retransmission() {
+        let mut history = RetransmissionHistory::default();
+        let now = Instant::now();
+
+        for seqnum in 0..4u16 {
+            let data = generate_rtp_packet(0x12345678, seqnum, 0, 4);
+            history.store(
+                0x12345678,
+                seqnum,
+                gst::Buffer::from_mut_slice(data),
+                now + Duration::from_millis(seqnum as u64 * 100),
+                Duration::from_millis(250),
+            );
+        }
+        history.set_rtx_ssrc(0x12345678, 0x11111111);
+
+        // Expired
+        assert!(history.retransmission(0x12345678, 0, 97).is_none());
+        // Unknown ssrc
+        assert!(history.retransmission(0x1, 1, 97).is_none());
+
+        let rtx = history.retransmission(0x12345678, 2, 97).unwrap();
+        let map = rtx.map_readable().unwrap();
+        let rtx_packet = RtpPacket::parse(&map).unwrap();
+        assert_eq!(rtx_packet.ssrc(), 0x11111111);
+        assert_eq!(rtx_packet.payload_type(), 97);
+        assert_eq!(&rtx_packet.payload()[..2], &2u16.to_be_bytes());
+        let rtx_seqnum = rtx_packet.sequence_number();
+        drop(map);
+
+        let rtx = history.retransmission(0x12345678, 3, 97).unwrap();
+        let map = rtx.map_readable().unwrap();
+        let rtx_packet = RtpPacket::parse(&map).unwrap();
+        assert_eq!(rtx_packet.sequence_number(), rtx_seqnum.wrapping_add(1));
+
+        assert_eq!(history.num_retransmitted(), 2);
+    }
+}
@@ -148,6 +148,8 @@ pub enum RtcpRecvReply {
     TimerReconsideration,
     /// Request a key unit for the given SSRC of ours
     RequestKeyUnit { ssrcs: Vec<u32>, fir: bool },
+    /// Request retransmission of the packets with the given sequence numbers of a SSRC of ours
+    RequestRetransmission { ssrc: u32, seqnums: Vec<u16> },
     /// A new cname to ssrc mapping was found in a sdes: (cname, ssrc)
     NewCName((String, u32)),
     /// A new RTP to NTP mapping was received for an ssrc: (ssrc, RTP, NTP)
@@ -170,6 +172,12 @@ pub enum RequestRemoteKeyUnitReply {
     TimerReconsideration,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RequestNackReply {
+    /// RTCP timer needs to be reconsidered.  Call poll_rtcp_send_timeout() to get the new time
+    TimerReconsideration,
+}
+
 impl Session {
     pub fn new() -> Self {
         let cname = generate_cname();
@@ -731,7 +739,28 @@ impl Session {
                         );
                     }
                 }
-                Ok(Packet::TransportFeedback(_)) | Ok(Packet::Unknown(_)) => (),
+                Ok(Packet::TransportFeedback(tf)) => {
+                    if let Ok(nack) = tf.parse_fci::<rtcp_types::Nack>() {
+                        let media_ssrc = tf.media_ssrc();
+                        if !self.local_senders.contains_key(&media_ssrc) {
+                            trace!("Not a local sender for ssrc {media_ssrc}");
+                            continue;
+                        }
+
+                        let seqnums = nack.entries().collect::<Vec<_>>();
+                        trace!(
+                            "Retransmission requested by {} for ssrc {media_ssrc}: {seqnums:?}",
+                            tf.sender_ssrc()
+                        );
+                        if !seqnums.is_empty() {
+                            replies.push(RtcpRecvReply::RequestRetransmission {
+                                ssrc: media_ssrc,
+                                seqnums,
+                            });
+                        }
+                    }
+                }
+                Ok(Packet::Unknown(_)) => (),
                 // TODO: in RFC4585 profile, need to listen for feedback messages and remove any
                 // that we would have sent
                 Err(_) => (),
@@ -904,7 +933,7 @@ impl Session {
         self.internal_rtcp_sender_src
     }
 
-    fn ensure_internal_send_src(&mut self) -> u32 {
+    pub(crate) fn ensure_internal_send_src(&mut self) -> u32 {
         match self.internal_rtcp_sender_src {
             Some(ssrc) => ssrc,
             None => loop {
@@ -1018,6 +1047,32 @@ impl Session {
         }
     }
 
+    /// Returns the transport-wide feedback packet `feedback` as it can be sent. Without
+    /// reduced-size RTCP (RFC 5506) it is prefixed by a minimal SR/RR and SDES to form a valid
+    /// compound RTCP packet.
+    pub(crate) fn feedback_packet(
+        &mut self,
+        feedback: Vec<u8>,
+        now: Instant,
+        ntp_now: SystemTime,
+    ) -> Vec<u8> {
+        if self.reduced_size_rtcp_allowed() {
+            return feedback;
+        }
+
+        let mut ssrcs_reported = vec![];
+        let mut rtcp = Compound::builder();
+        rtcp = self.generate_sr(rtcp, now, ntp_now, true, &mut ssrcs_reported);
+        rtcp = self.generate_rr(rtcp, now, ntp_now, true, &mut ssrcs_reported);
+        rtcp = self.generate_sdes(rtcp, true);
+
+        let size = rtcp.calculate_size().unwrap();
+        let mut data = vec![0; size + feedback.len()];
+        rtcp.write_into(&mut data[..size]).unwrap();
+        data[size..].copy_from_slice(&feedback);
+        data
+    }
+
     fn find_bye_sources(&mut self) -> HashMap<String, Vec<u32>> {
         let mut reason_ssrcs = HashMap::new();
         for source in self
@@ -1097,6 +1152,27 @@ impl Session {
         rtcp
     }
 
+    fn generate_nack<'a>(
+        &mut self,
+        mut rtcp: CompoundBuilder<'a>,
+        _now: Instant,
+    ) -> CompoundBuilder<'a> {
+        let ssrc = self.ensure_internal_send_src();
+
+        for source in self.remote_senders.values_mut() {
+            let nack = source.generate_nack();
+            if let Some(nack) = nack {
+                debug!("Generating NACK for sender {}: {:?}", source.ssrc(), nack);
+                rtcp = rtcp.add_packet(
+                    rtcp_types::TransportFeedback::builder_owned(nack)
+                        .sender_ssrc(ssrc)
+                        .media_ssrc(source.ssrc()),
+                );
+            }
+        }
+        rtcp
+    }
+
     fn generate_fir<'a>(
         &mut self,
         mut rtcp: CompoundBuilder<'a>,
@@ -1235,6 +1311,7 @@ impl Session {
             rtcp = self.generate_sdes(rtcp, is_early);
             rtcp = self.generate_pli(rtcp, now);
             rtcp = self.generate_fir(rtcp, now);
+            rtcp = self.generate_nack(rtcp, now);
             rtcp = self.generate_bye(rtcp, now);
 
             let size = rtcp.calculate_size().unwrap();
@@ -1585,6 +1662,49 @@ impl Session {
 
         replies
     }
+
+    /// Request retransmission of the packets with `seqnums` from the remote sender with `ssrc`
+    /// via a generic NACK (RFC 4585 section 6.2.1).  `max_delay` is the maximum time the NACK
+    /// may be delayed for it to still be useful.
+    pub(crate) fn request_nack(
+        &mut self,
+        now: Instant,
+        ssrc: u32,
+        seqnums: impl IntoIterator<Item = u16>,
+        max_delay: Duration,
+    ) -> Vec<RequestNackReply> {
+        let mut replies = Vec::new();
+
+        if !self.remote_senders.contains_key(&ssrc) {
+            trace!("No remote sender with ssrc {ssrc} known");
+            return replies;
+        };
+
+        let res = self.request_early_rtcp(now, max_delay);
+        if res == RequestEarlyRtcpResult::TimerReconsideration {
+            replies.push(RequestNackReply::TimerReconsideration);
+        }
+
+        if res != RequestEarlyRtcpResult::NotScheduled {
+            let source = self.remote_senders.get_mut(&ssrc).unwrap();
+            source.request_nack(seqnums);
+        }
+
+        replies
+    }
+
+    /// Returns the round trip time to the remote source with `ssrc`, if known.
+    ///
+    /// This is only known if the remote source reported about one of our local senders.
+    pub fn round_trip_time(&self, ssrc: u32) -> Option<Duration> {
+        self.local_senders
+            .values()
+            .flat_map(|sender| sender.received_report_blocks())
+            .filter(|(sender_ssrc, _rb)| *sender_ssrc == ssrc)
+            .map(|(_sender_ssrc, rb)| rb.round_trip_time())
+            .filter(|rtt| !rtt.is_zero())
+            .max()
+    }
 }
 
 fn generate_cname() -> String {
@@ -2569,6 +2689,72 @@ pub(crate) mod tests {
         assert_eq!(n_sr_ssrc, 1);
     }
 
+    #[test]
+    fn nack() {
+        init_logs();
+        let mut session = Session::new();
+        session.set_pt_clock_rate(TEST_PT, TEST_CLOCK_RATE);
+        session.set_profile(RtpProfile::Avpf);
+        let now = Instant::now();
+        let ntp_now = SystemTime::now();
+        let ssrc = 0x11223344;
+
+        let rtp_data = generate_rtp_packet(ssrc, 500, 0, 4);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        session_recv_first_packet_disable_probation(&mut session, &packet, now);
+        assert_eq!(
+            session.handle_recv(&packet, None, now),
+            RecvReply::Passthrough
+        );
+
+        // complete first regular rtcp
+        let (rtcp_data, now, ntp_now) = next_rtcp_packet(&mut session, now, ntp_now);
+        let RtcpSendReply::Data(_rtcp_data) = rtcp_data else {
+            unreachable!();
+        };
+
+        // unknown ssrc
+        assert!(session
+            .request_nack(now, 0x1, [502], Duration::from_secs(1))
+            .is_empty());
+        session.request_nack(now, ssrc, [502, 503, 505], Duration::from_secs(1));
+        assert!(session.next_early_rtcp_time.is_some());
+
+        let (rtcp_data, now, ntp_now) = next_rtcp_packet(&mut session, now, ntp_now);
+        let RtcpSendReply::Data(rtcp_data) = rtcp_data else {
+            unreachable!();
+        };
+
+        let rtcp = Compound::parse(&rtcp_data).unwrap();
+        let mut n_nack = 0;
+        for p in rtcp {
+            if let Ok(Packet::TransportFeedback(tf)) = p {
+                assert_eq!(tf.media_ssrc(), ssrc);
+                let nack = tf.parse_fci::<Nack>().unwrap();
+                assert_eq!(nack.entries().collect::<Vec<_>>(), vec![502, 503, 505]);
+                n_nack += 1;
+            }
+        }
+        assert_eq!(n_nack, 1);
+
+        // the sender of the NACKed stream is asked to retransmit
+        let mut sender = Session::new();
+        sender.set_pt_clock_rate(TEST_PT, TEST_CLOCK_RATE);
+        let packet = RtpPacket::parse(&rtp_data).unwrap();
+        assert_eq!(
+            sender.handle_send(&packet, now),
+            SendReply::NewSsrc(ssrc, TEST_PT)
+        );
+        assert_eq!(sender.handle_send(&packet, now), SendReply::Passthrough);
+
+        let rtcp = Compound::parse(&rtcp_data).unwrap();
+        let replies = sender.handle_rtcp_recv(rtcp, rtcp_data.len(), None, now, ntp_now);
+        assert!(replies.contains(&RtcpRecvReply::RequestRetransmission {
+            ssrc,
+            seqnums: vec![502, 503, 505]
+        }));
+    }
+
     #[test]
     fn point_to_point() {
         let mut session = Session::new();
@@ -2656,4 +2842,39 @@ pub(crate) mod tests {
         );
         assert!(!session.is_point_to_point);
     }
+
+    #[test]
+    fn feedback_packet() {
+        init_logs();
+        let mut session = Session::new();
+        session.set_profile(RtpProfile::Avpf);
+        let now = Instant::now();
+        let ntp_now = SystemTime::now();
+        let ssrc = session.ensure_internal_send_src();
+        // an empty transport-wide feedback packet
+        let mut feedback = vec![0x8f, 205, 0x00, 0x04];
+        feedback.extend_from_slice(&ssrc.to_be_bytes());
+        feedback.extend_from_slice(&[0; 12]);
+
+        // compound RTCP starting with a RR
+        let data = session.feedback_packet(feedback.clone(), now, ntp_now);
+        assert!(data.ends_with(&feedback));
+        let mut rtcp = Compound::parse(&data).unwrap();
+        let Some(Ok(Packet::Rr(rr))) = rtcp.next() else {
+            unreachable!();
+        };
+        assert_eq!(rr.ssrc(), ssrc);
+
+        // only without the RR/SDES if reduced-size RTCP is allowed
+        session.set_reduced_size_rtcp(true);
+        assert_eq!(
+            session.feedback_packet(feedback.clone(), now, ntp_now),
+            feedback
+        );
+        session.set_profile(RtpProfile::Avp);
+        assert_ne!(
+            session.feedback_packet(feedback.clone(), now, ntp_now),
+            feedback
+        );
+    }
 }
@@ -137,8 +137,7 @@ pub struct ReceivedRb {
 }
 
 impl ReceivedRb {
-    #[allow(unused)]
-    fn round_trip_time(&self) -> Duration {
+    pub(crate) fn round_trip_time(&self) -> Duration {
         let rb_send_ntp_time = self.rb.last_sr as u64 + self.rb.delay_since_last_sr as u64;
 
         // Can't calculate any round trip time
@@ -430,6 +429,8 @@ pub struct RemoteSendSource {
     send_fir_seqnum: u8,
     // Count from the ForceKeyUnitEvent to de-duplicate FIR
     send_fir_count: Option<u32>,
+    // Sequence numbers for which a generic NACK is pending with the next RTCP packet
+    send_nack_seqnums: Vec<u16>,
 }
 
 // The first time we recev a packet for jitter calculations
@@ -460,6 +461,7 @@ impl RemoteSendSource {
             send_fir: false,
             send_fir_seqnum: 0,
             send_fir_count: None,
+            send_nack_seqnums: Vec::new(),
         }
     }
 
@@ -961,6 +963,27 @@ impl RemoteSendSource {
             fir
         }
     }
+
+    pub(crate) fn request_nack(&mut self, seqnums: impl IntoIterator<Item = u16>) {
+        for seqnum in seqnums {
+            if !self.send_nack_seqnums.contains(&seqnum) {
+                self.send_nack_seqnums.push(seqnum);
+            }
+        }
+    }
+
+    pub(crate) fn generate_nack(&mut self) -> Option<rtcp_types::NackBuilder> {
+        if self.send_nack_seqnums.is_empty() {
+            return None;
+        }
+
+        let mut nack = rtcp_types::Nack::builder();
+        for seqnum in self.send_nack_seqnums.drain(..) {
+            nack = nack.add_rtp_sequence(seqnum);
+        }
+
+        Some(nack)
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,1032 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! SRTP / SRTCP (RFC 3711) with the AES-CM and AES-GCM (RFC 7714) protection profiles.
+
+use std::collections::HashMap;
+
+use aes::{Aes128, Aes256};
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, Nonce, Tag};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+type HmacSha1 = Hmac<Sha1>;
+
+// Number of packets before the highest received one that are still accepted
+const REPLAY_WINDOW_SIZE: u64 = 64;
+
+const SRTCP_E_FLAG: u32 = 0x8000_0000;
+const SRTCP_INDEX_MASK: u32 = 0x7fff_ffff;
+
+const HMAC_SHA1_KEY_LEN: usize = 20;
+const GCM_TAG_LEN: usize = 16;
+
+// Key derivation labels from RFC 3711 section 4.3.1
+const LABEL_RTP_ENCRYPTION: u8 = 0x00;
+const LABEL_RTCP_ENCRYPTION: u8 = 0x03;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error("No key for SSRC {0:#010x}")]
+    NoKey(u32),
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Invalid packet")]
+    InvalidPacket,
+    #[error("Authentication failed")]
+    AuthenticationFailed,
+    #[error("Replayed packet")]
+    Replayed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Null,
+    Aes128Icm,
+    Aes256Icm,
+    Aes128Gcm,
+    Aes256Gcm,
+}
+
+impl Cipher {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "null" => Some(Cipher::Null),
+            "aes-128-icm" => Some(Cipher::Aes128Icm),
+            "aes-256-icm" => Some(Cipher::Aes256Icm),
+            "aes-128-gcm" => Some(Cipher::Aes128Gcm),
+            "aes-256-gcm" => Some(Cipher::Aes256Gcm),
+            _ => None,
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            Cipher::Null | Cipher::Aes128Icm | Cipher::Aes128Gcm => 16,
+            Cipher::Aes256Icm | Cipher::Aes256Gcm => 32,
+        }
+    }
+
+    fn salt_len(self) -> usize {
+        if self.is_gcm() {
+            12
+        } else {
+            14
+        }
+    }
+
+    fn is_gcm(self) -> bool {
+        matches!(self, Cipher::Aes128Gcm | Cipher::Aes256Gcm)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Auth {
+    Null,
+    HmacSha1_32,
+    HmacSha1_80,
+}
+
+impl Auth {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "null" => Some(Auth::Null),
+            "hmac-sha1-32" => Some(Auth::HmacSha1_32),
+            "hmac-sha1-80" => Some(Auth::HmacSha1_80),
+            _ => None,
+        }
+    }
+
+    fn tag_len(self) -> usize {
+        match self {
+            Auth::Null => 0,
+            Auth::HmacSha1_32 => 4,
+            Auth::HmacSha1_80 => 10,
+        }
+    }
+}
+
+/// The master key of an SSRC together with the protection profiles for RTP and RTCP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MasterKey {
+    key: Vec<u8>,
+    salt: Vec<u8>,
+    rtp_cipher: Cipher,
+    rtp_auth: Auth,
+    rtcp_cipher: Cipher,
+    rtcp_auth: Auth,
+    roc: u32,
+}
+
+impl MasterKey {
+    pub fn new(
+        key: &[u8],
+        rtp_cipher: Cipher,
+        rtp_auth: Auth,
+        rtcp_cipher: Cipher,
+        rtcp_auth: Auth,
+        roc: u32,
+    ) -> Result<Self, Error> {
+        let cipher = if rtp_cipher != Cipher::Null {
+            rtp_cipher
+        } else {
+            rtcp_cipher
+        };
+        if rtp_cipher != Cipher::Null
+            && rtcp_cipher != Cipher::Null
+            && (rtp_cipher.key_len() != rtcp_cipher.key_len()
+                || rtp_cipher.salt_len() != rtcp_cipher.salt_len())
+        {
+            return Err(Error::InvalidKey(
+                "RTP and RTCP ciphers require different key sizes".into(),
+            ));
+        }
+
+        let key_len = cipher.key_len();
+        let salt_len = cipher.salt_len();
+        if key.len() != key_len + salt_len {
+            return Err(Error::InvalidKey(format!(
+                "expected {} bytes of key and salt, got {}",
+                key_len + salt_len,
+                key.len()
+            )));
+        }
+
+        Ok(Self {
+            key: key[..key_len].to_vec(),
+            salt: key[key_len..].to_vec(),
+            rtp_cipher,
+            rtp_auth,
+            rtcp_cipher,
+            rtcp_auth,
+            roc,
+        })
+    }
+
+    /// Parses the key from caps in the format used by the `srtpenc` / `srtpdec` elements.
+    ///
+    /// The caps contain the concatenated master key and salt as `srtp-key` buffer, the
+    /// `srtp-cipher`, `srtp-auth`, `srtcp-cipher` and `srtcp-auth` protection profiles and
+    /// optionally the initial rollover counter as `roc`. The RTCP profiles default to the RTP
+    /// ones. Authentication is implied by the AES-GCM ciphers.
+    pub fn from_caps(caps: &gst::CapsRef) -> Result<Self, Error> {
+        let s = caps
+            .structure(0)
+            .ok_or_else(|| Error::InvalidKey("empty caps".into()))?;
+
+        let key = s
+            .get::<gst::Buffer>("srtp-key")
+            .map_err(|_| Error::InvalidKey("no srtp-key".into()))?;
+        let key = key
+            .map_readable()
+            .map_err(|_| Error::InvalidKey("unreadable srtp-key".into()))?;
+
+        let field = |name: &str, default: Option<&str>| -> Result<String, Error> {
+            s.get_optional::<String>(name)
+                .ok()
+                .flatten()
+                .or_else(|| default.map(String::from))
+                .ok_or_else(|| Error::InvalidKey(format!("no {name}")))
+        };
+        let cipher = |name: &str| {
+            Cipher::from_name(name).ok_or_else(|| Error::InvalidKey(format!("cipher {name}")))
+        };
+        let auth = |name: &str| {
+            Auth::from_name(name).ok_or_else(|| Error::InvalidKey(format!("auth {name}")))
+        };
+
+        let rtp_cipher = field("srtp-cipher", None)?;
+        let rtp_auth = field("srtp-auth", Some("null"))?;
+        let rtcp_cipher = field("srtcp-cipher", Some(&rtp_cipher))?;
+        let rtcp_auth = field("srtcp-auth", Some(&rtp_auth))?;
+        let roc = s.get_optional::<u32>("roc").ok().flatten().unwrap_or(0);
+
+        Self::new(
+            &key,
+            cipher(&rtp_cipher)?,
+            auth(&rtp_auth)?,
+            cipher(&rtcp_cipher)?,
+            auth(&rtcp_auth)?,
+            roc,
+        )
+    }
+}
+
+/// Implements the AES-CM PRF of RFC 3711 section 4.3.3 with a key derivation rate of 0.
+fn derive_key(master_key: &[u8], master_salt: &[u8], label: u8, len: usize) -> Vec<u8> {
+    // The 96 bit salt of the AES-GCM profiles is padded with zeroes
+    let mut iv = [0u8; 16];
+    iv[..master_salt.len()].copy_from_slice(master_salt);
+    iv[7] ^= label;
+
+    let mut key = vec![0u8; len];
+    aes_cm_apply(master_key, &iv, &mut key);
+    key
+}
+
+fn aes_cm_apply(key: &[u8], iv: &[u8; 16], data: &mut [u8]) {
+    match key.len() {
+        16 => Aes128Ctr::new(key.into(), iv.into()).apply_keystream(data),
+        32 => Aes256Ctr::new(key.into(), iv.into()).apply_keystream(data),
+        _ => unreachable!(),
+    }
+}
+
+#[derive(Debug)]
+struct SessionKeys {
+    cipher: Cipher,
+    auth: Auth,
+    encryption_key: Vec<u8>,
+    auth_key: Vec<u8>,
+    salt: Vec<u8>,
+}
+
+impl SessionKeys {
+    fn derive(master: &MasterKey, cipher: Cipher, auth: Auth, label: u8) -> Self {
+        // AES-GCM provides authentication itself
+        let auth = if cipher.is_gcm() { Auth::Null } else { auth };
+
+        Self {
+            cipher,
+            auth,
+            encryption_key: derive_key(&master.key, &master.salt, label, cipher.key_len()),
+            auth_key: derive_key(&master.key, &master.salt, label + 1, HMAC_SHA1_KEY_LEN),
+            salt: derive_key(&master.key, &master.salt, label + 2, cipher.salt_len()),
+        }
+    }
+
+    fn auth_tag_len(&self) -> usize {
+        self.auth.tag_len()
+    }
+
+    fn hmac(&self, data: &[u8], roc: Option<u32>) -> HmacSha1 {
+        let mut mac = HmacSha1::new_from_slice(&self.auth_key).unwrap();
+        mac.update(data);
+        if let Some(roc) = roc {
+            mac.update(&roc.to_be_bytes());
+        }
+        mac
+    }
+
+    fn auth_tag(&self, data: &[u8], roc: Option<u32>) -> Vec<u8> {
+        let tag = self.hmac(data, roc).finalize().into_bytes();
+        tag[..self.auth_tag_len()].to_vec()
+    }
+
+    fn verify(&self, data: &[u8], roc: Option<u32>, tag: &[u8]) -> Result<(), Error> {
+        self.hmac(data, roc)
+            .verify_truncated_left(tag)
+            .map_err(|_| Error::AuthenticationFailed)
+    }
+
+    /// The counter for AES-CM from RFC 3711 section 4.1.1.
+    fn ctr_iv(&self, ssrc: u32, index: u64) -> [u8; 16] {
+        let mut iv = [0u8; 16];
+        iv[..14].copy_from_slice(&self.salt);
+        for (iv, b) in iv[4..8].iter_mut().zip(ssrc.to_be_bytes()) {
+            *iv ^= b;
+        }
+        for (iv, b) in iv[8..14].iter_mut().zip(&index.to_be_bytes()[2..]) {
+            *iv ^= b;
+        }
+        iv
+    }
+
+    /// The IV for AES-GCM from RFC 7714 sections 8.1 and 9.1.
+    fn gcm_iv(&self, ssrc: u32, index: u64) -> [u8; 12] {
+        let mut iv = [0u8; 12];
+        iv[2..6].copy_from_slice(&ssrc.to_be_bytes());
+        iv[6..12].copy_from_slice(&index.to_be_bytes()[2..]);
+        for (iv, b) in iv.iter_mut().zip(&self.salt) {
+            *iv ^= b;
+        }
+        iv
+    }
+
+    fn gcm_encrypt(&self, iv: &[u8; 12], aad: &[u8], data: &mut [u8]) -> Tag {
+        let nonce = Nonce::from_slice(iv);
+        match self.cipher {
+            Cipher::Aes128Gcm => Aes128Gcm::new_from_slice(&self.encryption_key)
+                .unwrap()
+                .encrypt_in_place_detached(nonce, aad, data),
+            Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(&self.encryption_key)
+                .unwrap()
+                .encrypt_in_place_detached(nonce, aad, data),
+            _ => unreachable!(),
+        }
+        .expect("packet size within AES-GCM limits")
+    }
+
+    fn gcm_decrypt(
+        &self,
+        iv: &[u8; 12],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), Error> {
+        let nonce = Nonce::from_slice(iv);
+        let tag = Tag::from_slice(tag);
+        match self.cipher {
+            Cipher::Aes128Gcm => Aes128Gcm::new_from_slice(&self.encryption_key)
+                .unwrap()
+                .decrypt_in_place_detached(nonce, aad, data, tag),
+            Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(&self.encryption_key)
+                .unwrap()
+                .decrypt_in_place_detached(nonce, aad, data, tag),
+            _ => unreachable!(),
+        }
+        .map_err(|_| Error::AuthenticationFailed)
+    }
+}
+
+/// Sliding window over the most recently received packet indices (RFC 3711 section 3.3.2).
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    // bit n is set if the packet with index `highest - n` was received
+    received: u64,
+}
+
+impl ReplayWindow {
+    fn check(&self, index: u64) -> Result<(), Error> {
+        let Some(highest) = self.highest else {
+            return Ok(());
+        };
+        if index > highest {
+            return Ok(());
+        }
+
+        let delta = highest - index;
+        if delta >= REPLAY_WINDOW_SIZE || self.received & (1 << delta) != 0 {
+            Err(Error::Replayed)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn update(&mut self, index: u64) {
+        match self.highest {
+            Some(highest) if index <= highest => {
+                self.received |= 1 << (highest - index);
+            }
+            Some(highest) => {
+                let shift = index - highest;
+                self.received = if shift >= REPLAY_WINDOW_SIZE {
+                    0
+                } else {
+                    self.received << shift
+                } | 1;
+                self.highest = Some(index);
+            }
+            None => {
+                self.received = 1;
+                self.highest = Some(index);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stream {
+    rtp: SessionKeys,
+    rtcp: SessionKeys,
+    // Rollover counter and the highest sequence number seen so far
+    roc: u32,
+    highest_seqnum: Option<u16>,
+    rtp_replay: ReplayWindow,
+    // Next SRTCP index to send
+    srtcp_index: u32,
+    rtcp_replay: ReplayWindow,
+}
+
+impl Stream {
+    fn new(master: &MasterKey) -> Self {
+        Self {
+            rtp: SessionKeys::derive(
+                master,
+                master.rtp_cipher,
+                master.rtp_auth,
+                LABEL_RTP_ENCRYPTION,
+            ),
+            rtcp: SessionKeys::derive(
+                master,
+                master.rtcp_cipher,
+                master.rtcp_auth,
+                LABEL_RTCP_ENCRYPTION,
+            ),
+            roc: master.roc,
+            highest_seqnum: None,
+            rtp_replay: ReplayWindow::default(),
+            srtcp_index: 0,
+            rtcp_replay: ReplayWindow::default(),
+        }
+    }
+
+    /// Estimates the packet index of `seqnum` following RFC 3711 appendix A.
+    ///
+    /// Returns `None` for packets from before the start of the stream.
+    fn packet_index(&self, seqnum: u16) -> Option<u64> {
+        let roc = self.roc as i64;
+        let v = match self.highest_seqnum {
+            None => roc,
+            Some(s_l) if s_l < 0x8000 => {
+                if seqnum > s_l && seqnum - s_l > 0x8000 {
+                    roc - 1
+                } else {
+                    roc
+                }
+            }
+            Some(s_l) => {
+                if s_l - 0x8000 > seqnum {
+                    roc + 1
+                } else {
+                    roc
+                }
+            }
+        };
+
+        let v = u32::try_from(v).ok()?;
+        Some(((v as u64) << 16) | seqnum as u64)
+    }
+
+    fn update_packet_index(&mut self, index: u64) {
+        let highest = self
+            .highest_seqnum
+            .map(|s_l| ((self.roc as u64) << 16) | s_l as u64);
+        if highest.map_or(true, |highest| index > highest) {
+            self.roc = (index >> 16) as u32;
+            self.highest_seqnum = Some(index as u16);
+        }
+    }
+}
+
+/// Returns the length of the RTP header including CSRCs and header extension, the SSRC and the
+/// sequence number of the RTP packet in `data`.
+fn parse_rtp_header(data: &[u8]) -> Result<(usize, u32, u16), Error> {
+    if data.len() < 12 || data[0] >> 6 != 2 {
+        return Err(Error::InvalidPacket);
+    }
+
+    let mut header_len = 12 + 4 * (data[0] & 0x0f) as usize;
+    if data[0] & 0x10 != 0 {
+        let ext = data
+            .get(header_len + 2..header_len + 4)
+            .ok_or(Error::InvalidPacket)?;
+        header_len += 4 + 4 * u16::from_be_bytes([ext[0], ext[1]]) as usize;
+    }
+    if header_len > data.len() {
+        return Err(Error::InvalidPacket);
+    }
+
+    let seqnum = u16::from_be_bytes([data[2], data[3]]);
+    let ssrc = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+
+    Ok((header_len, ssrc, seqnum))
+}
+
+/// Returns the SSRC of the sender of the first packet of the compound RTCP packet in `data`.
+fn parse_rtcp_ssrc(data: &[u8]) -> Result<u32, Error> {
+    if data.len() < 8 || data[0] >> 6 != 2 {
+        return Err(Error::InvalidPacket);
+    }
+
+    Ok(u32::from_be_bytes([data[4], data[5], data[6], data[7]]))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    ProtectRtp,
+    UnprotectRtp,
+    ProtectRtcp,
+    UnprotectRtcp,
+}
+
+/// Returns the caps of the unprotected stream for the caps of a protected stream, as `srtpdec`
+/// does: the media type is changed to `application/x-rtp` and the key material is removed.
+pub fn unprotected_caps(caps: &gst::CapsRef) -> gst::Caps {
+    let mut caps = caps.to_owned();
+    for s in caps.make_mut().iter_mut() {
+        if s.name() == "application/x-srtp" {
+            s.set_name("application/x-rtp");
+        } else if s.name() == "application/x-srtcp" {
+            s.set_name("application/x-rtcp");
+        }
+
+        let fields = s
+            .fields()
+            .filter(|f| {
+                f.starts_with("srtp-") || f.starts_with("srtcp-") || *f == "roc" || *f == "mki"
+            })
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>();
+        for field in fields {
+            s.remove_field(&field);
+        }
+    }
+    caps
+}
+
+/// Returns the caps of the protected stream for the caps of an unprotected stream.
+pub fn protected_caps(caps: &gst::CapsRef) -> gst::Caps {
+    let mut caps = caps.to_owned();
+    for s in caps.make_mut().iter_mut() {
+        if s.name() == "application/x-rtp" {
+            s.set_name("application/x-srtp");
+        } else if s.name() == "application/x-rtcp" {
+            s.set_name("application/x-srtcp");
+        }
+    }
+    caps
+}
+
+/// SRTP state of a session: the configured keys and the per-SSRC cryptographic contexts.
+#[derive(Debug, Default)]
+pub struct SrtpContext {
+    enabled: bool,
+    keys: HashMap<u32, (gst::Caps, MasterKey)>,
+    streams: HashMap<u32, Stream>,
+    num_auth_failures: u64,
+    num_replayed: u64,
+}
+
+impl SrtpContext {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Sets or removes the key for `ssrc`. The cryptographic context of `ssrc` is only reset if
+    /// the key changed.
+    pub fn set_key(&mut self, ssrc: u32, caps: Option<gst::Caps>) -> Result<(), Error> {
+        let Some(caps) = caps else {
+            self.keys.remove(&ssrc);
+            self.streams.remove(&ssrc);
+            return Ok(());
+        };
+
+        let key = MasterKey::from_caps(&caps)?;
+        if self
+            .keys
+            .get(&ssrc)
+            .map_or(true, |(_caps, old_key)| *old_key != key)
+        {
+            self.streams.remove(&ssrc);
+            self.keys.insert(ssrc, (caps, key));
+        }
+
+        Ok(())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = (u32, &gst::Caps)> + '_ {
+        self.keys.iter().map(|(&ssrc, (caps, _key))| (ssrc, caps))
+    }
+
+    pub fn clear_keys(&mut self) {
+        self.keys.clear();
+        self.streams.clear();
+    }
+
+    pub fn num_auth_failures(&self) -> u64 {
+        self.num_auth_failures
+    }
+
+    pub fn num_replayed(&self) -> u64 {
+        self.num_replayed
+    }
+
+    fn stream(&mut self, ssrc: u32) -> Result<&mut Stream, Error> {
+        if !self.streams.contains_key(&ssrc) {
+            let (_caps, key) = self.keys.get(&ssrc).ok_or(Error::NoKey(ssrc))?;
+            self.streams.insert(ssrc, Stream::new(key));
+        }
+
+        Ok(self.streams.get_mut(&ssrc).unwrap())
+    }
+
+    pub fn transform(&mut self, transform: Transform, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let res = match transform {
+            Transform::ProtectRtp => self.protect_rtp(data),
+            Transform::UnprotectRtp => self.unprotect_rtp(data),
+            Transform::ProtectRtcp => self.protect_rtcp(data),
+            Transform::UnprotectRtcp => self.unprotect_rtcp(data),
+        };
+
+        match res {
+            Err(Error::AuthenticationFailed) => self.num_auth_failures += 1,
+            Err(Error::Replayed) => self.num_replayed += 1,
+            _ => (),
+        }
+
+        res
+    }
+
+    fn protect_rtp(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let (header_len, ssrc, seqnum) = parse_rtp_header(data)?;
+        let stream = self.stream(ssrc)?;
+        let index = stream.packet_index(seqnum).ok_or(Error::InvalidPacket)?;
+        let keys = &stream.rtp;
+
+        let mut packet = Vec::with_capacity(data.len() + GCM_TAG_LEN.max(keys.auth_tag_len()));
+        packet.extend_from_slice(data);
+
+        match keys.cipher {
+            Cipher::Null => (),
+            Cipher::Aes128Icm | Cipher::Aes256Icm => aes_cm_apply(
+                &keys.encryption_key,
+                &keys.ctr_iv(ssrc, index),
+                &mut packet[header_len..],
+            ),
+            Cipher::Aes128Gcm | Cipher::Aes256Gcm => {
+                let (header, payload) = packet.split_at_mut(header_len);
+                let tag = keys.gcm_encrypt(&keys.gcm_iv(ssrc, index), header, payload);
+                packet.extend_from_slice(&tag);
+            }
+        }
+
+        if keys.auth != Auth::Null {
+            let tag = keys.auth_tag(&packet, Some((index >> 16) as u32));
+            packet.extend_from_slice(&tag);
+        }
+
+        stream.update_packet_index(index);
+
+        Ok(packet)
+    }
+
+    fn unprotect_rtp(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let (header_len, ssrc, seqnum) = parse_rtp_header(data)?;
+        let stream = self.stream(ssrc)?;
+        let keys = &stream.rtp;
+
+        let tag_len = keys.auth_tag_len();
+        let gcm_tag_len = if keys.cipher.is_gcm() { GCM_TAG_LEN } else { 0 };
+        if data.len() < header_len + tag_len + gcm_tag_len {
+            return Err(Error::InvalidPacket);
+        }
+
+        let index = stream.packet_index(seqnum).ok_or(Error::Replayed)?;
+        stream.rtp_replay.check(index)?;
+
+        let (authenticated, tag) = data.split_at(data.len() - tag_len);
+        if keys.auth != Auth::Null {
+            keys.verify(authenticated, Some((index >> 16) as u32), tag)?;
+        }
+
+        let mut packet = authenticated.to_vec();
+        match keys.cipher {
+            Cipher::Null => (),
+            Cipher::Aes128Icm | Cipher::Aes256Icm => aes_cm_apply(
+                &keys.encryption_key,
+                &keys.ctr_iv(ssrc, index),
+                &mut packet[header_len..],
+            ),
+            Cipher::Aes128Gcm | Cipher::Aes256Gcm => {
+                let (payload_end, gcm_tag) = data.split_at(data.len() - GCM_TAG_LEN);
+                let (header, payload) = packet[..payload_end.len()].split_at_mut(header_len);
+                keys.gcm_decrypt(&keys.gcm_iv(ssrc, index), header, payload, gcm_tag)?;
+                packet.truncate(payload_end.len());
+            }
+        }
+
+        stream.rtp_replay.update(index);
+        stream.update_packet_index(index);
+
+        Ok(packet)
+    }
+
+    fn protect_rtcp(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let ssrc = parse_rtcp_ssrc(data)?;
+        let stream = self.stream(ssrc)?;
+
+        let index = stream.srtcp_index;
+        stream.srtcp_index = (index + 1) & SRTCP_INDEX_MASK;
+
+        let keys = &stream.rtcp;
+        let trailer = if keys.cipher != Cipher::Null {
+            SRTCP_E_FLAG | index
+        } else {
+            index
+        }
+        .to_be_bytes();
+
+        let mut packet = Vec::with_capacity(data.len() + 4 + GCM_TAG_LEN.max(keys.auth_tag_len()));
+        packet.extend_from_slice(data);
+
+        match keys.cipher {
+            Cipher::Null => (),
+            Cipher::Aes128Icm | Cipher::Aes256Icm => aes_cm_apply(
+                &keys.encryption_key,
+                &keys.ctr_iv(ssrc, index as u64),
+                &mut packet[8..],
+            ),
+            Cipher::Aes128Gcm | Cipher::Aes256Gcm => {
+                let mut aad = [0u8; 12];
+                aad[..8].copy_from_slice(&packet[..8]);
+                aad[8..].copy_from_slice(&trailer);
+                let tag =
+                    keys.gcm_encrypt(&keys.gcm_iv(ssrc, index as u64), &aad, &mut packet[8..]);
+                packet.extend_from_slice(&tag);
+            }
+        }
+
+        packet.extend_from_slice(&trailer);
+
+        if keys.auth != Auth::Null {
+            let tag = keys.auth_tag(&packet, None);
+            packet.extend_from_slice(&tag);
+        }
+
+        Ok(packet)
+    }
+
+    fn unprotect_rtcp(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let ssrc = parse_rtcp_ssrc(data)?;
+        let stream = self.stream(ssrc)?;
+        let keys = &stream.rtcp;
+
+        let tag_len = keys.auth_tag_len();
+        let gcm_tag_len = if keys.cipher.is_gcm() { GCM_TAG_LEN } else { 0 };
+        if data.len() < 8 + gcm_tag_len + 4 + tag_len {
+            return Err(Error::InvalidPacket);
+        }
+
+        let (authenticated, tag) = data.split_at(data.len() - tag_len);
+        let (encrypted, trailer) = authenticated.split_at(authenticated.len() - 4);
+        let trailer = [trailer[0], trailer[1], trailer[2], trailer[3]];
+        let e_index = u32::from_be_bytes(trailer);
+        let index = e_index & SRTCP_INDEX_MASK;
+
+        stream.rtcp_replay.check(index as u64)?;
+
+        if keys.auth != Auth::Null {
+            keys.verify(authenticated, None, tag)?;
+        }
+
+        let mut packet = encrypted.to_vec();
+        if e_index & SRTCP_E_FLAG != 0 {
+            match keys.cipher {
+                Cipher::Null => return Err(Error::InvalidPacket),
+                Cipher::Aes128Icm | Cipher::Aes256Icm => aes_cm_apply(
+                    &keys.encryption_key,
+                    &keys.ctr_iv(ssrc, index as u64),
+                    &mut packet[8..],
+                ),
+                Cipher::Aes128Gcm | Cipher::Aes256Gcm => {
+                    let mut aad = [0u8; 12];
+                    aad[..8].copy_from_slice(&packet[..8]);
+                    aad[8..].copy_from_slice(&trailer);
+                    let payload_end = packet.len() - GCM_TAG_LEN;
+                    let (payload, gcm_tag) = packet.split_at_mut(payload_end);
+                    keys.gcm_decrypt(
+                        &keys.gcm_iv(ssrc, index as u64),
+                        &aad,
+                        &mut payload[8..],
+                        gcm_tag,
+                    )?;
+                    packet.truncate(payload_end);
+                }
+            }
+        } else if keys.cipher.is_gcm() {
+            // Authentication-only AES-GCM is not supported
+            return Err(Error::InvalidPacket);
+        }
+
+        stream.rtcp_replay.update(index as u64);
+
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtpbin2::session::tests::generate_rtp_packet;
+
+    fn key_caps(cipher: &str, auth: &str, key_len: usize) -> gst::Caps {
+        let key = (0..key_len as u8).collect::<Vec<_>>();
+        gst::Caps::builder("application/x-srtp")
+            .field("srtp-key", gst::Buffer::from_mut_slice(key))
+            .field("srtp-cipher", cipher)
+            .field("srtp-auth", auth)
+            .build()
+    }
+
+    fn rtcp_packet(ssrc: u32) -> Vec<u8> {
+        // empty receiver report followed by 8 bytes of padding data
+        let mut data = vec![0x80, 201, 0x00, 0x03];
+        data.extend_from_slice(&ssrc.to_be_bytes());
+        data.extend_from_slice(&[0xab; 8]);
+        data
+    }
+
+    #[test]
+    fn key_derivation() {
+        // RFC 3711 appendix B.3
+        let master_key = hex::decode("E1F97A0D3E018BE0D64FA32C06DE4139").unwrap();
+        let master_salt = hex::decode("0EC675AD498AFEEBB6960B3AABE6").unwrap();
+
+        assert_eq!(
+            derive_key(&master_key, &master_salt, 0x00, 16),
+            hex::decode("C61E7A93744F39EE10734AFE3FF7A087").unwrap()
+        );
+        assert_eq!(
+            derive_key(&master_key, &master_salt, 0x02, 14),
+            hex::decode("30CBBC08863D8C85D49DB34A9AE1").unwrap()
+        );
+        assert_eq!(
+            derive_key(&master_key, &master_salt, 0x01, 20),
+            hex::decode("CEBE321F6FF7716B6FD4AB49AF256A156D38BAA4").unwrap()
+        );
+    }
+
+    #[test]
+    fn aes_cm_keystream() {
+        // RFC 3711 appendix B.2
+        let keys = SessionKeys {
+            cipher: Cipher::Aes128Icm,
+            auth: Auth::Null,
+            encryption_key: hex::decode("2B7E151628AED2A6ABF7158809CF4F3C").unwrap(),
+            auth_key: vec![],
+            salt: hex::decode("F0F1F2F3F4F5F6F7F8F9FAFBFCFD").unwrap(),
+        };
+
+        let iv = keys.ctr_iv(0, 0);
+        assert_eq!(
+            iv.as_slice(),
+            hex::decode("F0F1F2F3F4F5F6F7F8F9FAFBFCFD0000").unwrap()
+        );
+
+        let mut keystream = [0u8; 32];
+        aes_cm_apply(&keys.encryption_key, &iv, &mut keystream);
+        assert_eq!(
+            keystream.as_slice(),
+            hex::decode("E03EAD0935C95E80E166B16DD92B4EB4D23513162B02D0F72A43A2FE4A5F97AB")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_key() {
+        gst::init().unwrap();
+
+        let caps = key_caps("aes-128-icm", "hmac-sha1-80", 16);
+        assert!(matches!(
+            MasterKey::from_caps(&caps),
+            Err(Error::InvalidKey(_))
+        ));
+
+        let caps = key_caps("aes-512-icm", "hmac-sha1-80", 30);
+        assert!(matches!(
+            MasterKey::from_caps(&caps),
+            Err(Error::InvalidKey(_))
+        ));
+    }
+
+    fn roundtrip(cipher: &str, auth: &str, key_len: usize) {
+        gst::init().unwrap();
+
+        let caps = key_caps(cipher, auth, key_len);
+        let mut sender = SrtpContext::default();
+        sender.set_key(0x12345678, Some(caps.clone())).unwrap();
+        let mut receiver = SrtpContext::default();
+        receiver.set_key(0x12345678, Some(caps)).unwrap();
+
+        for seqnum in [0xfffe, 0xffff, 0x0000, 0x0001] {
+            let data = generate_rtp_packet(0x12345678, seqnum, 0, 16);
+            let protected = sender.transform(Transform::ProtectRtp, &data).unwrap();
+            assert_ne!(protected, data);
+            let unprotected = receiver
+                .transform(Transform::UnprotectRtp, &protected)
+                .unwrap();
+            assert_eq!(unprotected, data);
+
+            // replays are rejected
+            assert_eq!(
+                receiver.transform(Transform::UnprotectRtp, &protected),
+                Err(Error::Replayed)
+            );
+        }
+        // the rollover counter was increased
+        assert_eq!(receiver.streams.get(&0x12345678).unwrap().roc, 1);
+
+        let data = rtcp_packet(0x12345678);
+        for _ in 0..2 {
+            let protected = sender.transform(Transform::ProtectRtcp, &data).unwrap();
+            assert_ne!(protected, data);
+            let unprotected = receiver
+                .transform(Transform::UnprotectRtcp, &protected)
+                .unwrap();
+            assert_eq!(unprotected, data);
+
+            assert_eq!(
+                receiver.transform(Transform::UnprotectRtcp, &protected),
+                Err(Error::Replayed)
+            );
+        }
+
+        // modified packets are rejected
+        let data = generate_rtp_packet(0x12345678, 2, 0, 16);
+        let mut protected = sender.transform(Transform::ProtectRtp, &data).unwrap();
+        let len = protected.len();
+        protected[len - 1] ^= 0x01;
+        assert_eq!(
+            receiver.transform(Transform::UnprotectRtp, &protected),
+            Err(Error::AuthenticationFailed)
+        );
+        assert_eq!(receiver.num_auth_failures(), 1);
+        assert_eq!(receiver.num_replayed(), 6);
+
+        // unknown SSRC
+        let data = generate_rtp_packet(0x1, 0, 0, 16);
+        assert_eq!(
+            sender.transform(Transform::ProtectRtp, &data),
+            Err(Error::NoKey(0x1))
+        );
+    }
+
+    #[test]
+    fn roundtrip_aes_128_icm_hmac_sha1_80() {
+        roundtrip("aes-128-icm", "hmac-sha1-80", 30);
+    }
+
+    #[test]
+    fn roundtrip_aes_256_icm_hmac_sha1_32() {
+        roundtrip("aes-256-icm", "hmac-sha1-32", 46);
+    }
+
+    #[test]
+    fn roundtrip_aes_128_gcm() {
+        roundtrip("aes-128-gcm", "null", 28);
+    }
+
+    #[test]
+    fn roundtrip_aes_256_gcm() {
+        roundtrip("aes-256-gcm", "null", 44);
+    }
+
+    #[test]
+    fn caps_conversion() {
+        gst::init().unwrap();
+
+        let caps = gst::Caps::builder("application/x-srtp")
+            .field("media", "video")
+            .field("payload", 96i32)
+            .field("clock-rate", 90000i32)
+            .field("srtp-key", gst::Buffer::from_mut_slice(vec![0u8; 30]))
+            .field("srtp-cipher", "aes-128-icm")
+            .field("srtp-auth", "hmac-sha1-80")
+            .field("srtcp-cipher", "aes-128-icm")
+            .field("srtcp-auth", "hmac-sha1-80")
+            .field("roc", 1u32)
+            .build();
+
+        let unprotected = unprotected_caps(&caps);
+        assert_eq!(
+            unprotected,
+            gst::Caps::builder("application/x-rtp")
+                .field("media", "video")
+                .field("payload", 96i32)
+                .field("clock-rate", 90000i32)
+                .build()
+        );
+
+        assert_eq!(
+            protected_caps(&unprotected),
+            gst::Caps::builder("application/x-srtp")
+                .field("media", "video")
+                .field("payload", 96i32)
+                .field("clock-rate", 90000i32)
+                .build()
+        );
+        assert_eq!(
+            protected_caps(&gst::Caps::new_empty_simple("application/x-rtcp")),
+            gst::Caps::new_empty_simple("application/x-srtcp")
+        );
+    }
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::default();
+
+        window.check(100).unwrap();
+        window.update(100);
+        assert_eq!(window.check(100), Err(Error::Replayed));
+
+        // reordered packets inside the window are accepted once
+        window.check(99).unwrap();
+        window.update(99);
+        assert_eq!(window.check(99), Err(Error::Replayed));
+
+        window.check(200).unwrap();
+        window.update(200);
+        // too old
+        assert_eq!(window.check(136), Err(Error::Replayed));
+        window.check(137).unwrap();
+    }
+}
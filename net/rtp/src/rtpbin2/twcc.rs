@@ -0,0 +1,664 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Transport-wide congestion control (draft-holmer-rmcat-transport-wide-cc-extensions-01).
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use rtp_types::RtpPacket;
+
+pub const TWCC_EXTENSION_URI: &str =
+    "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01";
+
+// How often feedback is sent while packets are received
+const FEEDBACK_INTERVAL: Duration = Duration::from_millis(100);
+// Number of sent packets that are remembered for matching with feedback
+const MAX_SENT_PACKETS: usize = 10_000;
+
+const REFERENCE_TIME_UNIT_US: i64 = 64_000;
+const DELTA_UNIT_US: i64 = 250;
+
+const RTCP_PT_RTPFB: u8 = 205;
+const RTCP_FMT_TWCC: u8 = 15;
+
+const STATUS_NOT_RECEIVED: u8 = 0;
+const STATUS_SMALL_DELTA: u8 = 1;
+const STATUS_LARGE_DELTA: u8 = 2;
+
+/// Returns the id of the transport-wide sequence number header extension if it is configured in
+/// the `extmap-<id>` fields of the RTP caps structure `s`.
+pub fn extension_id_from_caps(s: &gst::StructureRef) -> Option<u8> {
+    s.iter().find_map(|(name, value)| {
+        let id = name.strip_prefix("extmap-")?.parse::<u8>().ok()?;
+        // either the URI or a (direction, URI, attributes) array
+        let uri = value.get::<String>().ok().or_else(|| {
+            value
+                .get::<gst::Array>()
+                .ok()?
+                .as_slice()
+                .get(1)?
+                .get::<String>()
+                .ok()
+        })?;
+
+        (uri == TWCC_EXTENSION_URI).then_some(id)
+    })
+}
+
+/// Returns the elements of an RTP header extension in the one-byte or two-byte header format
+/// (RFC 8285), or `None` for any other format.
+fn extension_elements(profile: u16, data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut elements = vec![];
+    let mut pos = 0;
+
+    if profile == 0xbede {
+        while let Some(&b) = data.get(pos) {
+            pos += 1;
+            if b == 0 {
+                // padding
+                continue;
+            }
+            let id = b >> 4;
+            if id == 15 {
+                break;
+            }
+            let len = (b & 0x0f) as usize + 1;
+            elements.push((id, data.get(pos..pos + len)?));
+            pos += len;
+        }
+    } else if profile & 0xfff0 == 0x1000 {
+        while let Some(&id) = data.get(pos) {
+            pos += 1;
+            if id == 0 {
+                // padding
+                continue;
+            }
+            let len = *data.get(pos)? as usize;
+            pos += 1;
+            elements.push((id, data.get(pos..pos + len)?));
+            pos += len;
+        }
+    } else {
+        return None;
+    }
+
+    Some(elements)
+}
+
+/// Reads the transport-wide sequence number from the header extension with `id`.
+pub fn read_seqnum(rtp: &RtpPacket, id: u8) -> Option<u16> {
+    let (profile, data) = rtp.extension()?;
+    extension_elements(profile, data)?
+        .into_iter()
+        .find(|(element_id, element)| *element_id == id && element.len() == 2)
+        .map(|(_id, element)| u16::from_be_bytes([element[0], element[1]]))
+}
+
+/// Writes `seqnum` into the header extension with `id` of the RTP packet in `data`, adding or
+/// replacing the extension element.
+pub fn write_seqnum(data: &[u8], id: u8, seqnum: u16) -> Option<Vec<u8>> {
+    let rtp = RtpPacket::parse(data).ok()?;
+    let csrc_end = 12 + 4 * rtp.n_csrcs() as usize;
+
+    let (mut elements, payload_start) = match rtp.extension() {
+        Some((profile, ext)) => (extension_elements(profile, ext)?, csrc_end + 4 + ext.len()),
+        None => (vec![], csrc_end),
+    };
+
+    let seqnum = seqnum.to_be_bytes();
+    elements.retain(|(element_id, _element)| *element_id != id);
+    elements.push((id, &seqnum));
+
+    let one_byte = elements
+        .iter()
+        .all(|(id, element)| (1..=14).contains(id) && (1..=16).contains(&element.len()));
+    let mut ext = vec![];
+    for (id, element) in elements {
+        if one_byte {
+            ext.push((id << 4) | (element.len() - 1) as u8);
+        } else {
+            ext.push(id);
+            ext.push(element.len() as u8);
+        }
+        ext.extend_from_slice(element);
+    }
+    while ext.len() % 4 != 0 {
+        ext.push(0);
+    }
+    let profile: u16 = if one_byte { 0xbede } else { 0x1000 };
+
+    let mut packet = Vec::with_capacity(data.len() - payload_start + csrc_end + 4 + ext.len());
+    packet.extend_from_slice(&data[..csrc_end]);
+    packet[0] |= 0x10;
+    packet.extend_from_slice(&profile.to_be_bytes());
+    packet.extend_from_slice(&((ext.len() / 4) as u16).to_be_bytes());
+    packet.extend_from_slice(&ext);
+    packet.extend_from_slice(&data[payload_start..]);
+
+    Some(packet)
+}
+
+fn extend_seqnum(reference: u64, seqnum: u16) -> u64 {
+    let candidate = (reference & !0xffff) | seqnum as u64;
+    if candidate > reference && candidate - reference > 0x8000 && candidate >= 0x1_0000 {
+        candidate - 0x1_0000
+    } else if candidate < reference && reference - candidate > 0x8000 {
+        candidate + 0x1_0000
+    } else {
+        candidate
+    }
+}
+
+/// The content of a transport-wide feedback packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feedback {
+    pub base_seqnum: u16,
+    /// 24 bit reference time in multiples of 64ms
+    pub reference_time: u32,
+    pub fb_pkt_count: u8,
+    /// Arrival time in microseconds for each packet starting at `base_seqnum`, or `None` if
+    /// the packet was not received
+    pub arrivals: Vec<Option<i64>>,
+}
+
+impl Feedback {
+    /// Parses the feedback control information of a transport-wide feedback packet.
+    pub fn parse(fci: &[u8]) -> Option<Self> {
+        if fci.len() < 8 {
+            return None;
+        }
+
+        let base_seqnum = u16::from_be_bytes([fci[0], fci[1]]);
+        let status_count = u16::from_be_bytes([fci[2], fci[3]]) as usize;
+        let reference_time = u32::from_be_bytes([0, fci[4], fci[5], fci[6]]);
+        let fb_pkt_count = fci[7];
+
+        let mut pos = 8;
+        let mut symbols = Vec::with_capacity(status_count);
+        while symbols.len() < status_count {
+            let chunk = u16::from_be_bytes(fci.get(pos..pos + 2)?.try_into().unwrap());
+            pos += 2;
+
+            if chunk & 0x8000 == 0 {
+                // run length chunk
+                let symbol = ((chunk >> 13) & 0x3) as u8;
+                let run_length = (chunk & 0x1fff) as usize;
+                symbols.extend(std::iter::repeat(symbol).take(run_length));
+            } else if chunk & 0x4000 == 0 {
+                // status vector chunk with 1 bit symbols
+                symbols.extend((0..14).map(|i| ((chunk >> (13 - i)) & 0x1) as u8));
+            } else {
+                // status vector chunk with 2 bit symbols
+                symbols.extend((0..7).map(|i| ((chunk >> (12 - 2 * i)) & 0x3) as u8));
+            }
+        }
+        symbols.truncate(status_count);
+
+        let mut time = reference_time as i64 * REFERENCE_TIME_UNIT_US;
+        let mut arrivals = Vec::with_capacity(status_count);
+        for symbol in symbols {
+            let delta = match symbol {
+                STATUS_NOT_RECEIVED => {
+                    arrivals.push(None);
+                    continue;
+                }
+                STATUS_SMALL_DELTA => {
+                    let delta = *fci.get(pos)? as i64;
+                    pos += 1;
+                    delta
+                }
+                STATUS_LARGE_DELTA => {
+                    let delta = i16::from_be_bytes(fci.get(pos..pos + 2)?.try_into().unwrap());
+                    pos += 2;
+                    delta as i64
+                }
+                _ => return None,
+            };
+            time += delta * DELTA_UNIT_US;
+            arrivals.push(Some(time));
+        }
+
+        Some(Self {
+            base_seqnum,
+            reference_time,
+            fb_pkt_count,
+            arrivals,
+        })
+    }
+
+    /// Writes a complete transport-wide feedback RTCP packet.
+    ///
+    /// All arrival times must be representable as deltas to the previous one.
+    pub fn write(&self, sender_ssrc: u32, media_ssrc: u32) -> Vec<u8> {
+        let mut symbols = Vec::with_capacity(self.arrivals.len());
+        let mut deltas = vec![];
+        let mut time = self.reference_time as i64 * REFERENCE_TIME_UNIT_US;
+        for arrival in &self.arrivals {
+            let Some(arrival) = *arrival else {
+                symbols.push(STATUS_NOT_RECEIVED);
+                continue;
+            };
+            let delta = (arrival - time) / DELTA_UNIT_US;
+            time = arrival;
+            if let Ok(delta) = u8::try_from(delta) {
+                symbols.push(STATUS_SMALL_DELTA);
+                deltas.push(delta);
+            } else {
+                symbols.push(STATUS_LARGE_DELTA);
+                deltas.extend_from_slice(&(delta as i16).to_be_bytes());
+            }
+        }
+
+        let mut packet = vec![0x80 | RTCP_FMT_TWCC, RTCP_PT_RTPFB, 0, 0];
+        packet.extend_from_slice(&sender_ssrc.to_be_bytes());
+        packet.extend_from_slice(&media_ssrc.to_be_bytes());
+        packet.extend_from_slice(&self.base_seqnum.to_be_bytes());
+        packet.extend_from_slice(&(symbols.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&self.reference_time.to_be_bytes()[1..]);
+        packet.push(self.fb_pkt_count);
+
+        let mut pos = 0;
+        while pos < symbols.len() {
+            let remaining = &symbols[pos..];
+            let run_length = remaining
+                .iter()
+                .take(0x1fff)
+                .take_while(|&&symbol| symbol == remaining[0])
+                .count();
+
+            let chunk = if run_length >= 7 {
+                pos += run_length;
+                ((remaining[0] as u16) << 13) | run_length as u16
+            } else if remaining
+                .iter()
+                .take(14)
+                .all(|&symbol| symbol <= STATUS_SMALL_DELTA)
+            {
+                pos += remaining.len().min(14);
+                remaining
+                    .iter()
+                    .take(14)
+                    .enumerate()
+                    .fold(0x8000, |chunk, (i, &symbol)| {
+                        chunk | ((symbol as u16) << (13 - i))
+                    })
+            } else {
+                pos += remaining.len().min(7);
+                remaining
+                    .iter()
+                    .take(7)
+                    .enumerate()
+                    .fold(0xc000, |chunk, (i, &symbol)| {
+                        chunk | ((symbol as u16) << (12 - 2 * i))
+                    })
+            };
+            packet.extend_from_slice(&chunk.to_be_bytes());
+        }
+
+        packet.extend_from_slice(&deltas);
+        while packet.len() % 4 != 0 {
+            packet.push(0);
+        }
+
+        let length = (packet.len() / 4 - 1) as u16;
+        packet[2..4].copy_from_slice(&length.to_be_bytes());
+
+        packet
+    }
+}
+
+/// Returns the transport-wide feedback contained in the (compound) RTCP packet in `data`.
+pub fn parse_feedback_packets(data: &[u8]) -> Vec<Feedback> {
+    let mut feedback = vec![];
+    let mut pos = 0;
+
+    while let Some(header) = data.get(pos..pos + 4) {
+        let len = (u16::from_be_bytes([header[2], header[3]]) as usize + 1) * 4;
+        let Some(packet) = data.get(pos..pos + len) else {
+            break;
+        };
+        pos += len;
+
+        if packet[1] != RTCP_PT_RTPFB || packet[0] & 0x1f != RTCP_FMT_TWCC || packet.len() < 12 {
+            continue;
+        }
+
+        let padding = if packet[0] & 0x20 != 0 {
+            packet[len - 1] as usize
+        } else {
+            0
+        };
+        if let Some(fb) = packet
+            .get(12..len.saturating_sub(padding))
+            .and_then(Feedback::parse)
+        {
+            feedback.push(fb);
+        }
+    }
+
+    feedback
+}
+
+#[derive(Debug)]
+struct SentPacket {
+    seqnum: u64,
+    time: Option<gst::ClockTime>,
+    size: usize,
+    pt: u8,
+    ssrc: u32,
+}
+
+/// Assigns transport-wide sequence numbers to sent packets and matches received feedback with
+/// them.
+#[derive(Debug, Default)]
+pub struct TwccSender {
+    next_seqnum: u64,
+    packets: VecDeque<SentPacket>,
+}
+
+impl TwccSender {
+    pub fn next_seqnum(&self) -> u16 {
+        self.next_seqnum as u16
+    }
+
+    /// Remembers a packet that was sent with the next transport-wide sequence number.
+    pub fn packet_sent(&mut self, time: Option<gst::ClockTime>, size: usize, pt: u8, ssrc: u32) {
+        if self.packets.len() >= MAX_SENT_PACKETS {
+            self.packets.pop_front();
+        }
+        self.packets.push_back(SentPacket {
+            seqnum: self.next_seqnum,
+            time,
+            size,
+            pt,
+            ssrc,
+        });
+        self.next_seqnum += 1;
+    }
+
+    /// Returns the `RTPTWCCPacket` structures with send and arrival times for the packets that
+    /// are part of `feedback`.
+    pub fn handle_feedback(&self, feedback: &Feedback) -> Vec<gst::Structure> {
+        let (Some(first), Some(last)) = (self.packets.front(), self.packets.back()) else {
+            return vec![];
+        };
+        let base_seqnum = extend_seqnum(last.seqnum, feedback.base_seqnum);
+
+        feedback
+            .arrivals
+            .iter()
+            .enumerate()
+            .filter_map(|(i, arrival)| {
+                let seqnum = base_seqnum + i as u64;
+                let packet = self
+                    .packets
+                    .get(seqnum.checked_sub(first.seqnum)? as usize)?;
+                let time = packet.time?;
+
+                let mut s = gst::Structure::builder("RTPTWCCPacket")
+                    .field("seqnum", packet.seqnum as u16 as u32)
+                    .field("local-ts", time)
+                    .field("payload-type", packet.pt as u32)
+                    .field("ssrc", packet.ssrc)
+                    .field("size", packet.size as u32)
+                    .field("lost", arrival.is_none());
+                if let Some(arrival) = arrival {
+                    s = s.field(
+                        "remote-ts",
+                        gst::ClockTime::from_useconds(arrival.max(0) as u64),
+                    );
+                }
+
+                Some(s.build())
+            })
+            .collect()
+    }
+}
+
+/// Records the arrival times of received packets and generates transport-wide feedback.
+#[derive(Debug, Default)]
+pub struct TwccReceiver {
+    media_ssrc: Option<u32>,
+    base_time: Option<Instant>,
+    // extended seqnum -> arrival time in microseconds since `base_time`
+    packets: BTreeMap<u64, i64>,
+    max_seqnum: Option<u64>,
+    // first seqnum that was not reported yet
+    next_report: Option<u64>,
+    fb_pkt_count: u8,
+    next_feedback: Option<Instant>,
+}
+
+impl TwccReceiver {
+    /// Records the arrival of the packet with transport-wide sequence number `seqnum`.
+    ///
+    /// Returns `true` if feedback was not pending before and has to be scheduled.
+    pub fn packet_received(&mut self, seqnum: u16, ssrc: u32, now: Instant) -> bool {
+        let base_time = *self.base_time.get_or_insert(now);
+        self.media_ssrc.get_or_insert(ssrc);
+
+        let seqnum = self
+            .max_seqnum
+            .map_or(seqnum as u64, |max| extend_seqnum(max, seqnum));
+        if self.next_report.is_some_and(|next| seqnum < next) {
+            // already reported as lost
+            return false;
+        }
+        self.max_seqnum = self.max_seqnum.max(Some(seqnum));
+        self.packets.insert(
+            seqnum,
+            now.saturating_duration_since(base_time).as_micros() as i64,
+        );
+
+        if self.next_feedback.is_none() {
+            self.next_feedback = Some(now + FEEDBACK_INTERVAL);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn poll_feedback_timeout(&self) -> Option<Instant> {
+        self.next_feedback
+    }
+
+    /// Returns a feedback RTCP packet for all packets since the last feedback if it is time to
+    /// send one.
+    pub fn poll_feedback(&mut self, now: Instant, sender_ssrc: u32) -> Option<Vec<u8>> {
+        if self.next_feedback.map_or(true, |next| now < next) {
+            return None;
+        }
+        let (Some(media_ssrc), Some(max_seqnum), Some((&first_seqnum, &first_arrival))) = (
+            self.media_ssrc,
+            self.max_seqnum,
+            self.packets.first_key_value(),
+        ) else {
+            self.next_feedback = None;
+            return None;
+        };
+
+        let base_seqnum = self.next_report.unwrap_or(first_seqnum);
+        let reference_time = first_arrival.div_euclid(REFERENCE_TIME_UNIT_US);
+
+        let mut arrivals = vec![];
+        let mut time = reference_time * REFERENCE_TIME_UNIT_US;
+        for seqnum in base_seqnum..=max_seqnum {
+            if arrivals.len() == u16::MAX as usize {
+                break;
+            }
+            let Some(&arrival) = self.packets.get(&seqnum) else {
+                arrivals.push(None);
+                continue;
+            };
+            let delta = (arrival - time) / DELTA_UNIT_US;
+            if i16::try_from(delta).is_err() {
+                // the remaining packets are reported with a new reference time
+                break;
+            }
+            time += delta * DELTA_UNIT_US;
+            arrivals.push(Some(time));
+        }
+        while arrivals.last().is_some_and(Option::is_none) {
+            arrivals.pop();
+        }
+
+        let end = base_seqnum + arrivals.len() as u64;
+        self.packets = self.packets.split_off(&end);
+        self.next_report = Some(end);
+
+        let feedback = Feedback {
+            base_seqnum: base_seqnum as u16,
+            reference_time: (reference_time as u32) & 0x00ff_ffff,
+            fb_pkt_count: self.fb_pkt_count,
+            arrivals,
+        };
+        self.fb_pkt_count = self.fb_pkt_count.wrapping_add(1);
+        self.next_feedback = if self.packets.is_empty() {
+            None
+        } else {
+            Some(now + FEEDBACK_INTERVAL)
+        };
+
+        Some(feedback.write(sender_ssrc, media_ssrc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtpbin2::session::tests::generate_rtp_packet;
+
+    #[test]
+    fn extension_id() {
+        gst::init().unwrap();
+
+        let caps = gst::Caps::builder("application/x-rtp")
+            .field("extmap-1", "urn:ietf:params:rtp-hdrext:sdes:mid")
+            .field("extmap-3", TWCC_EXTENSION_URI)
+            .build();
+        assert_eq!(extension_id_from_caps(caps.structure(0).unwrap()), Some(3));
+
+        let caps = gst::Caps::builder("application/x-rtp")
+            .field("extmap-5", gst::Array::new(["", TWCC_EXTENSION_URI, ""]))
+            .build();
+        assert_eq!(extension_id_from_caps(caps.structure(0).unwrap()), Some(5));
+
+        let caps = gst::Caps::builder("application/x-rtp").build();
+        assert_eq!(extension_id_from_caps(caps.structure(0).unwrap()), None);
+    }
+
+    #[test]
+    fn write_read_seqnum() {
+        let data = generate_rtp_packet(0x12345678, 100, 0, 5);
+
+        let written = write_seqnum(&data, 3, 0x1234).unwrap();
+        let rtp = RtpPacket::parse(&written).unwrap();
+        assert_eq!(read_seqnum(&rtp, 3), Some(0x1234));
+        assert_eq!(rtp.payload(), &data[12..]);
+        assert_eq!(rtp.sequence_number(), 100);
+
+        // replacing the existing element and keeping others
+        let written = write_seqnum(&written, 5, 0x0001).unwrap();
+        let written = write_seqnum(&written, 3, 0x4321).unwrap();
+        let rtp = RtpPacket::parse(&written).unwrap();
+        assert_eq!(read_seqnum(&rtp, 3), Some(0x4321));
+        assert_eq!(read_seqnum(&rtp, 5), Some(0x0001));
+        assert_eq!(rtp.payload(), &data[12..]);
+        let (profile, ext) = rtp.extension().unwrap();
+        assert_eq!(profile, 0xbede);
+        assert_eq!(ext.len(), 8);
+    }
+
+    #[test]
+    fn feedback_roundtrip() {
+        let mut arrivals = vec![Some(64_000), None, Some(64_250), Some(120_000)];
+        // long run of received packets with small deltas and a negative delta
+        arrivals.extend((0..20).map(|i| Some(120_000 + i * 1_000)));
+        arrivals.push(Some(110_000));
+        arrivals.extend(std::iter::repeat(None).take(10));
+        arrivals.push(Some(200_000));
+
+        let feedback = Feedback {
+            base_seqnum: 65530,
+            reference_time: 1,
+            fb_pkt_count: 7,
+            arrivals,
+        };
+
+        let packet = feedback.write(0x1, 0x2);
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(packet[0], 0x80 | RTCP_FMT_TWCC);
+        assert_eq!(packet[1], RTCP_PT_RTPFB);
+        assert_eq!(
+            (u16::from_be_bytes([packet[2], packet[3]]) as usize + 1) * 4,
+            packet.len()
+        );
+
+        assert_eq!(parse_feedback_packets(&packet), vec![feedback]);
+    }
+
+    #[test]
+    fn receiver_sender() {
+        gst::init().unwrap();
+
+        let mut sender = TwccSender::default();
+        let mut receiver = TwccReceiver::default();
+        let now = Instant::now();
+
+        for i in 0..10u64 {
+            let seqnum = sender.next_seqnum();
+            sender.packet_sent(
+                Some(gst::ClockTime::from_mseconds(i * 10)),
+                1000,
+                96,
+                0x12345678,
+            );
+            // packet 3 is lost
+            if i != 3 {
+                let arrival = now + Duration::from_millis(i * 10 + 5);
+                let first = receiver.packet_received(seqnum, 0x12345678, arrival);
+                assert_eq!(first, i == 0);
+            }
+        }
+
+        assert_eq!(
+            receiver.poll_feedback_timeout(),
+            Some(now + Duration::from_millis(5) + FEEDBACK_INTERVAL)
+        );
+        assert!(receiver
+            .poll_feedback(now + Duration::from_millis(50), 0x1)
+            .is_none());
+
+        let packet = receiver
+            .poll_feedback(now + Duration::from_millis(200), 0x1)
+            .unwrap();
+        assert_eq!(receiver.poll_feedback_timeout(), None);
+
+        let feedback = parse_feedback_packets(&packet);
+        assert_eq!(feedback.len(), 1);
+        let packets = sender.handle_feedback(&feedback[0]);
+        assert_eq!(packets.len(), 10);
+
+        let mut prev_remote_ts = None;
+        for (i, packet) in packets.iter().enumerate() {
+            assert_eq!(packet.get::<u32>("seqnum").unwrap(), i as u32);
+            assert_eq!(
+                packet.get::<gst::ClockTime>("local-ts").unwrap(),
+                gst::ClockTime::from_mseconds(i as u64 * 10)
+            );
+            assert_eq!(packet.get::<bool>("lost").unwrap(), i == 3);
+            if i == 3 {
+                continue;
+            }
+            let remote_ts = packet.get::<gst::ClockTime>("remote-ts").unwrap();
+            if let Some(prev_remote_ts) = prev_remote_ts {
+                assert!(remote_ts > prev_remote_ts);
+            }
+            prev_remote_ts = Some(remote_ts);
+        }
+
+        // a late arrival of the lost packet is not reported again
+        assert!(!receiver.packet_received(3, 0x12345678, now + Duration::from_millis(300)));
+        assert_eq!(receiver.poll_feedback_timeout(), None);
+    }
+}
@@ -0,0 +1,438 @@
+// GStreamer RTSP Source 2
+//
+// SPDX-License-Identifier: MPL-2.0
+//
+// https://www.rfc-editor.org/rfc/rfc2617.html
+// https://www.rfc-editor.org/rfc/rfc7616.html
+
+use std::fmt;
+
+use data_encoding::{BASE64, HEXLOWER};
+use md5::Md5;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the password in debug logs
+        f.debug_struct("Credentials")
+            .field("user", &self.user)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DigestAlgorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl DigestAlgorithm {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "MD5" => Some(Self::Md5),
+            "MD5-SESS" => Some(Self::Md5Sess),
+            "SHA-256" => Some(Self::Sha256),
+            "SHA-256-SESS" => Some(Self::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Md5Sess => "MD5-sess",
+            Self::Sha256 => "SHA-256",
+            Self::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn is_sess(&self) -> bool {
+        matches!(self, Self::Md5Sess | Self::Sha256Sess)
+    }
+
+    // Higher is stronger, used to pick between multiple challenges
+    fn strength(&self) -> u8 {
+        match self {
+            Self::Md5 | Self::Md5Sess => 1,
+            Self::Sha256 | Self::Sha256Sess => 2,
+        }
+    }
+
+    fn hash(&self, data: &str) -> String {
+        match self {
+            Self::Md5 | Self::Md5Sess => HEXLOWER.encode(&Md5::digest(data.as_bytes())),
+            Self::Sha256 | Self::Sha256Sess => HEXLOWER.encode(&Sha256::digest(data.as_bytes())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: DigestAlgorithm,
+    // We only support qop=auth, auth-int would require hashing the body
+    qop_auth: bool,
+    stale: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Challenge {
+    Basic,
+    Digest(DigestChallenge),
+}
+
+impl Challenge {
+    fn strength(&self) -> u8 {
+        match self {
+            Challenge::Basic => 0,
+            Challenge::Digest(d) => d.algorithm.strength(),
+        }
+    }
+}
+
+/// Splits a `WWW-Authenticate` header value into `(scheme, params)` tuples.
+///
+/// Multiple `WWW-Authenticate` headers end up concatenated into one comma-separated value,
+/// so a new challenge starts whenever a token is followed by whitespace instead of `=`.
+fn split_challenges(value: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut challenges: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    let mut chars = value.chars().peekable();
+
+    loop {
+        // Skip separators
+        while matches!(chars.peek(), Some(c) if c.is_whitespace() || *c == ',') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut token = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == ',' || c == '=' {
+                break;
+            }
+            token.push(c);
+            chars.next();
+        }
+
+        while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+            chars.next();
+        }
+
+        if chars.peek() != Some(&'=') {
+            // A bare token is the scheme of a new challenge
+            challenges.push((token, Vec::new()));
+            continue;
+        }
+        chars.next();
+
+        while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+            chars.next();
+        }
+
+        let mut param_value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => {
+                        if let Some(c) = chars.next() {
+                            param_value.push(c);
+                        }
+                    }
+                    c => param_value.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c.is_whitespace() {
+                    break;
+                }
+                param_value.push(c);
+                chars.next();
+            }
+        }
+
+        match challenges.last_mut() {
+            Some((_, params)) => params.push((token.to_ascii_lowercase(), param_value)),
+            None => {
+                // Parameter without a scheme, ignore it
+                continue;
+            }
+        }
+    }
+
+    challenges
+}
+
+/// Parses all supported challenges out of a `WWW-Authenticate` header value.
+pub fn parse_challenges(value: &str) -> Vec<Challenge> {
+    let mut ret = Vec::new();
+
+    for (scheme, params) in split_challenges(value) {
+        let get = |name: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+        };
+
+        if scheme.eq_ignore_ascii_case("basic") {
+            ret.push(Challenge::Basic);
+        } else if scheme.eq_ignore_ascii_case("digest") {
+            let (Some(realm), Some(nonce)) = (get("realm"), get("nonce")) else {
+                gst::warning!(super::imp::CAT, "Digest challenge without realm or nonce");
+                continue;
+            };
+            let algorithm = match get("algorithm") {
+                Some(a) => match DigestAlgorithm::parse(&a) {
+                    Some(a) => a,
+                    None => {
+                        gst::info!(super::imp::CAT, "Unsupported digest algorithm {a}");
+                        continue;
+                    }
+                },
+                None => DigestAlgorithm::Md5,
+            };
+            let qop_auth = match get("qop") {
+                Some(qop) => {
+                    let qop_auth = qop.split(',').any(|q| q.trim() == "auth");
+                    if !qop_auth {
+                        gst::info!(super::imp::CAT, "Unsupported digest qop {qop}");
+                        continue;
+                    }
+                    true
+                }
+                None => false,
+            };
+            let stale = get("stale").is_some_and(|s| s.eq_ignore_ascii_case("true"));
+            ret.push(Challenge::Digest(DigestChallenge {
+                realm,
+                nonce,
+                opaque: get("opaque"),
+                algorithm,
+                qop_auth,
+                stale,
+            }));
+        } else {
+            gst::info!(
+                super::imp::CAT,
+                "Unsupported authentication scheme {scheme}"
+            );
+        }
+    }
+
+    ret
+}
+
+/// Generates `Authorization` header values for requests once a challenge has been received.
+#[derive(Debug)]
+pub struct Authenticator {
+    credentials: Credentials,
+    challenge: Challenge,
+    nonce_count: u32,
+}
+
+impl Authenticator {
+    /// Picks the strongest supported challenge from a `WWW-Authenticate` header value.
+    pub fn new(credentials: Credentials, www_authenticate: &str) -> Option<Self> {
+        let challenge = parse_challenges(www_authenticate)
+            .into_iter()
+            .max_by_key(Challenge::strength)?;
+
+        Some(Authenticator {
+            credentials,
+            challenge,
+            nonce_count: 0,
+        })
+    }
+
+    pub fn is_stale(&self) -> bool {
+        matches!(&self.challenge, Challenge::Digest(d) if d.stale)
+    }
+
+    pub fn nonce(&self) -> Option<&str> {
+        match &self.challenge {
+            Challenge::Basic => None,
+            Challenge::Digest(d) => Some(&d.nonce),
+        }
+    }
+
+    pub fn authorization(&mut self, method: &str, uri: &str) -> String {
+        let cnonce = {
+            let bytes: [u8; 16] = rand::thread_rng().gen();
+            HEXLOWER.encode(&bytes)
+        };
+        self.authorization_with_cnonce(method, uri, &cnonce)
+    }
+
+    fn authorization_with_cnonce(&mut self, method: &str, uri: &str, cnonce: &str) -> String {
+        let Credentials { user, password } = &self.credentials;
+
+        let d = match &self.challenge {
+            Challenge::Basic => {
+                let token = BASE64.encode(format!("{user}:{password}").as_bytes());
+                return format!("Basic {token}");
+            }
+            Challenge::Digest(d) => d,
+        };
+
+        self.nonce_count += 1;
+        let nc = format!("{:08x}", self.nonce_count);
+
+        let alg = d.algorithm;
+        let mut ha1 = alg.hash(&format!("{user}:{}:{password}", d.realm));
+        if alg.is_sess() {
+            ha1 = alg.hash(&format!("{ha1}:{}:{cnonce}", d.nonce));
+        }
+        let ha2 = alg.hash(&format!("{method}:{uri}"));
+        let response = if d.qop_auth {
+            alg.hash(&format!("{ha1}:{}:{nc}:{cnonce}:auth:{ha2}", d.nonce))
+        } else {
+            alg.hash(&format!("{ha1}:{}:{ha2}", d.nonce))
+        };
+
+        let mut value = format!(
+            "Digest username=\"{user}\", realm=\"{}\", nonce=\"{}\", uri=\"{uri}\", response=\"{response}\", algorithm={}",
+            d.realm,
+            d.nonce,
+            alg.as_str(),
+        );
+        if let Some(opaque) = &d.opaque {
+            value.push_str(&format!(", opaque=\"{opaque}\""));
+        }
+        if d.qop_auth {
+            value.push_str(&format!(", qop=auth, nc={nc}, cnonce=\"{cnonce}\""));
+        }
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> Credentials {
+        Credentials {
+            user: "Mufasa".to_string(),
+            password: "Circle of Life".to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_multiple_challenges() {
+        let challenges = parse_challenges(
+            "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=SHA-256, \
+            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+            opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\", \
+            Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=MD5, \
+            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+            opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\", Basic realm=\"foo\"",
+        );
+        assert_eq!(challenges.len(), 3);
+        assert!(matches!(
+            &challenges[0],
+            Challenge::Digest(DigestChallenge {
+                algorithm: DigestAlgorithm::Sha256,
+                qop_auth: true,
+                ..
+            })
+        ));
+        assert!(matches!(
+            &challenges[1],
+            Challenge::Digest(DigestChallenge {
+                algorithm: DigestAlgorithm::Md5,
+                ..
+            })
+        ));
+        assert_eq!(challenges[2], Challenge::Basic);
+    }
+
+    #[test]
+    fn basic() {
+        let mut auth = Authenticator::new(
+            Credentials {
+                user: "Aladdin".to_string(),
+                password: "open sesame".to_string(),
+            },
+            "Basic realm=\"WallyWorld\"",
+        )
+        .unwrap();
+        assert_eq!(
+            auth.authorization("DESCRIBE", "rtsp://localhost/test"),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7616.html#section-3.9.1
+    const RFC7616_CHALLENGE: &str = "realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+        nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+        opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+    const RFC7616_CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    #[test]
+    fn digest_md5() {
+        let mut auth = Authenticator::new(
+            credentials(),
+            &format!("Digest {RFC7616_CHALLENGE}, algorithm=MD5"),
+        )
+        .unwrap();
+        let value = auth.authorization_with_cnonce("GET", "/dir/index.html", RFC7616_CNONCE);
+        assert!(value.contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""));
+        assert!(value.contains("nc=00000001"));
+        assert!(value.contains("algorithm=MD5"));
+    }
+
+    #[test]
+    fn digest_sha256() {
+        let mut auth = Authenticator::new(
+            credentials(),
+            &format!("Digest {RFC7616_CHALLENGE}, algorithm=SHA-256"),
+        )
+        .unwrap();
+        let value = auth.authorization_with_cnonce("GET", "/dir/index.html", RFC7616_CNONCE);
+        assert!(value.contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+        assert!(value.contains("algorithm=SHA-256"));
+        assert!(value.contains("opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""));
+
+        // Nonce count increases on every request with the same nonce
+        let value = auth.authorization_with_cnonce("GET", "/dir/index.html", RFC7616_CNONCE);
+        assert!(value.contains("nc=00000002"));
+    }
+
+    #[test]
+    fn prefers_strongest_challenge() {
+        let auth = Authenticator::new(
+            credentials(),
+            &format!(
+                "Basic realm=\"foo\", Digest {RFC7616_CHALLENGE}, algorithm=MD5, \
+                Digest {RFC7616_CHALLENGE}, algorithm=SHA-256"
+            ),
+        )
+        .unwrap();
+        assert!(matches!(
+            auth.challenge,
+            Challenge::Digest(DigestChallenge {
+                algorithm: DigestAlgorithm::Sha256,
+                ..
+            })
+        ));
+    }
+}
@@ -11,11 +11,12 @@
 //
 // https://www.rfc-editor.org/rfc/rfc2326.html
 
-use std::collections::{btree_set::BTreeSet, HashMap};
+use std::collections::{btree_set::BTreeSet, BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Mutex;
 use std::time::Duration;
@@ -32,13 +33,15 @@ use tokio::task::JoinHandle;
 use tokio::time;
 
 use rtsp_types::headers::{
-    CSeq, NptRange, NptTime, Public, Range, RtpInfos, RtpLowerTransport, RtpProfile, RtpTransport,
-    RtpTransportParameters, Session, Transport, TransportMode, Transports, ACCEPT, CONTENT_BASE,
-    CONTENT_LOCATION, USER_AGENT,
+    CSeq, Public, RtpInfos, RtpLowerTransport, RtpProfile, RtpTransport, RtpTransportParameters,
+    Session, Transport, TransportMode, Transports, ACCEPT, ACCEPT_RANGES, AUTHORIZATION,
+    CONTENT_BASE, CONTENT_LOCATION, CONTENT_TYPE, LOCATION, MEDIA_PROPERTIES, PIPELINED_REQUESTS,
+    RANGE, REQUIRE, UNSUPPORTED, USER_AGENT, WWW_AUTHENTICATE,
 };
 use rtsp_types::{Message, Method, Request, Response, StatusCode, Version};
 
 use lru::LruCache;
+use percent_encoding::percent_decode_str;
 use url::Url;
 
 use gst::buffer::{MappedBuffer, Readable};
@@ -47,9 +50,12 @@ use gst::prelude::*;
 use gst::subclass::prelude::*;
 use gst_net::gio;
 
+use super::auth::{Authenticator, Credentials};
 use super::body::Body;
 use super::sdp;
+use super::tls::{TlsConnector, DEFAULT_RTSPS_PORT};
 use super::transport::RtspTransportInfo;
+use super::{RtspBackchannel, RtspVersion};
 
 const DEFAULT_LOCATION: Option<Url> = None;
 const DEFAULT_TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(5);
@@ -59,8 +65,25 @@ const DEFAULT_PROTOCOLS: &str = "udp-mcast,udp,tcp";
 // Equal to MTU + 8 by default to avoid incorrectly detecting an MTU sized buffer as having
 // possibly overflown our receive buffer, and triggering a doubling of the buffer sizes.
 const DEFAULT_RECEIVE_MTU: u32 = 1500 + 8;
+const DEFAULT_USER_ID: Option<&str> = None;
+const DEFAULT_USER_PW: Option<&str> = None;
+const DEFAULT_TLS_CA_FILE: Option<&str> = None;
+const DEFAULT_TLS_ACCEPT_INVALID_CERTS: bool = false;
+const DEFAULT_TLS_SERVER_NAME: Option<&str> = None;
+const DEFAULT_RTSP_PORT: u16 = 554;
+const DEFAULT_BACKCHANNEL: RtspBackchannel = RtspBackchannel::None;
+const DEFAULT_RTSP_VERSION: RtspVersion = RtspVersion::V1_0;
+const DEFAULT_RECONNECT_ATTEMPTS: i32 = 0;
+const DEFAULT_RECONNECT_BACKOFF: gst::ClockTime = gst::ClockTime::from_seconds(1);
+const MAX_RECONNECT_BACKOFF: gst::ClockTime = gst::ClockTime::from_seconds(30);
+// https://datatracker.ietf.org/doc/html/rfc2326#section-12.37
+const DEFAULT_SESSION_TIMEOUT: u64 = 60;
+
+// https://www.onvif.org/specs/stream/ONVIF-Streaming-Spec.pdf section 5.3
+const ONVIF_BACKCHANNEL_REQUIRE: &str = "www.onvif.org/ver20/backchannel";
 
 const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+const MAX_AUTH_RETRIES: u32 = 3;
 const MAX_BIND_PORT_RETRY: u16 = 100;
 const UDP_PACKET_MAX_SIZE: u32 = 65535 - 8;
 const RTCP_ADDR_CACHE_SIZE: usize = 100;
@@ -100,6 +123,15 @@ struct Settings {
     protocols: Vec<RtspProtocol>,
     timeout: gst::ClockTime,
     receive_mtu: u32,
+    user_id: Option<String>,
+    user_pw: Option<String>,
+    tls_ca_file: Option<PathBuf>,
+    tls_accept_invalid_certs: bool,
+    tls_server_name: Option<String>,
+    backchannel: RtspBackchannel,
+    reconnect_attempts: i32,
+    reconnect_backoff: gst::ClockTime,
+    default_rtsp_version: RtspVersion,
 }
 
 impl Default for Settings {
@@ -110,6 +142,15 @@ impl Default for Settings {
             timeout: DEFAULT_TIMEOUT,
             protocols: parse_protocols_str(DEFAULT_PROTOCOLS).unwrap(),
             receive_mtu: DEFAULT_RECEIVE_MTU,
+            user_id: DEFAULT_USER_ID.map(String::from),
+            user_pw: DEFAULT_USER_PW.map(String::from),
+            tls_ca_file: DEFAULT_TLS_CA_FILE.map(PathBuf::from),
+            tls_accept_invalid_certs: DEFAULT_TLS_ACCEPT_INVALID_CERTS,
+            tls_server_name: DEFAULT_TLS_SERVER_NAME.map(String::from),
+            backchannel: DEFAULT_BACKCHANNEL,
+            reconnect_attempts: DEFAULT_RECONNECT_ATTEMPTS,
+            reconnect_backoff: DEFAULT_RECONNECT_BACKOFF,
+            default_rtsp_version: DEFAULT_RTSP_VERSION,
         }
     }
 }
@@ -117,7 +158,12 @@ impl Default for Settings {
 #[derive(Debug)]
 enum Commands {
     Play,
-    //Pause,
+    Pause,
+    Seek {
+        range: sdp::PlayRange,
+        flush: bool,
+        seqnum: gst::Seqnum,
+    },
     Teardown(Option<oneshot::Sender<()>>),
     Data(rtsp_types::Data<Body>),
 }
@@ -127,6 +173,11 @@ pub struct RtspSrc {
     settings: Mutex<Settings>,
     task_handle: Mutex<Option<JoinHandle<()>>>,
     command_queue: Mutex<Option<mpsc::Sender<Commands>>>,
+    media_range: Mutex<Option<sdp::PlayRange>>,
+    last_seek_seqnum: Mutex<Option<gst::Seqnum>>,
+    /// Stream time of the last buffer that was output, recorded media resumes from there
+    /// after reconnecting.
+    last_position: Mutex<Option<gst::ClockTime>>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -139,12 +190,16 @@ pub enum RtspError {
     HeaderParser(#[from] rtsp_types::headers::HeaderParseError),
     #[error("SDP parse error")]
     SDPParser(#[from] sdp_types::ParserError),
+    #[error("TLS error")]
+    Tls(#[from] rustls::Error),
     #[error("Unexpected RTSP message: expected, received")]
     UnexpectedMessage(&'static str, rtsp_types::Message<Body>),
     #[error("Invalid RTSP message")]
     InvalidMessage(&'static str),
     #[error("Fatal error")]
     Fatal(String),
+    #[error("Not authorized")]
+    NotAuthorized(String),
 }
 
 pub(crate) static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
@@ -187,6 +242,17 @@ fn parse_protocols_str(s: &str) -> Result<Vec<RtspProtocol>, glib::Error> {
     Ok(acc)
 }
 
+fn url_credentials(url: &Url) -> Option<Credentials> {
+    if url.username().is_empty() {
+        return None;
+    }
+    let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+    Some(Credentials {
+        user: decode(url.username()),
+        password: url.password().map(decode).unwrap_or_default(),
+    })
+}
+
 impl RtspSrc {
     fn set_location(&self, uri: Option<&str>) -> Result<(), glib::Error> {
         if self.obj().current_state() > gst::State::Ready {
@@ -210,20 +276,15 @@ impl RtspSrc {
             )
         })?;
 
-        if uri.password().is_some() || !uri.username().is_empty() {
-            // TODO
-            gst::fixme!(CAT, "URI credentials are currently ignored");
-        }
-
         match (uri.host_str(), uri.port()) {
             (Some(_), Some(_)) | (Some(_), None) => Ok(()),
             _ => Err(glib::Error::new(gst::URIError::BadUri, "Invalid host")),
         }?;
 
         let protocols: &[RtspProtocol] = match uri.scheme() {
-            "rtspu" => &[RtspProtocol::UdpMulticast, RtspProtocol::Udp],
-            "rtspt" => &[RtspProtocol::Tcp],
-            "rtsp" => &settings.protocols,
+            "rtspu" | "rtspsu" => &[RtspProtocol::UdpMulticast, RtspProtocol::Udp],
+            "rtspt" | "rtspst" => &[RtspProtocol::Tcp],
+            "rtsp" | "rtsps" => &settings.protocols,
             scheme => {
                 return Err(glib::Error::new(
                     gst::URIError::UnsupportedProtocol,
@@ -308,6 +369,60 @@ impl ObjectImpl for RtspSrc {
                     .default_value(DEFAULT_TIMEOUT.into())
                     .mutable_ready()
                     .build(),
+                glib::ParamSpecString::builder("user-id")
+                    .nick("User ID")
+                    .blurb("RTSP user ID for authentication, overridden by credentials in the location URI")
+                    .default_value(DEFAULT_USER_ID)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("user-pw")
+                    .nick("User password")
+                    .blurb("RTSP user password for authentication, overridden by credentials in the location URI")
+                    .default_value(DEFAULT_USER_PW)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("tls-ca-file")
+                    .nick("TLS CA file")
+                    .blurb("PEM file with the CA certificates used to verify rtsps:// servers (NULL = system certificates)")
+                    .default_value(DEFAULT_TLS_CA_FILE)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("tls-accept-invalid-certs")
+                    .nick("TLS accept invalid certificates")
+                    .blurb("Do not verify the certificate of rtsps:// servers. This is insecure and should only be used for testing")
+                    .default_value(DEFAULT_TLS_ACCEPT_INVALID_CERTS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("tls-server-name")
+                    .nick("TLS server name")
+                    .blurb("Name to verify the certificate of rtsps:// servers against (NULL = host in the location URI)")
+                    .default_value(DEFAULT_TLS_SERVER_NAME)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("backchannel", DEFAULT_BACKCHANNEL)
+                    .nick("Backchannel")
+                    .blurb("The type of backchannel to set up. RTP data to send to the server is linked to the backchannel_%u sink pads")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecInt::builder("reconnect-attempts")
+                    .nick("Reconnect attempts")
+                    .blurb("Number of times to try to re-establish the session when the connection to the server is lost (0 = disabled, -1 = unlimited)")
+                    .minimum(-1)
+                    .default_value(DEFAULT_RECONNECT_ATTEMPTS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("reconnect-backoff")
+                    .nick("Reconnect backoff")
+                    .blurb("Delay before the first reconnection attempt, doubled after every failed attempt up to 30 seconds, in nanoseconds")
+                    .maximum(gst::ClockTime::MAX.into())
+                    .default_value(DEFAULT_RECONNECT_BACKOFF.into())
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("default-rtsp-version", DEFAULT_RTSP_VERSION)
+                    .nick("Default RTSP version")
+                    .blurb("The RTSP version to try first, falling back to RTSP 1.0 if the server doesn't support it")
+                    .mutable_ready()
+                    .build(),
             ]
         });
 
@@ -348,6 +463,54 @@ impl ObjectImpl for RtspSrc {
                 settings.timeout = timeout;
                 Ok(())
             }
+            "user-id" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.user_id = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "user-pw" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.user_pw = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "tls-ca-file" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.tls_ca_file = value
+                    .get::<Option<&str>>()
+                    .expect("type checked upstream")
+                    .map(PathBuf::from);
+                Ok(())
+            }
+            "tls-accept-invalid-certs" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.tls_accept_invalid_certs = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "tls-server-name" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.tls_server_name = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "backchannel" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.backchannel = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "reconnect-attempts" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.reconnect_attempts = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "reconnect-backoff" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.reconnect_backoff = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "default-rtsp-version" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.default_rtsp_version = value.get().expect("type checked upstream");
+                Ok(())
+            }
             name => unimplemented!("Property '{name}'"),
         };
 
@@ -392,6 +555,46 @@ impl ObjectImpl for RtspSrc {
                 let settings = self.settings.lock().unwrap();
                 settings.timeout.to_value()
             }
+            "user-id" => {
+                let settings = self.settings.lock().unwrap();
+                settings.user_id.to_value()
+            }
+            "user-pw" => {
+                let settings = self.settings.lock().unwrap();
+                settings.user_pw.to_value()
+            }
+            "tls-ca-file" => {
+                let settings = self.settings.lock().unwrap();
+                settings
+                    .tls_ca_file
+                    .as_ref()
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .to_value()
+            }
+            "tls-accept-invalid-certs" => {
+                let settings = self.settings.lock().unwrap();
+                settings.tls_accept_invalid_certs.to_value()
+            }
+            "tls-server-name" => {
+                let settings = self.settings.lock().unwrap();
+                settings.tls_server_name.to_value()
+            }
+            "backchannel" => {
+                let settings = self.settings.lock().unwrap();
+                settings.backchannel.to_value()
+            }
+            "reconnect-attempts" => {
+                let settings = self.settings.lock().unwrap();
+                settings.reconnect_attempts.to_value()
+            }
+            "reconnect-backoff" => {
+                let settings = self.settings.lock().unwrap();
+                settings.reconnect_backoff.to_value()
+            }
+            "default-rtsp-version" => {
+                let settings = self.settings.lock().unwrap();
+                settings.default_rtsp_version.to_value()
+            }
             name => unimplemented!("Property '{name}'"),
         }
     }
@@ -431,7 +634,15 @@ impl ElementImpl for RtspSrc {
             )
             .unwrap();
 
-            vec![src_pad_template]
+            let backchannel_pad_template = gst::PadTemplate::new(
+                "backchannel_%u",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Sometimes,
+                &gst::Caps::new_empty_simple("application/x-rtp"),
+            )
+            .unwrap();
+
+            vec![src_pad_template, backchannel_pad_template]
         });
 
         PAD_TEMPLATES.as_ref()
@@ -453,6 +664,10 @@ impl ElementImpl for RtspSrc {
                 //self.async_start().map_err(|_| gst::StateChangeError)?;
                 RUNTIME.spawn(async move { cmd_queue.send(Commands::Play).await });
             }
+            gst::StateChange::PlayingToPaused => {
+                let cmd_queue = self.cmd_queue();
+                RUNTIME.spawn(async move { cmd_queue.send(Commands::Pause).await });
+            }
             _ => {}
         }
 
@@ -497,6 +712,13 @@ impl ElementImpl for RtspSrc {
 
         Ok(ret)
     }
+
+    fn send_event(&self, event: gst::Event) -> bool {
+        match event.view() {
+            gst::EventView::Seek(e) => self.handle_seek(e),
+            _ => self.parent_send_event(event),
+        }
+    }
 }
 
 impl BinImpl for RtspSrc {}
@@ -505,7 +727,7 @@ impl URIHandlerImpl for RtspSrc {
     const URI_TYPE: gst::URIType = gst::URIType::Src;
 
     fn protocols() -> &'static [&'static str] {
-        &["rtsp", "rtspu", "rtspt"]
+        &["rtsp", "rtspu", "rtspt", "rtsps", "rtspsu", "rtspst"]
     }
 
     fn uri(&self) -> Option<String> {
@@ -523,6 +745,57 @@ type RtspStream =
     Pin<Box<dyn Stream<Item = Result<Message<Body>, super::tcp_message::ReadError>> + Send>>;
 type RtspSink = Pin<Box<dyn Sink<Message<Body>, Error = std::io::Error> + Send>>;
 
+fn tls_connector(url: &Url, settings: &Settings) -> Result<Option<TlsConnector>, RtspError> {
+    if !url.scheme().starts_with("rtsps") {
+        return Ok(None);
+    }
+    TlsConnector::new(
+        url,
+        settings.tls_server_name.as_deref(),
+        settings.tls_ca_file.as_deref(),
+        settings.tls_accept_invalid_certs,
+    )
+    .map(Some)
+}
+
+/// Opens the RTSP control connection, over TLS for `rtsps://` URLs
+async fn connect(
+    url: &Url,
+    tls: Option<&TlsConnector>,
+) -> Result<(RtspStream, RtspSink), RtspError> {
+    gst::info!(CAT, "Connecting to {url} ..");
+    let default_port = if tls.is_some() {
+        DEFAULT_RTSPS_PORT
+    } else {
+        DEFAULT_RTSP_PORT
+    };
+    let hostname_port = format!(
+        "{}:{}",
+        url.host_str().unwrap(),
+        url.port().unwrap_or(default_port)
+    );
+
+    let s = TcpStream::connect(hostname_port).await?;
+    let _ = s.set_nodelay(true);
+
+    gst::info!(CAT, "Connected!");
+
+    // Interleaved RTP/RTCP data goes over the same stream, so it is also encrypted
+    if let Some(tls) = tls {
+        let (read, write) = tokio::io::split(tls.connect(s).await?);
+        Ok((
+            Box::pin(super::tcp_message::async_read(read, MAX_MESSAGE_SIZE).fuse()),
+            Box::pin(super::tcp_message::async_write(write)),
+        ))
+    } else {
+        let (read, write) = s.into_split();
+        Ok((
+            Box::pin(super::tcp_message::async_read(read, MAX_MESSAGE_SIZE).fuse()),
+            Box::pin(super::tcp_message::async_write(write)),
+        ))
+    }
+}
+
 impl RtspSrc {
     #[track_caller]
     fn cmd_queue(&self) -> mpsc::Sender<Commands> {
@@ -530,13 +803,33 @@ impl RtspSrc {
     }
 
     fn start(&self) -> Result<(), gst::ErrorMessage> {
-        let Some(url) = self.settings.lock().unwrap().location.clone() else {
-            return Err(gst::error_msg!(
-                gst::ResourceError::Settings,
-                ["No location set"]
-            ));
+        let (mut url, credentials, tls) = {
+            let settings = self.settings.lock().unwrap();
+            let Some(url) = settings.location.clone() else {
+                return Err(gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ["No location set"]
+                ));
+            };
+            let credentials = url_credentials(&url).or_else(|| {
+                settings.user_id.as_ref().map(|user| Credentials {
+                    user: user.clone(),
+                    password: settings.user_pw.clone().unwrap_or_default(),
+                })
+            });
+            let tls = tls_connector(&url, &settings).map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Failed to configure TLS: {err:#?}"]
+                )
+            })?;
+            (url, credentials, tls)
         };
 
+        // Credentials must never be sent as part of the request URI
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+
         gst::info!(CAT, imp = self, "Location: {url}",);
 
         gst::info!(CAT, imp = self, "Starting RTSP connection thread.. ");
@@ -553,13 +846,8 @@ impl RtspSrc {
         }
 
         let join_handle = RUNTIME.spawn(async move {
-            gst::info!(CAT, "Connecting to {url} ..");
-            let hostname_port =
-                format!("{}:{}", url.host_str().unwrap(), url.port().unwrap_or(554));
-
-            // TODO: Add TLS support
-            let s = match TcpStream::connect(hostname_port).await {
-                Ok(s) => s,
+            let (stream, sink) = match connect(&url, tls.as_ref()).await {
+                Ok(v) => v,
                 Err(err) => {
                     gst::element_imp_error!(
                         task_src,
@@ -569,16 +857,8 @@ impl RtspSrc {
                     return;
                 }
             };
-            let _ = s.set_nodelay(true);
-
-            gst::info!(CAT, "Connected!");
-
-            let (read, write) = s.into_split();
-
-            let stream = Box::pin(super::tcp_message::async_read(read, MAX_MESSAGE_SIZE).fuse());
-            let sink = Box::pin(super::tcp_message::async_write(write));
 
-            let mut state = RtspTaskState::new(url, stream, sink);
+            let mut state = RtspTaskState::new(url, credentials, tls, stream, sink);
 
             let task_ret = task_src.rtsp_task(&mut state, rx).await;
             gst::info!(CAT, "Exited rtsp_task");
@@ -614,12 +894,22 @@ impl RtspSrc {
             }
 
             // Post the element error after cleanup
-            if let Err(err) = task_ret {
-                gst::element_imp_error!(
-                    task_src,
-                    gst::CoreError::Failed,
-                    ["RTSP task exited: {err:#?}"]
-                );
+            match task_ret {
+                Err(err @ RtspError::NotAuthorized(_)) => {
+                    gst::element_imp_error!(
+                        task_src,
+                        gst::ResourceError::NotAuthorized,
+                        ["RTSP task exited: {err:#?}"]
+                    );
+                }
+                Err(err) => {
+                    gst::element_imp_error!(
+                        task_src,
+                        gst::CoreError::Failed,
+                        ["RTSP task exited: {err:#?}"]
+                    );
+                }
+                Ok(_) => (),
             }
             gst::info!(CAT, "Cleanup complete");
         });
@@ -655,6 +945,9 @@ impl RtspSrc {
         }
 
         self.command_queue.lock().unwrap().take();
+        self.media_range.lock().unwrap().take();
+        self.last_seek_seqnum.lock().unwrap().take();
+        self.last_position.lock().unwrap().take();
 
         gst::info!(CAT, imp = self, "Stopped");
 
@@ -667,6 +960,16 @@ impl RtspSrc {
         caps: &gst::Caps,
         manager: &RtspManager,
     ) -> Result<gst_app::AppSrc> {
+        let obj = self.obj();
+        // Keep the existing srcpad when the session is set up again after reconnecting
+        if let Some(appsrc) = obj.by_name(&format!("rtp_appsrc_{rtpsession_n}")) {
+            let appsrc = appsrc
+                .downcast::<gst_app::AppSrc>()
+                .expect("rtp_appsrc_* elements are appsrcs");
+            appsrc.set_caps(Some(caps));
+            return Ok(appsrc);
+        }
+
         let callbacks = gst_app::AppSrcCallbacks::builder()
             .enough_data(|appsrc| {
                 gst::warning!(CAT, "appsrc {} is overrunning: enough data!", appsrc.name());
@@ -685,7 +988,6 @@ impl RtspSrc {
             .callbacks(callbacks)
             .is_live(true)
             .build();
-        let obj = self.obj();
         obj.add(&appsrc)?;
         appsrc
             .static_pad("src")
@@ -694,7 +996,31 @@ impl RtspSrc {
         let templ = obj.pad_template("stream_%u").unwrap();
         let ghostpad = gst::GhostPad::builder_from_template(&templ)
             .name(format!("stream_{}", rtpsession_n))
+            .query_function(|pad, parent, query| {
+                RtspSrc::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |imp| imp.src_query(pad.upcast_ref(), query),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                RtspSrc::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |imp| imp.src_event(pad.upcast_ref(), event),
+                )
+            })
             .build();
+        let imp_weak = self.downgrade();
+        ghostpad.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+            let Some(imp) = imp_weak.upgrade() else {
+                return gst::PadProbeReturn::Remove;
+            };
+            if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                imp.update_position(pad, buffer);
+            }
+            gst::PadProbeReturn::Ok
+        });
         gst::info!(CAT, "Adding ghost srcpad {}", ghostpad.name());
         obj.add_pad(&ghostpad)
             .expect("Adding a ghostpad should never fail");
@@ -707,6 +1033,12 @@ impl RtspSrc {
         rtpsession_n: usize,
         manager: &RtspManager,
     ) -> Result<gst_app::AppSrc> {
+        if let Some(appsrc) = self.obj().by_name(&format!("rtcp_appsrc_{rtpsession_n}")) {
+            return Ok(appsrc
+                .downcast::<gst_app::AppSrc>()
+                .expect("rtcp_appsrc_* elements are appsrcs"));
+        }
+
         let appsrc = gst_app::AppSrc::builder()
             .name(format!("rtcp_appsrc_{rtpsession_n}"))
             .format(gst::Format::Time)
@@ -743,6 +1075,14 @@ impl RtspSrc {
             .new_sample(on_rtcp)
             .build();
 
+        if let Some(rtcp_appsink) = self.obj().by_name(&format!("rtcp_appsink_{rtpsession_n}")) {
+            rtcp_appsink
+                .downcast::<gst_app::AppSink>()
+                .expect("rtcp_appsink_* elements are appsinks")
+                .set_callbacks(cbs);
+            return Ok(());
+        }
+
         let rtcp_appsink = gst_app::AppSink::builder()
             .name(format!("rtcp_appsink_{rtpsession_n}"))
             .sync(false)
@@ -757,6 +1097,274 @@ impl RtspSrc {
         Ok(())
     }
 
+    /// Exposes a `backchannel_%u` sink pad that feeds the sending side of the RTP session, and
+    /// hands the resulting RTP packets to `on_rtp` to send them to the server.
+    fn make_backchannel<
+        F: FnMut(&gst_app::AppSink) -> Result<gst::FlowSuccess, gst::FlowError> + Send + 'static,
+    >(
+        &self,
+        rtpsession_n: usize,
+        caps: &gst::Caps,
+        manager: &RtspManager,
+        on_rtp: F,
+    ) -> Result<()> {
+        let cbs = gst_app::app_sink::AppSinkCallbacks::builder()
+            .new_sample(on_rtp)
+            .build();
+
+        let obj = self.obj();
+        // Keep the existing sinkpad when the session is set up again after reconnecting
+        if let Some(rtp_appsink) = obj.by_name(&format!("rtp_appsink_{rtpsession_n}")) {
+            rtp_appsink
+                .downcast::<gst_app::AppSink>()
+                .expect("rtp_appsink_* elements are appsinks")
+                .set_callbacks(cbs);
+            return Ok(());
+        }
+
+        let (rtp_sinkpad, rtp_srcpad) = manager.rtp_send_pads(rtpsession_n).ok_or_else(|| {
+            RtspError::Fatal(format!("Failed to request backchannel pads {rtpsession_n}"))
+        })?;
+
+        // Only the fields that the payloader needs to produce what the server expects
+        let caps = {
+            let s = caps.structure(0).unwrap();
+            let mut filtered = gst::Structure::new_empty("application/x-rtp");
+            for field in [
+                "media",
+                "payload",
+                "clock-rate",
+                "encoding-name",
+                "encoding-params",
+            ] {
+                if let Ok(v) = s.value(field) {
+                    filtered.set_value(field, v.clone());
+                }
+            }
+            gst::Caps::from(filtered)
+        };
+        let capsfilter = gst::ElementFactory::make("capsfilter")
+            .name(format!("backchannel_capsfilter_{rtpsession_n}"))
+            .property("caps", &caps)
+            .build()?;
+
+        let rtp_appsink = gst_app::AppSink::builder()
+            .name(format!("rtp_appsink_{rtpsession_n}"))
+            .sync(false)
+            .async_(false)
+            .callbacks(cbs)
+            .build();
+
+        obj.add_many([&capsfilter, rtp_appsink.upcast_ref()])?;
+        capsfilter.static_pad("src").unwrap().link(&rtp_sinkpad)?;
+        rtp_srcpad.link(&rtp_appsink.static_pad("sink").unwrap())?;
+
+        let templ = obj.pad_template("backchannel_%u").unwrap();
+        let ghostpad = gst::GhostPad::builder_from_template(&templ)
+            .name(format!("backchannel_{}", rtpsession_n))
+            .build();
+        ghostpad.set_target(Some(&capsfilter.static_pad("sink").unwrap()))?;
+        gst::info!(
+            CAT,
+            "Adding backchannel sinkpad {} with {caps:?}",
+            ghostpad.name()
+        );
+        obj.add_pad(&ghostpad)
+            .expect("Adding a ghostpad should never fail");
+        capsfilter.sync_state_with_parent()?;
+        rtp_appsink.sync_state_with_parent()?;
+        Ok(())
+    }
+
+    /// Remembers the stream time of a buffer that is output on `pad`. The jitterbuffer maps the
+    /// `npt-start` of the PLAY response to the stream time, so this is the NPT position.
+    fn update_position(&self, pad: &gst::Pad, buffer: &gst::BufferRef) {
+        let Some(position) = pad
+            .sticky_event::<gst::event::Segment>(0)
+            .and_then(|event| {
+                event
+                    .segment()
+                    .downcast_ref::<gst::ClockTime>()?
+                    .to_stream_time(buffer.pts()?)
+            })
+        else {
+            return;
+        };
+        *self.last_position.lock().unwrap() = Some(position);
+    }
+
+    fn src_query(&self, pad: &gst::Pad, query: &mut gst::QueryRef) -> bool {
+        match query.view_mut() {
+            gst::QueryViewMut::Seeking(q) if q.format() == gst::Format::Time => {
+                let range = *self.media_range.lock().unwrap();
+                match range.filter(sdp::PlayRange::is_seekable) {
+                    Some(range) => q.set(true, gst::ClockTime::ZERO, range.stop),
+                    None => q.set(false, gst::ClockTime::ZERO, gst::ClockTime::NONE),
+                }
+                true
+            }
+            gst::QueryViewMut::Duration(q) if q.format() == gst::Format::Time => {
+                let range = *self.media_range.lock().unwrap();
+                match range.and_then(|r| r.stop) {
+                    Some(stop) => {
+                        q.set(stop);
+                        true
+                    }
+                    None => gst::Pad::query_default(pad, Some(&*self.obj()), query),
+                }
+            }
+            _ => gst::Pad::query_default(pad, Some(&*self.obj()), query),
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, event: gst::Event) -> bool {
+        match event.view() {
+            gst::EventView::Seek(e) => self.handle_seek(e),
+            _ => gst::Pad::event_default(pad, Some(&*self.obj()), event),
+        }
+    }
+
+    fn handle_seek(&self, event: &gst::event::Seek) -> bool {
+        let seqnum = event.seqnum();
+        {
+            // Seeks are sent upstream on every srcpad, but we must only handle them once
+            let mut last_seek_seqnum = self.last_seek_seqnum.lock().unwrap();
+            if *last_seek_seqnum == Some(seqnum) {
+                gst::debug!(CAT, imp = self, "Already handled seek {seqnum:?}");
+                return true;
+            }
+            last_seek_seqnum.replace(seqnum);
+        }
+
+        let Some(media_range) = self
+            .media_range
+            .lock()
+            .unwrap()
+            .filter(sdp::PlayRange::is_seekable)
+        else {
+            gst::warning!(CAT, imp = self, "Media does not advertise a seekable range");
+            return false;
+        };
+
+        let (rate, flags, start_type, start, stop_type, stop) = event.get();
+
+        if rate != 1.0 {
+            gst::error!(CAT, imp = self, "Only seeks with rate 1.0 are supported");
+            return false;
+        }
+
+        if start_type == gst::SeekType::End || stop_type == gst::SeekType::End {
+            gst::error!(CAT, imp = self, "Relative seeks are not supported");
+            return false;
+        }
+
+        let start: Option<gst::ClockTime> = match start.try_into() {
+            Ok(start) => start,
+            Err(_) => {
+                gst::error!(CAT, imp = self, "seek has invalid format");
+                return false;
+            }
+        };
+
+        let stop: Option<gst::ClockTime> = match stop.try_into() {
+            Ok(stop) => stop,
+            Err(_) => {
+                gst::error!(CAT, imp = self, "seek has invalid format");
+                return false;
+            }
+        };
+
+        let range = sdp::PlayRange {
+            start: Some(match start_type {
+                gst::SeekType::Set => start.unwrap_or(gst::ClockTime::ZERO),
+                _ => media_range.start.unwrap_or(gst::ClockTime::ZERO),
+            }),
+            stop: match stop_type {
+                gst::SeekType::Set => stop,
+                _ => None,
+            },
+        };
+
+        let flush = flags.contains(gst::SeekFlags::FLUSH);
+        if flush {
+            flush_appsrcs(self.rtp_appsrcs().iter(), true, seqnum);
+        }
+
+        gst::debug!(CAT, imp = self, "Seeking to {range:?}");
+        *self.last_position.lock().unwrap() = range.start;
+        let cmd_queue = self.cmd_queue();
+        RUNTIME.spawn(async move {
+            cmd_queue
+                .send(Commands::Seek {
+                    range,
+                    flush,
+                    seqnum,
+                })
+                .await
+        });
+
+        true
+    }
+
+    fn rtp_appsrcs(&self) -> Vec<gst_app::AppSrc> {
+        self.obj()
+            .children()
+            .into_iter()
+            .filter(|e| e.name().starts_with("rtp_appsrc_"))
+            .filter_map(|e| e.downcast::<gst_app::AppSrc>().ok())
+            .collect()
+    }
+
+    /// Decides how to answer a request sent by the server, and returns the new location if the
+    /// server redirects us.
+    ///
+    /// REDIRECT is handled right away, even if the server asks for it to take effect at a later
+    /// time with a `Range` header.
+    fn handle_server_request(&self, req: &Request<Body>) -> (StatusCode, Option<Url>) {
+        match req.method() {
+            Method::Redirect => {
+                let location = req
+                    .header(&LOCATION)
+                    .and_then(|l| Url::parse(l.as_str()).ok());
+                match location {
+                    Some(mut location) => {
+                        // Credentials must never be sent as part of the request URI
+                        let _ = location.set_username("");
+                        let _ = location.set_password(None);
+                        (StatusCode::Ok, Some(location))
+                    }
+                    None => {
+                        gst::warning!(CAT, imp = self, "REDIRECT without a valid Location");
+                        (StatusCode::BadRequest, None)
+                    }
+                }
+            }
+            Method::Announce | Method::SetParameter => {
+                self.post_server_request(req);
+                (StatusCode::Ok, None)
+            }
+            // The server checking if we are still alive
+            Method::GetParameter | Method::Options => (StatusCode::Ok, None),
+            _ => (StatusCode::NotImplemented, None),
+        }
+    }
+
+    /// Lets the application handle ANNOUNCE and SET_PARAMETER requests from the server.
+    fn post_server_request(&self, req: &Request<Body>) {
+        let obj = self.obj();
+        let body = String::from_utf8_lossy(req.body().as_ref());
+        let s = gst::Structure::builder("GstRtspSrc2ServerRequest")
+            .field("method", <&str>::from(req.method()))
+            .field_if_some("uri", req.request_uri().map(Url::as_str))
+            .field_if_some(
+                "content-type",
+                req.header(&CONTENT_TYPE).map(|v| v.as_str()),
+            )
+            .field("body", body.as_ref())
+            .build();
+        let _ = obj.post_message(gst::message::Element::builder(s).src(&*obj).build());
+    }
+
     fn post_start(&self, code: &str, text: &str) {
         let obj = self.obj();
         let msg = gst::message::Progress::builder(gst::ProgressType::Start, code, text)
@@ -781,45 +1389,33 @@ impl RtspSrc {
         let _ = obj.post_message(msg);
     }
 
-    async fn rtsp_task(
+    /// Creates the elements and tasks that move data between the server and the RTP manager
+    /// for every stream that was set up, and returns the appsrcs for each interleaved channel.
+    ///
+    /// When reconnecting, the existing elements and pads are reused and `discont` marks the
+    /// first buffer received on the new connection.
+    fn setup_streams(
         &self,
         state: &mut RtspTaskState,
-        mut cmd_rx: mpsc::Receiver<Commands>,
-    ) -> Result<()> {
+        manager: &RtspManager,
+        settings: &Settings,
+        discont: bool,
+    ) -> Result<HashMap<u8, gst_app::AppSrc>> {
         let cmd_tx = self.cmd_queue();
-
-        let settings = { self.settings.lock().unwrap().clone() };
-
-        // OPTIONS
-        state.options().await?;
-
-        // DESCRIBE
-        state.describe().await?;
-
-        let mut session: Option<Session> = None;
-        // SETUP streams (TCP interleaved)
-        state.setup_params = {
-            state
-                .setup(
-                    &mut session,
-                    settings.port_start,
-                    &settings.protocols,
-                    TransportMode::Play,
-                )
-                .await?
-        };
-        let manager = RtspManager::new(std::env::var("USE_RTP2").is_ok_and(|s| s == "1"));
-
-        let obj = self.obj();
-        manager
-            .add_to(obj.upcast_ref::<gst::Bin>())
-            .expect("Adding the manager cannot fail");
-
+        let timeout = settings.timeout;
+        let receive_mtu = settings.receive_mtu;
         let mut tcp_interleave_appsrcs = HashMap::new();
         for (rtpsession_n, p) in state.setup_params.iter_mut().enumerate() {
             let (tx, rx) = mpsc::channel(1);
             let on_rtcp = move |appsink: &_| on_rtcp_udp(appsink, tx.clone());
             match &mut p.transport {
+                RtspTransportInfo::UdpMulticast { .. } if p.backchannel => {
+                    gst::warning!(
+                        CAT,
+                        "Skipping backchannel {rtpsession_n}: multicast is not supported"
+                    );
+                    continue;
+                }
                 RtspTransportInfo::UdpMulticast {
                     dest,
                     port: (rtp_port, rtcp_port),
@@ -874,25 +1470,19 @@ impl RtspSrc {
                         }
                     };
 
-                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, &manager)?;
+                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, manager)?;
                     p.rtp_appsrc = Some(rtp_appsrc.clone());
                     // Spawn RTP udp receive task
                     state.handles.push(RUNTIME.spawn(async move {
-                        udp_rtp_task(
-                            &rtp_socket,
-                            rtp_appsrc,
-                            settings.timeout,
-                            settings.receive_mtu,
-                            None,
-                        )
-                        .await
+                        udp_rtp_task(&rtp_socket, rtp_appsrc, timeout, receive_mtu, None, discont)
+                            .await
                     }));
 
                     // Spawn RTCP udp send/recv task
                     if let Some(rtcp_socket) = rtcp_socket {
                         let rtcp_dest = rtcp_port.and_then(|p| Some(SocketAddr::new(*dest, p)));
-                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, &manager)?;
-                        self.make_rtcp_appsink(rtpsession_n, &manager, on_rtcp)?;
+                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, manager)?;
+                        self.make_rtcp_appsink(rtpsession_n, manager, on_rtcp)?;
                         state.handles.push(RUNTIME.spawn(async move {
                             udp_rtcp_task(&rtcp_socket, rtcp_appsrc, rtcp_dest, true, rx).await
                         }));
@@ -926,24 +1516,43 @@ impl RtspSrc {
                         _ => (None, None),
                     };
 
-                    // Spawn RTP udp receive task
-                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, &manager)?;
-                    p.rtp_appsrc = Some(rtp_appsrc.clone());
-                    state.handles.push(RUNTIME.spawn(async move {
-                        udp_rtp_task(
-                            &rtp_socket,
-                            rtp_appsrc,
-                            settings.timeout,
-                            settings.receive_mtu,
-                            rtp_sender_addr,
-                        )
-                        .await
-                    }));
+                    if p.backchannel {
+                        let Some(rtp_dest) = rtp_sender_addr else {
+                            gst::warning!(
+                                CAT,
+                                "Skipping backchannel {rtpsession_n}: no server port to send to"
+                            );
+                            continue;
+                        };
+                        // Spawn RTP udp send task
+                        let (rtp_tx, rtp_rx) = mpsc::channel(1);
+                        self.make_backchannel(rtpsession_n, &p.caps, manager, move |appsink| {
+                            on_backchannel_udp(appsink, &rtp_tx)
+                        })?;
+                        state.handles.push(RUNTIME.spawn(async move {
+                            udp_rtp_send_task(&rtp_socket, rtp_dest, rtp_rx).await
+                        }));
+                    } else {
+                        // Spawn RTP udp receive task
+                        let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, manager)?;
+                        p.rtp_appsrc = Some(rtp_appsrc.clone());
+                        state.handles.push(RUNTIME.spawn(async move {
+                            udp_rtp_task(
+                                &rtp_socket,
+                                rtp_appsrc,
+                                timeout,
+                                receive_mtu,
+                                rtp_sender_addr,
+                                discont,
+                            )
+                            .await
+                        }));
+                    }
 
                     // Spawn RTCP udp send/recv task
                     if let Some(rtcp_socket) = rtcp_socket {
-                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, &manager)?;
-                        self.make_rtcp_appsink(rtpsession_n, &manager, on_rtcp)?;
+                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, manager)?;
+                        self.make_rtcp_appsink(rtpsession_n, manager, on_rtcp)?;
                         state.handles.push(RUNTIME.spawn(async move {
                             udp_rtcp_task(&rtcp_socket, rtcp_appsrc, rtcp_sender_addr, false, rx)
                                 .await
@@ -953,24 +1562,75 @@ impl RtspSrc {
                 RtspTransportInfo::Tcp {
                     channels: (rtp_channel, rtcp_channel),
                 } => {
-                    let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, &manager)?;
-                    p.rtp_appsrc = Some(rtp_appsrc.clone());
-                    tcp_interleave_appsrcs.insert(*rtp_channel, rtp_appsrc);
+                    if p.backchannel {
+                        let rtp_channel = *rtp_channel;
+                        let cmd_tx = cmd_tx.clone();
+                        self.make_backchannel(rtpsession_n, &p.caps, manager, move |appsink| {
+                            on_backchannel_tcp(appsink, &cmd_tx, rtp_channel)
+                        })?;
+                    } else {
+                        let rtp_appsrc = self.make_rtp_appsrc(rtpsession_n, &p.caps, manager)?;
+                        p.rtp_appsrc = Some(rtp_appsrc.clone());
+                        tcp_interleave_appsrcs.insert(*rtp_channel, rtp_appsrc);
+                    }
 
                     if let Some(rtcp_channel) = rtcp_channel {
                         // RTCP SR
-                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, &manager)?;
+                        let rtcp_appsrc = self.make_rtcp_appsrc(rtpsession_n, manager)?;
                         tcp_interleave_appsrcs.insert(*rtcp_channel, rtcp_appsrc.clone());
                         // RTCP RR
                         let rtcp_channel = *rtcp_channel;
                         let cmd_tx = cmd_tx.clone();
-                        self.make_rtcp_appsink(rtpsession_n, &manager, move |appsink| {
+                        self.make_rtcp_appsink(rtpsession_n, manager, move |appsink| {
                             on_rtcp_tcp(appsink, cmd_tx.clone(), rtcp_channel)
                         })?;
                     }
                 }
             }
         }
+        Ok(tcp_interleave_appsrcs)
+    }
+
+    async fn rtsp_task(
+        &self,
+        state: &mut RtspTaskState,
+        mut cmd_rx: mpsc::Receiver<Commands>,
+    ) -> Result<()> {
+        let settings = { self.settings.lock().unwrap().clone() };
+
+        state.backchannel = settings.backchannel == RtspBackchannel::Onvif;
+        state.version = match settings.default_rtsp_version {
+            RtspVersion::V2_0 => Version::V2_0,
+            _ => Version::V1_0,
+        };
+
+        // OPTIONS
+        state.options().await?;
+
+        // DESCRIBE
+        state.describe().await?;
+
+        let mut session: Option<Session> = None;
+        // SETUP streams (TCP interleaved)
+        state.setup_params = {
+            state
+                .setup(
+                    &mut session,
+                    settings.port_start,
+                    &settings.protocols,
+                    TransportMode::Play,
+                )
+                .await?
+        };
+        *self.media_range.lock().unwrap() = state.seek_range();
+        let manager = RtspManager::new(std::env::var("USE_RTP2").is_ok_and(|s| s == "1"));
+
+        let obj = self.obj();
+        manager
+            .add_to(obj.upcast_ref::<gst::Bin>())
+            .expect("Adding the manager cannot fail");
+
+        let mut tcp_interleave_appsrcs = self.setup_streams(state, &manager, &settings, false)?;
 
         obj.no_more_pads();
 
@@ -1020,7 +1680,19 @@ impl RtspSrc {
             }
         });
 
-        let mut expected_response: Option<(Method, u32)> = None;
+        // cseq -> (method, range of PLAY requests, retried with credentials)
+        let mut expected_responses: BTreeMap<u32, (Method, Option<sdp::PlayRange>, bool)> =
+            BTreeMap::new();
+        // Range to send with the next PLAY, None when resuming after a PAUSE
+        let mut next_range = Some(state.initial_range());
+        let mut playing = false;
+        // Seqnum of the flushing seek that is waiting for its PLAY response
+        let mut pending_flush_stop: Option<gst::Seqnum> = None;
+        let mut keepalive = keepalive_timer(state.keepalive_interval());
+        // Interleaved channels whose next buffer is the first one after reconnecting
+        let mut tcp_discont = HashSet::new();
+        let mut connection_lost = None;
+        let mut redirected = false;
         loop {
             tokio::select! {
                 msg = state.stream.next() => match msg {
@@ -1041,56 +1713,157 @@ impl RtspSrc {
                         let mut buffer = gst::Buffer::from_slice(data.into_body());
                         let bufref = buffer.make_mut();
                         bufref.set_dts(t);
+                        if tcp_discont.remove(&channel_id) {
+                            bufref.set_flags(gst::BufferFlags::DISCONT);
+                        }
                         // TODO: Allow unlinked source pads
-                        if let Err(err) = appsrc.push_buffer(buffer) {
-                            gst::error!(CAT, "Failed to push buffer on pad {} for channel {}", appsrc.name(), channel_id);
-                            return Err(err.into());
+                        match appsrc.push_buffer(buffer) {
+                            Ok(_) => {}
+                            // Data from before a seek, dropped while flushing
+                            Err(gst::FlowError::Flushing) => {
+                                gst::trace!(CAT, "Dropping data on channel {channel_id}: flushing");
+                            }
+                            Err(err) => {
+                                gst::error!(CAT, "Failed to push buffer on pad {} for channel {}", appsrc.name(), channel_id);
+                                return Err(err.into());
+                            }
                         }
                     }
                     Some(Ok(rtsp_types::Message::Request(req))) => {
-                        // TODO: implement incoming GET_PARAMETER requests
                         gst::debug!(CAT, "<-- {req:#?}");
+                        let (status, location) = self.handle_server_request(&req);
+                        // A broken connection is noticed when reading the next message
+                        if let Err(err) = state.respond(&req, status).await {
+                            gst::warning!(CAT, "Failed to respond to {:?}: {err:?}", req.method());
+                        }
+                        let Some(location) = location else {
+                            continue;
+                        };
+                        if let Some(s) = &session {
+                            let _ = state.teardown(s).await;
+                        }
+                        state.tls = tls_connector(&location, &settings)?;
+                        state.url = location;
+                        redirected = true;
+                        connection_lost = Some(format!("Redirected to {}", state.url));
                     }
                     Some(Ok(rtsp_types::Message::Response(rsp))) => {
                         gst::debug!(CAT, "<-- {rsp:#?}");
-                        let Some((expected, cseq)) = &expected_response else {
+                        let cseq = match rsp.typed_header::<CSeq>() {
+                            Ok(Some(cseq)) => Some(*cseq),
+                            _ => expected_responses.keys().next().copied(),
+                        };
+                        let Some((cseq, (expected, range, retried))) =
+                            cseq.and_then(|cseq| expected_responses.remove_entry(&cseq))
+                        else {
                             continue;
                         };
                         let Some(s) = &session else {
                             return Err(RtspError::Fatal(format!("Can't handle {:?} response, no SETUP", expected)).into());
                         };
+                        if state.handle_unauthorized(&rsp, retried)? {
+                            let cseq = match &expected {
+                                Method::Play => state.play(s, range).await?,
+                                Method::Pause => state.pause(s).await?,
+                                Method::Teardown => state.teardown(s).await?,
+                                Method::GetParameter | Method::Options => state.keepalive(s).await?.1,
+                                m => unreachable!("BUG: unexpected response method: {m:?}"),
+                            };
+                            expected_responses.insert(cseq, (expected, range, true));
+                            continue;
+                        }
                         match expected {
                             Method::Play => {
-                                state.play_response(&rsp, *cseq, s).await?;
+                                state.play_response(&rsp, cseq, s).await?;
+                                if let Some(seqnum) = pending_flush_stop.take() {
+                                    flush_appsrcs(state.setup_params.iter().filter_map(|p| p.rtp_appsrc.as_ref()), false, seqnum);
+                                }
                                 self.post_complete("request", "PLAY response received");
                             }
-                            Method::Teardown => state.teardown_response(&rsp, *cseq, s).await?,
+                            Method::Pause => state.pause_response(&rsp, cseq, s).await?,
+                            Method::Teardown => state.teardown_response(&rsp, cseq, s).await?,
+                            m @ (Method::GetParameter | Method::Options) => state.keepalive_response(&rsp, cseq, m, s),
                             m => unreachable!("BUG: unexpected response method: {m:?}"),
                         };
                     }
                     Some(Err(e)) => {
-                        // TODO: reconnect or ignore if UDP sockets are still receiving data
-                        gst::error!(CAT, "I/O error: {e:?}, quitting");
-                        return Err(gst::FlowError::Error.into());
+                        if settings.reconnect_attempts == 0 {
+                            gst::error!(CAT, "I/O error: {e:?}, quitting");
+                            return Err(gst::FlowError::Error.into());
+                        }
+                        connection_lost = Some(format!("I/O error: {e:?}"));
                     }
                     None => {
-                        // TODO: reconnect or ignore if UDP sockets are still receiving data
-                        gst::error!(CAT, "TCP connection EOF, quitting");
-                        return Err(gst::FlowError::Eos.into());
+                        if settings.reconnect_attempts == 0 {
+                            gst::error!(CAT, "TCP connection EOF, quitting");
+                            return Err(gst::FlowError::Eos.into());
+                        }
+                        connection_lost = Some("TCP connection EOF".to_string());
                     }
                 },
+                _ = keepalive.tick() => {
+                    let Some(s) = &session else {
+                        continue;
+                    };
+                    match state.keepalive(s).await {
+                        Ok((method, cseq)) => {
+                            expected_responses.insert(cseq, (method, None, false));
+                        }
+                        Err(err) if settings.reconnect_attempts == 0 => return Err(err.into()),
+                        Err(err) => connection_lost = Some(format!("Keep-alive failed: {err:?}")),
+                    }
+                }
                 Some(cmd) = cmd_rx.recv() => match cmd {
                     Commands::Play => {
                         let Some(s) = &session else {
                             return Err(RtspError::InvalidMessage("Can't PLAY, no SETUP").into());
                         };
+                        if playing {
+                            continue;
+                        }
                         self.post_start("request", "PLAY request sent");
-                        let cseq = state.play(s).await.map_err(|err| {
+                        let range = next_range.take();
+                        let cseq = state.play(s, range).await.map_err(|err| {
                             self.post_cancelled("request", "PLAY request cancelled");
                             err
                         })?;
-                        expected_response = Some((Method::Play, cseq));
+                        expected_responses.insert(cseq, (Method::Play, range, false));
+                        playing = true;
                     },
+                    Commands::Pause => {
+                        let Some(s) = &session else {
+                            return Err(RtspError::InvalidMessage("Can't PAUSE, no SETUP").into());
+                        };
+                        // Live streams keep flowing, there is nothing to resume them from
+                        if !playing || !state.can_pause() {
+                            continue;
+                        }
+                        let cseq = state.pause(s).await?;
+                        expected_responses.insert(cseq, (Method::Pause, None, false));
+                        playing = false;
+                    }
+                    Commands::Seek { range, flush, seqnum } => {
+                        let Some(s) = &session else {
+                            return Err(RtspError::InvalidMessage("Can't seek, no SETUP").into());
+                        };
+                        if !playing {
+                            // Seeking before PLAY, or while paused, just changes where we start
+                            next_range = Some(range);
+                            if flush {
+                                flush_appsrcs(state.setup_params.iter().filter_map(|p| p.rtp_appsrc.as_ref()), false, seqnum);
+                            }
+                            continue;
+                        }
+                        if state.pause_supported {
+                            let cseq = state.pause(s).await?;
+                            expected_responses.insert(cseq, (Method::Pause, None, false));
+                        }
+                        if flush {
+                            pending_flush_stop = Some(seqnum);
+                        }
+                        let cseq = state.play(s, Some(range)).await?;
+                        expected_responses.insert(cseq, (Method::Play, Some(range), false));
+                    }
                     Commands::Teardown(tx) => {
                         gst::info!(CAT, "Received Teardown command");
                         let Some(s) = &session else {
@@ -1103,10 +1876,13 @@ impl RtspSrc {
                         break;
                     }
                     Commands::Data(data) => {
-                        // We currently only send RTCP RR as data messages, this will change when
-                        // we support TCP ONVIF backchannels
-                        state.sink.send(Message::Data(data)).await?;
-                        gst::debug!(CAT, "Sent RTCP RR over TCP");
+                        // RTCP and backchannel RTP packets on their interleaved channels
+                        let channel_id = data.channel_id();
+                        match state.sink.send(Message::Data(data)).await {
+                            Ok(()) => gst::trace!(CAT, "Sent data over TCP on channel {channel_id}"),
+                            Err(err) if settings.reconnect_attempts == 0 => return Err(err.into()),
+                            Err(err) => connection_lost = Some(format!("Failed to send data: {err:?}")),
+                        }
                     }
                 },
                 else => {
@@ -1114,9 +1890,183 @@ impl RtspSrc {
                     break;
                 }
             }
+
+            let Some(reason) = connection_lost.take() else {
+                continue;
+            };
+            let Some((new_session, appsrcs)) = self
+                .reconnect(
+                    state,
+                    &manager,
+                    &settings,
+                    &mut cmd_rx,
+                    &mut playing,
+                    &reason,
+                    std::mem::take(&mut redirected),
+                )
+                .await?
+            else {
+                // Torn down while reconnecting
+                break;
+            };
+            session = Some(new_session);
+            tcp_discont = appsrcs.keys().copied().collect();
+            tcp_interleave_appsrcs = appsrcs;
+            expected_responses.clear();
+            keepalive = keepalive_timer(state.keepalive_interval());
+            // Live streams continue from now, VOD resumes from the last position that was
+            // output
+            let mut range = state.initial_range();
+            if range.start.is_some() {
+                if let Some(position) = *self.last_position.lock().unwrap() {
+                    range.start = Some(position);
+                }
+            }
+            if playing {
+                let s = session.as_ref().unwrap();
+                let cseq = state.play(s, Some(range)).await?;
+                expected_responses.insert(cseq, (Method::Play, Some(range), false));
+            } else {
+                next_range = Some(range);
+            }
         }
         Ok(())
     }
+
+    /// Re-establishes the RTSP session after the connection to the server was lost, retrying
+    /// with an increasing backoff until `reconnect-attempts` is exhausted.
+    ///
+    /// After a REDIRECT, the first attempt is made right away and even if reconnecting is
+    /// disabled.
+    ///
+    /// Returns `None` if the element was shut down while reconnecting.
+    #[allow(clippy::too_many_arguments)]
+    async fn reconnect(
+        &self,
+        state: &mut RtspTaskState,
+        manager: &RtspManager,
+        settings: &Settings,
+        cmd_rx: &mut mpsc::Receiver<Commands>,
+        playing: &mut bool,
+        reason: &str,
+        redirected: bool,
+    ) -> Result<Option<(Session, HashMap<u8, gst_app::AppSrc>)>> {
+        let (max_attempts, mut backoff) = if redirected {
+            gst::info!(CAT, imp = self, "{reason}, reconnecting");
+            let max_attempts = match settings.reconnect_attempts {
+                0 => 1,
+                n => n,
+            };
+            (max_attempts, gst::ClockTime::ZERO)
+        } else {
+            gst::element_imp_warning!(
+                self,
+                gst::ResourceError::Read,
+                ["Lost connection to the server: {reason}, reconnecting"]
+            );
+            (settings.reconnect_attempts, settings.reconnect_backoff)
+        };
+        // The UDP sockets and their tasks are created again with the new session
+        for handle in state.handles.drain(..) {
+            handle.abort();
+        }
+
+        let mut attempt = 0;
+        loop {
+            if max_attempts >= 0 && attempt >= max_attempts {
+                return Err(RtspError::Fatal(format!(
+                    "Failed to reconnect after {attempt} attempts"
+                ))
+                .into());
+            }
+            attempt += 1;
+
+            let sleep = time::sleep(backoff.into());
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    Some(cmd) = cmd_rx.recv() => match cmd {
+                        Commands::Teardown(tx) => {
+                            gst::info!(CAT, "Received Teardown command while reconnecting");
+                            if let Some(tx) = tx {
+                                let _ = tx.send(());
+                            }
+                            return Ok(None);
+                        }
+                        Commands::Play => *playing = true,
+                        Commands::Pause => *playing = false,
+                        Commands::Seek { flush, seqnum, .. } => {
+                            gst::warning!(CAT, "Ignoring seek while reconnecting");
+                            if flush {
+                                flush_appsrcs(self.rtp_appsrcs().iter(), false, seqnum);
+                            }
+                        }
+                        // Backchannel and RTCP data has nowhere to go
+                        Commands::Data(_) => {}
+                    },
+                }
+            }
+
+            gst::info!(CAT, imp = self, "Reconnecting, attempt {attempt}");
+            match time::timeout(
+                settings.timeout.into(),
+                self.try_reconnect(state, manager, settings),
+            )
+            .await
+            {
+                Ok(Ok(ret)) => {
+                    gst::info!(CAT, imp = self, "Reconnected after {attempt} attempts");
+                    return Ok(Some(ret));
+                }
+                Ok(Err(err)) => gst::warning!(CAT, imp = self, "Reconnecting failed: {err:#?}"),
+                Err(_elapsed) => gst::warning!(CAT, imp = self, "Reconnecting timed out"),
+            }
+            backoff = (backoff * 2)
+                .max(settings.reconnect_backoff)
+                .min(MAX_RECONNECT_BACKOFF);
+        }
+    }
+
+    async fn try_reconnect(
+        &self,
+        state: &mut RtspTaskState,
+        manager: &RtspManager,
+        settings: &Settings,
+    ) -> Result<(Session, HashMap<u8, gst_app::AppSrc>)> {
+        let (stream, sink) = connect(&state.url, state.tls.as_ref()).await?;
+        state.stream = stream;
+        state.sink = sink;
+
+        state.options().await?;
+        state.describe().await?;
+
+        let mut session: Option<Session> = None;
+        let setup_params = state
+            .setup(
+                &mut session,
+                settings.port_start,
+                &settings.protocols,
+                TransportMode::Play,
+            )
+            .await?;
+        // The existing srcpads can only be continued if the server offers the same streams
+        if setup_params.len() != state.setup_params.len() {
+            return Err(RtspError::Fatal(format!(
+                "Number of streams changed from {} to {} after reconnecting",
+                state.setup_params.len(),
+                setup_params.len()
+            ))
+            .into());
+        }
+        state.setup_params = setup_params;
+        *self.media_range.lock().unwrap() = state.seek_range();
+        let Some(session) = session else {
+            return Err(RtspError::InvalidMessage("No session after SETUP").into());
+        };
+        let appsrcs = self.setup_streams(state, manager, settings, true)?;
+        Ok((session, appsrcs))
+    }
 }
 
 struct RtspManager {
@@ -1184,6 +2134,25 @@ impl RtspManager {
         self.recv.request_pad_simple(&name)
     }
 
+    /// Returns the sink pad for RTP to send, and the src pad with the resulting RTP packets
+    fn rtp_send_pads(&self, rtpsession: usize) -> Option<(gst::Pad, gst::Pad)> {
+        let (sink_name, src_name) = if self.using_rtp2 {
+            (
+                format!("rtp_sink_{}", rtpsession),
+                format!("rtp_src_{}", rtpsession),
+            )
+        } else {
+            (
+                format!("send_rtp_sink_{}", rtpsession),
+                format!("send_rtp_src_{}", rtpsession),
+            )
+        };
+        gst::info!(CAT, "requesting {sink_name} for sending RTP");
+        let sinkpad = self.send.request_pad_simple(&sink_name)?;
+        let srcpad = self.send.static_pad(&src_name)?;
+        Some((sinkpad, srcpad))
+    }
+
     fn rtcp_send_srcpad(&self, rtpsession: usize) -> Option<gst::Pad> {
         let name = if self.using_rtp2 {
             format!("rtcp_src_{}", rtpsession)
@@ -1214,6 +2183,17 @@ struct RtspTaskState {
     content_base_or_location: Option<String>,
     aggregate_control: Option<Url>,
     sdp: Option<sdp_types::Session>,
+    media_range: Option<sdp::PlayRange>,
+    pause_supported: bool,
+    get_parameter_supported: bool,
+    // RTSP 2.0 Media-Properties, assumed if the server doesn't say otherwise
+    random_access: bool,
+    backchannel: bool,
+    // Seconds after which the server expires the session without keep-alive
+    session_timeout: Option<u64>,
+    credentials: Option<Credentials>,
+    auth: Option<Authenticator>,
+    tls: Option<TlsConnector>,
 
     stream:
         Pin<Box<dyn Stream<Item = Result<Message<Body>, super::tcp_message::ReadError>> + Send>>,
@@ -1228,10 +2208,18 @@ struct RtspSetupParams {
     transport: RtspTransportInfo,
     rtp_appsrc: Option<gst_app::AppSrc>,
     caps: gst::Caps,
+    // Media that we send to the server
+    backchannel: bool,
 }
 
 impl RtspTaskState {
-    fn new(url: Url, stream: RtspStream, sink: RtspSink) -> Self {
+    fn new(
+        url: Url,
+        credentials: Option<Credentials>,
+        tls: Option<TlsConnector>,
+        stream: RtspStream,
+        sink: RtspSink,
+    ) -> Self {
         RtspTaskState {
             cseq: 0u32,
             url,
@@ -1239,6 +2227,15 @@ impl RtspTaskState {
             content_base_or_location: None,
             aggregate_control: None,
             sdp: None,
+            media_range: None,
+            pause_supported: false,
+            get_parameter_supported: false,
+            random_access: true,
+            backchannel: false,
+            session_timeout: None,
+            credentials,
+            auth: None,
+            tls,
             stream,
             sink,
             setup_params: Vec::new(),
@@ -1298,26 +2295,122 @@ impl RtspTaskState {
         Ok(())
     }
 
-    async fn options(&mut self) -> Result<(), RtspError> {
-        self.cseq += 1;
-        let req = Request::builder(Method::Options, self.version)
-            .typed_header::<CSeq>(&self.cseq.into())
-            .request_uri(self.url.clone())
-            .header(USER_AGENT, DEFAULT_USER_AGENT)
-            .build(Body::default());
+    fn add_authorization(&mut self, req: &mut Request<Body>) {
+        let Some(auth) = self.auth.as_mut() else {
+            return;
+        };
+        let method = <&str>::from(req.method());
+        let uri = req.request_uri().map(Url::as_str).unwrap_or("*");
+        let value = auth.authorization(method, uri);
+        req.insert_header(AUTHORIZATION, value);
+    }
+
+    /// Returns `true` if the request that caused `rsp` should be retried with the updated
+    /// credentials, and an error if the server rejected our credentials.
+    fn handle_unauthorized(
+        &mut self,
+        rsp: &Response<Body>,
+        retries: u32,
+    ) -> Result<bool, RtspError> {
+        if rsp.status() != StatusCode::Unauthorized {
+            return Ok(false);
+        }
+        let Some(credentials) = self.credentials.clone() else {
+            return Err(RtspError::NotAuthorized(
+                "Server requires authentication, but no credentials were provided".to_string(),
+            ));
+        };
+        let Some(challenge) = rsp.header(&WWW_AUTHENTICATE) else {
+            return Err(RtspError::InvalidMessage(
+                "401 response does not contain a WWW-Authenticate header",
+            ));
+        };
+        let Some(auth) = Authenticator::new(credentials, challenge.as_str()) else {
+            return Err(RtspError::Fatal(format!(
+                "No supported authentication scheme in challenge: {}",
+                challenge.as_str()
+            )));
+        };
+        // A new challenge after we already sent credentials means that the credentials were
+        // wrong, unless the server tells us that only the nonce has expired. Only retry once
+        // per nonce, and a limited number of times overall, so that a server that keeps
+        // reporting stale nonces can't make us retry forever.
+        if retries > 0 {
+            let same_nonce = auth.nonce().is_some()
+                && auth.nonce() == self.auth.as_ref().and_then(Authenticator::nonce);
+            if !auth.is_stale() || same_nonce || retries >= MAX_AUTH_RETRIES {
+                return Err(RtspError::NotAuthorized(format!(
+                    "Authentication failed: {}",
+                    rsp.reason_phrase()
+                )));
+            }
+        }
+        gst::debug!(CAT, "Authenticating with {auth:?}");
+        self.auth = Some(auth);
+        Ok(true)
+    }
 
+    async fn send_request(&mut self, mut req: Request<Body>) -> Result<(), RtspError> {
+        self.add_authorization(&mut req);
         gst::debug!(CAT, "-->> {req:#?}");
         self.sink.send(req.into()).await?;
+        Ok(())
+    }
 
-        let rsp = match self.stream.next().await {
-            Some(Ok(rtsp_types::Message::Response(rsp))) => Ok(rsp),
-            Some(Ok(m)) => Err(RtspError::UnexpectedMessage("OPTIONS response", m)),
-            Some(Err(e)) => Err(e.into()),
-            None => Err(
-                std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "options response").into(),
-            ),
-        }?;
-        gst::debug!(CAT, "<<-- {rsp:#?}");
+    /// Sends a request and waits for its response, retrying with authentication if the server
+    /// challenges us.
+    async fn request_response(
+        &mut self,
+        mut req: Request<Body>,
+        rsp_name: &'static str,
+    ) -> Result<Response<Body>, RtspError> {
+        let mut retries = 0;
+        loop {
+            self.send_request(req.clone()).await?;
+
+            let rsp = match self.stream.next().await {
+                Some(Ok(rtsp_types::Message::Response(rsp))) => Ok(rsp),
+                Some(Ok(m)) => Err(RtspError::UnexpectedMessage(rsp_name, m)),
+                Some(Err(e)) => Err(e.into()),
+                None => {
+                    Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, rsp_name).into())
+                }
+            }?;
+
+            if !self.handle_unauthorized(&rsp, retries)? {
+                return Ok(rsp);
+            }
+            retries += 1;
+            self.cseq += 1;
+            req.insert_typed_header::<CSeq>(&self.cseq.into());
+        }
+    }
+
+    async fn options(&mut self) -> Result<(), RtspError> {
+        let rsp = loop {
+            self.cseq += 1;
+            let req = Request::builder(Method::Options, self.version)
+                .typed_header::<CSeq>(&self.cseq.into())
+                .request_uri(self.url.clone())
+                .header(USER_AGENT, DEFAULT_USER_AGENT)
+                .build(Body::default());
+
+            let rsp = self.request_response(req, "OPTIONS response").await?;
+            gst::debug!(CAT, "<<-- {rsp:#?}");
+            // Servers answer with the highest version they support, usually with a 505 RTSP
+            // Version Not Supported error
+            if self.version == Version::V2_0 && rsp.version() != Version::V2_0 {
+                gst::info!(
+                    CAT,
+                    "Server doesn't support RTSP 2.0, falling back to RTSP 1.0"
+                );
+                self.version = Version::V1_0;
+                if rsp.status() != StatusCode::Ok {
+                    continue;
+                }
+            }
+            break rsp;
+        };
         Self::check_response(&rsp, self.cseq, Method::Options, None)?;
 
         let Ok(Some(methods)) = rsp.typed_header::<Public>() else {
@@ -1325,6 +2418,8 @@ impl RtspTaskState {
                 "OPTIONS response does not contain a valid Public header",
             ));
         };
+        self.pause_supported = methods.contains(&Method::Pause);
+        self.get_parameter_supported = methods.contains(&Method::GetParameter);
 
         let needed = [
             Method::Describe,
@@ -1349,28 +2444,45 @@ impl RtspTaskState {
         }
     }
 
+    /// Adds the ONVIF `Require` header to DESCRIBE, SETUP and PLAY requests if we want
+    /// a backchannel.
+    fn add_backchannel_require(&self, req: &mut Request<Body>) {
+        if self.backchannel {
+            req.insert_header(REQUIRE, ONVIF_BACKCHANNEL_REQUIRE);
+        }
+    }
+
     async fn describe(&mut self) -> Result<(), RtspError> {
         self.cseq += 1;
-        let req = Request::builder(Method::Describe, self.version)
+        let mut req = Request::builder(Method::Describe, self.version)
             .typed_header::<CSeq>(&self.cseq.into())
             .header(USER_AGENT, DEFAULT_USER_AGENT)
             .header(ACCEPT, "application/sdp")
             .request_uri(self.url.clone())
             .build(Body::default());
-
-        gst::debug!(CAT, "-->> {req:#?}");
-        self.sink.send(req.into()).await?;
-
-        let rsp = match self.stream.next().await {
-            Some(Ok(rtsp_types::Message::Response(rsp))) => Ok(rsp),
-            Some(Ok(m)) => Err(RtspError::UnexpectedMessage("DESCRIBE response", m)),
-            Some(Err(e)) => Err(e.into()),
-            None => Err(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "describe response",
-            )
-            .into()),
-        }?;
+        let plain_req = req.clone();
+        self.add_backchannel_require(&mut req);
+
+        let mut rsp = self.request_response(req, "DESCRIBE response").await?;
+        // Servers that don't know about the backchannel reply with 551 Option Not Supported,
+        // listing the options they don't support in an Unsupported header. Other errors are
+        // not caused by the Require header, and are reported as is.
+        if self.backchannel
+            && rsp.status() != StatusCode::Ok
+            && (rsp.status() == StatusCode::OptionNotSupported
+                || rsp.header(&UNSUPPORTED).is_some())
+        {
+            gst::warning!(
+                CAT,
+                "Server does not support the ONVIF backchannel ({}), continuing without it",
+                rsp.reason_phrase()
+            );
+            self.backchannel = false;
+            self.cseq += 1;
+            let mut req = plain_req;
+            req.insert_typed_header::<CSeq>(&self.cseq.into());
+            rsp = self.request_response(req, "DESCRIBE response").await?;
+        }
         gst::debug!(
             CAT,
             "<<-- Response {:#?}",
@@ -1384,10 +2496,22 @@ impl RtspTaskState {
             .map(|v| v.to_string());
 
         gst::info!(CAT, "{}", std::str::from_utf8(rsp.body()).unwrap());
-        // TODO: read range attribute from SDP for VOD use-cases
         let sdp = sdp_types::Session::parse(rsp.body())?;
         gst::debug!(CAT, "{sdp:#?}");
 
+        // The session-level range applies to all medias, otherwise use the first media range
+        self.media_range = sdp
+            .get_first_attribute_value("range")
+            .ok()
+            .flatten()
+            .or_else(|| {
+                sdp.medias
+                    .iter()
+                    .find_map(|m| m.get_first_attribute_value("range").ok().flatten())
+            })
+            .and_then(sdp::parse_npt_range);
+        gst::debug!(CAT, "Media range: {:?}", self.media_range);
+
         self.sdp.replace(sdp);
         Ok(())
     }
@@ -1446,7 +2570,8 @@ impl RtspTaskState {
         protocols: &[RtspProtocol],
         mode: TransportMode,
     ) -> Result<Vec<RtspSetupParams>, RtspError> {
-        let sdp = self.sdp.as_ref().expect("Must have SDP by now");
+        // Cloned because we need to send requests while iterating over the medias
+        let sdp = self.sdp.clone().expect("Must have SDP by now");
         let base = self
             .content_base_or_location
             .as_ref()
@@ -1460,7 +2585,6 @@ impl RtspTaskState {
             .and_then(|v| sdp::parse_control_path(v, &base));
         let mut b = gst::Structure::builder("application/x-rtp");
 
-        // TODO: parse range for VOD
         let skip_attrs = ["control", "range"];
         for sdp_types::Attribute { attribute, value } in &sdp.attributes {
             if skip_attrs.contains(&attribute.as_str()) {
@@ -1478,6 +2602,9 @@ impl RtspTaskState {
             .map(|c| c.connection_address.as_str())
             .filter(|c| !c.is_empty())
             .unwrap_or_else(|| base.host_str().unwrap());
+        // Lets an RTSP 2.0 server know that all our SETUP requests belong to the same session,
+        // even before it gave us one
+        let pipeline_id = rand::random::<u32>();
         let mut port_next = port_start;
         let mut stream_num = 0;
         let mut setup_params: Vec<RtspSetupParams> = Vec::new();
@@ -1503,6 +2630,13 @@ impl RtspTaskState {
                 continue;
             };
 
+            // ONVIF describes the backchannel from the client's point of view
+            let backchannel = m.attributes.iter().any(|a| a.attribute == "sendonly");
+            if backchannel && !self.backchannel {
+                gst::info!(CAT, "Ignoring sendonly media {} {}", m.media, m.fmt);
+                continue;
+            }
+
             // RTP caps
             let Ok(pt) = m.fmt.parse::<u8>() else {
                 gst::error!(CAT, "Could not parse pt: {}, ignoring media", m.fmt);
@@ -1599,31 +2733,32 @@ impl RtspTaskState {
             } else {
                 req
             };
-            let req = req.build(Body::default());
-            let cseq = self.cseq;
-
-            gst::debug!(CAT, "-->> {req:#?}");
-            self.sink.send(req.into()).await?;
+            let req = if self.version == Version::V2_0 {
+                req.header(ACCEPT_RANGES, "npt")
+                    .header(PIPELINED_REQUESTS, pipeline_id.to_string())
+            } else {
+                req
+            };
+            let mut req = req.build(Body::default());
+            self.add_backchannel_require(&mut req);
 
-            // RTSP 2 supports pipelining of SETUP requests, so this ping-pong would have to be
-            // reworked if we want to support it.
-            let rsp = match self.stream.next().await {
-                Some(Ok(rtsp_types::Message::Response(rsp))) => Ok(rsp),
-                Some(Ok(m)) => Err(RtspError::UnexpectedMessage("SETUP response", m)),
-                Some(Err(e)) => Err(e.into()),
-                None => Err(std::io::Error::new(
-                    std::io::ErrorKind::UnexpectedEof,
-                    "setup response",
-                )
-                .into()),
-            }?;
+            // The requests are tagged for RTSP 2.0 pipelining, but we still wait for each
+            // response before sending the next request.
+            let rsp = self.request_response(req, "SETUP response").await?;
             gst::debug!(CAT, "<<-- {rsp:#?}");
-            Self::check_response(&rsp, cseq, Method::Setup, session.as_ref())?;
+            Self::check_response(&rsp, self.cseq, Method::Setup, session.as_ref())?;
             let new_session = rsp
                 .typed_header::<Session>()?
                 .ok_or(RtspError::InvalidMessage("No session in SETUP response"))?;
+            if new_session.1.is_some() {
+                self.session_timeout = new_session.1;
+            }
             // Manually strip timeout field: https://github.com/sdroege/rtsp-types/issues/24
             session.replace(Session(new_session.0, None));
+            if let Some(props) = rsp.header(&MEDIA_PROPERTIES) {
+                gst::debug!(CAT, "Media-Properties: {}", props.as_str());
+                self.random_access = sdp::media_properties_random_access(props.as_str());
+            }
             let mut parsed_transport = if let Some(transports) = rsp.typed_header::<Transports>()? {
                 Self::parse_setup_transports(&transports, &mut s, &protocols, &mode)
             } else {
@@ -1695,24 +2830,68 @@ impl RtspTaskState {
                 transport: parsed_transport,
                 rtp_appsrc: None,
                 caps,
+                backchannel,
             });
         }
         Ok(setup_params)
     }
 
-    async fn play(&mut self, session: &Session) -> Result<u32, RtspError> {
+    /// The range to request with the first PLAY: from the start for recorded media, and from
+    /// `now` for live media.
+    fn initial_range(&self) -> sdp::PlayRange {
+        match self.media_range {
+            Some(range) if range.is_seekable() => sdp::PlayRange {
+                start: Some(range.start.unwrap_or(gst::ClockTime::ZERO)),
+                stop: None,
+            },
+            _ => sdp::PlayRange {
+                start: None,
+                stop: None,
+            },
+        }
+    }
+
+    /// The media range that the application can seek in. RTSP 2.0 servers can forbid seeking
+    /// in media that has a range, in which case the returned range is not seekable.
+    fn seek_range(&self) -> Option<sdp::PlayRange> {
+        self.media_range.map(|range| {
+            if self.random_access {
+                range
+            } else {
+                sdp::PlayRange {
+                    start: None,
+                    ..range
+                }
+            }
+        })
+    }
+
+    /// Recorded media can be paused and resumed at the same position.
+    fn can_pause(&self) -> bool {
+        self.pause_supported && self.media_range.is_some_and(|r| r.is_seekable())
+    }
+
+    /// Sends a PLAY request. Without a `range`, playback resumes from where it was paused.
+    async fn play(
+        &mut self,
+        session: &Session,
+        range: Option<sdp::PlayRange>,
+    ) -> Result<u32, RtspError> {
         self.cseq += 1;
         let request_uri = self.aggregate_control.as_ref().unwrap_or(&self.url).clone();
-        let req = Request::builder(Method::Play, self.version)
+        let mut req = Request::builder(Method::Play, self.version)
             .typed_header::<CSeq>(&self.cseq.into())
-            .typed_header::<Range>(&Range::Npt(NptRange::From(NptTime::Now)))
             .header(USER_AGENT, DEFAULT_USER_AGENT)
             .request_uri(request_uri)
             .typed_header::<Session>(session);
 
-        let req = req.build(Body::default());
-        gst::debug!(CAT, "-->> {req:#?}");
-        self.sink.send(req.into()).await?;
+        if let Some(range) = range {
+            req = req.header(RANGE, range.to_header_value());
+        }
+
+        let mut req = req.build(Body::default());
+        self.add_backchannel_require(&mut req);
+        self.send_request(req).await?;
         Ok(self.cseq)
     }
 
@@ -1723,30 +2902,85 @@ impl RtspTaskState {
         session: &Session,
     ) -> Result<(), RtspError> {
         Self::check_response(rsp, cseq, Method::Play, Some(session))?;
-        if let Some(RtpInfos::V1(rtpinfos)) = rsp.typed_header::<RtpInfos>()? {
-            for rtpinfo in rtpinfos {
-                for params in self.setup_params.iter_mut() {
-                    if params.control_url == rtpinfo.uri {
-                        let mut changed = false;
-                        let mut caps = params.rtp_appsrc.as_ref().unwrap().caps().unwrap();
-                        let capsref = caps.make_mut();
-                        if let Some(v) = rtpinfo.seq {
-                            capsref.set("seqnum-base", v as u32);
-                            changed = true;
-                        }
-                        if let Some(v) = rtpinfo.rtptime {
-                            capsref.set("clock-base", v);
-                            changed = true;
-                        }
-                        if changed {
-                            params.rtp_appsrc.as_ref().unwrap().set_caps(Some(&caps));
-                        }
-                    }
-                }
+        let range = rsp
+            .header(&RANGE)
+            .and_then(|v| sdp::parse_npt_range(v.as_str()));
+        gst::debug!(CAT, "PLAY range: {range:?}");
+        // (uri, seq, rtptime)
+        let rtpinfos: Vec<(Url, Option<u16>, Option<u32>)> = match rsp.typed_header::<RtpInfos>()? {
+            Some(RtpInfos::V1(rtpinfos)) => rtpinfos
+                .into_iter()
+                .map(|i| (i.uri, i.seq, i.rtptime))
+                .collect(),
+            // RTSP 2.0 lists every SSRC of the stream, and we only receive one
+            Some(RtpInfos::V2(rtpinfos)) => rtpinfos
+                .into_iter()
+                .filter_map(|i| {
+                    let ssrc_info = i.ssrc_infos.first()?;
+                    Some((i.uri, ssrc_info.seq, ssrc_info.rtptime))
+                })
+                .collect(),
+            _ => {
+                gst::warning!(CAT, "No RTP-Info header in PLAY response");
+                Vec::new()
             }
-        } else {
-            gst::warning!(CAT, "No RTPInfos V1 header in PLAY response");
         };
+        for params in self.setup_params.iter_mut() {
+            let Some(appsrc) = params.rtp_appsrc.as_ref() else {
+                continue;
+            };
+            let mut caps = appsrc.caps().unwrap();
+            let capsref = caps.make_mut();
+            let mut changed = false;
+            for (_, seq, rtptime) in rtpinfos.iter().filter(|i| params.control_url == i.0) {
+                if let Some(v) = seq {
+                    capsref.set("seqnum-base", *v as u32);
+                    changed = true;
+                }
+                if let Some(v) = rtptime {
+                    capsref.set("clock-base", *v);
+                    changed = true;
+                }
+            }
+            // Used by the jitterbuffer to timestamp the new segment
+            if let Some(range) = range {
+                if let Some(start) = range.start {
+                    capsref.set("npt-start", start.nseconds());
+                    changed = true;
+                }
+                if let Some(stop) = range.stop {
+                    capsref.set("npt-stop", stop.nseconds());
+                    changed = true;
+                }
+            }
+            if changed {
+                appsrc.set_caps(Some(&caps));
+            }
+        }
+        Ok(())
+    }
+
+    async fn pause(&mut self, session: &Session) -> Result<u32, RtspError> {
+        self.cseq += 1;
+        let request_uri = self.aggregate_control.as_ref().unwrap_or(&self.url).clone();
+        let req = Request::builder(Method::Pause, self.version)
+            .typed_header::<CSeq>(&self.cseq.into())
+            .header(USER_AGENT, DEFAULT_USER_AGENT)
+            .request_uri(request_uri)
+            .typed_header::<Session>(session);
+
+        let req = req.build(Body::default());
+        self.send_request(req).await?;
+        Ok(self.cseq)
+    }
+
+    async fn pause_response(
+        &mut self,
+        rsp: &Response<Body>,
+        cseq: u32,
+        session: &Session,
+    ) -> Result<(), RtspError> {
+        Self::check_response(rsp, cseq, Method::Pause, Some(session))?;
         Ok(())
     }
 
@@ -1760,8 +2994,7 @@ impl RtspTaskState {
             .typed_header::<Session>(session);
 
         let req = req.build(Body::default());
-        gst::debug!(CAT, "-->> {req:#?}");
-        self.sink.send(req.into()).await?;
+        self.send_request(req).await?;
         Ok(self.cseq)
     }
 
@@ -1774,6 +3007,75 @@ impl RtspTaskState {
         Self::check_response(rsp, cseq, Method::Teardown, Some(session))?;
         Ok(())
     }
+
+    /// Answers a request sent by the server.
+    async fn respond(&mut self, req: &Request<Body>, status: StatusCode) -> Result<(), RtspError> {
+        let mut rsp =
+            Response::builder(self.version, status).header(USER_AGENT, DEFAULT_USER_AGENT);
+        if let Ok(Some(cseq)) = req.typed_header::<CSeq>() {
+            rsp = rsp.typed_header::<CSeq>(&cseq);
+        }
+        if let Ok(Some(session)) = req.typed_header::<Session>() {
+            rsp = rsp.typed_header::<Session>(&session);
+        }
+        let rsp = rsp.build(Body::default());
+        gst::debug!(CAT, "-->> {rsp:#?}");
+        self.sink.send(rsp.into()).await?;
+        Ok(())
+    }
+
+    /// Interval at which to refresh the session so that it is sent before the server expires it
+    fn keepalive_interval(&self) -> Duration {
+        let timeout = self.session_timeout.unwrap_or(DEFAULT_SESSION_TIMEOUT);
+        // Same margin as the C rtspsrc
+        let secs = if timeout > 20 {
+            timeout - 5
+        } else {
+            timeout / 2
+        };
+        Duration::from_secs(secs.max(1))
+    }
+
+    /// Refreshes the session with an empty GET_PARAMETER, or an OPTIONS if the server doesn't
+    /// support GET_PARAMETER.
+    async fn keepalive(&mut self, session: &Session) -> Result<(Method, u32), RtspError> {
+        let method = if self.get_parameter_supported {
+            Method::GetParameter
+        } else {
+            Method::Options
+        };
+        self.cseq += 1;
+        let request_uri = self.aggregate_control.as_ref().unwrap_or(&self.url).clone();
+        let req = Request::builder(method.clone(), self.version)
+            .typed_header::<CSeq>(&self.cseq.into())
+            .header(USER_AGENT, DEFAULT_USER_AGENT)
+            .request_uri(request_uri)
+            .typed_header::<Session>(session);
+
+        let req = req.build(Body::default());
+        self.send_request(req).await?;
+        Ok((method, self.cseq))
+    }
+
+    fn keepalive_response(
+        &mut self,
+        rsp: &Response<Body>,
+        cseq: u32,
+        method: Method,
+        session: &Session,
+    ) {
+        // Servers are not required to reply to keep-alives in any particular way, the session
+        // was refreshed either way
+        if let Err(err) = Self::check_response(rsp, cseq, method, Some(session)) {
+            gst::warning!(CAT, "Keep-alive failed: {err:?}");
+        }
+    }
+}
+
+fn keepalive_timer(period: Duration) -> time::Interval {
+    let mut interval = time::interval_at(time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    interval
 }
 
 fn bind_port(port: u16, is_ipv4: bool) -> Result<UdpSocket, std::io::Error> {
@@ -1828,6 +3130,67 @@ async fn bind_start_port(port: u16, is_ipv4: bool) -> (UdpSocket, u16) {
     }
 }
 
+/// Flushes the RTP appsrcs and everything downstream of them. Flush events sent upstream into
+/// the appsrc stop its streaming task and drop the queued buffers, and on flush-stop the task is
+/// restarted, so downstream must stop flushing before that.
+fn flush_appsrcs<'a>(
+    appsrcs: impl Iterator<Item = &'a gst_app::AppSrc>,
+    start: bool,
+    seqnum: gst::Seqnum,
+) {
+    let event = if start {
+        gst::event::FlushStart::builder().seqnum(seqnum).build()
+    } else {
+        gst::event::FlushStop::builder(true).seqnum(seqnum).build()
+    };
+    for appsrc in appsrcs {
+        gst::debug!(CAT, "Sending {event:?} from {}", appsrc.name());
+        let pad = appsrc.static_pad("src").unwrap();
+        let _ = pad.push_event(event.clone());
+        let _ = pad.send_event(event.clone());
+    }
+}
+
+fn on_backchannel_udp(
+    appsink: &gst_app::AppSink,
+    tx: &mpsc::Sender<MappedBuffer<Readable>>,
+) -> Result<gst::FlowSuccess, gst::FlowError> {
+    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+    let Some(buffer) = sample.buffer_owned() else {
+        return Ok(gst::FlowSuccess::Ok);
+    };
+    let map = buffer.into_mapped_buffer_readable().map_err(|err| {
+        gst::error!(CAT, "Failed to map buffer: {err:?}");
+        gst::FlowError::Error
+    })?;
+    // Block instead of dropping, RTP packets are sent at the rate they are produced
+    match tx.blocking_send(map) {
+        Ok(_) => Ok(gst::FlowSuccess::Ok),
+        Err(_) => Err(gst::FlowError::Eos),
+    }
+}
+
+fn on_backchannel_tcp(
+    appsink: &gst_app::AppSink,
+    cmd_tx: &mpsc::Sender<Commands>,
+    rtp_channel: u8,
+) -> Result<gst::FlowSuccess, gst::FlowError> {
+    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+    let Some(buffer) = sample.buffer_owned() else {
+        return Ok(gst::FlowSuccess::Ok);
+    };
+    let map = buffer.into_mapped_buffer_readable().map_err(|err| {
+        gst::error!(CAT, "Failed to map buffer: {err:?}");
+        gst::FlowError::Error
+    })?;
+    let data = rtsp_types::Data::new(rtp_channel, Body::mapped(map));
+    // Sent in order through the command queue, unlike RTCP which can be sent from a new task
+    match cmd_tx.blocking_send(Commands::Data(data)) {
+        Ok(_) => Ok(gst::FlowSuccess::Ok),
+        Err(_) => Err(gst::FlowError::Eos),
+    }
+}
+
 fn on_rtcp_udp(
     appsink: &gst_app::AppSink,
     tx: mpsc::Sender<MappedBuffer<Readable>>,
@@ -1888,6 +3251,7 @@ async fn udp_rtp_task(
     timeout: gst::ClockTime,
     receive_mtu: u32,
     sender_addr: Option<SocketAddr>,
+    mut discont: bool,
 ) {
     let t = Duration::from_secs(timeout.into());
     let sender_addr = match sender_addr {
@@ -1965,16 +3329,25 @@ async fn udp_rtp_task(
                 let bufref = buffer.make_mut();
                 bufref.set_size(len);
                 bufref.set_dts(t);
+                if discont {
+                    bufref.set_flags(gst::BufferFlags::DISCONT);
+                    discont = false;
+                }
                 gst_net::NetAddressMeta::add(bufref, &gio_addr);
                 gst::trace!(CAT, "received RTP packet from {addr:?}");
-                if let Err(err) = appsrc.push_buffer(buffer) {
-                    break format!("UDP buffer push failed: {err:?}");
+                match appsrc.push_buffer(buffer) {
+                    Ok(_) => {}
+                    // Data from before a seek, dropped while flushing
+                    Err(gst::FlowError::Flushing) => {
+                        gst::trace!(CAT, "Dropping RTP packet: flushing");
+                    }
+                    Err(err) => break format!("UDP buffer push failed: {err:?}"),
                 }
             }
-            Ok(Err(_elapsed)) => {
-                break format!("No data after {} seconds, exiting", timeout.seconds())
-            }
-            Err(err) => break format!("UDP socket was closed: {err:?}"),
+            Ok(Err(err)) => break format!("UDP socket was closed: {err:?}"),
+            // The server doesn't send data while we're paused
+            Err(_elapsed) if appsrc.current_state() != gst::State::Playing => continue,
+            Err(_elapsed) => break format!("No data after {} seconds, exiting", timeout.seconds()),
         };
     };
     gst::element_error!(
@@ -1985,6 +3358,20 @@ async fn udp_rtp_task(
     );
 }
 
+async fn udp_rtp_send_task(
+    socket: &UdpSocket,
+    dest: SocketAddr,
+    mut rx: mpsc::Receiver<MappedBuffer<Readable>>,
+) {
+    gst::info!(CAT, "Sending backchannel RTP to {dest:?}");
+    while let Some(data) = rx.recv().await {
+        if let Err(err) = socket.send_to(data.as_ref(), dest).await {
+            gst::warning!(CAT, "Failed to send backchannel RTP to {dest:?}: {err:?}");
+        }
+    }
+    gst::info!(CAT, "Backchannel to {dest:?} closed");
+}
+
 async fn udp_rtcp_task(
     socket: &UdpSocket,
     appsrc: gst_app::AppSrc,
@@ -15,18 +15,23 @@
  * architectural issues, with the aim of making the two functionally equivalent.
  *
  * Implemented features:
- * * RTSP 1.0 support
+ * * RTSP 1.0 and 2.0 support
  * * Lower transports: TCP, UDP, UDP-Multicast
  * * RTCP SR and RTCP RR
  * * RTCP-based A/V sync
  * * Lower transport selection and priority (NEW!)
  *   - Also supports different lower transports for each SETUP
+ * * Basic and Digest (MD5, SHA-256) authentication
+ * * RTSP over TLS (`rtsps://`, `rtspsu://`, `rtspst://`)
+ * * PAUSE and seeking support with VOD
+ * * ONVIF audio backchannel (`backchannel=onvif`, `backchannel_%u` sink pads)
+ * * Session keep-alive and reconnection when the TCP connection is lost
+ * * Server requests: REDIRECT, ANNOUNCE and SET_PARAMETER (posted as element messages)
  *
  * Some missing features:
- * * SET_PARAMETER/GET_PARAMETER messages
+ * * Sending SET_PARAMETER/GET_PARAMETER requests from the application
  * * SRTP support
- * * VOD support: PAUSE, seeking, etc
- * * ONVIF backchannel and trick mode support
+ * * ONVIF trick mode support
  * * and more
  *
  * Please see the [README](https://gitlab.freedesktop.org/gstreamer/gst-plugins-rs/-/blob/main/net/rtsp/README.md)
@@ -35,17 +40,45 @@
 use gst::glib;
 use gst::prelude::*;
 
+mod auth;
 mod body;
 mod imp;
 mod sdp;
 mod tcp_message;
+mod tls;
 mod transport;
 
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRtspSrc2Backchannel")]
+#[non_exhaustive]
+pub enum RtspBackchannel {
+    #[enum_value(name = "No backchannel", nick = "none")]
+    None = 0,
+    #[enum_value(name = "ONVIF audio backchannel", nick = "onvif")]
+    Onvif = 1,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRtspSrc2Version")]
+#[non_exhaustive]
+pub enum RtspVersion {
+    #[enum_value(name = "RTSP 1.0", nick = "1-0")]
+    V1_0 = 0,
+    #[enum_value(name = "RTSP 2.0", nick = "2-0")]
+    V2_0 = 1,
+}
+
 glib::wrapper! {
     pub struct RtspSrc(ObjectSubclass<imp::RtspSrc>) @extends gst::Bin, gst::Element, gst::Object;
 }
 
 pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    #[cfg(feature = "doc")]
+    RtspBackchannel::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
+    #[cfg(feature = "doc")]
+    RtspVersion::static_type().mark_as_plugin_api(gst::PluginAPIFlags::empty());
     gst::Element::register(
         Some(plugin),
         "rtspsrc2",
@@ -429,3 +429,175 @@ pub fn parse_connections(conns: &Vec<Connection>) -> (BTreeSet<RtspProtocol>, bo
     }
     (conn_protocols, is_ipv4)
 }
+
+/// A Normal Play Time range, as used in the SDP `a=range` attribute and the `Range` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayRange {
+    /// `None` means `now`
+    pub start: Option<gst::ClockTime>,
+    pub stop: Option<gst::ClockTime>,
+}
+
+impl PlayRange {
+    /// Only ranges with a known start and end can be seeked in. Servers that don't allow
+    /// random access only give us the end of the range.
+    pub fn is_seekable(&self) -> bool {
+        self.start.is_some() && self.stop.is_some()
+    }
+
+    pub fn to_header_value(&self) -> String {
+        let start = self
+            .start
+            .map_or_else(|| "now".to_string(), format_npt_time);
+        let stop = self.stop.map(format_npt_time).unwrap_or_default();
+        format!("npt={start}-{stop}")
+    }
+}
+
+fn format_npt_time(t: gst::ClockTime) -> String {
+    format!("{}.{:06}", t.seconds(), t.useconds() % 1_000_000)
+}
+
+// https://datatracker.ietf.org/doc/html/rfc2326#section-3.6
+fn parse_npt_time(s: &str) -> Option<gst::ClockTime> {
+    let s = s.trim();
+    let (hms, frac) = match s.split_once('.') {
+        Some((hms, frac)) => (hms, Some(frac)),
+        None => (s, None),
+    };
+    let mut secs = 0u64;
+    for part in hms.split(':') {
+        secs = secs.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    let nsecs = match frac {
+        Some(frac) if !frac.is_empty() => {
+            let digits = frac.get(..frac.len().min(9))?;
+            digits.parse::<u64>().ok()? * 10u64.pow(9 - digits.len() as u32)
+        }
+        _ => 0,
+    };
+    Some(gst::ClockTime::from_seconds(secs) + gst::ClockTime::from_nseconds(nsecs))
+}
+
+/// Parses `npt=<start>-[<stop>]`, ignoring any other range units and parameters
+pub fn parse_npt_range(value: &str) -> Option<PlayRange> {
+    let value = value.split(';').next()?.trim();
+    let range = value.strip_prefix("npt")?.trim_start().strip_prefix('=')?;
+    let (start, stop) = range.split_once('-')?;
+    let start = match start.trim() {
+        "now" => None,
+        // A range without a start means from the beginning
+        "" => Some(gst::ClockTime::ZERO),
+        s => Some(parse_npt_time(s)?),
+    };
+    let stop = match stop.trim() {
+        "" => None,
+        s => Some(parse_npt_time(s)?),
+    };
+    Some(PlayRange { start, stop })
+}
+
+/// Whether a `Media-Properties` header value allows seeking to arbitrary positions
+///
+/// https://www.rfc-editor.org/rfc/rfc7826.html#section-18.29
+pub fn media_properties_random_access(value: &str) -> bool {
+    value.split(',').any(|prop| {
+        let (name, value) = match prop.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (prop.trim(), None),
+        };
+        // The value is the maximum duration between random access points
+        name.eq_ignore_ascii_case("Random-Access")
+            && value.map_or(true, |v| v.parse::<f64>().is_ok_and(|v| v > 0.0))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn npt_range() {
+        assert_eq!(
+            parse_npt_range("npt=now-"),
+            Some(PlayRange {
+                start: None,
+                stop: None
+            })
+        );
+        assert_eq!(
+            parse_npt_range("npt=0-"),
+            Some(PlayRange {
+                start: Some(gst::ClockTime::ZERO),
+                stop: None
+            })
+        );
+        assert_eq!(
+            parse_npt_range("npt=0.000-123.45"),
+            Some(PlayRange {
+                start: Some(gst::ClockTime::ZERO),
+                stop: Some(gst::ClockTime::from_mseconds(123_450)),
+            })
+        );
+        assert_eq!(
+            parse_npt_range("npt = 00:01:02.5-1:00:00;time=19970123T143720Z"),
+            Some(PlayRange {
+                start: Some(gst::ClockTime::from_mseconds(62_500)),
+                stop: Some(gst::ClockTime::from_seconds(3600)),
+            })
+        );
+        assert_eq!(parse_npt_range("clock=19961108T142300Z-"), None);
+        assert_eq!(parse_npt_range("npt=abc-"), None);
+    }
+
+    #[test]
+    fn npt_range_header_value() {
+        let range = PlayRange {
+            start: Some(gst::ClockTime::from_mseconds(62_500)),
+            stop: None,
+        };
+        assert_eq!(range.to_header_value(), "npt=62.500000-");
+        assert_eq!(parse_npt_range(&range.to_header_value()), Some(range));
+
+        let range = PlayRange {
+            start: None,
+            stop: Some(gst::ClockTime::from_seconds(10)),
+        };
+        assert_eq!(range.to_header_value(), "npt=now-10.000000");
+    }
+
+    #[test]
+    fn seekable_range() {
+        assert!(PlayRange {
+            start: Some(gst::ClockTime::ZERO),
+            stop: Some(gst::ClockTime::from_seconds(10)),
+        }
+        .is_seekable());
+        assert!(!PlayRange {
+            start: Some(gst::ClockTime::ZERO),
+            stop: None,
+        }
+        .is_seekable());
+        assert!(!PlayRange {
+            start: None,
+            stop: Some(gst::ClockTime::from_seconds(10)),
+        }
+        .is_seekable());
+    }
+
+    #[test]
+    fn media_properties() {
+        assert!(media_properties_random_access(
+            "Random-Access=2.5, Unlimited, Immutable"
+        ));
+        assert!(media_properties_random_access("Immutable,Random-Access"));
+        assert!(!media_properties_random_access("Random-Access=0"));
+        assert!(!media_properties_random_access("Random-Access=abc"));
+        assert!(!media_properties_random_access(
+            "No-Seeking, Time-Progressing, Time-Duration=0.0"
+        ));
+        assert!(!media_properties_random_access(
+            "Beginning-Only, Random-Access-Foo"
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+// GStreamer RTSP Source 2
+//
+// SPDX-License-Identifier: MPL-2.0
+//
+// https://www.rfc-editor.org/rfc/rfc7826.html#section-19.2
+
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use url::{Host, Url};
+
+use super::imp::{RtspError, CAT};
+
+/// Default port for `rtsps://` URLs
+pub const DEFAULT_RTSPS_PORT: u16 = 322;
+
+#[derive(Clone)]
+pub struct TlsConnector {
+    connector: tokio_rustls::TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    /// Creates a connector for `url`. The certificate is verified against `server_name` if set,
+    /// or against the host in `url` otherwise.
+    pub fn new(
+        url: &Url,
+        server_name: Option<&str>,
+        ca_file: Option<&Path>,
+        accept_invalid_certs: bool,
+    ) -> Result<Self, RtspError> {
+        let server_name = match (server_name, url.host()) {
+            (Some(name), _) => server_name_from_str(name)?,
+            (None, Some(Host::Domain(domain))) => server_name_from_str(domain)?,
+            (None, Some(Host::Ipv4(ip))) => ServerName::from(IpAddr::V4(ip)),
+            (None, Some(Host::Ipv6(ip))) => ServerName::from(IpAddr::V6(ip)),
+            (None, None) => return Err(RtspError::Fatal("No host in URL".to_string())),
+        };
+
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let config = if accept_invalid_certs {
+            gst::warning!(CAT, "Not verifying the TLS certificate of the RTSP server");
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoCertificateVerification(provider)))
+                .with_no_client_auth()
+        } else {
+            builder
+                .with_root_certificates(root_store(ca_file)?)
+                .with_no_client_auth()
+        };
+
+        Ok(TlsConnector {
+            connector: tokio_rustls::TlsConnector::from(Arc::new(config)),
+            server_name,
+        })
+    }
+
+    pub async fn connect(&self, stream: TcpStream) -> Result<TlsStream<TcpStream>, RtspError> {
+        gst::debug!(CAT, "Starting TLS handshake with {:?}", self.server_name);
+        let stream = self
+            .connector
+            .connect(self.server_name.clone(), stream)
+            .await?;
+        let (_, conn) = stream.get_ref();
+        gst::info!(
+            CAT,
+            "TLS handshake complete: {:?} {:?}",
+            conn.protocol_version(),
+            conn.negotiated_cipher_suite(),
+        );
+        Ok(stream)
+    }
+}
+
+fn server_name_from_str(name: &str) -> Result<ServerName<'static>, RtspError> {
+    ServerName::try_from(name.to_owned())
+        .map_err(|err| RtspError::Fatal(format!("Invalid TLS server name '{name}': {err}")))
+}
+
+fn root_store(ca_file: Option<&Path>) -> Result<RootCertStore, RtspError> {
+    let mut store = RootCertStore::empty();
+
+    let certs = match ca_file {
+        Some(path) => {
+            let mut reader = BufReader::new(File::open(path)?);
+            rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?
+        }
+        None => rustls_native_certs::load_native_certs()?,
+    };
+
+    let (added, ignored) = store.add_parsable_certificates(certs);
+    gst::debug!(
+        CAT,
+        "Loaded {added} CA certificates, ignored {ignored} invalid ones"
+    );
+    if added == 0 {
+        return Err(RtspError::Fatal(format!(
+            "No valid CA certificates found in {}",
+            ca_file.map_or("the system store".to_string(), |p| p.display().to_string())
+        )));
+    }
+
+    Ok(store)
+}
+
+/// Accepts any server certificate, but still checks that the handshake signatures are valid.
+#[derive(Debug)]
+struct NoCertificateVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
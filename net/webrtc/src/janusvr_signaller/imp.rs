@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::signaller::{Signallable, SignallableImpl};
+use crate::signaller::{Signallable, SignallableImpl, WebRTCSignallerRole};
 use crate::RUNTIME;
 
 use anyhow::{anyhow, Error};
@@ -16,6 +16,7 @@ use http::Uri;
 use once_cell::sync::Lazy;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::ops::ControlFlow;
 use std::sync::Mutex;
 use std::time::Duration;
@@ -97,6 +98,20 @@ struct AttachPluginMsg {
     apisecret: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct DetachMsg {
+    janus: String,
+    transaction: String,
+    session_id: u64,
+    handle_id: u64,
+    apisecret: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct SubscribeStream {
+    feed: JanusId,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 struct RoomRequestBody {
     request: String,
@@ -106,6 +121,8 @@ struct RoomRequestBody {
     id: Option<JanusId>,
     #[serde(skip_serializing_if = "Option::is_none")]
     display: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    streams: Option<Vec<SubscribeStream>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -164,6 +181,7 @@ enum OutgoingMessage {
     KeepAlive(KeepAliveMsg),
     CreateSession(CreateSessionMsg),
     AttachPlugin(AttachPluginMsg),
+    Detach(DetachMsg),
     RoomRequest(RoomRequestMsg),
     Publish(PublishMsg),
     Trickle(TrickleMsg),
@@ -182,10 +200,18 @@ struct InnerHangup {
     reason: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct Publisher {
+    id: JanusId,
+    display: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct RoomJoined {
     room: JanusId,
     id: JanusId,
+    #[serde(default)]
+    publishers: Vec<Publisher>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -193,6 +219,20 @@ struct RoomEvent {
     room: Option<JanusId>,
     error_code: Option<i32>,
     error: Option<String>,
+    #[serde(default)]
+    publishers: Vec<Publisher>,
+    unpublished: Option<JanusId>,
+    leaving: Option<JanusId>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RoomAttached {
+    room: JanusId,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RoomUpdated {
+    room: JanusId,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -213,6 +253,8 @@ struct RoomTalking {
 enum VideoRoomData {
     Joined(RoomJoined),
     Event(RoomEvent),
+    Attached(RoomAttached),
+    Updated(RoomUpdated),
     Destroyed(RoomDestroyed),
     Talking(RoomTalking),
     StoppedTalking(RoomTalking),
@@ -241,10 +283,27 @@ struct SuccessMsg {
 struct EventMsg {
     transaction: Option<String>,
     session_id: Option<u64>,
+    sender: Option<u64>,
     plugindata: Option<PluginData>,
     jsep: Option<Jsep>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct IncomingCandidate {
+    candidate: Option<String>,
+    #[serde(rename = "sdpMid")]
+    sdp_mid: Option<String>,
+    #[serde(rename = "sdpMLineIndex")]
+    sdp_m_line_index: Option<u32>,
+    completed: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IncomingTrickle {
+    sender: u64,
+    candidate: IncomingCandidate,
+}
+
 // IncomingMessage
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "janus", rename_all = "lowercase")]
@@ -256,6 +315,13 @@ enum JsonReply {
     Media,
     Error(InnerError),
     HangUp(InnerHangup),
+    Trickle(IncomingTrickle),
+}
+
+/// A subscriber handle receiving the streams of a single publisher
+struct Subscription {
+    feed_id: JanusId,
+    handle_id: u64,
 }
 
 #[derive(Default)]
@@ -268,6 +334,11 @@ struct State {
     transaction_id: Option<String>,
     room_id: Option<JanusId>,
     feed_id: Option<JanusId>,
+    // Subscriptions in Consumer role, by session ID
+    subscriptions: HashMap<String, Subscription>,
+    // Feeds a subscriber handle is being attached for, by transaction ID.
+    // `None` if the publisher left in the meantime.
+    pending_subscriptions: HashMap<String, Option<JanusId>>,
 }
 
 #[derive(Clone)]
@@ -277,6 +348,9 @@ struct Settings {
     feed_id: Option<JanusId>,
     display_name: Option<String>,
     secret_key: Option<String>,
+    role: WebRTCSignallerRole,
+    producer_peer_id: Option<String>,
+    excluded_producer_peer_ids: Vec<String>,
 }
 
 impl Default for Settings {
@@ -287,6 +361,9 @@ impl Default for Settings {
             room_id: None,
             feed_id: None,
             secret_key: None,
+            role: WebRTCSignallerRole::Producer,
+            producer_peer_id: None,
+            excluded_producer_peer_ids: vec![],
         }
     }
 }
@@ -299,6 +376,9 @@ pub struct Signaller {
     #[property(name="janus-endpoint", get, set, type = String, member = janus_endpoint, blurb = "The Janus server endpoint to POST SDP offer to")]
     #[property(name="display-name", get, set, type = String, member = display_name, blurb = "The name of the publisher in the Janus Video Room")]
     #[property(name="secret-key", get, set, type = String, member = secret_key, blurb = "The secret API key to communicate with Janus server")]
+    #[property(name="role", get, construct_only, type = WebRTCSignallerRole, member = role, builder(WebRTCSignallerRole::Producer), blurb = "Whether the signaller publishes into the Video Room (Producer) or subscribes to its publishers (Consumer)")]
+    #[property(name="producer-peer-id", get, set, type = String, member = producer_peer_id, blurb = "When in Consumer Role, the signaller will only subscribe to the publisher with this feed ID or display name")]
+    #[property(name="excluded-producer-peer-ids", get = Self::excluded_producer_peer_ids, set = Self::set_excluded_producer_peer_ids, type = gst::Array, blurb = "When in Consumer Role, the signaller will not subscribe to the publishers with these feed IDs or display names")]
     // Properties whose type depends of the Janus ID format (u64 or string) are implemented in Signaller subclasses
     settings: Mutex<Settings>,
 }
@@ -309,6 +389,33 @@ impl Signaller {
             .emit_by_name::<()>("error", &[&format!("Error: {msg}")]);
     }
 
+    fn is_subscriber(&self) -> bool {
+        self.settings.lock().unwrap().role == WebRTCSignallerRole::Consumer
+    }
+
+    fn excluded_producer_peer_ids(&self) -> gst::Array {
+        gst::Array::new(&self.settings.lock().unwrap().excluded_producer_peer_ids)
+    }
+
+    fn set_excluded_producer_peer_ids(&self, ids: gst::Array) {
+        self.settings.lock().unwrap().excluded_producer_peer_ids = ids
+            .iter()
+            .filter_map(|id| id.get::<String>().ok())
+            .collect();
+    }
+
+    /// Returns the session ID of the subscription using the `handle_id` plugin handle
+    fn subscription_session(&self, handle_id: Option<u64>) -> Option<String> {
+        let handle_id = handle_id?;
+        let state = self.state.lock().unwrap();
+
+        state
+            .subscriptions
+            .iter()
+            .find(|(_, subscription)| subscription.handle_id == handle_id)
+            .map(|(session_id, _)| session_id.clone())
+    }
+
     async fn connect(&self) -> Result<(), Error> {
         let settings = self.settings.lock().unwrap().clone();
         use tungstenite::client::IntoClientRequest;
@@ -451,6 +558,12 @@ impl Signaller {
                         );
                         self.set_session_id(data.id);
                         self.attach_plugin();
+                    } else if let Some(feed_id) = success
+                        .transaction
+                        .as_ref()
+                        .and_then(|t| self.state.lock().unwrap().pending_subscriptions.remove(t))
+                    {
+                        self.on_subscriber_attached(feed_id, data.id);
                     } else {
                         gst::trace!(
                             CAT,
@@ -491,18 +604,48 @@ impl Signaller {
                                 "Joined room {:?} successfully",
                                 joined.room
                             );
-                            self.session_requested();
+
+                            if self.is_subscriber() {
+                                for publisher in joined.publishers {
+                                    self.on_publisher(publisher, false);
+                                }
+                            } else {
+                                self.session_requested();
+                            }
                         }
                         VideoRoomData::Event(room_event) => {
                             if room_event.error_code.is_some() && room_event.error.is_some() {
-                                self.raise_error(format!(
+                                let msg = format!(
                                     "code: {}, reason: {}",
                                     room_event.error_code.unwrap(),
                                     room_event.error.unwrap(),
-                                ));
+                                );
+
+                                // A failing subscription should not take the others down
+                                if let Some(session_id) = self.subscription_session(event.sender) {
+                                    gst::warning!(
+                                        CAT,
+                                        imp = self,
+                                        "Subscription {session_id} failed: {msg}"
+                                    );
+                                    self.end_subscription(&session_id);
+                                } else {
+                                    self.raise_error(msg);
+                                }
                                 return;
                             }
 
+                            if self.is_subscriber() {
+                                for publisher in room_event.publishers {
+                                    self.on_publisher(publisher, true);
+                                }
+
+                                if let Some(feed_id) = room_event.unpublished.or(room_event.leaving)
+                                {
+                                    self.on_publisher_removed(&feed_id);
+                                }
+                            }
+
                             if let Some(jsep) = event.jsep {
                                 if jsep.r#type == "answer" {
                                     gst::trace!(CAT, imp = self, "Session requested successfully");
@@ -510,6 +653,40 @@ impl Signaller {
                                 }
                             }
                         }
+                        VideoRoomData::Attached(attached) => {
+                            let Some(session_id) = self.subscription_session(event.sender) else {
+                                gst::warning!(
+                                    CAT,
+                                    imp = self,
+                                    "Attached to room {} on unknown handle {:?}",
+                                    attached.room,
+                                    event.sender
+                                );
+                                return;
+                            };
+
+                            if let Some(jsep) = event.jsep.filter(|jsep| jsep.r#type == "offer") {
+                                gst::trace!(CAT, imp = self, "Got offer for {session_id}");
+                                self.handle_offer(&session_id, jsep.sdp);
+                            }
+                        }
+                        VideoRoomData::Updated(updated) => {
+                            // The streams of the publisher changed: start over with a new
+                            // subscription rather than renegotiating the existing session,
+                            // so that its pads are exposed anew
+                            if let Some(session_id) = self.subscription_session(event.sender) {
+                                gst::debug!(
+                                    CAT,
+                                    imp = self,
+                                    "Subscription {session_id} updated in room {}, resubscribing",
+                                    updated.room
+                                );
+
+                                if let Some(feed_id) = self.end_subscription(&session_id) {
+                                    self.subscribe(feed_id);
+                                }
+                            }
+                        }
                         VideoRoomData::Destroyed(room_destroyed) => {
                             gst::trace!(
                                 CAT,
@@ -535,7 +712,49 @@ impl Signaller {
             JsonReply::Error(error) => {
                 self.raise_error(format!("code: {}, reason: {}", error.code, error.reason))
             }
-            JsonReply::HangUp(hangup) => self.raise_error(format!("hangup: {}", hangup.reason)),
+            JsonReply::HangUp(hangup) => {
+                let sender = match hangup.sender {
+                    JanusId::Num(handle_id) => Some(handle_id),
+                    JanusId::Str(_) => None,
+                };
+
+                if let Some(session_id) = self.subscription_session(sender) {
+                    gst::info!(
+                        CAT,
+                        imp = self,
+                        "Subscription {session_id} hung up: {}",
+                        hangup.reason
+                    );
+                    self.end_subscription(&session_id);
+                } else {
+                    self.raise_error(format!("hangup: {}", hangup.reason))
+                }
+            }
+            JsonReply::Trickle(trickle) => {
+                let Some(session_id) = self.subscription_session(Some(trickle.sender)) else {
+                    gst::trace!(CAT, imp = self, "Ignoring trickle {trickle:?}");
+                    return;
+                };
+
+                let IncomingCandidate {
+                    candidate,
+                    sdp_mid,
+                    sdp_m_line_index,
+                    completed,
+                } = trickle.candidate;
+
+                if completed == Some(true) {
+                    gst::trace!(CAT, imp = self, "All candidates received for {session_id}");
+                    return;
+                }
+
+                if let (Some(candidate), Some(sdp_m_line_index)) = (candidate, sdp_m_line_index) {
+                    self.obj().emit_by_name::<()>(
+                        "handle-ice",
+                        &[&session_id, &sdp_m_line_index, &sdp_mid, &candidate],
+                    );
+                }
+            }
             // ignore for now
             JsonReply::Ack | JsonReply::Media => {}
         }
@@ -648,6 +867,7 @@ impl Signaller {
                 room,
                 id: feed_id,
                 display,
+                streams: None,
             },
         }));
     }
@@ -684,6 +904,7 @@ impl Signaller {
                 room,
                 id: Some(feed_id),
                 display,
+                streams: None,
             },
         }));
     }
@@ -723,7 +944,7 @@ impl Signaller {
         }));
     }
 
-    fn trickle(&self, candidate: &str, sdp_m_line_index: u32) {
+    fn trickle(&self, webrtc_session_id: &str, candidate: &str, sdp_m_line_index: u32) {
         let (transaction, session_id, handle_id, apisecret) = {
             let state = self.state.lock().unwrap();
             let settings = self.settings.lock().unwrap();
@@ -733,10 +954,24 @@ impl Signaller {
                 return;
             }
 
+            let handle_id = if settings.role == WebRTCSignallerRole::Consumer {
+                let Some(subscription) = state.subscriptions.get(webrtc_session_id) else {
+                    gst::debug!(
+                        CAT,
+                        imp = self,
+                        "Dropping candidate for ended session {webrtc_session_id}"
+                    );
+                    return;
+                };
+                subscription.handle_id
+            } else {
+                state.handle_id.unwrap()
+            };
+
             (
                 state.transaction_id.clone().unwrap(),
                 state.session_id.unwrap(),
-                state.handle_id.unwrap(),
+                handle_id,
                 settings.secret_key.clone(),
             )
         };
@@ -753,6 +988,238 @@ impl Signaller {
         }));
     }
 
+    fn on_publisher(&self, publisher: Publisher, new_connection: bool) {
+        let peer_id = publisher.id.to_string();
+        let matches = |id: &str| id == peer_id || Some(id) == publisher.display.as_deref();
+
+        {
+            let settings = self.settings.lock().unwrap();
+            match settings.producer_peer_id.as_deref() {
+                Some(id) if matches(id) => {
+                    gst::debug!(CAT, imp = self, "matching publisher {id:?}");
+                }
+                None => {
+                    if settings
+                        .excluded_producer_peer_ids
+                        .iter()
+                        .any(|id| matches(id.as_str()))
+                    {
+                        gst::debug!(CAT, imp = self, "ignoring excluded publisher {publisher:?}");
+                        return;
+                    }
+                    gst::debug!(CAT, imp = self, "catch-all mode, matching {publisher:?}");
+                }
+                _ => return,
+            }
+        }
+
+        {
+            let state = self.state.lock().unwrap();
+            if state.subscriptions.contains_key(&peer_id)
+                || state
+                    .pending_subscriptions
+                    .values()
+                    .any(|feed_id| feed_id.as_ref() == Some(&publisher.id))
+            {
+                gst::debug!(CAT, imp = self, "Already subscribed to {peer_id}");
+                return;
+            }
+        }
+
+        let meta = publisher.display.as_ref().map(|display| {
+            gst::Structure::builder("meta")
+                .field("display", display)
+                .build()
+        });
+        self.obj()
+            .emit_by_name::<()>("producer-added", &[&peer_id, &meta, &new_connection]);
+
+        self.subscribe(publisher.id);
+    }
+
+    fn on_publisher_removed(&self, feed_id: &JanusId) {
+        let session_id = feed_id.to_string();
+
+        let was_pending = {
+            let mut state = self.state.lock().unwrap();
+            let mut was_pending = false;
+            for pending in state.pending_subscriptions.values_mut() {
+                if pending.as_ref() == Some(feed_id) {
+                    *pending = None;
+                    was_pending = true;
+                }
+            }
+            was_pending
+        };
+
+        if self.end_subscription(&session_id).is_some() || was_pending {
+            gst::info!(CAT, imp = self, "Publisher {session_id} left");
+            self.obj()
+                .emit_by_name::<()>("producer-removed", &[&session_id, &None::<gst::Structure>]);
+        }
+    }
+
+    /// Attaches a new plugin handle to subscribe to `feed_id` with
+    fn subscribe(&self, feed_id: JanusId) {
+        let transaction = transaction_id();
+        let (session_id, apisecret) = {
+            let mut state = self.state.lock().unwrap();
+            let settings = self.settings.lock().unwrap();
+
+            gst::debug!(CAT, imp = self, "Subscribing to feed {feed_id}");
+            state
+                .pending_subscriptions
+                .insert(transaction.clone(), Some(feed_id));
+
+            (state.session_id.unwrap(), settings.secret_key.clone())
+        };
+        self.send(OutgoingMessage::AttachPlugin(AttachPluginMsg {
+            janus: "attach".to_string(),
+            transaction,
+            plugin: "janus.plugin.videoroom".to_string(),
+            session_id,
+            apisecret,
+        }));
+    }
+
+    fn on_subscriber_attached(&self, feed_id: Option<JanusId>, handle_id: u64) {
+        let Some(feed_id) = feed_id else {
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Publisher left, detaching handle {handle_id}"
+            );
+            self.detach(handle_id);
+            return;
+        };
+
+        let session_id = feed_id.to_string();
+        gst::trace!(
+            CAT,
+            imp = self,
+            "Attached handle {handle_id} for subscription {session_id}"
+        );
+
+        let (transaction, janus_session_id, room, apisecret) = {
+            let mut state = self.state.lock().unwrap();
+            let settings = self.settings.lock().unwrap();
+
+            state.subscriptions.insert(
+                session_id.clone(),
+                Subscription {
+                    feed_id: feed_id.clone(),
+                    handle_id,
+                },
+            );
+
+            (
+                state.transaction_id.clone().unwrap(),
+                state.session_id.unwrap(),
+                state.room_id.clone().unwrap(),
+                settings.secret_key.clone(),
+            )
+        };
+
+        self.obj()
+            .emit_by_name::<()>("session-started", &[&session_id, &session_id]);
+
+        self.send(OutgoingMessage::RoomRequest(RoomRequestMsg {
+            janus: "message".to_string(),
+            transaction,
+            session_id: janus_session_id,
+            handle_id,
+            apisecret,
+            body: RoomRequestBody {
+                request: "join".to_string(),
+                ptype: "subscriber".to_string(),
+                room,
+                id: None,
+                display: None,
+                streams: Some(vec![SubscribeStream { feed: feed_id }]),
+            },
+        }));
+    }
+
+    /// Ends the session of a subscription and detaches its handle, returning
+    /// the subscribed feed
+    fn end_subscription(&self, session_id: &str) -> Option<JanusId> {
+        let subscription = self
+            .state
+            .lock()
+            .unwrap()
+            .subscriptions
+            .remove(session_id)?;
+
+        self.obj()
+            .emit_by_name::<bool>("session-ended", &[&session_id]);
+        self.detach(subscription.handle_id);
+
+        Some(subscription.feed_id)
+    }
+
+    fn detach(&self, handle_id: u64) {
+        let (session_id, apisecret) = {
+            let state = self.state.lock().unwrap();
+            let settings = self.settings.lock().unwrap();
+
+            let Some(session_id) = state.session_id else {
+                return;
+            };
+
+            (session_id, settings.secret_key.clone())
+        };
+        self.send(OutgoingMessage::Detach(DetachMsg {
+            janus: "detach".to_string(),
+            transaction: transaction_id(),
+            session_id,
+            handle_id,
+            apisecret,
+        }));
+    }
+
+    fn start_subscription(
+        &self,
+        webrtc_session_id: &str,
+        answer: &gst_webrtc::WebRTCSessionDescription,
+    ) {
+        let (transaction, session_id, handle_id, apisecret) = {
+            let state = self.state.lock().unwrap();
+            let settings = self.settings.lock().unwrap();
+
+            let Some(subscription) = state.subscriptions.get(webrtc_session_id) else {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "No subscription for session {webrtc_session_id}"
+                );
+                return;
+            };
+
+            (
+                state.transaction_id.clone().unwrap(),
+                state.session_id.unwrap(),
+                subscription.handle_id,
+                settings.secret_key.clone(),
+            )
+        };
+        let sdp_data = answer.sdp().as_text().unwrap();
+        self.send(OutgoingMessage::Publish(PublishMsg {
+            janus: "message".to_string(),
+            transaction,
+            session_id,
+            handle_id,
+            apisecret,
+            body: PublishBody {
+                request: "start".to_string(),
+            },
+            jsep: Jsep {
+                sdp: sdp_data,
+                trickle: Some(true),
+                r#type: "answer".to_string(),
+            },
+        }));
+    }
+
     fn session_requested(&self) {
         self.obj().emit_by_name::<()>(
             "session-requested",
@@ -764,6 +1231,22 @@ impl Signaller {
         );
     }
 
+    fn handle_offer(&self, session_id: &str, sdp: String) {
+        match gst_sdp::SDPMessage::parse_buffer(sdp.as_bytes()) {
+            Ok(offer_sdp) => {
+                let offer = gst_webrtc::WebRTCSessionDescription::new(
+                    gst_webrtc::WebRTCSDPType::Offer,
+                    offer_sdp,
+                );
+                self.obj()
+                    .emit_by_name::<()>("session-description", &[&session_id, &offer]);
+            }
+            Err(err) => {
+                self.raise_error(format!("Could not parse offer SDP: {err}"));
+            }
+        }
+    }
+
     fn handle_answer(&self, sdp: String) {
         match gst_sdp::SDPMessage::parse_buffer(sdp.as_bytes()) {
             Ok(ans_sdp) => {
@@ -801,29 +1284,46 @@ impl SignallableImpl for Signaller {
         });
     }
 
-    fn send_sdp(&self, _session_id: &str, offer: &gst_webrtc::WebRTCSessionDescription) {
-        gst::info!(
-            CAT,
-            imp = self,
-            "sending SDP offer to peer: {:?}",
-            offer.sdp().as_text()
-        );
-
-        self.publish(offer);
+    fn send_sdp(&self, session_id: &str, sdp: &gst_webrtc::WebRTCSessionDescription) {
+        if self.is_subscriber() {
+            gst::info!(
+                CAT,
+                imp = self,
+                "sending SDP answer for {session_id}: {:?}",
+                sdp.sdp().as_text()
+            );
+
+            self.start_subscription(session_id, sdp);
+        } else {
+            gst::info!(
+                CAT,
+                imp = self,
+                "sending SDP offer to peer: {:?}",
+                sdp.sdp().as_text()
+            );
+
+            self.publish(sdp);
+        }
     }
 
     fn add_ice(
         &self,
-        _session_id: &str,
+        session_id: &str,
         candidate: &str,
         sdp_m_line_index: u32,
         _sdp_mid: Option<String>,
     ) {
-        self.trickle(candidate, sdp_m_line_index);
+        self.trickle(session_id, candidate, sdp_m_line_index);
     }
 
     fn stop(&self) {
         gst::info!(CAT, imp = self, "Stopping now");
+
+        // Subscriber handles go away with the Janus session, only leave the room
+        if self.is_subscriber() && self.state.lock().unwrap().feed_id.is_some() {
+            self.leave_room();
+        }
+
         let mut state = self.state.lock().unwrap();
 
         let send_task_handle = state.send_task_handle.take();
@@ -849,10 +1349,19 @@ impl SignallableImpl for Signaller {
         state.session_id = None;
         state.handle_id = None;
         state.transaction_id = None;
+        state.subscriptions.clear();
+        state.pending_subscriptions.clear();
     }
 
-    fn end_session(&self, _session_id: &str) {
-        self.leave_room();
+    fn end_session(&self, session_id: &str) {
+        if self.is_subscriber() {
+            let subscription = self.state.lock().unwrap().subscriptions.remove(session_id);
+            if let Some(subscription) = subscription {
+                self.detach(subscription.handle_id);
+            }
+        } else {
+            self.leave_room();
+        }
     }
 }
 
@@ -25,6 +25,8 @@ pub mod utils;
 pub mod webrtcsink;
 pub mod webrtcsrc;
 #[cfg(feature = "whip")]
+mod whep_signaller;
+#[cfg(feature = "whip")]
 mod whip_signaller;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
@@ -6,7 +6,7 @@ use gst::{
 };
 use once_cell::sync::Lazy;
 
-use super::imp::VideoEncoder;
+use super::imp::{set_encoders_bitrate, SharedEncoderLink, VideoEncoder};
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
@@ -294,12 +294,14 @@ impl CongestionController {
         element: &super::BaseWebRTCSink,
         stats: &gst::StructureRef,
         encoders: &mut [VideoEncoder],
+        shared_links: &mut [SharedEncoderLink],
     ) {
         let loss_percentage = stats.get::<f64>("packet-loss-pct").unwrap();
 
         self.apply_control_op(
             element,
             encoders,
+            shared_links,
             if loss_percentage > 10. {
                 CongestionControlOp::Decrease {
                     factor: ((100. - (0.5 * loss_percentage)) / 100.).clamp(0.7, 0.98),
@@ -319,10 +321,11 @@ impl CongestionController {
         element: &super::BaseWebRTCSink,
         stats: &gst::StructureRef,
         encoders: &mut [VideoEncoder],
+        shared_links: &mut [SharedEncoderLink],
     ) {
         if let Some(twcc_stats) = lookup_twcc_stats(stats) {
             let op = self.update_delay(element, &twcc_stats, self.lookup_rtt(stats));
-            self.apply_control_op(element, encoders, op, ControllerType::Delay);
+            self.apply_control_op(element, encoders, shared_links, op, ControllerType::Delay);
         }
     }
 
@@ -330,6 +333,7 @@ impl CongestionController {
         &mut self,
         element: &super::BaseWebRTCSink,
         encoders: &mut [VideoEncoder],
+        shared_links: &mut [SharedEncoderLink],
         control_op: CongestionControlOp,
         controller_type: ControllerType,
     ) {
@@ -341,7 +345,7 @@ impl CongestionController {
             control_op
         );
 
-        let n_encoders = encoders.len() as i32;
+        let n_encoders = (encoders.len() + shared_links.len()) as i32;
         let prev_bitrate = i32::min(self.target_bitrate_on_delay, self.target_bitrate_on_loss);
         match &control_op {
             CongestionControlOp::Hold => {}
@@ -412,12 +416,12 @@ impl CongestionController {
 
         let fec_percentage = (fec_ratio * 50f64) as u32;
 
-        for encoder in encoders.iter_mut() {
-            if encoder.set_bitrate(element, target_bitrate).is_ok() {
-                encoder
-                    .transceiver
-                    .set_property("fec-percentage", fec_percentage);
-            }
-        }
+        set_encoders_bitrate(
+            element,
+            encoders,
+            shared_links,
+            target_bitrate,
+            fec_percentage,
+        );
     }
 }
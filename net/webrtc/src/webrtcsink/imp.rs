@@ -42,6 +42,8 @@ const D3D11_MEMORY_FEATURE: &str = "memory:D3D11Memory";
 
 const RTP_TWCC_URI: &str =
     "http://www.ietf.org/id/draft-holmer-rmcat-transport-wide-cc-extensions-01";
+const RTP_STREAM_ID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id";
+const RTP_MID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:mid";
 
 const DEFAULT_STUN_SERVER: Option<&str> = Some("stun://stun.l.google.com:19302");
 const DEFAULT_MIN_BITRATE: u32 = 1000;
@@ -61,6 +63,18 @@ const DEFAULT_DO_CLOCK_SIGNALLING: bool = false;
 const DEFAULT_ENABLE_DATA_CHANNEL_NAVIGATION: bool = false;
 const DEFAULT_ICE_TRANSPORT_POLICY: WebRTCICETransportPolicy = WebRTCICETransportPolicy::All;
 const DEFAULT_START_BITRATE: u32 = 2048000;
+const DEFAULT_LAYER_MIN_BITRATE: u32 = 100000;
+const DEFAULT_SHARED_ENCODERS: bool = false;
+const DEFAULT_SHARED_ENCODER_RENDITIONS: [u32; 3] = [500000, 1500000, 4000000];
+const DEFAULT_RECORD_MUXER: &str = "mp4mux";
+const DEFAULT_RECORD_MAX_SIZE_TIME: u64 = 0;
+const DEFAULT_RECORD_VIDEO_BITRATE: u32 = 4000000;
+/* How long to wait for the recording muxer to finalize its
+ * output after stopping before shutting it down regardless */
+const RECORDING_EOS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/* A dropped simulcast layer is only restored once it can be given
+ * a bit more than its minimum bitrate, to avoid flapping */
+const LAYER_RESTORE_FACTOR: f64 = 1.25;
 /* Start adding some FEC when the bitrate > 2Mbps as we found experimentally
  * that it is not worth it below that threshold */
 #[cfg(feature = "v1_22")]
@@ -89,6 +103,12 @@ struct Settings {
     meta: Option<gst::Structure>,
     ice_transport_policy: WebRTCICETransportPolicy,
     signaller: Signallable,
+    shared_encoders: bool,
+    shared_encoder_renditions: gst::Array,
+    record_location: Option<String>,
+    record_muxer: String,
+    record_max_size_time: u64,
+    record_video_bitrate: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -217,6 +237,26 @@ struct WebRTCPad {
     stream_name: Option<String>,
     /// The payload selected in the answer, None at first
     payload: Option<i32>,
+    /// The simulcast layers we offered for this pad, if any
+    simulcast_layers: Vec<SimulcastLayer>,
+}
+
+/// A simulcast layer, as configured on a video sink pad
+#[derive(Clone, Debug)]
+struct SimulcastLayer {
+    /// The RTP stream ID of the layer
+    rid: String,
+    scale_resolution_down_by: f64,
+    bitrate_ratio: f64,
+    min_bitrate: u32,
+}
+
+/// State of the simulcast layer produced by a VideoEncoder
+struct EncoderLayer {
+    layer: SimulcastLayer,
+    /// Stops feeding the encoder while the layer is dropped
+    valve: gst::Element,
+    active: bool,
 }
 
 /// Wrapper around GStreamer encoder element, keeps track of factory
@@ -232,9 +272,60 @@ pub struct VideoEncoder {
     video_info: gst_video::VideoInfo,
     session_id: String,
     mitigation_mode: WebRTCSinkMitigationMode,
-    pub transceiver: gst_webrtc::WebRTCRTPTransceiver,
+    /// None for shared encoders, which aren't tied to a consumer
+    transceiver: Option<gst_webrtc::WebRTCRTPTransceiver>,
     /// name of the sink pad feeding this encoder
     stream_name: String,
+    /// The simulcast layer this encoder produces, if any
+    layer: Option<EncoderLayer>,
+}
+
+/// Encodes a raw video stream once per rendition, for all
+/// the consumers that negotiated the same codec
+struct SharedEncoder {
+    pipeline: gst::Pipeline,
+    /// Ordered by increasing bitrate
+    renditions: Vec<SharedRendition>,
+    /// Consumes the input stream
+    link: gst_utils::ConsumptionLink,
+    /// The sessions consuming one of the renditions
+    sessions: HashSet<String>,
+}
+
+struct SharedRendition {
+    bitrate: u32,
+    encoder: VideoEncoder,
+    producer: StreamProducer,
+}
+
+/// A consumer's link to the renditions of a shared encoder
+pub struct SharedEncoderLink {
+    stream_name: String,
+    /// Bitrates and producers of the renditions, ordered by increasing bitrate
+    renditions: Vec<(u32, StreamProducer)>,
+    /// Index of the rendition currently consumed
+    current: usize,
+    link: gst_utils::ConsumptionLink,
+    transceiver: gst_webrtc::WebRTCRTPTransceiver,
+}
+
+/// Records the input streams to files. Raw streams are encoded
+/// at a fixed bitrate, independently from the consumers
+struct Recording {
+    pipeline: gst::Pipeline,
+    /// Consume the input streams
+    links: Vec<gst_utils::ConsumptionLink>,
+    appsrcs: Vec<gst_app::AppSrc>,
+    /// Resolved once the muxer has finalized the files
+    eos_receiver: Option<futures::channel::oneshot::Receiver<()>>,
+}
+
+/// An input stream to record, and the codec to record it with
+struct RecordingInput {
+    stream_name: String,
+    producer: StreamProducer,
+    in_caps: gst::Caps,
+    codec: Codec,
 }
 
 struct Session {
@@ -247,6 +338,9 @@ struct Session {
     webrtc_pads: HashMap<u32, WebRTCPad>,
     peer_id: String,
     encoders: Vec<VideoEncoder>,
+    /// Links to shared encoders, used instead of encoders when
+    /// shared-encoders is enabled
+    shared_links: Vec<SharedEncoderLink>,
 
     // Our Homegrown controller (if cc_info.heuristic == Homegrown)
     congestion_controller: Option<CongestionController>,
@@ -505,6 +599,9 @@ struct NavigationEventHandler((glib::SignalHandlerId, WebRTCDataChannel));
 pub struct BaseWebRTCSink {
     state: Mutex<State>,
     settings: Mutex<Settings>,
+    /// Shared encoders, by stream name and codec
+    shared_encoders: Mutex<HashMap<String, SharedEncoder>>,
+    recording: Mutex<Option<Recording>>,
 }
 
 impl Default for Settings {
@@ -535,6 +632,12 @@ impl Default for Settings {
             meta: None,
             ice_transport_policy: DEFAULT_ICE_TRANSPORT_POLICY,
             signaller: signaller.upcast(),
+            shared_encoders: DEFAULT_SHARED_ENCODERS,
+            shared_encoder_renditions: gst::Array::new(DEFAULT_SHARED_ENCODER_RENDITIONS),
+            record_location: None,
+            record_muxer: DEFAULT_RECORD_MUXER.to_string(),
+            record_max_size_time: DEFAULT_RECORD_MAX_SIZE_TIME,
+            record_video_bitrate: DEFAULT_RECORD_VIDEO_BITRATE,
         }
     }
 }
@@ -960,13 +1063,238 @@ impl PayloadChainBuilder {
     }
 }
 
+impl SimulcastLayer {
+    fn from_structure(s: &gst::StructureRef) -> Result<Self, Error> {
+        let rid = s.get::<String>("rid").context("Layer has no rid")?;
+
+        // RFC 8851: rid-id = 1*(alpha-numeric / "-" / "_")
+        if rid.is_empty()
+            || rid.len() > 16
+            || !rid
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("Invalid rid {rid:?}");
+        }
+
+        let scale_resolution_down_by = s
+            .get_optional::<f64>("scale-resolution-down-by")?
+            .unwrap_or(1.);
+        if scale_resolution_down_by < 1. {
+            anyhow::bail!("Invalid scale-resolution-down-by {scale_resolution_down_by}");
+        }
+
+        let bitrate_ratio = s.get_optional::<f64>("bitrate-ratio")?.unwrap_or(1.);
+        if bitrate_ratio <= 0. {
+            anyhow::bail!("Invalid bitrate-ratio {bitrate_ratio}");
+        }
+
+        let min_bitrate = s
+            .get_optional::<u32>("min-bitrate")?
+            .unwrap_or(DEFAULT_LAYER_MIN_BITRATE);
+
+        Ok(Self {
+            rid,
+            scale_resolution_down_by,
+            bitrate_ratio,
+            min_bitrate,
+        })
+    }
+}
+
+/// Adds the fields webrtcbin translates to a=rid, a=simulcast and
+/// a=extmap attributes for the RTP stream ID and MID extensions
+fn add_simulcast_to_caps(caps: &mut gst::CapsRef, layers: &[SimulcastLayer]) {
+    let rids = layers
+        .iter()
+        .map(|layer| layer.rid.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    for s in caps.iter_mut() {
+        for layer in layers {
+            s.set(format!("rid-{}", layer.rid).as_str(), "send");
+        }
+        s.set("a-simulcast", format!("send {rids}"));
+
+        for uri in [RTP_STREAM_ID_URI, RTP_MID_URI] {
+            let extmaps = s
+                .iter()
+                .filter_map(|(key, value)| {
+                    let id = key.strip_prefix("extmap-")?.parse::<u32>().ok()?;
+                    Some((id, value))
+                })
+                .collect::<Vec<_>>();
+
+            let mapped = extmaps.iter().any(|(_, value)| {
+                if let Ok(ext_uri) = value.get::<&str>() {
+                    ext_uri == uri
+                } else if let Ok(ext) = value.get::<gst::Array>() {
+                    ext.iter().any(|v| v.get::<&str>().is_ok_and(|v| v == uri))
+                } else {
+                    false
+                }
+            });
+
+            if !mapped {
+                let id = utils::find_smallest_available_ext_id(extmaps.iter().map(|(id, _)| *id));
+                s.set(format!("extmap-{id}").as_str(), uri);
+            }
+        }
+    }
+}
+
+/// Looks up the ID an RTP header extension was mapped to in an SDP media
+fn find_extmap_id(media: &gst_sdp::SDPMediaRef, uri: &str) -> Option<u32> {
+    media
+        .attributes()
+        .filter(|attribute| attribute.key() == "extmap")
+        .filter_map(|attribute| attribute.value())
+        .find_map(|value| {
+            let mut fields = value.split_whitespace();
+            let id = fields.next()?;
+            if fields.next()? != uri {
+                return None;
+            }
+
+            // The ID may be followed by a direction, eg 1/sendonly
+            id.split('/').next()?.parse::<u32>().ok()
+        })
+}
+
+/// Returns the layers the peer accepted to receive, in the order we offered them
+fn accepted_simulcast_layers(
+    media: &gst_sdp::SDPMediaRef,
+    layers: &[SimulcastLayer],
+) -> Vec<SimulcastLayer> {
+    let rids = media
+        .attributes()
+        .filter(|attribute| attribute.key() == "rid")
+        .filter_map(|attribute| attribute.value())
+        .filter_map(|value| {
+            let mut fields = value.split_whitespace();
+            let rid = fields.next()?;
+            (fields.next()? == "recv").then_some(rid)
+        })
+        .collect::<HashSet<_>>();
+
+    layers
+        .iter()
+        .filter(|layer| rids.contains(layer.rid.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Sets the bitrate of each encoder. The bitrates of the encoders producing
+/// the simulcast layers of a stream are pooled and split between the layers
+/// according to their ratios, dropping the highest layers that can't be given
+/// their minimum bitrate and restoring them once they can again. Consumers
+/// of shared encoders switch to the rendition that best fits the bitrate.
+pub(crate) fn set_encoders_bitrate(
+    element: &super::BaseWebRTCSink,
+    encoders: &mut [VideoEncoder],
+    shared_links: &mut [SharedEncoderLink],
+    bitrate: i32,
+    fec_percentage: u32,
+) {
+    let mut layered_streams: BTreeMap<String, Vec<&mut VideoEncoder>> = BTreeMap::new();
+
+    for encoder in encoders.iter_mut() {
+        if encoder.layer.is_some() {
+            layered_streams
+                .entry(encoder.stream_name.clone())
+                .or_default()
+                .push(encoder);
+        } else if encoder.set_bitrate(element, bitrate).is_ok() {
+            encoder.set_fec_percentage(fec_percentage);
+        }
+    }
+
+    for shared_link in shared_links.iter_mut() {
+        shared_link.set_bitrate(element, bitrate);
+        shared_link
+            .transceiver
+            .set_property("fec-percentage", fec_percentage);
+    }
+
+    for layers in layered_streams.values_mut() {
+        let bitrates = simulcast_layer_bitrates(
+            &layers
+                .iter()
+                .map(|encoder| {
+                    let layer = encoder.layer.as_ref().unwrap();
+                    (&layer.layer, layer.active)
+                })
+                .collect::<Vec<_>>(),
+            bitrate,
+        );
+
+        for (encoder, layer_bitrate) in layers.iter_mut().zip(bitrates) {
+            if let Some(layer_bitrate) = layer_bitrate {
+                encoder.set_layer_active(element, true);
+
+                if encoder.set_bitrate(element, layer_bitrate).is_ok() {
+                    encoder.set_fec_percentage(fec_percentage);
+                }
+            } else {
+                encoder.set_layer_active(element, false);
+            }
+        }
+    }
+}
+
+/// Splits the pooled `bitrate` of the simulcast layers of a stream, given with
+/// whether they are currently active, between the layers. Returns the bitrate
+/// of each layer, or `None` for the layers that have to be dropped.
+fn simulcast_layer_bitrates(layers: &[(&SimulcastLayer, bool)], bitrate: i32) -> Vec<Option<i32>> {
+    let budget = bitrate as f64 * layers.len() as f64;
+
+    // Layers are ordered from the lowest to the highest quality, keep
+    // as many as we can afford, and always at least the lowest one
+    let mut n_active = 1;
+    for n in 2..=layers.len() {
+        let total_ratio = layers[..n]
+            .iter()
+            .map(|(layer, _)| layer.bitrate_ratio)
+            .sum::<f64>();
+        let affordable = layers[..n].iter().all(|(layer, active)| {
+            let min_bitrate = if *active {
+                layer.min_bitrate as f64
+            } else {
+                layer.min_bitrate as f64 * LAYER_RESTORE_FACTOR
+            };
+
+            budget * layer.bitrate_ratio / total_ratio >= min_bitrate
+        });
+
+        if !affordable {
+            break;
+        }
+
+        n_active = n;
+    }
+
+    let total_ratio = layers[..n_active.min(layers.len())]
+        .iter()
+        .map(|(layer, _)| layer.bitrate_ratio)
+        .sum::<f64>();
+
+    layers
+        .iter()
+        .enumerate()
+        .map(|(idx, (layer, _))| {
+            (idx < n_active).then(|| (budget * layer.bitrate_ratio / total_ratio) as i32)
+        })
+        .collect()
+}
+
 impl VideoEncoder {
     fn new(
         encoding_elements: &EncodingChain,
         video_info: gst_video::VideoInfo,
         session_id: &str,
         codec_name: &str,
-        transceiver: gst_webrtc::WebRTCRTPTransceiver,
+        transceiver: Option<gst_webrtc::WebRTCRTPTransceiver>,
         stream_name: String,
     ) -> Option<Self> {
         let halved_framerate = video_info.fps().mul(gst::Fraction::new(1, 2));
@@ -981,6 +1309,7 @@ impl VideoEncoder {
             mitigation_mode: WebRTCSinkMitigationMode::NONE,
             transceiver,
             stream_name,
+            layer: None,
         })
     }
 
@@ -1019,6 +1348,57 @@ impl VideoEncoder {
         Ok(bitrate)
     }
 
+    /// The height of the encoded video when not downscaled
+    /// for congestion control
+    fn max_height(&self) -> i32 {
+        let height = self.video_info.height() as i32;
+
+        match self.layer {
+            Some(ref layer) => {
+                let height = (height as f64 / layer.layer.scale_resolution_down_by) as i32;
+                ((height + 1) & !1).max(2)
+            }
+            None => height,
+        }
+    }
+
+    fn set_layer_active(&mut self, element: &super::BaseWebRTCSink, active: bool) {
+        let Some(ref mut layer) = self.layer else {
+            return;
+        };
+
+        if layer.active == active {
+            return;
+        }
+
+        gst::info!(
+            CAT,
+            obj = element,
+            "session {}: {} simulcast layer {} of stream {}",
+            self.session_id,
+            if active { "restoring" } else { "dropping" },
+            layer.layer.rid,
+            self.stream_name,
+        );
+
+        layer.active = active;
+        layer.valve.set_property("drop", !active);
+
+        if active {
+            // The encoder was starved while the layer was dropped
+            let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+                .all_headers(true)
+                .build();
+            self.element.static_pad("src").unwrap().send_event(event);
+        }
+    }
+
+    fn set_fec_percentage(&self, fec_percentage: u32) {
+        if let Some(ref transceiver) = self.transceiver {
+            transceiver.set_property("fec-percentage", fec_percentage);
+        }
+    }
+
     fn scale_height_round_2(&self, height: i32) -> i32 {
         let ratio = gst_video::calculate_display_ratio(
             self.video_info.width(),
@@ -1060,7 +1440,7 @@ impl VideoEncoder {
         // Hardcoded thresholds, may be tuned further in the future, and
         // adapted according to the codec in use
         if bitrate < 500000 {
-            let height = 360i32.min(self.video_info.height() as i32);
+            let height = 360i32.min(self.max_height());
             let width = self.scale_height_round_2(height);
 
             s.set("height", height);
@@ -1073,7 +1453,7 @@ impl VideoEncoder {
             self.mitigation_mode =
                 WebRTCSinkMitigationMode::DOWNSAMPLED | WebRTCSinkMitigationMode::DOWNSCALED;
         } else if bitrate < 1000000 {
-            let height = 360i32.min(self.video_info.height() as i32);
+            let height = 360i32.min(self.max_height());
             let width = self.scale_height_round_2(height);
 
             s.set("height", height);
@@ -1082,7 +1462,7 @@ impl VideoEncoder {
 
             self.mitigation_mode = WebRTCSinkMitigationMode::DOWNSCALED;
         } else if bitrate < 2000000 {
-            let height = 720i32.min(self.video_info.height() as i32);
+            let height = 720i32.min(self.max_height());
             let width = self.scale_height_round_2(height);
 
             s.set("height", height);
@@ -1090,6 +1470,15 @@ impl VideoEncoder {
             s.remove_field("framerate");
 
             self.mitigation_mode = WebRTCSinkMitigationMode::DOWNSCALED;
+        } else if self.layer.is_some() {
+            let height = self.max_height();
+            let width = self.scale_height_round_2(height);
+
+            s.set("height", height);
+            s.set("width", width);
+            s.remove_field("framerate");
+
+            self.mitigation_mode = WebRTCSinkMitigationMode::NONE;
         } else {
             s.remove_field("height");
             s.remove_field("width");
@@ -1120,106 +1509,590 @@ impl VideoEncoder {
     }
 
     fn gather_stats(&self) -> gst::Structure {
-        gst::Structure::builder("application/x-webrtcsink-video-encoder-stats")
+        let mut s = gst::Structure::builder("application/x-webrtcsink-video-encoder-stats")
             .field("bitrate", self.bitrate().unwrap_or(0i32))
             .field("mitigation-mode", self.mitigation_mode)
             .field("codec-name", self.codec_name.as_str())
             .field(
                 "fec-percentage",
-                self.transceiver.property::<u32>("fec-percentage"),
+                self.transceiver.as_ref().map_or(0, |transceiver| {
+                    transceiver.property::<u32>("fec-percentage")
+                }),
             )
-            .build()
+            .build();
+
+        if let Some(ref layer) = self.layer {
+            s.set("rid", layer.layer.rid.as_str());
+            s.set("active", layer.active);
+        }
+
+        s
     }
 }
 
-impl State {
-    fn finalize_session(&mut self, element: &super::BaseWebRTCSink, session: &mut Session) {
-        gst::info!(CAT, "Ending session {}", session.id);
-        session.pipeline.debug_to_dot_file_with_ts(
-            gst::DebugGraphDetails::all(),
-            format!("removing-session-{}-", session.id),
-        );
+impl SharedEncoder {
+    fn new(
+        element: &super::BaseWebRTCSink,
+        producer: &StreamProducer,
+        stream_name: &str,
+        in_caps: &gst::Caps,
+        codec: &Codec,
+        bitrates: &[u32],
+    ) -> Result<Self, Error> {
+        let pipeline = gst::Pipeline::builder()
+            .name(format!(
+                "shared-encoder-pipeline-{stream_name}-{}",
+                codec.encoder_name().unwrap_or_default()
+            ))
+            .build();
 
-        for ssrc in session.webrtc_pads.keys() {
-            session.links.remove(ssrc);
-        }
+        let appsrc = make_element("appsrc", Some(stream_name))?;
+        let tee = make_element("tee", None)?;
+        pipeline.add_many([&appsrc, &tee]).unwrap();
+        appsrc.link(&tee)?;
 
-        let stats_collection_handle = session.stats_collection_handle.take();
+        let video_info = gst_video::VideoInfo::from_caps(in_caps)?;
+        let mut renditions = Vec::new();
 
-        let finalizing_sessions = self.finalizing_sessions.clone();
-        let session_id = session.id.clone();
-        let (sessions, _cvar) = &*finalizing_sessions;
-        sessions.lock().unwrap().insert(session_id.clone());
+        for bitrate in bitrates {
+            let raw_filter = codec.raw_converter_filter()?;
+            let encoder = codec
+                .build_encoder()
+                .expect("We should always have an encoder for negotiated codecs")?;
+            // Consumers may have negotiated different profiles,
+            // pick the most compatible one
+            let parser_filter = gst::ElementFactory::make("capsfilter")
+                .property("caps", codec.parser_caps(true))
+                .build()
+                .with_context(|| "Failed to make element capsfilter")?;
+            let appsink = make_element("appsink", None)?
+                .downcast::<gst_app::AppSink>()
+                .unwrap();
+
+            let mut elements = vec![
+                make_element("queue", None)?,
+                make_converter_for_video_caps(in_caps, codec)?,
+                raw_filter.clone(),
+                encoder.clone(),
+                make_element("capsfilter", None)?,
+            ];
+            elements.extend(codec.build_parser()?);
+            elements.push(parser_filter.clone());
+            elements.push(appsink.clone().upcast());
+
+            pipeline.add_many(&elements).unwrap();
+            elements.insert(0, tee.clone());
+            gst::Element::link_many(&elements)
+                .with_context(|| format!("Linking shared encoder for {stream_name}"))?;
+
+            element.emit_by_name::<bool>("encoder-setup", &[&"shared", &stream_name, &encoder]);
+
+            let mut encoder = VideoEncoder::new(
+                &EncodingChain {
+                    raw_filter: Some(raw_filter),
+                    encoder: Some(encoder),
+                    pay_filter: parser_filter,
+                },
+                video_info.clone(),
+                "shared",
+                codec.caps.structure(0).unwrap().name(),
+                None,
+                stream_name.to_string(),
+            )
+            .expect("Raw input is always encoded");
 
-        let element = element.clone();
-        let pipeline = session.pipeline.clone();
-        RUNTIME.spawn_blocking(move || {
-            if let Some(stats_collection_handle) = stats_collection_handle {
-                stats_collection_handle.abort();
-                let _ = RUNTIME.block_on(stats_collection_handle);
+            if encoder.set_bitrate(element, *bitrate as i32).is_err() {
+                gst::warning!(
+                    CAT,
+                    obj = element,
+                    "Bitrate handling is not supported yet for {}, all renditions of {} \
+                     will be identical",
+                    encoder.factory_name,
+                    stream_name
+                );
             }
 
-            let _ = pipeline.set_state(gst::State::Null);
-            drop(pipeline);
+            renditions.push(SharedRendition {
+                bitrate: *bitrate,
+                encoder,
+                producer: StreamProducer::from(&appsink),
+            });
+        }
 
-            let (sessions, cvar) = &*finalizing_sessions;
-            let mut sessions = sessions.lock().unwrap();
-            sessions.remove(&session_id);
-            cvar.notify_one();
+        let clock = element.clock();
+        pipeline.use_clock(clock.as_ref());
+        pipeline.set_start_time(gst::ClockTime::NONE);
+        pipeline.set_base_time(element.base_time().unwrap());
 
-            gst::debug!(CAT, obj = element, "Session {session_id} ended");
+        let mut bus_stream = CustomBusStream::new(element, &pipeline.bus().unwrap());
+        let element_clone = element.downgrade();
+        let pipeline_clone = pipeline.downgrade();
+        RUNTIME.spawn(async move {
+            while let Some(msg) = bus_stream.next().await {
+                let Some(element) = element_clone.upgrade() else {
+                    break;
+                };
+                let Some(pipeline) = pipeline_clone.upgrade() else {
+                    break;
+                };
+                match msg.view() {
+                    gst::MessageView::Error(err) => {
+                        gst::error!(
+                            CAT,
+                            obj = pipeline,
+                            "shared encoder error: {}, details: {:?}",
+                            err.error(),
+                            err.debug()
+                        );
+                        element.imp().remove_shared_encoder_sessions(&pipeline);
+                    }
+                    gst::MessageView::Latency(..) => {
+                        gst::info!(CAT, obj = pipeline, "Recalculating latency");
+                        let _ = pipeline.recalculate_latency();
+                    }
+                    _ => (),
+                }
+            }
         });
-    }
 
-    fn end_session(
-        &mut self,
-        element: &super::BaseWebRTCSink,
-        session_id: &str,
-    ) -> Option<Session> {
-        if let Some(session) = self.sessions.remove(session_id) {
-            let mut session = session.into_inner();
-            self.finalize_session(element, &mut session);
-            Some(session)
-        } else {
-            None
-        }
-    }
+        let appsrc = appsrc.downcast::<gst_app::AppSrc>().unwrap();
+        gst_utils::StreamProducer::configure_consumer(&appsrc);
+        pipeline
+            .set_state(gst::State::Playing)
+            .with_context(|| format!("Starting shared encoder for {stream_name}"))?;
 
-    fn should_start_signaller(&mut self, element: &super::BaseWebRTCSink) -> bool {
-        self.signaller_state == SignallerState::Stopped
-            && element.current_state() >= gst::State::Paused
-            && self.codec_discovery_done
-    }
+        let link = producer
+            .add_consumer(&appsrc)
+            .map_err(|err| anyhow!("Could not link producer: {:?}", err))?;
 
-    fn queue_discovery(&mut self, stream_name: &str, discovery_info: DiscoveryInfo) {
-        if let Some(discos) = self.discoveries.get_mut(stream_name) {
-            discos.push(discovery_info);
-        } else {
-            self.discoveries
-                .insert(stream_name.to_string(), vec![discovery_info]);
-        }
+        Ok(Self {
+            pipeline,
+            renditions,
+            link,
+            sessions: HashSet::new(),
+        })
     }
 
-    fn remove_discovery(&mut self, stream_name: &str, discovery_info: &DiscoveryInfo) {
-        if let Some(discos) = self.discoveries.get_mut(stream_name) {
-            let position = discos
-                .iter()
-                .position(|d| d.id == discovery_info.id)
-                .expect(
-                    "We expect discovery to always be in the list of discoverers when removing",
-                );
-            discos.remove(position);
-        }
+    fn producers(&self) -> Vec<(u32, StreamProducer)> {
+        self.renditions
+            .iter()
+            .map(|rendition| (rendition.bitrate, rendition.producer.clone()))
+            .collect()
     }
 }
 
-impl Session {
-    fn new(
-        id: String,
-        pipeline: gst::Pipeline,
-        webrtcbin: gst::Element,
-        peer_id: String,
-        congestion_controller: Option<CongestionController>,
+impl SharedEncoderLink {
+    /// Switches to the highest rendition that fits the bitrate
+    fn set_bitrate(&mut self, element: &super::BaseWebRTCSink, bitrate: i32) {
+        let rendition = pick_rendition(
+            self.renditions.iter().map(|(bitrate, _)| *bitrate),
+            bitrate as i64,
+        );
+
+        if rendition == self.current {
+            return;
+        }
+
+        gst::log!(
+            CAT,
+            obj = element,
+            "switching {} to shared rendition {} for bitrate {}",
+            self.stream_name,
+            self.renditions[rendition].0,
+            bitrate
+        );
+
+        match self
+            .link
+            .change_producer(&self.renditions[rendition].1, false)
+        {
+            Ok(()) => self.current = rendition,
+            Err(err) => gst::warning!(
+                CAT,
+                obj = element,
+                "Failed to switch {} to shared rendition {}: {:?}",
+                self.stream_name,
+                self.renditions[rendition].0,
+                err
+            ),
+        }
+    }
+
+    fn gather_stats(&self) -> gst::Structure {
+        gst::Structure::builder("application/x-webrtcsink-shared-encoder-stats")
+            .field("stream-name", self.stream_name.as_str())
+            .field("bitrate", self.renditions[self.current].0)
+            .field(
+                "fec-percentage",
+                self.transceiver.property::<u32>("fec-percentage"),
+            )
+            .build()
+    }
+}
+
+/// Returns the index of the highest of the renditions, ordered by increasing
+/// bitrate, that fits `bitrate`, or of the lowest one if none does
+fn pick_rendition(
+    mut bitrates: impl DoubleEndedIterator<Item = u32> + ExactSizeIterator,
+    bitrate: i64,
+) -> usize {
+    bitrates
+        .rposition(|rendition_bitrate| rendition_bitrate as i64 <= bitrate)
+        .unwrap_or(0)
+}
+
+/// Returns the sorted and deduplicated bitrates of the
+/// shared-encoder-renditions property
+fn rendition_bitrates(renditions: &gst::Array) -> Vec<u32> {
+    let mut bitrates = renditions
+        .iter()
+        .map(|value| value.get::<u32>().expect("type checked upstream"))
+        .collect::<Vec<_>>();
+    bitrates.sort_unstable();
+    bitrates.dedup();
+    bitrates
+}
+
+/// Identifies the shared encoder of a stream for the consumers of a codec
+fn shared_encoder_key(stream_name: &str, codec: &Codec) -> String {
+    format!("{stream_name} {}", codec.caps)
+}
+
+/// Splits the inputs into those that are recorded and those that are
+/// skipped, as only the first video stream can be muxed
+fn select_recording_inputs(
+    inputs: &[RecordingInput],
+) -> (Vec<&RecordingInput>, Vec<&RecordingInput>) {
+    let mut has_video = false;
+
+    inputs.iter().partition(|input| {
+        let skip = input.codec.is_video() && has_video;
+        has_video |= input.codec.is_video();
+        !skip
+    })
+}
+
+impl Recording {
+    fn new(
+        element: &super::BaseWebRTCSink,
+        inputs: &[RecordingInput],
+        location: &str,
+        muxer: &str,
+        max_size_time: u64,
+        video_bitrate: u32,
+    ) -> Result<Self, Error> {
+        let pipeline = gst::Pipeline::builder()
+            .name(format!("recording-pipeline-{}", element.name()))
+            .build();
+
+        let splitmuxsink = gst::ElementFactory::make("splitmuxsink")
+            .property("location", location)
+            .property("muxer-factory", muxer)
+            .property("max-size-time", max_size_time)
+            .property("send-keyframe-requests", max_size_time > 0)
+            .build()
+            .with_context(|| "Failed to make element splitmuxsink")?;
+        pipeline.add(&splitmuxsink).unwrap();
+
+        let (inputs, skipped) = select_recording_inputs(inputs);
+        for input in skipped {
+            gst::warning!(
+                CAT,
+                obj = element,
+                "Only one video stream can be recorded, skipping {}",
+                input.stream_name
+            );
+        }
+
+        // One appsrc per selected input, in the same order
+        let mut appsrcs = Vec::new();
+
+        for input in &inputs {
+            let stream_name = input.stream_name.as_str();
+            let codec = &input.codec;
+
+            let sinkpad = splitmuxsink
+                .request_pad_simple(if codec.is_video() {
+                    "video"
+                } else {
+                    "audio_%u"
+                })
+                .ok_or_else(|| anyhow!("Failed to request recording pad for {stream_name}"))?;
+
+            let appsrc = make_element("appsrc", Some(stream_name))?;
+            let mut elements = vec![appsrc.clone(), make_element("queue", None)?];
+
+            let encoding_elements = if is_raw_caps(&input.in_caps) {
+                elements.push(match codec.is_video() {
+                    true => make_converter_for_video_caps(&input.in_caps, codec)?.upcast(),
+                    false => {
+                        gst::parse::bin_from_description("audioresample ! audioconvert", true)?
+                            .upcast()
+                    }
+                });
+
+                let raw_filter = codec.raw_converter_filter()?;
+                let encoder = codec
+                    .build_encoder()
+                    .expect("We should always have an encoder for negotiated codecs")?;
+                elements.push(raw_filter.clone());
+                elements.push(encoder.clone());
+                elements.push(make_element("capsfilter", None)?);
+
+                Some((raw_filter, encoder))
+            } else {
+                None
+            };
+
+            elements.extend(codec.build_parser()?);
+            let parser_filter = gst::ElementFactory::make("capsfilter")
+                .property("caps", codec.parser_caps(false))
+                .build()
+                .with_context(|| "Failed to make element capsfilter")?;
+            elements.push(parser_filter.clone());
+
+            pipeline.add_many(&elements).unwrap();
+            gst::Element::link_many(&elements)
+                .with_context(|| format!("Linking recording of {stream_name}"))?;
+            parser_filter
+                .static_pad("src")
+                .unwrap()
+                .link(&sinkpad)
+                .with_context(|| format!("Linking recording of {stream_name} to the muxer"))?;
+
+            if let Some((raw_filter, encoder)) = encoding_elements {
+                element
+                    .emit_by_name::<bool>("encoder-setup", &[&"recording", &stream_name, &encoder]);
+
+                if codec.is_video() {
+                    let mut encoder = VideoEncoder::new(
+                        &EncodingChain {
+                            raw_filter: Some(raw_filter),
+                            encoder: Some(encoder),
+                            pay_filter: parser_filter,
+                        },
+                        gst_video::VideoInfo::from_caps(&input.in_caps)?,
+                        "recording",
+                        codec.caps.structure(0).unwrap().name(),
+                        None,
+                        stream_name.to_string(),
+                    )
+                    .expect("Raw input is always encoded");
+
+                    if encoder.set_bitrate(element, video_bitrate as i32).is_err() {
+                        gst::warning!(
+                            CAT,
+                            obj = element,
+                            "Bitrate handling is not supported yet for {}, recording {} \
+                             with its default bitrate",
+                            encoder.factory_name,
+                            stream_name
+                        );
+                    }
+                }
+            }
+
+            appsrcs.push(appsrc.downcast::<gst_app::AppSrc>().unwrap());
+        }
+
+        if appsrcs.is_empty() {
+            anyhow::bail!("No stream to record");
+        }
+
+        let clock = element.clock();
+        pipeline.use_clock(clock.as_ref());
+        pipeline.set_start_time(gst::ClockTime::NONE);
+        pipeline.set_base_time(element.base_time().unwrap());
+
+        let (eos_sender, eos_receiver) = futures::channel::oneshot::channel();
+        let mut eos_sender = Some(eos_sender);
+        let mut bus_stream = CustomBusStream::new(element, &pipeline.bus().unwrap());
+        let element_clone = element.downgrade();
+        let pipeline_clone = pipeline.downgrade();
+        RUNTIME.spawn(async move {
+            while let Some(msg) = bus_stream.next().await {
+                let Some(element) = element_clone.upgrade() else {
+                    break;
+                };
+                let Some(pipeline) = pipeline_clone.upgrade() else {
+                    break;
+                };
+                match msg.view() {
+                    gst::MessageView::Error(err) => {
+                        gst::error!(
+                            CAT,
+                            obj = pipeline,
+                            "recording error: {}, details: {:?}",
+                            err.error(),
+                            err.debug()
+                        );
+                        // Not fatal, the consumers are unaffected
+                        gst::element_warning!(
+                            element,
+                            gst::ResourceError::Write,
+                            ["Recording failed: {}", err.error()]
+                        );
+                        element.imp().abort_recording(&pipeline);
+                    }
+                    gst::MessageView::Eos(..) => {
+                        if let Some(eos_sender) = eos_sender.take() {
+                            let _ = eos_sender.send(());
+                        }
+                    }
+                    gst::MessageView::Latency(..) => {
+                        gst::info!(CAT, obj = pipeline, "Recalculating latency");
+                        let _ = pipeline.recalculate_latency();
+                    }
+                    _ => (),
+                }
+            }
+        });
+
+        for appsrc in &appsrcs {
+            gst_utils::StreamProducer::configure_consumer(appsrc);
+        }
+
+        pipeline
+            .set_state(gst::State::Playing)
+            .with_context(|| "Starting recording")?;
+
+        let mut recording = Self {
+            pipeline,
+            links: Vec::new(),
+            appsrcs,
+            eos_receiver: Some(eos_receiver),
+        };
+
+        for (input, appsrc) in inputs.iter().zip(recording.appsrcs.iter()) {
+            match input.producer.add_consumer(appsrc) {
+                Ok(link) => recording.links.push(link),
+                Err(err) => {
+                    let _ = recording.pipeline.set_state(gst::State::Null);
+                    anyhow::bail!("Could not link producer: {:?}", err);
+                }
+            }
+        }
+
+        Ok(recording)
+    }
+
+    /// Drains the recording so that the muxer can finalize
+    /// the files, then shuts it down
+    fn finalize(mut self, element: &super::BaseWebRTCSink) {
+        self.links.clear();
+
+        for appsrc in &self.appsrcs {
+            let _ = appsrc.end_of_stream();
+        }
+
+        if let Some(eos_receiver) = self.eos_receiver.take() {
+            let res = RUNTIME.block_on(tokio::time::timeout(RECORDING_EOS_TIMEOUT, eos_receiver));
+            if !matches!(res, Ok(Ok(()))) {
+                gst::warning!(
+                    CAT,
+                    obj = element,
+                    "Recording did not drain, files may be incomplete"
+                );
+            }
+        }
+
+        let _ = self.pipeline.set_state(gst::State::Null);
+
+        gst::info!(CAT, obj = element, "Recording stopped");
+    }
+}
+
+impl State {
+    fn finalize_session(&mut self, element: &super::BaseWebRTCSink, session: &mut Session) {
+        gst::info!(CAT, "Ending session {}", session.id);
+        session.pipeline.debug_to_dot_file_with_ts(
+            gst::DebugGraphDetails::all(),
+            format!("removing-session-{}-", session.id),
+        );
+
+        for ssrc in session.webrtc_pads.keys() {
+            session.links.remove(ssrc);
+        }
+
+        session.shared_links.clear();
+        element.imp().release_shared_encoders(&session.id);
+
+        let stats_collection_handle = session.stats_collection_handle.take();
+
+        let finalizing_sessions = self.finalizing_sessions.clone();
+        let session_id = session.id.clone();
+        let (sessions, _cvar) = &*finalizing_sessions;
+        sessions.lock().unwrap().insert(session_id.clone());
+
+        let element = element.clone();
+        let pipeline = session.pipeline.clone();
+        RUNTIME.spawn_blocking(move || {
+            if let Some(stats_collection_handle) = stats_collection_handle {
+                stats_collection_handle.abort();
+                let _ = RUNTIME.block_on(stats_collection_handle);
+            }
+
+            let _ = pipeline.set_state(gst::State::Null);
+            drop(pipeline);
+
+            let (sessions, cvar) = &*finalizing_sessions;
+            let mut sessions = sessions.lock().unwrap();
+            sessions.remove(&session_id);
+            cvar.notify_one();
+
+            gst::debug!(CAT, obj = element, "Session {session_id} ended");
+        });
+    }
+
+    fn end_session(
+        &mut self,
+        element: &super::BaseWebRTCSink,
+        session_id: &str,
+    ) -> Option<Session> {
+        if let Some(session) = self.sessions.remove(session_id) {
+            let mut session = session.into_inner();
+            self.finalize_session(element, &mut session);
+            Some(session)
+        } else {
+            None
+        }
+    }
+
+    fn should_start_signaller(&mut self, element: &super::BaseWebRTCSink) -> bool {
+        self.signaller_state == SignallerState::Stopped
+            && element.current_state() >= gst::State::Paused
+            && self.codec_discovery_done
+    }
+
+    fn queue_discovery(&mut self, stream_name: &str, discovery_info: DiscoveryInfo) {
+        if let Some(discos) = self.discoveries.get_mut(stream_name) {
+            discos.push(discovery_info);
+        } else {
+            self.discoveries
+                .insert(stream_name.to_string(), vec![discovery_info]);
+        }
+    }
+
+    fn remove_discovery(&mut self, stream_name: &str, discovery_info: &DiscoveryInfo) {
+        if let Some(discos) = self.discoveries.get_mut(stream_name) {
+            let position = discos
+                .iter()
+                .position(|d| d.id == discovery_info.id)
+                .expect(
+                    "We expect discovery to always be in the list of discoverers when removing",
+                );
+            discos.remove(position);
+        }
+    }
+}
+
+impl Session {
+    fn new(
+        id: String,
+        pipeline: gst::Pipeline,
+        webrtcbin: gst::Element,
+        peer_id: String,
+        congestion_controller: Option<CongestionController>,
         rtpgccbwe: Option<gst::Element>,
         cc_info: CCInfo,
     ) -> Self {
@@ -1237,6 +2110,7 @@ impl Session {
             sdp: None,
             webrtc_pads: HashMap::new(),
             encoders: Vec::new(),
+            shared_links: Vec::new(),
             links: HashMap::new(),
             stats_sigid: None,
             codecs: None,
@@ -1254,8 +2128,16 @@ impl Session {
             .map(|s| s.to_send_value())
             .collect::<gst::Array>();
 
+        let shared_encoder_stats = self
+            .shared_links
+            .iter()
+            .map(SharedEncoderLink::gather_stats)
+            .map(|s| s.to_send_value())
+            .collect::<gst::Array>();
+
         let our_stats = gst::Structure::builder("application/x-webrtcsink-consumer-stats")
             .field("video-encoders", encoder_stats)
+            .field("shared-encoders", shared_encoder_stats)
             .build();
 
         ret.set("consumer-stats", our_stats);
@@ -1317,6 +2199,57 @@ impl Session {
             }
         };
 
+        if !webrtc_pad.simulcast_layers.is_empty() {
+            let sdp_media = self
+                .sdp
+                .as_ref()
+                .unwrap()
+                .media(webrtc_pad.media_idx)
+                .unwrap();
+            let layers = accepted_simulcast_layers(sdp_media, &webrtc_pad.simulcast_layers);
+
+            if layers.is_empty() {
+                gst::info!(
+                    CAT,
+                    obj = element,
+                    "Consumer {} did not accept simulcast for media {}",
+                    self.peer_id,
+                    webrtc_pad.media_idx
+                );
+            } else if !is_raw_caps(&webrtc_pad.in_caps) {
+                gst::warning!(
+                    CAT,
+                    obj = element,
+                    "Can't produce simulcast layers of already encoded stream {}",
+                    stream_name
+                );
+            } else {
+                return self
+                    .connect_simulcast_input_stream(element, producer, webrtc_pad, &codec, layers);
+            }
+        }
+
+        let shared_renditions = if codec.is_video()
+            && is_raw_caps(&webrtc_pad.in_caps)
+            && element.imp().settings.lock().unwrap().shared_encoders
+        {
+            Some(element.imp().acquire_shared_encoder(
+                &self.id,
+                producer,
+                stream_name,
+                &webrtc_pad.in_caps,
+                &codec,
+            )?)
+        } else {
+            None
+        };
+
+        // Shared encoders feed us encoded data
+        let input_caps = match shared_renditions {
+            Some(_) => gst::Caps::new_empty_simple(codec.caps.structure(0).unwrap().name()),
+            None => webrtc_pad.in_caps.clone(),
+        };
+
         let appsrc = make_element("appsrc", Some(stream_name))?;
         self.pipeline.add(&appsrc).unwrap();
 
@@ -1329,7 +2262,7 @@ impl Session {
             payloader,
             encoding_chain,
         } = PayloadChainBuilder::new(
-            &webrtc_pad.in_caps,
+            &input_caps,
             &output_caps,
             &codec,
             element.emit_by_name::<Option<gst::Element>>(
@@ -1343,21 +2276,7 @@ impl Session {
             element.emit_by_name::<bool>("encoder-setup", &[&self.peer_id, &stream_name, &enc]);
         }
 
-        let sdp = self.sdp.as_ref().unwrap();
-        let sdp_media = sdp.media(webrtc_pad.media_idx).unwrap();
-
-        let mut global_caps = gst::Caps::new_empty_simple("application/x-unknown");
-
-        sdp.attributes_to_caps(global_caps.get_mut().unwrap())
-            .unwrap();
-        sdp_media
-            .attributes_to_caps(global_caps.get_mut().unwrap())
-            .unwrap();
-
-        let caps = sdp_media
-            .caps_from_media(payload)
-            .unwrap()
-            .intersect(&global_caps);
+        let caps = self.media_caps(webrtc_pad.media_idx, payload);
 
         element.imp().configure_payloader(
             &self.peer_id,
@@ -1381,63 +2300,19 @@ impl Session {
             .property::<gst_webrtc::WebRTCRTPTransceiver>("transceiver");
         transceiver.set_property("codec-preferences", None::<gst::Caps>);
 
-        let s = caps.structure(0).unwrap();
-        let mut filtered_s = gst::Structure::new_empty("application/x-rtp");
-
-        filtered_s.extend(s.iter().filter_map(|(key, value)| {
-            if key.starts_with("a-") {
-                None
-            } else {
-                Some((key, value.to_owned()))
-            }
-        }));
-        filtered_s.set("ssrc", webrtc_pad.ssrc);
-
-        let caps = gst::Caps::builder_full().structure(filtered_s).build();
+        pay_filter.set_property("caps", payloader_filter_caps(&caps, webrtc_pad.ssrc));
 
-        pay_filter.set_property("caps", caps);
-
-        if codec.is_video() {
+        if codec.is_video() && shared_renditions.is_none() {
             let video_info = gst_video::VideoInfo::from_caps(&webrtc_pad.in_caps)?;
-            if let Some(mut enc) = VideoEncoder::new(
+            if let Some(enc) = VideoEncoder::new(
                 &encoding_chain,
                 video_info,
                 &self.id,
                 codec.caps.structure(0).unwrap().name(),
-                transceiver,
+                Some(transceiver.clone()),
                 stream_name.clone(),
             ) {
-                match self.cc_info.heuristic {
-                    WebRTCSinkCongestionControl::Disabled => {
-                        // If congestion control is disabled, we simply use the highest
-                        // known "safe" value for the bitrate.
-                        let _ = enc.set_bitrate(element, self.cc_info.max_bitrate as i32);
-                        enc.transceiver.set_property("fec-percentage", 50u32);
-                    }
-                    WebRTCSinkCongestionControl::Homegrown => {
-                        if let Some(congestion_controller) = self.congestion_controller.as_mut() {
-                            if let Ok(bitrate) = enc.bitrate() {
-                                congestion_controller.target_bitrate_on_delay += bitrate;
-                                congestion_controller.target_bitrate_on_loss =
-                                    congestion_controller.target_bitrate_on_delay;
-                                enc.transceiver.set_property("fec-percentage", 0u32);
-                            }
-                        } else {
-                            /* If congestion control is disabled, we simply use the highest
-                             * known "safe" value for the bitrate. */
-                            let _ = enc.set_bitrate(element, self.cc_info.max_bitrate as i32);
-                            enc.transceiver.set_property("fec-percentage", 50u32);
-                        }
-                    }
-                    _ => enc.transceiver.set_property("fec-percentage", 0u32),
-                }
-
-                self.encoders.push(enc);
-
-                if let Some(rtpgccbwe) = self.rtpgccbwe.as_ref() {
-                    let max_bitrate = self.cc_info.max_bitrate * (self.encoders.len() as u32);
-                    rtpgccbwe.set_property("max-bitrate", max_bitrate);
-                }
+                self.add_encoder(element, enc);
             }
         }
 
@@ -1455,6 +2330,183 @@ impl Session {
             .link(&webrtc_pad.pad)
             .with_context(|| format!("Connecting input stream for {}", self.peer_id))?;
 
+        if let Some(renditions) = shared_renditions {
+            return self.add_shared_link(element, stream_name, &appsrc, transceiver, renditions);
+        }
+
+        match producer.add_consumer(&appsrc) {
+            Ok(link) => {
+                self.links.insert(webrtc_pad.ssrc, link);
+                Ok(())
+            }
+            Err(err) => Err(anyhow!("Could not link producer: {:?}", err)),
+        }
+    }
+
+    /// Called instead of connect_input_stream when the consumer accepted
+    /// simulcast layers for a raw video stream: encodes the stream once per
+    /// layer, and funnels the RTP streams of all layers to the WebRTCPad
+    fn connect_simulcast_input_stream(
+        &mut self,
+        element: &super::BaseWebRTCSink,
+        producer: &StreamProducer,
+        webrtc_pad: &WebRTCPad,
+        codec: &Codec,
+        layers: Vec<SimulcastLayer>,
+    ) -> Result<(), Error> {
+        let stream_name = webrtc_pad.stream_name.as_ref().unwrap();
+        let payload = webrtc_pad.payload.unwrap();
+
+        gst::info!(
+            CAT,
+            obj = element,
+            "Connecting simulcast layers {:?} of input stream {} for consumer {}",
+            layers.iter().map(|layer| &layer.rid).collect::<Vec<_>>(),
+            stream_name,
+            self.peer_id,
+        );
+
+        let sdp_media = self
+            .sdp
+            .as_ref()
+            .unwrap()
+            .media(webrtc_pad.media_idx)
+            .unwrap();
+        let rid_ext_id = find_extmap_id(sdp_media, RTP_STREAM_ID_URI)
+            .ok_or_else(|| anyhow!("Simulcast accepted without RTP stream ID extension"))?;
+        let mid_ext_id = find_extmap_id(sdp_media, RTP_MID_URI);
+
+        let caps = self.media_caps(webrtc_pad.media_idx, payload);
+
+        // See connect_input_stream()
+        let transceiver = webrtc_pad
+            .pad
+            .property::<gst_webrtc::WebRTCRTPTransceiver>("transceiver");
+        transceiver.set_property("codec-preferences", None::<gst::Caps>);
+        let mid = transceiver.mid();
+
+        let appsrc = make_element("appsrc", Some(stream_name))?;
+        let tee = make_element("tee", None)?;
+        let funnel = make_element("rtpfunnel", None)?;
+        self.pipeline.add_many([&appsrc, &tee, &funnel]).unwrap();
+        appsrc.link(&tee)?;
+
+        let output_caps = codec.output_filter().unwrap_or_else(gst::Caps::new_any);
+        let video_info = gst_video::VideoInfo::from_caps(&webrtc_pad.in_caps)?;
+        let first_encoder = self.encoders.len();
+        let mut ssrcs = self.webrtc_pads.keys().copied().collect::<HashSet<_>>();
+
+        for (idx, layer) in layers.into_iter().enumerate() {
+            let queue = make_element("queue", None)?;
+            let valve = make_element("valve", None)?;
+            self.pipeline.add_many([&queue, &valve]).unwrap();
+            gst::Element::link_many([&tee, &queue, &valve])?;
+
+            let PayloadChain {
+                payloader,
+                encoding_chain,
+            } = PayloadChainBuilder::new(
+                &webrtc_pad.in_caps,
+                &output_caps,
+                codec,
+                element.emit_by_name::<Option<gst::Element>>(
+                    "request-encoded-filter",
+                    &[&Some(&self.peer_id), &stream_name, &codec.caps],
+                ),
+            )
+            .build(&self.pipeline, &valve)?;
+
+            if let Some(ref enc) = encoding_chain.encoder {
+                element.emit_by_name::<bool>("encoder-setup", &[&self.peer_id, &stream_name, &enc]);
+            }
+
+            // The lowest layer uses the SSRC we offered
+            let ssrc = if idx == 0 {
+                webrtc_pad.ssrc
+            } else {
+                loop {
+                    let ssrc = fastrand::u32(..);
+                    if ssrcs.insert(ssrc) {
+                        break ssrc;
+                    }
+                }
+            };
+
+            element.imp().configure_payloader(
+                &self.peer_id,
+                stream_name,
+                &payloader,
+                codec,
+                Some(ssrc),
+                Some(&caps),
+                ExtensionConfigurationType::Skip,
+            )?;
+
+            let rid_ext = gst_rtp::RTPHeaderExtension::create_from_uri(RTP_STREAM_ID_URI)
+                .context("Failed to create RTP stream ID extension, make sure 'gst-plugins-good:rtpmanager' is installed")?;
+            rid_ext.set_id(rid_ext_id);
+            rid_ext.set_property("rid", &layer.rid);
+            payloader.emit_by_name::<()>("add-extension", &[&rid_ext]);
+
+            if let (Some(mid_ext_id), Some(mid)) = (mid_ext_id, mid.as_ref()) {
+                if let Some(mid_ext) = gst_rtp::RTPHeaderExtension::create_from_uri(RTP_MID_URI) {
+                    mid_ext.set_id(mid_ext_id);
+                    mid_ext.set_property("mid", mid);
+                    payloader.emit_by_name::<()>("add-extension", &[&mid_ext]);
+                }
+            }
+
+            let pay_filter = make_element("capsfilter", None)?;
+            pay_filter.set_property("caps", payloader_filter_caps(&caps, ssrc));
+            self.pipeline.add(&pay_filter).unwrap();
+            gst::Element::link_many([&encoding_chain.pay_filter, &pay_filter, &funnel])?;
+
+            let mut enc = VideoEncoder::new(
+                &encoding_chain,
+                video_info.clone(),
+                &self.id,
+                codec.caps.structure(0).unwrap().name(),
+                Some(transceiver.clone()),
+                stream_name.clone(),
+            )
+            .expect("Raw input is always encoded");
+
+            enc.layer = Some(EncoderLayer {
+                layer,
+                valve,
+                active: true,
+            });
+
+            // Scale the layer down right away
+            if let Ok(bitrate) = enc.bitrate() {
+                let _ = enc.set_bitrate(element, bitrate);
+            }
+
+            self.add_encoder(element, enc);
+        }
+
+        if self.congestion_controller.is_none() && self.rtpgccbwe.is_none() {
+            set_encoders_bitrate(
+                element,
+                &mut self.encoders[first_encoder..],
+                &mut [],
+                self.cc_info.max_bitrate as i32,
+                50,
+            );
+        }
+
+        let appsrc = appsrc.downcast::<gst_app::AppSrc>().unwrap();
+        gst_utils::StreamProducer::configure_consumer(&appsrc);
+        self.pipeline
+            .sync_children_states()
+            .with_context(|| format!("Connecting input stream for {}", self.peer_id))?;
+
+        funnel
+            .static_pad("src")
+            .unwrap()
+            .link(&webrtc_pad.pad)
+            .with_context(|| format!("Connecting input stream for {}", self.peer_id))?;
+
         match producer.add_consumer(&appsrc) {
             Ok(link) => {
                 self.links.insert(webrtc_pad.ssrc, link);
@@ -1463,6 +2515,147 @@ impl Session {
             Err(err) => Err(anyhow!("Could not link producer: {:?}", err)),
         }
     }
+
+    /// Caps of the negotiated payload for a media in the SDP
+    fn media_caps(&self, media_idx: u32, payload: i32) -> gst::Caps {
+        let sdp = self.sdp.as_ref().unwrap();
+        let sdp_media = sdp.media(media_idx).unwrap();
+
+        let mut global_caps = gst::Caps::new_empty_simple("application/x-unknown");
+
+        sdp.attributes_to_caps(global_caps.get_mut().unwrap())
+            .unwrap();
+        sdp_media
+            .attributes_to_caps(global_caps.get_mut().unwrap())
+            .unwrap();
+
+        sdp_media
+            .caps_from_media(payload)
+            .unwrap()
+            .intersect(&global_caps)
+    }
+
+    /// Starts consuming the rendition of a shared encoder that best
+    /// fits the initial bitrate
+    fn add_shared_link(
+        &mut self,
+        element: &super::BaseWebRTCSink,
+        stream_name: &str,
+        appsrc: &gst_app::AppSrc,
+        transceiver: gst_webrtc::WebRTCRTPTransceiver,
+        renditions: Vec<(u32, StreamProducer)>,
+    ) -> Result<(), Error> {
+        let congestion_controlled =
+            self.congestion_controller.is_some() || self.rtpgccbwe.is_some();
+
+        // Without congestion control, we simply use the highest rendition
+        // that fits the highest known "safe" value for the bitrate.
+        let initial_bitrate = if congestion_controlled {
+            self.cc_info.start_bitrate
+        } else {
+            self.cc_info.max_bitrate
+        };
+
+        let current = pick_rendition(
+            renditions.iter().map(|(bitrate, _)| *bitrate),
+            initial_bitrate as i64,
+        );
+
+        let link = renditions[current]
+            .1
+            .add_consumer(appsrc)
+            .map_err(|err| anyhow!("Could not link producer: {:?}", err))?;
+
+        gst::debug!(
+            CAT,
+            obj = element,
+            "Consumer {} starting with rendition {} of shared encoder for {}",
+            self.peer_id,
+            renditions[current].0,
+            stream_name
+        );
+
+        if let Some(congestion_controller) = self.congestion_controller.as_mut() {
+            congestion_controller.target_bitrate_on_delay += renditions[current].0 as i32;
+            congestion_controller.target_bitrate_on_loss =
+                congestion_controller.target_bitrate_on_delay;
+        }
+
+        transceiver.set_property(
+            "fec-percentage",
+            if congestion_controlled { 0u32 } else { 50u32 },
+        );
+
+        self.shared_links.push(SharedEncoderLink {
+            stream_name: stream_name.to_string(),
+            renditions,
+            current,
+            link,
+            transceiver,
+        });
+
+        if let Some(rtpgccbwe) = self.rtpgccbwe.as_ref() {
+            let max_bitrate =
+                self.cc_info.max_bitrate * ((self.encoders.len() + self.shared_links.len()) as u32);
+            rtpgccbwe.set_property("max-bitrate", max_bitrate);
+        }
+
+        Ok(())
+    }
+
+    /// Sets up congestion control for a new encoder and starts tracking it
+    fn add_encoder(&mut self, element: &super::BaseWebRTCSink, mut enc: VideoEncoder) {
+        match self.cc_info.heuristic {
+            WebRTCSinkCongestionControl::Disabled => {
+                // If congestion control is disabled, we simply use the highest
+                // known "safe" value for the bitrate.
+                let _ = enc.set_bitrate(element, self.cc_info.max_bitrate as i32);
+                enc.set_fec_percentage(50);
+            }
+            WebRTCSinkCongestionControl::Homegrown => {
+                if let Some(congestion_controller) = self.congestion_controller.as_mut() {
+                    if let Ok(bitrate) = enc.bitrate() {
+                        congestion_controller.target_bitrate_on_delay += bitrate;
+                        congestion_controller.target_bitrate_on_loss =
+                            congestion_controller.target_bitrate_on_delay;
+                        enc.set_fec_percentage(0);
+                    }
+                } else {
+                    /* If congestion control is disabled, we simply use the highest
+                     * known "safe" value for the bitrate. */
+                    let _ = enc.set_bitrate(element, self.cc_info.max_bitrate as i32);
+                    enc.set_fec_percentage(50);
+                }
+            }
+            _ => enc.set_fec_percentage(0),
+        }
+
+        self.encoders.push(enc);
+
+        if let Some(rtpgccbwe) = self.rtpgccbwe.as_ref() {
+            let max_bitrate =
+                self.cc_info.max_bitrate * ((self.encoders.len() + self.shared_links.len()) as u32);
+            rtpgccbwe.set_property("max-bitrate", max_bitrate);
+        }
+    }
+}
+
+/// Caps for the capsfilter downstream of a payloader, constructed
+/// from the caps of the negotiated SDP media
+fn payloader_filter_caps(caps: &gst::Caps, ssrc: u32) -> gst::Caps {
+    let s = caps.structure(0).unwrap();
+    let mut filtered_s = gst::Structure::new_empty("application/x-rtp");
+
+    filtered_s.extend(s.iter().filter_map(|(key, value)| {
+        if key.starts_with("a-") || key.starts_with("rid-") {
+            None
+        } else {
+            Some((key, value.to_owned()))
+        }
+    }));
+    filtered_s.set("ssrc", ssrc);
+
+    gst::Caps::builder_full().structure(filtered_s).build()
 }
 
 impl Drop for PipelineWrapper {
@@ -1526,6 +2719,41 @@ impl InputStream {
     fn msid(&self) -> Option<String> {
         self.sink_pad.property("msid")
     }
+
+    fn simulcast_layers(&self, element: &super::BaseWebRTCSink) -> Vec<SimulcastLayer> {
+        let mut rids = HashSet::new();
+
+        self.sink_pad
+            .property::<gst::Array>("simulcast-layers")
+            .iter()
+            .filter_map(|value| {
+                let layer = value
+                    .get::<gst::Structure>()
+                    .map_err(Error::from)
+                    .and_then(|s| SimulcastLayer::from_structure(&s))
+                    .and_then(|layer| {
+                        if rids.insert(layer.rid.clone()) {
+                            Ok(layer)
+                        } else {
+                            Err(anyhow!("Duplicate rid {}", layer.rid))
+                        }
+                    });
+
+                match layer {
+                    Ok(layer) => Some(layer),
+                    Err(err) => {
+                        gst::warning!(
+                            CAT,
+                            obj = element,
+                            "Ignoring simulcast layer of {}: {err:#}",
+                            self.sink_pad.name()
+                        );
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
 }
 
 impl NavigationEventHandler {
@@ -1774,12 +3002,230 @@ impl BaseWebRTCSink {
                         anyhow::bail!("Failed to compute RTP base time. clock-rate: {clock_rate}");
                     };
 
-                    payloader.set_property("timestamp-offset", (rtp_basetime & 0xffff_ffff) as u32);
-                }
-            }
+                    payloader.set_property("timestamp-offset", (rtp_basetime & 0xffff_ffff) as u32);
+                }
+            }
+        }
+
+        self.configure_congestion_control(payloader, codec, extension_configuration_type)
+    }
+
+    /// Returns the renditions of the shared encoder for a stream and codec,
+    /// creating it if needed, and registers the session as one of its consumers
+    fn acquire_shared_encoder(
+        &self,
+        session_id: &str,
+        producer: &StreamProducer,
+        stream_name: &str,
+        in_caps: &gst::Caps,
+        codec: &Codec,
+    ) -> Result<Vec<(u32, StreamProducer)>, Error> {
+        let key = shared_encoder_key(stream_name, codec);
+
+        if let Some(shared_encoder) = self.shared_encoders.lock().unwrap().get_mut(&key) {
+            shared_encoder.sessions.insert(session_id.to_string());
+            return Ok(shared_encoder.producers());
+        }
+
+        let bitrates = rendition_bitrates(&self.settings.lock().unwrap().shared_encoder_renditions);
+        if bitrates.is_empty() {
+            anyhow::bail!("No shared encoder renditions configured");
+        }
+
+        gst::info!(
+            CAT,
+            imp = self,
+            "Creating shared encoder for {stream_name} with {codec:?} and renditions {bitrates:?}"
+        );
+
+        // Not holding the lock here, as encoder-setup is emitted
+        let shared_encoder = SharedEncoder::new(
+            &self.obj(),
+            producer,
+            stream_name,
+            in_caps,
+            codec,
+            &bitrates,
+        )?;
+
+        let mut shared_encoders = self.shared_encoders.lock().unwrap();
+        let shared_encoder = match shared_encoders.entry(key) {
+            std::collections::hash_map::Entry::Occupied(entry) => {
+                // Another session created it in the meantime
+                let _ = shared_encoder.pipeline.set_state(gst::State::Null);
+                entry.into_mut()
+            }
+            std::collections::hash_map::Entry::Vacant(entry) => entry.insert(shared_encoder),
+        };
+
+        shared_encoder.sessions.insert(session_id.to_string());
+        Ok(shared_encoder.producers())
+    }
+
+    /// Unregisters a session from the shared encoders it consumed,
+    /// and tears down those it was the last consumer of
+    fn release_shared_encoders(&self, session_id: &str) {
+        let mut shared_encoders = self.shared_encoders.lock().unwrap();
+
+        for shared_encoder in shared_encoders.values_mut() {
+            shared_encoder.sessions.remove(session_id);
+        }
+
+        let unused = shared_encoders
+            .iter()
+            .filter(|(_, shared_encoder)| shared_encoder.sessions.is_empty())
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in unused {
+            let shared_encoder = shared_encoders.remove(&key).unwrap();
+            gst::info!(CAT, imp = self, "Removing unused shared encoder {key}");
+
+            RUNTIME.spawn_blocking(move || {
+                drop(shared_encoder.link);
+                let _ = shared_encoder.pipeline.set_state(gst::State::Null);
+            });
+        }
+    }
+
+    /// Ends the sessions consuming a shared encoder that errored out
+    fn remove_shared_encoder_sessions(&self, pipeline: &gst::Pipeline) {
+        let sessions = self
+            .shared_encoders
+            .lock()
+            .unwrap()
+            .values()
+            .find(|shared_encoder| &shared_encoder.pipeline == pipeline)
+            .map(|shared_encoder| shared_encoder.sessions.clone())
+            .unwrap_or_default();
+
+        for session_id in sessions {
+            let _ = self.remove_session(&session_id, true);
+        }
+    }
+
+    /// Starts recording the input streams, see #GstBaseWebRTCSink::start-recording
+    fn start_recording(&self) -> Result<(), Error> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let location = settings
+            .record_location
+            .as_deref()
+            .ok_or_else(|| anyhow!("No record-location set"))?;
+
+        let muxer = gst::ElementFactory::find(&settings.record_muxer)
+            .ok_or_else(|| anyhow!("No muxer factory {}", settings.record_muxer))?;
+
+        let inputs = {
+            let state = self.state.lock().unwrap();
+
+            if !state.codec_discovery_done {
+                anyhow::bail!("Input streams are not ready yet");
+            }
+
+            state
+                .streams
+                .iter()
+                .map(|(name, stream)| {
+                    let producer = stream
+                        .producer
+                        .clone()
+                        .ok_or_else(|| anyhow!("Input stream {name} is not ready yet"))?;
+                    let in_caps = stream
+                        .in_caps
+                        .clone()
+                        .ok_or_else(|| anyhow!("Input stream {name} has no caps yet"))?;
+
+                    // Pick the first codec the consumers may be offered
+                    // that the muxer supports
+                    let codec = if is_raw_caps(&in_caps) {
+                        stream
+                            .out_caps
+                            .iter()
+                            .flat_map(|caps| caps.iter())
+                            .find_map(|s| {
+                                s.get::<i32>("payload")
+                                    .ok()
+                                    .and_then(|pt| state.codecs.get(&pt))
+                                    .filter(|codec| muxer.can_sink_any_caps(&codec.caps))
+                                    .cloned()
+                            })
+                    } else if muxer.can_sink_any_caps(&in_caps) {
+                        Codecs::from_map(&state.codecs).find_for_encoded_caps(&in_caps)
+                    } else {
+                        None
+                    };
+                    let Some(codec) = codec else {
+                        anyhow::bail!("No codec supported by {} for {name}", settings.record_muxer);
+                    };
+
+                    Ok(RecordingInput {
+                        stream_name: name.clone(),
+                        producer,
+                        in_caps,
+                        codec,
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?
+        };
+
+        if self.recording.lock().unwrap().is_some() {
+            anyhow::bail!("Already recording");
         }
 
-        self.configure_congestion_control(payloader, codec, extension_configuration_type)
+        gst::info!(CAT, imp = self, "Starting recording to {location}");
+
+        // Not holding the lock here, as encoder-setup is emitted
+        let recording = Recording::new(
+            &self.obj(),
+            &inputs,
+            location,
+            &settings.record_muxer,
+            settings.record_max_size_time,
+            settings.record_video_bitrate,
+        )?;
+
+        let mut current = self.recording.lock().unwrap();
+        if current.is_some() {
+            // Started concurrently
+            drop(current);
+            let _ = recording.pipeline.set_state(gst::State::Null);
+            anyhow::bail!("Already recording");
+        }
+        *current = Some(recording);
+
+        Ok(())
+    }
+
+    /// Stops recording, returns false if we were not recording
+    fn stop_recording(&self) -> bool {
+        let Some(recording) = self.recording.lock().unwrap().take() else {
+            return false;
+        };
+
+        gst::info!(CAT, imp = self, "Stopping recording");
+
+        let element = self.obj().clone();
+        RUNTIME.spawn_blocking(move || recording.finalize(&element));
+
+        true
+    }
+
+    /// Shuts down a recording that errored out
+    fn abort_recording(&self, pipeline: &gst::Pipeline) {
+        let mut recording = self.recording.lock().unwrap();
+
+        if recording
+            .as_ref()
+            .is_some_and(|recording| &recording.pipeline == pipeline)
+        {
+            let recording = recording.take().unwrap();
+
+            RUNTIME.spawn_blocking(move || {
+                drop(recording.links);
+                let _ = recording.pipeline.set_state(gst::State::Null);
+            });
+        }
     }
 
     fn generate_ssrc(&self, webrtc_pads: &HashMap<u32, WebRTCPad>) -> u32 {
@@ -1834,6 +3280,7 @@ impl BaseWebRTCSink {
                 ssrc,
                 stream_name: None,
                 payload: None,
+                simulcast_layers: Vec::new(),
             },
         );
     }
@@ -1850,6 +3297,25 @@ impl BaseWebRTCSink {
         let ssrc = self.generate_ssrc(webrtc_pads);
         let media_idx = webrtc_pads.len() as i32;
 
+        let simulcast_layers = if stream.is_video {
+            stream.simulcast_layers(&self.obj())
+        } else {
+            Vec::new()
+        };
+
+        // We only support offering simulcast
+        let simulcast_layers = if media.is_some() && !simulcast_layers.is_empty() {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Not sending simulcast layers of {} when answering an offer",
+                stream.sink_pad.name()
+            );
+            Vec::new()
+        } else {
+            simulcast_layers
+        };
+
         let mut payloader_caps = match media {
             Some(media) => {
                 let discovery_info = stream.create_discovery();
@@ -1887,6 +3353,10 @@ impl BaseWebRTCSink {
             let payloader_caps_mut = payloader_caps.make_mut();
             payloader_caps_mut.set("ssrc", ssrc);
 
+            if !simulcast_layers.is_empty() {
+                add_simulcast_to_caps(payloader_caps_mut, &simulcast_layers);
+            }
+
             if self.settings.lock().unwrap().do_clock_signalling {
                 // Add RFC7273 attributes when using an NTP or PTP clock
                 let clock = self
@@ -1994,6 +3464,7 @@ impl BaseWebRTCSink {
                     ssrc,
                     stream_name: Some(stream.sink_pad.name().to_string()),
                     payload: None,
+                    simulcast_layers,
                 },
             );
         }
@@ -2022,6 +3493,12 @@ impl BaseWebRTCSink {
         let settings = self.settings.lock().unwrap();
         let signaller = settings.signaller.clone();
         drop(settings);
+
+        let recording = self.recording.lock().unwrap().take();
+        if let Some(recording) = recording {
+            recording.finalize(&self.obj());
+        }
+
         let mut state = self.state.lock().unwrap();
 
         let session_ids: Vec<_> = state.sessions.keys().map(|k| k.to_owned()).collect();
@@ -3125,7 +4602,12 @@ impl BaseWebRTCSink {
         if let Some(session) = state.sessions.get_mut(session_id) {
             let session = session.unwrap_mut();
             if let Some(congestion_controller) = session.congestion_controller.as_mut() {
-                congestion_controller.loss_control(&self.obj(), stats, &mut session.encoders);
+                congestion_controller.loss_control(
+                    &self.obj(),
+                    stats,
+                    &mut session.encoders,
+                    &mut session.shared_links,
+                );
             }
             stats.clone_into(&mut session.stats);
         }
@@ -3149,6 +4631,7 @@ impl BaseWebRTCSink {
                                 &this.obj(),
                                 stats,
                                 &mut session.encoders,
+                                &mut session.shared_links,
                             );
                         }
                         session.stats = stats.to_owned();
@@ -3177,7 +4660,7 @@ impl BaseWebRTCSink {
         if let Some(session) = state.sessions.get_mut(session_id) {
             let session = session.unwrap_mut();
 
-            let n_encoders = session.encoders.len();
+            let n_encoders = session.encoders.len() + session.shared_links.len();
 
             let fec_ratio = {
                 if settings.do_fec && bitrate > DO_FEC_THRESHOLD {
@@ -3197,13 +4680,13 @@ impl BaseWebRTCSink {
                 rtpxsend.set_property("stuffing-kbps", (bitrate as f64 / 1000.) as i32);
             }
 
-            for encoder in session.encoders.iter_mut() {
-                if encoder.set_bitrate(&self.obj(), encoders_bitrate).is_ok() {
-                    encoder
-                        .transceiver
-                        .set_property("fec-percentage", (fec_percentage as u32).min(100));
-                }
-            }
+            set_encoders_bitrate(
+                &self.obj(),
+                &mut session.encoders,
+                &mut session.shared_links,
+                encoders_bitrate,
+                (fec_percentage as u32).min(100),
+            );
         }
     }
 
@@ -3803,6 +5286,19 @@ impl BaseWebRTCSink {
                                 video_info.fps().mul(gst::Fraction::new(1, 2));
                             encoder.video_info = video_info.clone();
                         });
+
+                    self.shared_encoders
+                        .lock()
+                        .unwrap()
+                        .values_mut()
+                        .flat_map(|shared_encoder| shared_encoder.renditions.iter_mut())
+                        .map(|rendition| &mut rendition.encoder)
+                        .filter(|encoder| encoder.stream_name == stream_name)
+                        .for_each(|encoder| {
+                            encoder.halved_framerate =
+                                video_info.fps().mul(gst::Fraction::new(1, 2));
+                            encoder.video_info = video_info.clone();
+                        });
                 }
             }
         }
@@ -4042,6 +5538,103 @@ impl ObjectImpl for BaseWebRTCSink {
                     .flags(glib::ParamFlags::READABLE | gst::PARAM_FLAG_MUTABLE_READY)
                     .blurb("The Signallable object to use to handle WebRTC Signalling")
                     .build(),
+                /**
+                 * GstBaseWebRTCSink:shared-encoders:
+                 *
+                 * When enabled, raw video streams are encoded once per codec and
+                 * rendition rather than once per consumer, see
+                 * #GstBaseWebRTCSink:shared-encoder-renditions. Congestion control then
+                 * switches each consumer to the rendition that best fits its
+                 * bandwidth instead of adjusting the encoder bitrate.
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                glib::ParamSpecBoolean::builder("shared-encoders")
+                    .nick("Shared encoders")
+                    .blurb("Whether consumers negotiating the same video codec should share encoders")
+                    .default_value(DEFAULT_SHARED_ENCODERS)
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstBaseWebRTCSink:shared-encoder-renditions:
+                 *
+                 * The bitrates (in bit/sec) of the renditions produced by shared
+                 * encoders. The resolution and framerate of each rendition are
+                 * picked according to its bitrate.
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                gst::ParamSpecArray::builder("shared-encoder-renditions")
+                    .nick("Shared encoder renditions")
+                    .blurb("Bitrates (in bit/sec) of the renditions produced by shared encoders")
+                    .element_spec(&glib::ParamSpecUInt::builder("bitrate")
+                        .nick("Bitrate")
+                        .blurb("Bitrate of the rendition (in bit/sec)")
+                        .minimum(1)
+                        .build()
+                    )
+                    .mutable_ready()
+                    .build(),
+                /**
+                 * GstBaseWebRTCSink:record-location:
+                 *
+                 * Location of the files written when recording, see
+                 * #GstBaseWebRTCSink::start-recording. When
+                 * #GstBaseWebRTCSink:record-max-size-time is set, this should
+                 * contain a placeholder for the fragment index, e.g.
+                 * `recording-%05d.mp4`.
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                glib::ParamSpecString::builder("record-location")
+                    .nick("Record location")
+                    .blurb("Location of the recorded files")
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstBaseWebRTCSink:record-muxer:
+                 *
+                 * The factory name of the muxer used when recording, for instance
+                 * `mp4mux`, or `isofmp4mux` for fragmented MP4.
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                glib::ParamSpecString::builder("record-muxer")
+                    .nick("Record muxer")
+                    .blurb("Factory name of the muxer used when recording")
+                    .default_value(DEFAULT_RECORD_MUXER)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstBaseWebRTCSink:record-max-size-time:
+                 *
+                 * The duration (in nanoseconds) after which a new file is started
+                 * when recording, 0 to record to a single file.
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                glib::ParamSpecUInt64::builder("record-max-size-time")
+                    .nick("Record max size time")
+                    .blurb("Duration of the recorded files (in nanoseconds, 0 = single file)")
+                    .default_value(DEFAULT_RECORD_MAX_SIZE_TIME)
+                    .mutable_playing()
+                    .build(),
+                /**
+                 * GstBaseWebRTCSink:record-video-bitrate:
+                 *
+                 * The bitrate (in bit/sec) raw video streams are encoded at when
+                 * recording. Recorded streams are encoded separately from those sent
+                 * to consumers, and are not subject to congestion control.
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                glib::ParamSpecUInt::builder("record-video-bitrate")
+                    .nick("Record video bitrate")
+                    .blurb("Bitrate (in bit/sec) of the recorded video streams")
+                    .minimum(1)
+                    .default_value(DEFAULT_RECORD_VIDEO_BITRATE)
+                    .mutable_playing()
+                    .build(),
             ]
         });
 
@@ -4121,6 +5714,36 @@ impl ObjectImpl for BaseWebRTCSink {
                     .get::<WebRTCICETransportPolicy>()
                     .expect("type checked upstream");
             }
+            "shared-encoders" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.shared_encoders = value.get::<bool>().expect("type checked upstream");
+            }
+            "shared-encoder-renditions" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.shared_encoder_renditions =
+                    value.get::<gst::Array>().expect("type checked upstream");
+            }
+            "record-location" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.record_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "record-muxer" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.record_muxer = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_RECORD_MUXER.to_string());
+            }
+            "record-max-size-time" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.record_max_size_time = value.get::<u64>().expect("type checked upstream");
+            }
+            "record-video-bitrate" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.record_video_bitrate = value.get::<u32>().expect("type checked upstream");
+            }
             _ => unimplemented!(),
         }
     }
@@ -4185,6 +5808,30 @@ impl ObjectImpl for BaseWebRTCSink {
                 settings.ice_transport_policy.to_value()
             }
             "signaller" => self.settings.lock().unwrap().signaller.to_value(),
+            "shared-encoders" => {
+                let settings = self.settings.lock().unwrap();
+                settings.shared_encoders.to_value()
+            }
+            "shared-encoder-renditions" => {
+                let settings = self.settings.lock().unwrap();
+                settings.shared_encoder_renditions.to_value()
+            }
+            "record-location" => {
+                let settings = self.settings.lock().unwrap();
+                settings.record_location.to_value()
+            }
+            "record-muxer" => {
+                let settings = self.settings.lock().unwrap();
+                settings.record_muxer.to_value()
+            }
+            "record-max-size-time" => {
+                let settings = self.settings.lock().unwrap();
+                settings.record_max_size_time.to_value()
+            }
+            "record-video-bitrate" => {
+                let settings = self.settings.lock().unwrap();
+                settings.record_video_bitrate.to_value()
+            }
             _ => unimplemented!(),
         }
     }
@@ -4257,10 +5904,64 @@ impl ObjectImpl for BaseWebRTCSink {
                     })
                     .return_type::<Vec<String>>()
                     .build(),
+                /**
+                 * GstBaseWebRTCSink::start-recording:
+                 *
+                 * Starts recording the input streams to
+                 * #GstBaseWebRTCSink:record-location, muxed with
+                 * #GstBaseWebRTCSink:record-muxer. Raw streams are encoded
+                 * separately from those sent to consumers, with the first
+                 * codec they may be offered that the muxer supports.
+                 *
+                 * Only one video stream can be recorded. Codec discovery
+                 * must have completed before recording can start.
+                 *
+                 * Returns: True if recording started
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                glib::subclass::Signal::builder("start-recording")
+                    .action()
+                    .class_handler(|_, args| {
+                        let element = args[0].get::<super::BaseWebRTCSink>().expect("signal arg");
+                        let this = element.imp();
+
+                        let res = match this.start_recording() {
+                            Ok(()) => true,
+                            Err(err) => {
+                                gst::warning!(CAT, obj = element, "Failed to start recording: {err:?}");
+                                false
+                            }
+                        };
+
+                        Some(res.to_value())
+                    })
+                    .return_type::<bool>()
+                    .build(),
+                /**
+                 * GstBaseWebRTCSink::stop-recording:
+                 *
+                 * Stops recording, the files are finalized asynchronously.
+                 *
+                 * Returns: True if a recording was stopped
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                glib::subclass::Signal::builder("stop-recording")
+                    .action()
+                    .class_handler(|_, args| {
+                        let element = args[0].get::<super::BaseWebRTCSink>().expect("signal arg");
+
+                        Some(element.imp().stop_recording().to_value())
+                    })
+                    .return_type::<bool>()
+                    .build(),
                 /**
                  * GstBaseWebRTCSink::encoder-setup:
-                 * @consumer_id: Identifier of the consumer, or "discovery"
-                 *   when the encoder is used in a discovery pipeline.
+                 * @consumer_id: Identifier of the consumer, "discovery"
+                 *   when the encoder is used in a discovery pipeline, "shared"
+                 *   when the encoder is shared between consumers, or "recording"
+                 *   when the encoder is used for recording.
                  * @pad_name: The name of the corresponding input pad
                  * @encoder: The constructed encoder
                  *
@@ -4736,6 +6437,54 @@ pub(super) mod whip {
     }
 }
 
+#[cfg(feature = "whip")]
+pub(super) mod whep {
+    use super::*;
+    use crate::whep_signaller::WhepServerSignaller;
+
+    #[derive(Default)]
+    pub struct WhepServerSink {}
+
+    impl ObjectImpl for WhepServerSink {
+        fn constructed(&self) {
+            let element = self.obj();
+            let ws = element
+                .upcast_ref::<crate::webrtcsink::BaseWebRTCSink>()
+                .imp();
+
+            let _ = ws.set_signaller(WhepServerSignaller::default().upcast());
+        }
+    }
+
+    impl GstObjectImpl for WhepServerSink {}
+
+    impl ElementImpl for WhepServerSink {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "WhepServerSink",
+                    "Sink/Network/WebRTC",
+                    "WebRTC sink with WHEP server signaller",
+                    "agent <agent@local>",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+    }
+
+    impl BinImpl for WhepServerSink {}
+
+    impl BaseWebRTCSinkImpl for WhepServerSink {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for WhepServerSink {
+        const NAME: &'static str = "GstWhepServerSink";
+        type Type = crate::webrtcsink::WhepServerSink;
+        type ParentType = crate::webrtcsink::BaseWebRTCSink;
+    }
+}
+
 #[cfg(feature = "livekit")]
 pub(super) mod livekit {
     use super::*;
@@ -4856,3 +6605,388 @@ pub(super) mod janus {
         type ParentType = crate::webrtcsink::BaseWebRTCSink;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(rid: &str, bitrate_ratio: f64, min_bitrate: u32) -> SimulcastLayer {
+        SimulcastLayer {
+            rid: rid.to_string(),
+            scale_resolution_down_by: 1.,
+            bitrate_ratio,
+            min_bitrate,
+        }
+    }
+
+    fn test_sdp() -> gst_sdp::SDPMessage {
+        let sdp = "v=0\r\n\
+                   o=- 0 0 IN IP4 127.0.0.1\r\n\
+                   s=-\r\n\
+                   t=0 0\r\n\
+                   m=video 9 UDP/TLS/RTP/SAVPF 96\r\n\
+                   c=IN IP4 0.0.0.0\r\n\
+                   a=rtpmap:96 VP8/90000\r\n\
+                   a=extmap:1 urn:ietf:params:rtp-hdrext:sdes:mid\r\n\
+                   a=extmap:3/recvonly urn:ietf:params:rtp-hdrext:sdes:rtp-stream-id\r\n\
+                   a=rid:h recv\r\n\
+                   a=rid:f recv\r\n\
+                   a=rid:q send\r\n\
+                   a=simulcast:recv h;f\r\n";
+
+        gst_sdp::SDPMessage::parse_buffer(sdp.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn simulcast_layer_from_structure() {
+        gst::init().unwrap();
+
+        let layer = SimulcastLayer::from_structure(
+            &gst::Structure::builder("layer").field("rid", "q").build(),
+        )
+        .unwrap();
+        assert_eq!(layer.rid, "q");
+        assert_eq!(layer.scale_resolution_down_by, 1.);
+        assert_eq!(layer.bitrate_ratio, 1.);
+        assert_eq!(layer.min_bitrate, DEFAULT_LAYER_MIN_BITRATE);
+
+        let layer = SimulcastLayer::from_structure(
+            &gst::Structure::builder("layer")
+                .field("rid", "h-1_a")
+                .field("scale-resolution-down-by", 2.)
+                .field("bitrate-ratio", 0.5)
+                .field("min-bitrate", 50000u32)
+                .build(),
+        )
+        .unwrap();
+        assert_eq!(layer.rid, "h-1_a");
+        assert_eq!(layer.scale_resolution_down_by, 2.);
+        assert_eq!(layer.bitrate_ratio, 0.5);
+        assert_eq!(layer.min_bitrate, 50000);
+    }
+
+    #[test]
+    fn simulcast_layer_from_malformed_structure() {
+        gst::init().unwrap();
+
+        for s in [
+            // no rid
+            gst::Structure::builder("layer").build(),
+            // rid of the wrong type
+            gst::Structure::builder("layer").field("rid", 1i32).build(),
+            // invalid rids
+            gst::Structure::builder("layer").field("rid", "").build(),
+            gst::Structure::builder("layer").field("rid", "a b").build(),
+            gst::Structure::builder("layer").field("rid", "q;h").build(),
+            gst::Structure::builder("layer")
+                .field("rid", "abcdefghijklmnopq")
+                .build(),
+            // upscaling
+            gst::Structure::builder("layer")
+                .field("rid", "q")
+                .field("scale-resolution-down-by", 0.5)
+                .build(),
+            // no bitrate share
+            gst::Structure::builder("layer")
+                .field("rid", "q")
+                .field("bitrate-ratio", 0.)
+                .build(),
+            gst::Structure::builder("layer")
+                .field("rid", "q")
+                .field("bitrate-ratio", -1.)
+                .build(),
+            // fields of the wrong type
+            gst::Structure::builder("layer")
+                .field("rid", "q")
+                .field("scale-resolution-down-by", 2i32)
+                .build(),
+            gst::Structure::builder("layer")
+                .field("rid", "q")
+                .field("min-bitrate", "100000")
+                .build(),
+        ] {
+            assert!(SimulcastLayer::from_structure(&s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_find_extmap_id() {
+        gst::init().unwrap();
+
+        let sdp = test_sdp();
+        let media = sdp.media(0).unwrap();
+
+        assert_eq!(find_extmap_id(media, RTP_MID_URI), Some(1));
+        // with a direction
+        assert_eq!(find_extmap_id(media, RTP_STREAM_ID_URI), Some(3));
+        assert_eq!(
+            find_extmap_id(
+                media,
+                "urn:ietf:params:rtp-hdrext:sdes:repaired-rtp-stream-id"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_accepted_simulcast_layers() {
+        gst::init().unwrap();
+
+        let sdp = test_sdp();
+        let media = sdp.media(0).unwrap();
+
+        // Only the layers the peer wants to receive are kept, in our order,
+        // and rids we didn't offer are ignored
+        let layers = [
+            layer("q", 1., 100000),
+            layer("f", 4., 400000),
+            layer("h", 2., 200000),
+        ];
+        let accepted = accepted_simulcast_layers(media, &layers)
+            .into_iter()
+            .map(|layer| layer.rid)
+            .collect::<Vec<_>>();
+        assert_eq!(accepted, ["f", "h"]);
+
+        assert!(accepted_simulcast_layers(media, &[layer("x", 1., 100000)]).is_empty());
+    }
+
+    #[test]
+    fn simulcast_layer_drop_and_restore() {
+        let layers = [
+            layer("q", 1., 100000),
+            layer("h", 2., 200000),
+            layer("f", 4., 400000),
+        ];
+        let with_state = |active: [bool; 3]| layers.iter().zip(active).collect::<Vec<_>>();
+
+        // Enough for all layers, the pooled bitrate is split according to the ratios
+        assert_eq!(
+            simulcast_layer_bitrates(&with_state([true; 3]), 300000),
+            [Some(128571), Some(257142), Some(514285)]
+        );
+
+        // Not enough for the minimum bitrate of the highest layer
+        assert_eq!(
+            simulcast_layer_bitrates(&with_state([true; 3]), 200000),
+            [Some(200000), Some(400000), None]
+        );
+
+        // The lowest layer is always kept and gets all the bitrate
+        assert_eq!(
+            simulcast_layer_bitrates(&with_state([true; 3]), 50000),
+            [Some(150000), None, None]
+        );
+
+        // An active layer is kept as long as it gets its minimum bitrate, but a
+        // dropped layer is only restored with some headroom above it
+        assert_eq!(
+            simulcast_layer_bitrates(&with_state([true; 3]), 250000),
+            [Some(107142), Some(214285), Some(428571)]
+        );
+        assert_eq!(
+            simulcast_layer_bitrates(&with_state([true, true, false]), 250000),
+            [Some(250000), Some(500000), None]
+        );
+        assert_eq!(
+            simulcast_layer_bitrates(&with_state([true, true, false]), 300000),
+            [Some(128571), Some(257142), Some(514285)]
+        );
+    }
+
+    #[test]
+    fn test_pick_rendition() {
+        let bitrates = [250000u32, 500000, 1000000];
+
+        // The highest rendition that fits
+        assert_eq!(pick_rendition(bitrates.into_iter(), 2000000), 2);
+        assert_eq!(pick_rendition(bitrates.into_iter(), 1000000), 2);
+        assert_eq!(pick_rendition(bitrates.into_iter(), 999999), 1);
+        assert_eq!(pick_rendition(bitrates.into_iter(), 500000), 1);
+        assert_eq!(pick_rendition(bitrates.into_iter(), 300000), 0);
+        // The lowest rendition if none fits
+        assert_eq!(pick_rendition(bitrates.into_iter(), 100000), 0);
+        assert_eq!(pick_rendition(bitrates.into_iter(), -1), 0);
+        // Bitrates that don't fit into an i32 are not wrapped around
+        assert_eq!(pick_rendition([u32::MAX].into_iter(), i32::MAX as i64), 0);
+        assert_eq!(pick_rendition([250000, u32::MAX].into_iter(), 300000), 0);
+    }
+
+    #[test]
+    fn test_rendition_bitrates() {
+        gst::init().unwrap();
+
+        let element = glib::Object::new::<crate::webrtcsink::WebRTCSink>();
+        let ws = element
+            .upcast_ref::<crate::webrtcsink::BaseWebRTCSink>()
+            .imp();
+
+        assert_eq!(
+            rendition_bitrates(&ws.settings.lock().unwrap().shared_encoder_renditions),
+            DEFAULT_SHARED_ENCODER_RENDITIONS
+        );
+
+        // Sorted by increasing bitrate, without duplicates
+        element.set_property_from_str(
+            "shared-encoder-renditions",
+            "<(uint)1000000, (uint)250000, (uint)500000, (uint)250000>",
+        );
+        assert_eq!(
+            rendition_bitrates(&ws.settings.lock().unwrap().shared_encoder_renditions),
+            [250000, 500000, 1000000]
+        );
+
+        element.set_property("shared-encoder-renditions", gst::Array::new([300000u32]));
+        assert_eq!(
+            rendition_bitrates(&ws.settings.lock().unwrap().shared_encoder_renditions),
+            [300000]
+        );
+
+        element.set_property(
+            "shared-encoder-renditions",
+            gst::Array::new(Vec::<u32>::new()),
+        );
+        assert!(
+            rendition_bitrates(&ws.settings.lock().unwrap().shared_encoder_renditions).is_empty()
+        );
+    }
+
+    #[test]
+    fn shared_encoder_reused_by_sessions() {
+        gst::init().unwrap();
+
+        let element = glib::Object::new::<crate::webrtcsink::WebRTCSink>();
+        let ws = element
+            .upcast_ref::<crate::webrtcsink::BaseWebRTCSink>()
+            .imp();
+
+        let caps = gst::Caps::builder("video/x-raw")
+            .field("format", "I420")
+            .field("width", 640i32)
+            .field("height", 480i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build();
+        let codec = Codec::new(
+            "VP8",
+            gst::StreamType::VIDEO,
+            &gst::Caps::new_empty_simple("video/x-vp8"),
+            &glib::List::new(),
+            &glib::List::new(),
+            &glib::List::new(),
+        );
+
+        // An encoder for the stream that was already set up for another session
+        let input_producer = StreamProducer::from(&gst_app::AppSink::builder().build());
+        let rendition_appsink = gst_app::AppSink::builder().build();
+        let video_info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+        ws.shared_encoders.lock().unwrap().insert(
+            shared_encoder_key("video_0", &codec),
+            SharedEncoder {
+                pipeline: gst::Pipeline::new(),
+                renditions: vec![SharedRendition {
+                    bitrate: 500000,
+                    encoder: VideoEncoder {
+                        factory_name: "vp8enc".to_string(),
+                        codec_name: "video/x-vp8".to_string(),
+                        element: gst::Bin::new().upcast(),
+                        filter: gst::Bin::new().upcast(),
+                        halved_framerate: gst::Fraction::new(15, 1),
+                        video_info,
+                        session_id: "shared".to_string(),
+                        mitigation_mode: WebRTCSinkMitigationMode::NONE,
+                        transceiver: None,
+                        stream_name: "video_0".to_string(),
+                        layer: None,
+                    },
+                    producer: StreamProducer::from(&rendition_appsink),
+                }],
+                link: input_producer
+                    .add_consumer(&gst_app::AppSrc::builder().build())
+                    .unwrap(),
+                sessions: HashSet::from(["session-0".to_string()]),
+            },
+        );
+
+        // Creating a new encoder would fail, as the codec has no encoder
+        for session_id in ["session-1", "session-2"] {
+            let renditions = ws
+                .acquire_shared_encoder(session_id, &input_producer, "video_0", &caps, &codec)
+                .unwrap();
+            assert_eq!(renditions.len(), 1);
+            assert_eq!(renditions[0].0, 500000);
+            assert_eq!(renditions[0].1.appsink(), &rendition_appsink);
+        }
+
+        let shared_encoders = ws.shared_encoders.lock().unwrap();
+        assert_eq!(shared_encoders.len(), 1);
+        assert_eq!(
+            shared_encoders.values().next().unwrap().sessions,
+            HashSet::from([
+                "session-0".to_string(),
+                "session-1".to_string(),
+                "session-2".to_string()
+            ])
+        );
+        drop(shared_encoders);
+
+        // The encoder is only removed with its last consumer
+        ws.release_shared_encoders("session-0");
+        ws.release_shared_encoders("session-1");
+        assert_eq!(ws.shared_encoders.lock().unwrap().len(), 1);
+        ws.release_shared_encoders("session-2");
+        assert!(ws.shared_encoders.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn recording_input_selection() {
+        gst::init().unwrap();
+
+        let input = |stream_name: &str, stream_type, caps: &str| RecordingInput {
+            stream_name: stream_name.to_string(),
+            producer: StreamProducer::from(&gst_app::AppSink::builder().build()),
+            in_caps: gst::Caps::new_empty_simple(caps),
+            codec: Codec::new(
+                caps,
+                stream_type,
+                &gst::Caps::new_empty_simple(caps),
+                &glib::List::new(),
+                &glib::List::new(),
+                &glib::List::new(),
+            ),
+        };
+        let names = |inputs: Vec<&RecordingInput>| {
+            inputs
+                .into_iter()
+                .map(|input| input.stream_name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let inputs = [
+            input("audio_0", gst::StreamType::AUDIO, "audio/x-opus"),
+            input("video_0", gst::StreamType::VIDEO, "video/x-vp8"),
+            input("video_1", gst::StreamType::VIDEO, "video/x-h264"),
+            input("audio_1", gst::StreamType::AUDIO, "audio/x-opus"),
+            input("video_2", gst::StreamType::VIDEO, "video/x-vp8"),
+        ];
+
+        // Only the first video stream is recorded, along with all audio streams,
+        // in the order of the inputs
+        let (selected, skipped) = select_recording_inputs(&inputs);
+        assert_eq!(names(selected), ["audio_0", "video_0", "audio_1"]);
+        assert_eq!(names(skipped), ["video_1", "video_2"]);
+
+        // The producers stay paired with their inputs
+        let (selected, _) = select_recording_inputs(&inputs);
+        for (selected, idx) in selected.into_iter().zip([0, 1, 3]) {
+            assert_eq!(selected.producer.appsink(), inputs[idx].producer.appsink());
+        }
+
+        let (selected, skipped) = select_recording_inputs(&inputs[3..4]);
+        assert_eq!(names(selected), ["audio_1"]);
+        assert!(skipped.is_empty());
+
+        let (selected, skipped) = select_recording_inputs(&[]);
+        assert!(selected.is_empty());
+        assert!(skipped.is_empty());
+    }
+}
@@ -63,6 +63,11 @@ glib::wrapper! {
     pub struct WhipWebRTCSink(ObjectSubclass<imp::whip::WhipWebRTCSink>) @extends BaseWebRTCSink, gst::Bin, gst::Element, gst::Object, @implements gst::ChildProxy, gst_video::Navigation;
 }
 
+#[cfg(feature = "whip")]
+glib::wrapper! {
+    pub struct WhepServerSink(ObjectSubclass<imp::whep::WhepServerSink>) @extends BaseWebRTCSink, gst::Bin, gst::Element, gst::Object, @implements gst::ChildProxy, gst_video::Navigation;
+}
+
 #[cfg(feature = "livekit")]
 glib::wrapper! {
     pub struct LiveKitWebRTCSink(ObjectSubclass<imp::livekit::LiveKitWebRTCSink>) @extends BaseWebRTCSink, gst::Bin, gst::Element, gst::Object, @implements gst::ChildProxy, gst_video::Navigation;
@@ -158,6 +163,35 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
         gst::Rank::NONE,
         WhipWebRTCSink::static_type(),
     )?;
+    #[cfg(feature = "whip")]
+    /**
+     * element-whepserversink:
+     *
+     * The `whepserversink` serves the streams it is fed to WHEP clients, such
+     * as browsers or the `whepsrc` element, from an embedded HTTP server.
+     *
+     * Each client POSTs its offer to `<host-addr>/whep/endpoint` and receives
+     * the answer along with the location of the session resource. Trickled
+     * candidates can be PATCHed to that resource and a DELETE ends the
+     * session. When `signaller::auth-token` is set, clients must present it
+     * as a bearer token.
+     *
+     * ```bash
+     * $ gst-launch-1.0 videotestsrc is-live=true ! whepserversink signaller::host-addr=http://127.0.0.1:8190
+     * ```
+     *
+     * ```bash
+     * $ gst-launch-1.0 whepsrc whep-endpoint=http://127.0.0.1:8190/whep/endpoint video-caps="application/x-rtp,payload=96,encoding-name=VP8,media=video,clock-rate=90000" ! rtpvp8depay ! vp8dec ! videoconvert ! autovideosink
+     * ```
+     *
+     * Since: plugins-rs-0.13.0
+     */
+    gst::Element::register(
+        Some(plugin),
+        "whepserversink",
+        gst::Rank::NONE,
+        WhepServerSink::static_type(),
+    )?;
     #[cfg(feature = "livekit")]
     gst::Element::register(
         Some(plugin),
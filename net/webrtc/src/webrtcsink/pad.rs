@@ -9,9 +9,19 @@ pub struct WebRTCSinkPad {
     settings: Mutex<Settings>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct Settings {
     msid: Option<String>,
+    simulcast_layers: gst::Array,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            msid: None,
+            simulcast_layers: gst::Array::new(Vec::<gst::Structure>::new()),
+        }
+    }
 }
 
 #[glib::object_subclass]
@@ -24,10 +34,50 @@ impl ObjectSubclass for WebRTCSinkPad {
 impl ObjectImpl for WebRTCSinkPad {
     fn properties() -> &'static [glib::ParamSpec] {
         static PROPS: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
-            vec![glib::ParamSpecString::builder("msid")
-                .flags(glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY)
-                .blurb("Remote MediaStream ID in use for this pad")
-                .build()]
+            vec![
+                glib::ParamSpecString::builder("msid")
+                    .flags(glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY)
+                    .blurb("Remote MediaStream ID in use for this pad")
+                    .build(),
+                /**
+                 * GstWebRTCSinkPad:simulcast-layers:
+                 *
+                 * Simulcast layers to encode a video stream to, ordered from
+                 * the lowest to the highest quality. Each layer is described
+                 * by a structure with the following fields:
+                 *
+                 * * `rid` (string, mandatory): the RTP stream ID of the layer
+                 * * `scale-resolution-down-by` (double, default 1.0): factor
+                 *   to downscale the input resolution by
+                 * * `bitrate-ratio` (double, default 1.0): share of the bitrate
+                 *   allocated to the stream that this layer receives, relative
+                 *   to the other layers
+                 * * `min-bitrate` (uint, default 100000): the layer is dropped
+                 *   by congestion control when it can't be given at least this
+                 *   bitrate. The lowest layer is never dropped.
+                 *
+                 * For example:
+                 *
+                 * ``` text
+                 * <"layer,rid=q,scale-resolution-down-by=4.0,bitrate-ratio=1.0",
+                 *  "layer,rid=h,scale-resolution-down-by=2.0,bitrate-ratio=3.0",
+                 *  "layer,rid=f,bitrate-ratio=12.0">
+                 * ```
+                 *
+                 * Only used when webrtcsink creates the offer.
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                gst::ParamSpecArray::builder("simulcast-layers")
+                    .flags(glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY)
+                    .blurb("Simulcast layers to encode this video stream to, from lowest to highest quality")
+                    .element_spec(
+                        &glib::ParamSpecBoxed::builder::<gst::Structure>("simulcast-layer")
+                            .blurb("A simulcast layer, with rid, scale-resolution-down-by, bitrate-ratio and min-bitrate fields")
+                            .build(),
+                    )
+                    .build(),
+            ]
         });
         PROPS.as_ref()
     }
@@ -39,6 +89,10 @@ impl ObjectImpl for WebRTCSinkPad {
                     .get::<Option<String>>()
                     .expect("type checked upstream")
             }
+            "simulcast-layers" => {
+                settings.simulcast_layers =
+                    value.get::<gst::Array>().expect("type checked upstream")
+            }
             name => panic!("no writable property {name:?}"),
         }
     }
@@ -46,6 +100,7 @@ impl ObjectImpl for WebRTCSinkPad {
         let settings = self.settings.lock().unwrap();
         match pspec.name() {
             "msid" => settings.msid.to_value(),
+            "simulcast-layers" => settings.simulcast_layers.to_value(),
             name => panic!("no readable property {name:?}"),
         }
     }
@@ -1310,7 +1310,7 @@ impl BaseWebRTCSrc {
         obj.remove(bin)?;
 
         for pad in obj.src_pads() {
-            if pad.name().contains(id) {
+            if session_id_from_pad_name(&pad.name()) == Some(id) {
                 if !pad.push_event(gst::event::Eos::new()) {
                     gst::warning!(CAT, imp = self, "failed to send EOS on {}", pad.name());
                 }
@@ -1323,6 +1323,16 @@ impl BaseWebRTCSrc {
     }
 }
 
+/// Returns the session ID of a source pad named `{video,audio}_<session-id>_<n>`.
+/// Only the exact session has to match, as IDs like Janus feed IDs can be
+/// prefixes of each other.
+fn session_id_from_pad_name(name: &str) -> Option<&str> {
+    name.strip_prefix("video_")
+        .or_else(|| name.strip_prefix("audio_"))
+        .and_then(|suffix| suffix.rsplit_once('_'))
+        .map(|(session_id, _)| session_id)
+}
+
 impl ElementImpl for BaseWebRTCSrc {
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
@@ -1670,3 +1680,115 @@ pub(super) mod livekit {
         type ParentType = crate::webrtcsrc::BaseWebRTCSrc;
     }
 }
+
+#[cfg(feature = "janus")]
+pub(super) mod janus {
+    use super::*;
+    use crate::{
+        janusvr_signaller::{JanusVRSignallerStr, JanusVRSignallerU64},
+        signaller::WebRTCSignallerRole,
+    };
+
+    #[derive(Debug, Clone, Default)]
+    struct JanusSettings {
+        use_string_ids: bool,
+    }
+
+    #[derive(Default, glib::Properties)]
+    #[properties(wrapper_type = crate::webrtcsrc::JanusVRWebRTCSrc)]
+    pub struct JanusVRWebRTCSrc {
+        /**
+         * GstJanusVRWebRTCSrc:use-string-ids:
+         *
+         * By default Janus uses `u64` ids to identitify the room, the feed, etc.
+         * But it can be changed to strings using the `strings_ids` option in `janus.plugin.videoroom.jcfg`.
+         * In such case, `janusvrwebrtcsrc` has to be created using `use-string-ids=true` so its signaller
+         * uses the right types for such ids and properties.
+         *
+         * Since: plugins-rs-0.13.0
+         */
+        #[property(name="use-string-ids", get, construct_only, type = bool, member = use_string_ids, blurb = "Use strings instead of u64 for Janus IDs, see strings_ids config option in janus.plugin.videoroom.jcfg")]
+        settings: Mutex<JanusSettings>,
+    }
+
+    #[glib::derived_properties]
+    impl ObjectImpl for JanusVRWebRTCSrc {
+        fn constructed(&self) {
+            self.parent_constructed();
+            let settings = self.settings.lock().unwrap();
+            let element = self.obj();
+            let ws = element
+                .upcast_ref::<crate::webrtcsrc::BaseWebRTCSrc>()
+                .imp();
+
+            if settings.use_string_ids {
+                let _ = ws.set_signaller(
+                    JanusVRSignallerStr::new(WebRTCSignallerRole::Consumer).upcast(),
+                );
+            } else {
+                let _ = ws.set_signaller(
+                    JanusVRSignallerU64::new(WebRTCSignallerRole::Consumer).upcast(),
+                );
+            }
+        }
+    }
+
+    impl GstObjectImpl for JanusVRWebRTCSrc {}
+
+    impl BinImpl for JanusVRWebRTCSrc {}
+
+    impl ElementImpl for JanusVRWebRTCSrc {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+                gst::subclass::ElementMetadata::new(
+                    "JanusVRWebRTCSrc",
+                    "Source/Network/WebRTC",
+                    "WebRTC source with Janus Video Room signaller",
+                    "agent <agent@local>",
+                )
+            });
+
+            Some(&*ELEMENT_METADATA)
+        }
+    }
+
+    impl BaseWebRTCSrcImpl for JanusVRWebRTCSrc {}
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for JanusVRWebRTCSrc {
+        const NAME: &'static str = "GstJanusVRWebRTCSrc";
+        type Type = crate::webrtcsrc::JanusVRWebRTCSrc;
+        type ParentType = crate::webrtcsrc::BaseWebRTCSrc;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_id_from_pad_name() {
+        assert_eq!(session_id_from_pad_name("video_1234_0"), Some("1234"));
+        assert_eq!(session_id_from_pad_name("audio_1234_12"), Some("1234"));
+        // Session IDs may contain underscores
+        assert_eq!(session_id_from_pad_name("video_feed_1_0"), Some("feed_1"));
+        assert_eq!(session_id_from_pad_name("video_1234"), None);
+        assert_eq!(session_id_from_pad_name("data_1234_0"), None);
+
+        // Janus feed IDs that are prefixes of each other only match their own pads
+        let pads = ["video_12_0", "audio_12_0", "video_123_0", "audio_1234_1"];
+        for (feed_id, expected) in [
+            ("1", vec![]),
+            ("12", vec!["video_12_0", "audio_12_0"]),
+            ("123", vec!["video_123_0"]),
+            ("1234", vec!["audio_1234_1"]),
+        ] {
+            let matching = pads
+                .iter()
+                .copied()
+                .filter(|name| session_id_from_pad_name(name) == Some(feed_id))
+                .collect::<Vec<_>>();
+            assert_eq!(matching, expected, "feed {feed_id}");
+        }
+    }
+}
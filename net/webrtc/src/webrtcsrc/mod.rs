@@ -59,6 +59,11 @@ glib::wrapper! {
     pub struct LiveKitWebRTCSrc(ObjectSubclass<imp::livekit::LiveKitWebRTCSrc>) @extends BaseWebRTCSrc, gst::Bin, gst::Element, gst::Object, gst::ChildProxy;
 }
 
+#[cfg(feature = "janus")]
+glib::wrapper! {
+    pub struct JanusVRWebRTCSrc(ObjectSubclass<imp::janus::JanusVRWebRTCSrc>) @extends BaseWebRTCSrc, gst::Bin, gst::Element, gst::Object, @implements gst::ChildProxy;
+}
+
 glib::wrapper! {
     pub struct WebRTCSrcPad(ObjectSubclass<pad::WebRTCSrcPad>) @extends gst::GhostPad, gst::ProxyPad, gst::Pad, gst::Object;
 }
@@ -139,5 +144,37 @@ pub fn register(plugin: Option<&gst::Plugin>) -> Result<(), glib::BoolError> {
         LiveKitWebRTCSrc::static_type(),
     )?;
 
+    #[cfg(feature = "janus")]
+    /**
+     * element-janusvrwebrtcsrc:
+     *
+     * The `janusvrwebrtcsrc` plays streams published in a [Video Room](https://janus.conf.meetecho.com/docs/videoroom)
+     * of the [Janus Gateway](https://github.com/meetecho/janus-gateway), for example by `janusvrwebrtcsink`.
+     *
+     * The element joins the room and subscribes to the feeds of its publishers, each feed in its
+     * own session, so that the pads of a feed are named after its ID, e.g. `video_<feed-id>_0`.
+     * Feeds of publishers joining later are subscribed to as they start publishing and the pads of
+     * a feed are removed when its publisher leaves.
+     *
+     * By default all the publishers of the room are subscribed to. Use `signaller::producer-peer-id`
+     * to only subscribe to the publisher with the given feed ID or display name, or
+     * `signaller::excluded-producer-peer-ids=<a,b,c>` to ignore publishers `a`, `b` and `c`.
+     *
+     * ```bash
+     * $ gst-launch-1.0 janusvrwebrtcsrc signaller::room-id=1234 ! videoconvert ! autovideosink
+     * ```
+     *
+     * As with `janusvrwebrtcsink`, `use-string-ids=true` has to be set if Janus is configured to use
+     * string IDs.
+     *
+     * Since: plugins-rs-0.13.0
+     */
+    gst::Element::register(
+        plugin,
+        "janusvrwebrtcsrc",
+        gst::Rank::NONE,
+        JanusVRWebRTCSrc::static_type(),
+    )?;
+
     Ok(())
 }
@@ -0,0 +1,926 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::signaller::{Signallable, SignallableImpl};
+use crate::utils::{build_link_header, wait_async, WaitError};
+use crate::RUNTIME;
+use gst::glib::{self, RustClosure};
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_sdp::SDPMessage;
+use gst_webrtc::{WebRTCICEGatheringState, WebRTCSessionDescription};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+use tokio::sync::oneshot;
+use url::Url;
+use warp::{
+    http,
+    hyper::{
+        header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, LINK, LOCATION},
+        Body,
+    },
+    Filter,
+};
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "webrtc-whep-signaller",
+        gst::DebugColorFlags::empty(),
+        Some("WebRTC WHEP signaller"),
+    )
+});
+
+const DEFAULT_TIMEOUT: u32 = 15;
+
+const ROOT: &str = "whep";
+const ENDPOINT_PATH: &str = "endpoint";
+const RESOURCE_PATH: &str = "resource";
+const DEFAULT_HOST_ADDR: &str = "http://127.0.0.1:8080";
+const DEFAULT_STUN_SERVER: Option<&str> = Some("stun://stun.l.google.com:19303");
+const CONTENT_SDP: &str = "application/sdp";
+const CONTENT_TRICKLE_ICE: &str = "application/trickle-ice-sdpfrag";
+
+#[derive(Debug)]
+struct Settings {
+    stun_server: Option<String>,
+    turn_servers: gst::Array,
+    host_addr: Url,
+    timeout: u32,
+    auth_token: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            host_addr: Url::parse(DEFAULT_HOST_ADDR).unwrap(),
+            stun_server: DEFAULT_STUN_SERVER.map(String::from),
+            turn_servers: gst::Array::new(Vec::new() as Vec<glib::SendValue>),
+            timeout: DEFAULT_TIMEOUT,
+            auth_token: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    /// The media IDs of the offer, indexed by m-line, used to map
+    /// trickled candidates to their m-line
+    mids: Vec<Option<String>>,
+    /// Where to send the answer once ICE gathering is complete
+    answer_tx: Option<oneshot::Sender<Option<SDPMessage>>>,
+    /// Aborts the wait for the answer
+    canceller: Arc<Mutex<Option<futures::future::AbortHandle>>>,
+}
+
+#[derive(Default)]
+struct State {
+    shutdown_signal: Option<oneshot::Sender<()>>,
+    server_handle: Option<tokio::task::JoinHandle<()>>,
+    sessions: HashMap<String, Session>,
+}
+
+#[derive(Default)]
+pub struct WhepServer {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl WhepServer {
+    pub fn on_webrtcbin_ready(&self) -> RustClosure {
+        glib::closure!(|signaller: &super::WhepServerSignaller,
+                        session_id: &str,
+                        webrtcbin: &gst::Element| {
+            let session_id = session_id.to_string();
+
+            webrtcbin.connect_notify(
+                Some("ice-gathering-state"),
+                glib::clone!(
+                    #[weak]
+                    signaller,
+                    move |webrtcbin, _pspec| {
+                        let state =
+                            webrtcbin.property::<WebRTCICEGatheringState>("ice-gathering-state");
+
+                        match state {
+                            WebRTCICEGatheringState::Gathering => {
+                                gst::info!(
+                                    CAT,
+                                    obj = signaller,
+                                    "ICE gathering started for session {session_id}"
+                                );
+                            }
+                            WebRTCICEGatheringState::Complete => {
+                                gst::info!(
+                                    CAT,
+                                    obj = signaller,
+                                    "ICE gathering complete for session {session_id}"
+                                );
+
+                                let answer = webrtcbin
+                                    .property::<Option<WebRTCSessionDescription>>(
+                                        "local-description",
+                                    )
+                                    .map(|desc| desc.sdp().to_owned());
+
+                                let tx = signaller
+                                    .imp()
+                                    .state
+                                    .lock()
+                                    .unwrap()
+                                    .sessions
+                                    .get_mut(&session_id)
+                                    .and_then(|session| session.answer_tx.take());
+
+                                match tx {
+                                    Some(tx) => {
+                                        if tx.send(answer).is_err() {
+                                            gst::error!(
+                                                CAT,
+                                                obj = signaller,
+                                                "Failed to send SDP answer for session {session_id}"
+                                            );
+                                        }
+                                    }
+                                    None => {
+                                        gst::debug!(
+                                            CAT,
+                                            obj = signaller,
+                                            "No pending answer for session {session_id}"
+                                        );
+                                    }
+                                }
+                            }
+                            _ => (),
+                        }
+                    }
+                ),
+            );
+        })
+    }
+
+    fn check_auth(&self, auth: Option<&str>) -> Result<(), http::Response<Body>> {
+        let settings = self.settings.lock().unwrap();
+
+        let Some(token) = settings.auth_token.as_deref() else {
+            return Ok(());
+        };
+
+        // Compare in constant time to not leak the token through the response time
+        if auth
+            .and_then(|auth| auth.strip_prefix("Bearer "))
+            .map_or(false, |auth| {
+                bool::from(auth.as_bytes().ct_eq(token.as_bytes()))
+            })
+        {
+            return Ok(());
+        }
+
+        gst::warning!(CAT, imp = self, "Rejecting unauthorized request");
+
+        Err(http::Response::builder()
+            .status(http::StatusCode::UNAUTHORIZED)
+            .header(http::header::WWW_AUTHENTICATE, "Bearer")
+            .body(Body::empty())
+            .unwrap())
+    }
+
+    fn link_headers(&self) -> HeaderMap {
+        let settings = self.settings.lock().unwrap();
+        let mut links = HeaderMap::new();
+
+        let servers = settings.stun_server.iter().cloned().chain(
+            settings
+                .turn_servers
+                .iter()
+                .filter_map(|server| server.get::<String>().ok()),
+        );
+
+        for server in servers {
+            match build_link_header(server.as_str()) {
+                Ok(link) => match HeaderValue::from_str(link.as_str()) {
+                    Ok(value) => {
+                        links.append(LINK, value);
+                    }
+                    Err(e) => {
+                        gst::error!(CAT, imp = self, "Invalid link header {link:?}: {e:?}");
+                    }
+                },
+                Err(e) => {
+                    gst::error!(CAT, imp = self, "Failed to parse {server:?}: {e:?}");
+                }
+            }
+        }
+
+        links
+    }
+
+    fn status_response(status: http::StatusCode) -> http::Response<Body> {
+        http::Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn error_response(err: String) -> http::Response<Body> {
+        http::Response::builder()
+            .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(err))
+            .unwrap()
+    }
+
+    async fn options_handler(&self) -> Result<http::Response<Body>, warp::Rejection> {
+        let mut res = http::Response::builder()
+            .status(http::StatusCode::NO_CONTENT)
+            .header("Accept-Post", CONTENT_SDP)
+            .body(Body::empty())
+            .unwrap();
+
+        res.headers_mut().extend(self.link_headers());
+
+        Ok(res)
+    }
+
+    async fn post_handler(
+        &self,
+        auth: Option<String>,
+        body: warp::hyper::body::Bytes,
+    ) -> Result<http::Response<Body>, warp::Rejection> {
+        if let Err(res) = self.check_auth(auth.as_deref()) {
+            return Ok(res);
+        }
+
+        let offer_sdp = match SDPMessage::parse_buffer(body.as_ref()) {
+            Ok(offer_sdp) => offer_sdp,
+            Err(err) => {
+                gst::error!(CAT, imp = self, "Could not parse offer SDP: {err}");
+                return Ok(Self::status_response(http::StatusCode::BAD_REQUEST));
+            }
+        };
+
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let mids = offer_sdp
+            .medias()
+            .map(|media| media.attribute_val("mid").map(String::from))
+            .collect();
+        let canceller = Arc::new(Mutex::new(None));
+        let (tx, rx) = oneshot::channel();
+
+        self.state.lock().unwrap().sessions.insert(
+            session_id.clone(),
+            Session {
+                mids,
+                answer_tx: Some(tx),
+                canceller: canceller.clone(),
+            },
+        );
+
+        let offer = WebRTCSessionDescription::new(gst_webrtc::WebRTCSDPType::Offer, offer_sdp);
+
+        gst::info!(CAT, imp = self, "Session {session_id} requested");
+
+        self.obj().emit_by_name::<()>(
+            "session-requested",
+            &[&session_id, &session_id, &Some(offer)],
+        );
+
+        let wait_timeout = self.settings.lock().unwrap().timeout;
+        let answer = match wait_async(&canceller, rx, wait_timeout).await {
+            Ok(Ok(Some(answer))) => Ok(answer),
+            Ok(Ok(None)) => Err("SDP answer is empty".to_string()),
+            Ok(Err(_)) => Err("Channel closed, can't receive SDP".to_string()),
+            Err(WaitError::FutureAborted) => Err("Aborted".to_string()),
+            Err(WaitError::FutureError(err)) => Err(err.to_string()),
+        };
+
+        let answer = match answer.and_then(|answer| {
+            answer
+                .as_text()
+                .map_err(|e| format!("Failed to get SDP answer: {e:?}"))
+        }) {
+            Ok(answer) => answer,
+            Err(err) => {
+                gst::error!(CAT, imp = self, "Session {session_id} failed: {err}");
+
+                if self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .sessions
+                    .remove(&session_id)
+                    .is_some()
+                {
+                    self.obj()
+                        .emit_by_name::<bool>("session-ended", &[&session_id]);
+                }
+
+                return Ok(Self::error_response(err));
+            }
+        };
+
+        gst::debug!(CAT, imp = self, "Answering session {session_id}: {answer}");
+
+        let resource_url = format!("/{ROOT}/{RESOURCE_PATH}/{session_id}");
+        let mut res = http::Response::builder()
+            .status(http::StatusCode::CREATED)
+            .header(CONTENT_TYPE, CONTENT_SDP)
+            .header(LOCATION, resource_url)
+            .body(Body::from(answer))
+            .unwrap();
+
+        res.headers_mut().extend(self.link_headers());
+
+        Ok(res)
+    }
+
+    async fn patch_handler(
+        &self,
+        id: String,
+        auth: Option<String>,
+        body: warp::hyper::body::Bytes,
+    ) -> Result<http::Response<Body>, warp::Rejection> {
+        if let Err(res) = self.check_auth(auth.as_deref()) {
+            return Ok(res);
+        }
+
+        let Ok(fragment) = std::str::from_utf8(body.as_ref()) else {
+            return Ok(Self::status_response(http::StatusCode::BAD_REQUEST));
+        };
+
+        let mids = match self.state.lock().unwrap().sessions.get(&id) {
+            Some(session) => session.mids.clone(),
+            None => return Ok(Self::status_response(http::StatusCode::NOT_FOUND)),
+        };
+
+        // Candidates apply to the m-line of the last `a=mid` seen in the fragment
+        let mut mid = None;
+        for line in fragment.lines().map(str::trim) {
+            if let Some(value) = line.strip_prefix("a=mid:") {
+                mid = Some(value.to_string());
+            } else if let Some(candidate) = line.strip_prefix("a=candidate:") {
+                let mline = mid
+                    .as_ref()
+                    .and_then(|mid| mids.iter().position(|m| m.as_ref() == Some(mid)))
+                    .unwrap_or(0) as u32;
+                let candidate = format!("candidate:{candidate}");
+
+                gst::trace!(
+                    CAT,
+                    imp = self,
+                    "Session {id}: remote candidate {candidate} for mline {mline}"
+                );
+
+                self.obj()
+                    .emit_by_name::<()>("handle-ice", &[&id, &mline, &mid.clone(), &candidate]);
+            }
+        }
+
+        Ok(Self::status_response(http::StatusCode::NO_CONTENT))
+    }
+
+    async fn delete_handler(
+        &self,
+        id: String,
+        auth: Option<String>,
+    ) -> Result<http::Response<Body>, warp::Rejection> {
+        if let Err(res) = self.check_auth(auth.as_deref()) {
+            return Ok(res);
+        }
+
+        let Some(session) = self.state.lock().unwrap().sessions.remove(&id) else {
+            return Ok(Self::status_response(http::StatusCode::NOT_FOUND));
+        };
+
+        if let Some(canceller) = &*session.canceller.lock().unwrap() {
+            canceller.abort();
+        }
+
+        if self
+            .obj()
+            .emit_by_name::<bool>("session-ended", &[&id.as_str()])
+        {
+            gst::info!(CAT, imp = self, "Ended session {id}");
+        } else {
+            gst::info!(CAT, imp = self, "Failed to end session {id}");
+        }
+
+        Ok(Self::status_response(http::StatusCode::OK))
+    }
+
+    /// The WHEP endpoint and resource routes, below `/whep`
+    fn routes(
+        &self,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection>
+           + Clone
+           + Send
+           + Sync
+           + 'static {
+        let prefix = warp::path(ROOT);
+
+        // POST /endpoint
+        let post_filter = warp::post()
+            .and(warp::path(ENDPOINT_PATH))
+            .and(warp::path::end())
+            .and(warp::header::exact(CONTENT_TYPE.as_str(), CONTENT_SDP))
+            .and(warp::header::optional::<String>(AUTHORIZATION.as_str()))
+            .and(warp::body::bytes())
+            .and_then(glib::clone!(
+                #[weak(rename_to = self_)]
+                self,
+                #[upgrade_or_panic]
+                move |auth, body| async move { self_.post_handler(auth, body).await }
+            ));
+
+        // OPTIONS /endpoint
+        let options_filter = warp::options()
+            .and(warp::path(ENDPOINT_PATH))
+            .and(warp::path::end())
+            .and_then(glib::clone!(
+                #[weak(rename_to = self_)]
+                self,
+                #[upgrade_or_panic]
+                move || async move { self_.options_handler().await }
+            ));
+
+        // PATCH /resource/:id
+        let patch_filter = warp::patch()
+            .and(warp::path(RESOURCE_PATH))
+            .and(warp::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::header::exact(
+                CONTENT_TYPE.as_str(),
+                CONTENT_TRICKLE_ICE,
+            ))
+            .and(warp::header::optional::<String>(AUTHORIZATION.as_str()))
+            .and(warp::body::bytes())
+            .and_then(glib::clone!(
+                #[weak(rename_to = self_)]
+                self,
+                #[upgrade_or_panic]
+                move |id, auth, body| async move { self_.patch_handler(id, auth, body).await }
+            ));
+
+        // DELETE /resource/:id
+        let delete_filter = warp::delete()
+            .and(warp::path(RESOURCE_PATH))
+            .and(warp::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::header::optional::<String>(AUTHORIZATION.as_str()))
+            .and_then(glib::clone!(
+                #[weak(rename_to = self_)]
+                self,
+                #[upgrade_or_panic]
+                move |id, auth| async move { self_.delete_handler(id, auth).await }
+            ));
+
+        prefix
+            .and(post_filter)
+            .or(prefix.and(options_filter))
+            .or(prefix.and(patch_filter))
+            .or(prefix.and(delete_filter))
+    }
+
+    fn serve(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let host_addr = self.settings.lock().unwrap().host_addr.clone();
+        let addr: SocketAddr = match host_addr.socket_addrs(|| None) {
+            Ok(v) => {
+                // pick the first vector item
+                gst::info!(CAT, imp = self, "using {:?} as address", v[0]);
+                v[0]
+            }
+            Err(e) => {
+                gst::error!(CAT, imp = self, "error getting addr from uri {e:?}");
+                self.obj()
+                    .emit_by_name::<()>("error", &[&format!("Unable to start WHEP Server: {e:?}")]);
+                return None;
+            }
+        };
+
+        let (tx, rx) = oneshot::channel::<()>();
+        self.state.lock().unwrap().shutdown_signal = Some(tx);
+
+        let s = warp::serve(self.routes());
+        let jh = RUNTIME.spawn(async move {
+            let (_, server) = s.bind_with_graceful_shutdown(addr, async move {
+                match rx.await {
+                    Ok(_) => gst::debug!(CAT, "Server shut down signal received"),
+                    Err(e) => gst::error!(CAT, "{e:?}: Sender dropped"),
+                }
+            });
+
+            server.await;
+            gst::debug!(CAT, "Stopped the server task...");
+        });
+
+        gst::debug!(CAT, imp = self, "Started the server...");
+        Some(jh)
+    }
+
+    fn set_host_addr(&self, host_addr: &str) -> Result<(), url::ParseError> {
+        let mut settings = self.settings.lock().unwrap();
+        settings.host_addr = Url::parse(host_addr)?;
+        Ok(())
+    }
+}
+
+impl SignallableImpl for WhepServer {
+    fn start(&self) {
+        gst::info!(CAT, imp = self, "starting the WHEP server");
+        let jh = self.serve();
+        let mut state = self.state.lock().unwrap();
+        state.server_handle = jh;
+    }
+
+    fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        for (_, session) in state.sessions.drain() {
+            if let Some(canceller) = &*session.canceller.lock().unwrap() {
+                canceller.abort();
+            }
+        }
+
+        if let Some(tx) = state.shutdown_signal.take() {
+            if tx.send(()).is_err() {
+                gst::error!(
+                    CAT,
+                    imp = self,
+                    "Failed to send shutdown signal. Receiver dropped"
+                );
+            }
+        }
+
+        let handle = state.server_handle.take();
+        drop(state);
+
+        if let Some(handle) = handle {
+            gst::debug!(CAT, imp = self, "Await server handle to join");
+            RUNTIME.block_on(async {
+                if let Err(e) = handle.await {
+                    gst::error!(CAT, imp = self, "Failed to join server handle: {e:?}");
+                };
+            });
+        }
+
+        gst::info!(CAT, imp = self, "stopped the WHEP server");
+    }
+
+    fn end_session(&self, session_id: &str) {
+        gst::info!(CAT, imp = self, "Session {session_id} ended");
+
+        if let Some(session) = self.state.lock().unwrap().sessions.remove(session_id) {
+            if let Some(canceller) = &*session.canceller.lock().unwrap() {
+                canceller.abort();
+            }
+        }
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for WhepServer {
+    const NAME: &'static str = "GstWhepServerSignaller";
+    type Type = super::WhepServerSignaller;
+    type ParentType = glib::Object;
+    type Interfaces = (Signallable,);
+}
+
+impl ObjectImpl for WhepServer {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecBoolean::builder("manual-sdp-munging")
+                    .nick("Manual SDP munging")
+                    .blurb("Whether the signaller manages SDP munging itself")
+                    .default_value(false)
+                    .read_only()
+                    .build(),
+                glib::ParamSpecString::builder("host-addr")
+                    .nick("Host address")
+                    .blurb("The the host address of the WHEP endpoint e.g., http://127.0.0.1:8080")
+                    .default_value(DEFAULT_HOST_ADDR)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("stun-server")
+                    .nick("STUN Server")
+                    .blurb("The STUN server of the form stun://hostname:port")
+                    .default_value(DEFAULT_STUN_SERVER)
+                    .build(),
+                gst::ParamSpecArray::builder("turn-servers")
+                    .nick("List of TURN Servers to user")
+                    .blurb("The TURN servers of the form <\"turn(s)://username:password@host:port\", \"turn(s)://username1:password1@host1:port1\">")
+                    .element_spec(&glib::ParamSpecString::builder("turn-server")
+                        .nick("TURN Server")
+                        .blurb("The TURN server of the form turn(s)://username:password@host:port.")
+                        .build()
+                    )
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("timeout")
+                    .nick("Timeout")
+                    .blurb("Value in seconds to wait for an SDP answer before failing a WHEP request (0 = No timeout).")
+                    .maximum(3600)
+                    .default_value(DEFAULT_TIMEOUT)
+                    .build(),
+                /**
+                 * GstWhepServerSignaller:auth-token:
+                 *
+                 * Bearer token that WHEP clients must present in the
+                 * `Authorization` header. Requests are not authenticated when
+                 * unset.
+                 *
+                 * Since: plugins-rs-0.13.0
+                 */
+                glib::ParamSpecString::builder("auth-token")
+                    .nick("Authorization Token")
+                    .blurb("Bearer token clients need to present to the WHEP endpoint")
+                    .write_only()
+                    .build(),
+            ]
+        });
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "host-addr" => {
+                if let Err(e) =
+                    self.set_host_addr(value.get::<&str>().expect("type checked upstream"))
+                {
+                    gst::error!(CAT, "Couldn't set the host address as {e:?}, fallback to the default value {DEFAULT_HOST_ADDR:?}");
+                }
+            }
+            "stun-server" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.stun_server = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+            }
+            "turn-servers" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.turn_servers = value.get::<gst::Array>().expect("type checked upstream")
+            }
+            "timeout" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.timeout = value.get().unwrap();
+            }
+            "auth-token" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.auth_token = value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "manual-sdp-munging" => false.to_value(),
+            "host-addr" => settings.host_addr.to_string().to_value(),
+            "stun-server" => settings.stun_server.to_value(),
+            "turn-servers" => settings.turn_servers.to_value(),
+            "timeout" => settings.timeout.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::whep_signaller::WhepServerSignaller;
+
+    const OFFER: &str = "v=0\r\n\
+                         o=- 0 0 IN IP4 127.0.0.1\r\n\
+                         s=-\r\n\
+                         t=0 0\r\n\
+                         m=video 9 UDP/TLS/RTP/SAVPF 96\r\n\
+                         c=IN IP4 0.0.0.0\r\n\
+                         a=mid:video0\r\n\
+                         a=recvonly\r\n\
+                         a=rtpmap:96 VP8/90000\r\n\
+                         m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+                         c=IN IP4 0.0.0.0\r\n\
+                         a=mid:audio1\r\n\
+                         a=recvonly\r\n\
+                         a=rtpmap:111 OPUS/48000/2\r\n";
+
+    const ANSWER: &str = "v=0\r\n\
+                          o=- 1 0 IN IP4 127.0.0.1\r\n\
+                          s=-\r\n\
+                          t=0 0\r\n\
+                          m=video 9 UDP/TLS/RTP/SAVPF 96\r\n\
+                          c=IN IP4 0.0.0.0\r\n\
+                          a=mid:video0\r\n\
+                          a=sendonly\r\n\
+                          a=rtpmap:96 VP8/90000\r\n";
+
+    #[derive(Debug, Default)]
+    struct Events {
+        ice: Vec<(String, u32, Option<String>, String)>,
+        ended: Vec<String>,
+    }
+
+    /// Creates a signaller that answers all offers right away, as the sink does
+    /// once ICE gathering completed, and records the other signals
+    fn signaller(auth_token: Option<&str>) -> (WhepServerSignaller, Arc<Mutex<Events>>) {
+        gst::init().unwrap();
+
+        let signaller = WhepServerSignaller::default();
+        signaller.set_property("auth-token", auth_token);
+        let events = Arc::new(Mutex::new(Events::default()));
+
+        signaller.connect("session-requested", false, |args| {
+            let signaller = args[0].get::<WhepServerSignaller>().unwrap();
+            let session_id = args[1].get::<String>().unwrap();
+
+            let tx = signaller
+                .imp()
+                .state
+                .lock()
+                .unwrap()
+                .sessions
+                .get_mut(&session_id)
+                .and_then(|session| session.answer_tx.take())
+                .unwrap();
+            tx.send(Some(SDPMessage::parse_buffer(ANSWER.as_bytes()).unwrap()))
+                .unwrap();
+
+            None
+        });
+
+        let events_clone = events.clone();
+        signaller.connect("handle-ice", false, move |args| {
+            events_clone.lock().unwrap().ice.push((
+                args[1].get().unwrap(),
+                args[2].get().unwrap(),
+                args[3].get().unwrap(),
+                args[4].get().unwrap(),
+            ));
+            None
+        });
+
+        let events_clone = events.clone();
+        signaller.connect("session-ended", false, move |args| {
+            events_clone
+                .lock()
+                .unwrap()
+                .ended
+                .push(args[1].get().unwrap());
+            Some(true.to_value())
+        });
+
+        (signaller, events)
+    }
+
+    fn post(auth: Option<&str>) -> warp::test::RequestBuilder {
+        let mut request = warp::test::request()
+            .method("POST")
+            .path("/whep/endpoint")
+            .header(CONTENT_TYPE, CONTENT_SDP)
+            .body(OFFER);
+        if let Some(auth) = auth {
+            request = request.header(AUTHORIZATION, auth);
+        }
+        request
+    }
+
+    #[test]
+    fn post_patch_delete() {
+        let (signaller, events) = signaller(None);
+        let routes = signaller.imp().routes();
+
+        RUNTIME.block_on(async {
+            let res = post(None).reply(&routes).await;
+            assert_eq!(res.status(), http::StatusCode::CREATED);
+            assert_eq!(res.headers()[CONTENT_TYPE], CONTENT_SDP);
+            assert_eq!(res.body().as_ref(), ANSWER.as_bytes());
+            let resource = res.headers()[LOCATION].to_str().unwrap().to_string();
+            let session_id = resource.strip_prefix("/whep/resource/").unwrap();
+            assert!(signaller
+                .imp()
+                .state
+                .lock()
+                .unwrap()
+                .sessions
+                .contains_key(session_id));
+
+            // Trickled candidates are mapped to the m-line of their mid
+            let res = warp::test::request()
+                .method("PATCH")
+                .path(&resource)
+                .header(CONTENT_TYPE, CONTENT_TRICKLE_ICE)
+                .body(
+                    "a=mid:audio1\r\n\
+                     a=candidate:1 1 UDP 2122252543 192.168.1.2 50000 typ host\r\n",
+                )
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), http::StatusCode::NO_CONTENT);
+            assert_eq!(
+                events.lock().unwrap().ice,
+                [(
+                    session_id.to_string(),
+                    1,
+                    Some("audio1".to_string()),
+                    "candidate:1 1 UDP 2122252543 192.168.1.2 50000 typ host".to_string()
+                )]
+            );
+
+            let res = warp::test::request()
+                .method("DELETE")
+                .path(&resource)
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), http::StatusCode::OK);
+            assert_eq!(events.lock().unwrap().ended, [session_id.to_string()]);
+            assert!(signaller.imp().state.lock().unwrap().sessions.is_empty());
+
+            // The session is gone
+            let res = warp::test::request()
+                .method("PATCH")
+                .path(&resource)
+                .header(CONTENT_TYPE, CONTENT_TRICKLE_ICE)
+                .body("a=mid:audio1\r\n")
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), http::StatusCode::NOT_FOUND);
+            let res = warp::test::request()
+                .method("DELETE")
+                .path(&resource)
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), http::StatusCode::NOT_FOUND);
+        });
+    }
+
+    #[test]
+    fn post_invalid_offer() {
+        let (signaller, _events) = signaller(None);
+        let routes = signaller.imp().routes();
+
+        RUNTIME.block_on(async {
+            let res = warp::test::request()
+                .method("POST")
+                .path("/whep/endpoint")
+                .header(CONTENT_TYPE, CONTENT_SDP)
+                .body("not an SDP")
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), http::StatusCode::BAD_REQUEST);
+            assert!(signaller.imp().state.lock().unwrap().sessions.is_empty());
+        });
+    }
+
+    #[test]
+    fn bearer_auth() {
+        let (signaller, events) = signaller(Some("secret"));
+        let routes = signaller.imp().routes();
+
+        RUNTIME.block_on(async {
+            for auth in [
+                None,
+                Some("Bearer wrong"),
+                Some("secret"),
+                Some("Basic secret"),
+            ] {
+                let res = post(auth).reply(&routes).await;
+                assert_eq!(res.status(), http::StatusCode::UNAUTHORIZED, "{auth:?}");
+                assert_eq!(res.headers()[http::header::WWW_AUTHENTICATE], "Bearer");
+            }
+            assert!(signaller.imp().state.lock().unwrap().sessions.is_empty());
+
+            let res = post(Some("Bearer secret")).reply(&routes).await;
+            assert_eq!(res.status(), http::StatusCode::CREATED);
+            let resource = res.headers()[LOCATION].to_str().unwrap().to_string();
+
+            // The resource requires the token as well
+            let res = warp::test::request()
+                .method("PATCH")
+                .path(&resource)
+                .header(CONTENT_TYPE, CONTENT_TRICKLE_ICE)
+                .body("a=mid:video0\r\n")
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), http::StatusCode::UNAUTHORIZED);
+
+            let res = warp::test::request()
+                .method("DELETE")
+                .path(&resource)
+                .header(AUTHORIZATION, "Bearer wrong")
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), http::StatusCode::UNAUTHORIZED);
+            assert!(events.lock().unwrap().ended.is_empty());
+
+            let res = warp::test::request()
+                .method("DELETE")
+                .path(&resource)
+                .header(AUTHORIZATION, "Bearer secret")
+                .reply(&routes)
+                .await;
+            assert_eq!(res.status(), http::StatusCode::OK);
+            assert_eq!(events.lock().unwrap().ended.len(), 1);
+        });
+    }
+}
@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::signaller::Signallable;
+use gst::{glib, prelude::ObjectExt, subclass::prelude::ObjectSubclassIsExt};
+
+mod imp;
+
+glib::wrapper! {
+    pub struct WhepServerSignaller(ObjectSubclass<imp::WhepServer>) @implements Signallable;
+}
+
+impl Default for WhepServerSignaller {
+    fn default() -> Self {
+        let sig: WhepServerSignaller = glib::Object::new();
+        sig.connect_closure("webrtcbin-ready", false, sig.imp().on_webrtcbin_ready());
+        sig
+    }
+}